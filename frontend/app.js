@@ -83,6 +83,7 @@
     if (op === 'create') return COLORS.green;
     if (op === 'modify') return COLORS.blue;
     if (op === 'delete') return COLORS.red;
+    if (op === 'truncate') return COLORS.fgDim;
     return COLORS.fgDim;
   }
 
@@ -137,9 +138,15 @@
   const $restorePanelRight = document.getElementById('restore-panel-right');
   const $restoreCancel = document.getElementById('restore-cancel');
   const $restoreConfirm = document.getElementById('restore-confirm');
+  const $restorePanels = document.getElementById('restore-panels');
+  const $restoreConflict = document.getElementById('restore-conflict');
+  const $restoreConflictAncestor = document.getElementById('restore-conflict-ancestor');
+  const $restoreConflictWorking = document.getElementById('restore-conflict-working');
+  const $restoreConflictTarget = document.getElementById('restore-conflict-target');
 
   // ---- API helpers ---------------------------------------------------------
-  const API = '';
+  // Projects of a shared daemon are served under /projects/<name>/
+  const API = (location.pathname.match(/^\/projects\/[^/]+/) || [''])[0];
 
   async function api(path) {
     const res = await fetch(API + path);
@@ -163,7 +170,10 @@
     });
     if (!res.ok) {
       const data = await res.json().catch(() => ({ message: res.statusText }));
-      throw new Error(data.message || res.statusText);
+      const err = new Error(data.message || res.statusText);
+      err.status = res.status;
+      err.data = data;
+      throw err;
     }
     return res.json();
   }
@@ -203,6 +213,7 @@
         formatNumber(st.history) + ' / ' + formatNumber(st.max_history);
       $statsQuotaFill.style.width = quotaPct + '%';
       $statsQuotaText.textContent = formatBytes(st.quota) + ' / ' + formatBytes(st.max_quota);
+      loadUsageHistory();
     } catch {
       $statsHistoryFill.style.width = '0%';
       $statsHistoryText.textContent = '0 / 0';
@@ -211,6 +222,24 @@
     }
   }
 
+  /** Show the most recent daily usage samples as a tooltip on the toolbar stats. */
+  async function loadUsageHistory() {
+    try {
+      const samples = await apiJson('/api/stats/history');
+      const lines = samples
+        .slice(-7)
+        .map(
+          (s) =>
+            s.at.slice(0, 10) + '  ' + formatNumber(s.history) + ' \u00B7 ' + formatBytes(s.quota)
+        );
+      $toolbarStats.title = lines.length
+        ? t('toolbar.usageHistory') + '\n' + lines.join('\n')
+        : '';
+    } catch {
+      $toolbarStats.title = '';
+    }
+  }
+
   // ---- File list -----------------------------------------------------------
 
   async function loadFiles() {
@@ -1535,9 +1564,9 @@
     const toChecksum = entry.checksum;
 
     if (!toChecksum) {
-      // Delete event - show message
-      $diffViewer.innerHTML =
-        '<div class="empty-state">' + escapeHtml(t('state.fileDeleted')) + '</div>';
+      // Delete or truncate event - show message
+      const message = entry.op === 'delete' ? t('state.fileDeleted') : t('state.fileEmpty');
+      $diffViewer.innerHTML = '<div class="empty-state">' + escapeHtml(message) + '</div>';
       $diffMeta.textContent =
         opLabel(entry.op) + ' \u2022 ' + formatDateTime(new Date(entry.timestamp));
       return;
@@ -1559,6 +1588,22 @@
     if (entry.size != null) {
       $diffMeta.textContent += ' \u2022 ' + formatSize(entry.size);
     }
+    if (entry.language) {
+      $diffMeta.textContent += ' \u2022 ' + entry.language;
+    }
+    if (entry.writer) {
+      $diffMeta.textContent += ' \u2022 ' + entry.writer;
+    }
+    if (entry.restored_from) {
+      $diffMeta.textContent +=
+        ' \u2022 ' +
+        t('diff.restoredFrom', {
+          v: toChecksum.slice(0, 8),
+          time: formatDateTime(new Date(entry.restored_from)),
+        });
+    }
+    // Hook for syntax highlighting of the rendered lines
+    $diffViewer.dataset.language = entry.language || '';
 
     $diffViewer.innerHTML =
       '<div class="loading">' + escapeHtml(t('state.computingDiff')) + '</div>';
@@ -1803,8 +1848,12 @@
     diffSingleMode = !fromChecksum;
 
     if (diff.hunks.length === 0) {
-      $diffViewer.innerHTML =
-        '<div class="empty-state">' + escapeHtml(t('state.noChanges')) + '</div>';
+      const text = diff.truncated ? t('diff.truncated') : t('state.noChanges');
+      $diffViewer.innerHTML = '<div class="empty-state">' + escapeHtml(text) + '</div>';
+      if (diff.truncated) {
+        $diffViewer.firstChild.classList.add('diff-truncated');
+        $diffViewer.firstChild.addEventListener('click', downloadPatch);
+      }
       return;
     }
 
@@ -1869,11 +1918,13 @@
       }
     }
 
-    // Trailing separator
+    // Trailing separator; for a diff cut short, a link to the full patch instead
     const lastHunk = diff.hunks[diff.hunks.length - 1];
     const lastEndOld = hunkEndLine(lastHunk, 'old');
     const lastEndNew = hunkEndLine(lastHunk, 'new');
-    if (lastEndOld <= diff.old_total || lastEndNew <= diff.new_total) {
+    if (diff.truncated) {
+      diffRows.push({ type: 'truncated', text: t('diff.truncated') });
+    } else if (lastEndOld <= diff.old_total || lastEndNew <= diff.new_total) {
       const remaining = Math.max(diff.old_total - lastEndOld + 1, diff.new_total - lastEndNew + 1);
       if (remaining > 0) {
         diffRows.push({
@@ -1890,6 +1941,19 @@
     initVirtualScroll();
   }
 
+  /** Download the full unified diff of the versions last shown. */
+  function downloadPatch() {
+    let url =
+      '/api/patch?file=' +
+      encodeURIComponent(currentFile) +
+      '&to=' +
+      encodeURIComponent(lastDiffToChecksum);
+    if (lastDiffFromChecksum) {
+      url += '&from=' + encodeURIComponent(lastDiffFromChecksum);
+    }
+    window.location.href = url;
+  }
+
   function hunkEndLine(hunk, side) {
     let line = side === 'old' ? hunk.old_start : hunk.new_start;
     const incTag = side === 'old' ? 'delete' : 'insert';
@@ -1975,12 +2039,17 @@
       const tr = document.createElement('tr');
       tr.style.height = ROW_HEIGHT + 'px';
 
-      if (row.type === 'separator') {
+      if (row.type === 'separator' || row.type === 'truncated') {
         tr.className = 'diff-separator';
         const td = document.createElement('td');
         td.colSpan = showOld ? 4 : 2;
         td.textContent = row.text;
-        td.addEventListener('click', () => expandSeparator(i));
+        if (row.type === 'truncated') {
+          td.className = 'diff-truncated';
+          td.addEventListener('click', downloadPatch);
+        } else {
+          td.addEventListener('click', () => expandSeparator(i));
+        }
         tr.appendChild(td);
       } else {
         tr.className = 'diff-line-' + row.type;
@@ -2114,10 +2183,36 @@
 
   // ---- Filter input --------------------------------------------------------
   let filterTimeout = null;
+  let pathsTimeout = null;
+  const $filterPaths = document.getElementById('filter-paths');
   $filter.addEventListener('input', () => {
     clearTimeout(filterTimeout);
     filterTimeout = setTimeout(renderFileList, 80);
+    clearTimeout(pathsTimeout);
+    pathsTimeout = setTimeout(suggestPaths, 150);
   });
+
+  // Offer tracked paths starting with the query (not for glob patterns)
+  async function suggestPaths() {
+    const query = $filter.value.trim();
+    $filterPaths.innerHTML = '';
+    if (!query || /[*?]/.test(query)) return;
+    try {
+      const paths = await apiJson(
+        '/api/paths?limit=20&prefix=' +
+          encodeURIComponent(query) +
+          (hideDeletedFiles ? '' : '&include_deleted=true')
+      );
+      if ($filter.value.trim() !== query) return;
+      for (const path of paths) {
+        const option = document.createElement('option');
+        option.value = path;
+        $filterPaths.appendChild(option);
+      }
+    } catch {
+      // Suggestions are best-effort
+    }
+  }
   const $filterClear = document.querySelector('#sidebar-header .filter-clear');
   if ($filterClear) {
     $filterClear.addEventListener('click', () => {
@@ -2612,6 +2707,7 @@
         : '\u2014';
     $restoreInfoTo.textContent = selectedRestoreChecksum.slice(0, 12);
     $restoreInfoStats.innerHTML = '';
+    resetRestoreConflict();
 
     // Show loading state
     $restorePanelLeft.innerHTML =
@@ -2642,8 +2738,49 @@
     }
   }
 
+  // Set when the server reported that the working copy has unrecorded changes; the
+  // next confirm overwrites them.
+  let restoreConflictShown = false;
+
+  function resetRestoreConflict() {
+    restoreConflictShown = false;
+    $restoreConflict.hidden = true;
+    $restorePanels.hidden = false;
+    $restoreConfirm.textContent = t('restore.confirm');
+    $restoreCancel.textContent = t('restore.cancel');
+  }
+
+  function fillConflictPane($pane, side, emptyText) {
+    $pane.classList.toggle('restore-conflict-empty', !side || side.content == null);
+    if (!side) {
+      $pane.textContent = emptyText;
+    } else if (side.content == null) {
+      $pane.textContent = t('restore.noText', { size: formatBytes(side.size) });
+    } else {
+      $pane.textContent = side.content;
+    }
+  }
+
+  /** Show the three versions a restore would choose between: last recorded, working, target. */
+  function showRestoreConflict(conflict) {
+    restoreConflictShown = true;
+    $restorePanels.hidden = true;
+    $restoreConflict.hidden = false;
+    fillConflictPane($restoreConflictAncestor, conflict.ancestor, t('restore.noAncestor'));
+    fillConflictPane($restoreConflictWorking, conflict.working);
+    fillConflictPane($restoreConflictTarget, conflict.snapshot);
+    $restoreInfoFrom.textContent = conflict.working.checksum.slice(0, 12);
+    $restoreInfoStats.innerHTML =
+      '<div class="restore-info-value restore-conflict-note">' +
+      escapeHtml(t('restore.conflict')) +
+      '</div>';
+    $restoreConfirm.textContent = t('restore.overwrite');
+    $restoreCancel.textContent = t('restore.keep');
+  }
+
   function closeRestoreModal() {
     $restoreOverlay.setAttribute('aria-hidden', 'true');
+    resetRestoreConflict();
     restoreRows = [];
     $restorePanelLeft.innerHTML = '';
     $restorePanelRight.innerHTML = '';
@@ -2945,12 +3082,17 @@
       await apiPost('/api/restore', {
         file: currentFile,
         checksum: selectedRestoreChecksum,
+        check_conflict: !restoreConflictShown,
       });
       $status.textContent = t('status.restoreRequested', { file: currentFile });
       closeRestoreModal();
       refreshAfterRestore();
     } catch (e) {
-      $status.textContent = e.message;
+      if (e.status === 409 && e.data && e.data.conflict) {
+        showRestoreConflict(e.data.conflict);
+      } else {
+        $status.textContent = e.message;
+      }
       $restoreConfirm.disabled = false;
       $restoreCancel.disabled = false;
     }
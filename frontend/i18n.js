@@ -20,6 +20,7 @@ var I18N = (function () {
       'toolbar.scanning': 'Scanning...',
       'toolbar.history': 'History',
       'toolbar.quota': 'Quota',
+      'toolbar.usageHistory': 'Usage over time',
       // -- sidebar --
       'sidebar.filterPlaceholder': 'filter files...',
       'sidebar.clearFilter': 'Clear filter',
@@ -51,6 +52,7 @@ var I18N = (function () {
       'state.noActivity': 'No activity',
       'state.noChanges': 'No changes',
       'state.fileDeleted': 'File was deleted in this version',
+      'state.fileEmpty': 'File was empty in this version',
       'state.loadingHistory': 'Loading history...',
       'state.computingDiff': 'Computing diff...',
       'state.failedSnapshot': '(failed to load snapshot)',
@@ -79,13 +81,26 @@ var I18N = (function () {
       'restore.loading': 'Loading diff...',
       'restore.noChanges': 'No changes between these versions',
       'restore.closeHint': 'Press <kbd>Esc</kbd> or click outside to close.',
+      'restore.conflict':
+        'The working copy has changes not yet in history. Restoring overwrites them.',
+      'restore.ancestor': 'Last Recorded',
+      'restore.working': 'Working Copy',
+      'restore.target': 'Version to Restore',
+      'restore.overwrite': 'Restore Anyway',
+      'restore.keep': 'Keep Working Copy',
+      'restore.noText': 'Binary or too large to show ({size})',
+      'restore.noAncestor': 'No recorded version (the file was deleted)',
       // -- diff --
       'diff.unchangedLines': '\u00B7\u00B7\u00B7 {n} unchanged lines \u00B7\u00B7\u00B7',
       'diff.filesSelected': '{n} files selected',
+      'diff.truncated':
+        'File too large to diff in full: only changes near the start are shown. Click to download the full patch',
+      'diff.restoredFrom': 'restored from version {v} of {time}',
       // -- ops --
       'op.create': 'Create',
       'op.modify': 'Modify',
       'op.delete': 'Delete',
+      'op.truncate': 'Truncate',
       // -- help modal --
       'help.title': 'Help',
       'help.intro':
@@ -120,6 +135,7 @@ var I18N = (function () {
       'toolbar.scanning': '\u626B\u63CF\u4E2D...',
       'toolbar.history': '\u5386\u53F2',
       'toolbar.quota': '\u914D\u989D',
+      'toolbar.usageHistory': '\u7528\u91CF\u53D8\u5316',
       // -- sidebar --
       'sidebar.filterPlaceholder': '\u7B5B\u9009\u6587\u4EF6...',
       'sidebar.clearFilter': '\u6E05\u9664\u7B5B\u9009',
@@ -151,6 +167,7 @@ var I18N = (function () {
       'state.noActivity': '\u65E0\u6D3B\u52A8',
       'state.noChanges': '\u65E0\u53D8\u66F4',
       'state.fileDeleted': '\u6587\u4EF6\u5728\u6B64\u7248\u672C\u4E2D\u5DF2\u88AB\u5220\u9664',
+      'state.fileEmpty': '\u6587\u4EF6\u5728\u6B64\u7248\u672C\u4E2D\u4E3A\u7A7A',
       'state.loadingHistory': '\u52A0\u8F7D\u5386\u53F2\u4E2D...',
       'state.computingDiff': '\u8BA1\u7B97\u5DEE\u5F02\u4E2D...',
       'state.failedSnapshot': '(\u52A0\u8F7D\u5FEB\u7167\u5931\u8D25)',
@@ -179,13 +196,25 @@ var I18N = (function () {
       'restore.loading': '\u52A0\u8F7D\u5DEE\u5F02\u4E2D...',
       'restore.noChanges': '\u4E24\u4E2A\u7248\u672C\u4E4B\u95F4\u6CA1\u6709\u53D8\u66F4',
       'restore.closeHint': '\u6309 <kbd>Esc</kbd> \u6216\u70B9\u51FB\u5916\u90E8\u5173\u95ED\u3002',
+      'restore.conflict': '\u5DE5\u4F5C\u526F\u672C\u6709\u5C1A\u672A\u8BB0\u5F55\u7684\u66F4\u6539\uFF0C\u6062\u590D\u5C06\u8986\u76D6\u5B83\u4EEC\u3002',
+      'restore.ancestor': '\u6700\u540E\u8BB0\u5F55\u7684\u7248\u672C',
+      'restore.working': '\u5DE5\u4F5C\u526F\u672C',
+      'restore.target': '\u8981\u6062\u590D\u7684\u7248\u672C',
+      'restore.overwrite': '\u4ECD\u7136\u6062\u590D',
+      'restore.keep': '\u4FDD\u7559\u5DE5\u4F5C\u526F\u672C',
+      'restore.noText': '\u4E8C\u8FDB\u5236\u6216\u8FC7\u5927\uFF0C\u65E0\u6CD5\u663E\u793A\uFF08{size}\uFF09',
+      'restore.noAncestor': '\u6CA1\u6709\u8BB0\u5F55\u7684\u7248\u672C\uFF08\u6587\u4EF6\u5DF2\u88AB\u5220\u9664\uFF09',
       // -- diff --
       'diff.unchangedLines': '\u00B7\u00B7\u00B7 {n} \u884C\u672A\u53D8\u66F4 \u00B7\u00B7\u00B7',
       'diff.filesSelected': '\u5DF2\u9009\u62E9 {n} \u4E2A\u6587\u4EF6',
+      'diff.truncated':
+        '\u6587\u4EF6\u8FC7\u5927\uFF0C\u4EC5\u663E\u793A\u5F00\u5934\u90E8\u5206\u7684\u5DEE\u5F02\u3002\u70B9\u51FB\u4E0B\u8F7D\u5B8C\u6574\u8865\u4E01',
+      'diff.restoredFrom': '\u6062\u590D\u81EA {time} \u7684\u7248\u672C {v}',
       // -- ops --
       'op.create': '\u521B\u5EFA',
       'op.modify': '\u4FEE\u6539',
       'op.delete': '\u5220\u9664',
+      'op.truncate': '\u6E05\u7A7A',
       // -- help modal --
       'help.title': '\u5E2E\u52A9',
       'help.intro':
@@ -0,0 +1,42 @@
+//! Token clients on other machines must present to change anything on a server bound
+//! beyond loopback (`--bind`). It lives as `api.token` in the user state directory,
+//! created by the first such server; clients send `$FTM_TOKEN` as a bearer token.
+//! Reads other than logs, the index and signatures, and every request from this
+//! machine, need no token.
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+const TOKEN_FILE: &str = "api.token";
+
+/// Location of the token: `api.token` in the state dir.
+pub fn path() -> Option<PathBuf> {
+    crate::user_state::state_dir().map(|d| d.join(TOKEN_FILE))
+}
+
+/// The token servers accept, created on first use.
+pub fn load_or_create() -> Result<String> {
+    let path = path().context("No user state directory for the API token")?;
+    if !path.exists() {
+        let mut bytes = [0u8; 32];
+        getrandom::fill(&mut bytes)
+            .map_err(|e| anyhow::anyhow!("No randomness for token: {}", e))?;
+        crate::user_state::create_secret(&path, &hex::encode(bytes))?;
+    }
+
+    let token = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read API token {}", path.display()))?;
+    let token = token.trim();
+    if token.is_empty() {
+        anyhow::bail!("Empty API token {}", path.display());
+    }
+    Ok(token.to_string())
+}
+
+/// Token this client sends, from `$FTM_TOKEN`.
+pub fn from_env() -> Option<String> {
+    std::env::var("FTM_TOKEN")
+        .ok()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
@@ -0,0 +1,238 @@
+//! Login-time autostart of `ftm serve --resume`.
+//! Linux: systemd user unit; macOS: LaunchAgent plist; Windows: HKCU Run key.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Command line the autostart entry runs: the current binary serving with `--resume`,
+/// logging into the user state directory.
+fn serve_args(port: Option<u16>) -> Result<(PathBuf, Vec<String>)> {
+    let exe = std::env::current_exe().context("Failed to locate the ftm executable")?;
+    let mut args = Vec::new();
+    if let Some(port) = port {
+        args.extend(["--port".to_string(), port.to_string()]);
+    }
+    args.extend(["serve".to_string(), "--resume".to_string()]);
+    if let Some(state_dir) = crate::user_state::state_dir() {
+        args.push("--log-dir".to_string());
+        args.push(state_dir.join("logs").to_string_lossy().into_owned());
+    }
+    Ok((exe, args))
+}
+
+/// Run a helper command (systemctl, launchctl); failures are reported, not fatal,
+/// so the entry is still installed when e.g. no user session bus is available.
+#[cfg_attr(windows, allow(dead_code))]
+fn run_best_effort(program: &str, args: &[&str]) -> bool {
+    match Command::new(program).args(args).output() {
+        Ok(out) if out.status.success() => true,
+        Ok(out) => {
+            eprintln!(
+                "Warning: '{} {}' failed: {}",
+                program,
+                args.join(" "),
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            false
+        }
+        Err(e) => {
+            eprintln!("Warning: could not run '{}': {}", program, e);
+            false
+        }
+    }
+}
+
+#[cfg_attr(windows, allow(dead_code))]
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+
+    const UNIT_NAME: &str = "ftm.service";
+
+    fn unit_path() -> Result<PathBuf> {
+        let config = dirs::config_dir().context("No config directory available")?;
+        Ok(config.join("systemd").join("user").join(UNIT_NAME))
+    }
+
+    /// Quote an argument for a systemd ExecStart line.
+    fn quote(arg: &str) -> String {
+        if arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+            format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+        } else {
+            arg.to_string()
+        }
+    }
+
+    pub fn enable(port: Option<u16>) -> Result<PathBuf> {
+        let (exe, args) = serve_args(port)?;
+        let exec = std::iter::once(exe.to_string_lossy().into_owned())
+            .chain(args)
+            .map(|a| quote(&a))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let unit = format!(
+            "[Unit]\n\
+             Description=FTM file version tracking\n\
+             \n\
+             [Service]\n\
+             ExecStart={}\n\
+             Restart=on-failure\n\
+             \n\
+             [Install]\n\
+             WantedBy=default.target\n",
+            exec
+        );
+        let path = unit_path()?;
+        write_file(&path, &unit)?;
+        if run_best_effort("systemctl", &["--user", "daemon-reload"]) {
+            run_best_effort("systemctl", &["--user", "enable", UNIT_NAME]);
+        }
+        Ok(path)
+    }
+
+    pub fn disable() -> Result<Option<PathBuf>> {
+        let path = unit_path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        run_best_effort("systemctl", &["--user", "disable", UNIT_NAME]);
+        std::fs::remove_file(&path)?;
+        run_best_effort("systemctl", &["--user", "daemon-reload"]);
+        Ok(Some(path))
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+
+    const LABEL: &str = "com.ftm.serve";
+
+    fn plist_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().context("No home directory available")?;
+        Ok(home
+            .join("Library")
+            .join("LaunchAgents")
+            .join(format!("{}.plist", LABEL)))
+    }
+
+    fn escape_xml(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    pub fn enable(port: Option<u16>) -> Result<PathBuf> {
+        let (exe, args) = serve_args(port)?;
+        let program_args: String = std::iter::once(exe.to_string_lossy().into_owned())
+            .chain(args)
+            .map(|a| format!("        <string>{}</string>\n", escape_xml(&a)))
+            .collect();
+        let plist = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n\
+             <dict>\n\
+             \x20   <key>Label</key>\n\
+             \x20   <string>{}</string>\n\
+             \x20   <key>ProgramArguments</key>\n\
+             \x20   <array>\n\
+             {}\
+             \x20   </array>\n\
+             \x20   <key>RunAtLoad</key>\n\
+             \x20   <true/>\n\
+             </dict>\n\
+             </plist>\n",
+            LABEL, program_args
+        );
+        let path = plist_path()?;
+        write_file(&path, &plist)?;
+        run_best_effort("launchctl", &["load", "-w", &path.to_string_lossy()]);
+        Ok(path)
+    }
+
+    pub fn disable() -> Result<Option<PathBuf>> {
+        let path = plist_path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        run_best_effort("launchctl", &["unload", "-w", &path.to_string_lossy()]);
+        std::fs::remove_file(&path)?;
+        Ok(Some(path))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::*;
+
+    const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+    const VALUE_NAME: &str = "ftm";
+
+    pub fn enable(port: Option<u16>) -> Result<PathBuf> {
+        let (exe, args) = serve_args(port)?;
+        let command = std::iter::once(exe.to_string_lossy().into_owned())
+            .chain(args)
+            .map(|a| format!("\"{}\"", a))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let status = Command::new("reg")
+            .args([
+                "add", RUN_KEY, "/v", VALUE_NAME, "/t", "REG_SZ", "/d", &command, "/f",
+            ])
+            .status()
+            .context("Failed to run reg.exe")?;
+        if !status.success() {
+            anyhow::bail!("Failed to write the Run registry key");
+        }
+        Ok(PathBuf::from(format!(r"{}\{}", RUN_KEY, VALUE_NAME)))
+    }
+
+    pub fn disable() -> Result<Option<PathBuf>> {
+        let removed = Command::new("reg")
+            .args(["delete", RUN_KEY, "/v", VALUE_NAME, "/f"])
+            .output()
+            .context("Failed to run reg.exe")?
+            .status
+            .success();
+        Ok(removed.then(|| PathBuf::from(format!(r"{}\{}", RUN_KEY, VALUE_NAME))))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use super::*;
+
+    pub fn enable(_port: Option<u16>) -> Result<PathBuf> {
+        anyhow::bail!("Autostart is not supported on this platform")
+    }
+
+    pub fn disable() -> Result<Option<PathBuf>> {
+        anyhow::bail!("Autostart is not supported on this platform")
+    }
+}
+
+/// Install the login-time entry. `port` is passed through to `ftm serve` when given.
+pub fn enable(port: Option<u16>) -> Result<()> {
+    let location = platform::enable(port)?;
+    println!("Autostart enabled: {}", location.display());
+    println!("At login, ftm serve --resume will resume watching the last checked-out directory.");
+    Ok(())
+}
+
+/// Remove the login-time entry, if installed.
+pub fn disable() -> Result<()> {
+    match platform::disable()? {
+        Some(location) => println!("Autostart disabled: removed {}", location.display()),
+        None => println!("Autostart is not enabled."),
+    }
+    Ok(())
+}
@@ -0,0 +1,393 @@
+//! Portable index bundles (`ftm export-index` / `ftm import-index`) for moving history
+//! between machines. A bundle is a directory holding `manifest.json` (format tag,
+//! history entries and the snapshots they reference, with algorithm and size) and
+//! `snapshots/<checksum>` for each of those snapshots. Every snapshot is checked
+//! against the manifest before anything is imported.
+//!
+//! `ftm export-file` / `ftm import-file` use the same layout for the history of a
+//! single file, packed into one tar archive that is easy to send to someone.
+
+use crate::client::{
+    base_url, check_response, client_health, handle_connection_error, make_client,
+    resolve_tracked_file, ServerTarget, SyncTarget,
+};
+use crate::storage::ChecksumVerifier;
+use crate::store::is_checksum_hex;
+use crate::tar;
+use crate::types::{HashAlgo, HistoryEntry, Operation};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const FORMAT: &str = "ftm-index-bundle";
+const VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+const SNAPSHOTS: &str = "snapshots";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Always `ftm-index-bundle`.
+    pub format: String,
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    /// Directory the exporting server watched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The file whose history this is, for bundles written by `export-file`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    pub entries: Vec<HistoryEntry>,
+    pub snapshots: Vec<SnapshotRef>,
+}
+
+/// A snapshot stored in the bundle.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotRef {
+    pub checksum: String,
+    #[serde(default)]
+    pub algo: HashAlgo,
+    pub size: u64,
+}
+
+#[derive(Serialize)]
+struct FileQuery<'a> {
+    file: &'a str,
+}
+
+#[derive(Serialize)]
+struct ChecksumQuery<'a> {
+    checksum: &'a str,
+}
+
+/// Reads one snapshot of a bundle by checksum.
+type Open<'a> = dyn Fn(&str) -> Result<Box<dyn Read + Send>> + 'a;
+
+/// Checksum of the snapshot an entry references, if it has one.
+fn snapshot_of(entry: &HistoryEntry) -> Option<&str> {
+    match entry.op {
+        Operation::Delete | Operation::Truncate => None,
+        _ => entry.checksum.as_deref(),
+    }
+}
+
+fn fetch_index(port: u16) -> Result<Vec<HistoryEntry>> {
+    let resp = make_client()
+        .get(format!("{}/api/index", base_url(port)))
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    resp.json().context("Failed to parse index response")
+}
+
+/// Download every snapshot `entries` reference, streaming each into `save`, which
+/// returns how many bytes it wrote.
+fn fetch_snapshots(
+    port: u16,
+    entries: &[HistoryEntry],
+    mut save: impl FnMut(&str, &mut dyn Read) -> Result<u64>,
+) -> Result<Vec<SnapshotRef>> {
+    let mut algos: BTreeMap<&str, HashAlgo> = BTreeMap::new();
+    for entry in entries {
+        if let Some(checksum) = snapshot_of(entry) {
+            algos.entry(checksum).or_insert(entry.algo);
+        }
+    }
+
+    let client = make_client();
+    let mut snapshots = Vec::with_capacity(algos.len());
+    for (checksum, algo) in algos {
+        let resp = client
+            .get(format!("{}/api/snapshot", base_url(port)))
+            .query(&ChecksumQuery { checksum })
+            .send()
+            .map_err(handle_connection_error)?;
+        let mut content = check_response(resp)?;
+        let size = save(checksum, &mut content).context("Failed to read snapshot")?;
+        snapshots.push(SnapshotRef {
+            checksum: checksum.to_string(),
+            algo,
+            size,
+        });
+    }
+    Ok(snapshots)
+}
+
+/// Write the server's history and every snapshot it references into the bundle
+/// directory `dir`, which must not contain a bundle yet.
+pub fn export(port: u16, dir: &Path) -> Result<()> {
+    let manifest_path = dir.join(MANIFEST);
+    if manifest_path.exists() {
+        anyhow::bail!("{} already exists", manifest_path.display());
+    }
+    let source = client_health(port)?.watch_dir;
+    let entries = fetch_index(port)?;
+
+    let snapshots_dir = dir.join(SNAPSHOTS);
+    std::fs::create_dir_all(&snapshots_dir)
+        .with_context(|| format!("Failed to create {}", snapshots_dir.display()))?;
+    let snapshots = fetch_snapshots(port, &entries, |checksum, content| {
+        let mut file = std::fs::File::create(snapshots_dir.join(checksum))?;
+        Ok(std::io::copy(content, &mut file)?)
+    })?;
+
+    let manifest = Manifest {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        exported_at: Utc::now(),
+        source,
+        file: None,
+        entries,
+        snapshots,
+    };
+    std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)
+        .with_context(|| format!("Failed to write {}", manifest_path.display()))?;
+    println!(
+        "Exported {} entries ({} snapshots) to {}",
+        manifest.entries.len(),
+        manifest.snapshots.len(),
+        dir.display()
+    );
+    Ok(())
+}
+
+/// Write the history of `file` and every version of it into the tar archive `output`,
+/// which must not exist yet. The manifest comes last, after the snapshots.
+pub fn export_file(port: u16, file: &str, output: &Path) -> Result<()> {
+    if output.exists() {
+        anyhow::bail!("{} already exists", output.display());
+    }
+    let source = client_health(port)?.watch_dir;
+    let file = &resolve_tracked_file(port, file);
+    let resp = make_client()
+        .get(format!("{}/api/history", base_url(port)))
+        .query(&FileQuery { file })
+        .send()
+        .map_err(handle_connection_error)?;
+    let entries: Vec<HistoryEntry> = check_response(resp)?
+        .json()
+        .context("Failed to parse history response")?;
+    if entries.is_empty() {
+        anyhow::bail!("No history for '{}'", file);
+    }
+
+    let out = std::fs::File::create_new(output)
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    let write = || -> Result<Manifest> {
+        let mut out = std::io::BufWriter::new(out);
+        let now = Utc::now();
+        let snapshots = fetch_snapshots(port, &entries, |checksum, content| {
+            let name = format!("{}/{}", SNAPSHOTS, checksum);
+            tar::append_from(&mut out, &name, content, now.timestamp())
+        })?;
+        let manifest = Manifest {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            exported_at: now,
+            source,
+            file: Some(file.to_string()),
+            entries,
+            snapshots,
+        };
+        let json = serde_json::to_vec_pretty(&manifest)?;
+        tar::append(&mut out, MANIFEST, &json, now.timestamp())?;
+        tar::finish(&mut out)?;
+        std::io::Write::flush(&mut out)?;
+        Ok(manifest)
+    };
+    let manifest = write().inspect_err(|_| {
+        let _ = std::fs::remove_file(output);
+    })?;
+    println!(
+        "Exported {} entries ({} versions) of '{}' to {}",
+        manifest.entries.len(),
+        manifest.snapshots.len(),
+        file,
+        output.display()
+    );
+    Ok(())
+}
+
+fn parse_manifest(content: &[u8], origin: &Path) -> Result<Manifest> {
+    let manifest: Manifest = serde_json::from_slice(content)
+        .with_context(|| format!("Invalid bundle manifest in {}", origin.display()))?;
+    if manifest.format != FORMAT {
+        anyhow::bail!("{} is not an ftm index bundle", origin.display());
+    }
+    if manifest.version > VERSION {
+        anyhow::bail!(
+            "Bundle version {} is newer than this ftm supports ({})",
+            manifest.version,
+            VERSION
+        );
+    }
+    Ok(manifest)
+}
+
+/// Check the bundle at `origin` is complete: every snapshot `open` streams matches its
+/// checksum and size, and every entry's snapshot is part of the bundle.
+fn validate(manifest: &Manifest, origin: &Path, open: &Open) -> Result<()> {
+    let mut problems = Vec::new();
+    for snapshot in &manifest.snapshots {
+        let short = &snapshot.checksum[..8.min(snapshot.checksum.len())];
+        if !is_checksum_hex(&snapshot.checksum) {
+            problems.push(format!("invalid checksum {}", snapshot.checksum));
+            continue;
+        }
+        let Ok(mut content) = open(&snapshot.checksum) else {
+            problems.push(format!("snapshot {} is missing", short));
+            continue;
+        };
+        let mut verifier = ChecksumVerifier::new(Some(snapshot.algo));
+        match std::io::copy(&mut content, &mut verifier) {
+            Err(e) => problems.push(format!("snapshot {} could not be read: {}", short, e)),
+            Ok(size) if size != snapshot.size => {
+                problems.push(format!("snapshot {} has the wrong size", short))
+            }
+            Ok(_) if !verifier.matches(&snapshot.checksum) => {
+                problems.push(format!("snapshot {} does not match its checksum", short))
+            }
+            Ok(_) => {}
+        }
+    }
+    let listed: HashSet<&str> = manifest
+        .snapshots
+        .iter()
+        .map(|s| s.checksum.as_str())
+        .collect();
+    for entry in &manifest.entries {
+        if let Some(checksum) = snapshot_of(entry).filter(|c| !listed.contains(c)) {
+            problems.push(format!(
+                "snapshot {} of '{}' is not in the bundle",
+                &checksum[..8.min(checksum.len())],
+                entry.file
+            ));
+        }
+    }
+
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "Bundle {} failed validation:\n  {}",
+            origin.display(),
+            problems.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Streams the snapshots of the bundle directory `dir`.
+fn open_in_dir(dir: &Path) -> impl Fn(&str) -> Result<Box<dyn Read + Send>> + '_ {
+    move |checksum| {
+        Ok(Box::new(std::fs::File::open(
+            dir.join(SNAPSHOTS).join(checksum),
+        )?))
+    }
+}
+
+/// Read the bundle in `dir` and check it is complete.
+pub fn load(dir: &Path) -> Result<Manifest> {
+    let manifest_path = dir.join(MANIFEST);
+    let content = std::fs::read(&manifest_path)
+        .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+    let manifest = parse_manifest(&content, &manifest_path)?;
+    validate(&manifest, dir, &open_in_dir(dir))?;
+    Ok(manifest)
+}
+
+/// The archive written by `export-file`, with where each snapshot sits in it.
+struct Archive<'a> {
+    path: &'a Path,
+    snapshots: HashMap<String, tar::Member>,
+}
+
+impl Archive<'_> {
+    /// Stream the snapshot `checksum` straight out of the archive.
+    fn open(&self, checksum: &str) -> Result<Box<dyn Read + Send>> {
+        let member = self
+            .snapshots
+            .get(checksum)
+            .context("Snapshot missing from archive")?;
+        let mut file = std::fs::File::open(self.path)?;
+        file.seek(SeekFrom::Start(member.offset))?;
+        Ok(Box::new(file.take(member.size)))
+    }
+}
+
+/// Read the archive written by `export-file` and check it is complete. Returns the
+/// manifest and the archive.
+fn load_archive(path: &Path) -> Result<(Manifest, Archive<'_>)> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut manifest = None;
+    let mut snapshots = HashMap::new();
+    for member in
+        tar::members(&mut file).with_context(|| format!("Failed to read {}", path.display()))?
+    {
+        if member.name == MANIFEST {
+            let mut content = Vec::new();
+            file.seek(SeekFrom::Start(member.offset))?;
+            (&mut file).take(member.size).read_to_end(&mut content)?;
+            manifest = Some(parse_manifest(&content, path)?);
+        } else if let Some(checksum) = member.name.strip_prefix(&format!("{}/", SNAPSHOTS)) {
+            snapshots.insert(checksum.to_string(), member);
+        }
+    }
+    let manifest = manifest.with_context(|| format!("{} has no {}", path.display(), MANIFEST))?;
+    let archive = Archive { path, snapshots };
+    validate(&manifest, path, &|checksum| archive.open(checksum))?;
+    Ok((manifest, archive))
+}
+
+/// Add the entries of a validated bundle and the snapshots they need, streamed with
+/// `open`, to the server's history. Entries already present are skipped and imported
+/// entries get new local seqs; signatures are kept and still verify.
+fn import_manifest(port: u16, manifest: &Manifest, origin: &Path, open: &Open) -> Result<()> {
+    let have: HashSet<String> = fetch_index(port)?
+        .iter()
+        .filter_map(snapshot_of)
+        .map(str::to_string)
+        .collect();
+
+    let server = ServerTarget::new(&base_url(port));
+    let mut uploaded = 0;
+    for snapshot in &manifest.snapshots {
+        if have.contains(&snapshot.checksum) {
+            continue;
+        }
+        server.store_snapshot(&snapshot.checksum, open(&snapshot.checksum)?)?;
+        uploaded += 1;
+    }
+
+    let added = server.merge(&manifest.entries)?;
+    if added == 0 {
+        println!(
+            "History already contains every entry of {}",
+            origin.display()
+        );
+    } else {
+        println!(
+            "Imported {} entries ({} snapshots) from {}",
+            added,
+            uploaded,
+            origin.display()
+        );
+    }
+    Ok(())
+}
+
+/// Validate the bundle in `dir`, then add its history to the server's.
+pub fn import(port: u16, dir: &Path) -> Result<()> {
+    let manifest = load(dir)?;
+    import_manifest(port, &manifest, dir, &open_in_dir(dir))
+}
+
+/// Validate the archive written by `export-file`, then add the file's history to the
+/// server's. The file keeps its path relative to the watch directory.
+pub fn import_file(port: u16, archive: &Path) -> Result<()> {
+    let (manifest, snapshots) = load_archive(archive)?;
+    import_manifest(port, &manifest, archive, &|checksum| {
+        snapshots.open(checksum)
+    })
+}
@@ -1,5 +1,8 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
 
 // ---------------------------------------------------------------------------
 // Response types (mirrors server types for deserialization)
@@ -12,11 +15,14 @@ struct MessageResponse {
 
 #[derive(Deserialize)]
 pub struct HealthInfo {
-    #[allow(dead_code)]
     pub status: String,
-    #[allow(dead_code)]
     pub pid: Option<u32>,
     pub watch_dir: Option<String>,
+    #[serde(default)]
+    pub mirror_of: Option<String>,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "tray"), allow(dead_code))]
+    pub paused: bool,
 }
 
 #[derive(Deserialize)]
@@ -34,6 +40,22 @@ pub struct HistoryEntry {
     pub file: String,
     pub checksum: Option<String>,
     pub size: Option<u64>,
+    #[serde(default)]
+    pub size_delta: Option<i64>,
+    #[serde(default)]
+    pub lines: Option<usize>,
+    #[serde(default)]
+    pub lines_delta: Option<i64>,
+    #[serde(default)]
+    pub writer: Option<String>,
+    #[serde(default)]
+    pub user_initiated: bool,
+    #[serde(default)]
+    pub trashed: bool,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub restored_from: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -42,25 +64,90 @@ pub struct ScanResult {
     pub modified: usize,
     pub deleted: usize,
     pub unchanged: usize,
+    #[serde(default)]
+    pub snapshots_paused: bool,
+    #[serde(default)]
+    pub changes: Vec<ScanChange>,
+}
+
+#[derive(Deserialize)]
+pub struct ScanChange {
+    pub file: String,
+    pub op: String,
 }
 
 #[derive(Deserialize)]
 struct CleanResult {
     entries_trimmed: usize,
     bytes_freed_trim: u64,
+    #[serde(default)]
+    entries_collapsed: usize,
     files_removed: usize,
     bytes_removed: u64,
+    #[serde(default)]
+    trash_purged: usize,
+    #[serde(default)]
+    tmp_removed: usize,
+    #[serde(default)]
+    dirs_removed: usize,
+}
+
+#[derive(Deserialize)]
+struct UsageSample {
+    at: String,
+    history: usize,
+    quota: u64,
+}
+
+#[derive(Deserialize)]
+struct UncleanResult {
+    entries_restored: usize,
+    snapshots_restored: usize,
+}
+
+#[derive(Deserialize)]
+struct OrphanSnapshot {
+    checksum: String,
+    size: u64,
+    #[serde(default)]
+    modified: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MigrateStoreResult {
+    snapshots_moved: usize,
+    dirs_removed: usize,
 }
 
 #[derive(Serialize)]
 struct CheckoutRequest {
     directory: String,
+    force: bool,
 }
 
 #[derive(Serialize)]
 struct RestoreRequest {
     file: String,
     checksum: String,
+    from_trash: bool,
+}
+
+#[derive(Serialize)]
+struct RmRequest {
+    file: String,
+}
+
+#[derive(Serialize)]
+struct ShareRequest {
+    file: String,
+    checksum: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct ShareResponse {
+    path: String,
+    expires_at: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Deserialize)]
@@ -91,26 +178,202 @@ struct StatsInfo {
     max_history: usize,
     quota: u64,
     max_quota: u64,
+    #[serde(default)]
+    free_space: Option<u64>,
+    #[serde(default)]
+    min_free_space: u64,
+    #[serde(default)]
+    quota_warn_percent: u64,
+    #[serde(default)]
+    corrupt_count: usize,
+    #[serde(default)]
+    missing_count: usize,
+    #[serde(default)]
+    verified_at: Option<String>,
+    #[serde(default)]
+    last_scan: Option<LastScanInfo>,
+}
+
+#[derive(Deserialize)]
+struct LastScanInfo {
+    at: String,
+    created: usize,
+    modified: usize,
+    deleted: usize,
+}
+
+#[derive(Deserialize)]
+struct DedupInfo {
+    references: usize,
+    snapshots: usize,
+    logical_bytes: u64,
+    physical_bytes: u64,
+    duplicate_references: usize,
+}
+
+#[derive(Deserialize)]
+struct AgeBucket {
+    age: String,
+    snapshots: usize,
+    bytes: u64,
+}
+
+#[derive(Serialize)]
+struct AuditQuery<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    op: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct SimilarQuery {
+    threshold: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SimilarInfo {
+    file: String,
+    source_file: String,
+    similarity: u8,
+    kind: String,
+}
+
+#[derive(Serialize)]
+struct SignaturesQuery<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    public_key: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct SignatureReport {
+    public_key: String,
+    valid: usize,
+    unsigned: usize,
+    invalid: Vec<InvalidSignature>,
+    #[serde(default)]
+    complete: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct InvalidSignature {
+    seq: u64,
+    file: String,
+}
+
+#[derive(Serialize)]
+struct SyncBlobQuery<'a> {
+    checksum: &'a str,
+}
+
+/// History entry as exchanged by push/pull; passed through between servers unchanged.
+/// Only the fields that identify it are read, every other one (signature included) is
+/// carried along in `rest`.
+#[derive(Serialize, Deserialize)]
+pub struct SyncEntry {
+    timestamp: String,
+    op: String,
+    file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+    #[serde(flatten)]
+    rest: serde_json::Map<String, serde_json::Value>,
+}
+
+impl SyncEntry {
+    fn key(&self) -> (&str, &str, &str, Option<&str>) {
+        (
+            &self.timestamp,
+            &self.op,
+            &self.file,
+            self.checksum.as_deref(),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct SyncEntriesResult {
+    added: usize,
 }
 
 // ---------------------------------------------------------------------------
 // Client helpers
 // ---------------------------------------------------------------------------
 
-fn base_url(port: u16) -> String {
-    format!("http://127.0.0.1:{}", port)
+/// Host all client requests go to; loopback unless set otherwise at startup.
+static SERVER_HOST: Mutex<Option<IpAddr>> = Mutex::new(None);
+
+/// Set the host used by subsequent client requests.
+pub fn set_server_host(host: IpAddr) {
+    *SERVER_HOST.lock().unwrap() = Some(host);
+}
+
+/// Server on another machine named by `--host` or `FTM_SERVER`, as it goes in a URL;
+/// takes precedence over `SERVER_HOST`.
+static REMOTE_HOST: Mutex<Option<String>> = Mutex::new(None);
+
+/// Send subsequent client requests to `host`, a name or an address.
+pub fn set_remote_host(host: &str) {
+    let host = match host.parse::<std::net::Ipv6Addr>() {
+        Ok(ip) => format!("[{}]", ip),
+        Err(_) => host.to_string(),
+    };
+    *REMOTE_HOST.lock().unwrap() = Some(host);
+}
+
+/// Project of a shared daemon that client requests go to (see `ServerInfo::project`).
+static SERVER_PROJECT: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn set_server_project(project: Option<String>) {
+    *SERVER_PROJECT.lock().unwrap() = project;
+}
+
+/// URL of the server on `port` itself, ignoring any project (IPv6 hosts are bracketed).
+pub fn daemon_url(port: u16) -> String {
+    if let Some(host) = REMOTE_HOST.lock().unwrap().as_deref() {
+        return format!("http://{}:{}", host, port);
+    }
+    let host = SERVER_HOST
+        .lock()
+        .unwrap()
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    format!("http://{}", SocketAddr::new(host, port))
+}
+
+/// Base URL of the server on `port`, or of the selected project on it.
+pub fn base_url(port: u16) -> String {
+    match SERVER_PROJECT.lock().unwrap().as_deref() {
+        Some(project) => format!("{}/projects/{}", daemon_url(port), project),
+        None => daemon_url(port),
+    }
 }
 
-fn make_client() -> reqwest::blocking::Client {
+pub fn make_client() -> reqwest::blocking::Client {
+    let mut headers = reqwest::header::HeaderMap::new();
+    // Servers on other machines only take changes with their API token
+    if let Some(token) = crate::api_token::from_env() {
+        if let Ok(value) = format!("Bearer {}", token).parse() {
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+    }
     reqwest::blocking::Client::builder()
         .no_proxy()
+        .default_headers(headers)
         .build()
         .expect("failed to build HTTP client")
 }
 
 /// Send a request and handle connection errors with a friendly message.
-fn handle_connection_error(err: reqwest::Error) -> anyhow::Error {
-    if err.is_connect() {
+pub fn handle_connection_error(err: reqwest::Error) -> anyhow::Error {
+    if err.is_connect() && REMOTE_HOST.lock().unwrap().is_some() {
+        anyhow::anyhow!(
+            "No ftm server reachable at {}",
+            err.url()
+                .map_or(String::new(), |u| u.origin().ascii_serialization())
+        )
+    } else if err.is_connect() {
         anyhow::anyhow!("Server not running. Use 'ftm checkout <dir>' to start.")
     } else {
         err.into()
@@ -118,7 +381,7 @@ fn handle_connection_error(err: reqwest::Error) -> anyhow::Error {
 }
 
 /// Extract error message from a non-success HTTP response.
-fn check_response(resp: reqwest::blocking::Response) -> Result<reqwest::blocking::Response> {
+pub fn check_response(resp: reqwest::blocking::Response) -> Result<reqwest::blocking::Response> {
     if resp.status().is_success() {
         Ok(resp)
     } else {
@@ -181,11 +444,12 @@ pub fn wait_for_server_shutdown(port: u16, timeout: std::time::Duration) -> bool
     }
 }
 
-pub fn client_checkout(port: u16, directory: &str) -> Result<()> {
+pub fn client_checkout(port: u16, directory: &str, force: bool) -> Result<()> {
     let resp = make_client()
         .post(format!("{}/api/checkout", base_url(port)))
         .json(&CheckoutRequest {
             directory: directory.to_string(),
+            force,
         })
         .send()
         .map_err(handle_connection_error)?;
@@ -195,11 +459,78 @@ pub fn client_checkout(port: u16, directory: &str) -> Result<()> {
     Ok(())
 }
 
+#[derive(Serialize)]
+struct AddProjectRequest {
+    directory: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AddProjectResponse {
+    pub name: String,
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct ProjectInfo {
+    pub name: String,
+    pub watch_dir: Option<String>,
+}
+
+/// Web UI address of a project on the server at `port`.
+pub fn project_url(port: u16, name: &str) -> String {
+    format!("{}/projects/{}/", daemon_url(port), name)
+}
+
+pub fn project_add(port: u16, directory: &str, name: Option<&str>) -> Result<AddProjectResponse> {
+    let resp = make_client()
+        .post(format!("{}/api/projects", daemon_url(port)))
+        .json(&AddProjectRequest {
+            directory: directory.to_string(),
+            name: name.map(String::from),
+        })
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    resp.json().context("Failed to parse response")
+}
+
+pub fn projects(port: u16) -> Result<Vec<ProjectInfo>> {
+    let resp = make_client()
+        .get(format!("{}/api/projects", daemon_url(port)))
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    resp.json().context("Failed to parse response")
+}
+
+pub fn client_project_add(port: u16, directory: &str, name: Option<&str>) -> Result<()> {
+    let added = project_add(port, directory, name)?;
+    println!("{}", added.message);
+    println!("Web UI: {}", project_url(port, &added.name));
+    Ok(())
+}
+
+pub fn client_project_list(port: u16) -> Result<()> {
+    let projects = projects(port)?;
+    if projects.is_empty() {
+        println!("No projects. Add one with 'ftm project add <dir>'.");
+    }
+    for p in projects {
+        println!("  {}  {}", p.name, p.watch_dir.as_deref().unwrap_or("-"));
+    }
+    Ok(())
+}
+
 pub fn client_ls(port: u16, include_deleted: bool) -> Result<()> {
     // Best-effort: show current watch directory
     if let Ok(health) = client_health(port) {
         if let Some(dir) = &health.watch_dir {
             println!("Watch directory: {}", dir);
+            if health.status == "degraded" {
+                println!("  Unavailable (volume unmounted?); watching resumes when it returns");
+            }
         }
     }
 
@@ -248,14 +579,60 @@ fn print_file_tree(nodes: &[FileTreeNode], prefix: &str) {
     }
 }
 
+/// The index key for a file argument: a path under the checked-out directory, absolute
+/// or relative to the current directory, becomes relative to it; anything else is taken
+/// to be a key already.
+fn resolve_file_arg(port: u16, file: &str) -> String {
+    let Some(watch_dir) = client_health(port).ok().and_then(|h| h.watch_dir) else {
+        return file.to_string();
+    };
+    let Ok(path) = crate::path_util::resolve_cli_path(std::path::Path::new(file)) else {
+        return file.to_string();
+    };
+    match path.strip_prefix(&watch_dir) {
+        Ok(rel) if !rel.as_os_str().is_empty() => {
+            crate::path_util::normalize_rel_path(&rel.to_string_lossy())
+        }
+        _ => file.to_string(),
+    }
+}
+
+/// Like `resolve_file_arg`, but from a subdirectory a key relative to the watch dir
+/// still works when the resolved path has no history and the key does.
+pub fn resolve_tracked_file(port: u16, file: &str) -> String {
+    let resolved = resolve_file_arg(port, file);
+    if resolved != file
+        && fetch_history(port, &resolved).is_ok_and(|h| h.is_empty())
+        && fetch_history(port, file).is_ok_and(|h| !h.is_empty())
+    {
+        return file.to_string();
+    }
+    resolved
+}
+
 pub fn client_history(port: u16, file: &str) -> Result<()> {
-    let resp = make_client()
-        .get(format!("{}/api/history", base_url(port)))
+    let resolved = resolve_file_arg(port, file);
+    let mut entries = fetch_history(port, &resolved)?;
+    // From a subdirectory, a key relative to the watch dir still works
+    let file = if entries.is_empty() && resolved != file {
+        entries = fetch_history(port, file)?;
+        if entries.is_empty() {
+            &resolved
+        } else {
+            file
+        }
+    } else {
+        &resolved
+    };
+    // Best-effort: how many older versions trim removed
+    let trimmed = make_client()
+        .get(format!("{}/api/stats/file", base_url(port)))
         .query(&[("file", file)])
         .send()
-        .map_err(handle_connection_error)?;
-    let resp = check_response(resp)?;
-    let entries: Vec<HistoryEntry> = resp.json().context("Failed to parse response")?;
+        .ok()
+        .and_then(|r| check_response(r).ok())
+        .and_then(|r| r.json::<FileStats>().ok())
+        .map_or(0, |st| st.trimmed_versions);
 
     if entries.is_empty() {
         println!("No history for '{}'", file);
@@ -263,10 +640,19 @@ pub fn client_history(port: u16, file: &str) -> Result<()> {
         println!("History for '{}':", file);
         for entry in entries.iter().rev() {
             let checksum_short = entry.checksum.as_ref().map(|c| &c[..8]).unwrap_or("-");
-            let size_str = entry
+            let mut size_str = entry
                 .size
                 .map(|s| format!("{} bytes", s))
                 .unwrap_or_else(|| "-".to_string());
+            if let Some(delta) = entry.size_delta {
+                size_str.push_str(&format!(" ({:+})", delta));
+            }
+            if let Some(lines) = entry.lines {
+                size_str.push_str(&format!(", {} lines", lines));
+                if let Some(delta) = entry.lines_delta {
+                    size_str.push_str(&format!(" ({:+})", delta));
+                }
+            }
             // Parse and reformat timestamp to local time
             let display_time = match chrono::DateTime::parse_from_rfc3339(&entry.timestamp) {
                 Ok(dt) => {
@@ -275,113 +661,1063 @@ pub fn client_history(port: u16, file: &str) -> Result<()> {
                 }
                 Err(_) => entry.timestamp.clone(),
             };
+            if let Some(writer) = &entry.writer {
+                size_str.push_str(&format!(" | by {}", writer));
+            }
+            if let Some(source) = &entry.source {
+                size_str.push_str(&format!(" | {}", source));
+            }
+            if let Some(at) = &entry.restored_from {
+                let at = chrono::DateTime::parse_from_rfc3339(at)
+                    .map(|dt| {
+                        dt.with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M:%S")
+                            .to_string()
+                    })
+                    .unwrap_or_else(|_| at.clone());
+                size_str.push_str(&format!(" from version {} of {}", checksum_short, at));
+            }
+            if entry.user_initiated {
+                size_str.push_str(" | via ftm rm");
+            }
+            if entry.trashed {
+                size_str.push_str(" | moved to trash");
+            }
             println!(
                 "  {} | {} | {} | {}",
                 display_time, entry.op, checksum_short, size_str
             );
         }
     }
+    print_trimmed_note(trimmed);
     Ok(())
 }
 
-pub fn client_restore(port: u16, file: &str, checksum: &str) -> Result<()> {
-    let resp = make_client()
-        .post(format!("{}/api/restore", base_url(port)))
-        .json(&RestoreRequest {
-            file: file.to_string(),
-            checksum: checksum.to_string(),
-        })
-        .send()
-        .map_err(handle_connection_error)?;
-    let resp = check_response(resp)?;
-    let msg: MessageResponse = resp.json().context("Failed to parse response")?;
-    println!("{}", msg.message);
-    Ok(())
+/// History of `file` (see `resolve_tracked_file`) with its key; an error when it has none.
+fn tracked_history(port: u16, file: &str) -> Result<(String, Vec<HistoryEntry>)> {
+    let file = resolve_tracked_file(port, file);
+    let entries = fetch_history(port, &file)?;
+    if entries.is_empty() {
+        anyhow::bail!("No history for '{}'", file);
+    }
+    Ok((file, entries))
+}
+
+/// Full checksum of the version of `file` among `entries` that starts with `prefix`.
+fn resolve_version(entries: &[HistoryEntry], file: &str, prefix: &str) -> Result<String> {
+    if prefix.len() < 8 {
+        anyhow::bail!("Checksum prefix must be at least 8 characters");
+    }
+    let mut matching: Vec<&str> = entries
+        .iter()
+        .filter_map(|e| e.checksum.as_deref())
+        .filter(|c| c.starts_with(prefix))
+        .collect();
+    matching.sort_unstable();
+    matching.dedup();
+    match matching.as_slice() {
+        [checksum] => Ok(checksum.to_string()),
+        [] => anyhow::bail!("No version of '{}' matches {}", file, prefix),
+        _ => anyhow::bail!(
+            "{} matches several versions of '{}'; give more characters",
+            prefix,
+            file
+        ),
+    }
 }
 
-pub fn client_scan(port: u16) -> Result<()> {
+/// Print version `checksum` of `file`, or with None its latest, to stdout as stored.
+pub fn client_cat(port: u16, file: &str, checksum: Option<&str>) -> Result<()> {
+    let (file, entries) = tracked_history(port, file)?;
+    let checksum = match checksum {
+        Some(prefix) => resolve_version(&entries, &file, prefix)?,
+        None => {
+            let last = entries.last().unwrap();
+            if last.op == "delete" {
+                anyhow::bail!("'{}' is deleted; name the version to print", file);
+            }
+            match last.checksum.clone() {
+                Some(checksum) => checksum,
+                None => return Ok(()), // empty file
+            }
+        }
+    };
     let resp = make_client()
-        .post(format!("{}/api/scan", base_url(port)))
+        .get(format!("{}/api/snapshot", base_url(port)))
+        .query(&[("checksum", &checksum)])
         .send()
         .map_err(handle_connection_error)?;
-    let resp = check_response(resp)?;
-    let result: ScanResult = resp.json().context("Failed to parse response")?;
-    println!(
-        "Scan complete: {} created, {} modified, {} deleted, {} unchanged",
-        result.created, result.modified, result.deleted, result.unchanged
-    );
+    let mut resp = check_response(resp)?;
+    let mut stdout = std::io::stdout().lock();
+    resp.copy_to(&mut stdout)
+        .context("Failed to read the version")?;
+    std::io::Write::flush(&mut stdout)?;
     Ok(())
 }
 
-fn format_bytes(n: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
-    if n >= GB {
-        format!("{:.1} GB", n as f64 / GB as f64)
-    } else if n >= MB {
-        format!("{:.1} MB", n as f64 / MB as f64)
-    } else if n >= KB {
-        format!("{:.1} KB", n as f64 / KB as f64)
-    } else {
-        format!("{} bytes", n)
-    }
+#[derive(Deserialize)]
+struct DiffResult {
+    hunks: Vec<DiffHunk>,
+    #[serde(default)]
+    truncated: bool,
 }
 
-pub fn client_clean(port: u16) -> Result<()> {
+#[derive(Deserialize)]
+struct DiffHunk {
+    old_start: usize,
+    new_start: usize,
+    lines: Vec<DiffLine>,
+}
+
+#[derive(Deserialize)]
+struct DiffLine {
+    tag: String,
+    content: String,
+}
+
+/// Print the changes from version `from` to version `to` of `file` as a unified diff.
+/// Without `to` it is the latest version; without `from` the one before `to`.
+pub fn client_diff(port: u16, file: &str, from: Option<&str>, to: Option<&str>) -> Result<()> {
+    let (file, entries) = tracked_history(port, file)?;
+    // Checksums of the versions with content, oldest first (None: empty)
+    let versions: Vec<Option<&str>> = entries
+        .iter()
+        .filter(|e| e.op != "delete")
+        .map(|e| e.checksum.as_deref())
+        .collect();
+    let to = match to {
+        Some(prefix) => Some(resolve_version(&entries, &file, prefix)?),
+        None => versions
+            .last()
+            .with_context(|| format!("'{}' has no versions with content", file))?
+            .map(str::to_string),
+    };
+    let Some(to) = to else {
+        anyhow::bail!("The version to compare is empty; name another");
+    };
+    let from = match from {
+        Some(prefix) => Some(resolve_version(&entries, &file, prefix)?),
+        None => {
+            let at = versions.iter().rposition(|c| *c == Some(to.as_str()));
+            at.and_then(|i| i.checked_sub(1))
+                .and_then(|i| versions[i])
+                .map(str::to_string)
+        }
+    };
+
+    let mut query = vec![("to", to.as_str())];
+    if let Some(ref from) = from {
+        query.push(("from", from));
+    }
     let resp = make_client()
-        .post(format!("{}/api/clean", base_url(port)))
+        .get(format!("{}/api/diff", base_url(port)))
+        .query(&query)
         .send()
         .map_err(handle_connection_error)?;
-    let resp = check_response(resp)?;
-    let result: CleanResult = resp.json().context("Failed to parse response")?;
-    if result.entries_trimmed == 0 && result.files_removed == 0 {
-        println!("Clean complete: nothing to remove");
-        return Ok(());
-    }
-    if result.entries_trimmed > 0 {
+    let diff: DiffResult = check_response(resp)?
+        .json()
+        .context("Failed to parse diff response")?;
+
+    let short = |c: Option<&str>| c.map_or_else(|| "empty".to_string(), |c| c[..8].to_string());
+    println!("--- a/{} ({})", file, short(from.as_deref()));
+    println!("+++ b/{} ({})", file, short(Some(&to)));
+    for hunk in &diff.hunks {
+        let old_len = hunk.lines.iter().filter(|l| l.tag != "insert").count();
+        let new_len = hunk.lines.iter().filter(|l| l.tag != "delete").count();
         println!(
-            "Trim: {} history entries trimmed, {} freed",
-            result.entries_trimmed,
-            format_bytes(result.bytes_freed_trim)
+            "@@ -{},{} +{},{} @@",
+            hunk.old_start, old_len, hunk.new_start, new_len
         );
+        for line in &hunk.lines {
+            let sign = match line.tag.as_str() {
+                "insert" => '+',
+                "delete" => '-',
+                _ => ' ',
+            };
+            println!("{}{}", sign, line.content);
+        }
     }
-    if result.files_removed > 0 {
-        println!(
-            "Orphan: {} snapshot(s) removed, {} freed",
-            result.files_removed,
-            format_bytes(result.bytes_removed)
+    if diff.truncated {
+        eprintln!(
+            "Note: only the first lines of these versions were compared; \
+             /api/patch has the full diff."
         );
     }
-    println!("Clean complete");
     Ok(())
 }
 
-pub fn client_stats(port: u16) -> Result<()> {
+pub fn client_rm(port: u16, file: &str) -> Result<()> {
+    let file = &resolve_tracked_file(port, file);
     let resp = make_client()
-        .get(format!("{}/api/stats", base_url(port)))
+        .post(format!("{}/api/rm", base_url(port)))
+        .json(&RmRequest {
+            file: file.to_string(),
+        })
         .send()
         .map_err(handle_connection_error)?;
     let resp = check_response(resp)?;
-    let st: StatsInfo = resp.json().context("Failed to parse stats response")?;
-    println!("History: {} / {}", st.history, st.max_history);
-    println!(
-        "Quota:   {} / {}",
-        format_bytes(st.quota),
-        format_bytes(st.max_quota)
-    );
+    let msg: MessageResponse = resp.json().context("Failed to parse response")?;
+    println!("{}", msg.message);
     Ok(())
 }
 
-pub fn client_version(port: u16) -> Result<()> {
-    println!("Client version: {}", env!("CARGO_PKG_VERSION"));
-
-    match make_client()
-        .get(format!("{}/api/version", base_url(port)))
-        .timeout(std::time::Duration::from_secs(2))
+/// Print a link serving one version of `file` read-only until `expires` has passed.
+pub fn client_share(port: u16, file: &str, checksum: &str, expires: &str) -> Result<()> {
+    let file = &resolve_tracked_file(port, file);
+    let resp = make_client()
+        .post(format!("{}/api/share", base_url(port)))
+        .json(&ShareRequest {
+            file: file.to_string(),
+            checksum: checksum.to_string(),
+            expires_in: parse_duration(expires)?,
+        })
         .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let share: ShareResponse = resp.json().context("Failed to parse response")?;
+    println!("{}{}", base_url(port), share.path);
+    println!(
+        "Expires {}",
+        share
+            .expires_at
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S")
+    );
+    if REMOTE_HOST.lock().unwrap().is_none()
+        && SERVER_HOST.lock().unwrap().is_none_or(|h| h.is_loopback())
     {
-        Ok(resp) => {
+        println!(
+            "The server only listens on this machine; to reach the link from elsewhere, \
+             check out with --bind or set settings.ui_listen"
+        );
+    }
+    Ok(())
+}
+
+/// Restore `file` to version `checksum`, or with None its last version (`--from-trash`).
+pub fn client_restore(port: u16, file: &str, checksum: Option<&str>) -> Result<()> {
+    let file = &resolve_tracked_file(port, file);
+    let resp = make_client()
+        .post(format!("{}/api/restore", base_url(port)))
+        .json(&RestoreRequest {
+            file: file.to_string(),
+            checksum: checksum.unwrap_or_default().to_string(),
+            from_trash: checksum.is_none(),
+        })
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let msg: MessageResponse = resp.json().context("Failed to parse response")?;
+    println!("{}", msg.message);
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct RestoreDirRequest {
+    dir: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RestoredFile {
+    file: String,
+    checksum: String,
+}
+
+#[derive(Deserialize)]
+struct RestoreDirResponse {
+    restored: Vec<RestoredFile>,
+}
+
+pub fn client_restore_dir(port: u16, dir: &str, at: Option<&str>) -> Result<()> {
+    let at = at
+        .map(parse_timestamp)
+        .transpose()?
+        .map(|dt| dt.to_rfc3339());
+    let resp = make_client()
+        .post(format!("{}/api/restore-dir", base_url(port)))
+        .json(&RestoreDirRequest {
+            dir: dir.to_string(),
+            at,
+        })
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let result: RestoreDirResponse = resp.json().context("Failed to parse response")?;
+    if result.restored.is_empty() {
+        println!("No deleted files to restore under '{}'", dir);
+        return Ok(());
+    }
+    for f in &result.restored {
+        println!("  {}  {}", &f.checksum[..8.min(f.checksum.len())], f.file);
+    }
+    println!("Restored {} file(s) under '{}'", result.restored.len(), dir);
+    Ok(())
+}
+
+/// Download a zip of every file under `dir` as it was at `at` (default: now) into
+/// `output`, which must not exist yet.
+pub fn client_export_dir(
+    port: u16,
+    dir: &str,
+    at: Option<&str>,
+    output: &std::path::Path,
+) -> Result<()> {
+    if output.exists() {
+        anyhow::bail!("{} already exists", output.display());
+    }
+    let mut req = make_client()
+        .get(format!("{}/api/export-dir", base_url(port)))
+        .query(&[("path", dir)]);
+    if let Some(at) = at {
+        req = req.query(&[("at", parse_timestamp(at)?.to_rfc3339())]);
+    }
+    let resp = check_response(req.send().map_err(handle_connection_error)?)?;
+    let archive = resp.bytes().context("Failed to read response")?;
+    std::fs::File::create_new(output)
+        .and_then(|mut f| std::io::Write::write_all(&mut f, &archive))
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    println!(
+        "Exported '{}' as of {} to {} ({})",
+        dir,
+        at.unwrap_or("now"),
+        output.display(),
+        format_bytes(archive.len() as u64)
+    );
+    Ok(())
+}
+
+pub fn client_scan(port: u16, dry_run: bool, force: bool, verbose: bool, json: bool) -> Result<()> {
+    let mut req = make_client().post(format!("{}/api/scan", base_url(port)));
+    if dry_run {
+        req = req.query(&[("dry_run", "true")]);
+    }
+    if force {
+        req = req.query(&[("force", "true")]);
+    }
+    if verbose || json {
+        req = req.query(&[("details", "true")]);
+    }
+    let resp = req.send().map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    if json {
+        let mut result: serde_json::Value = resp.json().context("Failed to parse response")?;
+        // The server leaves out an empty list; always include it here
+        if let Some(obj) = result.as_object_mut() {
+            obj.entry("changes")
+                .or_insert_with(|| serde_json::json!([]));
+        }
+        println!("{}", serde_json::to_string_pretty(&result)?);
+        return Ok(());
+    }
+    let result: ScanResult = resp.json().context("Failed to parse response")?;
+    if dry_run {
+        for change in &result.changes {
+            let action = match change.op.as_str() {
+                "create" => "create",
+                "delete" => "delete",
+                _ => "modify",
+            };
+            println!("Would {}: {}", action, change.file);
+        }
+        println!(
+            "Dry run: {} created, {} modified, {} deleted, {} unchanged (nothing recorded)",
+            result.created, result.modified, result.deleted, result.unchanged
+        );
+        return Ok(());
+    }
+    for change in &result.changes {
+        let action = match change.op.as_str() {
+            "create" => "Created",
+            "delete" => "Deleted",
+            _ => "Modified",
+        };
+        println!("{}: {}", action, change.file);
+    }
+    println!(
+        "Scan complete: {} created, {} modified, {} deleted, {} unchanged",
+        result.created, result.modified, result.deleted, result.unchanged
+    );
+    if result.snapshots_paused {
+        println!(
+            "Warning: free disk space is below settings.min_free_space; changed files were not snapshotted"
+        );
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct BulkBeginRequest {
+    timeout_secs: u64,
+}
+
+#[derive(Deserialize)]
+struct BulkBeginResponse {
+    until: String,
+}
+
+pub fn client_bulk_begin(port: u16, timeout: &str) -> Result<()> {
+    let resp = make_client()
+        .post(format!("{}/api/bulk-begin", base_url(port)))
+        .json(&BulkBeginRequest {
+            timeout_secs: parse_duration(timeout)?,
+        })
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let bulk: BulkBeginResponse = resp.json().context("Failed to parse response")?;
+    println!(
+        "Bulk mode on until {}: changes are noted, then recorded by one scan at 'ftm bulk-end'",
+        format_local_time(&bulk.until)
+    );
+    Ok(())
+}
+
+pub fn client_bulk_end(port: u16) -> Result<()> {
+    let resp = make_client()
+        .post(format!("{}/api/bulk-end", base_url(port)))
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let msg: MessageResponse = resp.json().context("Failed to parse response")?;
+    println!("{}", msg.message);
+    Ok(())
+}
+
+pub fn client_pause(port: u16) -> Result<()> {
+    println!("{}", set_paused(port, true)?);
+    Ok(())
+}
+
+pub fn client_resume(port: u16) -> Result<()> {
+    println!("{}", set_paused(port, false)?);
+    Ok(())
+}
+
+/// Pause or resume recording; returns the server's confirmation message.
+pub fn set_paused(port: u16, paused: bool) -> Result<String> {
+    let action = if paused { "pause" } else { "resume" };
+    let resp = make_client()
+        .post(format!("{}/api/{}", base_url(port), action))
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let msg: MessageResponse = resp.json().context("Failed to parse response")?;
+    Ok(msg.message)
+}
+
+#[derive(Deserialize)]
+struct FileStats {
+    versions: usize,
+    snapshots: usize,
+    snapshot_bytes: u64,
+    shared_bytes: u64,
+    oldest: Option<String>,
+    newest: Option<String>,
+    avg_interval_secs: Option<f64>,
+    #[serde(default)]
+    trimmed_versions: usize,
+}
+
+fn format_local_time(ts: &str) -> String {
+    chrono::DateTime::parse_from_rfc3339(ts)
+        .map(|dt| {
+            dt.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_else(|_| ts.to_string())
+}
+
+fn format_interval(secs: f64) -> String {
+    if secs >= 86400.0 {
+        format!("{:.1} days", secs / 86400.0)
+    } else if secs >= 3600.0 {
+        format!("{:.1} hours", secs / 3600.0)
+    } else if secs >= 60.0 {
+        format!("{:.1} minutes", secs / 60.0)
+    } else {
+        format!("{:.1} seconds", secs)
+    }
+}
+
+pub fn client_file_stats(port: u16, file: &str) -> Result<()> {
+    let file = &resolve_tracked_file(port, file);
+    let resp = make_client()
+        .get(format!("{}/api/stats/file", base_url(port)))
+        .query(&[("file", file)])
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let st: FileStats = resp.json().context("Failed to parse stats response")?;
+    if st.versions == 0 {
+        println!("No history for '{}'", file);
+        print_trimmed_note(st.trimmed_versions);
+        return Ok(());
+    }
+    println!("Stats for '{}':", file);
+    println!("Versions:  {}", st.versions);
+    println!(
+        "Snapshots: {} ({}, {} shared with other files)",
+        st.snapshots,
+        format_bytes(st.snapshot_bytes),
+        format_bytes(st.shared_bytes)
+    );
+    if let (Some(oldest), Some(newest)) = (&st.oldest, &st.newest) {
+        println!("Oldest:    {}", format_local_time(oldest));
+        println!("Newest:    {}", format_local_time(newest));
+    }
+    if let Some(secs) = st.avg_interval_secs {
+        println!("Average change interval: {}", format_interval(secs));
+    }
+    print_trimmed_note(st.trimmed_versions);
+    Ok(())
+}
+
+/// Explain versions missing from a file's history because trim removed them.
+fn print_trimmed_note(trimmed: usize) {
+    if trimmed > 0 {
+        println!(
+            "{} older version(s) were trimmed (settings.max_history / settings.max_quota)",
+            trimmed
+        );
+    }
+}
+
+fn format_bytes(n: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+    if n >= GB {
+        format!("{:.1} GB", n as f64 / GB as f64)
+    } else if n >= MB {
+        format!("{:.1} MB", n as f64 / MB as f64)
+    } else if n >= KB {
+        format!("{:.1} KB", n as f64 / KB as f64)
+    } else {
+        format!("{} bytes", n)
+    }
+}
+
+pub fn client_clean(port: u16) -> Result<()> {
+    let resp = make_client()
+        .post(format!("{}/api/clean", base_url(port)))
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let result: CleanResult = resp.json().context("Failed to parse response")?;
+    if result.entries_trimmed == 0
+        && result.entries_collapsed == 0
+        && result.files_removed == 0
+        && result.trash_purged == 0
+        && result.tmp_removed == 0
+        && result.dirs_removed == 0
+    {
+        println!("Clean complete: nothing to remove");
+        return Ok(());
+    }
+    if result.entries_trimmed > 0 {
+        println!(
+            "Trim: {} history entries trimmed, {} freed",
+            result.entries_trimmed,
+            format_bytes(result.bytes_freed_trim)
+        );
+    }
+    if result.entries_collapsed > 0 {
+        println!(
+            "Collapse: {} repeated version(s) of unchanged files removed",
+            result.entries_collapsed
+        );
+    }
+    if result.files_removed > 0 {
+        println!(
+            "Orphan: {} snapshot(s) removed, {} freed",
+            result.files_removed,
+            format_bytes(result.bytes_removed)
+        );
+    }
+    if result.trash_purged > 0 {
+        println!(
+            "Trash: {} expired snapshot(s) permanently deleted",
+            result.trash_purged
+        );
+    }
+    if result.tmp_removed > 0 || result.dirs_removed > 0 {
+        println!(
+            "Store: {} stale temp file(s), {} empty directories removed",
+            result.tmp_removed, result.dirs_removed
+        );
+    }
+    println!("Clean complete");
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct GcQuery {
+    aggressive: bool,
+}
+
+/// Run gc and report every phase, including those that removed nothing.
+pub fn client_gc(port: u16, aggressive: bool) -> Result<()> {
+    let resp = make_client()
+        .post(format!("{}/api/gc", base_url(port)))
+        .query(&GcQuery { aggressive })
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let result: CleanResult = resp.json().context("Failed to parse response")?;
+    println!(
+        "Trim:     {} history entries trimmed, {} freed",
+        result.entries_trimmed,
+        format_bytes(result.bytes_freed_trim)
+    );
+    println!(
+        "Collapse: {} repeated version(s) removed",
+        result.entries_collapsed
+    );
+    println!(
+        "Orphan:   {} snapshot(s) removed, {} freed",
+        result.files_removed,
+        format_bytes(result.bytes_removed)
+    );
+    println!(
+        "Trash:    {} snapshot(s) permanently deleted{}",
+        result.trash_purged,
+        if aggressive { "" } else { " (expired)" }
+    );
+    println!(
+        "Temp:     {} stale temp file(s) removed",
+        result.tmp_removed
+    );
+    println!(
+        "Dirs:     {} empty directories removed",
+        result.dirs_removed
+    );
+    println!("GC complete");
+    Ok(())
+}
+
+pub fn client_unclean(port: u16) -> Result<()> {
+    let resp = make_client()
+        .post(format!("{}/api/unclean", base_url(port)))
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let result: UncleanResult = resp.json().context("Failed to parse response")?;
+    if result.entries_restored == 0 && result.snapshots_restored == 0 {
+        println!("Trash is empty: nothing to recover");
+        return Ok(());
+    }
+    println!(
+        "Recovered {} history entries and {} snapshot(s) from trash",
+        result.entries_restored, result.snapshots_restored
+    );
+    if result.entries_restored > 0 {
+        println!(
+            "Raise settings.max_history / settings.max_quota to keep them past the next clean"
+        );
+    }
+    Ok(())
+}
+
+pub fn client_orphans(port: u16) -> Result<()> {
+    let resp = make_client()
+        .get(format!("{}/api/orphans", base_url(port)))
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let orphans: Vec<OrphanSnapshot> = resp.json().context("Failed to parse response")?;
+    if orphans.is_empty() {
+        println!("No orphan snapshots");
+        return Ok(());
+    }
+    let now = chrono::Utc::now();
+    for orphan in &orphans {
+        let age = orphan
+            .modified
+            .as_deref()
+            .and_then(|m| chrono::DateTime::parse_from_rfc3339(m).ok())
+            .map(|m| {
+                let secs = (now - m.with_timezone(&chrono::Utc)).num_milliseconds() as f64;
+                format!("{} old", format_interval(secs.max(0.0) / 1000.0))
+            })
+            .unwrap_or_else(|| "age unknown".to_string());
+        println!(
+            "  {}  {:>10}  {}",
+            &orphan.checksum[..12.min(orphan.checksum.len())],
+            format_bytes(orphan.size),
+            age
+        );
+    }
+    let total: u64 = orphans.iter().map(|o| o.size).sum();
+    println!(
+        "{} orphan snapshot(s), {}; ftm clean would remove them",
+        orphans.len(),
+        format_bytes(total)
+    );
+    Ok(())
+}
+
+pub fn client_migrate_store(port: u16) -> Result<()> {
+    let resp = make_client()
+        .post(format!("{}/api/migrate-store", base_url(port)))
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let result: MigrateStoreResult = resp.json().context("Failed to parse response")?;
+    if result.snapshots_moved == 0 {
+        println!("Store already matches settings.shard_depth: nothing to move");
+    } else {
+        println!("Moved {} snapshot(s)", result.snapshots_moved);
+    }
+    if result.dirs_removed > 0 {
+        println!("Removed {} empty directories", result.dirs_removed);
+    }
+    Ok(())
+}
+
+pub fn client_stats(port: u16, quota_projection: bool, age: bool) -> Result<()> {
+    let resp = make_client()
+        .get(format!("{}/api/stats", base_url(port)))
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let st: StatsInfo = resp.json().context("Failed to parse stats response")?;
+    println!("History: {} / {}", st.history, st.max_history);
+    println!(
+        "Quota:   {} / {}",
+        format_bytes(st.quota),
+        format_bytes(st.max_quota)
+    );
+    let over_warn_level = st.quota_warn_percent > 0
+        && u128::from(st.quota) * 100
+            > u128::from(st.max_quota) * u128::from(st.quota_warn_percent);
+    if over_warn_level {
+        let quota_percent = u128::from(st.quota) * 100 / u128::from(st.max_quota.max(1));
+        println!(
+            "Warning: snapshot quota is {}% full (settings.quota_warn_percent = {}%); oldest history is trimmed past 100%",
+            quota_percent, st.quota_warn_percent
+        );
+    }
+    if let Some(free) = st.free_space {
+        println!(
+            "Disk:    {} free (min {})",
+            format_bytes(free),
+            format_bytes(st.min_free_space)
+        );
+        if free < st.min_free_space {
+            println!(
+                "Warning: snapshotting is paused until free space exceeds settings.min_free_space"
+            );
+        }
+    }
+
+    if let Some(at) = &st.verified_at {
+        let at = chrono::DateTime::parse_from_rfc3339(at)
+            .map(|dt| {
+                dt.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_else(|_| at.clone());
+        if st.corrupt_count + st.missing_count == 0 {
+            println!("Integrity: ok (last verified {})", at);
+        } else {
+            println!(
+                "Integrity: {} corrupt, {} missing snapshot(s) (last verified {})",
+                st.corrupt_count, st.missing_count, at
+            );
+        }
+    }
+
+    if let Some(scan) = &st.last_scan {
+        println!(
+            "Last scan: {} ({} created, {} modified, {} deleted)",
+            format_local_time(&scan.at),
+            scan.created,
+            scan.modified,
+            scan.deleted
+        );
+    }
+
+    let dedup: Option<DedupInfo> = make_client()
+        .get(format!("{}/api/dedup", base_url(port)))
+        .send()
+        .ok()
+        .and_then(|r| check_response(r).ok())
+        .and_then(|r| r.json().ok());
+    if let Some(d) = dedup.filter(|d| d.references > 0) {
+        println!(
+            "Dedup:   {} referenced by {} versions, {} stored in {} snapshots",
+            format_bytes(d.logical_bytes),
+            d.references,
+            format_bytes(d.physical_bytes),
+            d.snapshots
+        );
+        println!(
+            "         {} duplicate references avoided, {} saved ({:.2}x)",
+            d.duplicate_references,
+            format_bytes(d.logical_bytes.saturating_sub(d.physical_bytes)),
+            d.logical_bytes as f64 / d.physical_bytes.max(1) as f64
+        );
+    }
+
+    // Best-effort: growth over the most recent daily samples
+    const SHOWN_SAMPLES: usize = 7;
+    let samples: Vec<UsageSample> = make_client()
+        .get(format!("{}/api/stats/history", base_url(port)))
+        .send()
+        .ok()
+        .and_then(|r| check_response(r).ok())
+        .and_then(|r| r.json().ok())
+        .unwrap_or_default();
+    if samples.len() > 1 {
+        println!();
+        println!("Growth (daily samples):");
+        let start = samples.len().saturating_sub(SHOWN_SAMPLES + 1);
+        for pair in samples[start..].windows(2) {
+            let (prev, cur) = (&pair[0], &pair[1]);
+            let day = cur.at.get(..10).unwrap_or(&cur.at);
+            let bytes = cur.quota as i64 - prev.quota as i64;
+            println!(
+                "  {}  {:>8} entries ({:+})  {:>10} ({}{})",
+                day,
+                cur.history,
+                cur.history as i64 - prev.history as i64,
+                format_bytes(cur.quota),
+                if bytes < 0 { "-" } else { "+" },
+                format_bytes(bytes.unsigned_abs())
+            );
+        }
+    }
+    if quota_projection {
+        println!();
+        print_quota_projection(&st, &samples);
+    }
+    if age {
+        let resp = make_client()
+            .get(format!("{}/api/stats/age", base_url(port)))
+            .send()
+            .map_err(handle_connection_error)?;
+        let buckets: Vec<AgeBucket> = check_response(resp)?
+            .json()
+            .context("Failed to parse age stats")?;
+        println!();
+        println!("Snapshots by age of their newest version:");
+        for b in buckets {
+            println!(
+                "  {:<10}  {:>6} snapshots  {:>10}",
+                b.age.replace('_', " "),
+                b.snapshots,
+                format_bytes(b.bytes)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Estimate when max_history and max_quota will be reached, from the growth between the
+/// oldest daily sample of the last 30 days and the current usage.
+fn print_quota_projection(st: &StatsInfo, samples: &[UsageSample]) {
+    const WINDOW_DAYS: i64 = 30;
+    const HORIZON_DAYS: f64 = 3650.0;
+    let now = chrono::Utc::now();
+    let base = samples.iter().find_map(|s| {
+        let at = chrono::DateTime::parse_from_rfc3339(&s.at).ok()?;
+        let age = now.signed_duration_since(at);
+        (age <= chrono::Duration::days(WINDOW_DAYS)).then_some((s, age))
+    });
+    let Some((base, age)) = base.filter(|(_, age)| *age >= chrono::Duration::days(1)) else {
+        println!(
+            "Projection: not enough usage history yet (sampled once a day); check again after a day of activity"
+        );
+        return;
+    };
+    let days = age.num_seconds() as f64 / 86_400.0;
+    println!("Projection (growth over the last {:.0} days):", days);
+
+    let eta = |current: u64, max: u64, per_day: f64| -> String {
+        if current >= max {
+            return "reached; the oldest history is being trimmed".to_string();
+        }
+        if per_day <= 0.0 {
+            return "not growing".to_string();
+        }
+        let left = (max - current) as f64 / per_day;
+        if left > HORIZON_DAYS {
+            return "not within 10 years".to_string();
+        }
+        let at = now + chrono::Duration::seconds((left * 86_400.0) as i64);
+        format!(
+            "reached in ~{:.0} days ({})",
+            left.ceil(),
+            at.with_timezone(&chrono::Local).format("%Y-%m-%d")
+        )
+    };
+
+    let entries_per_day = (st.history as f64 - base.history as f64) / days;
+    println!(
+        "  History: {:+.1} entries/day, max_history ({}) {}",
+        entries_per_day,
+        st.max_history,
+        eta(st.history as u64, st.max_history as u64, entries_per_day)
+    );
+    let bytes_per_day = (st.quota as f64 - base.quota as f64) / days;
+    println!(
+        "  Quota:   {}{}/day, max_quota ({}) {}",
+        if bytes_per_day < 0.0 { "-" } else { "+" },
+        format_bytes(bytes_per_day.abs() as u64),
+        format_bytes(st.max_quota),
+        eta(st.quota, st.max_quota, bytes_per_day)
+    );
+}
+
+pub fn client_verify_signatures(port: u16, public_key: Option<&str>) -> Result<()> {
+    let resp = make_client()
+        .get(format!("{}/api/signatures", base_url(port)))
+        .query(&SignaturesQuery { public_key })
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let report: SignatureReport = resp.json().context("Failed to parse response")?;
+    println!("Public key: {}", report.public_key);
+    println!(
+        "{} entries with a valid signature, {} unsigned",
+        report.valid, report.unsigned
+    );
+    for bad in &report.invalid {
+        println!("  INVALID  seq {}  {}", bad.seq, bad.file);
+    }
+    match report.complete {
+        Some(true) => println!("Signed entries are complete and in order"),
+        Some(false) => println!("  INVALID  signed entries were removed or reordered"),
+        None => {}
+    }
+    if !report.invalid.is_empty() {
+        anyhow::bail!(
+            "{} entries failed signature verification",
+            report.invalid.len()
+        );
+    }
+    if report.complete == Some(false) {
+        anyhow::bail!("History signature verification failed");
+    }
+    Ok(())
+}
+
+/// Parse a duration such as `90s`, `30m`, `24h` or `7d` into seconds.
+fn parse_duration(value: &str) -> Result<u64> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid duration '{}' (expected e.g. 30m, 24h or 7d)",
+            value
+        )
+    };
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(split);
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 24 * 3600,
+        _ => return Err(invalid()),
+    };
+    amount
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit_secs))
+        .filter(|&secs| secs > 0)
+        .ok_or_else(invalid)
+}
+
+/// Parse a `--since` / `--at` value: an RFC 3339 timestamp, or a date (local midnight).
+fn parse_timestamp(since: &str) -> Result<chrono::DateTime<chrono::Utc>> {
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Ok(dt.with_timezone(&chrono::Utc));
+    }
+    chrono::NaiveDate::parse_from_str(since, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .and_then(|dt| dt.and_local_timezone(chrono::Local).earliest())
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .with_context(|| format!("Invalid date '{}' (expected YYYY-MM-DD or RFC 3339)", since))
+}
+
+/// Export history entries and events as JSON lines, in the order they were recorded.
+pub fn client_audit(
+    port: u16,
+    since: Option<&str>,
+    output: Option<&std::path::Path>,
+) -> Result<()> {
+    let since = since
+        .map(parse_timestamp)
+        .transpose()?
+        .map(|dt| dt.to_rfc3339());
+    let resp = make_client()
+        .get(format!("{}/api/audit", base_url(port)))
+        .query(&AuditQuery { since, op: None })
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let records: Vec<serde_json::Value> = resp.json().context("Failed to parse response")?;
+
+    let mut out = String::new();
+    for record in &records {
+        out.push_str(&serde_json::to_string(record)?);
+        out.push('\n');
+    }
+    match output {
+        Some(path) => {
+            std::fs::write(path, out)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("Wrote {} records to {}", records.len(), path.display());
+        }
+        None => print!("{}", out),
+    }
+    Ok(())
+}
+
+pub fn client_similar(port: u16, threshold: u8, since: Option<&str>) -> Result<()> {
+    let since = since
+        .map(parse_timestamp)
+        .transpose()?
+        .map(|dt| dt.to_rfc3339());
+    let resp = make_client()
+        .get(format!("{}/api/similar", base_url(port)))
+        .query(&SimilarQuery { threshold, since })
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let found: Vec<SimilarInfo> = resp.json().context("Failed to parse response")?;
+
+    if found.is_empty() {
+        println!(
+            "No new files at least {}% similar to another file",
+            threshold
+        );
+        return Ok(());
+    }
+    for m in &found {
+        println!(
+            "  {} <- {}  ({}%, {})",
+            m.file, m.source_file, m.similarity, m.kind
+        );
+    }
+    Ok(())
+}
+
+pub fn client_version(port: u16) -> Result<()> {
+    println!("Client version: {}", env!("CARGO_PKG_VERSION"));
+
+    match make_client()
+        .get(format!("{}/api/version", base_url(port)))
+        .timeout(std::time::Duration::from_secs(2))
+        .send()
+    {
+        Ok(resp) => {
             let resp = check_response(resp)?;
             let info: VersionInfo = resp.json().context("Failed to parse version response")?;
             println!("Server version: {}", info.version);
@@ -393,6 +1729,190 @@ pub fn client_version(port: u16) -> Result<()> {
     Ok(())
 }
 
+#[derive(Deserialize)]
+struct PathMatch {
+    path: String,
+    tracked: bool,
+    pattern: Option<String>,
+    reason: String,
+}
+
+/// Report whether `path` (absolute, or relative to the watched directory) would be
+/// tracked and which pattern or setting decided it.
+pub fn client_check_path(port: u16, path: &str) -> Result<()> {
+    let resp = make_client()
+        .get(format!("{}/api/match", base_url(port)))
+        .query(&[("path", path)])
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let m: PathMatch = resp.json().context("Failed to parse response")?;
+    let verdict = if m.tracked { "tracked" } else { "not tracked" };
+    match m.pattern {
+        Some(pattern) => println!("{}: {} ({}: {})", m.path, verdict, m.reason, pattern),
+        None => println!("{}: {} ({})", m.path, verdict, m.reason),
+    }
+    Ok(())
+}
+
+/// How long `ftm selftest` waits for the watcher to record each change.
+const SELFTEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+fn fetch_history(port: u16, file: &str) -> Result<Vec<HistoryEntry>> {
+    let resp = make_client()
+        .get(format!("{}/api/history", base_url(port)))
+        .query(&[("file", file)])
+        .send()
+        .map_err(handle_connection_error)?;
+    check_response(resp)?
+        .json()
+        .context("Failed to parse response")
+}
+
+/// Poll the history of `file` until it has `count` entries, the last one with `op`.
+/// Returns that entry and how long it took.
+fn wait_for_entry(
+    port: u16,
+    file: &str,
+    count: usize,
+    op: &str,
+) -> Result<(HistoryEntry, std::time::Duration)> {
+    let start = std::time::Instant::now();
+    loop {
+        let mut entries = fetch_history(port, file)?;
+        if entries.len() >= count && entries.last().is_some_and(|e| e.op == op) {
+            return Ok((entries.pop().unwrap(), start.elapsed()));
+        }
+        if start.elapsed() > SELFTEST_TIMEOUT {
+            anyhow::bail!(
+                "no '{}' entry for {} within {}s",
+                op,
+                file,
+                SELFTEST_TIMEOUT.as_secs()
+            );
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
+
+/// Write, modify, restore and delete a probe file in the watched directory, checking
+/// each step shows up in history. The probe's entries stay in history.
+pub fn client_selftest(port: u16) -> Result<()> {
+    let health = client_health(port)?;
+    if let Some(source) = health.mirror_of {
+        anyhow::bail!(
+            "Read-only mirror of {}: there is no watcher to test",
+            source
+        );
+    }
+    let watch_dir = std::path::PathBuf::from(health.watch_dir.context("No directory checked out")?);
+    let name = format!(
+        "ftm-selftest-{}.txt",
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    );
+    let probe = watch_dir.join(&name);
+
+    let resp = make_client()
+        .get(format!("{}/api/match", base_url(port)))
+        .query(&[("path", probe.to_string_lossy().as_ref())])
+        .send()
+        .map_err(handle_connection_error)?;
+    let m: PathMatch = check_response(resp)?
+        .json()
+        .context("Failed to parse response")?;
+    if !m.tracked {
+        anyhow::bail!(
+            "Self-test cannot run: a probe file {} would not be tracked ({})",
+            name,
+            m.reason
+        );
+    }
+
+    println!("Probe file: {}", probe.display());
+    let result = run_selftest(port, &probe, &name);
+    if probe.exists() {
+        std::fs::remove_file(&probe).ok();
+    }
+    match result {
+        Ok(()) => {
+            println!("Self-test passed");
+            Ok(())
+        }
+        Err(e) => anyhow::bail!("Self-test FAILED: {}", e),
+    }
+}
+
+fn run_selftest(port: u16, probe: &std::path::Path, name: &str) -> Result<()> {
+    let original = format!("ftm self-test {}\n", name);
+    std::fs::write(probe, &original).context("cannot write the probe file")?;
+    let (created, took) = wait_for_entry(port, name, 1, "create")
+        .context("the watcher did not record the new file")?;
+    println!("  create captured in {:.1}s", took.as_secs_f64());
+
+    std::fs::write(probe, format!("{}modified\n", original))
+        .context("cannot modify the probe file")?;
+    let (_, took) = wait_for_entry(port, name, 2, "modify")
+        .context("the watcher did not record the modification")?;
+    println!("  modify captured in {:.1}s", took.as_secs_f64());
+
+    let checksum = created
+        .checksum
+        .context("the create entry has no snapshot")?;
+    let resp = make_client()
+        .post(format!("{}/api/restore", base_url(port)))
+        .json(&RestoreRequest {
+            file: name.to_string(),
+            checksum,
+            from_trash: false,
+        })
+        .send()
+        .map_err(handle_connection_error)?;
+    check_response(resp).context("restore failed")?;
+    if std::fs::read_to_string(probe).ok().as_deref() != Some(original.as_str()) {
+        anyhow::bail!("the restored probe file does not have its original content");
+    }
+    println!("  restore of the first version ok");
+
+    std::fs::remove_file(probe).context("cannot delete the probe file")?;
+    let (_, took) = wait_for_entry(port, name, 3, "delete")
+        .context("the watcher did not record the deletion")?;
+    println!("  delete captured in {:.1}s", took.as_secs_f64());
+    Ok(())
+}
+
+/// Command opening a URL in the default browser: `$BROWSER` when set, else the
+/// platform's opener.
+fn browser_command(url: &str) -> std::process::Command {
+    let mut cmd = match std::env::var_os("BROWSER").filter(|b| !b.is_empty()) {
+        Some(browser) => std::process::Command::new(browser),
+        None if cfg!(target_os = "macos") => std::process::Command::new("open"),
+        None if cfg!(windows) => {
+            let mut cmd = std::process::Command::new("cmd");
+            cmd.args(["/C", "start", ""]);
+            cmd
+        }
+        None => std::process::Command::new("xdg-open"),
+    };
+    cmd.arg(url)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    cmd
+}
+
+/// Open the Web UI of the server on `port` in the default browser. The URL is always
+/// printed, so it can be opened by hand when no browser can be launched.
+pub fn client_open(port: u16) -> Result<()> {
+    client_health(port)?;
+    let url = format!("{}/", base_url(port));
+    println!("Web UI: {}", url);
+    match browser_command(&url).status() {
+        Ok(status) if status.success() => {}
+        _ => eprintln!("Could not launch a browser; open the URL above manually."),
+    }
+    Ok(())
+}
+
 pub fn client_config_get(port: u16, key: Option<&str>) -> Result<()> {
     let mut req = make_client().get(format!("{}/api/config", base_url(port)));
     if let Some(k) = key {
@@ -406,6 +1926,12 @@ pub fn client_config_get(port: u16, key: Option<&str>) -> Result<()> {
 }
 
 pub fn client_config_set(port: u16, key: &str, value: &str) -> Result<()> {
+    println!("{}", config_set(port, key, value)?);
+    Ok(())
+}
+
+/// Set a config value; returns the server's confirmation message.
+pub fn config_set(port: u16, key: &str, value: &str) -> Result<String> {
     let resp = make_client()
         .post(format!("{}/api/config", base_url(port)))
         .json(&ConfigSetRequest {
@@ -416,7 +1942,50 @@ pub fn client_config_set(port: u16, key: &str, value: &str) -> Result<()> {
         .map_err(handle_connection_error)?;
     let resp = check_response(resp)?;
     let msg: MessageResponse = resp.json().context("Failed to parse response")?;
-    println!("{}", msg.message);
+    Ok(msg.message)
+}
+
+#[derive(Deserialize)]
+struct ConfigChangeInfo {
+    timestamp: String,
+    key: String,
+    old_value: String,
+    new_value: String,
+}
+
+/// List recorded `config set` changes, oldest first, optionally for a single key.
+pub fn client_config_log(port: u16, key: Option<&str>) -> Result<()> {
+    let resp = make_client()
+        .get(format!("{}/api/audit", base_url(port)))
+        .query(&AuditQuery {
+            since: None,
+            op: Some("config_change"),
+        })
+        .send()
+        .map_err(handle_connection_error)?;
+    let resp = check_response(resp)?;
+    let mut changes: Vec<ConfigChangeInfo> = resp.json().context("Failed to parse response")?;
+    if let Some(key) = key {
+        changes.retain(|c| c.key == key);
+    }
+
+    if changes.is_empty() {
+        println!("No recorded config changes");
+        return Ok(());
+    }
+    for change in &changes {
+        let display_time = match chrono::DateTime::parse_from_rfc3339(&change.timestamp) {
+            Ok(dt) => dt
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            Err(_) => change.timestamp.clone(),
+        };
+        println!(
+            "  {} | {} | {} -> {}",
+            display_time, change.key, change.old_value, change.new_value
+        );
+    }
     Ok(())
 }
 
@@ -461,3 +2030,203 @@ pub fn client_logs(port: u16) -> Result<()> {
         }
     }
 }
+
+/// Normalize a remote server URL: default to http:// and drop trailing slashes.
+pub fn normalize_remote(remote: &str) -> String {
+    let url = remote.trim().trim_end_matches('/');
+    if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("http://{}", url)
+    }
+}
+
+/// Receiving end of a sync: a server's `/api/sync` endpoints, or a mirror's own store.
+pub trait SyncTarget {
+    /// History it already has.
+    fn history(&self) -> Result<Vec<SyncEntry>>;
+    /// Store one snapshot streamed from `content`, which must hash to `checksum`.
+    fn store_snapshot(&self, checksum: &str, content: Box<dyn std::io::Read + Send>) -> Result<()>;
+    /// Add entries whose snapshots are stored; returns how many were new.
+    fn merge_entries(&self, entries: &[&SyncEntry]) -> Result<usize>;
+}
+
+/// The sync endpoints of the server at `base`.
+pub struct ServerTarget {
+    client: reqwest::blocking::Client,
+    base: String,
+}
+
+impl ServerTarget {
+    pub fn new(base: &str) -> Self {
+        Self {
+            client: make_client(),
+            base: base.to_string(),
+        }
+    }
+
+    /// Post history entries, in whatever form the caller holds them.
+    pub fn merge<T: Serialize>(&self, entries: &[T]) -> Result<usize> {
+        let resp = self
+            .client
+            .post(format!("{}/api/sync/entries", self.base))
+            .json(entries)
+            .send()
+            .with_context(|| format!("Failed to reach {}", self.base))?;
+        let result: SyncEntriesResult = check_response(resp)?
+            .json()
+            .context("Failed to parse sync response")?;
+        Ok(result.added)
+    }
+}
+
+impl SyncTarget for ServerTarget {
+    fn history(&self) -> Result<Vec<SyncEntry>> {
+        fetch_sync_index(&self.client, &self.base)
+    }
+
+    fn store_snapshot(&self, checksum: &str, content: Box<dyn std::io::Read + Send>) -> Result<()> {
+        let resp = self
+            .client
+            .post(format!("{}/api/sync/snapshot", self.base))
+            .query(&SyncBlobQuery { checksum })
+            .body(reqwest::blocking::Body::new(content))
+            .send()
+            .with_context(|| format!("Failed to reach {}", self.base))?;
+        check_response(resp)?;
+        Ok(())
+    }
+
+    fn merge_entries(&self, entries: &[&SyncEntry]) -> Result<usize> {
+        self.merge(entries)
+    }
+}
+
+/// What `sync_from` copied.
+pub struct SyncOutcome {
+    pub added: usize,
+    pub snapshots: usize,
+    /// Snapshots that could not be copied, with the reason; their entries were left
+    /// out, to be copied by a later sync.
+    pub failed: Vec<(String, anyhow::Error)>,
+}
+
+fn fetch_sync_index(client: &reqwest::blocking::Client, base: &str) -> Result<Vec<SyncEntry>> {
+    let resp = client
+        .get(format!("{}/api/index", base))
+        .send()
+        .with_context(|| format!("Failed to reach {}", base))?;
+    let resp = check_response(resp)?;
+    resp.json().context("Failed to parse index response")
+}
+
+/// Copy the history entries `to` is missing from the server at `from`, streaming each
+/// snapshot blob `to` does not reference yet.
+pub fn sync_from(from: &str, to: &dyn SyncTarget) -> Result<SyncOutcome> {
+    let client = make_client();
+    let source = fetch_sync_index(&client, from)?;
+    let target = to.history()?;
+
+    let known: HashSet<_> = target.iter().map(SyncEntry::key).collect();
+    let have: HashSet<&str> = target
+        .iter()
+        .filter_map(|e| e.checksum.as_deref())
+        .collect();
+    let missing: Vec<&SyncEntry> = source
+        .iter()
+        .filter(|e| !known.contains(&e.key()))
+        .collect();
+    let mut outcome = SyncOutcome {
+        added: 0,
+        snapshots: 0,
+        failed: Vec::new(),
+    };
+    if missing.is_empty() {
+        return Ok(outcome);
+    }
+
+    let mut blobs: Vec<&str> = missing
+        .iter()
+        .filter(|e| e.op != "delete")
+        .filter_map(|e| e.checksum.as_deref())
+        .filter(|c| !have.contains(c))
+        .collect();
+    blobs.sort_unstable();
+    blobs.dedup();
+
+    for checksum in blobs {
+        let copied = client
+            .get(format!("{}/api/snapshot", from))
+            .query(&SyncBlobQuery { checksum })
+            .send()
+            .with_context(|| format!("Failed to reach {}", from))
+            .and_then(check_response)
+            .and_then(|resp| to.store_snapshot(checksum, Box::new(resp)));
+        match copied {
+            Ok(()) => outcome.snapshots += 1,
+            Err(e) => outcome.failed.push((checksum.to_string(), e)),
+        }
+    }
+
+    let entries: Vec<&SyncEntry> = missing
+        .into_iter()
+        .filter(|e| {
+            e.checksum
+                .as_deref()
+                .is_none_or(|c| !outcome.failed.iter().any(|(f, _)| f == c))
+        })
+        .collect();
+    if !entries.is_empty() {
+        outcome.added = to.merge_entries(&entries)?;
+    }
+    Ok(outcome)
+}
+
+/// Copy from `from` to the server at `to`, failing if any snapshot could not be copied
+/// (the entries that could be are kept). Returns (entries added, snapshots transferred).
+fn sync_between(from: &str, to: &str) -> Result<(usize, usize)> {
+    let outcome = sync_from(from, &ServerTarget::new(to))?;
+    if let Some((checksum, e)) = outcome.failed.first() {
+        anyhow::bail!(
+            "{} snapshots could not be copied, so their entries were left out; {}: {:#}",
+            outcome.failed.len(),
+            &checksum[..8.min(checksum.len())],
+            e
+        );
+    }
+    Ok((outcome.added, outcome.snapshots))
+}
+
+/// Send local history entries and snapshots missing on `remote`.
+pub fn client_push(port: u16, remote: &str) -> Result<()> {
+    client_health(port)?;
+    let local = base_url(port);
+    let remote = normalize_remote(remote);
+    let (added, blobs) = sync_between(&local, &remote)?;
+    if added == 0 {
+        println!("{} is up to date", remote);
+    } else {
+        println!(
+            "Pushed {} entries ({} snapshots) to {}",
+            added, blobs, remote
+        );
+    }
+    Ok(())
+}
+
+/// Fetch history entries and snapshots from `remote` that are missing locally.
+pub fn client_pull(port: u16, remote: &str) -> Result<()> {
+    client_health(port)?;
+    let local = base_url(port);
+    let remote = normalize_remote(remote);
+    let (added, blobs) = sync_between(&remote, &local)?;
+    if added == 0 {
+        println!("Already up to date with {}", remote);
+    } else {
+        println!(
+            "Pulled {} entries ({} snapshots) from {}",
+            added, blobs, remote
+        );
+    }
+    Ok(())
+}
@@ -1,13 +1,58 @@
 use crate::path_util;
 use anyhow::Result;
-use glob::Pattern;
+use glob::{MatchOptions, Pattern};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WatchConfig {
     pub patterns: Vec<String>,
+    /// How `patterns` admit files.
+    #[serde(default)]
+    pub mode: WatchMode,
     pub exclude: Vec<String>,
+    /// Deepest directory level below the watched directory that is traversed and
+    /// watched (its direct subdirectories are level 1); 0 = unlimited.
+    #[serde(default)]
+    pub max_depth: usize,
+    /// Track files and directories whose name starts with a dot (`.env`, `.config/`).
+    /// When false they are skipped like excluded paths, whatever the patterns say.
+    #[serde(default = "default_include_hidden")]
+    pub include_hidden: bool,
+    /// Subdirectories (relative to the watched directory) that are scanned and watched;
+    /// everything else is skipped without being read. Empty = the whole directory.
+    #[serde(default)]
+    pub roots: Vec<String>,
+}
+
+fn default_include_hidden() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchMode {
+    /// A file is tracked when a pattern ends with its extension (`*.rs`, anywhere).
+    #[default]
+    Extension,
+    /// A file is tracked only when its relative path matches a pattern as a glob, with
+    /// `*` staying within one directory (`src/**/*.rs`, `config/*.yaml`).
+    IncludeOnly,
+}
+
+/// Which file watcher events lead to a scan that snapshots the changed files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotTrigger {
+    /// Only a writer closing the file (or a create, delete or rename), so a file is
+    /// hashed once per save. Linux only; elsewhere the same as `DebouncedModify`.
+    CloseWrite,
+    /// Any change, once events have been quiet for 500 ms.
+    #[default]
+    DebouncedModify,
+    /// No events: scan every few seconds and pick up changes by size and mtime.
+    Poll,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,7 +60,8 @@ pub struct Settings {
     /// Global history queue size (max total entries across all files).
     pub max_history: usize,
     pub max_file_size: u64,
-    /// Max total size in bytes of referenced snapshots. Oldest history and snapshots are trimmed when exceeded.
+    /// Max total size in bytes of referenced and trashed snapshots. Oldest history and
+    /// snapshots are trimmed, and the oldest trash purged, when exceeded.
     #[serde(default = "default_max_quota")]
     pub max_quota: u64,
     /// Interval in seconds between periodic full scans. Minimum 2.
@@ -24,6 +70,134 @@ pub struct Settings {
     /// Interval in seconds between periodic clean (orphan snapshot removal). Minimum 2.
     #[serde(default = "default_clean_interval")]
     pub clean_interval: u64,
+    /// Days trimmed/cleaned snapshots stay in `.ftm/trash/` before permanent deletion (0 = delete immediately).
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+    /// Versions of each existing file that trimming never removes, even past max_history
+    /// or max_quota; 0 = none.
+    #[serde(default)]
+    pub min_versions_per_file: usize,
+    /// Max files per second examined by background (periodic) scans; 0 = unlimited.
+    #[serde(default)]
+    pub scan_throttle: u64,
+    /// Minimum free bytes on the `.ftm` volume; below it snapshotting pauses (deletes are
+    /// still recorded). 0 disables the guard.
+    #[serde(default = "default_min_free_space")]
+    pub min_free_space: u64,
+    /// Seconds between background integrity checks of a batch of snapshots; 0 disables.
+    #[serde(default = "default_verify_interval")]
+    pub verify_interval: u64,
+    /// Sign new history entries with the user's ed25519 key (kept outside `.ftm`).
+    #[serde(default)]
+    pub sign_history: bool,
+    /// Diffs computed at once; 0 = min(2, CPU cores).
+    #[serde(default)]
+    pub diff_workers: usize,
+    /// Milliseconds a diff request may take, including waiting for a free worker.
+    #[serde(default = "default_diff_timeout_ms")]
+    pub diff_timeout_ms: u64,
+    /// Warn when referenced snapshot bytes exceed this percentage of max_quota; 0 disables.
+    #[serde(default = "default_quota_warn_percent")]
+    pub quota_warn_percent: u64,
+    /// Record creation and deletion of empty files (no snapshot). Emptying a tracked file is
+    /// always recorded, as a truncation.
+    #[serde(default)]
+    pub track_empty_files: bool,
+    /// Snapshots the file watcher may record per minute, with bursts up to the same
+    /// number; changes past the limit are coalesced into a later scan (0 = unlimited).
+    #[serde(default = "default_max_snapshots_per_minute")]
+    pub max_snapshots_per_minute: u64,
+    /// Checkout and manual scans refuse to start when more files than this match the
+    /// watch patterns, unless forced (0 = unlimited).
+    #[serde(default = "default_max_tracked_files")]
+    pub max_tracked_files: usize,
+    /// Record the name of the process that wrote each change, where the platform allows
+    /// (see `writers`).
+    #[serde(default)]
+    pub track_writers: bool,
+    /// When the watched directory is a git work tree, wait out git operations holding
+    /// `.git/index.lock` and record a branch switch (`.git/HEAD` changed) with one scan,
+    /// tagged `git` in history.
+    #[serde(default)]
+    pub git_aware: bool,
+    /// Keep the extended attributes (e.g. macOS Finder tags, quarantine flags) of each
+    /// recorded version and put them back on restore; Unix only.
+    #[serde(default)]
+    pub track_xattrs: bool,
+    /// `address:port` of a second listener serving only the Web UI and GET endpoints,
+    /// e.g. on the LAN while the full API stays on localhost; empty = off.
+    #[serde(default)]
+    pub ui_listen: String,
+    /// API requests each client address may send per second, in bursts up to the same
+    /// number; 0 = unlimited (the default).
+    #[serde(default)]
+    pub api_rate_limit: u64,
+    /// Let watcher and periodic scans skip re-reading directories whose mtime has not
+    /// changed since the last scan. Off for filesystems that do not update directory
+    /// mtimes reliably.
+    #[serde(default = "default_incremental_scan")]
+    pub incremental_scan: bool,
+    /// Directory of a snapshot store shared with other projects, so identical content
+    /// is stored once; empty = `.ftm/snapshots`.
+    #[serde(default)]
+    pub snapshot_store: String,
+    /// Directory levels (one per leading checksum char) snapshots are sharded into,
+    /// 0 to 4. Existing snapshots stay where they are until `ftm migrate-store`.
+    #[serde(default = "default_shard_depth")]
+    pub shard_depth: usize,
+    /// What makes the watcher snapshot a change: immediacy against hashing CPU.
+    #[serde(default)]
+    pub snapshot_trigger: SnapshotTrigger,
+    /// Retention preset (see `RETENTION_PROFILES`) max_history, max_quota and
+    /// trash_retention_days were last set from; empty once any of them differs.
+    #[serde(default)]
+    pub profile: String,
+}
+
+/// Named retention preset behind `settings.profile`: the values it sets.
+struct RetentionProfile {
+    name: &'static str,
+    max_history: usize,
+    max_quota: u64,
+    trash_retention_days: u64,
+}
+
+const RETENTION_PROFILES: &[RetentionProfile] = &[
+    // Smallest footprint: recent versions only, trimmed ones gone at the next clean
+    RetentionProfile {
+        name: "minimal",
+        max_history: 1_000,
+        max_quota: 100 * 1024 * 1024,
+        trash_retention_days: 0,
+    },
+    // Busy trees: bounded history with a short undo window for trims
+    RetentionProfile {
+        name: "aggressive",
+        max_history: 5_000,
+        max_quota: 512 * 1024 * 1024,
+        trash_retention_days: 1,
+    },
+    RetentionProfile {
+        name: "default",
+        max_history: 10_000,
+        max_quota: 1024 * 1024 * 1024,
+        trash_retention_days: 7,
+    },
+    // Keep as much as practical, and trimmed history for a month
+    RetentionProfile {
+        name: "archival",
+        max_history: 100_000,
+        max_quota: 10 * 1024 * 1024 * 1024,
+        trash_retention_days: 30,
+    },
+];
+
+fn retention_profile(name: &str) -> Option<&'static RetentionProfile> {
+    RETENTION_PROFILES.iter().find(|p| p.name == name)
+}
+
+fn default_shard_depth() -> usize {
+    crate::store::DEFAULT_SHARD_DEPTH
 }
 
 fn default_max_quota() -> u64 {
@@ -38,6 +212,49 @@ fn default_clean_interval() -> u64 {
     3600
 }
 
+fn default_trash_retention_days() -> u64 {
+    7
+}
+
+fn default_verify_interval() -> u64 {
+    600
+}
+
+fn default_min_free_space() -> u64 {
+    100 * 1024 * 1024 // 100MB
+}
+
+fn default_diff_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_quota_warn_percent() -> u64 {
+    90
+}
+
+fn default_max_snapshots_per_minute() -> u64 {
+    1000
+}
+
+fn default_max_tracked_files() -> usize {
+    100_000
+}
+
+fn default_incremental_scan() -> bool {
+    true
+}
+
+/// Whether a path would be tracked, and the pattern or setting that decided it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathMatch {
+    /// Relative to the watched directory.
+    pub path: String,
+    pub tracked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    pub reason: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub watch: WatchConfig,
@@ -45,6 +262,9 @@ pub struct Config {
     /// Compiled exclude patterns; not serialized, built from watch.exclude.
     #[serde(skip, default)]
     pub exclude_compiled: Vec<Pattern>,
+    /// Compiled watch patterns for `WatchMode::IncludeOnly`; built from watch.patterns.
+    #[serde(skip, default)]
+    pub include_compiled: Vec<Pattern>,
 }
 
 impl Default for Config {
@@ -66,18 +286,27 @@ impl Default for Config {
                 "*.conf".into(),
                 "*.ini".into(),
             ],
+            mode: WatchMode::default(),
             exclude: vec![
                 "**/target/**".into(),
                 "**/node_modules/**".into(),
                 "**/.git/**".into(),
                 "**/.ftm/**".into(),
             ],
+            max_depth: 0,
+            include_hidden: default_include_hidden(),
+            roots: Vec::new(),
         };
         let exclude_compiled = watch
             .exclude
             .iter()
             .filter_map(|p| Pattern::new(p).ok())
             .collect();
+        let include_compiled = watch
+            .patterns
+            .iter()
+            .filter_map(|p| Pattern::new(p).ok())
+            .collect();
         Self {
             watch,
             settings: Settings {
@@ -86,12 +315,72 @@ impl Default for Config {
                 max_quota: default_max_quota(),
                 scan_interval: default_scan_interval(),
                 clean_interval: default_clean_interval(),
+                trash_retention_days: default_trash_retention_days(),
+                min_versions_per_file: 0,
+                scan_throttle: 0,
+                min_free_space: default_min_free_space(),
+                verify_interval: default_verify_interval(),
+                sign_history: false,
+                diff_workers: 0,
+                diff_timeout_ms: default_diff_timeout_ms(),
+                quota_warn_percent: default_quota_warn_percent(),
+                track_empty_files: false,
+                max_snapshots_per_minute: default_max_snapshots_per_minute(),
+                max_tracked_files: default_max_tracked_files(),
+                track_writers: false,
+                git_aware: false,
+                track_xattrs: false,
+                ui_listen: String::new(),
+                api_rate_limit: 0,
+                incremental_scan: default_incremental_scan(),
+                snapshot_store: String::new(),
+                shard_depth: default_shard_depth(),
+                snapshot_trigger: SnapshotTrigger::default(),
+                profile: String::new(),
             },
             exclude_compiled,
+            include_compiled,
         }
     }
 }
 
+/// Every key of `get_value` and `set_value`.
+pub const KEYS: &[&str] = &[
+    "settings.max_history",
+    "settings.max_file_size",
+    "settings.max_quota",
+    "settings.scan_interval",
+    "settings.clean_interval",
+    "settings.trash_retention_days",
+    "settings.min_versions_per_file",
+    "settings.scan_throttle",
+    "settings.min_free_space",
+    "settings.verify_interval",
+    "settings.sign_history",
+    "settings.diff_workers",
+    "settings.diff_timeout_ms",
+    "settings.quota_warn_percent",
+    "settings.track_empty_files",
+    "settings.max_snapshots_per_minute",
+    "settings.max_tracked_files",
+    "settings.track_writers",
+    "settings.git_aware",
+    "settings.track_xattrs",
+    "settings.ui_listen",
+    "settings.api_rate_limit",
+    "settings.incremental_scan",
+    "settings.snapshot_store",
+    "settings.shard_depth",
+    "settings.snapshot_trigger",
+    "settings.profile",
+    "watch.patterns",
+    "watch.mode",
+    "watch.exclude",
+    "watch.max_depth",
+    "watch.include_hidden",
+    "watch.roots",
+];
+
 impl Config {
     pub fn load(path: &Path) -> Result<Self> {
         let content = std::fs::read_to_string(path)?;
@@ -103,9 +392,33 @@ impl Config {
             config.settings.clean_interval = 2;
         }
         config.build_exclude_compiled();
+        config.build_include_compiled();
+        config.sync_profile();
         Ok(config)
     }
 
+    /// Forget `settings.profile` once the values it set have been changed by hand.
+    fn sync_profile(&mut self) {
+        let s = &self.settings;
+        let matches = retention_profile(&s.profile).is_some_and(|p| {
+            p.max_history == s.max_history
+                && p.max_quota == s.max_quota
+                && p.trash_retention_days == s.trash_retention_days
+        });
+        if !matches {
+            self.settings.profile.clear();
+        }
+    }
+
+    fn build_include_compiled(&mut self) {
+        self.include_compiled = self
+            .watch
+            .patterns
+            .iter()
+            .filter_map(|p| Pattern::new(p).ok())
+            .collect();
+    }
+
     fn build_exclude_compiled(&mut self) {
         self.exclude_compiled = self
             .watch
@@ -115,9 +428,12 @@ impl Config {
             .collect();
     }
 
+    /// Write to a temporary file first, so a crash never leaves a truncated config.
     pub fn save(&self, path: &Path) -> Result<()> {
         let content = serde_yaml::to_string(self)?;
-        std::fs::write(path, content)?;
+        let tmp = path.with_extension("yaml.tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, path)?;
         Ok(())
     }
 
@@ -127,24 +443,200 @@ impl Config {
         let rel_path = path.strip_prefix(root_dir).unwrap_or(path);
         let path_str = path_util::normalize_rel_path(&rel_path.to_string_lossy());
 
-        if self.excluded_by_patterns(&path_str, None) {
+        if !self.in_roots(&path_str)
+            || self.hidden_excluded(&path_str)
+            || self.excluded_by_patterns(&path_str, None)
+        {
             return false;
         }
+        self.include_pattern(path, &path_str).is_some()
+    }
+
+    /// `watch.roots` without trailing slashes, leaving out those inside another root.
+    pub fn watch_roots(&self) -> Vec<String> {
+        let roots: Vec<String> = self
+            .watch
+            .roots
+            .iter()
+            .map(|r| path_util::normalize_rel_path(r.trim()))
+            .map(|r| r.trim_start_matches("./").trim_matches('/').to_string())
+            .filter(|r| !r.is_empty())
+            .collect();
+        let mut kept: Vec<String> = Vec::new();
+        for root in &roots {
+            let nested = roots
+                .iter()
+                .any(|other| other != root && root.starts_with(&format!("{}/", other)));
+            if !nested && !kept.contains(root) {
+                kept.push(root.clone());
+            }
+        }
+        kept
+    }
+
+    /// Directories the scanner and the watcher start from: the `watch.roots` under
+    /// `root_dir` that a walk from `root_dir` would enter (within `watch.max_depth`, not
+    /// excluded), or `root_dir` itself when there are none.
+    pub fn root_dirs(&self, root_dir: &Path) -> Vec<PathBuf> {
+        let roots = self.watch_roots();
+        if roots.is_empty() {
+            return vec![root_dir.to_path_buf()];
+        }
+        roots
+            .iter()
+            .filter(|r| {
+                let segments: Vec<&str> = r.split('/').collect();
+                self.within_max_depth(segments.len())
+                    && (1..=segments.len()).all(|end| {
+                        !self.is_excluded_dir(&root_dir.join(segments[..end].join("/")), root_dir)
+                    })
+            })
+            .map(|r| root_dir.join(r))
+            .collect()
+    }
+
+    /// Whether the relative path `path_str` lies in one of `watch.roots` (always, when
+    /// there are none).
+    fn in_roots(&self, path_str: &str) -> bool {
+        if self.watch.roots.is_empty() {
+            return true;
+        }
+        let roots = self.watch_roots();
+        roots.is_empty()
+            || roots.iter().any(|r| {
+                path_str
+                    .strip_prefix(r.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+
+    /// Why `path` is or is not tracked: `watch.max_depth`, the first hidden or excluded
+    /// parent directory (as the scanner descends), a hidden file name, an exclude pattern
+    /// on the file, the include pattern, or `settings.max_file_size`. `path` is absolute, `root_dir` the project root.
+    pub fn explain_path(&self, path: &Path, root_dir: &Path) -> PathMatch {
+        let rel_path = path.strip_prefix(root_dir).unwrap_or(path);
+        let path_str = path_util::normalize_rel_path(&rel_path.to_string_lossy());
+        let result = |tracked: bool, pattern: Option<&str>, reason: String| PathMatch {
+            path: path_str.clone(),
+            tracked,
+            pattern: pattern.map(str::to_string),
+            reason,
+        };
+
+        let segments: Vec<&str> = path_str.split('/').collect();
+        if !self.within_max_depth(segments.len() - 1) {
+            return result(
+                false,
+                None,
+                format!("deeper than watch.max_depth ({})", self.watch.max_depth),
+            );
+        }
+        if !self.in_roots(&path_str) {
+            return result(
+                false,
+                None,
+                format!("outside watch.roots ({})", self.watch_roots().join(", ")),
+            );
+        }
+        for end in 1..segments.len() {
+            let dir = segments[..end].join("/");
+            if self.hidden_excluded(segments[end - 1]) {
+                return result(
+                    false,
+                    None,
+                    format!(
+                        "directory '{}' is hidden and watch.include_hidden is false",
+                        dir
+                    ),
+                );
+            }
+            if let Some(p) = self.exclude_pattern(&dir, Some(&format!("{}/", dir))) {
+                return result(false, Some(p), format!("directory '{}' is excluded", dir));
+            }
+        }
+        if self.hidden_excluded(segments[segments.len() - 1]) {
+            return result(
+                false,
+                None,
+                "hidden and watch.include_hidden is false".to_string(),
+            );
+        }
+        if let Some(p) = self.exclude_pattern(&path_str, None) {
+            return result(false, Some(p), "excluded".to_string());
+        }
+        let Some(include) = self.include_pattern(path, &path_str) else {
+            return result(false, None, "no watch pattern matches".to_string());
+        };
+        match std::fs::metadata(path) {
+            Ok(m) if m.len() > self.settings.max_file_size => result(
+                false,
+                None,
+                format!(
+                    "larger than settings.max_file_size ({} > {} bytes)",
+                    m.len(),
+                    self.settings.max_file_size
+                ),
+            ),
+            _ => result(true, Some(include), "matches watch pattern".to_string()),
+        }
+    }
 
-        // Check include patterns
-        if let Some(ext) = path.extension() {
-            let ext_suffix = format!(".{}", ext.to_string_lossy());
-            return self.watch.patterns.iter().any(|p| p.ends_with(&ext_suffix));
+    /// Whether a directory `depth` levels below the watched directory is traversed.
+    pub fn within_max_depth(&self, depth: usize) -> bool {
+        self.watch.max_depth == 0 || depth <= self.watch.max_depth
+    }
+
+    /// The watch pattern admitting `path` (relative: `path_str`), by file extension or,
+    /// in `include_only` mode, by full path.
+    fn include_pattern(&self, path: &Path, path_str: &str) -> Option<&str> {
+        if self.watch.mode == WatchMode::IncludeOnly {
+            let options = MatchOptions {
+                require_literal_separator: true,
+                ..MatchOptions::new()
+            };
+            return self
+                .include_compiled
+                .iter()
+                .find(|p| p.matches_with(path_str, options))
+                .map(Pattern::as_str);
         }
+        let ext_suffix = format!(".{}", path.extension()?.to_string_lossy());
+        self.watch
+            .patterns
+            .iter()
+            .find(|p| p.ends_with(&ext_suffix))
+            .map(String::as_str)
+    }
+
+    /// Check if a directory path matches any exclude pattern.
+    /// Used to skip entire directory trees early.
+    pub fn is_excluded_dir(&self, path: &Path, root_dir: &Path) -> bool {
+        let rel_path = path.strip_prefix(root_dir).unwrap_or(path);
+        let path_str = path_util::normalize_rel_path(&rel_path.to_string_lossy());
+        let dir_str = format!("{}/", path_str);
+        self.hidden_excluded(&path_str) || self.excluded_by_patterns(&path_str, Some(&dir_str))
+    }
 
-        false
+    /// Whether `watch.include_hidden` leaves out the relative path `path_str`, because
+    /// one of its components starts with a dot.
+    fn hidden_excluded(&self, path_str: &str) -> bool {
+        !self.watch.include_hidden
+            && path_str
+                .split('/')
+                .any(|c| c.starts_with('.') && c != "." && c != "..")
     }
 
     /// Returns true if path_str or (if provided) dir_str matches any compiled exclude pattern.
     pub(crate) fn excluded_by_patterns(&self, path_str: &str, dir_str: Option<&str>) -> bool {
+        self.exclude_pattern(path_str, dir_str).is_some()
+    }
+
+    /// The first exclude pattern matching path_str or (if provided) dir_str.
+    fn exclude_pattern(&self, path_str: &str, dir_str: Option<&str>) -> Option<&str> {
         self.exclude_compiled
             .iter()
-            .any(|p| p.matches(path_str) || dir_str.is_some_and(|d| p.matches(d)))
+            .find(|p| p.matches(path_str) || dir_str.is_some_and(|d| p.matches(d)))
+            .map(Pattern::as_str)
     }
 
     /// Get a config value by dot-notation key (e.g. "settings.max_history").
@@ -155,12 +647,59 @@ impl Config {
             "settings.max_quota" => Ok(self.settings.max_quota.to_string()),
             "settings.scan_interval" => Ok(self.settings.scan_interval.to_string()),
             "settings.clean_interval" => Ok(self.settings.clean_interval.to_string()),
+            "settings.trash_retention_days" => Ok(self.settings.trash_retention_days.to_string()),
+            "settings.min_versions_per_file" => Ok(self.settings.min_versions_per_file.to_string()),
+            "settings.scan_throttle" => Ok(self.settings.scan_throttle.to_string()),
+            "settings.min_free_space" => Ok(self.settings.min_free_space.to_string()),
+            "settings.verify_interval" => Ok(self.settings.verify_interval.to_string()),
+            "settings.sign_history" => Ok(self.settings.sign_history.to_string()),
+            "settings.diff_workers" => Ok(self.settings.diff_workers.to_string()),
+            "settings.diff_timeout_ms" => Ok(self.settings.diff_timeout_ms.to_string()),
+            "settings.quota_warn_percent" => Ok(self.settings.quota_warn_percent.to_string()),
+            "settings.track_empty_files" => Ok(self.settings.track_empty_files.to_string()),
+            "settings.max_snapshots_per_minute" => {
+                Ok(self.settings.max_snapshots_per_minute.to_string())
+            }
+            "settings.max_tracked_files" => Ok(self.settings.max_tracked_files.to_string()),
+            "settings.track_writers" => Ok(self.settings.track_writers.to_string()),
+            "settings.git_aware" => Ok(self.settings.git_aware.to_string()),
+            "settings.track_xattrs" => Ok(self.settings.track_xattrs.to_string()),
+            "settings.ui_listen" => Ok(self.settings.ui_listen.clone()),
+            "settings.api_rate_limit" => Ok(self.settings.api_rate_limit.to_string()),
+            "settings.incremental_scan" => Ok(self.settings.incremental_scan.to_string()),
+            "settings.snapshot_store" => Ok(self.settings.snapshot_store.clone()),
+            "settings.shard_depth" => Ok(self.settings.shard_depth.to_string()),
+            "settings.profile" => Ok(self.settings.profile.clone()),
+            "settings.snapshot_trigger" => Ok(match self.settings.snapshot_trigger {
+                SnapshotTrigger::CloseWrite => "close_write",
+                SnapshotTrigger::DebouncedModify => "debounced_modify",
+                SnapshotTrigger::Poll => "poll",
+            }
+            .to_string()),
             "watch.patterns" => Ok(self.watch.patterns.join(",")),
+            "watch.mode" => Ok(match self.watch.mode {
+                WatchMode::Extension => "extension",
+                WatchMode::IncludeOnly => "include_only",
+            }
+            .to_string()),
             "watch.exclude" => Ok(self.watch.exclude.join(",")),
+            "watch.max_depth" => Ok(self.watch.max_depth.to_string()),
+            "watch.include_hidden" => Ok(self.watch.include_hidden.to_string()),
+            "watch.roots" => Ok(self.watch.roots.join(",")),
             _ => anyhow::bail!(
                 "Unknown config key '{}'. Valid keys: settings.max_history, \
-                 settings.max_file_size, settings.max_quota, settings.scan_interval, settings.clean_interval, \
-                 watch.patterns, watch.exclude",
+                 settings.max_file_size, settings.max_quota, settings.scan_interval, \
+                 settings.clean_interval, settings.trash_retention_days, \
+                 settings.min_versions_per_file, settings.scan_throttle, \
+                 settings.min_free_space, settings.verify_interval, settings.sign_history, \
+                 settings.diff_workers, settings.diff_timeout_ms, settings.quota_warn_percent, \
+                 settings.track_empty_files, settings.max_snapshots_per_minute, \
+                 settings.max_tracked_files, settings.track_writers, settings.git_aware, \
+                 settings.track_xattrs, settings.ui_listen, settings.api_rate_limit, \
+                 settings.incremental_scan, settings.snapshot_store, settings.shard_depth, \
+                 settings.snapshot_trigger, settings.profile, \
+                 watch.patterns, watch.mode, watch.exclude, watch.max_depth, \
+                 watch.include_hidden, watch.roots",
                 key
             ),
         }
@@ -206,20 +745,273 @@ impl Config {
                 }
                 self.settings.clean_interval = v;
             }
+            "settings.trash_retention_days" => {
+                self.settings.trash_retention_days = value.parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid value for trash_retention_days: {}", value)
+                })?;
+            }
+            "settings.min_versions_per_file" => {
+                self.settings.min_versions_per_file = value.parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid value for min_versions_per_file: {}", value)
+                })?;
+            }
+            "settings.scan_throttle" => {
+                self.settings.scan_throttle = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for scan_throttle: {}", value))?;
+            }
+            "settings.min_free_space" => {
+                self.settings.min_free_space = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for min_free_space: {}", value))?;
+            }
+            "settings.verify_interval" => {
+                self.settings.verify_interval = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for verify_interval: {}", value))?;
+            }
+            "settings.sign_history" => {
+                self.settings.sign_history = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for sign_history: {}", value))?;
+            }
+            "settings.diff_workers" => {
+                self.settings.diff_workers = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for diff_workers: {}", value))?;
+            }
+            "settings.diff_timeout_ms" => {
+                let v: u64 = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for diff_timeout_ms: {}", value))?;
+                if v == 0 {
+                    anyhow::bail!("diff_timeout_ms must be > 0, got {}", v);
+                }
+                self.settings.diff_timeout_ms = v;
+            }
+            "settings.quota_warn_percent" => {
+                let v: u64 = value.parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid value for quota_warn_percent: {}", value)
+                })?;
+                if v > 100 {
+                    anyhow::bail!("quota_warn_percent must be <= 100, got {}", v);
+                }
+                self.settings.quota_warn_percent = v;
+            }
+            "settings.track_empty_files" => {
+                self.settings.track_empty_files = value.parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid value for track_empty_files: {}", value)
+                })?;
+            }
+            "settings.max_snapshots_per_minute" => {
+                self.settings.max_snapshots_per_minute = value.parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid value for max_snapshots_per_minute: {}", value)
+                })?;
+            }
+            "settings.max_tracked_files" => {
+                self.settings.max_tracked_files = value.parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid value for max_tracked_files: {}", value)
+                })?;
+            }
+            "settings.track_writers" => {
+                self.settings.track_writers = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for track_writers: {}", value))?;
+            }
+            "settings.git_aware" => {
+                self.settings.git_aware = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for git_aware: {}", value))?;
+            }
+            "settings.track_xattrs" => {
+                self.settings.track_xattrs = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for track_xattrs: {}", value))?;
+            }
+            "settings.ui_listen" => {
+                let value = value.trim();
+                if !value.is_empty() && value.parse::<std::net::SocketAddr>().is_err() {
+                    anyhow::bail!(
+                        "Invalid value for ui_listen: {} (expected address:port, e.g. 0.0.0.0:13581)",
+                        value
+                    );
+                }
+                self.settings.ui_listen = value.to_string();
+            }
+            "settings.api_rate_limit" => {
+                self.settings.api_rate_limit = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for api_rate_limit: {}", value))?;
+            }
+            "settings.incremental_scan" => {
+                self.settings.incremental_scan = value.parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid value for incremental_scan: {}", value)
+                })?;
+            }
+            "settings.snapshot_store" => {
+                if !value.is_empty() && !Path::new(value).is_absolute() {
+                    anyhow::bail!("settings.snapshot_store must be an absolute path");
+                }
+                self.settings.snapshot_store = value.to_string();
+            }
+            "settings.shard_depth" => {
+                let depth: usize = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for shard_depth: {}", value))?;
+                if depth > crate::store::MAX_SHARD_DEPTH {
+                    anyhow::bail!(
+                        "settings.shard_depth must be at most {}",
+                        crate::store::MAX_SHARD_DEPTH
+                    );
+                }
+                self.settings.shard_depth = depth;
+            }
+            "settings.snapshot_trigger" => {
+                self.settings.snapshot_trigger = match value.trim() {
+                    "close_write" => SnapshotTrigger::CloseWrite,
+                    "debounced_modify" => SnapshotTrigger::DebouncedModify,
+                    "poll" => SnapshotTrigger::Poll,
+                    _ => anyhow::bail!(
+                        "Invalid value for snapshot_trigger: {} (expected close_write, \
+                         debounced_modify or poll)",
+                        value
+                    ),
+                };
+            }
+            "settings.profile" => {
+                let Some(profile) = retention_profile(value.trim()) else {
+                    let names: Vec<&str> = RETENTION_PROFILES.iter().map(|p| p.name).collect();
+                    anyhow::bail!(
+                        "Unknown profile '{}' (expected one of: {})",
+                        value,
+                        names.join(", ")
+                    );
+                };
+                self.settings.max_history = profile.max_history;
+                self.settings.max_quota = profile.max_quota;
+                self.settings.trash_retention_days = profile.trash_retention_days;
+                self.settings.profile = profile.name.to_string();
+            }
             "watch.patterns" => {
                 self.watch.patterns = value.split(',').map(|s| s.trim().to_string()).collect();
+                self.build_include_compiled();
+            }
+            "watch.mode" => {
+                self.watch.mode = match value.trim() {
+                    "extension" => WatchMode::Extension,
+                    "include_only" => WatchMode::IncludeOnly,
+                    _ => anyhow::bail!(
+                        "Invalid value for mode: {} (expected extension or include_only)",
+                        value
+                    ),
+                };
             }
             "watch.exclude" => {
                 self.watch.exclude = value.split(',').map(|s| s.trim().to_string()).collect();
                 self.build_exclude_compiled();
             }
+            "watch.max_depth" => {
+                self.watch.max_depth = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for max_depth: {}", value))?;
+            }
+            "watch.include_hidden" => {
+                self.watch.include_hidden = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for include_hidden: {}", value))?;
+            }
+            "watch.roots" => {
+                let roots: Vec<String> = value
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+                check_roots(&roots)?;
+                self.watch.roots = roots;
+            }
             _ => anyhow::bail!(
                 "Unknown config key '{}'. Valid keys: settings.max_history, \
-                 settings.max_file_size, settings.max_quota, settings.scan_interval, settings.clean_interval, \
-                 watch.patterns, watch.exclude",
+                 settings.max_file_size, settings.max_quota, settings.scan_interval, \
+                 settings.clean_interval, settings.trash_retention_days, \
+                 settings.min_versions_per_file, settings.scan_throttle, \
+                 settings.min_free_space, settings.verify_interval, settings.sign_history, \
+                 settings.diff_workers, settings.diff_timeout_ms, settings.quota_warn_percent, \
+                 settings.track_empty_files, settings.max_snapshots_per_minute, \
+                 settings.max_tracked_files, settings.track_writers, settings.git_aware, \
+                 settings.track_xattrs, settings.ui_listen, settings.api_rate_limit, \
+                 settings.incremental_scan, settings.snapshot_store, settings.shard_depth, \
+                 settings.snapshot_trigger, settings.profile, \
+                 watch.patterns, watch.mode, watch.exclude, watch.max_depth, \
+                 watch.include_hidden, watch.roots",
                 key
             ),
         }
+        self.sync_profile();
+        Ok(())
+    }
+
+    /// Take every value of `next`, validated as `set_value` would; on failure nothing
+    /// changes and the error of each invalid key is returned. A changed profile applies
+    /// its limits unless `next` changes them too.
+    pub fn update_from(&mut self, next: &Config) -> Result<(), BTreeMap<String, String>> {
+        let mut updated = self.clone();
+        let mut errors = BTreeMap::new();
+        let keys = std::iter::once("settings.profile")
+            .chain(KEYS.iter().copied().filter(|k| *k != "settings.profile"));
+        for key in keys {
+            let (Ok(old), Ok(value)) = (self.get_value(key), next.get_value(key)) else {
+                continue;
+            };
+            if value == old {
+                continue;
+            }
+            // Lists are taken as they are, since the comma-joined form may be ambiguous
+            let result = match key {
+                "settings.profile" if value.is_empty() => {
+                    updated.settings.profile.clear();
+                    Ok(())
+                }
+                "watch.patterns" => {
+                    updated.watch.patterns = next.watch.patterns.clone();
+                    updated.build_include_compiled();
+                    Ok(())
+                }
+                "watch.exclude" => {
+                    updated.watch.exclude = next.watch.exclude.clone();
+                    updated.build_exclude_compiled();
+                    Ok(())
+                }
+                "watch.roots" => check_roots(&next.watch.roots)
+                    .map(|()| updated.watch.roots = next.watch.roots.clone()),
+                _ => updated.set_value(key, &value),
+            };
+            if let Err(e) = result {
+                errors.insert(key.to_string(), e.to_string());
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        updated.sync_profile();
+        *self = updated;
         Ok(())
     }
 }
+
+/// `watch.roots` must stay inside the watched directory.
+fn check_roots(roots: &[String]) -> Result<()> {
+    for root in roots {
+        let path = Path::new(root);
+        if path.is_absolute()
+            || path
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            anyhow::bail!(
+                "watch.roots must be relative paths inside the watched directory: {}",
+                root
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,120 @@
+//! Content type of snapshots, recorded with each history entry so `/api/snapshot` can
+//! serve an accurate `Content-Type` and the web UI knows the language to highlight.
+//! Detection looks at magic numbers in the first bytes, then the file extension, then a
+//! `#!` line; content that is not UTF-8 text without a known signature is
+//! `application/octet-stream`.
+
+use std::path::Path;
+
+/// Bytes of a file's start kept for sniffing.
+pub const SNIFF_LEN: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentType {
+    pub mime: &'static str,
+    /// Language name for syntax highlighting (e.g. `rust`), for source and markup files.
+    pub language: Option<&'static str>,
+}
+
+impl ContentType {
+    const fn new(mime: &'static str, language: Option<&'static str>) -> Self {
+        Self { mime, language }
+    }
+}
+
+const OCTET_STREAM: ContentType = ContentType::new("application/octet-stream", None);
+const PLAIN_TEXT: ContentType = ContentType::new("text/plain", None);
+
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x7fELF", "application/x-executable"),
+];
+
+fn by_extension(ext: &str) -> Option<ContentType> {
+    let (mime, language) = match ext {
+        "rs" => ("text/x-rust", "rust"),
+        "py" => ("text/x-python", "python"),
+        "md" | "markdown" => ("text/markdown", "markdown"),
+        "json" => ("application/json", "json"),
+        "yml" | "yaml" => ("application/yaml", "yaml"),
+        "toml" => ("application/toml", "toml"),
+        "js" | "mjs" | "cjs" => ("text/javascript", "javascript"),
+        "ts" => ("text/x-typescript", "typescript"),
+        "html" | "htm" => ("text/html", "html"),
+        "css" => ("text/css", "css"),
+        "xml" => ("application/xml", "xml"),
+        "svg" => ("image/svg+xml", "xml"),
+        "sh" | "bash" => ("text/x-shellscript", "shell"),
+        "c" | "h" => ("text/x-c", "c"),
+        "cc" | "cpp" | "cxx" | "hpp" => ("text/x-c++", "cpp"),
+        "go" => ("text/x-go", "go"),
+        "java" => ("text/x-java", "java"),
+        "ini" | "conf" => ("text/plain", "ini"),
+        "txt" | "log" | "vision" | "task" => return Some(PLAIN_TEXT),
+        "csv" => return Some(ContentType::new("text/csv", None)),
+        _ => return None,
+    };
+    Some(ContentType::new(mime, Some(language)))
+}
+
+/// Language of a script from its `#!` line, e.g. `#!/usr/bin/env python3`.
+fn by_shebang(head: &[u8]) -> Option<ContentType> {
+    let line = head.strip_prefix(b"#!")?;
+    let line = &line[..line.iter().position(|&b| b == b'\n').unwrap_or(line.len())];
+    let line = std::str::from_utf8(line).ok()?;
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|w| !w.starts_with('-'))?;
+    }
+    match program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+        "python" => by_extension("py"),
+        "sh" | "bash" | "zsh" | "dash" => by_extension("sh"),
+        "node" => by_extension("js"),
+        _ => None,
+    }
+}
+
+/// Content type of the file at `path` given its first bytes (at most `SNIFF_LEN`) and
+/// whether the whole content is valid UTF-8.
+pub fn detect(path: &Path, head: &[u8], utf8: bool) -> ContentType {
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return ContentType::new(mime, None);
+    }
+    if !utf8 || head.contains(&0) {
+        return OCTET_STREAM;
+    }
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    ext.as_deref()
+        .and_then(by_extension)
+        .or_else(|| by_shebang(head))
+        .unwrap_or(PLAIN_TEXT)
+}
+
+/// `Content-Type` header for serving content of type `mime`. Types a browser would
+/// render as an active document (HTML, SVG) are served as plain text, since snapshots
+/// share an origin with the web UI.
+pub fn header_value(mime: &str) -> String {
+    let mime = match mime {
+        "text/html" | "image/svg+xml" | "application/xhtml+xml" => "text/plain",
+        m => m,
+    };
+    let textual = mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json" | "application/yaml" | "application/toml" | "application/xml"
+        );
+    if textual {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
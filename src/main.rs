@@ -1,27 +1,78 @@
+mod api_token;
+mod autostart;
+mod bundle;
 mod client;
 mod config;
+mod content_type;
+mod mdns;
+mod mirror;
+mod os_trash;
 mod path_util;
+mod registry;
+mod rpc;
 mod scanner;
 mod server;
+mod share;
+mod signing;
+mod similarity;
 mod storage;
+mod store;
+mod tar;
+mod timeline;
+#[cfg(feature = "tray")]
+mod tray;
 mod types;
+mod user_state;
+mod volume;
 mod watcher;
+mod workspace;
+mod writers;
+mod xattrs;
+mod zip;
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "ftm", about = "File Time Machine - Text file version tracking")]
 struct Cli {
-    /// Server port (used by serve and all client commands)
-    #[arg(long, default_value_t = 13580, global = true)]
-    port: u16,
+    /// Server port (used by serve and all client commands). Client commands default to the
+    /// port registered in the nearest .ftm above the current directory, then 13580.
+    /// `auto` lets checkout pick any free port.
+    #[arg(long, global = true)]
+    port: Option<PortArg>,
+
+    /// Server on another machine, as host or host:port, e.g. one reached over SSH port
+    /// forwarding. Only read-only commands may target it; FTM_SERVER sets it for those.
+    #[arg(long, global = true)]
+    host: Option<String>,
 
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Value of `--port`: a fixed port number, or `auto` to bind any free port.
+#[derive(Clone, Copy)]
+enum PortArg {
+    Fixed(u16),
+    Auto,
+}
+
+impl std::str::FromStr for PortArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(PortArg::Auto);
+        }
+        s.parse()
+            .map(PortArg::Fixed)
+            .map_err(|_| format!("invalid port '{}': expected a number or 'auto'", s))
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Print client and server version
@@ -30,6 +81,13 @@ enum Commands {
     Checkout {
         /// Directory to watch (absolute or relative path)
         directory: PathBuf,
+        /// Address the server binds to (default 127.0.0.1; IPv6 such as ::1 is supported,
+        /// :: listens dual-stack). Non-loopback addresses are advertised on the LAN via mDNS (_ftm._tcp)
+        #[arg(long)]
+        bind: Option<IpAddr>,
+        /// Check out even if more files match than settings.max_tracked_files
+        #[arg(long)]
+        force: bool,
     },
     /// List tracked files (excludes deleted by default; use --include-deleted to show all)
     Ls {
@@ -38,35 +96,239 @@ enum Commands {
         include_deleted: bool,
     },
     /// Scan directory for changes (detect creates, modifies, deletes)
-    Scan,
+    Scan {
+        /// Hash files and list the changes a scan would record, without recording them
+        #[arg(long)]
+        dry_run: bool,
+        /// Scan even if more files match than settings.max_tracked_files
+        #[arg(long)]
+        force: bool,
+        /// List every created, modified and deleted file
+        #[arg(long, short)]
+        verbose: bool,
+        /// Print the full result, with every changed file, as JSON
+        #[arg(long, conflicts_with = "verbose")]
+        json: bool,
+    },
+    /// Stop snapshotting for a bulk operation (e.g. checking out a big branch): changes
+    /// are only noted until bulk-end, then recorded by one scan
+    BulkBegin {
+        /// End bulk mode by itself after this long: a number with unit s, m, h or d
+        #[arg(long, default_value = "30m")]
+        timeout: String,
+    },
+    /// End bulk mode and record the changes made meanwhile
+    BulkEnd,
+    /// Pause recording until resume: changes are only noted, then recorded by one scan
+    Pause,
+    /// Resume recording and record the changes made while paused
+    Resume,
+    /// Show a system-tray icon with the server's status and quick actions (needs yad)
+    #[cfg(feature = "tray")]
+    Tray,
     /// Remove snapshot files not referenced by any history entry
     Clean,
-    /// Show version history for a file
+    /// Recover trimmed history entries and snapshots still in the trash
+    Unclean,
+    /// Clean and report what every step (trim, orphans, trash, temp files, empty directories) removed
+    Gc {
+        /// Also empty the trash now instead of after the retention window (cannot be undone)
+        #[arg(long)]
+        aggressive: bool,
+    },
+    /// List snapshot files no history entry references (what clean would remove), without deleting them
+    Orphans,
+    /// Move existing snapshots into the layout of settings.shard_depth
+    MigrateStore,
+    /// Show version history for a file (a path, or its key in the watched directory)
     History { file: String },
+    /// Print the content of a version of a file (default: its latest)
+    Cat {
+        file: String,
+        /// Checksum of the version (at least first 8 chars)
+        checksum: Option<String>,
+    },
+    /// Show the changes between two versions of a file (default: its last two)
+    Diff {
+        file: String,
+        /// Checksum of the older version (at least first 8 chars)
+        from: Option<String>,
+        /// Checksum of the newer version (default: the latest)
+        to: Option<String>,
+    },
     /// Restore a file to a specific version
     Restore {
         file: String,
         /// Checksum of the version to restore (at least first 8 chars)
+        #[arg(required_unless_present = "from_trash")]
+        checksum: Option<String>,
+        /// Bring back the last version of a deleted file, e.g. one moved to the Trash
+        #[arg(long, conflicts_with = "checksum")]
+        from_trash: bool,
+    },
+    /// Print a link serving one version of a file read-only, e.g. to send a colleague
+    Share {
+        file: String,
+        /// Checksum of the version to share (at least first 8 chars)
         checksum: String,
+        /// How long the link works: a number with unit s, m, h or d
+        #[arg(long, default_value = "24h")]
+        expires: String,
+    },
+    /// Delete a tracked file, recording the delete as intended (snapshotting unsaved content first)
+    Rm { file: String },
+    /// Restore every deleted file under a directory to its last known version
+    RestoreDir {
+        /// Directory relative to the watched root ("." for everything)
+        dir: String,
+        /// Only files that existed at this time, at their version then (YYYY-MM-DD or RFC 3339)
+        #[arg(long)]
+        at: Option<String>,
+    },
+    /// Download a zip of every file under a directory as it was at a given time
+    ExportDir {
+        /// Directory relative to the watched root ("." for everything)
+        dir: String,
+        /// Files as they were at this time (YYYY-MM-DD or RFC 3339; default: now)
+        #[arg(long)]
+        at: Option<String>,
+        /// Zip archive to create (must not exist yet)
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Start the server and watch every directory listed in the workspace file
+    Up {
+        /// Workspace file (default: workspace.yaml in the user config directory,
+        /// e.g. ~/.config/ftm/workspace.yaml)
+        #[arg(long)]
+        workspace: Option<PathBuf>,
+    },
+    /// Serve several directories from one server, each as a named project
+    Project {
+        #[command(subcommand)]
+        action: ProjectAction,
+    },
+    /// Show whether a path would be tracked and which pattern or setting decided it
+    CheckPath {
+        /// File path (absolute or relative to the current directory)
+        path: PathBuf,
     },
     /// Get or set configuration values
     Config {
         #[command(subcommand)]
         action: ConfigAction,
     },
-    /// Show history and quota usage (current / max)
-    Stats,
-    /// Start the FTM server (daemon mode, internal use only)
-    #[command(hide = true)]
+    /// Show history and quota usage (current / max), or the history statistics of one file
+    Stats {
+        /// Show version count, snapshot bytes and change timing of this file instead
+        file: Option<String>,
+        /// Also estimate when max_history and max_quota will be reached at the recent growth rate
+        #[arg(long, conflicts_with = "file")]
+        quota_projection: bool,
+        /// Also break referenced snapshots down by age (today, this week, this month, older)
+        #[arg(long, conflicts_with = "file")]
+        age: bool,
+    },
+    /// Export every recorded operation (versions, restores, config changes, cleans) as JSON lines
+    Audit {
+        /// Only records from this date on (YYYY-MM-DD or RFC 3339)
+        #[arg(long)]
+        since: Option<String>,
+        /// Write to this file instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// List new files that are likely copies or renames of other tracked files
+    Similar {
+        /// Minimum share of common lines, in percent
+        #[arg(long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(1..=100))]
+        threshold: u8,
+        /// Only files created from this date on (YYYY-MM-DD or RFC 3339)
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Speak JSON-RPC over stdin/stdout, for editor plugins
+    Rpc,
+    /// Check history entry signatures (see settings.sign_history)
+    VerifySignatures {
+        /// Hex ed25519 public key to verify against (default: your own signing key)
+        #[arg(long)]
+        public_key: Option<String>,
+    },
+    /// Run the FTM server in the foreground (checkout starts one automatically)
     Serve {
         /// Custom log directory (default: .ftm/logs/)
         #[arg(long)]
         log_dir: Option<PathBuf>,
+        /// Record the bound port in this .ftm directory's server.json right after binding
+        #[arg(long)]
+        register: Option<PathBuf>,
+        /// Address to bind (default 127.0.0.1)
+        #[arg(long)]
+        bind: Option<IpAddr>,
+        /// Serve a read-only mirror of another FTM server, pulling its history every
+        /// settings.scan_interval seconds
+        #[arg(long, value_name = "SOURCE_URL")]
+        mirror: Option<String>,
+        /// Directory holding the mirror's .ftm (default: current directory)
+        #[arg(long, requires = "mirror")]
+        mirror_dir: Option<PathBuf>,
+        /// Re-check out the directory of the last successful checkout (for a daemon started at login)
+        #[arg(long, conflicts_with = "mirror")]
+        resume: bool,
+    },
+    /// Send history entries and snapshots missing on another FTM server
+    Push {
+        /// Base URL of the remote server (e.g. http://desktop.local:13580)
+        remote: String,
+    },
+    /// Fetch history entries and snapshots missing here from another FTM server
+    Pull {
+        /// Base URL of the remote server (e.g. http://desktop.local:13580)
+        remote: String,
+    },
+    /// Write the history and every snapshot it references into a portable bundle directory
+    ExportIndex {
+        /// Bundle directory to create (must not contain a bundle yet)
+        dir: PathBuf,
+    },
+    /// Add the history of a bundle written by export-index, after validating every checksum
+    ImportIndex {
+        /// Bundle directory
+        dir: PathBuf,
     },
+    /// Write every version of one file and its history into a tar archive
+    ExportFile {
+        file: String,
+        /// Archive to create (must not exist yet)
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Add a file's history from an archive written by export-file, after validating every checksum
+    ImportFile {
+        /// Archive written by export-file
+        archive: PathBuf,
+    },
+    /// Start `ftm serve --resume` at login (systemd user unit, launchd agent, or Windows Run key)
+    Autostart {
+        #[command(subcommand)]
+        action: AutostartAction,
+    },
+    /// Open the Web UI in the default browser ($BROWSER if set)
+    Open,
+    /// Check end to end that changes are captured: write, modify, restore and delete a probe file
+    Selftest,
     /// Show logs (opens latest log file with less)
     Logs,
     /// Stop the running FTM server gracefully
-    Stop,
+    Stop {
+        /// Stop every running ftm server
+        #[arg(long, conflicts_with = "dir")]
+        all: bool,
+        /// Stop the server watching this directory
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -83,13 +345,95 @@ enum ConfigAction {
         /// New value (use comma-separated for list keys)
         value: String,
     },
+    /// List recorded config changes (oldest first)
+    Log {
+        /// Only changes to this key
+        key: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProjectAction {
+    /// Start watching a directory as another project of the running server
+    Add {
+        directory: PathBuf,
+        /// Project name used in URLs (default: the directory name)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// List the server's projects
+    List,
+}
+
+#[derive(Subcommand)]
+enum AutostartAction {
+    /// Install the login-time entry (uses --port if given)
+    Enable,
+    /// Remove the login-time entry
+    Disable,
+}
+
+/// Address the server binds to when `--bind` is not given.
+const DEFAULT_BIND: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// Port for client commands: explicit `--port`, else the server registered in the
+/// nearest `.ftm` above the current directory, else the default port. The registered
+/// host is used when it belongs to the chosen port.
+fn resolve_client_port(port: Option<PortArg>) -> u16 {
+    let discovered = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| registry::discover_server(&cwd));
+    let port = match (port, &discovered) {
+        (Some(PortArg::Fixed(p)), _) => p,
+        (_, Some(info)) => info.port,
+        _ => registry::DEFAULT_PORT,
+    };
+    if let Some(info) = discovered.filter(|info| info.port == port) {
+        client::set_server_host(info.host);
+        client::set_server_project(info.project);
+    }
+    port
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    // FTM_SERVER, set for a whole shell, only redirects the read-only commands; the
+    // others keep going to the local server, while an explicit --host refuses them
+    let remote = match cli.host.filter(|h| !h.is_empty()) {
+        Some(host) => Some(host),
+        None if is_read_only(&cli.command) => {
+            std::env::var("FTM_SERVER").ok().filter(|h| !h.is_empty())
+        }
+        None => None,
+    };
+    if let Some(remote) = remote {
+        return run_remote_command(cli.command, &remote, cli.port);
+    }
 
     match cli.command {
-        Commands::Serve { log_dir } => {
+        Commands::Serve {
+            log_dir,
+            register,
+            bind,
+            mirror,
+            mirror_dir,
+            resume,
+        } => {
+            let mirror = match mirror {
+                Some(source) => {
+                    let dir = match mirror_dir {
+                        Some(dir) => dir,
+                        None => std::env::current_dir()?,
+                    };
+                    std::fs::create_dir_all(&dir)?;
+                    Some(mirror::MirrorOptions {
+                        source: client::normalize_remote(&source),
+                        dir: dir.canonicalize()?,
+                    })
+                }
+                None => None,
+            };
+
             // Initialize logging
             if let Some(log_dir) = log_dir {
                 init_file_logging(&log_dir)?;
@@ -99,9 +443,27 @@ fn main() -> Result<()> {
 
             // Start async server (Web UI always enabled)
             let rt = tokio::runtime::Runtime::new()?;
-            rt.block_on(server::serve(cli.port))
+            let port = match cli.port {
+                Some(PortArg::Fixed(p)) => p,
+                Some(PortArg::Auto) => 0,
+                None => registry::DEFAULT_PORT,
+            };
+            rt.block_on(server::serve(
+                port,
+                bind.unwrap_or(DEFAULT_BIND),
+                register,
+                mirror,
+                resume,
+            ))
         }
-        Commands::Checkout { directory } => {
+        Commands::Checkout {
+            directory,
+            bind,
+            force,
+        } => {
+            let bind = bind.unwrap_or(DEFAULT_BIND);
+            client::set_server_host(registry::connect_host(bind));
+
             // Resolve to absolute path
             let abs_dir = if directory.is_absolute() {
                 directory
@@ -110,15 +472,25 @@ fn main() -> Result<()> {
             };
             let abs_dir = abs_dir.canonicalize().unwrap_or_else(|_| abs_dir.clone());
 
+            // With `--port auto` the server already watching this directory (if any)
+            // is found through its registry entry.
+            let existing_port = match cli.port {
+                Some(PortArg::Fixed(p)) => Some(p),
+                Some(PortArg::Auto) => {
+                    registry::read_server_info(&abs_dir.join(".ftm")).map(|info| info.port)
+                }
+                None => Some(registry::DEFAULT_PORT),
+            };
+
             // If a server is already watching the exact same directory, keep it
             // but still kill every other ftm process to guarantee a single server.
-            if client::is_server_running(cli.port) {
-                if let Ok(health) = client::client_health(cli.port) {
+            if let Some(port) = existing_port.filter(|p| client::is_server_running(*p)) {
+                if let Ok(health) = client::client_health(port) {
                     if let Some(ref watch_dir) = health.watch_dir {
-                        if PathBuf::from(watch_dir) == abs_dir {
+                        if std::path::Path::new(watch_dir) == abs_dir {
                             kill_all_servers(health.pid);
                             println!("Already watching: {}", abs_dir.display());
-                            println!("Web UI: http://127.0.0.1:{}", cli.port);
+                            println!("Web UI: {}", client::base_url(port));
                             return Ok(());
                         }
                     }
@@ -127,39 +499,329 @@ fn main() -> Result<()> {
 
             // Kill all ftm server processes, then start a fresh one.
             kill_all_servers(None);
-            wait_for_port_free(cli.port);
-            auto_start_server(cli.port, &abs_dir)?;
-
-            client::client_checkout(cli.port, &abs_dir.to_string_lossy())?;
-            println!("Web UI: http://127.0.0.1:{}", cli.port);
+            let requested_port = match cli.port {
+                Some(PortArg::Fixed(p)) => p,
+                Some(PortArg::Auto) => 0,
+                None => registry::DEFAULT_PORT,
+            };
+            if requested_port != 0 {
+                wait_for_port_free(bind, requested_port);
+            }
+            let port = auto_start_server(bind, requested_port, Some(&abs_dir))?;
+
+            client::client_checkout(port, &abs_dir.to_string_lossy(), force)?;
+            println!("Web UI: {}", client::base_url(port));
+            if let Some(path) = api_token::path().filter(|_| !bind.is_loopback()) {
+                println!(
+                    "Changes from other machines need FTM_TOKEN set to the token in {}",
+                    path.display()
+                );
+            }
             Ok(())
         }
-        Commands::Version => client::client_version(cli.port),
-        Commands::Ls { include_deleted } => client::client_ls(cli.port, include_deleted),
-        Commands::History { file } => client::client_history(cli.port, &file),
-        Commands::Restore { file, checksum } => client::client_restore(cli.port, &file, &checksum),
-        Commands::Scan => client::client_scan(cli.port),
-        Commands::Clean => client::client_clean(cli.port),
+        Commands::Up { workspace } => {
+            let path = workspace
+                .or_else(workspace::default_path)
+                .context("No user config directory for the workspace file")?;
+            let requested_port = match cli.port {
+                Some(PortArg::Fixed(p)) => p,
+                Some(PortArg::Auto) => 0,
+                None => registry::DEFAULT_PORT,
+            };
+            up(&path, requested_port)
+        }
+        Commands::Autostart { action } => match action {
+            AutostartAction::Enable => autostart::enable(match cli.port {
+                Some(PortArg::Fixed(p)) => Some(p),
+                Some(PortArg::Auto) => Some(0),
+                None => None,
+            }),
+            AutostartAction::Disable => autostart::disable(),
+        },
+        command => run_client_command(command, resolve_client_port(cli.port)),
+    }
+}
+
+/// Commands that only read a server's history, and so may target another machine.
+fn is_read_only(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Version
+            | Commands::Ls { .. }
+            | Commands::History { .. }
+            | Commands::Cat { .. }
+            | Commands::Diff { .. }
+            | Commands::Stats { .. }
+            | Commands::Audit { .. }
+            | Commands::Similar { .. }
+            | Commands::VerifySignatures { .. }
+            | Commands::ExportDir { .. }
+            | Commands::ExportFile { .. }
+            | Commands::Config {
+                action: ConfigAction::Get { .. } | ConfigAction::Log { .. }
+            }
+    )
+}
+
+/// Run a read-only command against the server at `remote` (`host` or `host:port`;
+/// an explicit `--port` wins).
+fn run_remote_command(command: Commands, remote: &str, port: Option<PortArg>) -> Result<()> {
+    if !is_read_only(&command) {
+        anyhow::bail!(
+            "Only read-only commands (version, ls, history, cat, diff, stats, audit, \
+             similar, verify-signatures, export-dir, export-file, config get/log) can \
+             target another host"
+        );
+    }
+    let (host, remote_port) = match remote.parse::<std::net::SocketAddr>() {
+        Ok(addr) => (addr.ip().to_string(), Some(addr.port())),
+        Err(_) => match remote.rsplit_once(':') {
+            Some((host, p)) if !host.contains(':') => (
+                host.to_string(),
+                Some(
+                    p.parse()
+                        .with_context(|| format!("Invalid port in '{}'", remote))?,
+                ),
+            ),
+            _ => (remote.trim_matches(['[', ']']).to_string(), None),
+        },
+    };
+    let port = match port {
+        Some(PortArg::Fixed(p)) => p,
+        Some(PortArg::Auto) => anyhow::bail!("--port auto only applies to checkout"),
+        None => remote_port.unwrap_or(registry::DEFAULT_PORT),
+    };
+    client::set_remote_host(&host);
+    run_client_command(command, port)
+}
+
+/// Dispatch commands that only talk to an already-running server.
+fn run_client_command(command: Commands, port: u16) -> Result<()> {
+    match command {
+        Commands::Serve { .. }
+        | Commands::Checkout { .. }
+        | Commands::Up { .. }
+        | Commands::Autostart { .. } => {
+            anyhow::bail!("This command runs locally and cannot be sent to a server")
+        }
+        Commands::Version => client::client_version(port),
+        Commands::Ls { include_deleted } => client::client_ls(port, include_deleted),
+        Commands::History { file } => client::client_history(port, &file),
+        Commands::Cat { file, checksum } => client::client_cat(port, &file, checksum.as_deref()),
+        Commands::Diff { file, from, to } => {
+            client::client_diff(port, &file, from.as_deref(), to.as_deref())
+        }
+        Commands::Restore {
+            file,
+            checksum,
+            from_trash: _,
+        } => client::client_restore(port, &file, checksum.as_deref()),
+        Commands::Share {
+            file,
+            checksum,
+            expires,
+        } => client::client_share(port, &file, &checksum, &expires),
+        Commands::Rm { file } => client::client_rm(port, &file),
+        Commands::RestoreDir { dir, at } => client::client_restore_dir(port, &dir, at.as_deref()),
+        Commands::ExportDir { dir, at, output } => {
+            client::client_export_dir(port, &dir, at.as_deref(), &output)
+        }
+        Commands::Scan {
+            dry_run,
+            force,
+            verbose,
+            json,
+        } => client::client_scan(port, dry_run, force, verbose, json),
+        Commands::BulkBegin { timeout } => client::client_bulk_begin(port, &timeout),
+        Commands::BulkEnd => client::client_bulk_end(port),
+        Commands::Pause => client::client_pause(port),
+        Commands::Resume => client::client_resume(port),
+        #[cfg(feature = "tray")]
+        Commands::Tray => tray::run(port),
+        Commands::Clean => client::client_clean(port),
+        Commands::Unclean => client::client_unclean(port),
+        Commands::Gc { aggressive } => client::client_gc(port, aggressive),
+        Commands::Orphans => client::client_orphans(port),
+        Commands::MigrateStore => client::client_migrate_store(port),
+        Commands::Project { action } => match action {
+            ProjectAction::Add { directory, name } => {
+                let directory = std::path::absolute(&directory)?;
+                let directory = directory.canonicalize().unwrap_or(directory);
+                client::client_project_add(port, &directory.to_string_lossy(), name.as_deref())
+            }
+            ProjectAction::List => client::client_project_list(port),
+        },
+        Commands::CheckPath { path } => {
+            let path = path_util::resolve_cli_path(&path)?;
+            client::client_check_path(port, &path.to_string_lossy())
+        }
         Commands::Config { action } => match action {
-            ConfigAction::Get { key } => client::client_config_get(cli.port, key.as_deref()),
-            ConfigAction::Set { key, value } => client::client_config_set(cli.port, &key, &value),
+            ConfigAction::Get { key } => client::client_config_get(port, key.as_deref()),
+            ConfigAction::Set { key, value } => client::client_config_set(port, &key, &value),
+            ConfigAction::Log { key } => client::client_config_log(port, key.as_deref()),
         },
-        Commands::Stats => client::client_stats(cli.port),
-        Commands::Logs => client::client_logs(cli.port),
-        Commands::Stop => {
-            if !client::is_server_running(cli.port) {
-                println!("Server is not running on port {}.", cli.port);
+        Commands::Stats {
+            file: None,
+            quota_projection,
+            age,
+        } => client::client_stats(port, quota_projection, age),
+        Commands::Stats {
+            file: Some(file), ..
+        } => client::client_file_stats(port, &file),
+        Commands::Audit { since, output } => {
+            client::client_audit(port, since.as_deref(), output.as_deref())
+        }
+        Commands::Similar { threshold, since } => {
+            client::client_similar(port, threshold, since.as_deref())
+        }
+        Commands::Rpc => rpc::run(port),
+        Commands::VerifySignatures { public_key } => {
+            client::client_verify_signatures(port, public_key.as_deref())
+        }
+        Commands::Push { remote } => client::client_push(port, &remote),
+        Commands::Pull { remote } => client::client_pull(port, &remote),
+        Commands::ExportIndex { dir } => bundle::export(port, &dir),
+        Commands::ImportIndex { dir } => bundle::import(port, &dir),
+        Commands::ExportFile { file, output } => bundle::export_file(port, &file, &output),
+        Commands::ImportFile { archive } => bundle::import_file(port, &archive),
+        Commands::Open => client::client_open(port),
+        Commands::Selftest => client::client_selftest(port),
+        Commands::Logs => client::client_logs(port),
+        Commands::Stop {
+            all: false,
+            dir: None,
+        } => {
+            if !client::is_server_running(port) {
+                println!("Server is not running on port {}.", port);
                 return Ok(());
             }
-            client::client_shutdown(cli.port)?;
-            if client::wait_for_server_shutdown(cli.port, std::time::Duration::from_secs(5)) {
-                println!("Server stopped.");
-            } else {
+            if !stop_server(port)? {
                 anyhow::bail!("Server did not stop within 5 seconds");
             }
+            println!("Server stopped.");
             Ok(())
         }
+        Commands::Stop { dir, .. } => stop_servers(dir.as_deref()),
+    }
+}
+
+/// Ask the server on `port` to shut down; returns whether it stopped within 5 seconds.
+fn stop_server(port: u16) -> Result<bool> {
+    client::client_shutdown(port)?;
+    Ok(client::wait_for_server_shutdown(
+        port,
+        std::time::Duration::from_secs(5),
+    ))
+}
+
+/// Gracefully stop every running server, or only the one watching `dir`.
+fn stop_servers(dir: Option<&std::path::Path>) -> Result<()> {
+    let dir = dir
+        .map(|d| d.canonicalize().unwrap_or_else(|_| d.to_path_buf()))
+        .map(|d| d.to_string_lossy().into_owned());
+
+    let mut stopped = 0;
+    let mut failed = 0;
+    for info in registry::running_servers() {
+        client::set_server_host(info.host);
+        // The pid check guards against a reused pid answering on a reused port
+        let Ok(health) = client::client_health(info.port) else {
+            continue;
+        };
+        if health.pid != Some(info.pid) {
+            continue;
+        }
+        if dir.is_some() && health.watch_dir != dir {
+            continue;
+        }
+        let watching = health.watch_dir.as_deref().unwrap_or("no directory");
+        if stop_server(info.port).unwrap_or(false) {
+            println!("Stopped server on port {} ({})", info.port, watching);
+            stopped += 1;
+        } else {
+            eprintln!("Server on port {} ({}) did not stop", info.port, watching);
+            failed += 1;
+        }
+    }
+
+    match (stopped + failed, dir) {
+        (0, Some(dir)) => println!("No running server is watching {}.", dir),
+        (0, None) => println!("No running ftm servers found."),
+        _ => {}
+    }
+    if failed > 0 {
+        anyhow::bail!("{} server(s) did not stop within 5 seconds", failed);
+    }
+    Ok(())
+}
+
+/// Watch every directory of the workspace file as a project of one server, starting
+/// the server when none runs on `port`. Directories already watched are left as
+/// they are; listed settings are applied either way.
+fn up(path: &std::path::Path, port: u16) -> Result<()> {
+    let ws = workspace::Workspace::load(path)?;
+    if ws.directories.is_empty() {
+        println!("No directories listed in {}", path.display());
+        return Ok(());
+    }
+
+    let port = if port != 0 && client::is_server_running(port) {
+        port
+    } else {
+        auto_start_server(DEFAULT_BIND, port, None)?
+    };
+    let existing = client::projects(port)?;
+
+    let mut failed = 0;
+    for dir in &ws.directories {
+        let dir_s = dir.path.to_string_lossy();
+        let watched = existing
+            .iter()
+            .find(|p| p.watch_dir.as_deref() == Some(dir_s.as_ref()));
+        let name = match watched {
+            Some(p) => {
+                println!("{}: already watching {}", p.name, dir_s);
+                p.name.clone()
+            }
+            None => match client::project_add(port, &dir_s, dir.name.as_deref()) {
+                Ok(added) => {
+                    println!(
+                        "{}: watching {} ({})",
+                        added.name,
+                        dir_s,
+                        client::project_url(port, &added.name)
+                    );
+                    added.name
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", dir_s, e);
+                    failed += 1;
+                    continue;
+                }
+            },
+        };
+
+        client::set_server_project(Some(name.clone()));
+        let applied = dir.config_values().and_then(|values| {
+            values
+                .iter()
+                .try_for_each(|(key, value)| client::config_set(port, key, value).map(drop))
+        });
+        client::set_server_project(None);
+        if let Err(e) = applied {
+            eprintln!("{}: {}", name, e);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} workspace directories could not be set up",
+            failed,
+            ws.directories.len()
+        );
     }
+    Ok(())
 }
 
 /// Kill every ftm process except ourselves and an optional `keep_pid`.
@@ -193,13 +855,13 @@ fn kill_all_servers(keep_pid: Option<u32>) {
 /// Wait until the given port is free (nothing listening). On Windows, the OS
 /// may not release the port immediately after the process exits; this avoids
 /// "address already in use" when starting a new server on the same port.
-fn wait_for_port_free(port: u16) {
+fn wait_for_port_free(bind: IpAddr, port: u16) {
     use std::io::ErrorKind;
     use std::net::TcpListener;
     let start = std::time::Instant::now();
     let timeout = std::time::Duration::from_secs(2);
     while start.elapsed() < timeout {
-        match TcpListener::bind(("127.0.0.1", port)) {
+        match TcpListener::bind((bind, port)) {
             Ok(_listener) => return,
             Err(e) if e.kind() == ErrorKind::AddrInUse => {
                 std::thread::sleep(std::time::Duration::from_millis(100));
@@ -210,22 +872,36 @@ fn wait_for_port_free(port: u16) {
 }
 
 /// Start a detached FTM server process in the background and wait for it to
-/// become healthy before returning.
+/// become healthy before returning. Returns the port the server listens on.
 ///
 /// The server is started with `--log-dir {watch_dir}/.ftm/logs/` so that
-/// tracing output is persisted to disk and accessible via `ftm logs`.
-fn auto_start_server(port: u16, watch_dir: &std::path::Path) -> Result<()> {
+/// tracing output is persisted to disk and accessible via `ftm logs`, and with
+/// `--register {watch_dir}/.ftm` so that with port 0 the actually bound port can
+/// be read back from `.ftm/server.json`. Without a watch directory (a server for
+/// workspace projects) logs go to the user state directory and the bound port is
+/// read from the list of running servers.
+fn auto_start_server(bind: IpAddr, port: u16, watch_dir: Option<&std::path::Path>) -> Result<u16> {
     use std::process::{Command, Stdio};
 
     let exe = std::env::current_exe().context("Failed to determine current executable path")?;
 
-    let log_dir = watch_dir.join(".ftm").join("logs");
+    let ftm_dir = watch_dir.map(|dir| dir.join(".ftm"));
+    let log_dir = match ftm_dir {
+        Some(ref ftm_dir) => ftm_dir.join("logs"),
+        None => user_state::state_dir()
+            .context("No user state directory for server logs")?
+            .join("logs"),
+    };
     let mut cmd = Command::new(&exe);
     cmd.arg("--port")
         .arg(port.to_string())
         .arg("serve")
         .arg("--log-dir")
         .arg(&log_dir);
+    if let Some(ref ftm_dir) = ftm_dir {
+        cmd.arg("--register").arg(ftm_dir);
+    }
+    cmd.arg("--bind").arg(bind.to_string());
 
     cmd.stdin(Stdio::null())
         .stdout(Stdio::null())
@@ -242,16 +918,33 @@ fn auto_start_server(port: u16, watch_dir: &std::path::Path) -> Result<()> {
     let child = cmd.spawn().context("Failed to start FTM server")?;
     let pid = child.id();
 
-    eprintln!("Starting FTM server on port {} (pid: {})...", port, pid);
+    if port == 0 {
+        eprintln!("Starting FTM server on a free port (pid: {})...", pid);
+    } else {
+        eprintln!("Starting FTM server on port {} (pid: {})...", port, pid);
+    }
 
     // Poll until the server is healthy or timeout.
     let start = std::time::Instant::now();
     let timeout = std::time::Duration::from_secs(10);
 
     loop {
-        if client::is_server_running(port) {
+        // With port 0, wait for the child to register the port it actually bound.
+        let bound_port = if port == 0 {
+            match ftm_dir {
+                Some(ref ftm_dir) => registry::read_server_info(ftm_dir),
+                None => registry::running_servers()
+                    .into_iter()
+                    .find(|info| info.pid == pid),
+            }
+            .filter(|info| info.pid == pid)
+            .map(|info| info.port)
+        } else {
+            Some(port)
+        };
+        if let Some(p) = bound_port.filter(|p| client::is_server_running(*p)) {
             eprintln!("Server is ready.");
-            return Ok(());
+            return Ok(p);
         }
         if start.elapsed() > timeout {
             anyhow::bail!("Timed out waiting for FTM server to start on port {}", port);
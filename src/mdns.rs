@@ -0,0 +1,72 @@
+//! mDNS/zeroconf advertisement of the Web UI (`_ftm._tcp.local.`).
+//! Only used when the server is bound to a non-loopback address, so other
+//! devices on the LAN can discover the history browser.
+
+use anyhow::{Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::net::IpAddr;
+use std::path::Path;
+use tracing::{info, warn};
+
+const SERVICE_TYPE: &str = "_ftm._tcp.local.";
+
+/// A registered advertisement; unregistered when dropped.
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Advertisement {
+    /// Advertise the server listening on `bind:port` for `watch_dir`.
+    /// An unspecified `bind` (0.0.0.0 / ::) advertises every interface address.
+    pub fn start(bind: IpAddr, port: u16, watch_dir: &Path) -> Result<Self> {
+        let host = sysinfo::System::host_name().unwrap_or_else(|| "ftm".into());
+        let dir_name = watch_dir
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| watch_dir.to_string_lossy().into_owned());
+        let instance = format!("ftm {} on {}", dir_name, host);
+        let properties = [
+            ("path", watch_dir.to_string_lossy().into_owned()),
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+        ];
+
+        let service = if bind.is_unspecified() {
+            ServiceInfo::new(
+                SERVICE_TYPE,
+                &instance,
+                &format!("{}.local.", host),
+                (),
+                port,
+                &properties[..],
+            )?
+            .enable_addr_auto()
+        } else {
+            ServiceInfo::new(
+                SERVICE_TYPE,
+                &instance,
+                &format!("{}.local.", host),
+                bind,
+                port,
+                &properties[..],
+            )?
+        };
+        let fullname = service.get_fullname().to_string();
+
+        let daemon = ServiceDaemon::new().context("Failed to start mDNS daemon")?;
+        daemon
+            .register(service)
+            .context("Failed to register mDNS service")?;
+        info!("Advertising Web UI via mDNS as '{}'", fullname);
+        Ok(Self { daemon, fullname })
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            warn!("Failed to unregister mDNS service: {}", e);
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
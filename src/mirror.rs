@@ -0,0 +1,65 @@
+//! Read-only mirror mode (`ftm serve --mirror <url>`).
+//! The mirror periodically pulls history entries and snapshot blobs from a source
+//! server into its own `.ftm` and serves them without a file watcher. Entries are
+//! only ever added, so versions survive even after the source trims them.
+
+use crate::client::{self, SyncEntry, SyncTarget};
+use crate::storage::Storage;
+use crate::types::HistoryEntry;
+use anyhow::Result;
+use std::path::PathBuf;
+use tracing::warn;
+
+/// Where a mirror pulls from and where it keeps its copy.
+#[derive(Debug, Clone)]
+pub struct MirrorOptions {
+    /// Base URL of the source server.
+    pub source: String,
+    /// Directory holding the mirror's `.ftm`.
+    pub dir: PathBuf,
+}
+
+/// The mirror's own store as the receiving end of a sync.
+struct MirrorStore<'a> {
+    storage: &'a Storage,
+    /// `settings.max_file_size`: larger snapshots are not taken.
+    max_size: u64,
+}
+
+impl SyncTarget for MirrorStore<'_> {
+    fn history(&self) -> Result<Vec<SyncEntry>> {
+        let index = self.storage.load_index()?;
+        Ok(serde_json::from_value(serde_json::to_value(
+            &index.history,
+        )?)?)
+    }
+
+    fn store_snapshot(
+        &self,
+        checksum: &str,
+        mut content: Box<dyn std::io::Read + Send>,
+    ) -> Result<()> {
+        self.storage
+            .import_snapshot(checksum, &mut content, self.max_size)
+    }
+
+    fn merge_entries(&self, entries: &[&SyncEntry]) -> Result<usize> {
+        let entries: Vec<HistoryEntry> = serde_json::from_value(serde_json::to_value(entries)?)?;
+        self.storage.merge_entries(entries)
+    }
+}
+
+/// Pull entries missing locally from `source`, fetching the snapshots they need.
+/// Entries whose snapshot cannot be fetched (or is over `max_size` bytes) are skipped
+/// until the next pull. Returns (entries added, snapshots fetched).
+pub fn pull_into(storage: &Storage, source: &str, max_size: u64) -> Result<(usize, usize)> {
+    let outcome = client::sync_from(source, &MirrorStore { storage, max_size })?;
+    for (checksum, e) in &outcome.failed {
+        warn!(
+            "Mirror: skipping snapshot {}: {:#}",
+            &checksum[..8.min(checksum.len())],
+            e
+        );
+    }
+    Ok((outcome.added, outcome.snapshots))
+}
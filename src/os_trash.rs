@@ -0,0 +1,96 @@
+//! The desktop trash (Trash / Recycle Bin), to tell a file the user moved there from one
+//! that was deleted. Linux follows the freedesktop.org trash spec (home trash only);
+//! macOS `~/.Trash` records no original paths, so a file name match there counts.
+//! Elsewhere nothing is ever found in the trash.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Original paths of the files in the trash, read once.
+pub struct OsTrash {
+    paths: HashSet<PathBuf>,
+    names: HashSet<String>,
+}
+
+impl OsTrash {
+    pub fn load() -> Self {
+        let mut trash = Self {
+            paths: HashSet::new(),
+            names: HashSet::new(),
+        };
+        if cfg!(target_os = "macos") {
+            if let Some(home) = std::env::var_os("HOME") {
+                trash.load_names(&Path::new(&home).join(".Trash"));
+            }
+        } else if cfg!(unix) {
+            let data_home = std::env::var_os("XDG_DATA_HOME")
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".local/share")));
+            if let Some(data_home) = data_home {
+                trash.load_trashinfo(&data_home.join("Trash").join("info"));
+            }
+        }
+        trash
+    }
+
+    /// Whether the file that was at absolute path `path` is in the trash now.
+    pub fn holds(&self, path: &Path) -> bool {
+        self.paths.contains(path)
+            || path
+                .file_name()
+                .is_some_and(|n| self.names.contains(&*n.to_string_lossy()))
+    }
+
+    fn load_names(&mut self, dir: &Path) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            self.names
+                .insert(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    /// `info/<name>.trashinfo` files hold the original path as `Path=<percent-encoded>`.
+    fn load_trashinfo(&mut self, info_dir: &Path) {
+        let Ok(entries) = std::fs::read_dir(info_dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|e| e != "trashinfo") {
+                continue;
+            }
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            if let Some(original) = content.lines().find_map(|l| l.strip_prefix("Path=")) {
+                self.paths
+                    .insert(PathBuf::from(percent_decode(original.trim())));
+            }
+        }
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
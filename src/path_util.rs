@@ -7,3 +7,33 @@
 pub fn normalize_rel_path(s: &str) -> String {
     s.replace('\\', "/")
 }
+
+/// Absolute form of a path given on the command line, which need not exist: `.` and
+/// `..` are resolved lexically, then the longest existing ancestor is canonicalized so
+/// the result compares equal to canonical watch directories.
+pub fn resolve_cli_path(path: &std::path::Path) -> std::io::Result<std::path::PathBuf> {
+    use std::path::{Component, PathBuf};
+
+    let mut resolved = PathBuf::new();
+    for component in std::path::absolute(path)?.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            other => resolved.push(other),
+        }
+    }
+    let mut existing = resolved.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            return Ok(resolved);
+        };
+        rest.push(name);
+        existing = parent;
+    }
+    let mut out = existing.canonicalize()?;
+    out.extend(rest.into_iter().rev());
+    Ok(out)
+}
@@ -0,0 +1,144 @@
+//! Per-directory server registry.
+//! The server watching a directory records its port in `.ftm/server.json`, so client
+//! commands run inside that directory can find it without `--port`. Every server also
+//! lists itself in the user state directory, so `ftm stop --all` can find them all.
+
+use crate::user_state;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+/// Port used when neither `--port` nor the registry provides one.
+pub const DEFAULT_PORT: u16 = 13580;
+
+const SERVER_FILE: &str = "server.json";
+const LOCK_FILE: &str = "lock";
+/// Subdirectory of the user state directory holding one `<pid>.json` per running server.
+const RUNNING_DIR: &str = "servers";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerInfo {
+    /// Address clients should connect to (see `connect_host`).
+    #[serde(default = "default_host")]
+    pub host: IpAddr,
+    pub port: u16,
+    pub pid: u32,
+    /// Project name when the directory is one of several served by one daemon; client
+    /// requests then go to `/projects/<name>/...`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+}
+
+fn default_host() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+/// Address a local client uses to reach a server bound to `bind`: loopback of the
+/// same family for unspecified addresses (0.0.0.0 / ::), otherwise `bind` itself.
+pub fn connect_host(bind: IpAddr) -> IpAddr {
+    match bind {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    }
+}
+
+/// Record the server watching `ftm_dir`.
+pub fn write_server_info(ftm_dir: &Path, info: &ServerInfo) -> Result<()> {
+    let content = serde_json::to_string_pretty(info)?;
+    std::fs::write(ftm_dir.join(SERVER_FILE), content)?;
+    Ok(())
+}
+
+/// Read the registered server of `ftm_dir`, if any.
+pub fn read_server_info(ftm_dir: &Path) -> Option<ServerInfo> {
+    let content = std::fs::read_to_string(ftm_dir.join(SERVER_FILE)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Remove the registry file, but only if it still belongs to `pid`
+/// (a newer server may have taken over the directory).
+pub fn remove_server_info(ftm_dir: &Path, pid: u32) {
+    if read_server_info(ftm_dir).is_some_and(|info| info.pid == pid) {
+        let _ = std::fs::remove_file(ftm_dir.join(SERVER_FILE));
+    }
+}
+
+/// Take the exclusive `.ftm/lock` of the server watching this directory. The returned
+/// file holds the lock until dropped; the OS releases it when the process exits, even on
+/// a crash. Returns `None` when another server holds it.
+pub fn lock_ftm_dir(ftm_dir: &Path) -> std::io::Result<Option<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(ftm_dir.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Walk up from `start` to the nearest directory containing `.ftm/`; returns that `.ftm` path.
+pub fn find_ftm_dir(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(".ftm"))
+        .find(|ftm_dir| ftm_dir.is_dir())
+}
+
+/// Server registered for the nearest `.ftm` above `start`.
+pub fn discover_server(start: &Path) -> Option<ServerInfo> {
+    find_ftm_dir(start).and_then(|ftm_dir| read_server_info(&ftm_dir))
+}
+
+fn running_dir() -> Result<PathBuf> {
+    user_state::state_dir()
+        .map(|dir| dir.join(RUNNING_DIR))
+        .context("No user state directory available")
+}
+
+/// List this server among the user's running servers.
+pub fn add_running_server(info: &ServerInfo) -> Result<()> {
+    let dir = running_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    let content = serde_json::to_string_pretty(info)?;
+    std::fs::write(dir.join(format!("{}.json", info.pid)), content)?;
+    Ok(())
+}
+
+pub fn remove_running_server(pid: u32) {
+    if let Ok(dir) = running_dir() {
+        let _ = std::fs::remove_file(dir.join(format!("{}.json", pid)));
+    }
+}
+
+/// The user's running servers. Entries left behind by processes that no longer exist
+/// (killed or crashed servers) are removed.
+pub fn running_servers() -> Vec<ServerInfo> {
+    use sysinfo::{Pid, ProcessesToUpdate, System};
+
+    let Ok(entries) = running_dir().and_then(|dir| Ok(std::fs::read_dir(dir)?)) else {
+        return Vec::new();
+    };
+    let listed: Vec<ServerInfo> = entries
+        .flatten()
+        .filter_map(|entry| std::fs::read_to_string(entry.path()).ok())
+        .filter_map(|content| serde_json::from_str(&content).ok())
+        .collect();
+
+    let pids: Vec<Pid> = listed.iter().map(|info| Pid::from_u32(info.pid)).collect();
+    let mut sys = System::new();
+    sys.refresh_processes(ProcessesToUpdate::Some(&pids), true);
+    let (mut alive, dead): (Vec<_>, Vec<_>) = listed
+        .into_iter()
+        .partition(|info| sys.process(Pid::from_u32(info.pid)).is_some());
+    for info in dead {
+        remove_running_server(info.pid);
+    }
+    alive.sort_by_key(|info| info.port);
+    alive
+}
@@ -0,0 +1,180 @@
+//! JSON-RPC 2.0 over stdio (`ftm rpc`) for editor plugins.
+//! Each line on stdin is one request; each response is written as one line on stdout.
+//! Requests are forwarded to the running server, so plugins never deal with ports.
+//!
+//! Methods:
+//! - `history {file}`: history entries of a file, oldest first
+//! - `snapshot {checksum}`: `{content}` of a text snapshot
+//! - `diff {from?, to}`: the same hunks as `GET /api/diff`
+//! - `restore {file, checksum}`: `{message}` after restoring that version
+
+use crate::client::{base_url, check_response, handle_connection_error, make_client};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Errors reported by ftm itself (unknown file, server not running, ...).
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: Option<String>,
+    /// Absent for notifications, which get no response.
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::new(SERVER_ERROR, e.to_string())
+    }
+}
+
+/// Serve requests from stdin until it closes.
+pub fn run(port: u16) -> Result<()> {
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout().lock();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_line(port, &line) {
+            writeln!(stdout, "{}", response)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn handle_line(port: u16, line: &str) -> Option<Value> {
+    let value: Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, e.to_string())),
+    };
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    let request: Request = match serde_json::from_value(value) {
+        Ok(r) => r,
+        Err(e) => return Some(error_response(id, INVALID_REQUEST, e.to_string())),
+    };
+    if request.jsonrpc.as_deref() != Some("2.0") {
+        return Some(error_response(
+            id,
+            INVALID_REQUEST,
+            "jsonrpc must be \"2.0\"",
+        ));
+    }
+
+    let result = dispatch(port, &request.method, request.params);
+    let id = request.id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => error_response(id, e.code, e.message),
+    })
+}
+
+fn error_response(id: Value, code: i64, message: impl Into<String>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message.into() },
+    })
+}
+
+fn params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+#[derive(Deserialize)]
+struct FileParams {
+    file: String,
+}
+
+#[derive(Deserialize)]
+struct SnapshotParams {
+    checksum: String,
+}
+
+#[derive(Deserialize)]
+struct DiffParams {
+    #[serde(default)]
+    from: Option<String>,
+    to: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct RestoreParams {
+    file: String,
+    checksum: String,
+}
+
+fn dispatch(port: u16, method: &str, raw: Value) -> Result<Value, RpcError> {
+    let url = |path: &str| format!("{}{}", base_url(port), path);
+    let get = |path: &str, query: &[(&str, &str)]| -> Result<reqwest::blocking::Response> {
+        let resp = make_client()
+            .get(url(path))
+            .query(query)
+            .send()
+            .map_err(handle_connection_error)?;
+        check_response(resp)
+    };
+
+    match method {
+        "history" => {
+            let p: FileParams = params(raw)?;
+            Ok(get("/api/history", &[("file", &p.file)])?
+                .json()
+                .map_err(anyhow::Error::from)?)
+        }
+        "snapshot" => {
+            let p: SnapshotParams = params(raw)?;
+            let bytes = get("/api/snapshot", &[("checksum", &p.checksum)])?
+                .bytes()
+                .map_err(anyhow::Error::from)?;
+            let content = String::from_utf8(bytes.to_vec())
+                .map_err(|_| RpcError::new(SERVER_ERROR, "Snapshot is not UTF-8 text"))?;
+            Ok(json!({ "content": content }))
+        }
+        "diff" => {
+            let p: DiffParams = params(raw)?;
+            let from = p.from.unwrap_or_default();
+            Ok(get("/api/diff", &[("from", &from), ("to", &p.to)])?
+                .json()
+                .map_err(anyhow::Error::from)?)
+        }
+        "restore" => {
+            let p: RestoreParams = params(raw)?;
+            let resp = make_client()
+                .post(url("/api/restore"))
+                .json(&p)
+                .send()
+                .map_err(handle_connection_error)?;
+            Ok(check_response(resp)?.json().map_err(anyhow::Error::from)?)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method '{}'", method),
+        )),
+    }
+}
@@ -1,24 +1,106 @@
 use crate::config::Config;
+use crate::os_trash::OsTrash;
 use crate::path_util;
-use crate::storage::{IndexView, Storage};
-use crate::types::{Index, Operation};
+use crate::storage::{CachedIndex, IndexView, Storage};
+use crate::types::{Index, LastScan, Operation};
 use anyhow::Result;
-use std::collections::HashSet;
+use serde::Serialize;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use tracing::info;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
 
-#[derive(serde::Serialize)]
+#[derive(Serialize)]
 pub struct ScanResult {
     pub created: usize,
     pub modified: usize,
     pub deleted: usize,
     pub unchanged: usize,
+    /// Snapshotting was paused because free disk space is below `settings.min_free_space`.
+    pub snapshots_paused: bool,
+    /// Changed files left for a later scan because the snapshot budget ran out.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub deferred: usize,
+    /// Every entry the scan recorded (or would record); only filled in by a dry run
+    /// and by scans asked for details.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<ScanChange>,
 }
 
+#[derive(Serialize)]
+pub struct ScanChange {
+    pub file: String,
+    pub op: Operation,
+}
+
+/// More files match than `settings.max_tracked_files` allows; see `Scanner::with_file_limit`.
+#[derive(Debug)]
+pub struct TooManyFiles {
+    pub limit: usize,
+    pub dir: PathBuf,
+}
+
+impl std::fmt::Display for TooManyFiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "More than {} files in {} match the watch patterns (settings.max_tracked_files). \
+             Narrow watch.patterns / watch.exclude / watch.max_depth, raise the limit, or use --force",
+            self.limit,
+            self.dir.display()
+        )
+    }
+}
+
+impl std::error::Error for TooManyFiles {}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// Directories listed by earlier scans, keyed by path with the mtime they had then.
+/// A directory's mtime changes whenever an entry is added, removed or renamed in it,
+/// so while it stays the same the cached listing can be reused instead of reading the
+/// directory again. Tracked files in it are still checked one by one for changes.
+/// Cloning shares the cache.
+#[derive(Clone, Default)]
+pub struct DirCache(Arc<Mutex<DirCacheInner>>);
+
+#[derive(Default)]
+struct DirCacheInner {
+    /// `watch` settings the listings were filtered with.
+    watch: String,
+    dirs: HashMap<PathBuf, (SystemTime, DirListing)>,
+}
+
+/// What a scan descends into and examines in one directory.
+#[derive(Clone, Default)]
+struct DirListing {
+    subdirs: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+/// Directories changed this recently are not cached: on filesystems with coarse
+/// timestamps another change could still land within the same mtime.
+const DIR_CACHE_SETTLE: Duration = Duration::from_secs(2);
+
 pub struct Scanner {
     root_dir: PathBuf,
     config: Config,
     storage: Storage,
+    /// Max files examined per second (0 = unlimited) and pacing state.
+    files_per_sec: u64,
+    paced: Cell<(Option<Instant>, u64)>,
+    /// Snapshots this scan may still record (None = unlimited).
+    budget: Cell<Option<usize>>,
+    dry_run: bool,
+    /// Fill in `ScanResult::changes` for a real scan too.
+    details: bool,
+    dir_cache: Option<DirCache>,
+    /// Most files the walk may find before the scan fails (None = unlimited).
+    file_limit: Option<usize>,
 }
 
 impl Scanner {
@@ -27,141 +109,358 @@ impl Scanner {
             root_dir,
             config,
             storage,
+            files_per_sec: 0,
+            paced: Cell::new((None, 0)),
+            budget: Cell::new(None),
+            dry_run: false,
+            details: false,
+            dir_cache: None,
+            file_limit: None,
+        }
+    }
+
+    /// Walk and hash as usual but record nothing; the entries a real scan would add are
+    /// listed in `ScanResult::changes`.
+    pub fn dry_run(mut self) -> Self {
+        self.storage = self.storage.dry_run();
+        self.dry_run = true;
+        self
+    }
+
+    /// List every recorded entry in `ScanResult::changes`, as a dry run does.
+    pub fn with_details(mut self) -> Self {
+        self.details = true;
+        self
+    }
+
+    /// Record at most `max` snapshots; files past the budget count as present but are
+    /// not examined, and are reported in `ScanResult::deferred`.
+    pub fn with_snapshot_budget(self, max: usize) -> Self {
+        self.budget.set(Some(max));
+        self
+    }
+
+    /// Reuse directory listings from `cache` where the directory's mtime is unchanged,
+    /// and remember this scan's listings there for the next one.
+    pub fn with_dir_cache(mut self, cache: DirCache) -> Self {
+        let watch = serde_json::to_string(&self.config.watch).unwrap_or_default();
+        {
+            let mut inner = cache.0.lock().unwrap();
+            if inner.watch != watch {
+                inner.dirs.clear();
+                inner.watch = watch;
+            }
+        }
+        self.dir_cache = Some(cache);
+        self
+    }
+
+    /// Fail with `TooManyFiles`, before recording anything, when the walk finds more
+    /// than `max` files (0 = unlimited).
+    pub fn with_file_limit(mut self, max: usize) -> Self {
+        self.file_limit = (max > 0).then_some(max);
+        self
+    }
+
+    /// Limit the scan to `files_per_sec` examined files (0 = unlimited).
+    pub fn throttled(mut self, files_per_sec: u64) -> Self {
+        self.files_per_sec = files_per_sec;
+        self
+    }
+
+    /// Sleep as needed so files are examined no faster than `files_per_sec`.
+    fn pace(&self) {
+        if self.files_per_sec == 0 {
+            return;
+        }
+        let (start, count) = self.paced.get();
+        let start = start.unwrap_or_else(Instant::now);
+        let due = Duration::from_secs_f64(count as f64 / self.files_per_sec as f64);
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+        self.paced.set((Some(start), count + 1));
+    }
+
+    fn note_change(&self, result: &mut ScanResult, file: String, op: Operation) {
+        if self.dry_run || self.details {
+            result.changes.push(ScanChange { file, op });
+        }
+    }
+
+    fn spend_budget(&self) {
+        if let Some(n) = self.budget.get() {
+            self.budget.set(Some(n.saturating_sub(1)));
         }
     }
 
     /// Perform a full scan of the directory, detecting creates, modifies, and deletes.
+    /// Unless dry, the scan is remembered as the last full scan in `.ftm/state.json`.
+    /// Holds `Storage::with_index_locked` from loading the index to saving it.
     pub fn scan(&self) -> Result<ScanResult> {
+        let result = self.storage.with_index_locked(|| -> Result<ScanResult> {
+            let mut index = self.storage.load_index()?;
+            let mut view = self.storage.build_index_view(&index);
+            let (result, index_changed) = self.scan_index(&mut index, &mut view)?;
+            if index_changed && !self.dry_run {
+                self.storage.save_index(&mut index)?;
+            }
+            Ok(result)
+        })?;
+        if self.dry_run {
+            return Ok(result);
+        }
+        let last = LastScan {
+            at: chrono::Utc::now(),
+            created: result.created,
+            modified: result.modified,
+            deleted: result.deleted,
+            unchanged: result.unchanged,
+        };
+        if let Err(e) = self.storage.record_scan(last) {
+            warn!("Failed to record the last scan: {}", e);
+        }
+        Ok(result)
+    }
+
+    /// Like `scan`, on an index kept by the caller between scans; it is only reloaded
+    /// when the saved index changed since. Holds `Storage::with_index_locked` throughout.
+    pub fn scan_cached(&self, cache: &mut CachedIndex) -> Result<ScanResult> {
+        if self.dry_run {
+            return self.scan();
+        }
+        self.storage.with_index_locked(|| {
+            self.storage.refresh_cached_index(cache)?;
+            match self.scan_index(&mut cache.index, &mut cache.view) {
+                Ok((result, true)) => {
+                    self.storage.save_cached_index(cache)?;
+                    Ok(result)
+                }
+                Ok((result, false)) => Ok(result),
+                Err(e) => {
+                    cache.invalidate();
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    /// Scan against `index`, returning the result and whether the index changed.
+    fn scan_index(&self, index: &mut Index, view: &mut IndexView) -> Result<(ScanResult, bool)> {
         let mut result = ScanResult {
             created: 0,
             modified: 0,
             deleted: 0,
             unchanged: 0,
+            snapshots_paused: false,
+            deferred: 0,
+            changes: Vec::new(),
         };
 
-        let mut index = self.storage.load_index()?;
-        let mut view = self.storage.build_index_view(&index);
+        // Writing more snapshots on a nearly full disk makes things worse; keep
+        // recording deletes but leave changed files for a later scan.
+        let min_free_space = self.config.settings.min_free_space;
+        if let Some(free) = self
+            .storage
+            .low_space(min_free_space)
+            .filter(|_| !self.dry_run)
+        {
+            warn!(
+                "Free disk space {} bytes is below settings.min_free_space ({} bytes); snapshotting paused",
+                free, min_free_space
+            );
+            result.snapshots_paused = true;
+        }
+
         let mut index_changed = false;
 
-        // Phase 1: Walk directory and snapshot all matching files
+        // Phase 1: Walk directory (or watch.roots) and snapshot all matching files
+        let files = self.tracked_files()?;
         let mut scanned_files = HashSet::new();
-        self.walk_and_snapshot(
-            &self.root_dir,
-            &mut scanned_files,
-            &mut result,
-            &mut index,
-            &mut view,
-            &mut index_changed,
-        )?;
+        for path in files {
+            self.snapshot_file(
+                &path,
+                &mut scanned_files,
+                &mut result,
+                index,
+                view,
+                &mut index_changed,
+            )?;
+        }
 
         // Phase 2: Detect deleted files (in index but not on disk)
-        self.detect_deletes(
-            &scanned_files,
-            &mut result,
-            &mut index,
-            &mut view,
-            &mut index_changed,
-        )?;
+        self.detect_deletes(&scanned_files, &mut result, index, view, &mut index_changed)?;
 
-        if index_changed {
-            self.storage.save_index(&index)?;
-        }
+        Ok((result, index_changed))
+    }
 
-        Ok(result)
+    /// Subdirectories to descend into and candidate files of `dir`, from the directory
+    /// cache when `dir` is unchanged since it was listed.
+    fn list_dir(&self, dir: &Path) -> Result<Option<DirListing>> {
+        let Some(cache) = &self.dir_cache else {
+            return self.read_dir_listing(dir);
+        };
+        let mtime = std::fs::metadata(dir).and_then(|m| m.modified()).ok();
+        if let Some(mtime) = mtime {
+            let inner = cache.0.lock().unwrap();
+            if let Some((cached, listing)) = inner.dirs.get(dir) {
+                if *cached == mtime {
+                    return Ok(Some(listing.clone()));
+                }
+            }
+        }
+        let listing = self.read_dir_listing(dir)?;
+        let settled = mtime.filter(|m| {
+            SystemTime::now()
+                .duration_since(*m)
+                .is_ok_and(|age| age >= DIR_CACHE_SETTLE)
+        });
+        let mut inner = cache.0.lock().unwrap();
+        match (settled, &listing) {
+            (Some(mtime), Some(listing)) => {
+                inner
+                    .dirs
+                    .insert(dir.to_path_buf(), (mtime, listing.clone()));
+            }
+            _ => {
+                inner.dirs.remove(dir);
+            }
+        }
+        Ok(listing)
     }
 
-    fn walk_and_snapshot(
-        &self,
-        dir: &Path,
-        scanned_files: &mut HashSet<String>,
-        result: &mut ScanResult,
-        index: &mut Index,
-        view: &mut IndexView,
-        index_changed: &mut bool,
-    ) -> Result<()> {
+    /// Read `dir`, keeping the subdirectories within `watch.max_depth` that are not
+    /// excluded and the files matching the watch patterns. None if it cannot be read.
+    fn read_dir_listing(&self, dir: &Path) -> Result<Option<DirListing>> {
         let entries = match std::fs::read_dir(dir) {
             Ok(entries) => entries,
-            Err(_) => return Ok(()),
+            Err(_) => return Ok(None),
         };
 
+        // Levels below the root of the directories inside `dir`
+        let depth = dir
+            .strip_prefix(&self.root_dir)
+            .map_or(0, |rel| rel.components().count())
+            + 1;
+
+        let mut listing = DirListing::default();
         for entry in entries {
             let entry = entry?;
             let path = entry.path();
 
             if path.is_dir() {
-                // Skip excluded directories
-                if !self.is_excluded_dir(&path) {
-                    self.walk_and_snapshot(
-                        &path,
-                        scanned_files,
-                        result,
-                        index,
-                        view,
-                        index_changed,
-                    )?;
+                // Skip excluded directories and those below watch.max_depth
+                if self.config.within_max_depth(depth)
+                    && !self.config.is_excluded_dir(&path, &self.root_dir)
+                {
+                    listing.subdirs.push(path);
                 }
             } else if path.is_file() && self.config.matches_path(&path, &self.root_dir) {
-                // Skip files exceeding max_file_size
-                let meta = match std::fs::metadata(&path) {
-                    Ok(m) if m.len() > self.config.settings.max_file_size => continue,
-                    Ok(m) => m,
-                    Err(_) => continue,
-                };
-
-                let rel_path = path.strip_prefix(&self.root_dir).unwrap_or(&path);
-                let file_key = path_util::normalize_rel_path(&rel_path.to_string_lossy());
-                scanned_files.insert(file_key.clone());
-
-                // Fast path: skip hashing if mtime and size unchanged
-                let mtime_nanos = meta
-                    .modified()
-                    .ok()
-                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                    .map(|d| d.as_nanos() as i64);
-                if let Some(last) = view.last_entry_for_file(index, &file_key) {
-                    if last.op != Operation::Delete
-                        && last.size == Some(meta.len())
-                        && last.mtime_nanos == mtime_nanos
-                    {
-                        result.unchanged += 1;
-                        continue;
-                    }
-                }
+                listing.files.push(path);
+            }
+        }
+        Ok(Some(listing))
+    }
 
-                match self
-                    .storage
-                    .save_snapshot_with_index(&path, &self.root_dir, index, view)?
-                {
-                    Some(entry) => match entry.op {
-                        Operation::Create => {
-                            info!("Scan: new file {}", entry.file);
-                            result.created += 1;
-                            *index_changed = true;
-                        }
-                        Operation::Modify => {
-                            info!("Scan: modified file {}", entry.file);
-                            result.modified += 1;
-                            *index_changed = true;
-                        }
-                        _ => {}
-                    },
-                    None => {
-                        result.unchanged += 1;
-                    }
+    /// Files under the root (or watch.roots) a scan tracks: those matching the watch
+    /// patterns within `watch.max_depth`, outside excluded directories and no larger
+    /// than `settings.max_file_size`. Fails with `TooManyFiles` as soon as the walk
+    /// finds more than the file limit.
+    pub fn tracked_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for dir in self.config.root_dirs(&self.root_dir) {
+            self.walk(&dir, &mut files)?;
+        }
+        Ok(files)
+    }
+
+    fn walk(&self, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        let Some(listing) = self.list_dir(dir)? else {
+            return Ok(());
+        };
+
+        for path in listing.subdirs {
+            self.walk(&path, files)?;
+        }
+
+        for path in listing.files {
+            // Skip files exceeding max_file_size
+            match std::fs::metadata(&path) {
+                Ok(m) if m.len() > self.config.settings.max_file_size => continue,
+                Ok(_) => {}
+                Err(_) => continue,
+            }
+            files.push(path);
+            if let Some(limit) = self.file_limit.filter(|limit| files.len() > *limit) {
+                return Err(TooManyFiles {
+                    limit,
+                    dir: self.root_dir.clone(),
                 }
+                .into());
             }
         }
 
         Ok(())
     }
 
-    /// Check if a directory path matches any exclude pattern.
-    /// Used to skip entire directory trees early.
-    fn is_excluded_dir(&self, path: &Path) -> bool {
+    fn snapshot_file(
+        &self,
+        path: &Path,
+        scanned_files: &mut HashSet<String>,
+        result: &mut ScanResult,
+        index: &mut Index,
+        view: &mut IndexView,
+        index_changed: &mut bool,
+    ) -> Result<()> {
+        self.pace();
+
         let rel_path = path.strip_prefix(&self.root_dir).unwrap_or(path);
-        let path_str = path_util::normalize_rel_path(&rel_path.to_string_lossy());
-        let dir_str = format!("{}/", path_str);
-        self.config.excluded_by_patterns(&path_str, Some(&dir_str))
+        let file_key = path_util::normalize_rel_path(&rel_path.to_string_lossy());
+        scanned_files.insert(file_key);
+
+        if result.snapshots_paused {
+            return Ok(());
+        }
+        if self.budget.get() == Some(0) {
+            result.deferred += 1;
+            return Ok(());
+        }
+
+        // Unchanged files (same mtime and size) are skipped without hashing
+        match self
+            .storage
+            .save_snapshot_with_index(path, &self.root_dir, index, view)?
+        {
+            Some(entry) => {
+                match entry.op {
+                    Operation::Create => {
+                        info!("Scan: new file {}", entry.file);
+                        self.spend_budget();
+                        result.created += 1;
+                        *index_changed = true;
+                    }
+                    Operation::Modify => {
+                        info!("Scan: modified file {}", entry.file);
+                        self.spend_budget();
+                        result.modified += 1;
+                        *index_changed = true;
+                    }
+                    Operation::Truncate => {
+                        info!("Scan: truncated file {}", entry.file);
+                        self.spend_budget();
+                        result.modified += 1;
+                        *index_changed = true;
+                    }
+                    _ => {}
+                }
+                self.note_change(result, entry.file, entry.op);
+            }
+            None => {
+                result.unchanged += 1;
+            }
+        }
+
+        Ok(())
     }
 
     fn detect_deletes(
@@ -183,19 +482,46 @@ impl Scanner {
             }
         }
 
+        // Read only when something was deleted
+        let os_trash = (!to_delete.is_empty()).then(OsTrash::load);
         for file_key in to_delete {
             let abs_path = self.root_dir.join(&file_key);
+            let trashed = os_trash.as_ref().is_some_and(|t| t.holds(&abs_path));
             if self
                 .storage
-                .record_delete_with_index(&abs_path, &self.root_dir, index, view)?
+                .record_delete_with_index(&abs_path, &self.root_dir, trashed, index, view)?
                 .is_some()
             {
                 info!("Scan: deleted file {}", file_key);
                 result.deleted += 1;
                 *index_changed = true;
+                self.note_change(result, file_key, Operation::Delete);
             }
         }
 
         Ok(())
     }
 }
+
+/// Lower the calling thread's CPU and I/O priority, for background scans that should
+/// not compete with interactive work. Best-effort; a no-op where unsupported.
+pub fn lower_current_thread_priority() {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        // On Linux both are per-thread when `who` is 0.
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+        libc::setpriority(libc::PRIO_PROCESS, 0, 10);
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << 13,
+        );
+    }
+    #[cfg(target_os = "macos")]
+    unsafe {
+        // Background band: low CPU priority and throttled I/O for this thread only.
+        libc::setpriority(libc::PRIO_DARWIN_THREAD, 0, libc::PRIO_DARWIN_BG);
+    }
+}
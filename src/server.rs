@@ -1,22 +1,44 @@
-use crate::config::Config;
-use crate::scanner::Scanner;
-use crate::storage::Storage;
-use crate::types::{CleanResult, FileTreeNode, HistoryEntry};
-use crate::watcher::FileWatcher;
+use crate::api_token;
+use crate::config::{Config, PathMatch, Settings};
+use crate::content_type;
+use crate::mdns::Advertisement;
+use crate::mirror::{self, MirrorOptions};
+use crate::registry::{self, ServerInfo};
+use crate::scanner::{DirCache, ScanResult, Scanner, TooManyFiles};
+use crate::similarity;
+use crate::storage::{CachedIndex, ChecksumVerifier, Storage};
+use crate::timeline;
+use crate::types::{
+    AgeBucket, AuditRecord, CleanResult, DedupStats, EntrySource, EventKind, FileStats,
+    FileTimeline, FileTreeNode, HashAlgo, HistoryDiffSummary, HistoryEntry, HistoryVersion,
+    LastScan, LineDelta, MaintenanceRecord, MigrateStoreResult, OrphanSnapshot, RestoreConflict,
+    SignatureReport, SimilarFile, UncleanResult, UsageSample, VerifyReport, WatcherStats,
+};
+use crate::user_state::UserState;
+use crate::volume::{Volume, VolumeState};
+use crate::watcher::{FileWatcher, WatcherCounters};
+use crate::zip;
 use anyhow::{Context, Result};
 use axum::body::Body;
-use axum::extract::{Query, State};
-use axum::http::{header, StatusCode};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Query, Request, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::middleware::{self, Next};
 use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
+use futures_util::{StreamExt, TryStreamExt};
 use rust_embed::Embed;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock as StdRwLock};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex, RwLock as StdRwLock};
 use std::time::Duration;
 use tokio::sync::{Notify, RwLock, Semaphore};
-use tokio::time::timeout;
+use tokio::time::timeout_at;
+use tokio_util::io::{ReaderStream, StreamReader, SyncIoBridge};
+use tower::ServiceExt;
 use tracing::{info, warn};
 
 // ---------------------------------------------------------------------------
@@ -30,23 +52,131 @@ type SharedConfig = Arc<StdRwLock<Config>>;
 struct WatchContext {
     watch_dir: PathBuf,
     config: SharedConfig,
+    /// Counters of the file watcher; None for a mirror, which does not watch.
+    watcher: Option<Arc<WatcherCounters>>,
+    /// Index kept for `/api/paths`, reloaded only when the saved index changes.
+    paths: Arc<StdMutex<CachedIndex>>,
+    /// Volume of `watch_dir`, flagged while unmounted.
+    volume: Arc<Volume>,
+    /// Last `.ftm/verify.json`, updated by the integrity verifier.
+    verify_report: Arc<StdMutex<VerifyReport>>,
+}
+
+impl Drop for WatchContext {
+    /// The file watcher, and its writer tracking, end with the context.
+    fn drop(&mut self) {
+        if let Some(watcher) = &self.watcher {
+            watcher.stop();
+        }
+    }
 }
 
 pub struct AppState {
     ctx: RwLock<Option<WatchContext>>,
+    /// Address the server is bound to.
+    bind: IpAddr,
+    /// Port the server is actually listening on (resolved when binding port 0).
+    port: u16,
+    /// mDNS advertisement of the Web UI, active while a LAN-bound server watches a directory.
+    mdns: StdMutex<Option<Advertisement>>,
+    /// Exclusive `.ftm/lock` of the watched directory, held while the server runs.
+    dir_lock: StdMutex<Option<std::fs::File>>,
+    /// Source URL when running as a read-only mirror; write endpoints are rejected.
+    mirror_of: Option<String>,
     shutdown: Notify,
-    /// Only one diff computation at a time. Permit is held inside spawn_blocking
-    /// so that on timeout the abandoned task keeps the permit until it finishes.
-    diff_semaphore: Arc<Semaphore>,
+    /// Bounds concurrent diff computations to `settings.diff_workers`: (size, permits).
+    /// Permits are held inside spawn_blocking so that on timeout the abandoned task
+    /// keeps its permit until it finishes.
+    diff_pool: StdMutex<(usize, Arc<Semaphore>)>,
+    /// Name of this project when it is one of the daemon's projects (see `projects`).
+    project: Option<String>,
+    /// Further directories served by this daemon, each with its own state and API
+    /// under `/projects/<name>/` (or `?project=<name>`).
+    projects: RwLock<BTreeMap<String, Project>>,
+    /// Names of projects still being checked out, reserved so two adds cannot take the
+    /// same name while neither holds the `projects` lock.
+    adding_projects: StdMutex<HashSet<String>>,
+    /// API request allowance per client address (`settings.api_rate_limit`).
+    request_buckets: StdMutex<HashMap<IpAddr, RequestBucket>>,
+    /// Line changes already computed for `/api/file-timeline` and
+    /// `/api/history-diff-summary`.
+    line_counts: timeline::LineCountCache,
+}
+
+/// Token bucket of one client under `settings.api_rate_limit`: refills `limit` tokens
+/// per second and holds at most `limit`, so bursts last at most one second.
+struct RequestBucket {
+    tokens: f64,
+    refilled: std::time::Instant,
+}
+
+impl RequestBucket {
+    /// Refill for the time passed; true when the bucket is full (the client is idle).
+    fn refill(&mut self, limit: u64) -> bool {
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit as f64).min(limit as f64);
+        self.refilled = now;
+        self.tokens >= limit as f64
+    }
+}
+
+/// A project of a multi-directory daemon.
+#[derive(Clone)]
+struct Project {
+    state: SharedState,
+    router: Router,
 }
 
 impl AppState {
-    fn new() -> Self {
+    fn new(bind: IpAddr, port: u16, mirror_of: Option<String>) -> Self {
         Self {
             ctx: RwLock::new(None),
+            bind,
+            port,
+            mdns: StdMutex::new(None),
+            dir_lock: StdMutex::new(None),
+            mirror_of,
             shutdown: Notify::new(),
-            diff_semaphore: Arc::new(Semaphore::new(1)),
+            diff_pool: StdMutex::new((0, Arc::new(Semaphore::new(0)))),
+            project: None,
+            projects: RwLock::new(BTreeMap::new()),
+            adding_projects: StdMutex::new(HashSet::new()),
+            request_buckets: StdMutex::new(HashMap::new()),
+            line_counts: timeline::LineCountCache::default(),
+        }
+    }
+
+    /// Take one request from `ip`'s allowance of `limit` per second, or return how long
+    /// until one is available.
+    fn take_request(&self, ip: IpAddr, limit: u64) -> Result<(), Duration> {
+        const MAX_CLIENTS: usize = 1024;
+        let mut buckets = self.request_buckets.lock().unwrap();
+        if buckets.len() >= MAX_CLIENTS {
+            buckets.retain(|_, b| !b.refill(limit));
+        }
+        let bucket = buckets.entry(ip).or_insert_with(|| RequestBucket {
+            tokens: limit as f64,
+            refilled: std::time::Instant::now(),
+        });
+        bucket.refill(limit);
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / limit as f64,
+            ));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Permits of the diff pool, rebuilt when the configured size changes. Diffs still
+    /// running on the old pool finish on their own permits.
+    fn diff_semaphore(&self, workers: usize) -> Arc<Semaphore> {
+        let mut pool = self.diff_pool.lock().unwrap();
+        if pool.0 != workers {
+            *pool = (workers, Arc::new(Semaphore::new(workers)));
         }
+        pool.1.clone()
     }
 
     /// Create a Storage instance for the current watch context.
@@ -59,6 +189,18 @@ impl AppState {
             (storage, c.watch_dir.clone())
         })
     }
+
+    async fn paths_cache(&self) -> Option<Arc<StdMutex<CachedIndex>>> {
+        self.ctx.read().await.as_ref().map(|c| c.paths.clone())
+    }
+
+    /// Whether background verification last found `checksum` corrupt.
+    async fn known_corrupt(&self, checksum: &str) -> bool {
+        self.ctx.read().await.as_ref().is_some_and(|c| {
+            let report = c.verify_report.lock().unwrap();
+            report.corrupt.iter().any(|c| c == checksum)
+        })
+    }
 }
 
 type SharedState = Arc<AppState>;
@@ -70,6 +212,33 @@ type SharedState = Arc<AppState>;
 #[derive(Deserialize)]
 struct CheckoutRequest {
     directory: String,
+    /// Check out even past `settings.max_tracked_files`.
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Deserialize)]
+struct AddProjectRequest {
+    directory: String,
+    /// Defaults to the directory's name.
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AddProjectResponse {
+    name: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ProjectInfo {
+    name: String,
+    watch_dir: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProjectQuery {
+    project: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -77,11 +246,44 @@ struct MessageResponse {
     message: String,
 }
 
+#[derive(Serialize)]
+struct ShareResponse {
+    /// Path of the link on this server, `/share/<token>`.
+    path: String,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
 #[derive(Serialize)]
 struct HealthResponse {
+    /// "ok", or "degraded" while the watched directory's volume is unmounted.
     status: String,
     pid: u32,
     watch_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mirror_of: Option<String>,
+    /// Free space on the watched `.ftm` volume is below `settings.min_free_space`.
+    low_disk_space: bool,
+    /// Snapshots found corrupt / missing by background verification.
+    corrupt_count: usize,
+    missing_count: usize,
+    /// Referenced snapshot bytes exceed `settings.quota_warn_percent` of max_quota.
+    quota_warning: bool,
+    /// When the file watcher last received a change, and last saved a snapshot for one;
+    /// null until it has.
+    last_event_at: Option<chrono::DateTime<chrono::Utc>>,
+    last_snapshot_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the last full (manual, periodic or post-checkout) scan completed.
+    last_scan_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Since when the watched directory has been unavailable; watching resumes, with a
+    /// full scan, once it is back.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unmounted_since: Option<chrono::DateTime<chrono::Utc>>,
+    /// When bulk mode (`ftm bulk-begin`) ends by itself, while it is on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bulk_until: Option<chrono::DateTime<chrono::Utc>>,
+    /// Set while recording is paused (`ftm pause`).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    paused: bool,
 }
 
 #[derive(Deserialize)]
@@ -90,11 +292,29 @@ struct FilesQuery {
     include_deleted: Option<bool>,
 }
 
+#[derive(Deserialize)]
+struct PathsQuery {
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    include_deleted: bool,
+    /// At most this many paths (default 100).
+    limit: Option<usize>,
+}
+
 #[derive(Deserialize)]
 struct HistoryQuery {
     file: String,
 }
 
+#[derive(Deserialize)]
+struct AuditQuery {
+    /// ISO 8601 timestamp; records before it are left out. Defaults to everything.
+    since: Option<String>,
+    /// Only records of this op, e.g. `modify` or `config_change`. Defaults to all.
+    op: Option<String>,
+}
+
 #[derive(Deserialize)]
 struct ActivityQuery {
     /// ISO 8601 timestamp for the start of the time range (inclusive).
@@ -108,7 +328,46 @@ struct ActivityQuery {
 #[derive(Deserialize)]
 struct RestoreRequest {
     file: String,
+    #[serde(default)]
+    checksum: String,
+    /// Restore the last version of a deleted file instead of `checksum`.
+    #[serde(default)]
+    from_trash: bool,
+    /// Refuse with the versions involved (409) when the working copy has changes not
+    /// yet in history.
+    #[serde(default)]
+    check_conflict: bool,
+}
+
+#[derive(Serialize)]
+struct RestoreConflictResponse {
+    message: String,
+    conflict: RestoreConflict,
+}
+
+#[derive(Deserialize)]
+struct RmRequest {
+    file: String,
+}
+
+#[derive(Deserialize)]
+struct BulkBeginRequest {
+    /// Seconds after which bulk mode ends by itself, should `bulk-end` never come.
+    timeout_secs: u64,
+}
+
+#[derive(Serialize)]
+struct BulkBeginResponse {
+    until: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize)]
+struct ShareRequest {
+    file: String,
+    /// Checksum of the version to share (a prefix is enough).
     checksum: String,
+    /// Seconds until the link expires.
+    expires_in: u64,
 }
 
 #[derive(Serialize)]
@@ -116,6 +375,22 @@ struct VersionResponse {
     version: String,
 }
 
+#[derive(Deserialize)]
+struct ScanQuery {
+    /// List what would be recorded without writing anything.
+    dry_run: Option<bool>,
+    /// Scan even past `settings.max_tracked_files`.
+    force: Option<bool>,
+    /// List every recorded entry in `changes`, not just the counts.
+    details: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct GcQuery {
+    /// Also purge trash still within the retention window.
+    aggressive: Option<bool>,
+}
+
 #[derive(Deserialize)]
 struct ConfigQuery {
     key: Option<String>,
@@ -133,12 +408,56 @@ struct ConfigResponse {
     data: String,
 }
 
+#[derive(Serialize)]
+struct ConfigErrorResponse {
+    message: String,
+    /// Error of each invalid key, by `config set` key (e.g. `settings.max_quota`).
+    errors: BTreeMap<String, String>,
+}
+
 #[derive(Serialize)]
 struct StatsResponse {
     history: usize,
     max_history: usize,
     quota: u64,
     max_quota: u64,
+    /// Free bytes on the `.ftm` volume, when known.
+    free_space: Option<u64>,
+    min_free_space: u64,
+    quota_warn_percent: u64,
+    /// Background verification findings; `verified_at` is unset before the first round.
+    corrupt_count: usize,
+    missing_count: usize,
+    verified_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// The last full scan and its counts; unset before the first.
+    last_scan: Option<LastScan>,
+}
+
+#[derive(Deserialize)]
+struct RestoreDirRequest {
+    dir: String,
+    /// ISO 8601 timestamp: restore the files that existed then. Defaults to any time.
+    at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExportDirQuery {
+    /// Directory relative to the watched root; empty or absent for everything.
+    #[serde(default)]
+    path: String,
+    /// ISO 8601 timestamp: the files that existed then, as they were. Defaults to now.
+    at: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RestoredFile {
+    file: String,
+    checksum: String,
+}
+
+#[derive(Serialize)]
+struct RestoreDirResponse {
+    restored: Vec<RestoredFile>,
 }
 
 #[derive(Serialize)]
@@ -152,6 +471,28 @@ struct SnapshotQuery {
     checksum: String,
 }
 
+#[derive(Deserialize)]
+struct UploadQuery {
+    /// File key, relative to the watch directory.
+    file: String,
+    /// BLAKE3 of the uploaded content.
+    checksum: String,
+    /// Who produced this version, e.g. `ci`; shown as its writer.
+    writer: Option<String>,
+}
+
+#[derive(Serialize)]
+struct UploadResponse {
+    message: String,
+    /// None when the content equals the file's current version.
+    entry: Option<HistoryEntry>,
+}
+
+#[derive(Serialize)]
+struct SyncEntriesResponse {
+    added: usize,
+}
+
 #[derive(Deserialize)]
 struct DiffQuery {
     /// Checksum of the "old" version. Empty or absent means diff against empty.
@@ -160,11 +501,35 @@ struct DiffQuery {
     to: String,
 }
 
+#[derive(Deserialize)]
+struct MatchQuery {
+    /// Absolute, or relative to the watch directory.
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct TimelineQuery {
+    /// File key, relative to the watch directory.
+    path: String,
+}
+
 #[derive(Serialize)]
 struct DiffResponse {
     hunks: Vec<DiffHunk>,
     old_total: usize,
     new_total: usize,
+    /// A version was larger than `MAX_INLINE_DIFF_BYTES`: only changes within its first
+    /// lines are listed, and `/api/patch` has the full diff.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct PatchQuery {
+    from: Option<String>,
+    to: String,
+    /// File key, for the patch header and download name.
+    file: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -181,10 +546,16 @@ struct DiffLine {
     content: String,
 }
 
+/// Versions larger than this are diffed by `/api/diff` only up to a line break within
+/// their first this many bytes, so the diff fits the time limit and memory stays bounded.
+const MAX_INLINE_DIFF_BYTES: u64 = 2 * 1024 * 1024;
+
 /// CPU-heavy diff computation. Returns hunks only; old_total/new_total are
 /// computed by the caller from line counts. Uses imara-diff (Histogram) for
-/// speed and stability.
-fn compute_diff_hunks(old_text: String, new_text: String) -> Vec<DiffHunk> {
+/// speed and stability. When the texts are cut-off heads of larger versions
+/// (`complete` unset), hunks reaching the end of either are dropped, as lines past the
+/// cut may match them.
+fn compute_diff_hunks(old_text: String, new_text: String, complete: bool) -> Vec<DiffHunk> {
     const CONTEXT_LINES: u32 = 3;
     use imara_diff::{Algorithm, Diff, InternedInput};
 
@@ -208,6 +579,12 @@ fn compute_diff_hunks(old_text: String, new_text: String) -> Vec<DiffHunk> {
         let before_end = hunk.before.end;
         let after_start = hunk.after.start;
         let after_end = hunk.after.end;
+        if !complete
+            && (before_end as usize == input.before.len()
+                || after_end as usize == input.after.len())
+        {
+            continue;
+        }
 
         let ctx_old_start = before_start.saturating_sub(CONTEXT_LINES);
         let ctx_new_end = (after_end + CONTEXT_LINES).min(input.after.len() as u32);
@@ -273,13 +650,106 @@ async fn health(State(state): State<SharedState>) -> impl IntoResponse {
     let watch_dir = guard
         .as_ref()
         .map(|c| c.watch_dir.to_string_lossy().to_string());
+    let (low_disk_space, corrupt_count, missing_count, quota_warning, last_scan_at) = guard
+        .as_ref()
+        .map(|ctx| {
+            let settings = ctx.config.read().unwrap().settings.clone();
+            let storage = Storage::for_settings(ctx.watch_dir.join(".ftm"), &settings);
+            let (corrupt_count, missing_count) = {
+                let report = ctx.verify_report.lock().unwrap();
+                (report.corrupt.len(), report.missing.len())
+            };
+            // Through the cached index, which is only read again after it changed
+            let quota_warning = storage
+                .cached_quota_warning(&mut ctx.paths.lock().unwrap(), settings.quota_warn_percent);
+            let last_scan = storage.dir_state().unwrap_or_default().last_scan;
+            (
+                storage.low_space(settings.min_free_space).is_some(),
+                corrupt_count,
+                missing_count,
+                matches!(quota_warning, Ok(Some(_))),
+                last_scan.map(|s| s.at),
+            )
+        })
+        .unwrap_or_default();
+    let counters = guard.as_ref().and_then(|ctx| ctx.watcher.as_ref());
+    let bulk_until = counters.and_then(|w| w.bulk_until());
+    let paused = counters.is_some_and(|w| w.paused());
+    let watcher = counters.map(|w| w.stats());
+    let unmounted_since = guard.as_ref().and_then(|ctx| ctx.volume.unmounted_since());
     Json(HealthResponse {
-        status: "ok".into(),
+        status: if unmounted_since.is_some() {
+            "degraded"
+        } else {
+            "ok"
+        }
+        .into(),
         pid: std::process::id(),
         watch_dir,
+        mirror_of: state.mirror_of.clone(),
+        low_disk_space,
+        corrupt_count,
+        missing_count,
+        quota_warning,
+        last_event_at: watcher.as_ref().and_then(|w| w.last_event_at),
+        last_snapshot_at: watcher.and_then(|w| w.last_snapshot_at),
+        last_scan_at,
+        unmounted_since,
+        bulk_until,
+        paused,
     })
 }
 
+/// Take the directory's `.ftm/lock` for this server, so two servers never race on
+/// index.json. The error names the server that already holds it.
+fn lock_dir(state: &AppState, ftm_dir: &Path) -> Result<(), ApiError> {
+    match registry::lock_ftm_dir(ftm_dir) {
+        Ok(Some(lock)) => {
+            *state.dir_lock.lock().unwrap() = Some(lock);
+            Ok(())
+        }
+        Ok(None) => {
+            let owner = registry::read_server_info(ftm_dir)
+                .map(|info| {
+                    format!(
+                        " (pid {}, port {}). Stop it with 'ftm --port {} stop' first",
+                        info.pid, info.port, info.port
+                    )
+                })
+                .unwrap_or_default();
+            Err(api_err(
+                StatusCode::CONFLICT,
+                format!(
+                    "{} is already watched by another ftm server{}.",
+                    ftm_dir.parent().unwrap_or(ftm_dir).display(),
+                    owner
+                ),
+            ))
+        }
+        Err(e) => Err(api_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to lock {}: {}", ftm_dir.display(), e),
+        )),
+    }
+}
+
+/// Initialize `.ftm` in `directory` if needed and load its config.
+/// Checks config.yaml (not the .ftm/ dir) because --log-dir may have already
+/// created .ftm/logs/ before checkout runs.
+fn init_ftm_dir(directory: &Path) -> Result<(PathBuf, Config)> {
+    let ftm_dir = directory.join(".ftm");
+    let config_path = ftm_dir.join("config.yaml");
+    if !config_path.exists() {
+        std::fs::create_dir_all(&ftm_dir)?;
+        Config::default().save(&config_path)?;
+        let index_content = serde_json::to_string_pretty(&crate::types::Index::default())?;
+        std::fs::write(ftm_dir.join("index.json"), index_content)?;
+        info!("Initialized .ftm in {}", directory.display());
+    }
+    let config = Config::load(&config_path)?;
+    Ok((ftm_dir, config))
+}
+
 async fn checkout(
     State(state): State<SharedState>,
     Json(req): Json<CheckoutRequest>,
@@ -306,31 +776,12 @@ async fn checkout(
         }
     }
 
-    // Initialize .ftm if needed.
-    // Check config.yaml (not .ftm/ dir) because --log-dir may have already
-    // created .ftm/logs/ before checkout runs.
-    let ftm_dir = directory.join(".ftm");
-    let config_path = ftm_dir.join("config.yaml");
-    if !config_path.exists() {
-        std::fs::create_dir_all(&ftm_dir)
-            .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-        let config = Config::default();
-        config
-            .save(&config_path)
-            .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-        let index = crate::types::Index::default();
-        let index_content = serde_json::to_string_pretty(&index)
-            .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-        std::fs::write(ftm_dir.join("index.json"), index_content)
-            .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-        info!("Initialized .ftm in {}", directory.display());
-    }
-
-    let config = Config::load(&ftm_dir.join("config.yaml"))
+    let (ftm_dir, config) = init_ftm_dir(&directory)
         .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !req.force {
+        check_tracked_files(&directory, &ftm_dir, &config).await?;
+    }
+    lock_dir(&state, &ftm_dir)?;
 
     // Wrap config in Arc<StdRwLock> so all components share the same instance.
     let shared_config: SharedConfig = Arc::new(StdRwLock::new(config));
@@ -338,26 +789,48 @@ async fn checkout(
     // Start watcher in background thread
     let watch_dir = directory.clone();
     let watcher = FileWatcher::new(watch_dir.clone(), shared_config.clone());
+    let watcher_counters = watcher.counters();
     watcher.watch_background();
 
     info!("Watching directory: {}", watch_dir.display());
 
-    // Spawn .ftm directory watchdog — auto-shutdown when .ftm is deleted
+    // Spawn .ftm directory watchdog — auto-shutdown when .ftm is deleted; while the
+    // volume is unmounted the server is degraded instead, and recovers when it returns
+    let volume = Arc::new(Volume::new(directory.clone()));
     {
         let ftm_dir = ftm_dir.clone();
         let state = state.clone();
+        let volume = volume.clone();
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(2));
             interval.tick().await; // skip immediate first tick
             loop {
                 interval.tick().await;
-                if !ftm_dir.exists() {
-                    warn!(
-                        ".ftm directory deleted ({}), shutting down server",
-                        ftm_dir.display()
-                    );
-                    state.shutdown.notify_one();
-                    break;
+                match volume.state() {
+                    VolumeState::Present => {
+                        if volume.set_unmounted(false) {
+                            info!(
+                                "Watched directory {} is available again",
+                                watch_dir.display()
+                            );
+                        }
+                    }
+                    VolumeState::Unmounted => {
+                        if volume.set_unmounted(true) {
+                            warn!(
+                                "Watched directory {} is unavailable (volume unmounted?); waiting for it to return",
+                                watch_dir.display()
+                            );
+                        }
+                    }
+                    VolumeState::FtmDeleted => {
+                        warn!(
+                            ".ftm directory deleted ({}), shutting down server",
+                            ftm_dir.display()
+                        );
+                        state.shutdown.notify_one();
+                        break;
+                    }
                 }
             }
         });
@@ -369,8 +842,19 @@ async fn checkout(
         let scan_watch_dir = directory.clone();
         let scan_config = shared_config.clone();
         let scan_ftm_dir = ftm_dir.clone();
+        let volume = volume.clone();
+        let scan_counters = watcher_counters.clone();
         tokio::spawn(async move {
+            // Carry on the schedule of a previous server rather than starting over
             let mut last_scan = tokio::time::Instant::now();
+            let interval = scan_config.read().unwrap().settings.scan_interval;
+            if let Some(age) = last_scan_age(&scan_ftm_dir, &scan_config)
+                .filter(|age| *age < Duration::from_secs(interval))
+            {
+                last_scan = last_scan.checked_sub(age).unwrap_or(last_scan);
+            }
+            let dir_cache = DirCache::default();
+            let mut quota_warned = false;
             loop {
                 let (scan_interval, cfg_snapshot) = {
                     let cfg = scan_config.read().unwrap();
@@ -385,25 +869,29 @@ async fn checkout(
                     continue;
                 }
 
-                if !scan_ftm_dir.exists() {
+                if !ftm_dir_available(&volume).await {
                     break;
                 }
+                // While paused or under bulk mode the watcher's scan at its end comes first
+                if scan_counters.holding() {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
 
                 last_scan = tokio::time::Instant::now();
                 let wd = scan_watch_dir.clone();
                 let cfg = cfg_snapshot;
                 let fd = scan_ftm_dir.clone();
-                match tokio::task::spawn_blocking(move || {
-                    let storage = Storage::for_settings(fd, &cfg.settings);
-                    Scanner::new(wd, cfg, storage).scan()
-                })
-                .await
-                {
+                let settings = cfg.settings.clone();
+                let dirs = settings.incremental_scan.then(|| dir_cache.clone());
+                match background_scan(wd, cfg, fd, dirs).await {
                     Ok(Ok(r)) => {
                         info!(
                             "Periodic scan: {} created, {} modified, {} deleted, {} unchanged",
                             r.created, r.modified, r.deleted, r.unchanged
                         );
+                        let fd = scan_ftm_dir.clone();
+                        quota_warned = check_quota(fd, settings, quota_warned).await;
                     }
                     Ok(Err(e)) => {
                         warn!("Periodic scan error: {}", e);
@@ -417,28 +905,35 @@ async fn checkout(
         info!("Periodic scanner started");
     }
 
-    // One-time scan 30s after checkout (only runs once)
+    // One-time scan 30s after checkout (only runs once), unless a full scan ran within
+    // scan_interval: then the periodic scanner is due soon enough.
     {
         let once_scan_watch_dir = directory.clone();
         let once_scan_config = shared_config.clone();
         let once_scan_ftm_dir = ftm_dir.clone();
+        let volume = volume.clone();
         tokio::spawn(async move {
             tokio::time::sleep(Duration::from_secs(30)).await;
-            if !once_scan_ftm_dir.exists() {
+            if !ftm_dir_available(&volume).await {
                 return;
             }
             let cfg_snapshot = {
                 let cfg = once_scan_config.read().unwrap();
                 cfg.clone()
             };
+            let interval = Duration::from_secs(cfg_snapshot.settings.scan_interval);
+            if let Some(age) =
+                last_scan_age(&once_scan_ftm_dir, &once_scan_config).filter(|age| *age < interval)
+            {
+                info!(
+                    "Skipping post-checkout scan: last full scan completed {}s ago",
+                    age.as_secs()
+                );
+                return;
+            }
             let wd = once_scan_watch_dir.clone();
             let fd = once_scan_ftm_dir.clone();
-            match tokio::task::spawn_blocking(move || {
-                let storage = Storage::for_settings(fd, &cfg_snapshot.settings);
-                Scanner::new(wd, cfg_snapshot, storage).scan()
-            })
-            .await
-            {
+            match background_scan(wd, cfg_snapshot, fd, None).await {
                 Ok(Ok(r)) => {
                     info!(
                         "Post-checkout scan (30s): {} created, {} modified, {} deleted, {} unchanged",
@@ -458,6 +953,7 @@ async fn checkout(
     // Spawn periodic cleaner — runs full clean (trim + orphan removal) every clean_interval seconds.
     {
         let clean_ftm_dir = ftm_dir.clone();
+        let volume = volume.clone();
         let clean_config = shared_config.clone();
         tokio::spawn(async move {
             let mut last_clean = tokio::time::Instant::now();
@@ -475,7 +971,7 @@ async fn checkout(
                     continue;
                 }
 
-                if !clean_ftm_dir.exists() {
+                if !ftm_dir_available(&volume).await {
                     break;
                 }
 
@@ -494,12 +990,24 @@ async fn checkout(
                                 r.entries_trimmed, r.bytes_freed_trim
                             );
                         }
+                        if r.entries_collapsed > 0 {
+                            info!(
+                                "Periodic clean: {} repeated version(s) collapsed",
+                                r.entries_collapsed
+                            );
+                        }
                         if r.files_removed > 0 {
                             info!(
                                 "Periodic clean: {} orphan snapshot(s) removed, {} freed",
                                 r.files_removed, r.bytes_removed
                             );
                         }
+                        if r.tmp_removed > 0 || r.dirs_removed > 0 {
+                            info!(
+                                "Periodic clean: {} stale temp file(s), {} empty directories removed",
+                                r.tmp_removed, r.dirs_removed
+                            );
+                        }
                     }
                     Ok(Err(e)) => {
                         warn!("Periodic clean error: {}", e);
@@ -513,21 +1021,219 @@ async fn checkout(
         info!("Periodic cleaner started");
     }
 
+    // Spawn integrity verifier — re-hashes a rotating batch of snapshots every verify_interval seconds.
+    let verify_report = load_verify_report(&ftm_dir, &shared_config);
+    {
+        const VERIFY_BATCH: usize = 100;
+        let verify_ftm_dir = ftm_dir.clone();
+        let last_report = verify_report.clone();
+        let volume = volume.clone();
+        let verify_config = shared_config.clone();
+        tokio::spawn(async move {
+            let mut last_verify = tokio::time::Instant::now();
+            loop {
+                let (verify_interval, settings) = {
+                    let cfg = verify_config.read().unwrap();
+                    (cfg.settings.verify_interval, cfg.settings.clone())
+                };
+
+                let elapsed = last_verify.elapsed().as_secs();
+                if verify_interval == 0 || elapsed < verify_interval {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+
+                if !ftm_dir_available(&volume).await {
+                    break;
+                }
+
+                last_verify = tokio::time::Instant::now();
+                let fd = verify_ftm_dir.clone();
+                match run_low_priority(move || {
+                    Storage::for_settings(fd, &settings).verify_round(VERIFY_BATCH)
+                })
+                .await
+                {
+                    Ok(Ok(round)) => {
+                        if round.checked > 0 {
+                            info!("Integrity check: {} snapshot(s) verified", round.checked);
+                        }
+                        *last_report.lock().unwrap() = round.report;
+                        for c in &round.newly_corrupt {
+                            warn!("Integrity check: snapshot {} is corrupt", c);
+                        }
+                        for c in &round.newly_missing {
+                            warn!("Integrity check: snapshot {} is missing", c);
+                        }
+                    }
+                    Ok(Err(e)) => {
+                        warn!("Integrity check error: {}", e);
+                    }
+                    Err(e) => {
+                        warn!("Integrity check task panic: {}", e);
+                    }
+                }
+            }
+        });
+        info!("Integrity verifier started");
+    }
+
+    // Spawn config reloader — applies hand edits of config.yaml within ~1s.
+    {
+        let reload_ftm_dir = ftm_dir.clone();
+        let volume = volume.clone();
+        let reload_shared_config = shared_config.clone();
+        tokio::spawn(async move {
+            let path = reload_ftm_dir.join("config.yaml");
+            let stamp = || {
+                let meta = std::fs::metadata(&path).ok()?;
+                Some((meta.modified().ok()?, meta.len()))
+            };
+            let mut seen = stamp();
+            loop {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                if !ftm_dir_available(&volume).await {
+                    break;
+                }
+                let current = stamp();
+                if current.is_none() || current == seen {
+                    continue;
+                }
+                seen = current;
+                let (ftm_dir, config) = (reload_ftm_dir.clone(), reload_shared_config.clone());
+                match tokio::task::spawn_blocking(move || reload_config(&ftm_dir, &config)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("Failed to reload config.yaml: {:#}", e),
+                    Err(e) => warn!("Config reload task panic: {}", e),
+                }
+            }
+        });
+    }
+
+    // Spawn read-only listener — serves the Web UI and GET endpoints on
+    // settings.ui_listen, rebinding within ~1s when `config set` changes it.
+    {
+        let volume = volume.clone();
+        let ui_config = shared_config.clone();
+        let ui_state = state.clone();
+        tokio::spawn(async move {
+            // Address in effect, and the sender that stops its listener (None when
+            // binding it failed, so a bad address is not retried every second)
+            let mut active = String::new();
+            let mut stop: Option<tokio::sync::oneshot::Sender<()>> = None;
+            loop {
+                if !ftm_dir_available(&volume).await {
+                    break;
+                }
+                let wanted = ui_config.read().unwrap().settings.ui_listen.clone();
+                if wanted != active {
+                    if let Some(stop) = stop.take() {
+                        let _ = stop.send(());
+                        info!("Read-only listener on {} stopped", active);
+                    }
+                    if !wanted.is_empty() {
+                        match start_read_only_listener(ui_state.clone(), &wanted) {
+                            Ok(tx) => stop = Some(tx),
+                            Err(e) => warn!("Read-only listener on {} failed: {:#}", wanted, e),
+                        }
+                    }
+                    active = wanted;
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+    }
+
     // Store context
     {
         let mut guard = state.ctx.write().await;
         *guard = Some(WatchContext {
             watch_dir: directory.clone(),
             config: shared_config,
+            watcher: Some(watcher_counters),
+            paths: Arc::default(),
+            volume,
+            verify_report,
+        });
+    }
+
+    // Sample usage now so stats history starts at checkout rather than the first clean.
+    if let Some((storage, _)) = state.storage().await {
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = storage.record_usage_sample() {
+                warn!("Failed to record usage sample: {}", e);
+            }
+        });
+    }
+
+    // A crash mid-snapshot leaves its temp file behind; recover the space right away
+    // instead of waiting for the first clean.
+    if let Some((storage, _)) = state.storage().await {
+        tokio::task::spawn_blocking(move || match storage.remove_stale_tmp() {
+            Ok(0) => {}
+            Ok(n) => info!("Removed {} stale temp snapshot file(s)", n),
+            Err(e) => warn!("Failed to remove stale temp snapshots: {}", e),
         });
     }
 
+    // Register our port so client commands run inside the directory can find us.
+    let server_info = ServerInfo {
+        host: registry::connect_host(state.bind),
+        port: state.port,
+        pid: std::process::id(),
+        project: state.project.clone(),
+    };
+    if let Err(e) = registry::write_server_info(&ftm_dir, &server_info) {
+        warn!("Failed to write server registry: {}", e);
+    }
+
+    // Reachable from the LAN: let other devices discover the Web UI.
+    if !state.bind.is_loopback() {
+        match Advertisement::start(state.bind, state.port, &directory) {
+            Ok(ad) => *state.mdns.lock().unwrap() = Some(ad),
+            Err(e) => warn!("mDNS advertisement failed: {}", e),
+        }
+    }
+
+    // Remember the directory so `ftm serve --resume` can pick it up after a restart.
+    if state.project.is_none() {
+        let mut user_state = UserState::load();
+        user_state.last_checkout = Some(directory.clone());
+        if let Err(e) = user_state.save() {
+            warn!("Failed to save user state: {}", e);
+        }
+    }
+
     Ok(Json(MessageResponse {
         message: format!("Checked out and watching: {}", directory.display()),
     }))
 }
 
-async fn files(
+/// Re-check out the directory recorded by the last successful checkout, if it still exists.
+async fn resume_last_checkout(state: SharedState) {
+    let Some(directory) = UserState::load().last_checkout else {
+        info!("Resume: no previous checkout recorded");
+        return;
+    };
+    if !directory.is_dir() {
+        warn!(
+            "Resume: last checked-out directory no longer exists: {}",
+            directory.display()
+        );
+        return;
+    }
+    // It was accepted when first checked out
+    let req = CheckoutRequest {
+        directory: directory.to_string_lossy().into_owned(),
+        force: true,
+    };
+    match checkout(State(state), Json(req)).await {
+        Ok(Json(msg)) => info!("Resume: {}", msg.message),
+        Err((_, Json(msg))) => warn!("Resume failed: {}", msg.message),
+    }
+}
+
+async fn files(
     State(state): State<SharedState>,
     Query(q): Query<FilesQuery>,
 ) -> Result<Json<Vec<FileTreeNode>>, ApiError> {
@@ -539,17 +1245,53 @@ async fn files(
     Ok(Json(tree))
 }
 
+/// Tracked paths starting with `prefix`, for completion in shells and the Web UI.
+async fn paths_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<PathsQuery>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let cache = state.paths_cache().await.ok_or_else(not_checked_out)?;
+    let paths = tokio::task::spawn_blocking(move || {
+        let mut cache = cache.lock().unwrap();
+        storage.refresh_cached_index(&mut cache)?;
+        let limit = q.limit.unwrap_or(100);
+        Ok::<_, anyhow::Error>(cache.paths_with_prefix(&q.prefix, q.include_deleted, limit))
+    })
+    .await
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(paths))
+}
+
 async fn history(
     State(state): State<SharedState>,
     Query(q): Query<HistoryQuery>,
-) -> Result<Json<Vec<HistoryEntry>>, ApiError> {
+) -> Result<Json<Vec<HistoryVersion>>, ApiError> {
     let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
     let entries = storage
-        .list_history(&q.file)
+        .list_history_versions(&q.file)
         .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     Ok(Json(entries))
 }
 
+async fn audit_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditRecord>>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let since = match q.since {
+        Some(ref s) => chrono::DateTime::parse_from_rfc3339(s)
+            .map(|dt| dt.with_timezone(&chrono::Utc))
+            .map_err(|e| api_err(StatusCode::BAD_REQUEST, format!("Invalid 'since': {}", e)))?,
+        None => chrono::DateTime::<chrono::Utc>::MIN_UTC,
+    };
+    let records = storage
+        .audit(since, q.op.as_deref())
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(records))
+}
+
 async fn activity(
     State(state): State<SharedState>,
     Query(q): Query<ActivityQuery>,
@@ -579,99 +1321,1274 @@ async fn activity(
 async fn restore(
     State(state): State<SharedState>,
     Json(req): Json<RestoreRequest>,
+) -> Result<Json<MessageResponse>, Response> {
+    let (storage, watch_dir) = state
+        .storage()
+        .await
+        .ok_or_else(|| not_checked_out().into_response())?;
+    if req.check_conflict && !req.from_trash {
+        let (storage, watch_dir) = state
+            .storage()
+            .await
+            .ok_or_else(|| not_checked_out().into_response())?;
+        let (file, checksum) = (req.file.clone(), req.checksum.clone());
+        let conflict = tokio::task::spawn_blocking(move || {
+            storage.restore_conflict(&file, &checksum, &watch_dir)
+        })
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?
+        .map_err(|e| api_err(StatusCode::NOT_FOUND, e.to_string()).into_response())?;
+        if let Some(conflict) = conflict {
+            let resp = RestoreConflictResponse {
+                message: format!(
+                    "'{}' has changes not yet in history; restoring would overwrite them",
+                    req.file
+                ),
+                conflict,
+            };
+            return Err((StatusCode::CONFLICT, Json(resp)).into_response());
+        }
+    }
+    restore_version(&storage, &watch_dir, &req).map_err(IntoResponse::into_response)
+}
+
+fn restore_version(
+    storage: &Storage,
+    watch_dir: &Path,
+    req: &RestoreRequest,
+) -> Result<Json<MessageResponse>, ApiError> {
+    if req.from_trash {
+        let checksum = storage
+            .restore_latest(&req.file, watch_dir)
+            .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        return Ok(Json(MessageResponse {
+            message: format!(
+                "Restored '{}' to its last version '{}'",
+                req.file,
+                &checksum[..8.min(checksum.len())]
+            ),
+        }));
+    }
+    let truncated_at = storage
+        .restore(&req.file, &req.checksum, watch_dir)
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut message = format!(
+        "Restored '{}' to checksum '{}'",
+        req.file,
+        &req.checksum[..8.min(req.checksum.len())]
+    );
+    if let Some(at) = truncated_at {
+        message.push_str(&format!(
+            " (note: the file had been truncated to 0 bytes at {})",
+            at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S")
+        ));
+    }
+    Ok(Json(MessageResponse { message }))
+}
+
+async fn rm_handler(
+    State(state): State<SharedState>,
+    Json(req): Json<RmRequest>,
 ) -> Result<Json<MessageResponse>, ApiError> {
     let (storage, watch_dir) = state.storage().await.ok_or_else(not_checked_out)?;
-    storage
-        .restore(&req.file, &req.checksum, &watch_dir)
+    let file = req.file.clone();
+    tokio::task::spawn_blocking(move || storage.remove_file(&file, &watch_dir))
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
         .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     Ok(Json(MessageResponse {
         message: format!(
-            "Restored '{}' to checksum '{}'",
-            req.file,
-            &req.checksum[..8.min(req.checksum.len())]
+            "Deleted '{}'; recover it with ftm history / ftm restore",
+            req.file
         ),
     }))
 }
 
+async fn restore_dir(
+    State(state): State<SharedState>,
+    Json(req): Json<RestoreDirRequest>,
+) -> Result<Json<RestoreDirResponse>, ApiError> {
+    let at = match req.at {
+        Some(ref s) => Some(
+            chrono::DateTime::parse_from_rfc3339(s)
+                .map(|dt| dt.with_timezone(&chrono::Utc))
+                .map_err(|e| api_err(StatusCode::BAD_REQUEST, format!("Invalid 'at': {}", e)))?,
+        ),
+        None => None,
+    };
+    let (storage, watch_dir) = state.storage().await.ok_or_else(not_checked_out)?;
+    let restored =
+        tokio::task::spawn_blocking(move || storage.restore_dir(&req.dir, at, &watch_dir))
+            .await
+            .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(RestoreDirResponse {
+        restored: restored
+            .into_iter()
+            .map(|(file, checksum)| RestoredFile { file, checksum })
+            .collect(),
+    }))
+}
+
+/// Zip of every file under a directory as it was at a time, for download.
+async fn export_dir_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<ExportDirQuery>,
+) -> Result<Response, ApiError> {
+    let at = match q.at {
+        Some(ref s) => Some(
+            chrono::DateTime::parse_from_rfc3339(s)
+                .map(|dt| dt.with_timezone(&chrono::Utc))
+                .map_err(|e| api_err(StatusCode::BAD_REQUEST, format!("Invalid 'at': {}", e)))?,
+        ),
+        None => None,
+    };
+    let (storage, watch_dir) = state.storage().await.ok_or_else(not_checked_out)?;
+    let name = Path::new(q.path.trim_matches('/'))
+        .file_name()
+        .or_else(|| watch_dir.file_name())
+        .map_or_else(
+            || "export".to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        );
+    let (storage, versions) = tokio::task::spawn_blocking(move || -> Result<_> {
+        let versions = storage.versions_at(&q.path, at)?;
+        Ok((storage, versions))
+    })
+    .await
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if versions.is_empty() {
+        return Err(api_err(
+            StatusCode::NOT_FOUND,
+            "No files there at that time",
+        ));
+    }
+
+    // Members are written as they are read, so at most one file is held in memory; a
+    // failure part way through leaves the download truncated.
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    let out = SyncIoBridge::new(writer);
+    tokio::task::spawn_blocking(move || {
+        let write = || -> Result<()> {
+            let mut zip = zip::ZipWriter::new(out);
+            for entry in &versions {
+                let content = match entry.checksum {
+                    Some(ref checksum) => storage.read_snapshot(checksum)?,
+                    None => Vec::new(),
+                };
+                zip.append(&entry.file, &content, entry.timestamp)?;
+            }
+            zip.finish()?.shutdown()?;
+            Ok(())
+        };
+        if let Err(e) = write() {
+            warn!("Export of a directory stopped: {:#}", e);
+        }
+    });
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.zip\"", name.replace('"', "_")),
+        )
+        .body(Body::from_stream(ReaderStream::new(reader)))
+        .unwrap())
+}
+
+/// Parse a single-range `Range: bytes=...` header against a body of `total` bytes.
+/// Returns the inclusive byte range, `Ok(None)` for headers we don't handle (served
+/// as a full response), or `Err(())` when the range is unsatisfiable.
+fn parse_byte_range(header: &str, total: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None); // multipart ranges: fall back to the full body
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return Ok(None);
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let n: u64 = suffix.parse().map_err(|_| ())?;
+            if n == 0 || total == 0 {
+                return Err(());
+            }
+            (total.saturating_sub(n), total - 1)
+        }
+        (start, end) => {
+            let start: u64 = start.parse().map_err(|_| ())?;
+            let end: u64 = if end.is_empty() {
+                total.saturating_sub(1)
+            } else {
+                end.parse::<u64>()
+                    .map_err(|_| ())?
+                    .min(total.saturating_sub(1))
+            };
+            (start, end)
+        }
+    };
+    if start >= total || start > end {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+/// Algorithm and MIME type recorded for the snapshot `checksum`, from the cached index.
+async fn snapshot_info(
+    state: &AppState,
+    storage: Storage,
+    checksum: String,
+) -> Result<(Option<HashAlgo>, Option<String>), ApiError> {
+    let cache = state.paths_cache().await.ok_or_else(not_checked_out)?;
+    tokio::task::spawn_blocking(move || {
+        let mut cache = cache.lock().unwrap();
+        storage.refresh_cached_index(&mut cache)?;
+        Ok::<_, anyhow::Error>(cache.snapshot_info(&checksum))
+    })
+    .await
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// Stream a snapshot from disk (chunked), honoring a single `Range` request. Full
+/// responses are checked against the checksum as they are sent and cut short if the
+/// content turns out corrupt; ranges are served unless background verification found
+/// the snapshot corrupt.
 async fn snapshot_handler(
     State(state): State<SharedState>,
     Query(q): Query<SnapshotQuery>,
+    headers: HeaderMap,
 ) -> Result<Response, ApiError> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
     let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
-    let content = storage
-        .read_snapshot(&q.checksum)
+    let path = storage
+        .snapshot_file(&q.checksum)
         .map_err(|e| api_err(StatusCode::NOT_FOUND, e.to_string()))?;
+    if state.known_corrupt(&q.checksum).await {
+        return Err(api_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!(
+                "Snapshot {} is corrupt (content does not match its checksum)",
+                &q.checksum[..8]
+            ),
+        ));
+    }
+    let (algo, mime) = snapshot_info(&state, storage, q.checksum.clone()).await?;
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| api_err(StatusCode::NOT_FOUND, e.to_string()))?;
+    let total = file
+        .metadata()
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .len();
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| parse_byte_range(v, total))
+        .unwrap_or(Ok(None));
+
+    let builder = Response::builder()
+        .header(
+            header::CONTENT_TYPE,
+            content_type::header_value(mime.as_deref().unwrap_or("text/plain")),
+        )
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::ACCEPT_RANGES, "bytes");
+    let response = match range {
+        Err(()) => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", total))
+            .body(Body::empty()),
+        Ok(Some((start, end))) => {
+            file.seek(std::io::SeekFrom::Start(start))
+                .await
+                .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let len = end - start + 1;
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, total),
+                )
+                .header(header::CONTENT_LENGTH, len)
+                .body(Body::from_stream(ReaderStream::new(file.take(len))))
+        }
+        Ok(None) => builder
+            .header(header::CONTENT_LENGTH, total)
+            .body(Body::from_stream(verified_chunks(file, algo, q.checksum))),
+    };
+    Ok(response.unwrap())
+}
+
+/// Chunks of `file`, hashed as they are read. Each chunk is held back until the next
+/// one is read, so a response whose content does not match `checksum` ends in an error
+/// before the last bytes go out rather than looking complete.
+fn verified_chunks(
+    file: tokio::fs::File,
+    algo: Option<HashAlgo>,
+    checksum: String,
+) -> impl futures_util::Stream<Item = std::io::Result<axum::body::Bytes>> {
+    let state = (ReaderStream::new(file), ChecksumVerifier::new(algo), None);
+    futures_util::stream::unfold(Some(state), move |state| {
+        let checksum = checksum.clone();
+        async move {
+            let (mut chunks, mut verifier, mut pending) = state?;
+            loop {
+                match chunks.next().await {
+                    Some(Ok(chunk)) => {
+                        verifier.update(&chunk);
+                        if let Some(ready) = pending.replace(chunk) {
+                            return Some((Ok(ready), Some((chunks, verifier, pending))));
+                        }
+                    }
+                    Some(Err(e)) => return Some((Err(e), None)),
+                    None if verifier.matches(&checksum) => return pending.map(|c| (Ok(c), None)),
+                    None => {
+                        warn!("Snapshot {} is corrupt", &checksum[..8]);
+                        return Some((Err(std::io::Error::other("corrupt snapshot")), None));
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Record a version uploaded by an external tool (CI, a remote agent) for a file it
+/// manages. The body, possibly chunked, is streamed into the snapshot store and kept
+/// only if it matches the given checksum and fits `settings.max_file_size`.
+async fn upload_snapshot_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<UploadQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<UploadResponse>, ApiError> {
+    let (max_file_size, min_free_space) = {
+        let guard = state.ctx.read().await;
+        let ctx = guard.as_ref().ok_or_else(not_checked_out)?;
+        let cfg = ctx.config.read().unwrap();
+        (cfg.settings.max_file_size, cfg.settings.min_free_space)
+    };
+    let too_large = || {
+        api_err(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Upload exceeds settings.max_file_size ({} bytes)",
+                max_file_size
+            ),
+        )
+    };
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if length.is_some_and(|len| len > max_file_size) {
+        return Err(too_large());
+    }
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    // Like scans, take no new snapshots on a nearly full disk
+    if let Some(free) = storage.low_space(min_free_space) {
+        return Err(api_err(
+            StatusCode::INSUFFICIENT_STORAGE,
+            format!(
+                "Free disk space {} bytes is below settings.min_free_space ({} bytes); upload refused",
+                free, min_free_space
+            ),
+        ));
+    }
+
+    let file = q.file.clone();
+    let stream = body.into_data_stream().map_err(std::io::Error::other);
+    let mut reader = SyncIoBridge::new(StreamReader::new(stream));
+    let entry = tokio::task::spawn_blocking(move || {
+        let mut counted = CountingReader {
+            inner: &mut reader,
+            read: 0,
+        };
+        let entry =
+            storage.record_upload(&q.file, &q.checksum, &mut counted, max_file_size, q.writer);
+        (entry, counted.read)
+    })
+    .await
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    let entry = match entry? {
+        (Err(_), read) if read > max_file_size => return Err(too_large()),
+        (entry, _) => entry.map_err(|e| api_err(StatusCode::BAD_REQUEST, format!("{:#}", e)))?,
+    };
+    let message = match &entry {
+        Some(e) => format!("Recorded {} of '{}'", e.op, e.file),
+        None => format!("'{}' unchanged", file),
+    };
+    Ok(Json(UploadResponse { message, entry }))
+}
+
+/// Counts the bytes read through it, to tell an oversized upload from other failures.
+struct CountingReader<'a> {
+    inner: &'a mut dyn std::io::Read,
+    read: u64,
+}
+
+impl std::io::Read for CountingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+/// Create a link serving one version read-only until it expires.
+async fn share_handler(
+    State(state): State<SharedState>,
+    Json(req): Json<ShareRequest>,
+) -> Result<Json<ShareResponse>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    if req.checksum.len() < 8 {
+        return Err(api_err(
+            StatusCode::BAD_REQUEST,
+            "Checksum prefix must be at least 8 characters",
+        ));
+    }
+    let expires_at = i64::try_from(req.expires_in)
+        .ok()
+        .filter(|&secs| secs > 0)
+        .and_then(chrono::Duration::try_seconds)
+        .and_then(|d| chrono::Utc::now().checked_add_signed(d))
+        .ok_or_else(|| api_err(StatusCode::BAD_REQUEST, "Invalid expiry"))?;
+    let token = storage
+        .share_version(&req.file, &req.checksum, expires_at)
+        .map_err(|e| api_err(StatusCode::NOT_FOUND, e.to_string()))?;
+    Ok(Json(ShareResponse {
+        path: format!("/share/{}", token),
+        expires_at,
+    }))
+}
+
+/// Serve the snapshot a share link grants access to.
+async fn shared_snapshot_handler(
+    State(state): State<SharedState>,
+    axum::extract::Path(token): axum::extract::Path<String>,
+) -> Result<Response, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let (storage, content, checksum) = tokio::task::spawn_blocking(move || {
+        let checksum = storage
+            .shared_snapshot(&token)
+            .map_err(|e| api_err(StatusCode::FORBIDDEN, e.to_string()))?;
+        let content = storage
+            .read_snapshot(&checksum)
+            .map_err(|e| api_err(StatusCode::NOT_FOUND, e.to_string()))?;
+        Ok::<_, ApiError>((storage, content, checksum))
+    })
+    .await
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+    let (_, mime) = snapshot_info(&state, storage, checksum).await?;
     Ok(Response::builder()
-        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(
+            header::CONTENT_TYPE,
+            content_type::header_value(mime.as_deref().unwrap_or("text/plain")),
+        )
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::CACHE_CONTROL, "private, no-store")
         .body(Body::from(content))
         .unwrap())
 }
 
-async fn diff_handler(
+/// Full history, for peers computing which entries they are missing.
+async fn index_handler(
     State(state): State<SharedState>,
-    Query(q): Query<DiffQuery>,
-) -> Result<Json<DiffResponse>, ApiError> {
+) -> Result<Json<Vec<HistoryEntry>>, ApiError> {
     let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let index = storage
+        .load_index()
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(index.history))
+}
 
-    let old_text = match q.from.as_deref().filter(|s| !s.is_empty()) {
-        Some(from) => {
-            let bytes = storage
-                .read_snapshot(from)
-                .map_err(|e| api_err(StatusCode::NOT_FOUND, e.to_string()))?;
-            String::from_utf8_lossy(&bytes).into_owned()
-        }
-        None => String::new(),
+/// Receive a snapshot blob pushed by a peer, streamed into the store. The body must
+/// hash to `checksum` and fit `settings.max_file_size`.
+async fn sync_snapshot_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<SnapshotQuery>,
+    body: Body,
+) -> Result<Json<MessageResponse>, ApiError> {
+    let max_file_size = {
+        let guard = state.ctx.read().await;
+        let ctx = guard.as_ref().ok_or_else(not_checked_out)?;
+        let cfg = ctx.config.read().unwrap();
+        cfg.settings.max_file_size
     };
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let checksum = q.checksum.clone();
+    let stream = body.into_data_stream().map_err(std::io::Error::other);
+    let mut reader = SyncIoBridge::new(StreamReader::new(stream));
+    let stored = tokio::task::spawn_blocking(move || {
+        let mut counted = CountingReader {
+            inner: &mut reader,
+            read: 0,
+        };
+        let stored = storage.import_snapshot(&checksum, &mut counted, max_file_size);
+        (stored, counted.read)
+    })
+    .await
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    match stored {
+        (Err(_), read) if read > max_file_size => {
+            return Err(api_err(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "Snapshot exceeds settings.max_file_size ({} bytes)",
+                    max_file_size
+                ),
+            ))
+        }
+        (stored, _) => stored.map_err(|e| api_err(StatusCode::BAD_REQUEST, e.to_string()))?,
+    }
+    Ok(Json(MessageResponse {
+        message: format!("Stored snapshot {}", &q.checksum[..8.min(q.checksum.len())]),
+    }))
+}
 
-    let new_bytes = storage
-        .read_snapshot(&q.to)
-        .map_err(|e| api_err(StatusCode::NOT_FOUND, e.to_string()))?;
-    let new_text = String::from_utf8_lossy(&new_bytes).into_owned();
+/// Largest body `/api/sync/entries` accepts: room for well over the 100,000 entries of
+/// the largest retention profile, while a runaway push cannot exhaust memory.
+const MAX_SYNC_ENTRIES_BYTES: usize = 128 * 1024 * 1024;
 
-    let old_total = old_text.lines().count();
-    let new_total = new_text.lines().count();
+/// Merge history entries pushed by a peer (their snapshots must already be here).
+async fn sync_entries_handler(
+    State(state): State<SharedState>,
+    Json(entries): Json<Vec<HistoryEntry>>,
+) -> Result<Json<SyncEntriesResponse>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let added = tokio::task::spawn_blocking(move || storage.merge_entries(entries))
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok(Json(SyncEntriesResponse { added }))
+}
 
-    // Serialize diff: only one at a time. Permit is held inside the blocking task
-    // so that on timeout the abandoned task keeps it until done; no new diff
-    // can start until that task finishes, preventing runaway CPU from many tasks.
-    let permit = state
-        .diff_semaphore
-        .clone()
-        .try_acquire_owned()
+/// Size of the diff worker pool for `settings.diff_workers` (0 = min(2, cores)).
+fn diff_workers(configured: usize) -> usize {
+    if configured > 0 {
+        return configured;
+    }
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    cores.min(2)
+}
+
+/// Run `job` on the diff worker pool: wait for a free worker and finish within one
+/// `settings.diff_timeout_ms` deadline. The held permit keeps an abandoned (timed-out)
+/// job counted until it finishes, so slow diffs cannot pile up beyond the pool size.
+async fn run_on_diff_pool<T: Send + 'static>(
+    state: &SharedState,
+    job: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, ApiError> {
+    let (workers, limit) = {
+        let guard = state.ctx.read().await;
+        let ctx = guard.as_ref().ok_or_else(not_checked_out)?;
+        let cfg = ctx.config.read().unwrap();
+        (
+            diff_workers(cfg.settings.diff_workers),
+            Duration::from_millis(cfg.settings.diff_timeout_ms),
+        )
+    };
+
+    let deadline = tokio::time::Instant::now() + limit;
+    let permit = timeout_at(deadline, state.diff_semaphore(workers).acquire_owned())
+        .await
         .map_err(|_| {
             api_err(
                 StatusCode::SERVICE_UNAVAILABLE,
-                "Another diff is in progress. Try again in a moment.",
+                format!(
+                    "All {} diff workers are busy. Try again in a moment.",
+                    workers
+                ),
             )
-        })?;
+        })?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    let hunks = match timeout(
-        Duration::from_secs(1),
+    match timeout_at(
+        deadline,
         tokio::task::spawn_blocking(move || {
-            let result = compute_diff_hunks(old_text, new_text);
+            let result = job();
             drop(permit);
             result
         }),
     )
     .await
     {
-        Ok(Ok(h)) => h,
-        Ok(Err(e)) => return Err(api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-        Err(_) => {
-            return Err(api_err(
-                StatusCode::REQUEST_TIMEOUT,
-                "Diff computation timed out (1s limit). File may be too large.",
-            ))
-        }
-    };
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(e)) => Err(api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(_) => Err(api_err(
+            StatusCode::REQUEST_TIMEOUT,
+            format!(
+                "Diff computation timed out ({}ms limit). File may be too large.",
+                limit.as_millis()
+            ),
+        )),
+    }
+}
+
+async fn diff_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<DiffQuery>,
+) -> Result<Json<DiffResponse>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let cache = state.paths_cache().await.ok_or_else(not_checked_out)?;
+
+    let (old, new) = tokio::task::spawn_blocking(move || -> Result<_> {
+        let from = q.from.as_deref().filter(|s| !s.is_empty());
+        // Line counts recorded with the versions spare counting them in large snapshots
+        let (from_lines, to_lines) = {
+            let mut cache = cache.lock().unwrap();
+            storage.refresh_cached_index(&mut cache)?;
+            let recorded = |checksum: &str| {
+                cache
+                    .index
+                    .history
+                    .iter()
+                    .rev()
+                    .find(|e| e.checksum.as_deref() == Some(checksum))
+                    .and_then(|e| e.lines)
+            };
+            (from.and_then(recorded), recorded(&q.to))
+        };
+        let old = match from {
+            Some(from) => {
+                Some(storage.read_snapshot_head(from, MAX_INLINE_DIFF_BYTES, from_lines)?)
+            }
+            None => None,
+        };
+        Ok((
+            old,
+            storage.read_snapshot_head(&q.to, MAX_INLINE_DIFF_BYTES, to_lines)?,
+        ))
+    })
+    .await
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| api_err(StatusCode::NOT_FOUND, e.to_string()))?;
+
+    let truncated = new.truncated || old.as_ref().is_some_and(|o| o.truncated);
+    let old_total = old.as_ref().map_or(0, |o| o.lines);
+    let new_total = new.lines;
+    let old_text = old.map(|o| o.text).unwrap_or_default();
+
+    let hunks = run_on_diff_pool(&state, move || {
+        compute_diff_hunks(old_text, new.text, !truncated)
+    })
+    .await?;
 
     Ok(Json(DiffResponse {
         hunks,
         old_total,
         new_total,
+        truncated,
     }))
 }
 
+/// Full unified diff of two versions as a download, for versions too large for
+/// `/api/diff`. Not bound by the diff time limit.
+async fn patch_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<PatchQuery>,
+) -> Result<Response, ApiError> {
+    use imara_diff::{Algorithm, BasicLineDiffPrinter, Diff, InternedInput, UnifiedDiffConfig};
+
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let file = q.file.unwrap_or_else(|| q.to.chars().take(12).collect());
+    let name = Path::new(&file)
+        .file_name()
+        .map_or_else(|| file.clone(), |n| n.to_string_lossy().into_owned());
+    let patch = tokio::task::spawn_blocking(move || -> Result<String> {
+        let old = match q.from.as_deref().filter(|s| !s.is_empty()) {
+            Some(from) => storage.read_snapshot(from)?,
+            None => Vec::new(),
+        };
+        let new = storage.read_snapshot(&q.to)?;
+        let (old, new) = (String::from_utf8_lossy(&old), String::from_utf8_lossy(&new));
+        let input = InternedInput::new(old.as_ref(), new.as_ref());
+        let mut diff = Diff::compute(Algorithm::Histogram, &input);
+        diff.postprocess_lines(&input);
+        let printer = BasicLineDiffPrinter(&input.interner);
+        let hunks = diff.unified_diff(&printer, UnifiedDiffConfig::default(), &input);
+        Ok(format!("--- a/{}\n+++ b/{}\n{}", file, file, hunks))
+    })
+    .await
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| api_err(StatusCode::NOT_FOUND, e.to_string()))?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/x-diff; charset=utf-8")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.patch\"", name.replace('"', "_")),
+        )
+        .body(Body::from(patch))
+        .unwrap())
+}
+
+/// Whether a path would be tracked and which pattern or setting decided it.
+async fn match_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<MatchQuery>,
+) -> Result<Json<PathMatch>, ApiError> {
+    let guard = state.ctx.read().await;
+    let ctx = guard.as_ref().ok_or_else(not_checked_out)?;
+    let path = ctx.watch_dir.join(&q.path);
+    let outside = || {
+        api_err(
+            StatusCode::BAD_REQUEST,
+            format!("'{}' is not inside {}", q.path, ctx.watch_dir.display()),
+        )
+    };
+    if path
+        .components()
+        .any(|c| c == std::path::Component::ParentDir)
+    {
+        return Err(outside());
+    }
+    if path == ctx.watch_dir || !path.starts_with(&ctx.watch_dir) {
+        return Err(outside());
+    }
+    let cfg = ctx.config.read().unwrap();
+    Ok(Json(cfg.explain_path(&path, &ctx.watch_dir)))
+}
+
+/// History of `file`, or 404 when it has none.
+async fn file_history(storage: Arc<Storage>, file: &str) -> Result<Vec<HistoryEntry>, ApiError> {
+    let path = file.to_string();
+    let entries = tokio::task::spawn_blocking(move || storage.list_history(&path))
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if entries.is_empty() {
+        return Err(api_err(
+            StatusCode::NOT_FOUND,
+            format!("No history for '{}'", file),
+        ));
+    }
+    Ok(entries)
+}
+
+/// Line changes of each version in `entries` (see `timeline::version_pairs`). Pairs
+/// already counted come from the cache; every other diff runs on the diff worker pool
+/// under its own time limit, and one that runs out of time gets none.
+async fn version_deltas(
+    state: &SharedState,
+    storage: Arc<Storage>,
+    entries: &[HistoryEntry],
+) -> Result<Vec<Option<LineDelta>>, ApiError> {
+    let mut deltas = Vec::with_capacity(entries.len());
+    for pair in timeline::version_pairs(entries) {
+        let Some(pair) = pair else {
+            deltas.push(None);
+            continue;
+        };
+        if let Some(delta) = state.line_counts.get(&pair) {
+            deltas.push(delta);
+            continue;
+        }
+        let storage = storage.clone();
+        let shared = state.clone();
+        let delta = run_on_diff_pool(state, move || {
+            let delta = timeline::pair_delta(&storage, &pair);
+            shared.line_counts.insert(pair, delta.clone());
+            delta
+        })
+        .await;
+        match delta {
+            Ok(delta) => deltas.push(delta),
+            Err((StatusCode::REQUEST_TIMEOUT, _)) => deltas.push(None),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(deltas)
+}
+
+/// Every version of a file with line counts and per-version change summaries, for
+/// editor gutters and timelines (see `version_deltas`).
+async fn file_timeline_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<TimelineQuery>,
+) -> Result<Json<FileTimeline>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let storage = Arc::new(storage);
+    let entries = file_history(storage.clone(), &q.path).await?;
+    let deltas = version_deltas(&state, storage, &entries).await?;
+    Ok(Json(timeline::file_timeline(&q.path, entries, deltas)))
+}
+
+/// Added/removed line counts of every version of a file, so a sparkline of change
+/// sizes needs one request instead of a diff per version (see `version_deltas`).
+async fn history_diff_summary_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<HistoryQuery>,
+) -> Result<Json<HistoryDiffSummary>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let storage = Arc::new(storage);
+    let entries = file_history(storage.clone(), &q.file).await?;
+    let deltas = version_deltas(&state, storage, &entries).await?;
+    Ok(Json(timeline::history_diff_summary(
+        &q.file, entries, deltas,
+    )))
+}
+
+/// The API and Web UI of one state: the daemon's own, or one of its projects'.
+fn api_router(state: SharedState) -> Router {
+    Router::new()
+        .route("/api/health", get(health))
+        .route("/api/projects", get(list_projects).post(add_project))
+        .route("/api/version", get(version_handler))
+        .route("/api/checkout", post(checkout))
+        .route("/api/files", get(files))
+        .route("/api/paths", get(paths_handler))
+        .route("/api/history", get(history))
+        .route("/api/activity", get(activity))
+        .route("/api/audit", get(audit_handler))
+        .route("/api/restore", post(restore))
+        .route("/api/restore-dir", post(restore_dir))
+        .route("/api/export-dir", get(export_dir_handler))
+        .route("/api/rm", post(rm_handler))
+        .route("/api/scan", post(scan))
+        .route("/api/bulk-begin", post(bulk_begin_handler))
+        .route("/api/bulk-end", post(bulk_end_handler))
+        .route("/api/pause", post(pause_handler))
+        .route("/api/resume", post(resume_handler))
+        .route("/api/clean", post(clean_handler))
+        .route("/api/unclean", post(unclean_handler))
+        .route("/api/gc", post(gc_handler))
+        .route("/api/orphans", get(orphans_handler))
+        .route("/api/migrate-store", post(migrate_store_handler))
+        .route("/api/maintenance", get(maintenance_handler))
+        .route("/api/config", get(config_get).post(config_set))
+        .route(
+            "/api/config/json",
+            get(config_json_get).put(config_json_put),
+        )
+        .route("/api/stats", get(stats_handler))
+        .route("/api/stats/history", get(stats_history_handler))
+        .route("/api/stats/age", get(age_stats_handler))
+        .route("/api/stats/file", get(file_stats_handler))
+        .route("/api/stats/watcher", get(watcher_stats_handler))
+        .route("/api/dedup", get(dedup_handler))
+        .route("/api/similar", get(similar_handler))
+        .route("/api/signatures", get(signatures_handler))
+        .route("/api/logs", get(logs_handler))
+        .route(
+            "/api/snapshot",
+            get(snapshot_handler).post(upload_snapshot_handler),
+        )
+        .route("/api/share", post(share_handler))
+        .route("/share/{token}", get(shared_snapshot_handler))
+        .route("/api/diff", get(diff_handler))
+        .route("/api/patch", get(patch_handler))
+        .route("/api/file-timeline", get(file_timeline_handler))
+        .route(
+            "/api/history-diff-summary",
+            get(history_diff_summary_handler),
+        )
+        .route("/api/match", get(match_handler))
+        .route("/api/index", get(index_handler))
+        .route("/api/sync/snapshot", post(sync_snapshot_handler))
+        .route(
+            "/api/sync/entries",
+            post(sync_entries_handler).layer(DefaultBodyLimit::max(MAX_SYNC_ENTRIES_BYTES)),
+        )
+        .route("/api/shutdown", post(shutdown_handler))
+        .fallback(static_handler)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            reject_writes_on_mirror,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            route_projects,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+        .with_state(state)
+}
+
+/// Answer 429 to clients sending more than `settings.api_rate_limit` API or share-link
+/// requests per second, so a runaway script or browser tab cannot starve the watcher.
+/// Shutdown is always let through.
+async fn rate_limit(State(state): State<SharedState>, req: Request, next: Next) -> Response {
+    static NO_PEER_WARNING: std::sync::Once = std::sync::Once::new();
+    let path = req.uri().path();
+    let limit = match state.ctx.read().await.as_ref() {
+        Some(c) => c.config.read().unwrap().settings.api_rate_limit,
+        None => 0,
+    };
+    let limited =
+        (path.starts_with("/api/") && path != "/api/shutdown") || path.starts_with("/share/");
+    if limit > 0 && limited {
+        let ip = match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(c) => c.0.ip(),
+            None => {
+                // Without the peer address every client shares one bucket
+                NO_PEER_WARNING.call_once(|| {
+                    warn!("Client addresses are unknown; rate-limiting all clients together")
+                });
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+            }
+        };
+        if let Err(wait) = state.take_request(ip, limit) {
+            let mut resp = api_err(
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "Too many requests (settings.api_rate_limit is {} per second)",
+                    limit
+                ),
+            )
+            .into_response();
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+            resp.headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.into());
+            return resp;
+        }
+    }
+    next.run(req).await
+}
+
+/// Send `/projects/<name>/...` and `?project=<name>` requests to that project's router.
+/// Inside a project, daemon-wide endpoints are refused.
+async fn route_projects(State(state): State<SharedState>, req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    if let Some(ref name) = state.project {
+        if path == "/api/projects" || path == "/api/shutdown" {
+            return api_err(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "'{}' is a project of a shared daemon; send {} to the daemon itself",
+                    name, path
+                ),
+            )
+            .into_response();
+        }
+        return next.run(req).await;
+    }
+
+    let (name, inner_path) = match path.strip_prefix("/projects/") {
+        Some(rest) => match rest.split_once('/') {
+            Some((name, rest)) => (name.to_string(), Some(format!("/{}", rest))),
+            // Relative Web UI assets need the trailing slash
+            None => {
+                return Response::builder()
+                    .status(StatusCode::PERMANENT_REDIRECT)
+                    .header(header::LOCATION, format!("{}/", path))
+                    .body(Body::empty())
+                    .unwrap()
+            }
+        },
+        None => match Query::<ProjectQuery>::try_from_uri(req.uri()) {
+            Ok(Query(ProjectQuery {
+                project: Some(name),
+            })) => (name, None),
+            _ => return next.run(req).await,
+        },
+    };
+    let Some(project) = state.projects.read().await.get(&name).cloned() else {
+        return api_err(
+            StatusCode::NOT_FOUND,
+            format!("No project named '{}'", name),
+        )
+        .into_response();
+    };
+
+    let (mut parts, body) = req.into_parts();
+    if let Some(inner_path) = inner_path {
+        let uri = match parts.uri.query() {
+            Some(q) => format!("{}?{}", inner_path, q),
+            None => inner_path,
+        };
+        parts.uri = uri.parse().unwrap_or_default();
+    }
+    match project
+        .router
+        .oneshot(Request::from_parts(parts, body))
+        .await
+    {
+        Ok(resp) => resp,
+        Err(never) => match never {},
+    }
+}
+
+async fn list_projects(State(state): State<SharedState>) -> Json<Vec<ProjectInfo>> {
+    let projects = state.projects.read().await.clone();
+    let mut list = Vec::with_capacity(projects.len());
+    for (name, project) in projects {
+        let watch_dir = project
+            .state
+            .ctx
+            .read()
+            .await
+            .as_ref()
+            .map(|c| c.watch_dir.to_string_lossy().into_owned());
+        list.push(ProjectInfo { name, watch_dir });
+    }
+    Json(list)
+}
+
+/// Start watching another directory as a project of this daemon.
+async fn add_project(
+    State(state): State<SharedState>,
+    Json(req): Json<AddProjectRequest>,
+) -> Result<Json<AddProjectResponse>, ApiError> {
+    if state.mirror_of.is_some() {
+        return Err(api_err(
+            StatusCode::FORBIDDEN,
+            "A mirror cannot serve projects",
+        ));
+    }
+    let name = match req.name {
+        Some(name) => name,
+        None => Path::new(&req.directory)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(api_err(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Invalid project name '{}': use letters, digits, '-', '_' and '.'",
+                name
+            ),
+        ));
+    }
+
+    // Reserve the name rather than hold the write lock, which would stall every
+    // request to the other projects for the whole checkout
+    {
+        let projects = state.projects.read().await;
+        let mut adding = state.adding_projects.lock().unwrap();
+        if projects.contains_key(&name) || !adding.insert(name.clone()) {
+            return Err(api_err(
+                StatusCode::CONFLICT,
+                format!("Project '{}' already exists", name),
+            ));
+        }
+    }
+    let _reserved = ProjectReservation {
+        state: &state,
+        name: name.clone(),
+    };
+    let project_state = Arc::new(AppState {
+        project: Some(name.clone()),
+        ..AppState::new(state.bind, state.port, None)
+    });
+    let Json(msg) = checkout(
+        State(project_state.clone()),
+        Json(CheckoutRequest {
+            directory: req.directory,
+            force: false,
+        }),
+    )
+    .await?;
+    state.projects.write().await.insert(
+        name.clone(),
+        Project {
+            router: api_router(project_state.clone()),
+            state: project_state,
+        },
+    );
+    info!("Project '{}' added", name);
+    Ok(Json(AddProjectResponse {
+        message: format!("Project '{}': {}", name, msg.message),
+        name,
+    }))
+}
+
+/// A name in `adding_projects`, released when the add finishes, fails or is dropped.
+struct ProjectReservation<'a> {
+    state: &'a AppState,
+    name: String,
+}
+
+impl Drop for ProjectReservation<'_> {
+    fn drop(&mut self) {
+        self.state
+            .adding_projects
+            .lock()
+            .unwrap()
+            .remove(&self.name);
+    }
+}
+
+/// Mirrors only serve reads: reject every other request except shutdown.
+async fn reject_writes_on_mirror(
+    State(state): State<SharedState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if let Some(ref source) = state.mirror_of {
+        if req.method() != Method::GET && req.uri().path() != "/api/shutdown" {
+            return api_err(
+                StatusCode::FORBIDDEN,
+                format!("Read-only mirror of {}", source),
+            )
+            .into_response();
+        }
+    }
+    next.run(req).await
+}
+
+/// Reads that reveal more than browsing history does: server logs, the whole index for
+/// syncing, and the signature report.
+const PRIVATE_READS: &[&str] = &["/api/logs", "/api/index", "/api/signatures"];
+
+/// Requests served on the read-only listener and to clients without the API token.
+fn is_read_only_request(req: &Request) -> bool {
+    let path = req.uri().path();
+    // The same endpoints of a project, under /projects/<name>
+    let path = path
+        .strip_prefix("/projects/")
+        .and_then(|rest| rest.find('/').map(|i| &rest[i..]))
+        .unwrap_or(path);
+    (req.method() == Method::GET || req.method() == Method::HEAD) && !PRIVATE_READS.contains(&path)
+}
+
+/// On a server bound beyond loopback, clients on other machines may only make reads
+/// `is_read_only_request` allows unless they send the API token (see `api_token`).
+async fn require_token_from_remote(
+    State(token): State<Arc<str>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let remote = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .is_none_or(|c| !c.0.ip().to_canonical().is_loopback());
+    if remote && !is_read_only_request(&req) {
+        let given = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        // Hashes compare in constant time, not revealing how much of a guess matched
+        if given.is_none_or(|g| blake3::hash(g.as_bytes()) != blake3::hash(token.as_bytes())) {
+            return api_err(
+                StatusCode::UNAUTHORIZED,
+                "From another machine, changes and the logs, index and signatures need the server's API token in FTM_TOKEN",
+            )
+            .into_response();
+        }
+    }
+    next.run(req).await
+}
+
+/// Refuse everything but reads on the read-only listener (`settings.ui_listen`).
+async fn reject_writes_on_read_only(req: Request, next: Next) -> Response {
+    if !is_read_only_request(&req) {
+        return api_err(
+            StatusCode::FORBIDDEN,
+            "Read-only listener; use the API port on this machine for changes, logs, the index or signatures",
+        )
+        .into_response();
+    }
+    next.run(req).await
+}
+
+/// Bind `addr` and serve the read-only router on it until the returned sender fires.
+fn start_read_only_listener(
+    state: SharedState,
+    addr: &str,
+) -> Result<tokio::sync::oneshot::Sender<()>> {
+    let addr: SocketAddr = addr.parse().context("Invalid address")?;
+    let listener = bind_listener(addr.ip(), addr.port())?;
+    let app = api_router(state).layer(middleware::from_fn(reject_writes_on_read_only));
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(async move {
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        if let Err(e) = axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown)
+            .await
+        {
+            warn!("Read-only listener error: {}", e);
+        }
+    });
+    info!("Read-only Web UI listening on {}", addr);
+    Ok(stop)
+}
+
+/// The directory's `.ftm/verify.json`, kept in memory for `/api/health`.
+fn load_verify_report(ftm_dir: &Path, config: &SharedConfig) -> Arc<StdMutex<VerifyReport>> {
+    let settings = config.read().unwrap().settings.clone();
+    let report = Storage::for_settings(ftm_dir.to_path_buf(), &settings)
+        .verify_report()
+        .unwrap_or_default();
+    Arc::new(StdMutex::new(report))
+}
+
+/// Set up the mirror's storage and keep pulling from the source every
+/// `scan_interval` seconds (re-read each round, like the periodic scanner).
+async fn start_mirror(state: SharedState, opts: MirrorOptions) -> Result<()> {
+    let (ftm_dir, config) = init_ftm_dir(&opts.dir)?;
+    lock_dir(&state, &ftm_dir).map_err(|(_, Json(msg))| anyhow::anyhow!(msg.message))?;
+    let shared_config: SharedConfig = Arc::new(StdRwLock::new(config));
+    {
+        let mut guard = state.ctx.write().await;
+        *guard = Some(WatchContext {
+            watch_dir: opts.dir.clone(),
+            config: shared_config.clone(),
+            watcher: None,
+            paths: Arc::default(),
+            volume: Arc::new(Volume::new(opts.dir.clone())),
+            verify_report: load_verify_report(&ftm_dir, &shared_config),
+        });
+    }
+    let server_info = ServerInfo {
+        host: registry::connect_host(state.bind),
+        port: state.port,
+        pid: std::process::id(),
+        project: None,
+    };
+    if let Err(e) = registry::write_server_info(&ftm_dir, &server_info) {
+        warn!("Failed to write server registry: {}", e);
+    }
+    info!("Mirroring {} into {}", opts.source, opts.dir.display());
+
+    tokio::spawn(async move {
+        loop {
+            let settings = shared_config.read().unwrap().settings.clone();
+            let fd = ftm_dir.clone();
+            let source = opts.source.clone();
+            match tokio::task::spawn_blocking(move || {
+                let storage = Storage::for_settings(fd, &settings);
+                let pulled = mirror::pull_into(&storage, &source, settings.max_file_size)?;
+                storage.record_usage_sample()?;
+                Ok::<_, anyhow::Error>(pulled)
+            })
+            .await
+            {
+                Ok(Ok((added, fetched))) => {
+                    if added > 0 {
+                        info!(
+                            "Mirror pull: {} entries added, {} snapshots fetched",
+                            added, fetched
+                        );
+                    }
+                }
+                Ok(Err(e)) => warn!("Mirror pull error: {:#}", e),
+                Err(e) => warn!("Mirror pull task panic: {}", e),
+            }
+            let interval = shared_config.read().unwrap().settings.scan_interval;
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    });
+    Ok(())
+}
+
 async fn shutdown_handler(State(state): State<SharedState>) -> Json<MessageResponse> {
     info!("Shutdown requested via API");
     state.shutdown.notify_one();
@@ -680,7 +2597,106 @@ async fn shutdown_handler(State(state): State<SharedState>) -> Json<MessageRespo
     })
 }
 
-async fn scan(State(state): State<SharedState>) -> Result<impl IntoResponse, ApiError> {
+/// Run background work (periodic scans, verification) on its own low-priority thread.
+/// A dedicated thread keeps the lowered priority from sticking to a tokio
+/// blocking-pool thread.
+async fn run_low_priority<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, tokio::sync::oneshot::error::RecvError> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        crate::scanner::lower_current_thread_priority();
+        let _ = tx.send(f());
+    });
+    rx.await
+}
+
+/// Log a warning when snapshot usage first exceeds `settings.quota_warn_percent` of
+/// max_quota, and a note once it drops back. Returns whether usage is above it.
+async fn check_quota(ftm_dir: PathBuf, settings: crate::config::Settings, warned: bool) -> bool {
+    let percent = settings.quota_warn_percent;
+    let max_quota = settings.max_quota;
+    let usage = tokio::task::spawn_blocking(move || {
+        Storage::for_settings(ftm_dir, &settings).quota_warning(percent)
+    })
+    .await;
+    match usage {
+        Ok(Ok(Some(quota))) => {
+            if !warned {
+                warn!(
+                    "Snapshot quota {}% full ({} of {} bytes), above settings.quota_warn_percent ({}%)",
+                    u128::from(quota) * 100 / u128::from(max_quota.max(1)),
+                    quota,
+                    max_quota,
+                    percent
+                );
+            }
+            true
+        }
+        Ok(Ok(None)) => {
+            if warned {
+                info!("Snapshot quota back below settings.quota_warn_percent");
+            }
+            false
+        }
+        Ok(Err(e)) => {
+            warn!("Quota check error: {}", e);
+            warned
+        }
+        Err(e) => {
+            warn!("Quota check task panic: {}", e);
+            warned
+        }
+    }
+}
+
+/// Whether a background task should carry on: true once `.ftm` is there, waiting while
+/// its volume is unmounted; false when it was deleted and the server is shutting down.
+async fn ftm_dir_available(volume: &Volume) -> bool {
+    loop {
+        match volume.state() {
+            VolumeState::Present => return true,
+            VolumeState::FtmDeleted => return false,
+            VolumeState::Unmounted => tokio::time::sleep(Duration::from_secs(2)).await,
+        }
+    }
+}
+
+/// How long ago the last full scan recorded in `ftm_dir` completed.
+fn last_scan_age(ftm_dir: &Path, config: &SharedConfig) -> Option<Duration> {
+    let settings = config.read().unwrap().settings.clone();
+    let last_scan = Storage::for_settings(ftm_dir.to_path_buf(), &settings)
+        .dir_state()
+        .ok()?
+        .last_scan?;
+    (chrono::Utc::now() - last_scan.at).to_std().ok()
+}
+
+/// Periodic (background) scan, paced by `settings.scan_throttle`. With `dirs`,
+/// directories unchanged since the previous scan are not read again.
+async fn background_scan(
+    watch_dir: PathBuf,
+    config: Config,
+    ftm_dir: PathBuf,
+    dirs: Option<DirCache>,
+) -> Result<Result<ScanResult>, tokio::sync::oneshot::error::RecvError> {
+    run_low_priority(move || {
+        let storage =
+            Storage::for_settings(ftm_dir, &config.settings).with_source(EntrySource::Scan);
+        let throttle = config.settings.scan_throttle;
+        let mut scanner = Scanner::new(watch_dir, config, storage).throttled(throttle);
+        if let Some(dirs) = dirs {
+            scanner = scanner.with_dir_cache(dirs);
+        }
+        scanner.scan()
+    })
+    .await
+}
+
+async fn scan(
+    State(state): State<SharedState>,
+    Query(q): Query<ScanQuery>,
+) -> Result<impl IntoResponse, ApiError> {
     let (storage, watch_dir) = state.storage().await.ok_or_else(not_checked_out)?;
     let config = {
         let guard = state.ctx.read().await;
@@ -688,13 +2704,49 @@ async fn scan(State(state): State<SharedState>) -> Result<impl IntoResponse, Api
         let cfg = ctx.config.read().unwrap();
         cfg.clone()
     };
-    let scanner = Scanner::new(watch_dir, config, storage);
-    let result = scanner
-        .scan()
-        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let limit = match q.force {
+        Some(true) => 0,
+        _ => config.settings.max_tracked_files,
+    };
+    let storage = storage.with_source(EntrySource::Manual);
+    let mut scanner = Scanner::new(watch_dir, config, storage).with_file_limit(limit);
+    if q.dry_run.unwrap_or(false) {
+        scanner = scanner.dry_run();
+    }
+    if q.details.unwrap_or(false) {
+        scanner = scanner.with_details();
+    }
+    let result = scanner.scan().map_err(scan_err)?;
     Ok(Json(result))
 }
 
+/// 422 when the walk found more files than `settings.max_tracked_files`, else 500.
+fn scan_err(e: anyhow::Error) -> ApiError {
+    let status = if e.is::<TooManyFiles>() {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    api_err(status, e.to_string())
+}
+
+/// Refuse directories where more files than `settings.max_tracked_files` match: the
+/// initial scan and watch registration of such trees can bring the machine to a halt.
+/// Walks `dir` as the scanner does, stopping once past the limit.
+async fn check_tracked_files(dir: &Path, ftm_dir: &Path, config: &Config) -> Result<(), ApiError> {
+    let limit = config.settings.max_tracked_files;
+    if limit == 0 {
+        return Ok(());
+    }
+    let storage = Storage::for_settings(ftm_dir.to_path_buf(), &config.settings);
+    let scanner = Scanner::new(dir.to_path_buf(), config.clone(), storage).with_file_limit(limit);
+    tokio::task::spawn_blocking(move || scanner.tracked_files())
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(scan_err)?;
+    Ok(())
+}
+
 async fn clean_handler(State(state): State<SharedState>) -> Result<Json<CleanResult>, ApiError> {
     let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
     let result = tokio::task::spawn_blocking(move || storage.clean())
@@ -704,6 +2756,53 @@ async fn clean_handler(State(state): State<SharedState>) -> Result<Json<CleanRes
     Ok(Json(result))
 }
 
+async fn gc_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<GcQuery>,
+) -> Result<Json<CleanResult>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let aggressive = q.aggressive.unwrap_or(false);
+    let result = tokio::task::spawn_blocking(move || storage.gc(aggressive))
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(result))
+}
+
+/// Snapshots clean would remove as orphans, listed without removing anything.
+async fn orphans_handler(
+    State(state): State<SharedState>,
+) -> Result<Json<Vec<OrphanSnapshot>>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let orphans = tokio::task::spawn_blocking(move || storage.list_orphans())
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(orphans))
+}
+
+async fn migrate_store_handler(
+    State(state): State<SharedState>,
+) -> Result<Json<MigrateStoreResult>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let result = tokio::task::spawn_blocking(move || storage.migrate_store())
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(result))
+}
+
+async fn unclean_handler(
+    State(state): State<SharedState>,
+) -> Result<Json<UncleanResult>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let result = tokio::task::spawn_blocking(move || storage.unclean())
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(result))
+}
+
 async fn version_handler() -> impl IntoResponse {
     Json(VersionResponse {
         version: env!("CARGO_PKG_VERSION").to_string(),
@@ -730,46 +2829,434 @@ async fn config_get(
 }
 
 async fn stats_handler(State(state): State<SharedState>) -> Result<Json<StatsResponse>, ApiError> {
-    let (max_history, max_quota) = {
+    let (max_history, max_quota, min_free_space, quota_warn_percent) = {
         let guard = state.ctx.read().await;
         let ctx = guard.as_ref().ok_or_else(not_checked_out)?;
         let cfg = ctx.config.read().unwrap();
-        (cfg.settings.max_history, cfg.settings.max_quota)
+        (
+            cfg.settings.max_history,
+            cfg.settings.max_quota,
+            cfg.settings.min_free_space,
+            cfg.settings.quota_warn_percent,
+        )
     };
     let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
-    let (history, quota) = tokio::task::spawn_blocking(move || storage.history_and_quota_stats())
-        .await
-        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let (history, quota, free_space, report, dir_state) = tokio::task::spawn_blocking(move || {
+        let (history, quota) = storage.history_and_quota_stats()?;
+        let report = storage.verify_report()?;
+        let dir_state = storage.dir_state()?;
+        Ok::<_, anyhow::Error>((history, quota, storage.available_space(), report, dir_state))
+    })
+    .await
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     Ok(Json(StatsResponse {
         history,
         max_history,
         quota,
         max_quota,
+        free_space,
+        min_free_space,
+        quota_warn_percent,
+        corrupt_count: report.corrupt.len(),
+        missing_count: report.missing.len(),
+        verified_at: report.last_run,
+        last_scan: dir_state.last_scan,
     }))
 }
 
-async fn config_set(
+async fn dedup_handler(State(state): State<SharedState>) -> Result<Json<DedupStats>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let stats = tokio::task::spawn_blocking(move || storage.dedup_stats())
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(stats))
+}
+
+async fn watcher_stats_handler(
     State(state): State<SharedState>,
-    Json(req): Json<ConfigSetRequest>,
-) -> Result<Json<MessageResponse>, ApiError> {
+) -> Result<Json<WatcherStats>, ApiError> {
     let guard = state.ctx.read().await;
     let ctx = guard.as_ref().ok_or_else(not_checked_out)?;
+    let counters = ctx.watcher.as_ref().ok_or_else(|| {
+        api_err(
+            StatusCode::CONFLICT,
+            "A mirror does not watch files; it has no watcher statistics",
+        )
+    })?;
+    Ok(Json(counters.stats()))
+}
 
-    let mut cfg = ctx.config.write().unwrap();
-    cfg.set_value(&req.key, &req.value)
-        .map_err(|e| api_err(StatusCode::BAD_REQUEST, e.to_string()))?;
+/// Counters (and bulk mode and pause switches) of the file watcher; a mirror has none.
+async fn watcher_counters(state: &AppState) -> Result<Arc<WatcherCounters>, ApiError> {
+    let guard = state.ctx.read().await;
+    let ctx = guard.as_ref().ok_or_else(not_checked_out)?;
+    ctx.watcher
+        .clone()
+        .ok_or_else(|| api_err(StatusCode::CONFLICT, "A mirror does not watch files"))
+}
+
+/// Start bulk mode: until `bulk-end` or the timeout, changes are noted but not
+/// snapshotted, then recorded by one scan.
+async fn bulk_begin_handler(
+    State(state): State<SharedState>,
+    Json(req): Json<BulkBeginRequest>,
+) -> Result<Json<BulkBeginResponse>, ApiError> {
+    let counters = watcher_counters(&state).await?;
+    let until = i64::try_from(req.timeout_secs)
+        .ok()
+        .filter(|&secs| secs > 0)
+        .and_then(chrono::Duration::try_seconds)
+        .and_then(|d| chrono::Utc::now().checked_add_signed(d))
+        .ok_or_else(|| api_err(StatusCode::BAD_REQUEST, "Invalid timeout"))?;
+    counters.begin_bulk(until);
+    info!("Bulk mode on until {}", until);
+    Ok(Json(BulkBeginResponse { until }))
+}
+
+async fn bulk_end_handler(
+    State(state): State<SharedState>,
+) -> Result<Json<MessageResponse>, ApiError> {
+    let counters = watcher_counters(&state).await?;
+    let message = if counters.end_bulk() {
+        info!("Bulk mode ended");
+        "Bulk mode ended; the changes made meanwhile are being recorded".to_string()
+    } else {
+        "Bulk mode was not on".to_string()
+    };
+    Ok(Json(MessageResponse { message }))
+}
+
+/// Pause recording until `resume`: changes are noted but not snapshotted, then
+/// recorded by one scan.
+async fn pause_handler(
+    State(state): State<SharedState>,
+) -> Result<Json<MessageResponse>, ApiError> {
+    let counters = watcher_counters(&state).await?;
+    let message = if counters.pause() {
+        info!("Recording paused");
+        "Recording paused; changes are noted until 'ftm resume'".to_string()
+    } else {
+        "Recording was already paused".to_string()
+    };
+    Ok(Json(MessageResponse { message }))
+}
+
+async fn resume_handler(
+    State(state): State<SharedState>,
+) -> Result<Json<MessageResponse>, ApiError> {
+    let counters = watcher_counters(&state).await?;
+    let message = if counters.resume() {
+        info!("Recording resumed");
+        "Recording resumed; the changes made meanwhile are being recorded".to_string()
+    } else {
+        "Recording was not paused".to_string()
+    };
+    Ok(Json(MessageResponse { message }))
+}
+
+async fn file_stats_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<HistoryQuery>,
+) -> Result<Json<FileStats>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let stats = tokio::task::spawn_blocking(move || storage.file_stats(&q.file))
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(stats))
+}
+
+#[derive(Deserialize)]
+struct SimilarQuery {
+    /// Minimum similarity in percent (1-100). Defaults to 80.
+    threshold: Option<u8>,
+    /// ISO 8601 timestamp; only files created at or after it are checked.
+    since: Option<String>,
+}
+
+async fn similar_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<SimilarQuery>,
+) -> Result<Json<Vec<SimilarFile>>, ApiError> {
+    let threshold = q.threshold.unwrap_or(80);
+    if !(1..=100).contains(&threshold) {
+        return Err(api_err(
+            StatusCode::BAD_REQUEST,
+            format!("threshold must be between 1 and 100, got {}", threshold),
+        ));
+    }
+    let since = match q.since {
+        Some(ref s) => chrono::DateTime::parse_from_rfc3339(s)
+            .map(|dt| dt.with_timezone(&chrono::Utc))
+            .map_err(|e| api_err(StatusCode::BAD_REQUEST, format!("Invalid 'since': {}", e)))?,
+        None => chrono::DateTime::<chrono::Utc>::MIN_UTC,
+    };
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let found = run_on_diff_pool(&state, move || {
+        similarity::find_similar(&storage, threshold, since)
+    })
+    .await?
+    .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(found))
+}
+
+#[derive(Deserialize)]
+struct SignaturesQuery {
+    /// Hex public key to check against; defaults to this server's signing key.
+    public_key: Option<String>,
+}
+
+async fn signatures_handler(
+    State(state): State<SharedState>,
+    Query(q): Query<SignaturesQuery>,
+) -> Result<Json<SignatureReport>, ApiError> {
+    let key = match q.public_key.as_deref() {
+        Some(hex_key) => crate::signing::parse_public_key(hex_key)
+            .map_err(|e| api_err(StatusCode::BAD_REQUEST, e.to_string()))?,
+        None => crate::signing::existing_signing_key()
+            .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .ok_or_else(|| {
+                api_err(
+                    StatusCode::NOT_FOUND,
+                    "No signing key on this machine; pass the public key to check against",
+                )
+            })?
+            .verifying_key(),
+    };
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let report = tokio::task::spawn_blocking(move || storage.verify_signatures(&key))
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(report))
+}
+
+/// Referenced snapshots by the age of their newest version.
+async fn age_stats_handler(
+    State(state): State<SharedState>,
+) -> Result<Json<Vec<AgeBucket>>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let buckets = tokio::task::spawn_blocking(move || storage.age_stats())
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(buckets))
+}
+
+/// Daily usage samples recorded by clean (periodic or manual) and at checkout.
+async fn stats_history_handler(
+    State(state): State<SharedState>,
+) -> Result<Json<Vec<UsageSample>>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let samples = storage
+        .usage_history()
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(samples))
+}
 
-    // Persist to config.yaml
-    let config_path = ctx.watch_dir.join(".ftm").join("config.yaml");
-    cfg.save(&config_path)
+/// The most recent cleans that trimmed history or removed snapshots, oldest first.
+async fn maintenance_handler(
+    State(state): State<SharedState>,
+) -> Result<Json<Vec<MaintenanceRecord>>, ApiError> {
+    let (storage, _) = state.storage().await.ok_or_else(not_checked_out)?;
+    let dir_state = storage
+        .dir_state()
         .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(dir_state.maintenance))
+}
 
+async fn config_set(
+    State(state): State<SharedState>,
+    Json(req): Json<ConfigSetRequest>,
+) -> Result<Json<MessageResponse>, ApiError> {
+    let guard = state.ctx.read().await;
+    let ctx = guard.as_ref().ok_or_else(not_checked_out)?;
+    update_config(ctx, &[req.key.as_str()], |cfg| {
+        cfg.set_value(&req.key, &req.value)
+            .map_err(|e| api_err(StatusCode::BAD_REQUEST, e.to_string()))
+    })
+    .await?;
     Ok(Json(MessageResponse {
         message: format!("Set {} = {}", req.key, req.value),
     }))
 }
 
+/// The whole config as JSON, in the shape of `config.yaml`.
+async fn config_json_get(State(state): State<SharedState>) -> Result<Json<Config>, ApiError> {
+    let guard = state.ctx.read().await;
+    let ctx = guard.as_ref().ok_or_else(not_checked_out)?;
+    let cfg = ctx.config.read().unwrap().clone();
+    Ok(Json(cfg))
+}
+
+/// Replace the whole config. Every key is validated as by `config set`; if any is
+/// invalid nothing changes and the response names each with its error.
+async fn config_json_put(
+    State(state): State<SharedState>,
+    payload: Result<Json<Config>, JsonRejection>,
+) -> Result<Json<Config>, Response> {
+    let Json(next) =
+        payload.map_err(|e| api_err(StatusCode::BAD_REQUEST, e.body_text()).into_response())?;
+    let guard = state.ctx.read().await;
+    let ctx = guard
+        .as_ref()
+        .ok_or_else(|| not_checked_out().into_response())?;
+    let checked = ctx.config.read().unwrap().clone().update_from(&next);
+    if let Err(errors) = checked {
+        let resp = ConfigErrorResponse {
+            message: format!(
+                "Invalid values for {}",
+                errors.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+            errors,
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(resp)).into_response());
+    }
+    update_config(ctx, crate::config::KEYS, |cfg| {
+        cfg.update_from(&next).map_err(|errors| {
+            let first = errors.into_iter().next().unwrap_or_default();
+            api_err(StatusCode::BAD_REQUEST, format!("{}: {}", first.0, first.1))
+        })
+    })
+    .await
+    .map_err(IntoResponse::into_response)?;
+    let cfg = ctx.config.read().unwrap().clone();
+    Ok(Json(cfg))
+}
+
+/// Apply `update` to the config, persist it, and record a change event for each of
+/// `keys` whose value changed. A new snapshot store gets a copy of every snapshot
+/// before it is used.
+async fn update_config(
+    ctx: &WatchContext,
+    keys: &[&str],
+    update: impl Fn(&mut Config) -> Result<(), ApiError>,
+) -> Result<(), ApiError> {
+    let ftm_dir = ctx.watch_dir.join(".ftm");
+    let (old_settings, new_settings) = {
+        let cfg = ctx.config.read().unwrap();
+        let mut next = cfg.clone();
+        update(&mut next)?;
+        (cfg.settings.clone(), next.settings)
+    };
+    let moves_store = old_settings.snapshot_store != new_settings.snapshot_store;
+    let move_snapshots = |leave_old: bool| {
+        let (ftm_dir, from, to) = (ftm_dir.clone(), old_settings.clone(), new_settings.clone());
+        async move {
+            tokio::task::spawn_blocking(move || move_snapshot_store(ftm_dir, &from, &to, leave_old))
+                .await
+                .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        }
+    };
+    if moves_store {
+        let copied = move_snapshots(false).await?;
+        info!("Copied {} snapshots to the new snapshot store", copied);
+    }
+
+    let (storage, changes) = {
+        let mut cfg = ctx.config.write().unwrap();
+        let mut next = cfg.clone();
+        update(&mut next)?;
+
+        // Persist to config.yaml
+        next.save(&ftm_dir.join("config.yaml"))
+            .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+        let changes = config_changes(&cfg, &next, keys);
+        let storage = Storage::for_settings(ftm_dir.clone(), &next.settings);
+        *cfg = next;
+        (storage, changes)
+    };
+    // Recorded once the config lock is released, as recording may wait for a scan
+    tokio::task::spawn_blocking(move || storage.record_events(changes))
+        .await
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| api_err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // Snapshots written to the old store meanwhile are copied too before it is left
+    if moves_store {
+        move_snapshots(true).await?;
+    }
+    Ok(())
+}
+
+/// A change event for each of `keys` whose value differs between `old` and `new`.
+fn config_changes(old: &Config, new: &Config, keys: &[&str]) -> Vec<EventKind> {
+    keys.iter()
+        .filter_map(|key| {
+            let old_value = old.get_value(key).unwrap_or_default();
+            let new_value = new.get_value(key).unwrap_or_default();
+            (new_value != old_value).then(|| EventKind::ConfigChange {
+                key: key.to_string(),
+                old_value,
+                new_value,
+            })
+        })
+        .collect()
+}
+
+/// Take the values of a hand-edited `config.yaml` that differ from the config in
+/// effect, validated as by `config set`. When any is invalid, the errors are logged and
+/// the config in effect is kept. Our own saves reload to no change.
+fn reload_config(ftm_dir: &Path, config: &SharedConfig) -> Result<()> {
+    let (storage, changes) = {
+        let mut cfg = config.write().unwrap();
+        let mut edited = Config::load(&ftm_dir.join("config.yaml"))?;
+        if edited.settings.snapshot_store != cfg.settings.snapshot_store {
+            // Moving the store copies every snapshot first, which only `config set` does
+            warn!(
+                "Ignoring settings.snapshot_store in config.yaml; change it with `ftm config set`"
+            );
+            edited.settings.snapshot_store = cfg.settings.snapshot_store.clone();
+        }
+        let mut next = cfg.clone();
+        if let Err(errors) = next.update_from(&edited) {
+            for (key, error) in errors {
+                warn!("Edited config.yaml not applied: {}: {}", key, error);
+            }
+            return Ok(());
+        }
+        let changes = config_changes(&cfg, &next, crate::config::KEYS);
+        let storage = Storage::for_settings(ftm_dir.to_path_buf(), &next.settings);
+        *cfg = next;
+        (storage, changes)
+    };
+    for change in &changes {
+        if let EventKind::ConfigChange {
+            key,
+            old_value,
+            new_value,
+        } = change
+        {
+            info!(
+                "Reloaded config.yaml: {} {} -> {}",
+                key, old_value, new_value
+            );
+        }
+    }
+    // The new config is in effect either way; only the record of the change can fail
+    storage.record_events(changes)
+}
+
+/// Copy the project's snapshots from the store of `from` into that of `to`; with
+/// `leave_old`, then stop using the old store. Returns the number copied.
+fn move_snapshot_store(
+    ftm_dir: PathBuf,
+    from: &Settings,
+    to: &Settings,
+    leave_old: bool,
+) -> Result<usize> {
+    let old = Storage::for_settings(ftm_dir.clone(), from);
+    let copied = old.copy_snapshots_to(&Storage::for_settings(ftm_dir, to))?;
+    if leave_old {
+        old.abandon_snapshot_store()?;
+    }
+    Ok(copied)
+}
+
 async fn logs_handler(State(state): State<SharedState>) -> Result<Json<LogsResponse>, ApiError> {
     let guard = state.ctx.read().await;
     let ctx = guard.as_ref().ok_or_else(not_checked_out)?;
@@ -836,41 +3323,110 @@ async fn static_handler(uri: axum::http::Uri) -> Response {
     }
 }
 
-pub async fn serve(port: u16) -> Result<()> {
-    let state = Arc::new(AppState::new());
-    let shutdown_state = state.clone();
+/// Bind the server socket. The IPv6 wildcard `::` is made dual-stack so it also
+/// accepts IPv4 connections, regardless of the OS default for IPV6_V6ONLY.
+fn bind_listener(bind: IpAddr, port: u16) -> std::io::Result<tokio::net::TcpListener> {
+    use socket2::{Domain, Socket, Type};
 
-    let app = Router::new()
-        .route("/api/health", get(health))
-        .route("/api/version", get(version_handler))
-        .route("/api/checkout", post(checkout))
-        .route("/api/files", get(files))
-        .route("/api/history", get(history))
-        .route("/api/activity", get(activity))
-        .route("/api/restore", post(restore))
-        .route("/api/scan", post(scan))
-        .route("/api/clean", post(clean_handler))
-        .route("/api/config", get(config_get).post(config_set))
-        .route("/api/stats", get(stats_handler))
-        .route("/api/logs", get(logs_handler))
-        .route("/api/snapshot", get(snapshot_handler))
-        .route("/api/diff", get(diff_handler))
-        .route("/api/shutdown", post(shutdown_handler))
-        .fallback(static_handler)
-        .with_state(state);
+    let addr = SocketAddr::new(bind, port);
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if bind.is_ipv6() && bind.is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    // Same as tokio's TcpListener::bind: allow rebinding a port in TIME_WAIT.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
 
-    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
-        .await
-        .context("Failed to bind server port")?;
+/// Run the server until shutdown. When `register` is set, the bound port is
+/// recorded in that `.ftm` directory's server.json as soon as the listener is up.
+/// With `mirror`, the server serves a read-only copy pulled from another server;
+/// with `resume`, it re-checks out the last watched directory.
+pub async fn serve(
+    port: u16,
+    bind: IpAddr,
+    register: Option<PathBuf>,
+    mirror: Option<MirrorOptions>,
+    resume: bool,
+) -> Result<()> {
+    let listener = bind_listener(bind, port).context("Failed to bind server port")?;
+    // Reachable from other machines: their changes need the API token
+    let token: Option<Arc<str>> = if bind.is_loopback() {
+        None
+    } else {
+        let token = api_token::load_or_create().context("Failed to set up the API token")?;
+        Some(token.into())
+    };
 
     let local_addr = listener.local_addr()?;
     // Print the actual address so tests can parse it when using port 0
     println!("Listening on {}", local_addr);
 
+    let running = ServerInfo {
+        host: registry::connect_host(bind),
+        port: local_addr.port(),
+        pid: std::process::id(),
+        project: None,
+    };
+    if let Err(e) = registry::add_running_server(&running) {
+        warn!("Failed to list server in the user state directory: {}", e);
+    }
+
+    if let Some(ftm_dir) = register {
+        std::fs::create_dir_all(&ftm_dir)?;
+        registry::write_server_info(&ftm_dir, &running)
+            .context("Failed to write server registry")?;
+    }
+
+    let state = Arc::new(AppState::new(
+        bind,
+        local_addr.port(),
+        mirror.as_ref().map(|m| m.source.clone()),
+    ));
+    let shutdown_state = state.clone();
+    if let Some(opts) = mirror {
+        start_mirror(state.clone(), opts)
+            .await
+            .context("Failed to start mirror")?;
+    } else if resume {
+        resume_last_checkout(state.clone()).await;
+    }
+
+    let mut app = api_router(state.clone());
+    if let Some(token) = token {
+        if let Some(path) = api_token::path() {
+            info!(
+                "Changes from other machines need the token in {}",
+                path.display()
+            );
+        }
+        app = app.layer(middleware::from_fn_with_state(
+            token,
+            require_token_from_remote,
+        ));
+    }
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+
     axum::serve(listener, app)
         .with_graceful_shutdown(shutdown_signal(shutdown_state))
         .await?;
 
+    if let Some(ctx) = state.ctx.write().await.take() {
+        registry::remove_server_info(&ctx.watch_dir.join(".ftm"), std::process::id());
+    }
+    for project in state.projects.read().await.values() {
+        if let Some(ctx) = project.state.ctx.write().await.take() {
+            registry::remove_server_info(&ctx.watch_dir.join(".ftm"), std::process::id());
+        }
+        drop(project.state.mdns.lock().unwrap().take());
+    }
+    registry::remove_running_server(std::process::id());
+    drop(state.mdns.lock().unwrap().take());
+
     info!("Server stopped");
     Ok(())
 }
@@ -0,0 +1,82 @@
+//! Share links (`ftm share`): tokens that let anyone holding them read one snapshot
+//! until they expire, without access to the rest of the API. Tokens are signed with a
+//! random per-directory key in `.ftm/share.key`; deleting it revokes every link.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::path::Path;
+
+const KEY_FILE: &str = "share.key";
+
+fn load_key(ftm_dir: &Path) -> Result<Option<[u8; 32]>> {
+    let path = ftm_dir.join(KEY_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let hex_key = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read share key {}", path.display()))?;
+    hex::decode(hex_key.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .map(Some)
+        .with_context(|| format!("Invalid share key {}", path.display()))
+}
+
+fn load_or_create_key(ftm_dir: &Path) -> Result<[u8; 32]> {
+    if let Some(key) = load_key(ftm_dir)? {
+        return Ok(key);
+    }
+    let path = ftm_dir.join(KEY_FILE);
+    let mut key = [0u8; 32];
+    getrandom::fill(&mut key).map_err(|e| anyhow::anyhow!("No randomness for key: {}", e))?;
+    std::fs::write(&path, hex::encode(key))
+        .with_context(|| format!("Failed to write share key {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(key)
+}
+
+fn mac(key: &[u8; 32], checksum: &str, expires: i64) -> blake3::Hash {
+    blake3::keyed_hash(
+        key,
+        format!("ftm-share-v1\n{}\n{}", checksum, expires).as_bytes(),
+    )
+}
+
+/// Token granting read access to snapshot `checksum` until `expires_at`:
+/// `<checksum>.<expiry unix seconds>.<keyed BLAKE3 of both>`.
+pub fn create_token(ftm_dir: &Path, checksum: &str, expires_at: DateTime<Utc>) -> Result<String> {
+    let key = load_or_create_key(ftm_dir)?;
+    let expires = expires_at.timestamp();
+    Ok(format!(
+        "{}.{}.{}",
+        checksum,
+        expires,
+        mac(&key, checksum, expires).to_hex()
+    ))
+}
+
+/// The checksum a token grants access to, if it was signed here and has not expired.
+pub fn check_token(ftm_dir: &Path, token: &str) -> Result<String> {
+    let invalid = || anyhow::anyhow!("Invalid share link");
+    let mut parts = token.split('.');
+    let (Some(checksum), Some(expires), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let expires: i64 = expires.parse().map_err(|_| invalid())?;
+    let signature = blake3::Hash::from_hex(signature).map_err(|_| invalid())?;
+    let key = load_key(ftm_dir)?.ok_or_else(invalid)?;
+    // blake3::Hash compares in constant time
+    if mac(&key, checksum, expires) != signature {
+        return Err(invalid());
+    }
+    if Utc::now().timestamp() >= expires {
+        anyhow::bail!("Share link expired");
+    }
+    Ok(checksum.to_string())
+}
@@ -0,0 +1,147 @@
+//! Optional ed25519 signing of history entries (`settings.sign_history`).
+//! The private key lives in the user state directory, outside `.ftm`, so whoever can
+//! edit a watched directory's index cannot forge entries; `ftm verify-signatures`
+//! checks them against the public key.
+
+use crate::types::{HistoryEntry, Index};
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const KEY_FILE: &str = "signing.key";
+
+static KEY: OnceLock<SigningKey> = OnceLock::new();
+
+/// Location of the private key: `$FTM_SIGNING_KEY`, else `signing.key` in the state dir.
+fn key_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("FTM_SIGNING_KEY") {
+        return Some(PathBuf::from(path));
+    }
+    crate::user_state::state_dir().map(|d| d.join(KEY_FILE))
+}
+
+fn load_or_create() -> Result<SigningKey> {
+    let path = key_path().context("No user state directory for the signing key")?;
+    if !path.exists() {
+        let mut seed = [0u8; 32];
+        getrandom::fill(&mut seed).map_err(|e| anyhow::anyhow!("No randomness for key: {}", e))?;
+        crate::user_state::create_secret(&path, &hex::encode(seed))?;
+    }
+
+    let hex_seed = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read signing key {}", path.display()))?;
+    let seed: [u8; 32] = hex::decode(hex_seed.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .with_context(|| format!("Invalid signing key {}", path.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// The user's signing key, created on first use and cached for the process.
+pub fn signing_key() -> Result<&'static SigningKey> {
+    if let Some(key) = KEY.get() {
+        return Ok(key);
+    }
+    let key = load_or_create()?;
+    Ok(KEY.get_or_init(|| key))
+}
+
+/// The user's signing key if it exists, for reads that must not create it.
+pub fn existing_signing_key() -> Result<Option<&'static SigningKey>> {
+    if KEY.get().is_none() && !key_path().is_some_and(|p| p.exists()) {
+        return Ok(None);
+    }
+    signing_key().map(Some)
+}
+
+pub fn public_key_hex(key: &VerifyingKey) -> String {
+    hex::encode(key.to_bytes())
+}
+
+pub fn parse_public_key(hex_key: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(hex_key.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .context("Public key must be 64 hex characters")?;
+    VerifyingKey::from_bytes(&bytes).context("Invalid public key")
+}
+
+/// Bytes covered by an entry's signature: every recorded field except the signature
+/// itself and the machine-local mtime cache. The user-initiated and trashed markers, the
+/// source and the restored-from time are appended only when set, so entries signed
+/// before they existed still verify. Entries merged from another index are checked
+/// against the seq they were signed with there.
+fn payload(entry: &HistoryEntry) -> String {
+    let mut payload = format!(
+        "ftm-entry-v1\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+        entry.signed_seq.unwrap_or(entry.seq),
+        entry.timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        entry.op,
+        entry.file,
+        entry.checksum.as_deref().unwrap_or_default(),
+        entry.algo,
+        entry.size.map(|s| s.to_string()).unwrap_or_default()
+    );
+    if entry.user_initiated {
+        payload.push_str("\nuser-initiated");
+    }
+    if entry.trashed {
+        payload.push_str("\ntrashed");
+    }
+    if let Some(source) = entry.source {
+        payload.push_str(&format!("\nsource:{}", source));
+    }
+    if let Some(at) = entry.restored_from {
+        payload.push_str(&format!(
+            "\nrestored_from:{}",
+            at.to_rfc3339_opts(SecondsFormat::Nanos, true)
+        ));
+    }
+    payload
+}
+
+pub fn sign_entry(key: &SigningKey, entry: &mut HistoryEntry) {
+    entry.sig = Some(hex::encode(key.sign(payload(entry).as_bytes()).to_bytes()));
+}
+
+fn verify(key: &VerifyingKey, payload: &str, sig: &str) -> bool {
+    hex::decode(sig)
+        .ok()
+        .and_then(|b| <[u8; 64]>::try_from(b).ok())
+        .map(|b| Signature::from_bytes(&b))
+        .is_some_and(|sig| key.verify(payload.as_bytes(), &sig).is_ok())
+}
+
+/// None when the entry is unsigned; otherwise whether its signature is valid.
+pub fn verify_entry(key: &VerifyingKey, entry: &HistoryEntry) -> Option<bool> {
+    let sig = entry.sig.as_deref()?;
+    Some(verify(key, &payload(entry), sig))
+}
+
+/// Bytes covered by the history signature: the signature of every signed entry, in
+/// seq order, so an entry taken out or moved breaks it even though each remaining
+/// entry still verifies on its own.
+fn history_payload(index: &Index) -> String {
+    let mut payload = String::from("ftm-history-v1");
+    for sig in index.history.iter().filter_map(|e| e.sig.as_deref()) {
+        payload.push('\n');
+        payload.push_str(sig);
+    }
+    payload
+}
+
+/// Sign the signed entries of `index` as a whole, after any change to its history.
+pub fn sign_history(key: &SigningKey, index: &mut Index) {
+    index.history_sig = Some(hex::encode(
+        key.sign(history_payload(index).as_bytes()).to_bytes(),
+    ));
+}
+
+/// None when history carries no signature over its entries; otherwise whether every
+/// signed entry is still there, in order.
+pub fn verify_history(key: &VerifyingKey, index: &Index) -> Option<bool> {
+    let sig = index.history_sig.as_deref()?;
+    Some(verify(key, &history_payload(index), sig))
+}
@@ -0,0 +1,152 @@
+//! Copy and rename detection: new files whose first version shares most of its lines
+//! with a version of another tracked file.
+
+use crate::storage::Storage;
+use crate::types::{CopyKind, HistoryEntry, Operation, SimilarFile};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// Versions of other files diffed against each new file, closest in size first.
+const CANDIDATES_PER_FILE: usize = 20;
+/// A source deleted this close to the new file's creation makes the match a rename.
+const RENAME_WINDOW_SECS: i64 = 60;
+/// Newest created files checked per call; older ones need a later `since`.
+const MAX_CHECKED_FILES: usize = 500;
+/// Versions larger than this are neither checked nor compared against.
+const MAX_COMPARED_BYTES: u64 = 1024 * 1024;
+
+/// Share of lines two texts have in common (2 * common / total), using the same
+/// Histogram diff as the Web UI.
+pub fn line_similarity(a: &str, b: &str) -> f64 {
+    use imara_diff::{Algorithm, Diff, InternedInput};
+
+    let input = InternedInput::new(a, b);
+    let total = input.before.len() + input.after.len();
+    if total == 0 {
+        return 1.0;
+    }
+    let diff = Diff::compute(Algorithm::Histogram, &input);
+    let changed = (diff.count_removals() + diff.count_additions()) as usize;
+    1.0 - changed as f64 / total as f64
+}
+
+/// Upper bound of the similarity two texts of these byte sizes can reach, used to skip
+/// diffs that cannot meet the threshold. Line and byte proportions are close enough for
+/// a pre-filter.
+fn size_bound(a: u64, b: u64) -> f64 {
+    if a + b == 0 {
+        return 1.0;
+    }
+    2.0 * a.min(b) as f64 / (a + b) as f64
+}
+
+fn read_text(storage: &Storage, checksum: &str) -> Option<String> {
+    let bytes = storage.read_snapshot(checksum).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Files created at or after `since` (the newest `MAX_CHECKED_FILES` of them) whose
+/// first version is at least `threshold` percent similar to the latest version (before
+/// that point) of another file, deleted files included. Each new file is reported
+/// once, with its best match. Versions over `MAX_COMPARED_BYTES` are skipped.
+pub fn find_similar(
+    storage: &Storage,
+    threshold: u8,
+    since: DateTime<Utc>,
+) -> Result<Vec<SimilarFile>> {
+    let index = storage.load_index()?;
+    let comparable =
+        |e: &HistoryEntry| e.size != Some(0) && e.size.is_none_or(|s| s <= MAX_COMPARED_BYTES);
+    let created: Vec<usize> = index
+        .history
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.op == Operation::Create && e.timestamp >= since && comparable(e))
+        .map(|(i, _)| i)
+        .collect();
+    let checked = &created[created.len().saturating_sub(MAX_CHECKED_FILES)..];
+
+    let mut latest: HashMap<&str, &HistoryEntry> = HashMap::new();
+    let mut found = Vec::new();
+    for (i, entry) in index.history.iter().enumerate() {
+        if checked.binary_search(&i).is_ok() {
+            let versions = latest.values().copied().filter(|c| comparable(c));
+            if let Some(m) = best_match(storage, entry, versions, threshold) {
+                found.push(m);
+            }
+        }
+        if entry.checksum.is_some() {
+            latest.insert(&entry.file, entry);
+        }
+    }
+
+    // A source that disappeared around the time of the copy was most likely renamed
+    let window = chrono::Duration::seconds(RENAME_WINDOW_SECS);
+    for m in &mut found {
+        let renamed = index.history.iter().any(|e| {
+            e.op == Operation::Delete
+                && e.file == m.source_file
+                && (e.timestamp - m.timestamp).abs() <= window
+        });
+        if renamed {
+            m.kind = CopyKind::Rename;
+        }
+    }
+    Ok(found)
+}
+
+fn best_match<'a>(
+    storage: &Storage,
+    entry: &HistoryEntry,
+    versions: impl Iterator<Item = &'a HistoryEntry>,
+    threshold: u8,
+) -> Option<SimilarFile> {
+    let checksum = entry.checksum.as_deref()?;
+    let size = entry.size.unwrap_or(0);
+    let min = f64::from(threshold) / 100.0;
+
+    let mut candidates: Vec<&HistoryEntry> = versions
+        .filter(|c| c.file != entry.file)
+        .filter(|c| size_bound(size, c.size.unwrap_or(0)) >= min)
+        .collect();
+    candidates.sort_by_key(|c| {
+        (
+            c.checksum.as_deref() != Some(checksum),
+            c.size.unwrap_or(0).abs_diff(size),
+        )
+    });
+    candidates.truncate(CANDIDATES_PER_FILE);
+    if candidates.is_empty() {
+        return None;
+    }
+
+    let text = read_text(storage, checksum)?;
+    let mut best: Option<(f64, &HistoryEntry)> = None;
+    for candidate in candidates {
+        let other = candidate.checksum.as_deref()?;
+        let similarity = if other == checksum {
+            1.0
+        } else {
+            match read_text(storage, other) {
+                Some(other_text) => line_similarity(&other_text, &text),
+                None => continue,
+            }
+        };
+        if best.is_none_or(|(s, _)| similarity > s) {
+            best = Some((similarity, candidate));
+        }
+    }
+
+    let (similarity, source) = best?;
+    let percent = (similarity * 100.0).floor() as u8;
+    (percent >= threshold).then(|| SimilarFile {
+        file: entry.file.clone(),
+        checksum: checksum.to_string(),
+        timestamp: entry.timestamp,
+        source_file: source.file.clone(),
+        source_checksum: source.checksum.clone().unwrap_or_default(),
+        similarity: percent,
+        kind: CopyKind::Copy,
+    })
+}
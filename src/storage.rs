@@ -1,22 +1,259 @@
+use crate::content_type::{self, ContentType};
 use crate::path_util;
-use crate::types::{CleanResult, FileTreeNode, HistoryEntry, Index, Operation};
+use crate::share;
+use crate::signing;
+use crate::store::{
+    self, FsIndexStore, FsSnapshotStore, IndexStamp, IndexStore, SnapshotStore, SnapshotWriter,
+};
+use crate::types::{
+    AgeBucket, AuditRecord, CleanResult, ConflictSide, DedupStats, DirState, EntrySource,
+    EventKind, FileStats, FileTreeNode, HashAlgo, HistoryEntry, HistoryVersion, Index,
+    InvalidSignature, LastScan, MaintenanceRecord, MigrateStoreResult, Operation, OrphanSnapshot,
+    RestoreConflict, SignatureReport, TrashedEntry, UncleanResult, UsageSample, VerifyReport,
+    VerifyRound,
+};
+use crate::writers::WriterLog;
+use crate::xattrs;
 use anyhow::{Context, Result};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Local, Utc};
 use sha2::{Digest, Sha256};
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::{Read, Write};
 use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+const MIN_EVENTS_KEPT: usize = 1000;
+/// Temp snapshot files older than this are leftovers of an interrupted write.
+const STALE_TMP_AGE: Duration = Duration::from_secs(3600);
+/// Versions larger than this are described without their text in a restore conflict.
+const CONFLICT_CONTENT_LIMIT: usize = 1024 * 1024;
 
 pub struct Storage {
     ftm_dir: PathBuf,
     max_history: usize,
     max_quota: u64,
+    trash_retention_days: u64,
+    /// Versions of each existing file that trimming keeps regardless of the limits.
+    min_versions_per_file: usize,
+    sign_history: bool,
+    track_empty_files: bool,
+    /// Keep the extended attributes of each recorded version (see `xattrs`).
+    track_xattrs: bool,
+    /// Hash and build entries without writing snapshots (see `dry_run`).
+    dry_run: bool,
+    /// Source of `HistoryEntry::writer` (see `with_writers`), and whether to look for
+    /// processes holding files open for writing.
+    writers: Option<(Arc<WriterLog>, bool)>,
+    /// `HistoryEntry::source` of entries recorded by scans (see `with_source`).
+    source: Option<EntrySource>,
+    snapshots: Arc<dyn SnapshotStore>,
+    index_store: Arc<dyn IndexStore>,
+    /// Algorithm of each snapshot the history references, read on first need.
+    algos: OnceLock<HashMap<String, HashAlgo>>,
 }
 
+#[derive(Default)]
 pub struct IndexView {
     pub(crate) last_by_file: HashMap<String, usize>,
 }
 
+/// Index and view kept across scans by a long-lived worker (the watcher), so a batch
+/// does not re-read the whole index; see `Storage::refresh_cached_index`.
+#[derive(Default)]
+pub struct CachedIndex {
+    pub(crate) index: Index,
+    pub(crate) view: IndexView,
+    /// Saved state `index` matches; None until loaded, or after a failed scan.
+    stamp: Option<IndexStamp>,
+    /// Referenced snapshot bytes of `index`, computed on first use after a reload.
+    quota: Option<u64>,
+}
+
+impl CachedIndex {
+    /// Force a reload on the next refresh, e.g. when `index` was changed but not saved.
+    pub fn invalidate(&mut self) {
+        self.stamp = None;
+    }
+
+    /// Sorted paths starting with `prefix`, at most `limit`; files whose last entry is a
+    /// delete only with `include_deleted`.
+    pub fn paths_with_prefix(
+        &self,
+        prefix: &str,
+        include_deleted: bool,
+        limit: usize,
+    ) -> Vec<String> {
+        let prefix = path_util::normalize_rel_path(prefix);
+        let mut paths: Vec<&String> = self
+            .view
+            .last_by_file
+            .iter()
+            .filter(|(file, &i)| {
+                file.starts_with(&prefix)
+                    && (include_deleted || self.index.history[i].op != Operation::Delete)
+            })
+            .map(|(file, _)| file)
+            .collect();
+        paths.sort_unstable();
+        paths.into_iter().take(limit).cloned().collect()
+    }
+
+    /// Algorithm the history recorded the snapshot `checksum` with, and the MIME type
+    /// of its newest entry that has one.
+    pub fn snapshot_info(&self, checksum: &str) -> (Option<HashAlgo>, Option<String>) {
+        let mut entries = self
+            .index
+            .history
+            .iter()
+            .rev()
+            .filter(|e| e.checksum.as_deref() == Some(checksum));
+        let Some(newest) = entries.next() else {
+            return (None, None);
+        };
+        let mime = newest
+            .mime
+            .clone()
+            .or_else(|| entries.find_map(|e| e.mime.clone()));
+        (Some(newest.algo), mime)
+    }
+}
+
+/// Start of a snapshot's text, from `Storage::read_snapshot_head`.
+pub struct SnapshotHead {
+    pub text: String,
+    /// Lines of the whole snapshot.
+    pub lines: usize,
+    /// `text` is only part of the snapshot.
+    pub truncated: bool,
+}
+
+/// A file copied into the snapshot store by `stream_hash_and_save`.
+struct StreamedFile {
+    /// The copy, kept once committed under `checksum`; None in a dry run.
+    writer: Option<Box<dyn SnapshotWriter>>,
+    /// BLAKE3 checksum.
+    checksum: String,
+    size: u64,
+    sha256: Option<String>,
+    lines: Option<usize>,
+    content_type: ContentType,
+}
+
+/// Files and directories written by `write_versions_atomically`, for rolling back.
+#[derive(Default)]
+struct StagedRestore {
+    /// Temporary files not yet moved into place, with their targets.
+    staged: Vec<(PathBuf, PathBuf)>,
+    /// Targets already moved into place.
+    placed: Vec<PathBuf>,
+    /// Directories created for targets, parents first.
+    created_dirs: Vec<PathBuf>,
+}
+
+impl StagedRestore {
+    /// Create `dir` and any missing ancestors, remembering each one created.
+    fn create_dirs(&mut self, dir: &Path) -> Result<()> {
+        let missing: Vec<&Path> = dir.ancestors().take_while(|d| !d.exists()).collect();
+        for d in missing.into_iter().rev() {
+            std::fs::create_dir(d)?;
+            self.created_dirs.push(d.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn roll_back(self) {
+        for (tmp, _) in &self.staged {
+            std::fs::remove_file(tmp).ok();
+        }
+        for target in &self.placed {
+            std::fs::remove_file(target).ok();
+        }
+        for dir in self.created_dirs.iter().rev() {
+            std::fs::remove_dir(dir).ok();
+        }
+    }
+}
+
+/// Counts lines of content fed in chunks, as `str::lines` would, as long as the content
+/// is valid UTF-8.
+#[derive(Default)]
+struct LineCounter {
+    newlines: usize,
+    last: Option<u8>,
+    /// Trailing bytes of an incomplete UTF-8 sequence, completed by the next chunk.
+    pending: Vec<u8>,
+    binary: bool,
+}
+
+impl LineCounter {
+    fn update(&mut self, chunk: &[u8]) {
+        if self.binary || chunk.is_empty() {
+            return;
+        }
+        self.newlines += chunk.iter().filter(|&&b| b == b'\n').count();
+        self.last = chunk.last().copied();
+        let joined;
+        let bytes = if self.pending.is_empty() {
+            chunk
+        } else {
+            joined = [self.pending.as_slice(), chunk].concat();
+            &joined
+        };
+        match std::str::from_utf8(bytes) {
+            Ok(_) => self.pending.clear(),
+            Err(e) if e.error_len().is_none() => self.pending = bytes[e.valid_up_to()..].to_vec(),
+            Err(_) => self.binary = true,
+        }
+    }
+
+    fn finish(self) -> Option<usize> {
+        if self.binary || !self.pending.is_empty() {
+            return None;
+        }
+        Some(self.newlines + usize::from(self.last.is_some_and(|b| b != b'\n')))
+    }
+}
+
+/// What orphan removal deleted from the snapshot store.
+#[derive(Default)]
+struct OrphanCleanup {
+    files_removed: usize,
+    bytes_removed: u64,
+    tmp_removed: usize,
+    dirs_removed: usize,
+}
+
+/// Drop versions repeating the checksum of the previous version of their file, which
+/// races can record (a restore followed by the watcher seeing the same write). The first
+/// of a run is kept, taking the mtime of the last so fast skips still match the file.
+/// Restores and the first version after a delete are never dropped. Returns the number
+/// dropped.
+fn collapse_repeated_versions(index: &mut Index) -> usize {
+    // Position in the kept history of each file's latest version
+    let mut latest: HashMap<String, usize> = HashMap::new();
+    let mut kept: Vec<HistoryEntry> = Vec::with_capacity(index.history.len());
+    let mut collapsed = 0;
+    for entry in std::mem::take(&mut index.history) {
+        let repeats = entry.checksum.is_some()
+            && entry.restored_from.is_none()
+            && matches!(entry.op, Operation::Create | Operation::Modify)
+            && latest.get(&entry.file).is_some_and(|&i| {
+                kept[i].op != Operation::Delete && kept[i].checksum == entry.checksum
+            });
+        if repeats {
+            let first = &mut kept[latest[&entry.file]];
+            first.mtime_nanos = entry.mtime_nanos.or(first.mtime_nanos);
+            collapsed += 1;
+            continue;
+        }
+        latest.insert(entry.file.clone(), kept.len());
+        kept.push(entry);
+    }
+    index.history = kept;
+    collapsed
+}
+
 enum BuildNode {
     File(usize),
     Dir(BTreeMap<String, BuildNode>),
@@ -54,80 +291,570 @@ impl IndexView {
     }
 }
 
+/// Incremental check of snapshot content against its checksum, under the algorithm the
+/// history recorded it with, or under both when that is not known.
+pub struct ChecksumVerifier {
+    blake3: Option<blake3::Hasher>,
+    sha256: Option<Sha256>,
+}
+
+impl ChecksumVerifier {
+    pub fn new(algo: Option<HashAlgo>) -> Self {
+        Self {
+            blake3: (algo != Some(HashAlgo::Sha256)).then(blake3::Hasher::new),
+            sha256: (algo != Some(HashAlgo::Blake3)).then(Sha256::new),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        if let Some(ref mut hasher) = self.blake3 {
+            hasher.update(data);
+        }
+        if let Some(ref mut hasher) = self.sha256 {
+            hasher.update(data);
+        }
+    }
+
+    pub fn matches(self, checksum: &str) -> bool {
+        self.blake3
+            .is_some_and(|h| h.finalize().to_hex().as_str() == checksum)
+            || self
+                .sha256
+                .is_some_and(|h| hex::encode(h.finalize()) == checksum)
+    }
+}
+
+impl Write for ChecksumVerifier {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 impl Storage {
-    pub fn new(ftm_dir: PathBuf, max_history: usize, max_quota: u64) -> Self {
+    pub fn new(
+        ftm_dir: PathBuf,
+        max_history: usize,
+        max_quota: u64,
+        trash_retention_days: u64,
+    ) -> Self {
         Self {
+            snapshots: Arc::new(FsSnapshotStore::new(&ftm_dir)),
+            index_store: Arc::new(FsIndexStore::new(&ftm_dir)),
             ftm_dir,
             max_history,
             max_quota,
+            trash_retention_days,
+            min_versions_per_file: 0,
+            sign_history: false,
+            track_empty_files: false,
+            track_xattrs: false,
+            dry_run: false,
+            writers: None,
+            source: None,
+            algos: OnceLock::new(),
         }
     }
 
     /// Build from current settings (single source for ftm_dir + config).
     pub fn for_settings(ftm_dir: PathBuf, settings: &crate::config::Settings) -> Self {
-        Self::new(ftm_dir, settings.max_history, settings.max_quota)
+        let storage = Self {
+            sign_history: settings.sign_history,
+            track_empty_files: settings.track_empty_files,
+            track_xattrs: settings.track_xattrs,
+            min_versions_per_file: settings.min_versions_per_file,
+            ..Self::new(
+                ftm_dir,
+                settings.max_history,
+                settings.max_quota,
+                settings.trash_retention_days,
+            )
+        };
+        let snapshots = if settings.snapshot_store.is_empty() {
+            FsSnapshotStore::new(&storage.ftm_dir)
+        } else {
+            FsSnapshotStore::shared(Path::new(&settings.snapshot_store), &storage.ftm_dir)
+        };
+        Self {
+            snapshots: Arc::new(snapshots.with_shard_depth(settings.shard_depth)),
+            ..storage
+        }
+    }
+
+    /// Compute entries as usual but write nothing to the store: snapshots are hashed
+    /// without being copied and entries are not signed. The caller must not save the
+    /// index it passes in.
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Keep snapshots and the index in other backends than the `.ftm/` layout. Usage
+    /// stats, verification and trash bookkeeping stay in `.ftm/`.
+    #[allow(dead_code)]
+    pub fn with_stores(
+        mut self,
+        snapshots: Arc<dyn SnapshotStore>,
+        index_store: Arc<dyn IndexStore>,
+    ) -> Self {
+        self.snapshots = snapshots;
+        self.index_store = index_store;
+        self
+    }
+
+    /// Record which process wrote each new version, as noted by `writers`; with
+    /// `look_up_open` also a process still writing it (see `WriterLog::writer_of`).
+    pub fn with_writers(mut self, writers: Arc<WriterLog>, look_up_open: bool) -> Self {
+        self.writers = Some((writers, look_up_open));
+        self
+    }
+
+    /// Mark entries recorded through this storage (by a scan) as coming from `source`.
+    pub fn with_source(mut self, source: EntrySource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Writer of the version of `file_path` being recorded; a dry run leaves the noted
+    /// writes for the real scan.
+    fn writer_of(&self, file_path: &Path) -> Option<String> {
+        match &self.writers {
+            Some((writers, look_up_open)) if !self.dry_run => {
+                writers.writer_of(file_path, *look_up_open)
+            }
+            _ => None,
+        }
+    }
+
+    /// Append a newly recorded entry (signing it when `sign_history` is on); returns
+    /// its position in history.
+    fn push_entry(&self, index: &mut Index, entry: HistoryEntry) -> Result<usize> {
+        let pos = index.push(entry);
+        if self.sign_history && !self.dry_run {
+            signing::sign_entry(signing::signing_key()?, &mut index.history[pos]);
+        }
+        Ok(pos)
+    }
+
+    /// Append an event to the index, keeping the newest `max_history` events (at least
+    /// `MIN_EVENTS_KEPT`, so a small history limit does not erase the audit trail).
+    fn push_event(&self, index: &mut Index, kind: EventKind) {
+        index.push_event(kind);
+        let keep = self.max_history.max(MIN_EVENTS_KEPT);
+        let excess = index.events.len().saturating_sub(keep);
+        index.events.drain(..excess);
+    }
+
+    /// Record an event in the saved index.
+    pub fn record_event(&self, kind: EventKind) -> Result<()> {
+        self.record_events(vec![kind])
+    }
+
+    /// Record `kinds` in the saved index with one save, never in the middle of a
+    /// watcher scan (see `with_index_locked`).
+    pub fn record_events(&self, kinds: Vec<EventKind>) -> Result<()> {
+        if kinds.is_empty() {
+            return Ok(());
+        }
+        self.with_index_locked(|| {
+            let mut index = self.load_index()?;
+            for kind in kinds {
+                self.push_event(&mut index, kind);
+            }
+            self.save_index(&mut index)
+        })
+    }
+
+    /// Run `f` while no other thread of this process holds the index of this directory
+    /// through this call, so a scan on a cached index and an event recorded meanwhile
+    /// do not overwrite each other's save.
+    pub fn with_index_locked<T>(&self, f: impl FnOnce() -> T) -> T {
+        static LOCKS: OnceLock<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>> = OnceLock::new();
+        let lock = LOCKS
+            .get_or_init(Mutex::default)
+            .lock()
+            .unwrap()
+            .entry(self.ftm_dir.clone())
+            .or_default()
+            .clone();
+        let _held = lock.lock().unwrap_or_else(|e| e.into_inner());
+        f()
+    }
+
+    /// Every history entry and event recorded at or after `since`, in seq order.
+    /// The machine-local mtime cache is left out.
+    pub fn audit(&self, since: DateTime<Utc>, op: Option<&str>) -> Result<Vec<AuditRecord>> {
+        let index = self.load_index()?;
+        let entries = index
+            .history
+            .into_iter()
+            .filter(|e| e.timestamp >= since)
+            .map(|mut e| {
+                e.mtime_nanos = None;
+                AuditRecord::Entry(e)
+            });
+        let events = index
+            .events
+            .into_iter()
+            .filter(|e| e.timestamp >= since)
+            .map(AuditRecord::Event);
+        let mut records: Vec<AuditRecord> = entries
+            .chain(events)
+            .filter(|r| op.is_none_or(|op| r.op() == op))
+            .collect();
+        records.sort_by_key(AuditRecord::seq);
+        Ok(records)
+    }
+
+    fn usage_history_path(&self) -> PathBuf {
+        self.ftm_dir.join("stats.json")
     }
 
-    fn index_path(&self) -> PathBuf {
-        self.ftm_dir.join("index.json")
+    fn verify_report_path(&self) -> PathBuf {
+        self.ftm_dir.join("verify.json")
     }
 
-    fn snapshots_dir(&self) -> PathBuf {
-        self.ftm_dir.join("snapshots")
+    fn dir_state_path(&self) -> PathBuf {
+        self.ftm_dir.join("state.json")
     }
 
-    /// Get snapshot path using two-level directory structure: {checksum[0]}/{checksum[1]}/{checksum}
-    fn snapshot_path(&self, checksum: &str) -> PathBuf {
-        let c1 = &checksum[0..1];
-        let c2 = &checksum[1..2];
-        self.snapshots_dir().join(c1).join(c2).join(checksum)
+    fn trash_entries_path(&self) -> PathBuf {
+        self.ftm_dir.join("trash").join("entries.jsonl")
     }
 
     pub fn load_index(&self) -> Result<Index> {
-        let path = self.index_path();
-        if path.exists() {
-            let content = std::fs::read_to_string(&path)?;
-            Ok(serde_json::from_str(&content)?)
+        let mut index = self.index_store.load()?;
+        index.normalize_seq();
+        Ok(index)
+    }
+
+    /// Save `index`, signing its history as a whole while `sign_history` is on (see
+    /// `signing::sign_history`); with it off, a history signature that would go stale
+    /// is dropped.
+    pub fn save_index(&self, index: &mut Index) -> Result<()> {
+        if self.sign_history {
+            signing::sign_history(signing::signing_key()?, index);
         } else {
-            Ok(Index::default())
+            index.history_sig = None;
         }
+        self.index_store.save(index)
     }
 
-    pub fn save_index(&self, index: &Index) -> Result<()> {
-        let content = serde_json::to_string(index)?;
-        std::fs::write(self.index_path(), content)?;
+    /// Reload `cache` unless the saved index is unchanged (same mtime and size) since it
+    /// was loaded or saved through it.
+    pub fn refresh_cached_index(&self, cache: &mut CachedIndex) -> Result<()> {
+        let stamp = self.index_store.stamp();
+        if stamp.is_some() && stamp == cache.stamp {
+            return Ok(());
+        }
+        cache.index = self.load_index()?;
+        cache.view.rebuild(&cache.index);
+        cache.stamp = stamp;
+        cache.quota = None;
         Ok(())
     }
 
+    pub fn save_cached_index(&self, cache: &mut CachedIndex) -> Result<()> {
+        let result = self.save_index(&mut cache.index);
+        cache.quota = None;
+        cache.stamp = match result {
+            Ok(()) => self.index_store.stamp(),
+            Err(_) => None,
+        };
+        result
+    }
+
     pub fn build_index_view(&self, index: &Index) -> IndexView {
         IndexView::from_index(index)
     }
 
-    /// Read-only stats: (history entry count, total bytes of referenced snapshots).
-    /// Each checksum is counted once for volume (deduplicated).
+    /// Read-only stats: (history entry count, bytes counted against max_quota).
     pub fn history_and_quota_stats(&self) -> Result<(usize, u64)> {
         let index = self.load_index()?;
-        let n = index.history.len();
-        let mut checksum_size: HashMap<String, u64> = HashMap::new();
+        Ok((index.history.len(), self.quota_bytes(&index)))
+    }
+
+    /// Bytes counted against max_quota: the snapshots `index` references, each checksum
+    /// once, and those waiting in the trash, which still take up the disk.
+    fn quota_bytes(&self, index: &Index) -> u64 {
+        self.referenced_bytes(index) + self.trash_bytes()
+    }
+
+    fn trash_bytes(&self) -> u64 {
+        self.snapshots
+            .trashed()
+            .map(|trashed| trashed.iter().map(|(_, size)| size).sum())
+            .unwrap_or(0)
+    }
+
+    /// Bytes of the snapshots `index` references, each checksum counted once.
+    fn referenced_bytes(&self, index: &Index) -> u64 {
+        let mut checksum_size: HashMap<&str, u64> = HashMap::new();
         for entry in &index.history {
             if let Some(ref c) = entry.checksum {
-                checksum_size.entry(c.clone()).or_insert_with(|| {
-                    entry.size.unwrap_or_else(|| {
-                        std::fs::metadata(self.snapshot_path(c))
-                            .map(|m| m.len())
-                            .unwrap_or(0)
-                    })
+                checksum_size.entry(c).or_insert_with(|| {
+                    entry
+                        .size
+                        .unwrap_or_else(|| self.snapshots.size(c).unwrap_or(0))
                 });
             }
         }
-        let total_volume: u64 = checksum_size.values().sum();
-        Ok((n, total_volume))
+        checksum_size.values().sum()
+    }
+
+    /// Logical vs physical snapshot bytes over the current history.
+    pub fn dedup_stats(&self) -> Result<DedupStats> {
+        let index = self.load_index()?;
+        let mut physical: HashMap<&str, u64> = HashMap::new();
+        let mut references = 0usize;
+        let mut logical_bytes = 0u64;
+        for entry in &index.history {
+            let Some(c) = entry.checksum.as_deref() else {
+                continue;
+            };
+            let size = *physical
+                .entry(c)
+                .or_insert_with(|| self.snapshots.size(c).unwrap_or(0));
+            references += 1;
+            logical_bytes += entry.size.unwrap_or(size);
+        }
+        Ok(DedupStats {
+            references,
+            snapshots: physical.len(),
+            logical_bytes,
+            physical_bytes: physical.values().sum(),
+            duplicate_references: references - physical.len(),
+        })
+    }
+
+    /// Referenced snapshots (count and bytes) by the age of their newest version, from
+    /// today to older than a month.
+    pub fn age_stats(&self) -> Result<Vec<AgeBucket>> {
+        let mut newest: HashMap<String, (DateTime<Utc>, Option<u64>)> = HashMap::new();
+        self.for_each_entry(|entry| {
+            if let Some(c) = entry.checksum {
+                let seen = newest.entry(c).or_insert((entry.timestamp, entry.size));
+                seen.0 = seen.0.max(entry.timestamp);
+            }
+        })?;
+
+        let now = Utc::now();
+        let midnight = Local::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .and_then(|t| t.and_local_timezone(Local).earliest())
+            .map_or(now, |t| t.with_timezone(&Utc));
+        let bounds = [
+            ("today", midnight),
+            ("this_week", now - chrono::Duration::days(7)),
+            ("this_month", now - chrono::Duration::days(30)),
+            ("older", DateTime::<Utc>::MIN_UTC),
+        ];
+        let mut buckets: Vec<AgeBucket> = bounds
+            .iter()
+            .map(|(age, _)| AgeBucket {
+                age: age.to_string(),
+                snapshots: 0,
+                bytes: 0,
+            })
+            .collect();
+        for (checksum, (at, size)) in newest {
+            let i = bounds
+                .iter()
+                .position(|(_, since)| at >= *since)
+                .unwrap_or(bounds.len() - 1);
+            buckets[i].snapshots += 1;
+            buckets[i].bytes += size.unwrap_or_else(|| self.snapshots.size(&checksum).unwrap_or(0));
+        }
+        Ok(buckets)
+    }
+
+    /// Version count, snapshot bytes and timing of one file's history.
+    pub fn file_stats(&self, file_path: &str) -> Result<FileStats> {
+        let index = self.load_index()?;
+        let file = path_util::normalize_rel_path(file_path);
+        let entries: Vec<&HistoryEntry> = index.history.iter().filter(|e| e.file == file).collect();
+        let own: HashSet<&str> = entries
+            .iter()
+            .filter_map(|e| e.checksum.as_deref())
+            .collect();
+        let shared: HashSet<&str> = index
+            .history
+            .iter()
+            .filter(|e| e.file != file)
+            .filter_map(|e| e.checksum.as_deref())
+            .filter(|c| own.contains(c))
+            .collect();
+        let size_of = |c: &str| self.snapshots.size(c).unwrap_or(0);
+        let oldest = entries.first().map(|e| e.timestamp);
+        let newest = entries.last().map(|e| e.timestamp);
+        let avg_interval_secs = oldest.zip(newest).and_then(|(first, last)| {
+            let gaps = entries.len().checked_sub(1).filter(|&n| n > 0)?;
+            Some((last - first).num_milliseconds() as f64 / 1000.0 / gaps as f64)
+        });
+        Ok(FileStats {
+            file: file.clone(),
+            versions: entries.len(),
+            snapshots: own.len(),
+            snapshot_bytes: own.iter().map(|c| size_of(c)).sum(),
+            shared_bytes: shared.iter().map(|c| size_of(c)).sum(),
+            oldest,
+            newest,
+            avg_interval_secs,
+            trimmed_versions: self
+                .dir_state()
+                .unwrap_or_default()
+                .trimmed
+                .get(&file)
+                .copied()
+                .unwrap_or(0),
+        })
+    }
+
+    /// Free bytes available on the volume holding `.ftm`, if it can be determined.
+    pub fn available_space(&self) -> Option<u64> {
+        fs4::available_space(&self.ftm_dir).ok()
+    }
+
+    /// Available space when it is below `min_free_space` (0 disables the check).
+    pub fn low_space(&self, min_free_space: u64) -> Option<u64> {
+        if min_free_space == 0 {
+            return None;
+        }
+        self.available_space().filter(|&free| free < min_free_space)
+    }
+
+    /// Referenced snapshot bytes when they exceed `percent` of max_quota (0 disables).
+    pub fn quota_warning(&self, percent: u64) -> Result<Option<u64>> {
+        if percent == 0 {
+            return Ok(None);
+        }
+        let (_, quota) = self.history_and_quota_stats()?;
+        Ok(self.over_quota_percent(quota, percent).then_some(quota))
+    }
+
+    /// `quota_warning` through `cache`, so the index is only read again after it changed.
+    pub fn cached_quota_warning(
+        &self,
+        cache: &mut CachedIndex,
+        percent: u64,
+    ) -> Result<Option<u64>> {
+        if percent == 0 {
+            return Ok(None);
+        }
+        self.refresh_cached_index(cache)?;
+        let quota = match cache.quota {
+            Some(quota) => quota,
+            None => *cache.quota.insert(self.quota_bytes(&cache.index)),
+        };
+        Ok(self.over_quota_percent(quota, percent).then_some(quota))
+    }
+
+    fn over_quota_percent(&self, quota: u64, percent: u64) -> bool {
+        u128::from(quota) * 100 > u128::from(self.max_quota) * u128::from(percent)
+    }
+
+    /// Check every entry's signature against `key`.
+    pub fn verify_signatures(&self, key: &ed25519_dalek::VerifyingKey) -> Result<SignatureReport> {
+        let index = self.load_index()?;
+        let mut report = SignatureReport {
+            public_key: signing::public_key_hex(key),
+            valid: 0,
+            unsigned: 0,
+            invalid: Vec::new(),
+            complete: signing::verify_history(key, &index),
+        };
+        for entry in &index.history {
+            match signing::verify_entry(key, entry) {
+                None => report.unsigned += 1,
+                Some(true) => report.valid += 1,
+                Some(false) => report.invalid.push(InvalidSignature {
+                    seq: entry.seq,
+                    file: entry.file.clone(),
+                }),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Recorded usage samples, oldest first.
+    pub fn usage_history(&self) -> Result<Vec<UsageSample>> {
+        let path = self.usage_history_path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Record current usage: one sample per UTC day (today's is updated in place),
+    /// keeping the most recent `MAX_USAGE_SAMPLES` days.
+    pub fn record_usage_sample(&self) -> Result<UsageSample> {
+        const MAX_USAGE_SAMPLES: usize = 365;
+        let (history, quota) = self.history_and_quota_stats()?;
+        let sample = UsageSample {
+            at: Utc::now(),
+            history,
+            quota,
+        };
+        let mut samples = self.usage_history()?;
+        if samples
+            .last()
+            .is_some_and(|last| last.at.date_naive() == sample.at.date_naive())
+        {
+            samples.pop();
+        }
+        samples.push(sample.clone());
+        if samples.len() > MAX_USAGE_SAMPLES {
+            samples.drain(..samples.len() - MAX_USAGE_SAMPLES);
+        }
+        std::fs::write(self.usage_history_path(), serde_json::to_string(&samples)?)?;
+        Ok(sample)
+    }
+
+    pub fn compute_checksum(algo: HashAlgo, content: &[u8]) -> String {
+        match algo {
+            HashAlgo::Sha256 => hex::encode(Sha256::digest(content)),
+            HashAlgo::Blake3 => blake3::hash(content).to_hex().to_string(),
+        }
     }
 
-    pub fn compute_checksum(content: &[u8]) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(content);
-        hex::encode(hasher.finalize())
+    /// Share link token for the version of `file_path` whose checksum starts with
+    /// `checksum_prefix`, valid until `expires_at` (see `share`). Fails when the prefix
+    /// matches more than one version.
+    pub fn share_version(
+        &self,
+        file_path: &str,
+        checksum_prefix: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String> {
+        let index = self.load_index()?;
+        let file_path_norm = path_util::normalize_rel_path(file_path);
+        let matching: BTreeSet<&str> = index
+            .history
+            .iter()
+            .filter(|e| path_util::normalize_rel_path(&e.file) == file_path_norm)
+            .filter_map(|e| e.checksum.as_deref())
+            .filter(|c| c.starts_with(checksum_prefix))
+            .collect();
+        // A link outlives the command, so it must name exactly the version meant
+        let mut matching = matching.into_iter();
+        let checksum = matching.next().context("Version not found in history")?;
+        if matching.next().is_some() {
+            anyhow::bail!(
+                "Checksum prefix {} matches several versions; give more characters",
+                checksum_prefix
+            );
+        }
+        share::create_token(&self.ftm_dir, checksum, expires_at)
+    }
+
+    /// Checksum of the snapshot a share link token grants access to.
+    pub fn shared_snapshot(&self, token: &str) -> Result<String> {
+        share::check_token(&self.ftm_dir, token)
     }
 
     /// Get the last entry for a specific file (any operation type)
@@ -139,39 +866,92 @@ impl Storage {
         index.history.iter().rev().find(|e| e.file == file)
     }
 
-    /// Stream file: read in chunks, hash and write to temp in one pass, then rename to snapshot path.
-    /// Returns (checksum, size), or None if the file was modified during read.
-    /// Caller must remove temp on same-checksum early return.
+    /// Stream file: read in chunks, hash, count lines and write to a new snapshot in one
+    /// pass. Returns None if the file was modified during read. The SHA-256 (when
+    /// `also_sha256`) lets callers compare against entries recorded before BLAKE3. The
+    /// snapshot is discarded unless the caller commits it.
     fn stream_hash_and_save(
         &self,
         file_path: &Path,
-        tmp_path: &Path,
-    ) -> Result<Option<(String, u64)>> {
-        const BUF_SIZE: usize = 65536;
+        also_sha256: bool,
+    ) -> Result<Option<StreamedFile>> {
         let mut reader = std::fs::File::open(file_path).context("Failed to read file")?;
-        let mut tmp_file = std::fs::File::create(tmp_path)?;
-        let mut hasher = Sha256::new();
+        let streamed = self.stream_to_snapshot(&mut reader, file_path, also_sha256, u64::MAX)?;
+
+        // Verify the file was not modified during our read.
+        // If the current on-disk size differs from what we read, another write
+        // has started (truncate + partial write), so discard this snapshot.
+        let current_size = std::fs::metadata(file_path).map(|m| m.len()).unwrap_or(0);
+        if current_size != streamed.size {
+            return Ok(None);
+        }
+        Ok(Some(streamed))
+    }
+
+    /// Copy `reader` into a new snapshot while hashing it and counting its lines; the
+    /// content type is detected as for a file at `path`. Fails once more than
+    /// `max_size` bytes were read, discarding what was written.
+    fn stream_to_snapshot(
+        &self,
+        reader: &mut dyn Read,
+        path: &Path,
+        also_sha256: bool,
+        max_size: u64,
+    ) -> Result<StreamedFile> {
+        const BUF_SIZE: usize = 65536;
+        let mut writer = if self.dry_run {
+            None
+        } else {
+            Some(self.snapshots.create()?)
+        };
+        let mut sink = std::io::sink();
+        let out: &mut dyn Write = match writer.as_mut() {
+            Some(w) => w,
+            None => &mut sink,
+        };
+        let mut size = 0u64;
+        let mut head = Vec::with_capacity(content_type::SNIFF_LEN);
+        let mut hasher = blake3::Hasher::new();
+        let mut sha256 = also_sha256.then(Sha256::new);
+        let mut lines = LineCounter::default();
         let mut buf = [0u8; BUF_SIZE];
         loop {
             let n = reader.read(&mut buf)?;
             if n == 0 {
                 break;
             }
+            size += n as u64;
+            if size > max_size {
+                anyhow::bail!("Content exceeds {} bytes", max_size);
+            }
             hasher.update(&buf[..n]);
-            tmp_file.write_all(&buf[..n])?;
-        }
-        let checksum = hex::encode(hasher.finalize());
-        let size = std::fs::metadata(tmp_path)?.len();
-
-        // Verify the file was not modified during our read.
-        // If the current on-disk size differs from what we read, another write
-        // has started (truncate + partial write), so discard this snapshot.
-        let current_size = std::fs::metadata(file_path).map(|m| m.len()).unwrap_or(0);
-        if current_size != size {
-            return Ok(None);
+            if let Some(ref mut h) = sha256 {
+                h.update(&buf[..n]);
+            }
+            lines.update(&buf[..n]);
+            let sniff = n.min(content_type::SNIFF_LEN - head.len());
+            head.extend_from_slice(&buf[..sniff]);
+            out.write_all(&buf[..n])?;
         }
+        out.flush()?;
+
+        let lines = lines.finish();
+        Ok(StreamedFile {
+            writer,
+            checksum: hasher.finalize().to_hex().to_string(),
+            size,
+            sha256: sha256.map(|h| hex::encode(h.finalize())),
+            lines,
+            content_type: content_type::detect(path, &head, lines.is_some()),
+        })
+    }
 
-        Ok(Some((checksum, size)))
+    /// File mtime in nanoseconds since Unix epoch, as stored in `HistoryEntry::mtime_nanos`.
+    fn mtime_nanos(meta: &std::fs::Metadata) -> Option<i64> {
+        meta.modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as i64)
     }
 
     #[allow(dead_code)]
@@ -180,7 +960,7 @@ impl Storage {
         let mut view = IndexView::from_index(&index);
         let entry = self.save_snapshot_with_index(file_path, root_dir, &mut index, &mut view)?;
         if entry.is_some() {
-            self.save_index(&index)?;
+            self.save_index(&mut index)?;
         }
         Ok(entry)
     }
@@ -191,25 +971,85 @@ impl Storage {
         root_dir: &Path,
         index: &mut Index,
         view: &mut IndexView,
+    ) -> Result<Option<HistoryEntry>> {
+        self.save_snapshot_as(file_path, root_dir, index, view, self.source, None)
+    }
+
+    /// `save_snapshot_with_index` with an explicit entry source, and for a restore the
+    /// time of the version restored.
+    fn save_snapshot_as(
+        &self,
+        file_path: &Path,
+        root_dir: &Path,
+        index: &mut Index,
+        view: &mut IndexView,
+        source: Option<EntrySource>,
+        restored_from: Option<DateTime<Utc>>,
     ) -> Result<Option<HistoryEntry>> {
         let rel_path = file_path.strip_prefix(root_dir).unwrap_or(file_path);
         let file_key = path_util::normalize_rel_path(&rel_path.to_string_lossy());
 
-        let tmp_dir = self.snapshots_dir().join(".tmp");
-        std::fs::create_dir_all(&tmp_dir)?;
-        let tmp_path = tmp_dir.join(uuid::Uuid::new_v4().to_string());
-
-        let (checksum, size) = match self.stream_hash_and_save(file_path, &tmp_path)? {
-            Some(v) => v,
-            None => {
-                std::fs::remove_file(&tmp_path).ok();
+        // Fast path: skip reading and hashing if mtime and size are unchanged
+        let meta = std::fs::metadata(file_path).ok();
+        let mtime_nanos = meta.as_ref().and_then(Self::mtime_nanos);
+        if let (Some(meta), Some(last)) = (&meta, view.last_entry_for_file(index, &file_key)) {
+            if last.op != Operation::Delete
+                && last.size == Some(meta.len())
+                && mtime_nanos.is_some()
+                && last.mtime_nanos == mtime_nanos
+            {
                 return Ok(None);
             }
+        }
+
+        let also_sha256 = view
+            .last_entry_for_file(index, &file_key)
+            .is_some_and(|e| e.checksum.is_some() && e.algo == HashAlgo::Sha256);
+        let StreamedFile {
+            writer,
+            checksum,
+            size,
+            sha256,
+            lines,
+            content_type,
+        } = match self.stream_hash_and_save(file_path, also_sha256)? {
+            Some(v) => v,
+            None => return Ok(None),
         };
 
         if size == 0 {
-            std::fs::remove_file(&tmp_path).ok();
-            return Ok(None);
+            // Emptying a file with recorded content is always recorded; new empty files
+            // only with `track_empty_files`
+            let op = match view.last_entry_for_file(index, &file_key) {
+                Some(e) if e.op != Operation::Delete && e.checksum.is_some() => Operation::Truncate,
+                Some(e) if e.op != Operation::Delete => return Ok(None),
+                _ if self.track_empty_files => Operation::Create,
+                _ => return Ok(None),
+            };
+            let entry = HistoryEntry {
+                seq: 0,
+                timestamp: Utc::now(),
+                op,
+                file: file_key,
+                checksum: None,
+                algo: HashAlgo::default(),
+                size: Some(0),
+                mtime_nanos,
+                lines: Some(0),
+                mime: None,
+                language: None,
+                writer: self.writer_of(file_path),
+                user_initiated: false,
+                trashed: false,
+                source,
+                restored_from,
+                sig: None,
+                signed_seq: None,
+            };
+            let pos = self.push_entry(index, entry)?;
+            let entry = index.history[pos].clone();
+            view.update_last_for_file(entry.file.clone(), pos);
+            return Ok(Some(entry));
         }
 
         let last_entry = view.last_entry_for_file(index, &file_key);
@@ -217,8 +1057,12 @@ impl Storage {
             Some(entry) => {
                 if entry.op == Operation::Delete {
                     Operation::Create
-                } else if entry.checksum.as_deref() == Some(checksum.as_str()) {
-                    std::fs::remove_file(&tmp_path).ok();
+                } else if entry.checksum.as_deref()
+                    == Some(match entry.algo {
+                        HashAlgo::Blake3 => checksum.as_str(),
+                        HashAlgo::Sha256 => sha256.as_deref().unwrap_or_default(),
+                    })
+                {
                     return Ok(None);
                 } else {
                     Operation::Modify
@@ -227,40 +1071,56 @@ impl Storage {
             None => Operation::Create,
         };
 
-        let snapshot_path = self.snapshot_path(&checksum);
-        if !snapshot_path.exists() {
-            if let Some(parent) = snapshot_path.parent() {
-                std::fs::create_dir_all(parent)?;
+        if let Some(writer) = writer {
+            writer.commit(&checksum)?;
+        }
+        if self.track_xattrs && !self.dry_run {
+            let kept = xattrs::read(file_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|attrs| xattrs::save(&self.ftm_dir, &checksum, &file_key, &attrs));
+            if let Err(e) = kept {
+                tracing::warn!("Extended attributes of {} not kept: {}", file_key, e);
             }
-            std::fs::rename(&tmp_path, &snapshot_path)?;
-        } else {
-            std::fs::remove_file(&tmp_path)?;
         }
 
         let mtime_nanos = std::fs::metadata(file_path)
             .ok()
-            .and_then(|m| m.modified().ok())
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_nanos() as i64);
+            .as_ref()
+            .and_then(Self::mtime_nanos);
 
         let entry = HistoryEntry {
+            seq: 0,
             timestamp: Utc::now(),
             op,
             file: file_key,
             checksum: Some(checksum),
+            algo: HashAlgo::Blake3,
             size: Some(size),
             mtime_nanos,
+            lines,
+            mime: Some(content_type.mime.to_string()),
+            language: content_type.language.map(str::to_string),
+            writer: self.writer_of(file_path),
+            user_initiated: false,
+            trashed: false,
+            source,
+            restored_from,
+            sig: None,
+            signed_seq: None,
         };
 
-        index.history.push(entry.clone());
-        view.update_last_for_file(entry.file.clone(), index.history.len() - 1);
+        let pos = self.push_entry(index, entry)?;
+        let entry = index.history[pos].clone();
+        view.update_last_for_file(entry.file.clone(), pos);
         Ok(Some(entry))
     }
 
+    /// Record that `file_path` is gone; `trashed` when it was found in the desktop trash.
     pub fn record_delete_with_index(
         &self,
         file_path: &Path,
         root_dir: &Path,
+        trashed: bool,
         index: &mut Index,
         view: &mut IndexView,
     ) -> Result<Option<HistoryEntry>> {
@@ -270,19 +1130,81 @@ impl Storage {
         if !view.last_by_file.contains_key(&file_key) {
             return Ok(None);
         }
-
         let entry = HistoryEntry {
-            timestamp: Utc::now(),
+            trashed,
+            ..Self::delete_entry(file_key, self.source)
+        };
+        self.push_delete(entry, index, view).map(Some)
+    }
+
+    fn delete_entry(file_key: String, source: Option<EntrySource>) -> HistoryEntry {
+        HistoryEntry {
+            seq: 0,
+            timestamp: Utc::now(),
             op: Operation::Delete,
             file: file_key,
             checksum: None,
+            algo: HashAlgo::default(),
             size: None,
             mtime_nanos: None,
-        };
+            lines: None,
+            mime: None,
+            language: None,
+            writer: None,
+            user_initiated: false,
+            trashed: false,
+            source,
+            restored_from: None,
+            sig: None,
+            signed_seq: None,
+        }
+    }
 
-        index.history.push(entry.clone());
-        view.update_last_for_file(entry.file.clone(), index.history.len() - 1);
-        Ok(Some(entry))
+    fn push_delete(
+        &self,
+        entry: HistoryEntry,
+        index: &mut Index,
+        view: &mut IndexView,
+    ) -> Result<HistoryEntry> {
+        let pos = self.push_entry(index, entry)?;
+        let entry = index.history[pos].clone();
+        view.update_last_for_file(entry.file.clone(), pos);
+        Ok(entry)
+    }
+
+    /// Delete the tracked file `file_path` from the working tree on the user's behalf.
+    /// Content not yet in history is snapshotted first so the file can be restored;
+    /// the delete is recorded as user-initiated.
+    pub fn remove_file(&self, file_path: &str, root_dir: &Path) -> Result<HistoryEntry> {
+        let mut index = self.load_index()?;
+        let mut view = self.build_index_view(&index);
+        let file_key = path_util::normalize_rel_path(file_path);
+        if !view
+            .last_entry_for_file(&index, &file_key)
+            .is_some_and(|e| e.op != Operation::Delete)
+        {
+            anyhow::bail!("'{}' is not a tracked file", file_key);
+        }
+
+        let path = root_dir.join(&file_key);
+        if path.is_file() {
+            let manual = Some(EntrySource::Manual);
+            self.save_snapshot_as(&path, root_dir, &mut index, &mut view, manual, None)?;
+        }
+        let entry = HistoryEntry {
+            user_initiated: true,
+            ..Self::delete_entry(file_key, Some(EntrySource::Manual))
+        };
+        let entry = self.push_delete(entry, &mut index, &mut view)?;
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to delete {}", path.display()))
+            }
+        }
+        self.save_index(&mut index)?;
+        Ok(entry)
     }
 
     /// Record delete for every file in the index whose path equals or is under `path_prefix`.
@@ -303,7 +1225,7 @@ impl Storage {
             &mut view,
         )?;
         if count > 0 {
-            self.save_index(&index)?;
+            self.save_index(&mut index)?;
         }
         Ok(count)
     }
@@ -341,22 +1263,16 @@ impl Storage {
 
         let count = files_to_delete.len();
         for file_key in files_to_delete {
-            let entry = HistoryEntry {
-                timestamp: Utc::now(),
-                op: Operation::Delete,
-                file: file_key,
-                checksum: None,
-                size: None,
-                mtime_nanos: None,
-            };
-            index.history.push(entry.clone());
-            view.update_last_for_file(entry.file.clone(), index.history.len() - 1);
+            self.push_delete(Self::delete_entry(file_key, self.source), index, view)?;
         }
         Ok(count)
     }
 
-    /// Trim oldest history entries until both max_history and max_quota are satisfied.
-    /// Removes snapshot files that become unreferenced.
+    /// Trim oldest history entries until both max_history and max_quota are satisfied,
+    /// skipping those that would leave an existing file with fewer than
+    /// min_versions_per_file versions (so the limits may stay exceeded). Trimmed entries
+    /// and snapshot files that become unreferenced go to the trash, whose oldest
+    /// snapshots are then purged to fit in what max_quota leaves.
     /// Returns (entries_removed, bytes_freed).
     pub(crate) fn trim_history_and_quota(&self, index: &mut Index) -> Result<(usize, u64)> {
         let n = index.history.len();
@@ -370,19 +1286,43 @@ impl Storage {
             if let Some(ref c) = entry.checksum {
                 *ref_count.entry(c.clone()).or_default() += 1;
                 checksum_size.entry(c.clone()).or_insert_with(|| {
-                    entry.size.unwrap_or_else(|| {
-                        std::fs::metadata(self.snapshot_path(c))
-                            .map(|m| m.len())
-                            .unwrap_or(0)
-                    })
+                    entry
+                        .size
+                        .unwrap_or_else(|| self.snapshots.size(c).unwrap_or(0))
                 });
             }
         }
         let mut total_volume: u64 = checksum_size.values().sum();
 
+        // Versions left per existing file, for the min_versions_per_file floor
+        let mut versions: HashMap<String, usize> = HashMap::new();
+        if self.min_versions_per_file > 0 {
+            let mut deleted: HashSet<&str> = HashSet::new();
+            for entry in &index.history {
+                if entry.op == Operation::Delete {
+                    deleted.insert(&entry.file);
+                } else {
+                    deleted.remove(entry.file.as_str());
+                    *versions.entry(entry.file.clone()).or_default() += 1;
+                }
+            }
+            versions.retain(|file, _| !deleted.contains(file.as_str()));
+        }
+
+        let mut remove = vec![false; n];
         let mut to_remove = 0usize;
-        while (n - to_remove > self.max_history || total_volume > self.max_quota) && to_remove < n {
-            let entry = &index.history[to_remove];
+        for (i, entry) in index.history.iter().enumerate() {
+            if n - to_remove <= self.max_history && total_volume <= self.max_quota {
+                break;
+            }
+            if entry.op != Operation::Delete {
+                if let Some(left) = versions.get_mut(&entry.file) {
+                    if *left <= self.min_versions_per_file {
+                        continue;
+                    }
+                    *left -= 1;
+                }
+            }
             if let Some(ref c) = entry.checksum {
                 if let Some(count) = ref_count.get_mut(c) {
                     *count = count.saturating_sub(1);
@@ -393,6 +1333,7 @@ impl Storage {
                     }
                 }
             }
+            remove[i] = true;
             to_remove += 1;
         }
 
@@ -400,11 +1341,16 @@ impl Storage {
             return Ok((0, 0));
         }
 
-        let snapshots_to_delete: HashSet<String> = index.history[..to_remove]
-            .iter()
-            .filter_map(|e| e.checksum.as_ref().cloned())
-            .collect();
-        index.history.drain(0..to_remove);
+        let (trimmed, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut index.history)
+            .into_iter()
+            .zip(remove)
+            .partition(|(_, remove)| *remove);
+        index.history = kept.into_iter().map(|(e, _)| e).collect();
+        let trimmed: Vec<HistoryEntry> = trimmed.into_iter().map(|(e, _)| e).collect();
+        let snapshots_to_delete: HashSet<String> =
+            trimmed.iter().filter_map(|e| e.checksum.clone()).collect();
+        self.count_trimmed(&trimmed, false)?;
+        self.trash_entries(trimmed)?;
 
         let mut bytes_freed = 0u64;
         for c in &snapshots_to_delete {
@@ -412,110 +1358,850 @@ impl Storage {
                 if let Some(&size) = checksum_size.get(c) {
                     bytes_freed += size;
                 }
-                let _ = std::fs::remove_file(self.snapshot_path(c));
+                let _ = self.discard_snapshot(c);
             }
         }
+        self.fit_trash_in_quota(total_volume)?;
 
         Ok((to_remove, bytes_freed))
     }
 
-    /// Run full clean: trim history/quota then remove orphan snapshots.
-    /// Returns combined stats (trim + orphan).
+    /// Permanently delete the oldest trashed snapshots until they fit in what max_quota
+    /// leaves beside the `referenced` bytes. Returns the number deleted.
+    fn fit_trash_in_quota(&self, referenced: u64) -> Result<usize> {
+        let mut trashed = self.snapshots.trashed()?;
+        let room = self.max_quota.saturating_sub(referenced);
+        let mut bytes: u64 = trashed.iter().map(|(_, size)| size).sum();
+        if bytes <= room {
+            return Ok(0);
+        }
+        trashed.sort_unstable();
+        let Some(&(cutoff, _)) = trashed.iter().find(|(_, size)| {
+            bytes -= size;
+            bytes <= room
+        }) else {
+            return Ok(0);
+        };
+        self.snapshots.purge_trash(cutoff)
+    }
+
+    /// Run full clean: trim history/quota, remove orphan snapshots (with stale temp
+    /// files and empty shard directories), then purge trash older than the retention
+    /// window. Returns combined stats.
     pub fn clean(&self) -> Result<CleanResult> {
+        self.clean_inner(false)
+    }
+
+    /// Clean; with `aggressive`, the whole trash is purged regardless of the retention
+    /// window.
+    pub fn gc(&self, aggressive: bool) -> Result<CleanResult> {
+        self.clean_inner(aggressive)
+    }
+
+    fn clean_inner(&self, purge_all_trash: bool) -> Result<CleanResult> {
         let mut index = self.load_index()?;
+        let entries_collapsed = collapse_repeated_versions(&mut index);
         let (entries_trimmed, bytes_freed_trim) = self.trim_history_and_quota(&mut index)?;
-        if entries_trimmed > 0 {
-            self.save_index(&index)?;
+        let orphans = self.clean_orphan_snapshots_inner(&index)?;
+        let trash_purged = self.purge_trash(purge_all_trash)?;
+        self.remove_unreferenced_xattrs(&index)?;
+        let removed_any = entries_trimmed > 0
+            || entries_collapsed > 0
+            || orphans.files_removed > 0
+            || trash_purged > 0;
+        if removed_any {
+            self.push_event(
+                &mut index,
+                EventKind::Clean {
+                    entries_trimmed,
+                    entries_collapsed,
+                    files_removed: orphans.files_removed,
+                    trash_purged,
+                },
+            );
+            self.save_index(&mut index)?;
         }
-        let (files_removed, bytes_removed) = self.clean_orphan_snapshots_inner(&index)?;
-        Ok(CleanResult {
+        self.record_usage_sample()?;
+        let result = CleanResult {
             entries_trimmed,
             bytes_freed_trim,
-            files_removed,
-            bytes_removed,
+            entries_collapsed,
+            files_removed: orphans.files_removed,
+            bytes_removed: orphans.bytes_removed,
+            trash_purged,
+            tmp_removed: orphans.tmp_removed,
+            dirs_removed: orphans.dirs_removed,
+        };
+        if removed_any {
+            self.record_maintenance(&result)?;
+        }
+        Ok(result)
+    }
+
+    /// Delete leftovers of snapshot writes (temp files in `snapshots/.tmp`) older than
+    /// `STALE_TMP_AGE`; younger ones may belong to a snapshot being written. Run by clean
+    /// and at checkout. Returns the number deleted.
+    pub fn remove_stale_tmp(&self) -> Result<usize> {
+        self.snapshots.remove_stale_writes(STALE_TMP_AGE)
+    }
+
+    /// Drop the extended attributes kept for versions neither the history nor the trash
+    /// still holds.
+    fn remove_unreferenced_xattrs(&self, index: &Index) -> Result<usize> {
+        let trashed = self.load_trash_entries()?;
+        let referenced: HashSet<&str> = index
+            .history
+            .iter()
+            .chain(trashed.iter().map(|t| &t.entry))
+            .filter_map(|e| e.checksum.as_deref())
+            .collect();
+        xattrs::remove_unreferenced(&self.ftm_dir, &referenced)
+    }
+
+    /// Move a snapshot into the trash, or delete it when trash is disabled.
+    fn discard_snapshot(&self, checksum: &str) -> Result<()> {
+        self.snapshots
+            .remove(checksum, self.trash_retention_days > 0)
+    }
+
+    /// Trashed entries, one JSON object per line in the order they were trashed.
+    fn load_trash_entries(&self) -> Result<Vec<TrashedEntry>> {
+        let path = self.trash_entries_path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        std::fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    fn trash_lines(entries: &[TrashedEntry]) -> Result<String> {
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        Ok(lines)
+    }
+
+    fn save_trash_entries(&self, entries: &[TrashedEntry]) -> Result<()> {
+        let path = self.trash_entries_path();
+        if entries.is_empty() {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, Self::trash_lines(entries)?)?;
+        Ok(())
+    }
+
+    /// Keep trimmed history entries so `unclean` can bring them back, appended to those
+    /// already trashed.
+    fn trash_entries(&self, entries: Vec<HistoryEntry>) -> Result<()> {
+        if self.trash_retention_days == 0 || entries.is_empty() {
+            return Ok(());
+        }
+        let trashed_at = Utc::now();
+        let trash: Vec<TrashedEntry> = entries
+            .into_iter()
+            .map(|entry| TrashedEntry { trashed_at, entry })
+            .collect();
+        let path = self.trash_entries_path();
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let mut file = std::fs::File::options()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        file.write_all(Self::trash_lines(&trash)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Permanently delete trashed snapshots and entries older than the retention window,
+    /// or all of them with `everything`. Returns the number of snapshot files deleted.
+    fn purge_trash(&self, everything: bool) -> Result<usize> {
+        let retention = if everything {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(self.trash_retention_days * 24 * 3600)
+        };
+        let cutoff = std::time::SystemTime::now()
+            .checked_sub(retention)
+            .unwrap_or(std::time::UNIX_EPOCH);
+
+        let purged = self.snapshots.purge_trash(cutoff)?;
+
+        let cutoff: DateTime<Utc> = cutoff.into();
+        let entries = self.load_trash_entries()?;
+        let before = entries.len();
+        let kept: Vec<TrashedEntry> = entries
+            .into_iter()
+            .filter(|e| e.trashed_at > cutoff)
+            .collect();
+        if kept.len() != before {
+            self.save_trash_entries(&kept)?;
+        }
+        Ok(purged)
+    }
+
+    /// Recover everything in the trash: snapshots go back to the store and trimmed
+    /// entries whose snapshot is available are merged back into the history.
+    pub fn unclean(&self) -> Result<UncleanResult> {
+        let snapshots_restored = self.snapshots.restore_trash()?;
+
+        let entries: Vec<HistoryEntry> = self
+            .load_trash_entries()?
+            .into_iter()
+            .map(|t| t.entry)
+            .filter(|e| {
+                e.checksum
+                    .as_deref()
+                    .is_none_or(|c| self.snapshot_exists(c))
+            })
+            .collect();
+        self.count_trimmed(&entries, true)?;
+        let entries_restored = self.insert_entries(entries, true)?;
+        self.save_trash_entries(&[])?;
+        if entries_restored > 0 || snapshots_restored > 0 {
+            self.record_event(EventKind::Unclean {
+                entries_restored,
+                snapshots_restored,
+            })?;
+        }
+
+        Ok(UncleanResult {
+            entries_restored,
+            snapshots_restored,
         })
     }
 
-    /// Read the raw bytes of a snapshot by its full checksum.
+    fn not_found(checksum: &str) -> anyhow::Error {
+        anyhow::anyhow!("Snapshot not found: {}", &checksum[..8.min(checksum.len())])
+    }
+
+    /// Path of an existing snapshot by its full checksum, for callers that stream it.
+    /// Fails for backends that do not keep snapshots as local files.
+    pub fn snapshot_file(&self, checksum: &str) -> Result<PathBuf> {
+        self.snapshots
+            .local_path(checksum)
+            .ok_or_else(|| Self::not_found(checksum))
+    }
+
+    fn open_snapshot(&self, checksum: &str) -> Result<Box<dyn Read>> {
+        if !self.snapshots.contains(checksum) {
+            return Err(Self::not_found(checksum));
+        }
+        self.snapshots.open(checksum)
+    }
+
+    /// Read the raw bytes of a snapshot by its full checksum, verifying them against it.
     pub fn read_snapshot(&self, checksum: &str) -> Result<Vec<u8>> {
-        let path = self.snapshot_path(checksum);
+        let mut content = Vec::new();
+        self.open_snapshot(checksum)?.read_to_end(&mut content)?;
+        if !self.content_matches(checksum, &content) {
+            return Err(Self::corrupt(checksum));
+        }
+        Ok(content)
+    }
+
+    /// Re-hash a stored snapshot against its checksum without loading it into memory.
+    pub fn verify_snapshot(&self, checksum: &str) -> Result<()> {
+        let mut verifier = ChecksumVerifier::new(self.snapshot_algo(checksum));
+        std::io::copy(&mut self.open_snapshot(checksum)?, &mut verifier)?;
+        if verifier.matches(checksum) {
+            return Ok(());
+        }
+        Err(Self::corrupt(checksum))
+    }
+
+    /// Algorithm the history recorded `checksum` with; None for snapshots no entry
+    /// references (yet), which are checked against both.
+    pub fn snapshot_algo(&self, checksum: &str) -> Option<HashAlgo> {
+        self.algos
+            .get_or_init(|| {
+                let index = self.load_index().unwrap_or_default();
+                index
+                    .history
+                    .into_iter()
+                    .filter_map(|e| Some((e.checksum?, e.algo)))
+                    .collect()
+            })
+            .get(checksum)
+            .copied()
+    }
+
+    /// Text of a snapshot up to `limit` bytes, cut after the last line break within them.
+    /// Longer snapshots are verified by streaming, so only the head is held in memory;
+    /// their lines are counted the same way unless `lines` (the recorded
+    /// `HistoryEntry::lines`) is given.
+    pub fn read_snapshot_head(
+        &self,
+        checksum: &str,
+        limit: u64,
+        lines: Option<usize>,
+    ) -> Result<SnapshotHead> {
+        if self
+            .snapshots
+            .size(checksum)
+            .is_none_or(|size| size <= limit)
+        {
+            let text = String::from_utf8_lossy(&self.read_snapshot(checksum)?).into_owned();
+            return Ok(SnapshotHead {
+                lines: text.lines().count(),
+                text,
+                truncated: false,
+            });
+        }
+        self.verify_snapshot(checksum)?;
+        let mut reader = self.open_snapshot(checksum)?;
+        let mut head = Vec::new();
+        (&mut reader).take(limit).read_to_end(&mut head)?;
+        let lines = match lines {
+            Some(lines) => lines,
+            None => {
+                let mut newlines = head.iter().filter(|&&b| b == b'\n').count();
+                let mut last = head.last().copied();
+                let mut buf = vec![0u8; 64 * 1024];
+                loop {
+                    let n = reader.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    newlines += buf[..n].iter().filter(|&&b| b == b'\n').count();
+                    last = Some(buf[n - 1]);
+                }
+                newlines + usize::from(last.is_some_and(|b| b != b'\n'))
+            }
+        };
+        head.truncate(head.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1));
+        Ok(SnapshotHead {
+            text: String::from_utf8_lossy(&head).into_owned(),
+            lines,
+            truncated: true,
+        })
+    }
+
+    /// Findings of background verification so far (empty before the first round).
+    pub fn verify_report(&self) -> Result<VerifyReport> {
+        let path = self.verify_report_path();
         if !path.exists() {
-            anyhow::bail!("Snapshot not found: {}", &checksum[..8.min(checksum.len())]);
+            return Ok(VerifyReport::default());
         }
-        Ok(std::fs::read(&path)?)
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
     }
 
-    /// Check whether a snapshot file exists for the given checksum.
-    #[allow(dead_code)]
+    /// Directory state (empty before the first full scan).
+    pub fn dir_state(&self) -> Result<DirState> {
+        let path = self.dir_state_path();
+        if !path.exists() {
+            return Ok(DirState::default());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Remember `scan` as the last full scan in `.ftm/state.json`.
+    pub fn record_scan(&self, scan: LastScan) -> Result<()> {
+        let mut state = self.dir_state().unwrap_or_default();
+        state.last_scan = Some(scan);
+        std::fs::write(self.dir_state_path(), serde_json::to_string(&state)?)?;
+        Ok(())
+    }
+
+    /// Add trimmed `entries` to the per-file counts in `.ftm/state.json`, or with
+    /// `restored` take back those unclean returns to history.
+    fn count_trimmed(&self, entries: &[HistoryEntry], restored: bool) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut state = self.dir_state().unwrap_or_default();
+        for entry in entries {
+            let count = state.trimmed.entry(entry.file.clone()).or_default();
+            *count = if restored {
+                count.saturating_sub(1)
+            } else {
+                *count + 1
+            };
+        }
+        state.trimmed.retain(|_, count| *count > 0);
+        std::fs::write(self.dir_state_path(), serde_json::to_string(&state)?)?;
+        Ok(())
+    }
+
+    /// Remember a clean that removed something in `.ftm/state.json`, keeping the most
+    /// recent `MAX_MAINTENANCE_RECORDS`.
+    fn record_maintenance(&self, result: &CleanResult) -> Result<()> {
+        const MAX_MAINTENANCE_RECORDS: usize = 50;
+        let mut state = self.dir_state().unwrap_or_default();
+        state.maintenance.push(MaintenanceRecord {
+            at: Utc::now(),
+            result: result.clone(),
+        });
+        if state.maintenance.len() > MAX_MAINTENANCE_RECORDS {
+            state
+                .maintenance
+                .drain(..state.maintenance.len() - MAX_MAINTENANCE_RECORDS);
+        }
+        std::fs::write(self.dir_state_path(), serde_json::to_string(&state)?)?;
+        Ok(())
+    }
+
+    /// Re-hash the next `batch` referenced snapshots (in checksum order, continuing after
+    /// the previous round and wrapping around), updating `.ftm/verify.json`.
+    pub fn verify_round(&self, batch: usize) -> Result<VerifyRound> {
+        let index = self.load_index()?;
+        let referenced: BTreeSet<&str> = index
+            .history
+            .iter()
+            .filter_map(|e| e.checksum.as_deref())
+            .collect();
+        let mut report = self.verify_report()?;
+        let mut corrupt: BTreeSet<String> = report
+            .corrupt
+            .drain(..)
+            .filter(|c| referenced.contains(c.as_str()))
+            .collect();
+        let mut missing: BTreeSet<String> = report
+            .missing
+            .drain(..)
+            .filter(|c| referenced.contains(c.as_str()))
+            .collect();
+
+        let cursor = report.cursor.clone().unwrap_or_default();
+        let next: Vec<&str> = referenced
+            .iter()
+            .filter(|c| **c > cursor.as_str())
+            .chain(referenced.iter().filter(|c| **c <= cursor.as_str()))
+            .take(batch)
+            .copied()
+            .collect();
+
+        let mut newly_corrupt = Vec::new();
+        let mut newly_missing = Vec::new();
+        for &checksum in &next {
+            if !self.snapshot_exists(checksum) {
+                corrupt.remove(checksum);
+                if missing.insert(checksum.to_string()) {
+                    newly_missing.push(checksum.to_string());
+                }
+            } else if self.verify_snapshot(checksum).is_err() {
+                missing.remove(checksum);
+                if corrupt.insert(checksum.to_string()) {
+                    newly_corrupt.push(checksum.to_string());
+                }
+            } else {
+                corrupt.remove(checksum);
+                missing.remove(checksum);
+            }
+        }
+
+        report.cursor = next.last().map(|c| c.to_string()).or(report.cursor);
+        report.last_run = Some(Utc::now());
+        report.corrupt = corrupt.into_iter().collect();
+        report.missing = missing.into_iter().collect();
+        std::fs::write(self.verify_report_path(), serde_json::to_string(&report)?)?;
+        Ok(VerifyRound {
+            checked: next.len(),
+            newly_corrupt,
+            newly_missing,
+            report,
+        })
+    }
+
+    fn corrupt(checksum: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Snapshot {} is corrupt (content does not match its checksum)",
+            &checksum[..8.min(checksum.len())]
+        )
+    }
+
+    /// Whether `content` hashes to `checksum` under the algorithm it was recorded with:
+    /// BLAKE3, or SHA-256 for older snapshots.
+    fn content_matches(&self, checksum: &str, content: &[u8]) -> bool {
+        let mut verifier = ChecksumVerifier::new(self.snapshot_algo(checksum));
+        verifier.update(content);
+        verifier.matches(checksum)
+    }
+
+    /// Check whether a snapshot exists for the given checksum.
     pub fn snapshot_exists(&self, checksum: &str) -> bool {
-        self.snapshot_path(checksum).exists()
+        store::is_checksum_hex(checksum) && self.snapshots.contains(checksum)
     }
 
-    /// Remove snapshot files that are not referenced by any HistoryEntry in the index.
-    /// Returns (files_removed, bytes_removed). Skips `.tmp/` under snapshots.
-    fn clean_orphan_snapshots_inner(&self, index: &Index) -> Result<(usize, u64)> {
-        let referenced: HashSet<String> = index
+    /// Copy every snapshot the history references into `target`'s store, skipping
+    /// those already there. Returns the number copied.
+    pub fn copy_snapshots_to(&self, target: &Storage) -> Result<usize> {
+        let index = self.load_index()?;
+        let referenced: BTreeSet<&str> = index
             .history
             .iter()
-            .filter_map(|e| e.checksum.clone())
+            .filter_map(|e| e.checksum.as_deref())
             .collect();
+        let mut copied = 0;
+        for checksum in referenced {
+            if target.snapshots.contains(checksum) || !self.snapshots.contains(checksum) {
+                continue;
+            }
+            let mut writer = target.snapshots.create()?;
+            std::io::copy(&mut self.snapshots.open(checksum)?, &mut writer)?;
+            writer.commit(checksum)?;
+            copied += 1;
+        }
+        Ok(copied)
+    }
 
-        let snap_dir = self.snapshots_dir();
-        if !snap_dir.exists() {
-            return Ok((0, 0));
+    /// Stop using the snapshot store after moving to another (see `SnapshotStore::abandon`).
+    pub fn abandon_snapshot_store(&self) -> Result<()> {
+        self.snapshots.abandon()
+    }
+
+    /// Move every snapshot into the store's current layout (`settings.shard_depth`),
+    /// then drop the directories that left empty.
+    pub fn migrate_store(&self) -> Result<MigrateStoreResult> {
+        Ok(MigrateStoreResult {
+            snapshots_moved: self.snapshots.migrate()?,
+            dirs_removed: self.snapshots.compact()?,
+        })
+    }
+
+    /// Store a snapshot received from another instance, streamed from `content`. It
+    /// must hash to `checksum` under BLAKE3 or SHA-256 and be at most `max_size` bytes;
+    /// an already present snapshot is left untouched.
+    pub fn import_snapshot(
+        &self,
+        checksum: &str,
+        content: &mut dyn Read,
+        max_size: u64,
+    ) -> Result<()> {
+        const BUF_SIZE: usize = 65536;
+        if !store::is_checksum_hex(checksum) {
+            anyhow::bail!("Invalid checksum: {}", checksum);
+        }
+        if self.snapshots.contains(checksum) {
+            return Ok(());
+        }
+        let mut writer = self.snapshots.create()?;
+        let mut verifier = ChecksumVerifier::new(None);
+        let mut buf = vec![0u8; BUF_SIZE];
+        let mut size = 0u64;
+        loop {
+            let n = content.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            size += n as u64;
+            if size > max_size {
+                anyhow::bail!("Snapshot exceeds {} bytes", max_size);
+            }
+            verifier.update(&buf[..n]);
+            writer.write_all(&buf[..n])?;
+        }
+        if !verifier.matches(checksum) {
+            anyhow::bail!("Snapshot checksum mismatch");
         }
+        writer.commit(checksum)
+    }
 
-        let to_delete = Self::collect_orphan_snapshot_paths(&snap_dir, &referenced)?;
-        let mut bytes_removed = 0u64;
-        for path in &to_delete {
-            if let Ok(meta) = std::fs::metadata(path) {
-                bytes_removed += meta.len();
+    /// Record a version of `file` uploaded by an external tool rather than read from
+    /// the watched directory. The content is streamed from `content` into the store
+    /// and kept only if it hashes to `checksum` under BLAKE3 and is at most `max_size`
+    /// bytes; the entry is attributed to `writer` when given. Returns None when the
+    /// content equals the file's current version.
+    pub fn record_upload(
+        &self,
+        file: &str,
+        checksum: &str,
+        content: &mut dyn Read,
+        max_size: u64,
+        writer: Option<String>,
+    ) -> Result<Option<HistoryEntry>> {
+        let file_key = path_util::normalize_rel_path(file);
+        if file_key.is_empty()
+            || !Path::new(&file_key)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+        {
+            anyhow::bail!("Invalid file path: {}", file);
+        }
+        if !store::is_checksum_hex(checksum) {
+            anyhow::bail!("Invalid checksum: {}", checksum);
+        }
+
+        let also_sha256 = self
+            .get_last_entry_for_file(&self.load_index()?, &file_key)
+            .is_some_and(|e| {
+                e.op != Operation::Delete && e.checksum.is_some() && e.algo == HashAlgo::Sha256
+            });
+        let streamed = self
+            .stream_to_snapshot(content, Path::new(&file_key), also_sha256, max_size)
+            .with_context(|| format!("Upload of '{}' not recorded", file_key))?;
+        if streamed.checksum != checksum {
+            anyhow::bail!("Snapshot checksum mismatch");
+        }
+        let empty = streamed.size == 0;
+        if let Some(snapshot) = streamed.writer.filter(|_| !empty) {
+            snapshot.commit(checksum)?;
+        }
+
+        // The upload may take long; only now read the index, so entries the watcher
+        // recorded meanwhile are kept
+        let mut index = self.load_index()?;
+        let last = self
+            .get_last_entry_for_file(&index, &file_key)
+            .filter(|e| e.op != Operation::Delete);
+        let unchanged = match last {
+            Some(e) if empty => e.checksum.is_none(),
+            Some(e) => {
+                e.checksum.as_deref()
+                    == Some(match e.algo {
+                        HashAlgo::Blake3 => checksum,
+                        HashAlgo::Sha256 => streamed.sha256.as_deref().unwrap_or_default(),
+                    })
             }
-            std::fs::remove_file(path).context("Failed to remove orphan snapshot")?;
+            None => false,
+        };
+        if unchanged {
+            return Ok(None);
         }
+        let op = match (last, empty) {
+            (None, _) => Operation::Create,
+            (Some(_), true) => Operation::Truncate,
+            (Some(_), false) => Operation::Modify,
+        };
+        let entry = HistoryEntry {
+            seq: 0,
+            timestamp: Utc::now(),
+            op,
+            file: file_key,
+            checksum: (!empty).then(|| checksum.to_string()),
+            algo: if empty {
+                HashAlgo::default()
+            } else {
+                HashAlgo::Blake3
+            },
+            size: Some(streamed.size),
+            mtime_nanos: None,
+            lines: streamed.lines,
+            mime: (!empty).then(|| streamed.content_type.mime.to_string()),
+            language: streamed
+                .content_type
+                .language
+                .filter(|_| !empty)
+                .map(str::to_string),
+            writer,
+            user_initiated: false,
+            trashed: false,
+            source: Some(EntrySource::Upload),
+            restored_from: None,
+            sig: None,
+            signed_seq: None,
+        };
 
-        Ok((to_delete.len(), bytes_removed))
+        let pos = self.push_entry(&mut index, entry)?;
+        let entry = index.history[pos].clone();
+        self.save_index(&mut index)?;
+        Ok(Some(entry))
     }
 
-    /// Returns true if s is exactly 64 hex chars (SHA-256).
-    fn is_sha256_hex(s: &str) -> bool {
-        s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+    /// Merge history entries from another instance. Entries already present (same
+    /// timestamp, file, op and checksum) are skipped, so merging is idempotent; entries
+    /// with unsafe paths or without a local snapshot are rejected. Added entries get new
+    /// local seqs in the order given. Returns the number of entries added.
+    pub fn merge_entries(&self, entries: Vec<HistoryEntry>) -> Result<usize> {
+        self.insert_entries(entries, false)
     }
 
-    /// Recursively collect paths of snapshot files whose checksum is not in referenced. Skips .tmp.
-    fn collect_orphan_snapshot_paths(
-        dir: &Path,
-        referenced: &HashSet<String>,
-    ) -> Result<Vec<PathBuf>> {
-        let mut out = Vec::new();
-        for entry in std::fs::read_dir(dir).context("Failed to read snapshots directory")? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                if path.file_name().is_some_and(|n| n == ".tmp") {
-                    continue;
-                }
-                out.extend(Self::collect_orphan_snapshot_paths(&path, referenced)?);
-            } else if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if Self::is_sha256_hex(name) && !referenced.contains(name) {
-                    out.push(path);
+    /// Shared by merge and unclean. With `keep_seq`, entries go back to the position
+    /// their own seq gives them (entries restored from the trash of this index).
+    fn insert_entries(&self, entries: Vec<HistoryEntry>, keep_seq: bool) -> Result<usize> {
+        let mut index = self.load_index()?;
+        let mut known: HashSet<(DateTime<Utc>, String, Operation, Option<String>)> = index
+            .history
+            .iter()
+            .map(|e| (e.timestamp, e.file.clone(), e.op, e.checksum.clone()))
+            .collect();
+
+        let mut added = 0;
+        for mut entry in entries {
+            entry.file = path_util::normalize_rel_path(&entry.file);
+            let path = Path::new(&entry.file);
+            if entry.file.is_empty()
+                || !path.components().all(|c| matches!(c, Component::Normal(_)))
+            {
+                anyhow::bail!("Invalid file path in entry: {}", entry.file);
+            }
+            match (&entry.op, &entry.checksum) {
+                (Operation::Delete | Operation::Truncate, _) => entry.checksum = None,
+                (Operation::Create, None) if entry.size == Some(0) => {}
+                (_, Some(c)) if self.snapshot_exists(c) => {}
+                (_, Some(c)) => anyhow::bail!("Snapshot not found: {}", &c[..8.min(c.len())]),
+                (_, None) => anyhow::bail!("Entry for '{}' has no checksum", entry.file),
+            }
+            // mtime is only meaningful on the machine that recorded it
+            entry.mtime_nanos = None;
+            let key = (
+                entry.timestamp,
+                entry.file.clone(),
+                entry.op,
+                entry.checksum.clone(),
+            );
+            if known.insert(key) {
+                if keep_seq {
+                    index.history.push(entry);
+                } else if entry.sig.is_some() {
+                    // keep the recording machine's signature, and the seq it covers
+                    entry.signed_seq = Some(entry.signed_seq.unwrap_or(entry.seq));
+                    index.push(entry);
+                } else {
+                    self.push_entry(&mut index, entry)?;
                 }
+                added += 1;
+            }
+        }
+
+        if added > 0 {
+            if keep_seq {
+                index.history.sort_by_key(|e| e.seq);
+                index.normalize_seq();
             }
+            self.save_index(&mut index)?;
         }
-        Ok(out)
+        Ok(added)
     }
 
-    pub fn list_history(&self, file_path: &str) -> Result<Vec<HistoryEntry>> {
+    /// Remove snapshot files that are not referenced by any HistoryEntry in the index,
+    /// stale files in `.tmp/` and shard directories left empty.
+    /// In a shared store, snapshots referenced by the other projects are kept too, and so
+    /// are young ones whose entry another project may not have saved yet.
+    fn clean_orphan_snapshots_inner(&self, index: &Index) -> Result<OrphanCleanup> {
+        let to_delete = self.orphan_checksums(index)?;
+        let mut bytes_removed = 0u64;
+        for checksum in &to_delete {
+            bytes_removed += self.snapshots.size(checksum).unwrap_or(0);
+            self.discard_snapshot(checksum)
+                .context("Failed to remove orphan snapshot")?;
+        }
+
+        Ok(OrphanCleanup {
+            files_removed: to_delete.len(),
+            bytes_removed,
+            tmp_removed: self.remove_stale_tmp()?,
+            dirs_removed: self.snapshots.compact()?,
+        })
+    }
+
+    /// Orphan snapshots clean would remove now, without removing them: unreferenced
+    /// before any trim, oldest first.
+    pub fn list_orphans(&self) -> Result<Vec<OrphanSnapshot>> {
         let index = self.load_index()?;
-        let entries: Vec<HistoryEntry> = index
+        let mut orphans: Vec<OrphanSnapshot> = self
+            .orphan_checksums(&index)?
+            .into_iter()
+            .map(|checksum| OrphanSnapshot {
+                size: self.snapshots.size(&checksum).unwrap_or(0),
+                modified: self
+                    .snapshots
+                    .local_path(&checksum)
+                    .and_then(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+                    .map(DateTime::<Utc>::from),
+                checksum,
+            })
+            .collect();
+        orphans.sort_by_key(|o| o.modified);
+        Ok(orphans)
+    }
+
+    /// Snapshots no history entry references (this index's or, in a shared store, any
+    /// registered project's). In a shared store only those over an hour old count, as
+    /// younger ones may belong to an entry about to be saved.
+    fn orphan_checksums(&self, index: &Index) -> Result<Vec<String>> {
+        let mut referenced: HashSet<String> = index
             .history
             .iter()
-            .filter(|e| e.file == file_path)
-            .cloned()
+            .filter_map(|e| e.checksum.clone())
             .collect();
+        let shared_with = self.snapshots.shared_with()?;
+        for ftm_dir in shared_with.iter().flatten() {
+            let other = FsIndexStore::new(ftm_dir)
+                .load()
+                .with_context(|| format!("Failed to read the index of {}", ftm_dir.display()))?;
+            referenced.extend(other.history.into_iter().filter_map(|e| e.checksum));
+        }
+        let settled = |checksum: &str| {
+            shared_with.is_none()
+                || self
+                    .snapshots
+                    .local_path(checksum)
+                    .and_then(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+                    .and_then(|t| t.elapsed().ok())
+                    .is_some_and(|age| age >= STALE_TMP_AGE)
+        };
+
+        Ok(self
+            .snapshots
+            .list()?
+            .into_iter()
+            .filter(|c| !referenced.contains(c) && settled(c))
+            .collect())
+    }
+
+    /// Call `f` with each history entry without loading the whole index, so reads stay
+    /// within memory for huge histories. As in `Index::normalize_seq`, when any entry
+    /// predates seqs (seq 0) every entry gets its position instead, which takes a first
+    /// pass to find out.
+    fn for_each_entry(&self, mut f: impl FnMut(HistoryEntry)) -> Result<()> {
+        let mut renumber = false;
+        self.index_store
+            .for_each_entry(&mut |entry| renumber |= entry.seq == 0)?;
+        let mut position = 0;
+        self.index_store.for_each_entry(&mut |mut entry| {
+            position += 1;
+            if renumber {
+                entry.seq = position;
+            }
+            f(entry)
+        })
+    }
+
+    pub fn list_history(&self, file_path: &str) -> Result<Vec<HistoryEntry>> {
+        let mut entries = Vec::new();
+        self.for_each_entry(|e| {
+            if e.file == file_path {
+                entries.push(e);
+            }
+        })?;
+        entries.sort_by_key(|e| e.seq);
         Ok(entries)
     }
 
+    /// History of a file with the size and line count change of each entry, oldest first.
+    pub fn list_history_versions(&self, file_path: &str) -> Result<Vec<HistoryVersion>> {
+        fn delta(cur: Option<u64>, prev: Option<u64>) -> Option<i64> {
+            cur.zip(prev).map(|(c, p)| c as i64 - p as i64)
+        }
+        let (mut prev_size, mut prev_lines) = (Some(0u64), Some(0u64));
+        Ok(self
+            .list_history(file_path)?
+            .into_iter()
+            .map(|entry| {
+                let (size, lines) = match entry.op {
+                    Operation::Delete => (Some(0), Some(0)),
+                    _ => (entry.size, entry.lines.map(|l| l as u64)),
+                };
+                let size_delta = delta(size, prev_size);
+                let lines_delta = delta(lines, prev_lines);
+                (prev_size, prev_lines) = (size, lines);
+                HistoryVersion {
+                    entry,
+                    size_delta,
+                    lines_delta,
+                }
+            })
+            .collect())
+    }
+
     /// Return all history entries within the given time range.
     /// Both `since` and `until` are inclusive bounds.
     /// When `include_deleted` is false, entries for files whose last history entry is Delete are excluded.
@@ -542,24 +2228,22 @@ impl Storage {
     }
 
     pub fn list_files(&self, include_deleted: bool) -> Result<Vec<(String, usize)>> {
-        let index = self.load_index()?;
-        let mut file_counts: HashMap<String, usize> = HashMap::new();
-
-        for entry in &index.history {
-            *file_counts.entry(entry.file.clone()).or_default() += 1;
-        }
+        // Per file: entry count, and seq and whether it is a delete of the last entry
+        let mut file_counts: HashMap<String, (usize, u64, bool)> = HashMap::new();
+        self.for_each_entry(|entry| {
+            let counts = file_counts.entry(entry.file).or_default();
+            counts.0 += 1;
+            if entry.seq >= counts.1 {
+                counts.1 = entry.seq;
+                counts.2 = entry.op == Operation::Delete;
+            }
+        })?;
 
-        let mut files: Vec<(String, usize)> = if include_deleted {
-            file_counts.into_iter().collect()
-        } else {
-            file_counts
-                .into_iter()
-                .filter(|(file, _)| {
-                    self.get_last_entry_for_file(&index, file)
-                        .is_none_or(|e| e.op != Operation::Delete)
-                })
-                .collect()
-        };
+        let mut files: Vec<(String, usize)> = file_counts
+            .into_iter()
+            .filter(|(_, (_, _, deleted))| include_deleted || !deleted)
+            .map(|(file, (count, _, _))| (file, count))
+            .collect();
         files.sort_unstable_by(|a, b| a.0.cmp(&b.0));
         Ok(files)
     }
@@ -628,8 +2312,15 @@ impl Storage {
             .collect()
     }
 
-    pub fn restore(&self, file_path: &str, checksum_prefix: &str, root_dir: &Path) -> Result<()> {
-        let index = self.load_index()?;
+    /// Returns when the file was emptied, if its latest entry is a truncation (the
+    /// restore then replaced an empty file rather than the last recorded content).
+    pub fn restore(
+        &self,
+        file_path: &str,
+        checksum_prefix: &str,
+        root_dir: &Path,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let mut index = self.load_index()?;
         let file_path_norm = path_util::normalize_rel_path(file_path);
 
         // Find entry matching the checksum prefix (compare normalized paths for Windows compatibility)
@@ -645,25 +2336,360 @@ impl Storage {
             .context("Version not found in history")?;
 
         let full_checksum = entry.checksum.as_ref().unwrap().clone();
-        let snapshot_path = self.snapshot_path(&full_checksum);
-        if !snapshot_path.exists() {
-            anyhow::bail!("Snapshot file not found");
+        let version_at = entry.timestamp;
+        self.write_version(entry, root_dir)?;
+        let truncated_at = index
+            .history
+            .iter()
+            .rev()
+            .find(|e| path_util::normalize_rel_path(&e.file) == file_path_norm)
+            .filter(|e| e.op == Operation::Truncate)
+            .map(|e| e.timestamp);
+
+        self.push_event(
+            &mut index,
+            EventKind::Restore {
+                file: file_path_norm.clone(),
+                checksum: full_checksum,
+            },
+        );
+        self.record_restored(
+            [(file_path_norm.as_str(), version_at)],
+            root_dir,
+            &mut index,
+        )?;
+        self.save_index(&mut index)?;
+        Ok(truncated_at)
+    }
+
+    /// What restoring `checksum_prefix` would overwrite, when the working copy has
+    /// changes not yet in history; None when it matches the latest recorded version or
+    /// the version restored, or is gone.
+    pub fn restore_conflict(
+        &self,
+        file_path: &str,
+        checksum_prefix: &str,
+        root_dir: &Path,
+    ) -> Result<Option<RestoreConflict>> {
+        let index = self.load_index()?;
+        let file_path_norm = path_util::normalize_rel_path(file_path);
+        let mut versions = index
+            .history
+            .iter()
+            .filter(|e| path_util::normalize_rel_path(&e.file) == file_path_norm);
+        let target = versions
+            .clone()
+            .find(|e| {
+                e.checksum
+                    .as_ref()
+                    .is_some_and(|c| c.starts_with(checksum_prefix))
+            })
+            .context("Version not found in history")?;
+        let Ok(working) = std::fs::read(root_dir.join(&file_path_norm)) else {
+            return Ok(None);
+        };
+        let ancestor = versions
+            .next_back()
+            .filter(|e| e.op != Operation::Delete && e.checksum.is_some());
+        let matches = |entry: &HistoryEntry| {
+            entry.checksum.as_deref() == Some(&Self::compute_checksum(entry.algo, &working))
+        };
+        if matches(target) || ancestor.is_some_and(matches) {
+            return Ok(None);
         }
 
-        let content = std::fs::read(&snapshot_path)?;
+        let side = |checksum: String, content: Vec<u8>, timestamp| ConflictSide {
+            checksum,
+            size: content.len() as u64,
+            timestamp,
+            content: String::from_utf8(content)
+                .ok()
+                .filter(|text| text.len() <= CONFLICT_CONTENT_LIMIT),
+        };
+        let recorded = |entry: &HistoryEntry| -> Result<ConflictSide> {
+            let content = self.version_content(entry)?;
+            let checksum = entry.checksum.clone().unwrap_or_default();
+            Ok(side(checksum, content, Some(entry.timestamp)))
+        };
+        Ok(Some(RestoreConflict {
+            file: file_path_norm,
+            working: side(
+                Self::compute_checksum(HashAlgo::Blake3, &working),
+                working,
+                None,
+            ),
+            snapshot: recorded(target)?,
+            ancestor: ancestor.map(recorded).transpose()?,
+        }))
+    }
+
+    /// Bring a deleted file back as its last recorded version, e.g. after it was moved
+    /// to the desktop trash, which lies outside the watched directory. Returns the
+    /// checksum restored.
+    pub fn restore_latest(&self, file_path: &str, root_dir: &Path) -> Result<String> {
+        let mut index = self.load_index()?;
+        let file_path_norm = path_util::normalize_rel_path(file_path);
+        let mut versions = index
+            .history
+            .iter()
+            .rev()
+            .filter(|e| path_util::normalize_rel_path(&e.file) == file_path_norm);
+        // Existing files are refused even before the next scan records them
+        let exists = root_dir.join(&file_path_norm).exists();
+        match versions.next() {
+            Some(e) if e.op == Operation::Delete && !exists => {}
+            Some(_) => anyhow::bail!(
+                "'{}' is not deleted; pick a version with ftm restore <file> <checksum>",
+                file_path_norm
+            ),
+            None => anyhow::bail!("No history for '{}'", file_path_norm),
+        }
+        let entry = versions
+            .find(|e| e.checksum.is_some() || Self::is_empty_create(e))
+            .context("No recorded version to restore")?;
+
+        let checksum = entry.checksum.clone().unwrap_or_default();
+        let version_at = entry.timestamp;
+        self.write_version(entry, root_dir)?;
+        self.push_event(
+            &mut index,
+            EventKind::Restore {
+                file: file_path_norm.clone(),
+                checksum: checksum.clone(),
+            },
+        );
+        self.record_restored(
+            [(file_path_norm.as_str(), version_at)],
+            root_dir,
+            &mut index,
+        )?;
+        self.save_index(&mut index)?;
+        Ok(checksum)
+    }
 
-        // Verify checksum
-        if Self::compute_checksum(&content) != full_checksum {
-            anyhow::bail!("Snapshot checksum mismatch");
+    /// Record the files a restore just wrote back, each with the time of the version
+    /// written, as restore entries, rather than leaving them for the watcher to pick up.
+    fn record_restored<'a>(
+        &self,
+        files: impl IntoIterator<Item = (&'a str, DateTime<Utc>)>,
+        root_dir: &Path,
+        index: &mut Index,
+    ) -> Result<()> {
+        let mut view = self.build_index_view(index);
+        let source = Some(EntrySource::Restore);
+        for (file, version_at) in files {
+            let path = root_dir.join(file);
+            self.save_snapshot_as(&path, root_dir, index, &mut view, source, Some(version_at))?;
         }
+        Ok(())
+    }
+
+    /// A file recorded as empty when created (`track_empty_files`).
+    fn is_empty_create(entry: &HistoryEntry) -> bool {
+        entry.op == Operation::Create && entry.checksum.is_none() && entry.size == Some(0)
+    }
 
-        // Simply copy the snapshot to the target location
-        let target = root_dir.join(file_path);
+    /// Write a recorded version back to its file under `root_dir`, verifying the snapshot.
+    fn write_version(&self, entry: &HistoryEntry, root_dir: &Path) -> Result<()> {
+        let target = root_dir.join(&entry.file);
+        let content = self.version_content(entry)?;
         if let Some(parent) = target.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        std::fs::write(target, &content)?;
-
+        std::fs::write(&target, &content)?;
+        self.restore_xattrs(entry, &target);
         Ok(())
     }
+
+    /// Put back the extended attributes kept for `entry`'s version on `target`, if any.
+    /// Failing to is only logged: the content matters more than its attributes.
+    fn restore_xattrs(&self, entry: &HistoryEntry, target: &Path) {
+        let Some(checksum) = entry.checksum.as_deref() else {
+            return;
+        };
+        let restored =
+            xattrs::load(&self.ftm_dir, checksum, &entry.file).and_then(|attrs| match attrs {
+                Some(attrs) => xattrs::write(target, &attrs).map_err(anyhow::Error::from),
+                None => Ok(()),
+            });
+        if let Err(e) = restored {
+            tracing::warn!("Extended attributes of {} not restored: {}", entry.file, e);
+        }
+    }
+
+    /// Content of a recorded version, verified against its checksum.
+    fn version_content(&self, entry: &HistoryEntry) -> Result<Vec<u8>> {
+        if Self::is_empty_create(entry) {
+            return Ok(Vec::new());
+        }
+        let checksum = entry.checksum.as_deref().context("Entry has no content")?;
+        if !self.snapshots.contains(checksum) {
+            anyhow::bail!("Snapshot file not found");
+        }
+
+        let mut content = Vec::new();
+        self.snapshots.open(checksum)?.read_to_end(&mut content)?;
+        if Self::compute_checksum(entry.algo, &content) != checksum {
+            anyhow::bail!("Snapshot checksum mismatch");
+        }
+        Ok(content)
+    }
+
+    /// Write every version next to its target under a temporary name, then rename them
+    /// all into place. Targets must not exist. On any failure everything written so far
+    /// (and every directory created for it) is removed again, so the tree is never left
+    /// half-restored.
+    fn write_versions_atomically(&self, entries: &[HistoryEntry], root_dir: &Path) -> Result<()> {
+        let mut restore = StagedRestore::default();
+        let result = self.stage_and_place(entries, root_dir, &mut restore);
+        if result.is_err() {
+            restore.roll_back();
+        }
+        result
+    }
+
+    fn stage_and_place(
+        &self,
+        entries: &[HistoryEntry],
+        root_dir: &Path,
+        restore: &mut StagedRestore,
+    ) -> Result<()> {
+        for entry in entries {
+            let target = root_dir.join(&entry.file);
+            let content = self
+                .version_content(entry)
+                .with_context(|| format!("Failed to restore '{}'", entry.file))?;
+            let parent = target.parent().unwrap_or(root_dir);
+            restore.create_dirs(parent)?;
+            let name = target.file_name().unwrap_or_default().to_string_lossy();
+            let tmp = parent.join(format!(".{}.{}.ftm-restore", name, uuid::Uuid::new_v4()));
+            std::fs::write(&tmp, &content)
+                .with_context(|| format!("Failed to restore '{}'", entry.file))?;
+            self.restore_xattrs(entry, &tmp);
+            restore.staged.push((tmp, target));
+        }
+        let mut placed = 0;
+        let mut result = Ok(());
+        for (tmp, target) in &restore.staged {
+            // A file that appeared since the targets were chosen is not overwritten
+            if target.exists() {
+                result = Err(anyhow::anyhow!(
+                    "'{}' was created meanwhile",
+                    target.display()
+                ));
+                break;
+            }
+            if let Err(e) = std::fs::rename(tmp, target) {
+                result = Err(e.into());
+                break;
+            }
+            placed += 1;
+        }
+        // Pairs not placed stay staged, so a roll-back removes their temporary files
+        let moved = restore.staged.drain(..placed).map(|(_, target)| target);
+        restore.placed.extend(moved);
+        result.context("Failed to move restored files into place")
+    }
+
+    /// Prefix of the file keys under `dir`: empty for "" or ".", else `dir/`.
+    fn dir_prefix(dir: &str) -> String {
+        let dir = path_util::normalize_rel_path(dir);
+        let dir = dir.trim_matches('/');
+        if dir.is_empty() || dir == "." {
+            String::new()
+        } else {
+            format!("{}/", dir)
+        }
+    }
+
+    /// Last entry up to `at` (default: now) of every file under `dir` ("" for all) that
+    /// existed then, ordered by path. Entries without a checksum stand for empty files.
+    pub fn versions_at(&self, dir: &str, at: Option<DateTime<Utc>>) -> Result<Vec<HistoryEntry>> {
+        let index = self.load_index()?;
+        let prefix = Self::dir_prefix(dir);
+        let mut state: BTreeMap<&str, &HistoryEntry> = BTreeMap::new();
+        for entry in &index.history {
+            if entry.file.starts_with(&prefix) && at.is_none_or(|at| entry.timestamp <= at) {
+                state.insert(&entry.file, entry);
+            }
+        }
+        Ok(state
+            .into_values()
+            .filter(|e| e.op != Operation::Delete)
+            .cloned()
+            .collect())
+    }
+
+    /// Restore every file under `dir` ("" for all) that is deleted now (missing, whether
+    /// or not the delete was recorded yet) but existed at `at` (default: ever), each to
+    /// its last version with content up to then. Files that exist are left alone. All or
+    /// nothing: if any file cannot be restored, none is. Returns the restored
+    /// (file, checksum) pairs.
+    pub fn restore_dir(
+        &self,
+        dir: &str,
+        at: Option<DateTime<Utc>>,
+        root_dir: &Path,
+    ) -> Result<Vec<(String, String)>> {
+        let index = self.load_index()?;
+        let prefix = Self::dir_prefix(dir);
+
+        // Per file: last entry overall, last entry up to `at`, last version with content
+        // (or a tracked empty file, but not a truncation) up to `at`
+        let mut last: BTreeMap<&str, &HistoryEntry> = BTreeMap::new();
+        let mut at_state: HashMap<&str, &HistoryEntry> = HashMap::new();
+        let mut version: HashMap<&str, &HistoryEntry> = HashMap::new();
+        for entry in &index.history {
+            if !entry.file.starts_with(&prefix) {
+                continue;
+            }
+            last.insert(&entry.file, entry);
+            if at.is_none_or(|at| entry.timestamp <= at) {
+                at_state.insert(&entry.file, entry);
+                if entry.checksum.is_some() || Self::is_empty_create(entry) {
+                    version.insert(&entry.file, entry);
+                }
+            }
+        }
+
+        let targets: Vec<HistoryEntry> = last
+            .iter()
+            .filter(|(file, _)| {
+                (at.is_none()
+                    || at_state
+                        .get(*file)
+                        .is_some_and(|e| e.op != Operation::Delete))
+                    && !root_dir.join(file).exists()
+            })
+            .filter_map(|(file, _)| version.get(file).map(|e| (*e).clone()))
+            .collect();
+
+        self.write_versions_atomically(&targets, root_dir)?;
+        let restored: Vec<(String, String)> = targets
+            .iter()
+            .map(|e| (e.file.clone(), e.checksum.clone().unwrap_or_default()))
+            .collect();
+        if restored.is_empty() {
+            return Ok(restored);
+        }
+        // Writing many files takes a while; record on the index as saved since, so
+        // entries the watcher added meanwhile are kept
+        let mut index = self.load_index()?;
+        for (file, checksum) in &restored {
+            self.push_event(
+                &mut index,
+                EventKind::Restore {
+                    file: file.clone(),
+                    checksum: checksum.clone(),
+                },
+            );
+        }
+        self.record_restored(
+            targets.iter().map(|e| (e.file.as_str(), e.timestamp)),
+            root_dir,
+            &mut index,
+        )?;
+        self.save_index(&mut index)?;
+        Ok(restored)
+    }
 }
@@ -0,0 +1,621 @@
+//! Backends behind `Storage`. A `SnapshotStore` keeps snapshot content by checksum and
+//! an `IndexStore` keeps the index; history, trimming, trash bookkeeping and
+//! verification live in `Storage` on top of them, so another layout (SQLite blobs,
+//! object storage, encryption at rest) only has to implement these two traits.
+//! `FsSnapshotStore` and `FsIndexStore` are the `.ftm/` layout; `FsSnapshotStore` can
+//! also be a store shared by several projects (`settings.snapshot_store`).
+
+use crate::types::{HistoryEntry, Index};
+use anyhow::{Context, Result};
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Whether `s` is exactly 64 hex chars (a SHA-256 or BLAKE3 checksum).
+pub fn is_checksum_hex(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Identifies one saved state of the index, for telling whether a loaded copy is current.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexStamp {
+    pub modified: SystemTime,
+    pub len: u64,
+}
+
+pub trait IndexStore: Send + Sync {
+    /// The saved index, or an empty one if none was saved yet.
+    fn load(&self) -> Result<Index>;
+    fn save(&self, index: &Index) -> Result<()>;
+    /// Call `f` with each history entry of the saved index, in stored order, for reads
+    /// that need not hold the whole index in memory.
+    fn for_each_entry(&self, f: &mut dyn FnMut(HistoryEntry)) -> Result<()> {
+        self.load()?.history.into_iter().for_each(f);
+        Ok(())
+    }
+    /// Stamp of the saved index; None when it cannot be told, so copies are always
+    /// reloaded.
+    fn stamp(&self) -> Option<IndexStamp> {
+        None
+    }
+}
+
+/// A snapshot being written. Its checksum is only known once all content is written;
+/// dropping the writer without committing discards the content.
+pub trait SnapshotWriter: Write {
+    /// Keep the written content as snapshot `checksum`. A snapshot already stored under
+    /// that checksum is left as is.
+    fn commit(self: Box<Self>, checksum: &str) -> Result<()>;
+}
+
+pub trait SnapshotStore: Send + Sync {
+    fn contains(&self, checksum: &str) -> bool;
+    /// Stored size in bytes, or None if the snapshot is not stored.
+    fn size(&self, checksum: &str) -> Option<u64>;
+    fn open(&self, checksum: &str) -> Result<Box<dyn Read>>;
+    /// Local file holding the snapshot, for serving byte ranges; None when the backend
+    /// does not keep snapshots as files.
+    fn local_path(&self, _checksum: &str) -> Option<PathBuf> {
+        None
+    }
+    fn create(&self) -> Result<Box<dyn SnapshotWriter>>;
+    /// Checksums of all stored snapshots.
+    fn list(&self) -> Result<Vec<String>>;
+    /// Remove a snapshot, into the trash when `trash` is set.
+    fn remove(&self, checksum: &str, trash: bool) -> Result<()>;
+    /// Permanently delete snapshots trashed at or before `cutoff`. Returns the count.
+    fn purge_trash(&self, cutoff: SystemTime) -> Result<usize>;
+    /// When each trashed snapshot was trashed, with its size in bytes.
+    fn trashed(&self) -> Result<Vec<(SystemTime, u64)>>;
+    /// Move every trashed snapshot back into the store. Returns the number restored
+    /// that were not stored already.
+    fn restore_trash(&self) -> Result<usize>;
+    /// Delete leftovers of writes interrupted more than `age` ago. Returns the count.
+    fn remove_stale_writes(&self, age: Duration) -> Result<usize>;
+    /// Drop structure left empty by removals. Returns the number of items dropped.
+    fn compact(&self) -> Result<usize> {
+        Ok(0)
+    }
+    /// Move snapshots kept in an older layout into the current one. Returns the number
+    /// moved.
+    fn migrate(&self) -> Result<usize> {
+        Ok(0)
+    }
+    /// For a store shared between projects, the `.ftm` directories of the others, whose
+    /// indexes also reference snapshots here; None for a store of one project.
+    fn shared_with(&self) -> Result<Option<Vec<PathBuf>>> {
+        Ok(None)
+    }
+    /// This project's snapshots were copied to another store: delete a store of its
+    /// own (trash included), or stop counting it as a user of a shared one.
+    fn abandon(&self) -> Result<()>;
+}
+
+/// `.ftm/index.json`.
+pub struct FsIndexStore {
+    path: PathBuf,
+}
+
+impl FsIndexStore {
+    pub fn new(ftm_dir: &Path) -> Self {
+        Self {
+            path: ftm_dir.join("index.json"),
+        }
+    }
+}
+
+impl IndexStore for FsIndexStore {
+    fn load(&self) -> Result<Index> {
+        if !self.path.exists() {
+            return Ok(Index::default());
+        }
+        let content = std::fs::read_to_string(&self.path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn save(&self, index: &Index) -> Result<()> {
+        std::fs::write(&self.path, serde_json::to_string(index)?)?;
+        Ok(())
+    }
+
+    /// Parses `index.json` from a buffered reader one entry at a time; other fields are
+    /// skipped without being kept.
+    fn for_each_entry(&self, f: &mut dyn FnMut(HistoryEntry)) -> Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        let file = std::fs::File::open(&self.path)?;
+        let mut de = serde_json::Deserializer::from_reader(std::io::BufReader::new(file));
+        de.deserialize_map(HistoryVisitor(f))?;
+        de.end()?;
+        Ok(())
+    }
+
+    /// mtime and size of `index.json`.
+    fn stamp(&self) -> Option<IndexStamp> {
+        let meta = std::fs::metadata(&self.path).ok()?;
+        Some(IndexStamp {
+            modified: meta.modified().ok()?,
+            len: meta.len(),
+        })
+    }
+}
+
+/// Visits the `history` array of a serialized index, passing on each entry.
+struct HistoryVisitor<'a>(&'a mut dyn FnMut(HistoryEntry));
+
+impl<'de> Visitor<'de> for HistoryVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("an index")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "history" {
+                map.next_value_seed(HistoryVisitor(&mut *self.0))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(entry) = seq.next_element::<HistoryEntry>()? {
+            (self.0)(entry);
+        }
+        Ok(())
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for HistoryVisitor<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+/// Shard depth of stores created before it was configurable (`settings.shard_depth`).
+pub const DEFAULT_SHARD_DEPTH: usize = 2;
+pub const MAX_SHARD_DEPTH: usize = 4;
+
+/// One directory level per leading checksum char: {checksum[0]}/{checksum[1]}/{checksum}
+/// at depth 2, {checksum} directly in `dir` at depth 0.
+fn shard_path(dir: &Path, checksum: &str, depth: usize) -> PathBuf {
+    let mut path = dir.to_path_buf();
+    for c in checksum[..depth].chars() {
+        path.push(c.to_string());
+    }
+    path.join(checksum)
+}
+
+/// Where snapshot `checksum` is stored: at `depth`, or at any other depth until
+/// `ftm migrate-store` has moved it.
+fn find_shard_path(dir: &Path, checksum: &str, depth: usize) -> Option<PathBuf> {
+    std::iter::once(depth)
+        .chain((0..=MAX_SHARD_DEPTH).filter(|&d| d != depth))
+        .map(|d| shard_path(dir, checksum, d))
+        .find(|path| path.is_file())
+}
+
+/// Whether `path` is a shard directory: named after a single checksum char.
+fn is_shard_dir(path: &Path) -> bool {
+    path.is_dir()
+        && path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.len() == 1 && n.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Read-only files at `.ftm/snapshots/{c1}/{c2}/{checksum}` (one level per char up to
+/// the shard depth), written through temp files in `snapshots/.tmp`. Trashed snapshots
+/// sit flat in `.ftm/trash/snapshots/`, their mtime recording when they were trashed.
+///
+/// A shared store has the same layout under its own directory (`snapshots/`, `trash/`)
+/// plus `users/`, where each project writing to it records its `.ftm` directory.
+pub struct FsSnapshotStore {
+    dir: PathBuf,
+    trash_dir: PathBuf,
+    users: Option<StoreUsers>,
+    shard_depth: usize,
+}
+
+/// Registrations in `<store>/users/`: one file per project, named after a hash of its
+/// `.ftm` path and holding that path.
+struct StoreUsers {
+    dir: PathBuf,
+    ftm_dir: PathBuf,
+}
+
+impl StoreUsers {
+    fn file(&self) -> PathBuf {
+        let hash = blake3::hash(self.ftm_dir.to_string_lossy().as_bytes());
+        self.dir.join(&hash.to_hex()[..16])
+    }
+
+    fn register(&self) -> Result<()> {
+        let file = self.file();
+        if !file.exists() {
+            std::fs::create_dir_all(&self.dir)?;
+            std::fs::write(&file, self.ftm_dir.to_string_lossy().as_bytes())
+                .context("Failed to register with the shared snapshot store")?;
+        }
+        Ok(())
+    }
+
+    /// The other registered projects. Registrations whose `.ftm` is gone are dropped.
+    fn others(&self) -> Result<Vec<PathBuf>> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Ok(Vec::new());
+        };
+        let own = self.file();
+        let mut others = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path == own {
+                continue;
+            }
+            let ftm_dir = PathBuf::from(std::fs::read_to_string(&path)?.trim_end());
+            if ftm_dir.exists() {
+                others.push(ftm_dir);
+            } else {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+        Ok(others)
+    }
+}
+
+impl FsSnapshotStore {
+    pub fn new(ftm_dir: &Path) -> Self {
+        Self {
+            dir: ftm_dir.join("snapshots"),
+            trash_dir: ftm_dir.join("trash").join("snapshots"),
+            users: None,
+            shard_depth: DEFAULT_SHARD_DEPTH,
+        }
+    }
+
+    /// The store in `store_dir` shared between projects, as used by the project whose
+    /// `.ftm` directory is `ftm_dir`.
+    pub fn shared(store_dir: &Path, ftm_dir: &Path) -> Self {
+        Self {
+            dir: store_dir.join("snapshots"),
+            trash_dir: store_dir.join("trash"),
+            users: Some(StoreUsers {
+                dir: store_dir.join("users"),
+                ftm_dir: ftm_dir.to_path_buf(),
+            }),
+            shard_depth: DEFAULT_SHARD_DEPTH,
+        }
+    }
+
+    /// Store new snapshots `depth` directory levels deep (at most `MAX_SHARD_DEPTH`).
+    pub fn with_shard_depth(mut self, depth: usize) -> Self {
+        self.shard_depth = depth.min(MAX_SHARD_DEPTH);
+        self
+    }
+
+    /// Where a new snapshot `checksum` goes.
+    fn path(&self, checksum: &str) -> PathBuf {
+        shard_path(&self.dir, checksum, self.shard_depth)
+    }
+
+    /// Where snapshot `checksum` is stored, whatever its layout.
+    fn find(&self, checksum: &str) -> Option<PathBuf> {
+        if !is_checksum_hex(checksum) {
+            return None;
+        }
+        find_shard_path(&self.dir, checksum, self.shard_depth)
+    }
+
+    /// Remove shard directories under `dir` left empty, deepest first. Returns the count.
+    fn prune_shards(dir: &Path) -> Result<usize> {
+        let mut removed = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !is_shard_dir(&path) {
+                continue;
+            }
+            removed += Self::prune_shards(&path)?;
+            // Fails, as intended, while the directory still holds something
+            if std::fs::remove_dir(&path).is_ok() {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn tmp_dir(&self) -> PathBuf {
+        self.dir.join(".tmp")
+    }
+
+    /// Move a file to its place in the store, creating its shard directory. Compaction
+    /// may prune that directory while it is still empty, so a rename that finds it gone
+    /// is retried once.
+    fn move_into_store(from: &Path, to: &Path) -> std::io::Result<()> {
+        let parent = to.parent().unwrap();
+        std::fs::create_dir_all(parent)?;
+        match std::fs::rename(from, to) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && from.exists() => {
+                std::fs::create_dir_all(parent)?;
+                std::fs::rename(from, to)
+            }
+            result => result,
+        }
+    }
+
+    /// Snapshots are stored read-only so a stray write cannot silently alter history.
+    fn make_read_only(path: &Path) -> std::io::Result<()> {
+        let mut perms = std::fs::metadata(path)?.permissions();
+        perms.set_readonly(true);
+        std::fs::set_permissions(path, perms)
+    }
+
+    fn make_writable(path: &Path) -> std::io::Result<()> {
+        let mut perms = std::fs::metadata(path)?.permissions();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            perms.set_mode(perms.mode() | 0o200);
+        }
+        #[cfg(not(unix))]
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        std::fs::set_permissions(path, perms)
+    }
+
+    /// Recursively collect checksums of snapshot files under `dir`. Skips .tmp.
+    fn collect(dir: &Path, out: &mut Vec<String>) -> Result<()> {
+        for entry in std::fs::read_dir(dir).context("Failed to read snapshots directory")? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if path.file_name().is_some_and(|n| n == ".tmp") {
+                    continue;
+                }
+                Self::collect(&path, out)?;
+            } else if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                if is_checksum_hex(name) {
+                    out.push(name.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotStore for FsSnapshotStore {
+    fn contains(&self, checksum: &str) -> bool {
+        self.find(checksum).is_some()
+    }
+
+    fn size(&self, checksum: &str) -> Option<u64> {
+        std::fs::metadata(self.find(checksum)?)
+            .ok()
+            .map(|m| m.len())
+    }
+
+    fn open(&self, checksum: &str) -> Result<Box<dyn Read>> {
+        let path = self
+            .local_path(checksum)
+            .context("Snapshot file not found")?;
+        Ok(Box::new(std::fs::File::open(path)?))
+    }
+
+    fn local_path(&self, checksum: &str) -> Option<PathBuf> {
+        self.find(checksum)
+    }
+
+    fn create(&self) -> Result<Box<dyn SnapshotWriter>> {
+        // Registered before anything is written, so cleanup by other projects keeps it
+        if let Some(users) = &self.users {
+            users.register()?;
+        }
+        let tmp_dir = self.tmp_dir();
+        std::fs::create_dir_all(&tmp_dir)?;
+        let tmp_path = tmp_dir.join(uuid::Uuid::new_v4().to_string());
+        Ok(Box::new(FsSnapshotWriter {
+            file: Some(std::fs::File::create(&tmp_path)?),
+            tmp_path,
+            snapshots_dir: self.dir.clone(),
+            shard_depth: self.shard_depth,
+        }))
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let mut out = Vec::new();
+        if self.dir.exists() {
+            Self::collect(&self.dir, &mut out)?;
+        }
+        Ok(out)
+    }
+
+    fn remove(&self, checksum: &str, trash: bool) -> Result<()> {
+        let path = self.find(checksum).context("Snapshot file not found")?;
+        // Read-only files cannot be deleted or re-timestamped everywhere (Windows)
+        Self::make_writable(&path)?;
+        if !trash {
+            std::fs::remove_file(&path)?;
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.trash_dir)?;
+        let dest = self.trash_dir.join(checksum);
+        std::fs::rename(&path, &dest)?;
+        std::fs::File::options()
+            .write(true)
+            .open(&dest)?
+            .set_modified(SystemTime::now())?;
+        Ok(())
+    }
+
+    fn purge_trash(&self, cutoff: SystemTime) -> Result<usize> {
+        if !self.trash_dir.exists() {
+            return Ok(0);
+        }
+        let mut purged = 0;
+        for entry in std::fs::read_dir(&self.trash_dir)? {
+            let path = entry?.path();
+            let trashed = std::fs::metadata(&path).and_then(|m| m.modified());
+            if trashed.is_ok_and(|t| t <= cutoff) {
+                std::fs::remove_file(&path).context("Failed to purge trashed snapshot")?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    fn trashed(&self) -> Result<Vec<(SystemTime, u64)>> {
+        if !self.trash_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut trashed = Vec::new();
+        for entry in std::fs::read_dir(&self.trash_dir)? {
+            let meta = entry?.metadata()?;
+            if meta.is_file() {
+                trashed.push((meta.modified()?, meta.len()));
+            }
+        }
+        Ok(trashed)
+    }
+
+    fn restore_trash(&self) -> Result<usize> {
+        if !self.trash_dir.exists() {
+            return Ok(0);
+        }
+        let mut restored = 0;
+        for entry in std::fs::read_dir(&self.trash_dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if !is_checksum_hex(&name) {
+                continue;
+            }
+            if self.contains(&name) {
+                std::fs::remove_file(&path)?;
+                continue;
+            }
+            let dest = self.path(&name);
+            Self::move_into_store(&path, &dest)?;
+            Self::make_read_only(&dest)?;
+            restored += 1;
+        }
+        Ok(restored)
+    }
+
+    fn remove_stale_writes(&self, age: Duration) -> Result<usize> {
+        let Ok(entries) = std::fs::read_dir(self.tmp_dir()) else {
+            return Ok(0);
+        };
+        let cutoff = SystemTime::now()
+            .checked_sub(age)
+            .unwrap_or(std::time::UNIX_EPOCH);
+        let mut removed = 0;
+        for entry in entries {
+            let path = entry?.path();
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified());
+            if path.is_file() && modified.is_ok_and(|t| t <= cutoff) {
+                std::fs::remove_file(&path).context("Failed to remove temp snapshot")?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove shard directories left empty, at any depth.
+    fn compact(&self) -> Result<usize> {
+        if !self.dir.is_dir() {
+            return Ok(0);
+        }
+        Self::prune_shards(&self.dir)
+    }
+
+    fn migrate(&self) -> Result<usize> {
+        let mut moved = 0;
+        for checksum in self.list()? {
+            let dest = self.path(&checksum);
+            if dest.is_file() {
+                continue;
+            }
+            let Some(from) = self.find(&checksum) else {
+                continue;
+            };
+            // Renaming keeps the file read-only
+            Self::move_into_store(&from, &dest)
+                .with_context(|| format!("Failed to move snapshot {}", checksum))?;
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    fn shared_with(&self) -> Result<Option<Vec<PathBuf>>> {
+        self.users.as_ref().map(StoreUsers::others).transpose()
+    }
+
+    fn abandon(&self) -> Result<()> {
+        if let Some(users) = &self.users {
+            let file = users.file();
+            if file.exists() {
+                std::fs::remove_file(file)?;
+            }
+            return Ok(());
+        }
+        for checksum in self.list()? {
+            self.remove(&checksum, false)?;
+        }
+        for dir in [&self.dir, &self.trash_dir] {
+            if dir.exists() {
+                std::fs::remove_dir_all(dir)
+                    .with_context(|| format!("Failed to remove {}", dir.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Temp file in `snapshots/.tmp`, moved into place by `commit`.
+struct FsSnapshotWriter {
+    /// Closed before the move.
+    file: Option<std::fs::File>,
+    tmp_path: PathBuf,
+    snapshots_dir: PathBuf,
+    shard_depth: usize,
+}
+
+impl Write for FsSnapshotWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl SnapshotWriter for FsSnapshotWriter {
+    fn commit(mut self: Box<Self>, checksum: &str) -> Result<()> {
+        if !is_checksum_hex(checksum) {
+            anyhow::bail!("Invalid checksum: {}", checksum);
+        }
+        let mut file = self.file.take().unwrap();
+        file.flush()?;
+        drop(file);
+        if find_shard_path(&self.snapshots_dir, checksum, self.shard_depth).is_some() {
+            return Ok(());
+        }
+        let dest = shard_path(&self.snapshots_dir, checksum, self.shard_depth);
+        FsSnapshotStore::move_into_store(&self.tmp_path, &dest)?;
+        FsSnapshotStore::make_read_only(&dest)?;
+        Ok(())
+    }
+}
+
+impl Drop for FsSnapshotWriter {
+    fn drop(&mut self) {
+        // Gone once committed into the store
+        self.file.take();
+        std::fs::remove_file(&self.tmp_path).ok();
+    }
+}
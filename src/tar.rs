@@ -0,0 +1,137 @@
+//! Just enough of the ustar format for bundle archives (`ftm export-file`): regular
+//! files with names under 100 bytes on write; on read, regular files are listed and
+//! every other member (directories, pax headers, links) is skipped.
+
+use anyhow::{Context, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const BLOCK: usize = 512;
+
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64> {
+    let text = std::str::from_utf8(field).context("Invalid tar header")?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).context("Invalid number in tar header")
+}
+
+fn header_checksum(header: &[u8; BLOCK]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+        .sum()
+}
+
+fn padding(len: u64) -> usize {
+    (BLOCK - (len as usize % BLOCK)) % BLOCK
+}
+
+fn header(name: &str, size: u64, mtime: i64) -> Result<[u8; BLOCK]> {
+    if name.len() >= 100 {
+        anyhow::bail!("Archive member name too long: {}", name);
+    }
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut header[100..108], 0o644);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size);
+    octal(&mut header[136..148], mtime.max(0) as u64);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    let checksum = format!("{:06o}\0 ", header_checksum(&header));
+    header[148..156].copy_from_slice(checksum.as_bytes());
+    Ok(header)
+}
+
+/// Append the regular file `name` with `data`, modified at `mtime` (Unix seconds).
+pub fn append(w: &mut impl Write, name: &str, data: &[u8], mtime: i64) -> Result<()> {
+    w.write_all(&header(name, data.len() as u64, mtime)?)?;
+    w.write_all(data)?;
+    w.write_all(&[0u8; BLOCK][..padding(data.len() as u64)])?;
+    Ok(())
+}
+
+/// Append the regular file `name` streamed from `data`, whose size is not known up
+/// front: the header is written once the content is, and the size is returned.
+pub fn append_from(
+    w: &mut (impl Write + Seek),
+    name: &str,
+    data: &mut (impl Read + ?Sized),
+    mtime: i64,
+) -> Result<u64> {
+    let start = w.stream_position()?;
+    w.write_all(&header(name, 0, mtime)?)?;
+    let size = std::io::copy(data, w)?;
+    w.write_all(&[0u8; BLOCK][..padding(size)])?;
+    let end = w.stream_position()?;
+    w.seek(SeekFrom::Start(start))?;
+    w.write_all(&header(name, size, mtime)?)?;
+    w.seek(SeekFrom::Start(end))?;
+    Ok(size)
+}
+
+/// Write the end-of-archive marker.
+pub fn finish(w: &mut impl Write) -> Result<()> {
+    w.write_all(&[0u8; 2 * BLOCK])?;
+    Ok(())
+}
+
+/// A regular file in an archive: its content is `size` bytes at `offset`.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// List the regular files of the archive in archive order, seeking over their content.
+pub fn members(r: &mut (impl Read + Seek)) -> Result<Vec<Member>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+    let mut files = Vec::new();
+    loop {
+        let mut header = [0u8; BLOCK];
+        match r.read_exact(&mut header) {
+            Ok(()) => {}
+            // Some writers stop without the end-of-archive blocks.
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if parse_octal(&header[148..156])? != header_checksum(&header) {
+            anyhow::bail!("Corrupt tar header (checksum mismatch)");
+        }
+        let field = |range: std::ops::Range<usize>| {
+            let bytes = &header[range];
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).into_owned()
+        };
+        let mut name = field(0..100);
+        let prefix = field(345..500);
+        if &header[257..262] == b"ustar" && !prefix.is_empty() {
+            name = format!("{}/{}", prefix, name);
+        }
+        let size = parse_octal(&header[124..136])?;
+        let offset = r.stream_position()?;
+        if offset + size > len {
+            anyhow::bail!("Archive ends inside '{}'", name);
+        }
+        r.seek(SeekFrom::Start(offset + size + padding(size) as u64))
+            .context("Failed to read archive")?;
+        if matches!(header[156], b'0' | 0) {
+            files.push(Member { name, offset, size });
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,138 @@
+//! Per-file timeline for editor gutters: every version of a file with its line count
+//! and a summary of what changed since the version before.
+
+use crate::storage::Storage;
+use crate::types::{
+    FileTimeline, HistoryDiffSummary, HistoryEntry, LineDelta, Operation, TimelineVersion,
+    VersionChange,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Line changes turning `old` into `new`, using the same Histogram diff as `/api/diff`.
+fn line_delta(old: &str, new: &str) -> LineDelta {
+    use imara_diff::{Algorithm, Diff, InternedInput};
+
+    let input = InternedInput::new(old, new);
+    let mut diff = Diff::compute(Algorithm::Histogram, &input);
+    diff.postprocess_lines(&input);
+    LineDelta {
+        added: diff.count_additions() as usize,
+        removed: diff.count_removals() as usize,
+        hunks: diff.hunks().count(),
+    }
+}
+
+/// For each version in `entries`, the snapshots its line changes are counted between:
+/// the content before it (deletes keep it, so a re-created file compares with the
+/// content it had before the delete) and its own. None for deletes.
+pub fn version_pairs(entries: &[HistoryEntry]) -> Vec<Option<SnapshotPair>> {
+    let mut prev: Option<String> = None;
+    entries
+        .iter()
+        .map(|entry| {
+            if entry.op == Operation::Delete {
+                return None;
+            }
+            let pair = (prev.clone(), entry.checksum.clone());
+            prev = entry.checksum.clone();
+            Some(pair)
+        })
+        .collect()
+}
+
+/// Line changes between the two sides of `pair`; None when either is not text.
+pub fn pair_delta(storage: &Storage, pair: &SnapshotPair) -> Option<LineDelta> {
+    let old = snapshot_text(storage, pair.0.as_deref())?;
+    let new = snapshot_text(storage, pair.1.as_deref())?;
+    Some(line_delta(&old, &new))
+}
+
+/// Timeline of `file` from its `entries`, oldest first, and the line changes of each
+/// (see `version_pairs`). Line counts are the ones recorded with the versions.
+pub fn file_timeline(
+    file: &str,
+    entries: Vec<HistoryEntry>,
+    deltas: Vec<Option<LineDelta>>,
+) -> FileTimeline {
+    let versions = entries
+        .into_iter()
+        .zip(deltas)
+        .map(|(entry, delta)| TimelineVersion {
+            seq: entry.seq,
+            timestamp: entry.timestamp,
+            op: entry.op,
+            checksum: entry.checksum,
+            size: entry.size,
+            lines: entry.lines,
+            delta,
+        })
+        .collect();
+    FileTimeline {
+        file: file.to_string(),
+        versions,
+    }
+}
+
+/// Snapshot pair: (previous, current) checksum, None for empty content.
+pub type SnapshotPair = (Option<String>, Option<String>);
+
+/// Line changes per snapshot pair, kept across requests. Snapshots are
+/// content-addressed, so an entry never goes stale. None: a side is not text.
+#[derive(Default)]
+pub struct LineCountCache {
+    counts: Mutex<HashMap<SnapshotPair, Option<LineDelta>>>,
+}
+
+impl LineCountCache {
+    /// Pairs held before the cache starts over.
+    const MAX_PAIRS: usize = 65536;
+
+    pub fn get(&self, pair: &SnapshotPair) -> Option<Option<LineDelta>> {
+        self.counts.lock().unwrap().get(pair).cloned()
+    }
+
+    pub fn insert(&self, pair: SnapshotPair, delta: Option<LineDelta>) {
+        let mut map = self.counts.lock().unwrap();
+        if map.len() >= Self::MAX_PAIRS {
+            map.clear();
+        }
+        map.insert(pair, delta);
+    }
+}
+
+/// Text of a snapshot (None: empty content); None when unreadable or not UTF-8.
+fn snapshot_text(storage: &Storage, checksum: Option<&str>) -> Option<String> {
+    match checksum {
+        Some(checksum) => storage
+            .read_snapshot(checksum)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok()),
+        None => Some(String::new()),
+    }
+}
+
+/// Added/removed line counts of every version of `file` from its `entries`, oldest
+/// first, and the line changes of each (see `version_pairs`).
+pub fn history_diff_summary(
+    file: &str,
+    entries: Vec<HistoryEntry>,
+    deltas: Vec<Option<LineDelta>>,
+) -> HistoryDiffSummary {
+    let versions = entries
+        .into_iter()
+        .zip(deltas)
+        .map(|(entry, delta)| VersionChange {
+            seq: entry.seq,
+            timestamp: entry.timestamp,
+            op: entry.op,
+            checksum: entry.checksum,
+            added: delta.as_ref().map(|d| d.added),
+            removed: delta.map(|d| d.removed),
+        })
+        .collect();
+    HistoryDiffSummary {
+        file: file.to_string(),
+        versions,
+    }
+}
@@ -0,0 +1,115 @@
+//! System-tray icon for `ftm tray` (cargo feature `tray`). The icon shows whether the
+//! server is watching, paused or unreachable, and its menu opens the Web UI, pauses or
+//! resumes recording and stops the server, each through this binary's client commands.
+//! It is drawn by `yad --notification`, driven over its stdin, so no GUI toolkit is
+//! linked into ftm.
+
+use crate::client;
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::Duration;
+
+/// How often the icon is brought up to date with the server.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(PartialEq)]
+enum Status {
+    Watching(String),
+    Paused(String),
+    Unreachable,
+}
+
+impl Status {
+    fn of(port: u16) -> Self {
+        match client::client_health(port) {
+            Ok(health) if health.paused => Status::Paused(health.watch_dir.unwrap_or_default()),
+            Ok(health) => Status::Watching(health.watch_dir.unwrap_or_default()),
+            Err(_) => Status::Unreachable,
+        }
+    }
+
+    fn icon(&self) -> &'static str {
+        match self {
+            Status::Watching(_) => "media-record",
+            Status::Paused(_) => "media-playback-pause",
+            Status::Unreachable => "dialog-error",
+        }
+    }
+
+    fn tooltip(&self, port: u16) -> String {
+        match self {
+            Status::Watching(dir) => format!("FTM: watching {}", dir),
+            Status::Paused(dir) => format!("FTM: paused ({})", dir),
+            Status::Unreachable => format!("FTM: no server on port {}", port),
+        }
+    }
+}
+
+/// Shell-quoted command line running this ftm binary against `port`. yad's menu
+/// syntax has no escapes, so a binary path with `!`, `|` or a line break is refused.
+fn ftm_command(port: u16) -> Result<String> {
+    let exe = std::env::current_exe().context("Cannot locate the ftm binary")?;
+    let exe = exe.to_string_lossy();
+    if exe.contains(['!', '|', '\n', '\r']) {
+        anyhow::bail!(
+            "ftm tray cannot put {} in its menu: move ftm to a path without '!', '|' or line breaks",
+            exe
+        );
+    }
+    let quoted = exe.replace('\'', r"'\''");
+    Ok(format!("'{}' --port {}", quoted, port))
+}
+
+/// yad's popup menu: `name!command` items separated by `|`; `quit` closes the tray.
+fn menu(ftm: &str, status: &Status) -> String {
+    let toggle = match status {
+        Status::Paused(_) => format!("Resume recording!{} resume", ftm),
+        _ => format!("Pause recording!{} pause", ftm),
+    };
+    format!(
+        "menu:Open Web UI!{ftm} open|{toggle}|Stop server!{ftm} stop|Quit tray!quit",
+        ftm = ftm,
+        toggle = toggle
+    )
+}
+
+fn spawn_icon(ftm: &str) -> Result<Child> {
+    Command::new("yad")
+        .args(["--notification", "--listen"])
+        .arg(format!("--command={} open", ftm))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("ftm tray needs 'yad' installed to draw its icon")
+}
+
+fn show(stdin: &mut ChildStdin, ftm: &str, status: &Status, port: u16) -> std::io::Result<()> {
+    writeln!(stdin, "icon:{}", status.icon())?;
+    writeln!(stdin, "tooltip:{}", status.tooltip(port))?;
+    writeln!(stdin, "{}", menu(ftm, status))?;
+    stdin.flush()
+}
+
+/// Show the icon for the server on `port` until it is quit from its menu.
+pub fn run(port: u16) -> Result<()> {
+    let ftm = ftm_command(port)?;
+    let mut icon = spawn_icon(&ftm)?;
+    let mut stdin = icon.stdin.take().context("No stdin for the tray icon")?;
+    let mut shown = None;
+    loop {
+        if icon.try_wait()?.is_some() {
+            return Ok(());
+        }
+        let status = Status::of(port);
+        if shown.as_ref() != Some(&status) {
+            // A closed pipe means the icon was quit between the two checks
+            if show(&mut stdin, &ftm, &status, port).is_err() {
+                return Ok(());
+            }
+            shown = Some(status);
+        }
+        std::thread::sleep(REFRESH_INTERVAL);
+    }
+}
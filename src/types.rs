@@ -1,12 +1,46 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum Operation {
     Create,
     Modify,
     Delete,
+    /// The file was emptied (0 bytes); recorded without a snapshot.
+    Truncate,
+}
+
+/// What recorded a history entry.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum EntrySource {
+    /// The file watcher, right after the change.
+    Watcher,
+    /// A periodic scan or the scan after checkout, catching what the watcher missed.
+    Scan,
+    /// A command: `ftm scan` or `ftm rm`.
+    Manual,
+    /// `ftm restore` writing an earlier version back.
+    Restore,
+    /// The watcher's scan after a git branch switch (`settings.git_aware`).
+    Git,
+    /// Content uploaded through `POST /api/snapshot` by an external tool.
+    Upload,
+}
+
+impl std::fmt::Display for EntrySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntrySource::Watcher => write!(f, "watcher"),
+            EntrySource::Scan => write!(f, "scan"),
+            EntrySource::Manual => write!(f, "manual"),
+            EntrySource::Restore => write!(f, "restore"),
+            EntrySource::Git => write!(f, "git"),
+            EntrySource::Upload => write!(f, "upload"),
+        }
+    }
 }
 
 impl std::fmt::Display for Operation {
@@ -15,27 +49,261 @@ impl std::fmt::Display for Operation {
             Operation::Create => write!(f, "create"),
             Operation::Modify => write!(f, "modify"),
             Operation::Delete => write!(f, "delete"),
+            Operation::Truncate => write!(f, "truncate"),
+        }
+    }
+}
+
+/// Hash algorithm of a snapshot checksum. New snapshots use BLAKE3; entries recorded
+/// before it was introduced carry no tag and are SHA-256.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgo {
+    pub fn is_sha256(&self) -> bool {
+        *self == HashAlgo::Sha256
+    }
+}
+
+impl std::fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashAlgo::Sha256 => write!(f, "sha256"),
+            HashAlgo::Blake3 => write!(f, "blake3"),
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HistoryEntry {
+    /// Position in this index's history, assigned when the entry is recorded here and
+    /// never reused. History is ordered and trimmed by seq; the timestamp is for display.
+    #[serde(default)]
+    pub seq: u64,
     pub timestamp: DateTime<Utc>,
     pub op: Operation,
     pub file: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub checksum: Option<String>,
+    /// Algorithm that produced `checksum`.
+    #[serde(default, skip_serializing_if = "HashAlgo::is_sha256")]
+    pub algo: HashAlgo,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub size: Option<u64>,
     /// File mtime in nanoseconds since Unix epoch; used for fast skip (avoids same-second false skip).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mtime_nanos: Option<i64>,
+    /// Line count of the version, counted when it was recorded; absent for deletes,
+    /// content that is not UTF-8 text, and entries recorded before it was introduced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<usize>,
+    /// Detected MIME type and highlighting language of the version's snapshot (see
+    /// `content_type`); absent for entries without a snapshot and older entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Name of the process that wrote the version (e.g. `vim`), when
+    /// `settings.track_writers` was on and it could be told.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub writer: Option<String>,
+    /// Set on deletes made through `ftm rm`, telling them apart from files that went
+    /// missing.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub user_initiated: bool,
+    /// Set on deletes of files found in the desktop trash (Trash / Recycle Bin) when the
+    /// delete was recorded; `ftm restore --from-trash` brings their last version back.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub trashed: bool,
+    /// What recorded the entry; absent for entries recorded before it was introduced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<EntrySource>,
+    /// On entries recorded by a restore, the time of the version it wrote back, which
+    /// with `file` and `checksum` identifies the entry restored from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restored_from: Option<DateTime<Utc>>,
+    /// Hex ed25519 signature over the entry, when `settings.sign_history` was on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sig: Option<String>,
+    /// On signed entries merged in by push/pull, the seq they had (and were signed with)
+    /// in the index that recorded them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_seq: Option<u64>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Index {
     pub history: Vec<HistoryEntry>,
+    /// Seq given to the next recorded entry; never decreases, even when history is trimmed.
+    #[serde(default)]
+    pub next_seq: u64,
+    /// Operations that are not file versions (restores, config changes, cleans). They
+    /// share the seq counter with history so the two interleave in recorded order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<IndexEvent>,
+    /// Hex ed25519 signature over the signatures of the signed entries in seq order, so
+    /// removing or reordering them is detected. Kept while `settings.sign_history` is on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_sig: Option<String>,
+}
+
+impl Index {
+    /// Append an entry, assigning it the next seq (starting at 1). Returns its position.
+    pub fn push(&mut self, mut entry: HistoryEntry) -> usize {
+        self.next_seq = self.next_seq.max(1);
+        entry.seq = self.next_seq;
+        self.next_seq += 1;
+        self.history.push(entry);
+        self.history.len() - 1
+    }
+
+    /// Bring a loaded index up to date. Entries written before seq existed (seq 0) are
+    /// numbered in their stored order; otherwise history is kept sorted by seq.
+    pub fn normalize_seq(&mut self) {
+        if self.history.iter().any(|e| e.seq == 0) {
+            for (i, entry) in self.history.iter_mut().enumerate() {
+                entry.seq = i as u64 + 1;
+            }
+        } else if !self.history.is_sorted_by_key(|e| e.seq) {
+            self.history.sort_by_key(|e| e.seq);
+        }
+        let end = self.history.last().map_or(1, |e| e.seq + 1);
+        let events_end = self.events.last().map_or(1, |e| e.seq + 1);
+        self.next_seq = self.next_seq.max(end).max(events_end);
+    }
+
+    /// Append an event, assigning it the next seq.
+    pub fn push_event(&mut self, kind: EventKind) {
+        self.next_seq = self.next_seq.max(1);
+        self.events.push(IndexEvent {
+            seq: self.next_seq,
+            timestamp: Utc::now(),
+            kind,
+        });
+        self.next_seq += 1;
+    }
+}
+
+/// A recorded operation other than a file version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEvent {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum EventKind {
+    /// A file was overwritten with the content of an earlier version.
+    Restore { file: String, checksum: String },
+    /// `ftm config set`; values are as shown by `ftm config get`.
+    ConfigChange {
+        key: String,
+        old_value: String,
+        new_value: String,
+    },
+    /// A clean that removed something.
+    Clean {
+        entries_trimmed: usize,
+        #[serde(default)]
+        entries_collapsed: usize,
+        files_removed: usize,
+        trash_purged: usize,
+    },
+    /// Trashed entries and snapshots put back.
+    Unclean {
+        entries_restored: usize,
+        snapshots_restored: usize,
+    },
+}
+
+impl EventKind {
+    /// The `op` it is serialized with.
+    pub fn op(&self) -> &'static str {
+        match self {
+            EventKind::Restore { .. } => "restore",
+            EventKind::ConfigChange { .. } => "config_change",
+            EventKind::Clean { .. } => "clean",
+            EventKind::Unclean { .. } => "unclean",
+        }
+    }
+}
+
+/// One line of `ftm audit`: a history entry or an event, ordered by seq.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AuditRecord {
+    Entry(HistoryEntry),
+    Event(IndexEvent),
+}
+
+impl AuditRecord {
+    pub fn seq(&self) -> u64 {
+        match self {
+            AuditRecord::Entry(e) => e.seq,
+            AuditRecord::Event(e) => e.seq,
+        }
+    }
+
+    pub fn op(&self) -> String {
+        match self {
+            AuditRecord::Entry(e) => e.op.to_string(),
+            AuditRecord::Event(e) => e.kind.op().to_string(),
+        }
+    }
+}
+
+/// One version involved in a restore conflict.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConflictSide {
+    pub checksum: String,
+    pub size: u64,
+    /// When the version was recorded; absent for the working copy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// The text, when the version is UTF-8 and small enough to show.
+    pub content: Option<String>,
+}
+
+/// A restore that would overwrite changes not yet in history: the working copy, the
+/// version to restore, and the latest recorded version the working copy was edited
+/// from, unless the file had been deleted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreConflict {
+    pub file: String,
+    pub working: ConflictSide,
+    pub snapshot: ConflictSide,
+    pub ancestor: Option<ConflictSide>,
+}
+
+/// A history entry as served by `/api/history`, with its size change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryVersion {
+    #[serde(flatten)]
+    pub entry: HistoryEntry,
+    /// Bytes gained (negative: lost) since the file's previous entry; a delete loses
+    /// the whole size. Absent when either size is unknown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_delta: Option<i64>,
+    /// Lines gained (negative: lost) since the previous entry, from the recorded line
+    /// counts. Absent when either count is unknown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines_delta: Option<i64>,
+}
+
+/// History entry removed by trim, kept in `.ftm/trash/` until the retention window passes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedEntry {
+    pub trashed_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub entry: HistoryEntry,
 }
 
 /// Result of clean (trim + orphan removal): counts for both phases.
@@ -45,10 +313,289 @@ pub struct CleanResult {
     pub entries_trimmed: usize,
     /// Bytes freed by trim (snapshots deleted due to trim).
     pub bytes_freed_trim: u64,
+    /// Entries dropped for repeating the previous version of their file.
+    #[serde(default)]
+    pub entries_collapsed: usize,
     /// Orphan snapshot files removed (not referenced by any history).
     pub files_removed: usize,
     /// Bytes freed by orphan removal.
     pub bytes_removed: u64,
+    /// Trashed snapshot files permanently deleted after the retention window.
+    pub trash_purged: usize,
+    /// Temp files of interrupted snapshot writes removed from `snapshots/.tmp`.
+    #[serde(default)]
+    pub tmp_removed: usize,
+    /// Empty shard directories removed under `snapshots/`.
+    #[serde(default)]
+    pub dirs_removed: usize,
+}
+
+/// Daily sample of storage usage, kept in `.ftm/stats.json` to show growth over time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageSample {
+    pub at: DateTime<Utc>,
+    pub history: usize,
+    pub quota: u64,
+}
+
+/// How effective content addressing is: every versioned entry references a snapshot,
+/// but identical content is stored only once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DedupStats {
+    /// History entries referencing a snapshot.
+    pub references: usize,
+    /// Distinct snapshots those entries reference.
+    pub snapshots: usize,
+    /// Bytes the references would take if each version were stored separately.
+    pub logical_bytes: u64,
+    /// Bytes of the referenced snapshot files on disk.
+    pub physical_bytes: u64,
+    /// References that reused an already stored snapshot (`references - snapshots`).
+    pub duplicate_references: usize,
+}
+
+/// Referenced snapshots whose newest version was recorded in one age range
+/// (`/api/stats/age`). A snapshot can only be trimmed away once that version is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgeBucket {
+    /// `today` (since local midnight), `this_week` (the 7 days before), `this_month`
+    /// (within 30 days) or `older`.
+    pub age: String,
+    pub snapshots: usize,
+    pub bytes: u64,
+}
+
+/// History statistics of a single file (`ftm stats <file>`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileStats {
+    pub file: String,
+    /// History entries recorded for the file, deletes included.
+    pub versions: usize,
+    /// Distinct snapshots those entries reference.
+    pub snapshots: usize,
+    /// Bytes of those snapshots on disk.
+    pub snapshot_bytes: u64,
+    /// Part of `snapshot_bytes` also referenced by other files' history, so not freed
+    /// if this file's history went away.
+    pub shared_bytes: u64,
+    pub oldest: Option<DateTime<Utc>>,
+    pub newest: Option<DateTime<Utc>>,
+    /// Mean time between consecutive entries; None with fewer than two.
+    pub avg_interval_secs: Option<f64>,
+    /// Older versions no longer in history because trim removed them.
+    pub trimmed_versions: usize,
+}
+
+/// What the file watcher has seen and recorded since checkout (`/api/stats/watcher`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatcherStats {
+    /// Every filesystem event delivered by the OS.
+    pub events_received: u64,
+    /// Mutations outside `.ftm/`, the events that trigger a scan.
+    pub events_relevant: u64,
+    /// Debounced batches of relevant events, each followed by one scan.
+    pub batches: u64,
+    /// Most relevant events in one batch.
+    pub largest_batch: u64,
+    pub snapshots_saved: u64,
+    /// Files examined by watcher scans whose content had not changed.
+    pub unchanged_skipped: u64,
+    pub deletes_recorded: u64,
+    /// Changed files left for a later scan by `settings.max_snapshots_per_minute`.
+    pub deferred: u64,
+    pub scan_errors: u64,
+    /// Times the OS reported dropped events (e.g. a queue overflow), each answered with
+    /// a full scan.
+    pub overflows: u64,
+    /// When the last relevant event arrived.
+    pub last_event_at: Option<DateTime<Utc>>,
+    /// When a watcher scan last saved a snapshot.
+    pub last_snapshot_at: Option<DateTime<Utc>>,
+}
+
+/// Progress and findings of background integrity verification, kept in `.ftm/verify.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VerifyReport {
+    /// Last checksum verified; the next round continues after it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<DateTime<Utc>>,
+    /// Referenced snapshots whose content no longer matches their checksum.
+    #[serde(default)]
+    pub corrupt: Vec<String>,
+    /// Referenced snapshots whose file is gone.
+    #[serde(default)]
+    pub missing: Vec<String>,
+}
+
+/// Per-directory state kept in `.ftm/state.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DirState {
+    /// The last full (manual, periodic or post-checkout) scan to complete.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_scan: Option<LastScan>,
+    /// The most recent cleans that removed something, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maintenance: Vec<MaintenanceRecord>,
+    /// Versions of each file removed from history by trim (max_history / max_quota),
+    /// less those `unclean` brought back.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub trimmed: BTreeMap<String, usize>,
+}
+
+/// A clean (manual, periodic or gc) that trimmed history or removed snapshots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceRecord {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub result: CleanResult,
+}
+
+/// When a full scan completed and what it recorded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastScan {
+    pub at: DateTime<Utc>,
+    pub created: usize,
+    pub modified: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+}
+
+/// Outcome of one verification round.
+#[derive(Debug, Clone)]
+pub struct VerifyRound {
+    pub checked: usize,
+    pub newly_corrupt: Vec<String>,
+    pub newly_missing: Vec<String>,
+    /// The report as saved after the round.
+    pub report: VerifyReport,
+}
+
+/// Result of checking history entry signatures against a public key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureReport {
+    /// Hex ed25519 public key the signatures were checked against.
+    pub public_key: String,
+    pub valid: usize,
+    pub unsigned: usize,
+    /// Entries whose signature does not match their content.
+    pub invalid: Vec<InvalidSignature>,
+    /// Whether the signed entries are all there and in order; None when history
+    /// carries no signature over them.
+    pub complete: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvalidSignature {
+    pub seq: u64,
+    pub file: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CopyKind {
+    Copy,
+    /// The source file was deleted around the time the new file appeared.
+    Rename,
+}
+
+/// A new file whose first version closely matches another tracked file's version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimilarFile {
+    pub file: String,
+    pub checksum: String,
+    pub timestamp: DateTime<Utc>,
+    pub source_file: String,
+    pub source_checksum: String,
+    /// Percentage of lines the two versions share.
+    pub similarity: u8,
+    pub kind: CopyKind,
+}
+
+/// Line changes between a version and the one before it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LineDelta {
+    pub added: usize,
+    pub removed: usize,
+    /// Number of separate changed regions.
+    pub hunks: usize,
+}
+
+/// One entry of a file's timeline (`/api/file-timeline`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimelineVersion {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub op: Operation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Line count recorded with the version; absent for deletes, content that is not
+    /// UTF-8 text, and versions recorded before line counts were.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<usize>,
+    /// Changes since the previous version (the first one is compared with an empty
+    /// file); absent when either side is not text or the diff ran out of time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<LineDelta>,
+}
+
+/// A file's full version list with per-version line changes, in one response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileTimeline {
+    pub file: String,
+    pub versions: Vec<TimelineVersion>,
+}
+
+/// Lines added and removed by one version of a file (`/api/history-diff-summary`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionChange {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub op: Operation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Counts against the previous content version (the first one is compared with an
+    /// empty file); absent for deletes, when either side is not text, and when the diff
+    /// ran out of time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed: Option<usize>,
+}
+
+/// Change sizes of every version of a file, oldest first, for sparklines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryDiffSummary {
+    pub file: String,
+    pub versions: Vec<VersionChange>,
+}
+
+/// Result of unclean: what was recovered from the trash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UncleanResult {
+    pub entries_restored: usize,
+    pub snapshots_restored: usize,
+}
+
+/// An unreferenced snapshot file, as listed by `/api/orphans`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanSnapshot {
+    pub checksum: String,
+    pub size: u64,
+    /// When the snapshot file was last written; absent when it cannot be told.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// Result of migrate-store: snapshots moved into the configured shard depth.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateStoreResult {
+    pub snapshots_moved: usize,
+    /// Shard directories left empty by the move and removed.
+    pub dirs_removed: usize,
 }
 
 /// Tree node for structured file listing (ls). Directories have children; files have count.
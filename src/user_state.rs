@@ -0,0 +1,68 @@
+//! User-level state shared by all servers of this user (not per directory).
+//! Stored as `state.json` in the platform state directory (`$XDG_STATE_HOME/ftm`,
+//! falling back to the local data directory), or in `$FTM_STATE_DIR` when set.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const STATE_FILE: &str = "state.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UserState {
+    /// Directory of the most recent successful checkout; `ftm serve --resume` re-checks it out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_checkout: Option<PathBuf>,
+}
+
+/// Directory holding the user state file.
+pub fn state_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("FTM_STATE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|d| d.join("ftm"))
+}
+
+/// Write a newly made secret to `path`, readable only by the user, unless another
+/// process created it first; either way `path` then holds the one every process uses.
+pub fn create_secret(path: &Path, secret: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    std::fs::write(&tmp, secret).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600))?;
+    }
+    // Linking fails if the file exists, so a secret another process handed out is
+    // never replaced.
+    let linked = std::fs::hard_link(&tmp, path);
+    let _ = std::fs::remove_file(&tmp);
+    match linked {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+            Err(e).with_context(|| format!("Failed to write {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl UserState {
+    /// Load the state file; missing or unreadable state is treated as empty.
+    pub fn load() -> Self {
+        state_dir()
+            .and_then(|dir| std::fs::read_to_string(dir.join(STATE_FILE)).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let dir = state_dir().context("No user state directory available")?;
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(STATE_FILE), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
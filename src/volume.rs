@@ -0,0 +1,84 @@
+//! The volume holding a watched directory. A removable drive that unmounts takes `.ftm`
+//! with it; until it returns nothing may scan the directory (every file would look
+//! deleted), and the server waits for it rather than shutting down as it does when
+//! `.ftm` itself is deleted.
+
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeState {
+    Present,
+    Unmounted,
+    FtmDeleted,
+}
+
+pub struct Volume {
+    dir: PathBuf,
+    /// Device of `dir` when `.ftm` was last seen in it.
+    dev: AtomicU64,
+    /// Unix milliseconds; 0 = mounted.
+    unmounted_since: AtomicI64,
+}
+
+#[cfg(unix)]
+fn device(dir: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(dir).ok().map(|m| m.dev())
+}
+
+#[cfg(not(unix))]
+fn device(dir: &Path) -> Option<u64> {
+    dir.is_dir().then_some(0)
+}
+
+impl Volume {
+    pub fn new(dir: PathBuf) -> Self {
+        let dev = device(&dir).unwrap_or_default();
+        Self {
+            dir,
+            dev: AtomicU64::new(dev),
+            unmounted_since: AtomicI64::new(0),
+        }
+    }
+
+    /// Present while `.ftm` is there. Without it, the directory was unmounted when it is
+    /// gone or now on another device (an empty mount point); otherwise `.ftm` was deleted.
+    pub fn state(&self) -> VolumeState {
+        let dev = device(&self.dir);
+        if self.dir.join(".ftm").is_dir() {
+            if let Some(dev) = dev {
+                self.dev.store(dev, Ordering::Relaxed);
+            }
+            return VolumeState::Present;
+        }
+        match dev {
+            Some(dev) if dev == self.dev.load(Ordering::Relaxed) => VolumeState::FtmDeleted,
+            _ => VolumeState::Unmounted,
+        }
+    }
+
+    /// Record whether the volume is away; returns whether that changed.
+    pub fn set_unmounted(&self, unmounted: bool) -> bool {
+        let since = if unmounted {
+            Utc::now().timestamp_millis()
+        } else {
+            0
+        };
+        let was = self.unmounted_since.load(Ordering::Relaxed);
+        if (was != 0) == unmounted {
+            return false;
+        }
+        self.unmounted_since.store(since, Ordering::Relaxed);
+        true
+    }
+
+    /// When the volume was found unmounted; None while it is mounted.
+    pub fn unmounted_since(&self) -> Option<DateTime<Utc>> {
+        match self.unmounted_since.load(Ordering::Relaxed) {
+            0 => None,
+            ms => DateTime::from_timestamp_millis(ms),
+        }
+    }
+}
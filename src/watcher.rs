@@ -1,23 +1,401 @@
-use crate::config::Config;
-use crate::scanner::Scanner;
-use crate::storage::Storage;
+use crate::config::{Config, SnapshotTrigger};
+use crate::scanner::{DirCache, Scanner};
+use crate::storage::{CachedIndex, Storage};
+use crate::types::{EntrySource, WatcherStats};
+use crate::volume::{Volume, VolumeState};
+use crate::writers::WriterLog;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
-use std::path::PathBuf;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
 use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
-use tracing::info;
+use tracing::{info, warn};
+
+/// Quiet time after the last triggering event before the scan runs.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// Time between scans under `SnapshotTrigger::Poll`.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the watcher checks, when idle, that its volume is still mounted.
+const VOLUME_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// A git operation holding `.git/index.lock` longer than this is taken to have died and
+/// left the lock behind.
+const GIT_LOCK_WAIT: Duration = Duration::from_secs(60);
+
+/// Token bucket behind `settings.max_snapshots_per_minute`: holds up to one minute's
+/// allowance and refills continuously.
+struct SnapshotBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl SnapshotBucket {
+    fn new() -> Self {
+        Self {
+            tokens: f64::INFINITY,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Whole snapshots available under a limit of `per_minute`.
+    fn available(&mut self, per_minute: u64) -> usize {
+        let now = Instant::now();
+        let rate = per_minute as f64 / 60.0;
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(per_minute as f64);
+        self.refilled = now;
+        self.tokens.floor() as usize
+    }
+
+    /// Time until the next snapshot is available under a limit of `per_minute`.
+    fn wait(&self, per_minute: u64) -> Duration {
+        let missing = (1.0 - self.tokens).max(0.0);
+        Duration::from_secs_f64(missing * 60.0 / per_minute as f64)
+    }
+
+    fn spend(&mut self, n: usize) {
+        self.tokens = (self.tokens - n as f64).max(0.0);
+    }
+}
+
+/// Directories registered with the OS watcher. Without `watch.max_depth` each root
+/// (`watch.roots`, or the watched directory) is watched recursively; with it each
+/// directory down to that depth gets its own non-recursive watch, so deeper trees cost
+/// nothing. With `watch.roots` the watched directory itself is also watched
+/// non-recursively, so a root created later triggers a scan.
+struct WatchedDirs {
+    root_dir: PathBuf,
+    recursive: HashSet<PathBuf>,
+    dirs: HashSet<PathBuf>,
+    /// `watch` settings the watches were registered for.
+    watch: String,
+}
+
+impl WatchedDirs {
+    fn new(root_dir: PathBuf) -> Self {
+        Self {
+            root_dir,
+            recursive: HashSet::new(),
+            dirs: HashSet::new(),
+            watch: String::new(),
+        }
+    }
+
+    /// Bring the registered watches in line with `cfg` and the directories now on disk.
+    /// With `watch.max_depth`, while the `watch` settings stay the same, only the roots
+    /// and the `touched` paths (those the batch's events named) are looked at instead
+    /// of walking every root again; None walks them all.
+    fn sync(
+        &mut self,
+        watcher: &mut RecommendedWatcher,
+        cfg: &Config,
+        touched: Option<&HashSet<PathBuf>>,
+    ) -> Result<()> {
+        let watch = serde_json::to_string(&cfg.watch).unwrap_or_default();
+        if let Some(touched) = touched.filter(|_| cfg.watch.max_depth > 0 && watch == self.watch) {
+            self.sync_touched(watcher, cfg, touched);
+            return Ok(());
+        }
+        self.watch = watch;
+
+        let roots: Vec<PathBuf> = cfg
+            .root_dirs(&self.root_dir)
+            .into_iter()
+            .filter(|dir| dir.is_dir())
+            .collect();
+        let mut recursive = HashSet::new();
+        let mut flat = HashSet::new();
+        if !cfg.watch_roots().is_empty() {
+            flat.insert(self.root_dir.clone());
+        }
+        for root in roots {
+            if cfg.watch.max_depth == 0 {
+                recursive.insert(root);
+            } else {
+                let depth = root
+                    .strip_prefix(&self.root_dir)
+                    .map_or(0, |rel| rel.components().count());
+                Self::collect(&self.root_dir, &root, depth, cfg, &mut flat);
+            }
+        }
+
+        // Removals first, so a directory changing mode is registered afresh
+        for (current, wanted) in [(&mut self.recursive, &recursive), (&mut self.dirs, &flat)] {
+            current.retain(|dir| {
+                let keep = wanted.contains(dir);
+                if !keep {
+                    let _ = watcher.unwatch(dir);
+                }
+                keep
+            });
+        }
+        for (current, wanted, mode) in [
+            (&mut self.recursive, recursive, RecursiveMode::Recursive),
+            (&mut self.dirs, flat, RecursiveMode::NonRecursive),
+        ] {
+            for dir in wanted {
+                if current.contains(&dir) {
+                    continue;
+                }
+                match watcher.watch(&dir, mode) {
+                    Ok(()) => {
+                        current.insert(dir);
+                    }
+                    Err(e) if dir == self.root_dir => return Err(e.into()),
+                    // A directory removed since it was listed is simply not watched
+                    Err(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Watch the roots not yet watched and the directories created or moved in at
+    /// `touched`, with everything below them down to `watch.max_depth`; stop watching
+    /// those removed or moved away.
+    fn sync_touched(
+        &mut self,
+        watcher: &mut RecommendedWatcher,
+        cfg: &Config,
+        touched: &HashSet<PathBuf>,
+    ) {
+        let roots = cfg.root_dirs(&self.root_dir);
+        let mut gone = Vec::new();
+        let mut wanted = HashSet::new();
+        for path in roots.iter().chain(touched) {
+            if !path.is_dir() {
+                if self.dirs.contains(path) {
+                    gone.push(path);
+                }
+                continue;
+            }
+            if self.dirs.contains(path) {
+                continue;
+            }
+            let in_root = roots.contains(path)
+                || (roots.iter().any(|root| path.starts_with(root))
+                    && path
+                        .parent()
+                        .is_some_and(|parent| self.dirs.contains(parent))
+                    && !cfg.is_excluded_dir(path, &self.root_dir));
+            let Some(depth) = path
+                .strip_prefix(&self.root_dir)
+                .ok()
+                .map(|rel| rel.components().count())
+                .filter(|depth| in_root && cfg.within_max_depth(*depth))
+            else {
+                continue;
+            };
+            Self::collect(&self.root_dir, path, depth, cfg, &mut wanted);
+        }
+
+        self.dirs.retain(|dir| {
+            let keep = !gone.iter().any(|path| dir.starts_with(path));
+            if !keep {
+                let _ = watcher.unwatch(dir);
+            }
+            keep
+        });
+        for dir in wanted {
+            if !self.dirs.contains(&dir) && watcher.watch(&dir, RecursiveMode::NonRecursive).is_ok()
+            {
+                self.dirs.insert(dir);
+            }
+        }
+    }
+
+    fn collect(root: &Path, dir: &Path, depth: usize, cfg: &Config, out: &mut HashSet<PathBuf>) {
+        out.insert(dir.to_path_buf());
+        if !cfg.within_max_depth(depth + 1) {
+            return;
+        }
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() && !cfg.is_excluded_dir(&path, root) {
+                Self::collect(root, &path, depth + 1, cfg, out);
+            }
+        }
+    }
+}
+
+/// Git state of the watched directory, for `settings.git_aware`.
+struct GitWatch {
+    git_dir: PathBuf,
+    /// `.git/HEAD` as of the last batch.
+    head: Option<String>,
+    /// When `.git/index.lock` was first seen, while it lasts.
+    locked_since: Option<Instant>,
+}
+
+impl GitWatch {
+    fn new(root_dir: &Path) -> Self {
+        let git_dir = root_dir.join(".git");
+        let head = std::fs::read_to_string(git_dir.join("HEAD")).ok();
+        Self {
+            git_dir,
+            head,
+            locked_since: None,
+        }
+    }
+
+    /// A git operation is under way: it holds the index lock.
+    fn busy(&mut self) -> bool {
+        if !self.git_dir.join("index.lock").exists() {
+            self.locked_since = None;
+            return false;
+        }
+        let since = *self.locked_since.get_or_insert_with(Instant::now);
+        since.elapsed() < GIT_LOCK_WAIT
+    }
+
+    /// The branch (or commit) HEAD now names, if it changed since the last call.
+    fn switched(&mut self) -> Option<String> {
+        let head = std::fs::read_to_string(self.git_dir.join("HEAD")).ok();
+        if head == self.head {
+            return None;
+        }
+        let previous = std::mem::replace(&mut self.head, head.clone());
+        previous.and(head).map(|head| {
+            let head = head.trim();
+            head.strip_prefix("ref: refs/heads/")
+                .unwrap_or(head)
+                .to_string()
+        })
+    }
+}
+
+/// Counters updated by the watcher thread; see `WatcherStats`.
+#[derive(Default)]
+pub struct WatcherCounters {
+    events_received: AtomicU64,
+    events_relevant: AtomicU64,
+    batches: AtomicU64,
+    largest_batch: AtomicU64,
+    snapshots_saved: AtomicU64,
+    unchanged_skipped: AtomicU64,
+    deletes_recorded: AtomicU64,
+    deferred: AtomicU64,
+    scan_errors: AtomicU64,
+    overflows: AtomicU64,
+    /// Unix milliseconds; 0 = never.
+    last_event_at: AtomicI64,
+    last_snapshot_at: AtomicI64,
+    /// While in the future (unix milliseconds), bulk mode (`ftm bulk-begin`) is on: the
+    /// watcher only notes events and leaves them to one scan when bulk mode ends.
+    bulk_until: AtomicI64,
+    /// Set by `ftm pause` until `ftm resume`: like bulk mode, without an end of its own.
+    paused: AtomicBool,
+    /// Set once the watcher should end, e.g. when its server stops.
+    stopped: AtomicBool,
+}
+
+impl WatcherCounters {
+    pub fn stats(&self) -> WatcherStats {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        WatcherStats {
+            events_received: get(&self.events_received),
+            events_relevant: get(&self.events_relevant),
+            batches: get(&self.batches),
+            largest_batch: get(&self.largest_batch),
+            snapshots_saved: get(&self.snapshots_saved),
+            unchanged_skipped: get(&self.unchanged_skipped),
+            deletes_recorded: get(&self.deletes_recorded),
+            deferred: get(&self.deferred),
+            scan_errors: get(&self.scan_errors),
+            overflows: get(&self.overflows),
+            last_event_at: Self::time(&self.last_event_at),
+            last_snapshot_at: Self::time(&self.last_snapshot_at),
+        }
+    }
+
+    /// Turn bulk mode on until `until`, or until `end_bulk`.
+    pub fn begin_bulk(&self, until: DateTime<Utc>) {
+        self.bulk_until
+            .store(until.timestamp_millis(), Ordering::Relaxed);
+    }
+
+    /// Turn bulk mode off; returns whether it was on.
+    pub fn end_bulk(&self) -> bool {
+        let was_on = self.bulk_until().is_some();
+        self.bulk_until.store(0, Ordering::Relaxed);
+        was_on
+    }
+
+    /// When bulk mode ends by itself, while it is on.
+    pub fn bulk_until(&self) -> Option<DateTime<Utc>> {
+        Self::time(&self.bulk_until).filter(|until| *until > Utc::now())
+    }
+
+    /// Pause recording; returns whether it was running.
+    pub fn pause(&self) -> bool {
+        !self.paused.swap(true, Ordering::Relaxed)
+    }
+
+    /// Resume recording; returns whether it was paused.
+    pub fn resume(&self) -> bool {
+        self.paused.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Ask the watcher to end; it does within a few seconds, stopping writer tracking.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Whether changes are only noted for now (paused, or in bulk mode).
+    pub fn holding(&self) -> bool {
+        self.paused() || self.bulk_until().is_some()
+    }
+
+    fn add(counter: &AtomicU64, n: usize) {
+        counter.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    fn touch(at: &AtomicI64) {
+        at.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    fn time(at: &AtomicI64) -> Option<DateTime<Utc>> {
+        match at.load(Ordering::Relaxed) {
+            0 => None,
+            ms => DateTime::from_timestamp_millis(ms),
+        }
+    }
+}
 
 pub struct FileWatcher {
     root_dir: PathBuf,
     config: Arc<RwLock<Config>>,
+    counters: Arc<WatcherCounters>,
 }
 
 impl FileWatcher {
     pub fn new(root_dir: PathBuf, config: Arc<RwLock<Config>>) -> Self {
-        Self { root_dir, config }
+        Self {
+            root_dir,
+            config,
+            counters: Arc::default(),
+        }
+    }
+
+    /// Counters of this watcher, readable while it runs.
+    pub fn counters(&self) -> Arc<WatcherCounters> {
+        self.counters.clone()
     }
 
     /// Start watching in a background thread (non-blocking).
@@ -30,42 +408,128 @@ impl FileWatcher {
         let (tx, rx) = mpsc::channel();
         let ftm_dir = self.root_dir.join(".ftm");
 
-        let _watcher = {
-            let mut w = RecommendedWatcher::new(
-                move |res: Result<Event, notify::Error>| {
-                    if let Ok(event) = res {
-                        let _ = tx.send(event);
-                    }
-                },
-                notify::Config::default(),
-            )?;
-            w.watch(&self.root_dir, RecursiveMode::Recursive)?;
-            w
+        let mut watcher = RecommendedWatcher::new(
+            move |res: Result<Event, notify::Error>| {
+                if let Ok(event) = res {
+                    let _ = tx.send(event);
+                }
+            },
+            notify::Config::default(),
+        )?;
+        let mut watched = WatchedDirs::new(self.root_dir.clone());
+        watched.sync(&mut watcher, &self.config.read().unwrap(), None)?;
+        let volume = Volume::new(self.root_dir.clone());
+        // Writer tracking starts with the watcher, or with the first batch after
+        // `settings.track_writers` is turned on, and stops with the first batch after
+        // it is turned off or when the watcher ends
+        let mut writers: Option<Arc<WriterLog>> = None;
+        let update_writers = |writers: &mut Option<Arc<WriterLog>>, cfg: &Config| {
+            if !cfg.settings.track_writers {
+                *writers = None;
+            } else if writers.is_none() {
+                *writers = Some(WriterLog::start(&self.root_dir));
+            }
         };
+        update_writers(&mut writers, &self.config.read().unwrap());
 
         info!("Watching directory: {}", self.root_dir.display());
 
-        loop {
-            // Block until a relevant event arrives.
+        let counters = &self.counters;
+        let trigger = || self.config.read().unwrap().settings.snapshot_trigger;
+        // Set when the OS dropped events; the next scan must not trust the dir cache
+        let missed = Cell::new(false);
+        // Paths named by events since the watches were last synced, relevant or not
+        let touched = RefCell::new(HashSet::new());
+        let is_relevant = |event: &Event| {
             // Skip:
             //  - Events whose paths are all inside .ftm/ (internal writes)
-            //  - Access/Other events (only react to actual mutations)
-            match rx.recv() {
-                Ok(event) => {
-                    if !Self::is_mutation(&event.kind) {
-                        continue;
+            //  - Events that do not trigger a snapshot under settings.snapshot_trigger
+            // A rescan request (queue overflow) always triggers.
+            let rescan = event.need_rescan();
+            if rescan {
+                WatcherCounters::add(&counters.overflows, 1);
+                missed.set(true);
+            }
+            let internal = event.paths.iter().all(|p| p.starts_with(&ftm_dir));
+            if !internal {
+                touched.borrow_mut().extend(event.paths.iter().cloned());
+            }
+            let relevant =
+                rescan || (Self::is_snapshot_trigger(&event.kind, trigger()) && !internal);
+            WatcherCounters::add(&counters.events_received, 1);
+            if relevant {
+                WatcherCounters::add(&counters.events_relevant, 1);
+                WatcherCounters::touch(&counters.last_event_at);
+            }
+            relevant
+        };
+        // Kept between batches; reloaded only when something else saves the index
+        let mut cached = CachedIndex::default();
+        let dir_cache = DirCache::default();
+        let mut bucket = SnapshotBucket::new();
+        let mut throttled = false;
+        // Changed files a rate-limited scan left behind; rescan without waiting for events.
+        let mut deferred = false;
+        // Changes noted while paused, under bulk mode or during a git operation, for the
+        // scan once that is over
+        let mut held = false;
+        let git_aware = || self.config.read().unwrap().settings.git_aware;
+        let mut git = GitWatch::new(&self.root_dir);
+
+        loop {
+            if counters.stopped() {
+                return Ok(());
+            }
+            // Block until a relevant event arrives; the loop ends when the channel closes.
+            let mut batch_events = 0;
+            let polling = trigger() == SnapshotTrigger::Poll;
+            if polling && !deferred {
+                // Events are only counted; the scan runs on the clock, or right away
+                // when the OS reports dropped events
+                let next = Instant::now() + POLL_INTERVAL;
+                loop {
+                    let remaining = next.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        break;
+                    }
+                    match rx.recv_timeout(remaining) {
+                        Ok(event) => {
+                            if is_relevant(&event) {
+                                break;
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return Ok(()),
                     }
-                    if event.paths.iter().all(|p| p.starts_with(&ftm_dir)) {
-                        continue;
+                }
+            } else if !deferred {
+                // Neither an unmount nor the end of a pause, of bulk mode or of a git
+                // operation need send an event, so check every few seconds, even while
+                // irrelevant events keep arriving
+                let check_at = Instant::now() + VOLUME_CHECK_INTERVAL;
+                loop {
+                    let remaining = check_at.saturating_duration_since(Instant::now());
+                    match rx.recv_timeout(remaining) {
+                        Ok(event) => {
+                            if is_relevant(&event) {
+                                batch_events += 1;
+                                break;
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return Ok(()),
                     }
                 }
-                Err(_) => break, // channel closed
+                let released = held && !counters.holding() && !(git_aware() && git.busy());
+                if batch_events == 0 && volume.state() == VolumeState::Present && !released {
+                    continue;
+                }
             }
 
             // Debounce: drain events until 500ms of silence.
-            // Only non-.ftm mutation events reset the deadline; irrelevant
+            // Only non-.ftm triggering events reset the deadline; other
             // events (Access, .ftm writes) are consumed without extending it.
-            let mut deadline = Instant::now() + Duration::from_millis(500);
+            let mut deadline = Instant::now() + if polling { Duration::ZERO } else { DEBOUNCE };
             loop {
                 let remaining = deadline.saturating_duration_since(Instant::now());
                 if remaining.is_zero() {
@@ -73,11 +537,10 @@ impl FileWatcher {
                 }
                 match rx.recv_timeout(remaining) {
                     Ok(event) => {
-                        if Self::is_mutation(&event.kind)
-                            && !event.paths.iter().all(|p| p.starts_with(&ftm_dir))
-                        {
+                        if is_relevant(&event) {
                             // Relevant mutation — reset deadline
-                            deadline = Instant::now() + Duration::from_millis(500);
+                            deadline = Instant::now() + DEBOUNCE;
+                            batch_events += 1;
                         }
                         // Irrelevant events consumed without resetting deadline
                     }
@@ -86,36 +549,188 @@ impl FileWatcher {
                 }
             }
 
-            // Perform a full directory scan to detect creates, modifies, and deletes
+            // Scanning while the volume is away would record every file as deleted. Once
+            // it is back its watches are registered afresh, and a full scan catches up
+            // on whatever changed meanwhile.
+            let remounted = volume.state() != VolumeState::Present;
+            if remounted {
+                if !Self::wait_for_volume(&volume, &rx) {
+                    return Ok(());
+                }
+                info!(
+                    "Watched directory {} is back; resuming watching with a full scan",
+                    self.root_dir.display()
+                );
+                watched = WatchedDirs::new(self.root_dir.clone());
+            }
+
+            // While paused, under bulk mode, or while git rewrites the work tree, the batch
+            // is only noted; a full scan once that is over records the final state instead
+            // of every intermediate one.
+            if counters.holding() || (git_aware() && git.busy()) {
+                held = true;
+                deferred = false;
+                continue;
+            }
+            let after_hold = std::mem::take(&mut held);
+            if after_hold {
+                info!(
+                    "Recording the changes held back (pause, bulk mode or a git operation) with one scan"
+                );
+            }
+            let switched_to = git.switched().filter(|_| git_aware());
+            if let Some(head) = &switched_to {
+                info!(
+                    "Git switched to {}; recording the switch with one scan",
+                    head
+                );
+            }
+
             let cfg = {
                 let c = self.config.read().unwrap();
                 c.clone()
             };
-            let storage = Storage::for_settings(ftm_dir.clone(), &cfg.settings);
-            match Scanner::new(self.root_dir.clone(), cfg, storage).scan() {
-                Ok(r) => {
-                    info!(
-                        "Watcher scan: +{} ~{} -{} ={}",
-                        r.created, r.modified, r.deleted, r.unchanged
+            // Watch directories created (or stop watching those removed) since the last
+            // batch; the scan below picks up anything written before the watch existed.
+            // After dropped events the touched paths may be incomplete.
+            let touched = touched.take();
+            let touched = (!missed.get()).then_some(&touched);
+            if let Err(e) = watched.sync(&mut watcher, &cfg, touched) {
+                warn!("Failed to update watched directories: {}", e);
+            }
+
+            // Out of snapshots: keep absorbing events until one is available, so every
+            // change made meanwhile is picked up by a single scan.
+            let limit = cfg.settings.max_snapshots_per_minute;
+            if limit > 0 && bucket.available(limit) == 0 {
+                if !throttled {
+                    warn!(
+                        "Snapshot rate limit reached ({} per minute); coalescing further changes",
+                        limit
                     );
+                    throttled = true;
+                }
+                let resume = Instant::now() + bucket.wait(limit);
+                loop {
+                    let remaining = resume.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        break;
+                    }
+                    match rx.recv_timeout(remaining) {
+                        Ok(event) => {
+                            if is_relevant(&event) {
+                                batch_events += 1;
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                    }
+                }
+            }
+
+            // Perform a full directory scan to detect creates, modifies, and deletes
+            update_writers(&mut writers, &cfg);
+            let source = if switched_to.is_some() {
+                EntrySource::Git
+            } else {
+                EntrySource::Watcher
+            };
+            // Changes made while events were being dropped are found by this scan
+            // rather than by the next periodic one
+            let overflowed = missed.take();
+            if overflowed {
+                warn!(
+                    "File watcher dropped events (event queue overflow); changes may have been missed, running a full scan"
+                );
+            }
+            let full_scan = overflowed || remounted || after_hold || switched_to.is_some();
+            let incremental = cfg.settings.incremental_scan && !full_scan;
+            let mut storage =
+                Storage::for_settings(ftm_dir.clone(), &cfg.settings).with_source(source);
+            if let Some(log) = &writers {
+                // A full scan may record many files; walking /proc for each is too slow
+                storage = storage.with_writers(log.clone(), !full_scan);
+            }
+            let mut scanner = Scanner::new(self.root_dir.clone(), cfg, storage);
+            if limit > 0 {
+                scanner = scanner.with_snapshot_budget(bucket.available(limit));
+            }
+            if incremental {
+                scanner = scanner.with_dir_cache(dir_cache.clone());
+            }
+            deferred = false;
+            WatcherCounters::add(&counters.batches, 1);
+            counters
+                .largest_batch
+                .fetch_max(batch_events, Ordering::Relaxed);
+            match scanner.scan_cached(&mut cached) {
+                Ok(r) => {
+                    WatcherCounters::add(&counters.snapshots_saved, r.created + r.modified);
+                    if r.created + r.modified > 0 {
+                        WatcherCounters::touch(&counters.last_snapshot_at);
+                    }
+                    WatcherCounters::add(&counters.unchanged_skipped, r.unchanged);
+                    WatcherCounters::add(&counters.deletes_recorded, r.deleted);
+                    WatcherCounters::add(&counters.deferred, r.deferred);
+                    // Polls finding nothing are not worth a log line every few seconds
+                    if !polling || r.created + r.modified + r.deleted > 0 {
+                        info!(
+                            "Watcher scan: +{} ~{} -{} ={}",
+                            r.created, r.modified, r.deleted, r.unchanged
+                        );
+                    }
+                    bucket.spend(r.created + r.modified);
+                    if r.deferred > 0 {
+                        info!(
+                            "Snapshot rate limit: {} file(s) deferred to the next scan",
+                            r.deferred
+                        );
+                        deferred = true;
+                    } else if throttled {
+                        info!("Watcher caught up after snapshot rate limiting");
+                        throttled = false;
+                    }
                 }
                 Err(e) => {
-                    tracing::warn!("Watcher scan error: {}", e);
+                    WatcherCounters::add(&counters.scan_errors, 1);
+                    warn!("Watcher scan error: {}", e);
                 }
             }
         }
+    }
 
-        Ok(())
+    /// Block until `.ftm` is back on `volume`, discarding events meanwhile. Returns
+    /// false when the event channel closes first.
+    fn wait_for_volume(volume: &Volume, rx: &mpsc::Receiver<Event>) -> bool {
+        while volume.state() != VolumeState::Present {
+            if let Err(RecvTimeoutError::Disconnected) = rx.recv_timeout(VOLUME_CHECK_INTERVAL) {
+                return false;
+            }
+        }
+        true
     }
 
-    /// Returns true for event kinds that represent actual filesystem mutations
-    /// (create, modify, remove, rename). Access and Other events are ignored.
-    fn is_mutation(kind: &notify::EventKind) -> bool {
-        matches!(
-            kind,
-            notify::EventKind::Create(_)
-                | notify::EventKind::Modify(_)
-                | notify::EventKind::Remove(_)
-        )
+    /// Returns true for event kinds that lead to a scan under `trigger`. Under
+    /// `DebouncedModify` these are all mutations (create, modify, remove, rename);
+    /// `CloseWrite` swaps content modifications for the writer closing the file;
+    /// `Poll` scans on a timer and no event triggers anything.
+    fn is_snapshot_trigger(kind: &notify::EventKind, trigger: SnapshotTrigger) -> bool {
+        use notify::event::{AccessKind, AccessMode, ModifyKind};
+        use notify::EventKind;
+
+        match trigger {
+            SnapshotTrigger::CloseWrite if cfg!(target_os = "linux") => matches!(
+                kind,
+                EventKind::Create(_)
+                    | EventKind::Remove(_)
+                    | EventKind::Modify(ModifyKind::Name(_))
+                    | EventKind::Access(AccessKind::Close(AccessMode::Write))
+            ),
+            SnapshotTrigger::CloseWrite | SnapshotTrigger::DebouncedModify => matches!(
+                kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ),
+            SnapshotTrigger::Poll => false,
+        }
     }
 }
@@ -0,0 +1,105 @@
+//! Workspace file (`workspace.yaml` in the user config directory, e.g.
+//! `~/.config/ftm/workspace.yaml`) listing the directories `ftm up` watches, each
+//! as a project of one server, with optional per-directory settings.
+//!
+//! ```yaml
+//! directories:
+//!   - path: ~/src/app
+//!   - path: ~/notes
+//!     name: notes
+//!     settings:
+//!       max_history: 500
+//!       watch.patterns: ["*.md", "*.txt"]
+//! ```
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+pub struct Workspace {
+    #[serde(default)]
+    pub directories: Vec<WorkspaceDir>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkspaceDir {
+    /// Absolute, `~/`-prefixed, or relative to the workspace file.
+    pub path: PathBuf,
+    /// Project name (default: the directory name).
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Config keys as accepted by `ftm config set`; the `settings.` prefix is optional.
+    #[serde(default)]
+    pub settings: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Default location of the workspace file.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("ftm").join("workspace.yaml"))
+}
+
+impl Workspace {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read workspace file {}", path.display()))?;
+        let mut workspace: Workspace = serde_yaml::from_str(&content)
+            .with_context(|| format!("Invalid workspace file {}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        for dir in &mut workspace.directories {
+            dir.path = resolve_path(&dir.path, base);
+        }
+        Ok(workspace)
+    }
+}
+
+fn resolve_path(path: &Path, base: &Path) -> PathBuf {
+    let path = match path.strip_prefix("~") {
+        Ok(rest) => match dirs::home_dir() {
+            Some(home) => home.join(rest),
+            None => path.to_path_buf(),
+        },
+        Err(_) => path.to_path_buf(),
+    };
+    let path = if path.is_absolute() {
+        path
+    } else {
+        base.join(path)
+    };
+    path.canonicalize().unwrap_or(path)
+}
+
+impl WorkspaceDir {
+    /// Settings as `config set` key/value pairs; lists become comma-separated values.
+    pub fn config_values(&self) -> Result<Vec<(String, String)>> {
+        self.settings
+            .iter()
+            .map(|(key, value)| {
+                let key = if key.contains('.') {
+                    key.clone()
+                } else {
+                    format!("settings.{}", key)
+                };
+                let value = match value {
+                    serde_yaml::Value::Sequence(items) => items
+                        .iter()
+                        .map(scalar)
+                        .collect::<Result<Vec<_>>>()?
+                        .join(","),
+                    other => scalar(other)?,
+                };
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+fn scalar(value: &serde_yaml::Value) -> Result<String> {
+    match value {
+        serde_yaml::Value::String(s) => Ok(s.clone()),
+        serde_yaml::Value::Number(n) => Ok(n.to_string()),
+        serde_yaml::Value::Bool(b) => Ok(b.to_string()),
+        other => anyhow::bail!("Unsupported setting value: {:?}", other),
+    }
+}
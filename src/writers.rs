@@ -0,0 +1,281 @@
+//! Best-effort attribution of changes to the process that made them
+//! (`settings.track_writers`), recorded as `HistoryEntry::writer`. On Linux a fanotify
+//! listener on the watched directory's mount notes which process closed each file after
+//! writing it. fanotify needs `CAP_SYS_ADMIN`; without it, and for files still being
+//! written, the process holding the file open for writing is looked up in `/proc`.
+//! Other platforms record no writer.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Writes noted longer ago than this are not attributed to a change.
+const WRITE_TTL: Duration = Duration::from_secs(300);
+/// Noted writes kept before expired ones are dropped.
+const MAX_NOTED: usize = 4096;
+
+/// Name of the last process to write each file, and when it closed the file.
+type Noted = Mutex<HashMap<PathBuf, (String, Instant)>>;
+
+pub struct WriterLog {
+    noted: Arc<Noted>,
+    /// fanotify listener feeding `noted`, stopped when the log is dropped.
+    #[cfg(target_os = "linux")]
+    _listener: Option<fanotify::Listener>,
+}
+
+impl WriterLog {
+    /// Start noting writes under `root` until the log is dropped.
+    pub fn start(root: &Path) -> Arc<Self> {
+        let noted = Arc::new(Noted::default());
+        #[cfg(target_os = "linux")]
+        {
+            let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+            let listener = fanotify::Listener::start(&root, noted.clone())
+                .inspect_err(|e| {
+                    tracing::info!("Writer tracking without fanotify ({}); using /proc", e)
+                })
+                .ok();
+            Arc::new(Self {
+                noted,
+                _listener: listener,
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = root;
+            Arc::new(Self { noted })
+        }
+    }
+
+    /// Name of the process that last wrote `path` (e.g. `vim`), if it can be told. With
+    /// `look_up_open` a process still holding it open for writing is looked for in
+    /// `/proc`, a walk over every descriptor of every process.
+    pub fn writer_of(&self, path: &Path, look_up_open: bool) -> Option<String> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let noted = self.noted.lock().unwrap().remove(&path);
+        noted
+            .filter(|(_, at)| at.elapsed() < WRITE_TTL)
+            .map(|(process, _)| process)
+            .or_else(|| look_up_open.then(|| open_for_writing(&path)).flatten())
+    }
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn note(noted: &Noted, path: PathBuf, process: String) {
+    let mut noted = noted.lock().unwrap();
+    if noted.len() >= MAX_NOTED {
+        noted.retain(|_, (_, at)| at.elapsed() < WRITE_TTL);
+    }
+    noted.insert(path, (process, Instant::now()));
+}
+
+#[cfg(target_os = "linux")]
+fn process_name(pid: u32) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(comm.trim_end().to_string()).filter(|c| !c.is_empty())
+}
+
+/// Another process that has `path` open for writing, found by walking `/proc/*/fd`.
+#[cfg(target_os = "linux")]
+fn open_for_writing(path: &Path) -> Option<String> {
+    let own = std::process::id();
+    for proc_entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let pid = match proc_entry.file_name().to_str().map(str::parse::<u32>) {
+            Some(Ok(pid)) if pid != own => pid,
+            _ => continue,
+        };
+        let Ok(fds) = std::fs::read_dir(proc_entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if std::fs::read_link(fd.path()).is_ok_and(|target| target == path)
+                && opened_writable(&proc_entry.path(), &fd.file_name())
+            {
+                return process_name(pid);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_for_writing(_path: &Path) -> Option<String> {
+    None
+}
+
+/// Whether descriptor `fd` of the process at `proc_dir` was opened for writing, from the
+/// octal `flags:` line of its fdinfo.
+#[cfg(target_os = "linux")]
+fn opened_writable(proc_dir: &Path, fd: &std::ffi::OsStr) -> bool {
+    std::fs::read_to_string(proc_dir.join("fdinfo").join(fd))
+        .ok()
+        .and_then(|info| {
+            let flags = info.lines().find_map(|l| l.strip_prefix("flags:"))?;
+            u32::from_str_radix(flags.trim(), 8).ok()
+        })
+        .is_some_and(|flags| flags & libc::O_ACCMODE as u32 != libc::O_RDONLY as u32)
+}
+
+#[cfg(target_os = "linux")]
+mod fanotify {
+    use super::{note, process_name, Noted};
+    use anyhow::{Context, Result};
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+    use tracing::warn;
+
+    const METADATA_LEN: usize = std::mem::size_of::<libc::fanotify_event_metadata>();
+
+    /// Thread reading fanotify events; dropping it stops the thread and closes the
+    /// fanotify descriptor.
+    pub(super) struct Listener {
+        /// eventfd that wakes the thread to stop.
+        stop: libc::c_int,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Listener {
+        /// Listen for files closed after writing on the mount holding `root` and note
+        /// those under it (outside `.ftm`) in `noted`.
+        pub(super) fn start(root: &Path, noted: Arc<Noted>) -> Result<Self> {
+            let flags = libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC;
+            let event_flags =
+                (libc::O_RDONLY | libc::O_LARGEFILE | libc::O_CLOEXEC) as libc::c_uint;
+            // SAFETY: plain syscall; the returned descriptor is owned by the listener thread.
+            let fd = unsafe { libc::fanotify_init(flags, event_flags) };
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error()).context("fanotify_init");
+            }
+            let c_root = CString::new(root.as_os_str().as_bytes())?;
+            // SAFETY: `fd` is a fanotify descriptor and `c_root` a NUL-terminated path.
+            let marked = unsafe {
+                libc::fanotify_mark(
+                    fd,
+                    libc::FAN_MARK_ADD | libc::FAN_MARK_MOUNT,
+                    libc::FAN_CLOSE_WRITE,
+                    libc::AT_FDCWD,
+                    c_root.as_ptr(),
+                )
+            };
+            if marked < 0 {
+                let err = std::io::Error::last_os_error();
+                // SAFETY: `fd` was opened above and is not used afterwards.
+                unsafe { libc::close(fd) };
+                return Err(err).context("fanotify_mark");
+            }
+            // SAFETY: plain syscall; the returned descriptor is owned by the Listener.
+            let stop = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+            if stop < 0 {
+                let err = std::io::Error::last_os_error();
+                // SAFETY: as above.
+                unsafe { libc::close(fd) };
+                return Err(err).context("eventfd");
+            }
+            let root = root.to_path_buf();
+            let thread = std::thread::spawn(move || {
+                read_events(fd, stop, root, &noted);
+                // SAFETY: the thread owns `fd` and nothing reads it any more.
+                unsafe { libc::close(fd) };
+            });
+            Ok(Self {
+                stop,
+                thread: Some(thread),
+            })
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            let one: u64 = 1;
+            // SAFETY: `stop` is our eventfd; it takes 8-byte writes.
+            unsafe { libc::write(self.stop, (&one as *const u64).cast(), 8) };
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+            // SAFETY: the thread has ended, so nothing else uses `stop`.
+            unsafe { libc::close(self.stop) };
+        }
+    }
+
+    /// Note events from `fd` until `stop` is signalled or reading fails.
+    fn read_events(fd: libc::c_int, stop: libc::c_int, root: PathBuf, noted: &Noted) {
+        let ftm_dir = root.join(".ftm");
+        let own = std::process::id() as i32;
+        let mut buf = vec![0u8; 64 * METADATA_LEN];
+        loop {
+            let mut fds = [
+                libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: stop,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            // SAFETY: `fds` is valid for its length.
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                warn!("Writer tracking stopped: {}", err);
+                return;
+            }
+            if fds[1].revents != 0 {
+                return;
+            }
+            if fds[0].revents == 0 {
+                continue;
+            }
+            // SAFETY: `buf` is valid for writes of its length.
+            let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+            if n < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                warn!("Writer tracking stopped: {}", err);
+                return;
+            }
+            let n = n as usize;
+            let mut offset = 0;
+            while offset + METADATA_LEN <= n {
+                // SAFETY: at least METADATA_LEN bytes remain at `offset`; the kernel
+                // wrote a metadata record there.
+                let meta: libc::fanotify_event_metadata =
+                    unsafe { std::ptr::read_unaligned(buf.as_ptr().add(offset).cast()) };
+                if meta.vers != libc::FANOTIFY_METADATA_VERSION {
+                    warn!("Writer tracking stopped: unsupported fanotify metadata version");
+                    return;
+                }
+                if (meta.event_len as usize) < METADATA_LEN {
+                    break;
+                }
+                offset += meta.event_len as usize;
+                if meta.fd < 0 {
+                    continue;
+                }
+                let path = std::fs::read_link(format!("/proc/self/fd/{}", meta.fd));
+                // SAFETY: the event's descriptor is ours to close.
+                unsafe { libc::close(meta.fd) };
+                let Ok(path) = path else {
+                    continue;
+                };
+                if meta.pid == own || !path.starts_with(&root) || path.starts_with(&ftm_dir) {
+                    continue;
+                }
+                if let Some(process) = process_name(meta.pid as u32) {
+                    note(noted, path, process);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Extended attributes of tracked files (`settings.track_xattrs`), e.g. macOS Finder
+//! tags or quarantine flags. They are captured when a version is recorded and kept
+//! beside the snapshot in `.ftm/xattrs/<checksum>/<hash of the file key>.json`, then
+//! written back when that version is restored. Only supported on Unix.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+const XATTRS_DIR: &str = "xattrs";
+
+/// Attribute name to value.
+pub type Attrs = BTreeMap<String, Vec<u8>>;
+
+#[derive(Serialize, Deserialize)]
+struct Sidecar {
+    file: String,
+    /// Values hex-encoded, as they may be binary.
+    attrs: BTreeMap<String, String>,
+}
+
+#[cfg(unix)]
+fn c_path(path: &Path) -> std::io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn list(path: *const libc::c_char, buf: *mut libc::c_char, size: usize) -> isize {
+    libc::listxattr(path, buf, size, 0)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn get(
+    path: *const libc::c_char,
+    name: *const libc::c_char,
+    buf: *mut u8,
+    size: usize,
+) -> isize {
+    libc::getxattr(path, name, buf.cast(), size, 0, 0)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn set(path: *const libc::c_char, name: *const libc::c_char, value: &[u8]) -> i32 {
+    libc::setxattr(path, name, value.as_ptr().cast(), value.len(), 0, 0)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+unsafe fn list(path: *const libc::c_char, buf: *mut libc::c_char, size: usize) -> isize {
+    libc::listxattr(path, buf, size)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+unsafe fn get(
+    path: *const libc::c_char,
+    name: *const libc::c_char,
+    buf: *mut u8,
+    size: usize,
+) -> isize {
+    libc::getxattr(path, name, buf.cast(), size)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+unsafe fn set(path: *const libc::c_char, name: *const libc::c_char, value: &[u8]) -> i32 {
+    libc::setxattr(path, name, value.as_ptr().cast(), value.len(), 0)
+}
+
+/// Call `f` with a buffer of the size it asked for with a null buffer, retrying while
+/// the value grows between the two calls.
+#[cfg(unix)]
+fn read_sized(f: impl Fn(*mut u8, usize) -> isize) -> std::io::Result<Vec<u8>> {
+    loop {
+        let size = f(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; size as usize];
+        let n = f(buf.as_mut_ptr(), buf.len());
+        if n >= 0 {
+            buf.truncate(n as usize);
+            return Ok(buf);
+        }
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+    }
+}
+
+/// Extended attributes of `path`; empty where the file system has none.
+#[cfg(unix)]
+pub fn read(path: &Path) -> std::io::Result<Attrs> {
+    let c_path = c_path(path)?;
+    let names = match read_sized(|buf, size| unsafe { list(c_path.as_ptr(), buf.cast(), size) }) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Attrs::new()),
+        Err(e) => return Err(e),
+    };
+    let mut attrs = Attrs::new();
+    for name in names.split(|&b| b == 0).filter(|n| !n.is_empty()) {
+        let c_name = std::ffi::CString::new(name)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let value =
+            read_sized(|buf, size| unsafe { get(c_path.as_ptr(), c_name.as_ptr(), buf, size) })?;
+        attrs.insert(String::from_utf8_lossy(name).into_owned(), value);
+    }
+    Ok(attrs)
+}
+
+#[cfg(not(unix))]
+pub fn read(_path: &Path) -> std::io::Result<Attrs> {
+    Ok(Attrs::new())
+}
+
+/// Set every attribute in `attrs` on `path`, leaving others in place.
+#[cfg(unix)]
+pub fn write(path: &Path, attrs: &Attrs) -> std::io::Result<()> {
+    let c_path = c_path(path)?;
+    for (name, value) in attrs {
+        let c_name = std::ffi::CString::new(name.as_bytes())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        if unsafe { set(c_path.as_ptr(), c_name.as_ptr(), value) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn write(_path: &Path, _attrs: &Attrs) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn sidecar_path(ftm_dir: &Path, checksum: &str, file: &str) -> PathBuf {
+    ftm_dir
+        .join(XATTRS_DIR)
+        .join(checksum)
+        .join(format!("{}.json", blake3::hash(file.as_bytes()).to_hex()))
+}
+
+/// Keep `attrs` as those of version `checksum` of `file`; nothing is kept for none.
+pub fn save(ftm_dir: &Path, checksum: &str, file: &str, attrs: &Attrs) -> Result<()> {
+    if attrs.is_empty() {
+        return Ok(());
+    }
+    let path = sidecar_path(ftm_dir, checksum, file);
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let sidecar = Sidecar {
+        file: file.to_string(),
+        attrs: attrs
+            .iter()
+            .map(|(name, value)| (name.clone(), hex::encode(value)))
+            .collect(),
+    };
+    std::fs::write(&path, serde_json::to_vec(&sidecar)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Attributes kept for version `checksum` of `file`, if any.
+pub fn load(ftm_dir: &Path, checksum: &str, file: &str) -> Result<Option<Attrs>> {
+    let path = sidecar_path(ftm_dir, checksum, file);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let sidecar: Sidecar = serde_json::from_slice(&std::fs::read(&path)?)
+        .with_context(|| format!("Invalid {}", path.display()))?;
+    let attrs = sidecar
+        .attrs
+        .into_iter()
+        .map(|(name, value)| Ok((name, hex::decode(value)?)))
+        .collect::<Result<Attrs>>()
+        .with_context(|| format!("Invalid {}", path.display()))?;
+    Ok(Some(attrs))
+}
+
+/// Delete the attributes kept for snapshots not in `referenced`; returns how many
+/// snapshots' worth were removed.
+pub fn remove_unreferenced(ftm_dir: &Path, referenced: &HashSet<&str>) -> Result<usize> {
+    let dir = ftm_dir.join(XATTRS_DIR);
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        if !referenced.contains(entry.file_name().to_string_lossy().as_ref()) {
+            std::fs::remove_dir_all(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
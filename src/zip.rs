@@ -0,0 +1,151 @@
+//! Just enough of the zip format for `ftm export-dir`: regular files, stored without
+//! compression, with UTF-8 names. Archives needing zip64 (over 4 GB or 65535 members)
+//! are refused.
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use std::io::Write;
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |c, &b| {
+        CRC_TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8)
+    })
+}
+
+/// MS-DOS (time, date) in local time; dates before 1980 become 1980-01-01.
+fn dos_time(at: DateTime<Utc>) -> (u16, u16) {
+    let at = at.with_timezone(&Local);
+    if at.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = (at.hour() << 11) | (at.minute() << 5) | (at.second() / 2);
+    let date = (((at.year() - 1980) as u32) << 9) | (at.month() << 5) | at.day();
+    (time as u16, date as u16)
+}
+
+fn too_large() -> anyhow::Error {
+    anyhow::anyhow!("Too large for a zip archive (over 4 GB or 65535 files)")
+}
+
+struct Member {
+    name: String,
+    crc: u32,
+    size: u32,
+    time: u16,
+    date: u16,
+    offset: u32,
+}
+
+pub struct ZipWriter<W: Write> {
+    out: W,
+    written: u64,
+    members: Vec<Member>,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            written: 0,
+            members: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Append the regular file `name` with `data`, modified at `mtime`.
+    pub fn append(&mut self, name: &str, data: &[u8], mtime: DateTime<Utc>) -> Result<()> {
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let offset = u32::try_from(self.written).map_err(|_| too_large())?;
+        if self.members.len() == usize::from(u16::MAX) {
+            return Err(too_large());
+        }
+        let name_len = u16::try_from(name.len()).map_err(|_| too_large())?;
+        let (time, date) = dos_time(mtime);
+        let member = Member {
+            name: name.to_string(),
+            crc: crc32(data),
+            size,
+            time,
+            date,
+            offset,
+        };
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        header.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        header.extend_from_slice(&0x0800u16.to_le_bytes()); // UTF-8 names
+        header.extend_from_slice(&0u16.to_le_bytes()); // stored
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&date.to_le_bytes());
+        header.extend_from_slice(&member.crc.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&name_len.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+        header.extend_from_slice(name.as_bytes());
+        self.write(&header)?;
+        self.write(data)?;
+        self.members.push(member);
+        Ok(())
+    }
+
+    /// Write the central directory and return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        let start = u32::try_from(self.written).map_err(|_| too_large())?;
+        let mut directory = Vec::new();
+        for m in &self.members {
+            directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            directory.extend_from_slice(&20u16.to_le_bytes()); // version made by
+            directory.extend_from_slice(&20u16.to_le_bytes()); // version needed
+            directory.extend_from_slice(&0x0800u16.to_le_bytes());
+            directory.extend_from_slice(&0u16.to_le_bytes());
+            directory.extend_from_slice(&m.time.to_le_bytes());
+            directory.extend_from_slice(&m.date.to_le_bytes());
+            directory.extend_from_slice(&m.crc.to_le_bytes());
+            directory.extend_from_slice(&m.size.to_le_bytes());
+            directory.extend_from_slice(&m.size.to_le_bytes());
+            directory.extend_from_slice(&(m.name.len() as u16).to_le_bytes());
+            // Extra field and comment lengths, disk number, internal and external attributes
+            directory.extend_from_slice(&[0u8; 12]);
+            directory.extend_from_slice(&m.offset.to_le_bytes());
+            directory.extend_from_slice(m.name.as_bytes());
+        }
+        let size = u32::try_from(directory.len()).map_err(|_| too_large())?;
+        let count = self.members.len() as u16;
+        directory.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        directory.extend_from_slice(&[0u8; 4]); // disk numbers
+        directory.extend_from_slice(&count.to_le_bytes());
+        directory.extend_from_slice(&count.to_le_bytes());
+        directory.extend_from_slice(&size.to_le_bytes());
+        directory.extend_from_slice(&start.to_le_bytes());
+        directory.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        self.write(&directory)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
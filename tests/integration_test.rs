@@ -1,12 +1,14 @@
 //! Integration tests for ftm CLI commands (server/client architecture).
 //!
-//! Run with: cargo test --release -- --test-threads=1
+//! Run with: cargo test --release
 
 use ctor::ctor;
 use serde::Deserialize;
+use std::cell::RefCell;
 use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
 use std::process::Command;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use tempfile::tempdir;
 
 // ---------------------------------------------------------------------------
@@ -42,6 +44,49 @@ fn kill_all_ftm_processes() {
 // Helpers
 // ---------------------------------------------------------------------------
 
+/// Tests run side by side, but `ftm checkout` kills every other ftm process: a test
+/// running it takes the machine for itself with `run_alone`.
+static MACHINE: RwLock<()> = RwLock::new(());
+
+enum MachineGuard {
+    Shared {
+        _guard: RwLockReadGuard<'static, ()>,
+    },
+    Alone {
+        _guard: RwLockWriteGuard<'static, ()>,
+    },
+}
+
+thread_local! {
+    /// Held by the test running on this thread until it ends.
+    static MACHINE_GUARD: RefCell<Option<MachineGuard>> = const { RefCell::new(None) };
+}
+
+/// Run the rest of this test with no other test's ftm processes around; it must come
+/// before the test starts any.
+fn run_alone() {
+    MACHINE_GUARD.with_borrow_mut(|guard| {
+        assert!(
+            guard.is_none(),
+            "run_alone() must come before ftm is started"
+        );
+        *guard = Some(MachineGuard::Alone {
+            _guard: MACHINE.write().unwrap_or_else(PoisonError::into_inner),
+        });
+    });
+}
+
+/// Share the machine with other tests, unless this one runs alone.
+fn share_machine() {
+    MACHINE_GUARD.with_borrow_mut(|guard| {
+        if guard.is_none() {
+            *guard = Some(MachineGuard::Shared {
+                _guard: MACHINE.read().unwrap_or_else(PoisonError::into_inner),
+            });
+        }
+    });
+}
+
 /// Guard that keeps the temp dir on test failure and prints its path.
 struct TestDirGuard {
     inner: Option<tempfile::TempDir>,
@@ -74,10 +119,35 @@ fn setup_test_dir() -> TestDirGuard {
     }
 }
 
+/// The ftm binary, with the user-level state it writes (last checkout, API token,
+/// signing key) kept under the target dir instead of $HOME. Tests that need their own
+/// state dir set `FTM_STATE_DIR` again.
+fn ftm_command() -> Command {
+    share_machine();
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_ftm"));
+    cmd.env(
+        "FTM_STATE_DIR",
+        Path::new(env!("CARGO_TARGET_TMPDIR")).join("state"),
+    );
+    cmd
+}
+
 /// Start the ftm server on a random port. Returns (child, actual_port).
 fn start_server() -> (std::process::Child, u16) {
-    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_ftm"))
+    start_server_with_args(&[])
+}
+
+/// Start `ftm serve` on a random port with extra serve arguments. Returns (child, port).
+fn start_server_with_args(extra: &[&str]) -> (std::process::Child, u16) {
+    start_server_with_env(extra, &[])
+}
+
+/// Like `start_server_with_args`, with extra environment variables for the server.
+fn start_server_with_env(extra: &[&str], envs: &[(&str, &Path)]) -> (std::process::Child, u16) {
+    let mut child = ftm_command()
         .args(["--port", "0", "serve"])
+        .args(extra)
+        .envs(envs.iter().copied())
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::null())
         .spawn()
@@ -108,54 +178,57 @@ fn start_server() -> (std::process::Child, u16) {
     (child, port)
 }
 
-/// Start server and checkout a directory. Returns (child, port).
+/// Start server and checkout a directory, logging to its `.ftm/logs` like a server
+/// started by `ftm checkout`. Returns (child, port).
 fn start_server_and_checkout(dir: &Path) -> (std::process::Child, u16) {
-    let (child, port) = start_server();
-    let path_s = dir.to_str().unwrap();
-    let out = run_ftm_with_port(port, &["checkout", path_s]);
-    assert!(
-        out.status.success(),
-        "checkout should succeed: stdout={}, stderr={}",
-        String::from_utf8_lossy(&out.stdout),
-        String::from_utf8_lossy(&out.stderr),
-    );
+    let log_dir = dir.join(".ftm/logs");
+    let (child, port) = start_server_with_args(&["--log-dir", log_dir.to_str().unwrap()]);
+    checkout_over_api(port, dir);
     // Brief delay for watcher to initialize
     std::thread::sleep(std::time::Duration::from_millis(50));
     (child, port)
 }
 
+/// Check out `dir` on the server at `port` directly; unlike `ftm checkout` this keeps
+/// that server (and its environment) and leaves other servers running.
+fn checkout_over_api(port: u16, dir: &Path) {
+    let resp = reqwest::blocking::Client::builder()
+        .no_proxy()
+        .build()
+        .unwrap()
+        .post(format!("http://127.0.0.1:{}/api/checkout", port))
+        .json(&serde_json::json!({ "directory": dir.to_str().unwrap() }))
+        .send()
+        .unwrap();
+    assert!(
+        resp.status().is_success(),
+        "checkout failed: {:?}",
+        resp.text()
+    );
+}
+
 fn stop_server(child: &mut std::process::Child) {
     let _ = child.kill();
     let _ = child.wait();
 }
 
-/// Spawn a thread that drains a pipe into a shared buffer. Returns the buffer.
+/// Spawn a thread that drains a pipe until EOF. Join the handle to get the bytes: the
+/// child having exited does not mean the thread has read everything it wrote.
 fn spawn_pipe_drainer(
     pipe: Option<impl Read + Send + 'static>,
-) -> std::sync::Arc<std::sync::Mutex<Vec<u8>>> {
-    let collector = std::sync::Arc::new(std::sync::Mutex::new(Vec::<u8>::new()));
-    let buf_ref = collector.clone();
+) -> std::thread::JoinHandle<Vec<u8>> {
     std::thread::spawn(move || {
+        let mut out = Vec::new();
         if let Some(mut pipe) = pipe {
-            let mut buf = [0u8; 4096];
-            loop {
-                match pipe.read(&mut buf) {
-                    Ok(0) | Err(_) => break,
-                    Ok(n) => {
-                        if let Ok(mut v) = buf_ref.lock() {
-                            v.extend_from_slice(&buf[..n]);
-                        }
-                    }
-                }
-            }
+            let _ = pipe.read_to_end(&mut out);
         }
-    });
-    collector
+        out
+    })
 }
 
 /// Run ftm with given args, draining stdout/stderr in background to avoid pipe deadlock (Windows/Unix).
 fn run_ftm_output(args: &[&str]) -> std::process::Output {
-    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_ftm"))
+    let mut child = ftm_command()
         .args(args)
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
@@ -164,8 +237,8 @@ fn run_ftm_output(args: &[&str]) -> std::process::Output {
     let stdout_collector = spawn_pipe_drainer(child.stdout.take());
     let stderr_collector = spawn_pipe_drainer(child.stderr.take());
     let status = child.wait().expect("failed to wait on ftm");
-    let stdout = std::mem::take(&mut *stdout_collector.lock().unwrap());
-    let stderr = std::mem::take(&mut *stderr_collector.lock().unwrap());
+    let stdout = stdout_collector.join().unwrap_or_default();
+    let stderr = stderr_collector.join().unwrap_or_default();
     std::process::Output {
         status,
         stdout,
@@ -183,6 +256,35 @@ fn run_ftm_with_port(port: u16, args: &[&str]) -> std::process::Output {
     run_ftm_output(&all)
 }
 
+/// Run ftm from `cwd` without --port, so the client must discover the server itself.
+fn run_ftm_in_dir(cwd: &Path, args: &[&str]) -> std::process::Output {
+    let mut child = ftm_command()
+        .args(args)
+        .current_dir(cwd)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("failed to spawn ftm");
+    let stdout_collector = spawn_pipe_drainer(child.stdout.take());
+    let stderr_collector = spawn_pipe_drainer(child.stderr.take());
+    let status = child.wait().expect("failed to wait on ftm");
+    let stdout = stdout_collector.join().unwrap_or_default();
+    let stderr = stderr_collector.join().unwrap_or_default();
+    std::process::Output {
+        status,
+        stdout,
+        stderr,
+    }
+}
+
+/// HTTP client for talking to a test server directly, bypassing any proxy.
+fn http() -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .no_proxy()
+        .build()
+        .unwrap()
+}
+
 /// Kill a process by PID (cross-platform: kill on Unix, taskkill on Windows).
 fn kill_process(pid: u32) {
     #[cfg(unix)]
@@ -334,16 +436,31 @@ fn wait_for_server_exit(child: &mut std::process::Child, timeout: std::time::Dur
 #[derive(Debug, Deserialize)]
 struct TestIndex {
     history: Vec<TestHistoryEntry>,
+    #[serde(default)]
+    next_seq: u64,
 }
 
 #[derive(Debug, Deserialize)]
 struct TestHistoryEntry {
+    #[serde(default)]
+    seq: u64,
+    timestamp: String,
     op: String,
     file: String,
     #[serde(default)]
     checksum: Option<String>,
     #[serde(default)]
+    algo: Option<String>,
+    #[serde(default)]
     size: Option<u64>,
+    #[serde(default)]
+    user_initiated: bool,
+    #[serde(default)]
+    trashed: bool,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    restored_from: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -436,6 +553,7 @@ mod checkout_tests {
 
     #[test]
     fn test_checkout_creates_ftm_directory() {
+        run_alone();
         let dir = setup_test_dir();
         let (mut server, port) = start_server();
 
@@ -461,6 +579,7 @@ mod checkout_tests {
 
     #[test]
     fn test_checkout_auto_starts_server() {
+        run_alone();
         let dir = setup_test_dir();
 
         let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
@@ -509,6 +628,7 @@ mod checkout_tests {
     /// Checkout should kill all ftm processes (including healthy ones) and start fresh.
     #[test]
     fn test_checkout_kills_all_servers() {
+        run_alone();
         if !cfg!(unix) {
             return;
         }
@@ -569,6 +689,7 @@ mod checkout_tests {
 
     #[test]
     fn test_checkout_same_dir_is_noop() {
+        run_alone();
         let dir = setup_test_dir();
         let (mut server, port) = start_server();
 
@@ -601,6 +722,7 @@ mod checkout_tests {
 
     #[test]
     fn test_checkout_switch_directory() {
+        run_alone();
         let dir_a = setup_test_dir();
         let dir_b = setup_test_dir();
 
@@ -678,2192 +800,7709 @@ mod checkout_tests {
     }
 }
 
-mod ls_tests {
+mod registry_tests {
     use super::*;
 
+    #[derive(Deserialize)]
+    struct ServerJson {
+        port: u16,
+        pid: u32,
+    }
+
     #[test]
-    fn test_ls_not_checked_out() {
-        let (mut server, port) = start_server();
+    fn test_checkout_writes_server_json() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let out = run_ftm_with_port(port, &["ls"]);
-        assert!(!out.status.success(), "ls should fail when not checked out");
-        assert!(
-            String::from_utf8_lossy(&out.stderr).contains("No directory checked out"),
-            "stderr: {}",
-            String::from_utf8_lossy(&out.stderr)
+        let content = std::fs::read_to_string(dir.path().join(".ftm/server.json"))
+            .expect("checkout should write .ftm/server.json");
+        let info: ServerJson = serde_json::from_str(&content).unwrap();
+        assert_eq!(info.port, port, "server.json should record the bound port");
+
+        // checkout replaces the spawned server with an auto-started one; ask it for its pid.
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let health: HealthPid = client
+            .get(format!("http://127.0.0.1:{}/api/health", port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(
+            Some(info.pid),
+            health.pid,
+            "server.json should record the server pid"
         );
 
         stop_server(&mut server);
     }
 
+    /// Client commands run inside (a subdirectory of) the watch dir find the port on their own.
     #[test]
-    fn test_ls_shows_watch_dir() {
+    fn test_client_discovers_port_from_cwd() {
         let dir = setup_test_dir();
-        let (mut server, port) = start_server_and_checkout(dir.path());
+        let sub = dir.path().join("nested/deeper");
+        std::fs::create_dir_all(&sub).unwrap();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
 
-        let output = run_ftm_with_port(port, &["ls"]);
-        assert!(output.status.success(), "ls should succeed");
-        let stdout = String::from_utf8_lossy(&output.stdout);
+        std::fs::write(dir.path().join("discover.yaml"), "found: yes").unwrap();
+        assert!(wait_for_index(dir.path(), "discover.yaml", 1, 3000));
 
-        let dir_str = dir
-            .path()
-            .canonicalize()
-            .unwrap_or_else(|_| dir.path().to_path_buf())
-            .to_string_lossy()
-            .to_string();
-        assert!(
-            stdout.contains(&dir_str),
-            "ls should show watch directory, got: {}",
-            stdout
-        );
+        let out = run_ftm_in_dir(&sub, &["ls"]);
         assert!(
-            stdout.contains("Watch directory:"),
-            "ls output should have 'Watch directory:' label"
+            out.status.success(),
+            "ls without --port should discover the server: stderr={}",
+            String::from_utf8_lossy(&out.stderr)
         );
+        assert!(String::from_utf8_lossy(&out.stdout).contains("discover.yaml"));
+
+        let out = run_ftm_in_dir(&sub, &["history", "discover.yaml"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("create"));
 
         stop_server(&mut server);
     }
 
+    /// `--port auto` starts the server on a free port, reports it, and registers it for discovery.
     #[test]
-    fn test_ls_empty() {
+    fn test_checkout_port_auto() {
+        run_alone();
         let dir = setup_test_dir();
-        let (mut server, port) = start_server_and_checkout(dir.path());
+        let path_s = dir.path().to_str().unwrap();
 
-        let out = run_ftm_with_port(port, &["ls"]);
-        assert!(out.status.success(), "ls should succeed");
+        let out = run_ftm_output(&["--port", "auto", "checkout", path_s]);
         assert!(
-            String::from_utf8_lossy(&out.stdout).contains("No files tracked yet"),
-            "stdout: {}",
-            String::from_utf8_lossy(&out.stdout)
+            out.status.success(),
+            "checkout --port auto should succeed: stdout={}, stderr={}",
+            String::from_utf8_lossy(&out.stdout),
+            String::from_utf8_lossy(&out.stderr),
+        );
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let url_line = stdout
+            .lines()
+            .find(|l| l.starts_with("Web UI: http://127.0.0.1:"))
+            .expect("checkout should print the web UI URL");
+        let port: u16 = url_line.rsplit(':').next().unwrap().parse().unwrap();
+        assert_ne!(port, 0);
+
+        let content = std::fs::read_to_string(dir.path().join(".ftm/server.json")).unwrap();
+        let info: ServerJson = serde_json::from_str(&content).unwrap();
+        assert_eq!(info.port, port, "server.json should record the chosen port");
+
+        let out = run_ftm_in_dir(dir.path(), &["ls"]);
+        assert!(
+            out.status.success(),
+            "ls should discover the auto-selected port: stderr={}",
+            String::from_utf8_lossy(&out.stderr)
         );
 
-        stop_server(&mut server);
+        // A second auto checkout of the same directory reuses the running server.
+        let out = run_ftm_output(&["--port", "auto", "checkout", path_s]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("Already watching"));
+
+        kill_process(info.pid);
     }
 
     #[test]
-    fn test_ls_after_checkout_and_write() {
+    fn test_invalid_port_value_rejected() {
+        let out = run_ftm_output(&["--port", "nope", "ls"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("expected a number or 'auto'"));
+    }
+
+    #[test]
+    fn test_graceful_stop_removes_server_json() {
         let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
+        let server_json = dir.path().join(".ftm/server.json");
+        assert!(server_json.exists());
 
-        std::fs::write(dir.path().join("a.yaml"), "a: 1").unwrap();
-        std::fs::write(dir.path().join("b.yaml"), "b: 2").unwrap();
-        std::fs::write(dir.path().join("c.yaml"), "c: 3").unwrap();
-
-        assert!(wait_for_index(dir.path(), "a.yaml", 1, 2000));
-        assert!(wait_for_index(dir.path(), "b.yaml", 1, 2000));
-        assert!(wait_for_index(dir.path(), "c.yaml", 1, 2000));
-
-        let out = run_ftm_with_port(port, &["ls"]);
-        assert!(out.status.success(), "ls should succeed");
-        let s = String::from_utf8_lossy(&out.stdout);
-        assert!(s.contains("a.yaml"), "stdout: {}", s);
-        assert!(s.contains("b.yaml"), "stdout: {}", s);
-        assert!(s.contains("c.yaml"), "stdout: {}", s);
+        let out = run_ftm_with_port(port, &["stop"]);
+        assert!(out.status.success());
+        assert!(wait_for_server_exit(
+            &mut server,
+            std::time::Duration::from_secs(5)
+        ));
+        assert!(
+            !server_json.exists(),
+            "graceful stop should unregister the server"
+        );
+    }
 
-        stop_server(&mut server);
+    /// Start a server listed in `state_dir` and check out `dir` through the API
+    /// (`ftm checkout` would kill the other servers).
+    fn start_listed_server(dir: &Path, state_dir: &Path) -> std::process::Child {
+        let (server, port) = start_server_with_env(&[], &[("FTM_STATE_DIR", state_dir)]);
+        let resp = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .post(format!("http://127.0.0.1:{}/api/checkout", port))
+            .json(&serde_json::json!({
+                "directory": dir.canonicalize().unwrap().to_str().unwrap()
+            }))
+            .send()
+            .unwrap();
+        assert!(resp.status().is_success());
+        server
     }
-}
 
-mod watcher_tests {
-    use super::*;
+    fn run_stop(state_dir: &Path, args: &[&str]) -> std::process::Output {
+        ftm_command()
+            .arg("stop")
+            .args(args)
+            .env("FTM_STATE_DIR", state_dir)
+            .output()
+            .expect("failed to run ftm stop")
+    }
 
     #[test]
-    fn test_excluded_files_not_tracked() {
-        let dir = setup_test_dir();
-        let (mut server, _port) = start_server_and_checkout(dir.path());
+    fn test_stop_by_dir_and_all() {
+        let state_dir = setup_test_dir();
+        let (dir_a, dir_b) = (setup_test_dir(), setup_test_dir());
+        let mut server_a = start_listed_server(dir_a.path(), state_dir.path());
+        let mut server_b = start_listed_server(dir_b.path(), state_dir.path());
 
-        // Write a file inside .ftm/ (excluded by default)
-        std::fs::write(dir.path().join(".ftm/sneaky.yaml"), "should: ignore").unwrap();
-        // Write a non-matching extension file
-        std::fs::write(dir.path().join("data.bin"), "binary stuff").unwrap();
-        // Write a tracked file as a reference
-        std::fs::write(dir.path().join("tracked.yaml"), "key: value").unwrap();
+        let out = run_stop(state_dir.path(), &["--dir", dir_a.path().to_str().unwrap()]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("Stopped server on port"));
+        let timeout = std::time::Duration::from_secs(5);
+        assert!(wait_for_server_exit(&mut server_a, timeout));
+        assert!(
+            server_b.try_wait().unwrap().is_none(),
+            "other server keeps running"
+        );
+
+        let out = run_stop(state_dir.path(), &["--all"]);
+        assert!(out.status.success());
+        assert!(wait_for_server_exit(&mut server_b, timeout));
+
+        let out = run_stop(state_dir.path(), &["--all"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("No running ftm servers found"));
+        assert_eq!(
+            std::fs::read_dir(state_dir.path().join("servers"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_one_daemon_serves_several_projects() {
+        let (dir_a, dir_b) = (setup_test_dir(), setup_test_dir());
+        let (mut server, port) = start_server();
 
+        let add = |dir: &Path, extra: &[&str]| {
+            let mut args = vec!["project", "add", dir.to_str().unwrap()];
+            args.extend_from_slice(extra);
+            let out = run_ftm_with_port(port, &args);
+            assert!(
+                out.status.success(),
+                "stderr: {}",
+                String::from_utf8_lossy(&out.stderr)
+            );
+            String::from_utf8_lossy(&out.stdout).into_owned()
+        };
+        add(dir_a.path(), &["--name", "alpha"]);
+        assert!(add(dir_b.path(), &["--name", "beta"]).contains("/projects/beta/"));
+        let out = run_ftm_with_port(port, &["project", "add", dir_b.path().to_str().unwrap()]);
         assert!(
-            wait_for_index(dir.path(), "tracked.yaml", 1, 2000),
-            "tracked.yaml should be recorded"
+            !out.status.success(),
+            "a watched directory cannot be added twice"
+        );
+        let dir_c = setup_test_dir();
+        let out = run_ftm_with_port(
+            port,
+            &[
+                "project",
+                "add",
+                dir_c.path().to_str().unwrap(),
+                "--name",
+                "alpha",
+            ],
+        );
+        assert!(!out.status.success(), "a name cannot be taken twice");
+        let missing = dir_c.path().join("missing");
+        let out = run_ftm_with_port(
+            port,
+            &[
+                "project",
+                "add",
+                missing.to_str().unwrap(),
+                "--name",
+                "gamma",
+            ],
         );
+        assert!(!out.status.success());
+        add(dir_c.path(), &["--name", "gamma"]);
 
-        let index = load_test_index(dir.path());
+        let out = run_ftm_with_port(port, &["project", "list"]);
+        let listed = String::from_utf8_lossy(&out.stdout).into_owned();
         assert!(
-            !index.history.iter().any(|e| e.file.contains("sneaky.yaml")),
-            "Files inside .ftm/ should not be tracked"
+            listed.contains("alpha") && listed.contains("beta"),
+            "{}",
+            listed
         );
+
+        // Clients inside a project directory are routed to that project
+        std::fs::write(dir_a.path().join("a.txt"), "in alpha").unwrap();
+        assert!(run_ftm_in_dir(dir_a.path(), &["scan"]).status.success());
+        let out = run_ftm_in_dir(dir_a.path(), &["history", "a.txt"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("create"));
+        assert!(load_test_index(dir_b.path()).history.is_empty());
+
+        let http = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let health: serde_json::Value = http
+            .get(format!("http://127.0.0.1:{}/api/health?project=beta", port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        let watch_dir = dir_b.path().canonicalize().unwrap();
+        assert_eq!(health["watch_dir"], watch_dir.to_str().unwrap());
+        let resp = http
+            .get(format!(
+                "http://127.0.0.1:{}/projects/delta/api/health",
+                port
+            ))
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 404);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_up_watches_workspace_directories() {
+        let (dir_a, dir_b, ws_dir) = (setup_test_dir(), setup_test_dir(), setup_test_dir());
+        let (mut server, port) = start_server();
+        let workspace = ws_dir.path().join("workspace.yaml");
+        std::fs::write(
+            &workspace,
+            format!(
+                "directories:\n  - path: {}\n    name: alpha\n    settings:\n      max_history: 7\n      watch.patterns: [\"*.md\", \"*.txt\"]\n  - path: {}\n  - path: {}\n",
+                dir_a.path().display(),
+                dir_b.path().display(),
+                ws_dir.path().join("missing").display(),
+            ),
+        )
+        .unwrap();
+        let up = || run_ftm_with_port(port, &["up", "--workspace", workspace.to_str().unwrap()]);
+
+        let out = up();
+        let stdout = String::from_utf8_lossy(&out.stdout).into_owned();
+        assert!(!out.status.success(), "the missing directory is reported");
+        assert!(stdout.contains("alpha: watching"), "stdout: {}", stdout);
+        let out = run_ftm_in_dir(dir_a.path(), &["config", "get", "settings.max_history"]);
+        assert_eq!(String::from_utf8_lossy(&out.stdout).trim(), "7");
+        let out = run_ftm_in_dir(dir_a.path(), &["config", "get", "watch.patterns"]);
+        assert_eq!(String::from_utf8_lossy(&out.stdout).trim(), "*.md,*.txt");
+        assert!(dir_b.path().join(".ftm/server.json").exists());
+
+        // Running it again leaves the watched directories alone
+        let out = up();
+        let stdout = String::from_utf8_lossy(&out.stdout).into_owned();
         assert!(
-            !index.history.iter().any(|e| e.file.contains("data.bin")),
-            "Non-matching extension files should not be tracked"
+            stdout.contains("alpha: already watching"),
+            "stdout: {}",
+            stdout
         );
 
         stop_server(&mut server);
     }
+}
+
+mod mdns_tests {
+    use super::*;
+
+    fn server_log(dir: &Path) -> String {
+        let log_dir = dir.join(".ftm/logs");
+        std::fs::read_dir(&log_dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| std::fs::read_to_string(e.path()).unwrap_or_default())
+            .collect()
+    }
 
+    /// Binding to a non-loopback address advertises the Web UI; loopback does not.
     #[test]
-    fn test_non_matching_extension_ignored() {
+    fn test_lan_bind_advertises_mdns() {
+        run_alone();
         let dir = setup_test_dir();
-        let (mut server, _port) = start_server_and_checkout(dir.path());
-
-        std::fs::write(dir.path().join("app.exe"), "not tracked").unwrap();
-        std::fs::write(dir.path().join("image.png"), "not tracked").unwrap();
-        // Reference file to prove watcher is running
-        std::fs::write(dir.path().join("ref.rs"), "fn main() {}").unwrap();
+        let path_s = dir.path().to_str().unwrap();
 
+        let out = run_ftm_output(&["--port", "auto", "checkout", path_s, "--bind", "0.0.0.0"]);
         assert!(
-            wait_for_index(dir.path(), "ref.rs", 1, 2000),
-            "ref.rs should be recorded"
+            out.status.success(),
+            "checkout --bind 0.0.0.0 should succeed: stderr={}",
+            String::from_utf8_lossy(&out.stderr)
         );
-
-        let index = load_test_index(dir.path());
+        let out = run_ftm_in_dir(dir.path(), &["ls"]);
+        assert!(out.status.success(), "server should be reachable locally");
         assert!(
-            !index.history.iter().any(|e| e.file == "app.exe"),
-            ".exe files should not be tracked"
+            server_log(dir.path()).contains("Advertising Web UI via mDNS"),
+            "LAN-bound server should advertise via mDNS"
         );
+        let out = run_ftm_in_dir(dir.path(), &["stop"]);
+        assert!(out.status.success());
+
+        let dir = setup_test_dir();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
         assert!(
-            !index.history.iter().any(|e| e.file == "image.png"),
-            ".png files should not be tracked"
+            !server_log(dir.path()).contains("mDNS"),
+            "loopback server should not advertise"
         );
-
         stop_server(&mut server);
     }
+}
+
+mod api_token_tests {
+    use super::*;
+
+    /// This machine's address on its default route, if it has one besides loopback.
+    fn lan_ip() -> Option<std::net::IpAddr> {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+        socket.connect("198.51.100.1:9").ok()?;
+        Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_loopback())
+    }
 
+    /// A server bound beyond loopback takes changes from other machines only with its
+    /// API token; reads, and every request from this machine, need none.
     #[test]
-    fn test_subdirectory_files_tracked() {
+    fn test_lan_bound_server_requires_token_for_remote_changes() {
+        let state_dir = setup_test_dir();
         let dir = setup_test_dir();
-        let sub_dir = dir.path().join("sub/deep");
-        std::fs::create_dir_all(&sub_dir).unwrap();
+        let (mut server, port) = start_server_with_env(
+            &["--bind", "0.0.0.0"],
+            &[("FTM_STATE_DIR", state_dir.path())],
+        );
+        let token = std::fs::read_to_string(state_dir.path().join("api.token")).unwrap();
+        assert_eq!(token.len(), 64);
 
-        let (mut server, port) = start_server_and_checkout(dir.path());
+        let http = http();
+        let resp = http
+            .post(format!("http://127.0.0.1:{}/api/checkout", port))
+            .json(&serde_json::json!({ "directory": dir.path().to_str().unwrap() }))
+            .send()
+            .unwrap();
+        assert!(resp.status().is_success(), "local clients need no token");
+
+        if let Some(lan) = lan_ip() {
+            let url = |path: &str| format!("http://{}:{}{}", lan, port, path);
+            assert_eq!(http.get(url("/api/health")).send().unwrap().status(), 200);
+            let scan = |auth: Option<&str>| {
+                let req = http.post(url("/api/scan"));
+                match auth {
+                    Some(token) => req.bearer_auth(token),
+                    None => req,
+                }
+                .send()
+                .unwrap()
+                .status()
+            };
+            assert_eq!(scan(None), 401);
+            assert_eq!(scan(Some("guess")), 401);
+            assert_eq!(scan(Some(&token)), 200);
+        }
 
-        std::fs::write(sub_dir.join("foo.rs"), "fn hello() {}").unwrap();
+        stop_server(&mut server);
+    }
+}
 
-        assert!(
-            wait_for_index(dir.path(), "sub/deep/foo.rs", 1, 2000),
-            "sub/deep/foo.rs should be recorded with relative path"
-        );
+mod ipv6_tests {
+    use super::*;
 
-        let ls_output = run_ftm_with_port(port, &["ls"]);
-        assert!(ls_output.status.success(), "ls should succeed");
-        let ls_stdout = String::from_utf8_lossy(&ls_output.stdout);
-        assert!(
-            ls_stdout.contains("foo.rs") && ls_stdout.contains("sub") && ls_stdout.contains("deep"),
-            "ls should show sub/deep/foo.rs (tree format); got:\n{}",
-            ls_stdout
-        );
+    fn registered_port(dir: &Path) -> u16 {
+        let content = std::fs::read_to_string(dir.join(".ftm/server.json")).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&content).unwrap();
+        v["port"].as_u64().unwrap() as u16
+    }
 
-        stop_server(&mut server);
+    fn health_ok(url: &str) -> bool {
+        reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(url)
+            .timeout(std::time::Duration::from_secs(2))
+            .send()
+            .is_ok_and(|r| r.status().is_success())
     }
 
+    /// `--bind ::1` serves over IPv6 loopback and clients use a bracketed literal.
     #[test]
-    fn test_empty_file_ignored() {
+    fn test_bind_ipv6_loopback() {
+        run_alone();
         let dir = setup_test_dir();
-        let (mut server, _port) = start_server_and_checkout(dir.path());
+        let path_s = dir.path().to_str().unwrap();
 
-        // Write an empty file
-        std::fs::write(dir.path().join("empty.txt"), "").unwrap();
-        // Write a non-empty reference file
-        std::fs::write(dir.path().join("notempty.txt"), "hello").unwrap();
+        let out = run_ftm_output(&["--port", "auto", "checkout", path_s, "--bind", "::1"]);
+        assert!(
+            out.status.success(),
+            "checkout --bind ::1 should succeed: stderr={}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert!(String::from_utf8_lossy(&out.stdout).contains("Web UI: http://[::1]:"));
+
+        let port = registered_port(dir.path());
+        assert!(health_ok(&format!("http://[::1]:{}/api/health", port)));
 
+        let out = run_ftm_in_dir(dir.path(), &["ls"]);
         assert!(
-            wait_for_index(dir.path(), "notempty.txt", 1, 2000),
-            "notempty.txt should be recorded"
+            out.status.success(),
+            "ls should reach the IPv6 server: stderr={}",
+            String::from_utf8_lossy(&out.stderr)
         );
+        let out = run_ftm_in_dir(dir.path(), &["stop"]);
+        assert!(out.status.success());
+    }
 
-        let index = load_test_index(dir.path());
+    /// `--bind ::` listens dual-stack: reachable over both IPv4 and IPv6.
+    #[test]
+    fn test_bind_dual_stack() {
+        run_alone();
+        let dir = setup_test_dir();
+        let path_s = dir.path().to_str().unwrap();
+
+        let out = run_ftm_output(&["--port", "auto", "checkout", path_s, "--bind", "::"]);
+        assert!(out.status.success());
+
+        let port = registered_port(dir.path());
+        assert!(health_ok(&format!("http://[::1]:{}/api/health", port)));
         assert!(
-            !index.history.iter().any(|e| e.file == "empty.txt"),
-            "Empty files should not be tracked"
+            health_ok(&format!("http://127.0.0.1:{}/api/health", port)),
+            ":: should also accept IPv4 connections"
         );
 
-        stop_server(&mut server);
+        let out = run_ftm_in_dir(dir.path(), &["stop"]);
+        assert!(out.status.success());
     }
 }
 
-mod rename_tests {
+mod remote_host_tests {
     use super::*;
 
-    /// Simulate file-manager "delete" (e.g. Finder, Nautilus, Explorer):
-    /// move (rename) a tracked file out of the watched directory.
-    /// The watcher should detect this as a delete.
+    /// Run ftm from `cwd` with FTM_SERVER set.
+    fn run_with_server_env(cwd: &Path, server: &str, args: &[&str]) -> std::process::Output {
+        ftm_command()
+            .args(args)
+            .current_dir(cwd)
+            .env("FTM_SERVER", server)
+            .output()
+            .expect("failed to run ftm")
+    }
+
     #[test]
-    fn test_file_moved_out_detected_as_delete() {
+    fn test_read_only_commands_target_host() {
         let dir = setup_test_dir();
-        // Create a directory outside the watched tree to move files into
-        // (simulates Trash / Recycle Bin or any external location).
-        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "hello\n").unwrap();
+        // Checked out over the API so that stopping this server leaves none behind
+        let (mut server, port) = start_server();
+        checkout_over_api(port, dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let first = load_test_index(dir.path()).history[0]
+            .checksum
+            .clone()
+            .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "hello\nworld\n").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        // Elsewhere, so nothing is discovered from a .ftm above the current directory
+        let elsewhere = setup_test_dir();
+        let port_s = port.to_string();
+
+        let out = run_ftm_in_dir(
+            elsewhere.path(),
+            &["--host", "127.0.0.1", "--port", &port_s, "ls"],
+        );
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert!(String::from_utf8_lossy(&out.stdout).contains("notes.txt (2 entries)"));
 
-        let (mut server, _port) = start_server_and_checkout(dir.path());
-        let file_path = dir.path().join("finder_del.txt");
+        let server_addr = format!("localhost:{}", port);
+        let out = run_with_server_env(elsewhere.path(), &server_addr, &["history", "notes.txt"]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert!(String::from_utf8_lossy(&out.stdout).starts_with("History for 'notes.txt':"));
+        let out = run_with_server_env(elsewhere.path(), &server_addr, &["stats"]);
+        assert!(out.status.success());
 
-        // Create the file so watcher records it
-        std::fs::write(&file_path, "will be moved to trash").unwrap();
+        let out = run_with_server_env(elsewhere.path(), &server_addr, &["cat", "notes.txt"]);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "hello\nworld\n");
+        let out = run_with_server_env(
+            elsewhere.path(),
+            &server_addr,
+            &["cat", "notes.txt", &first[..8]],
+        );
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "hello\n");
+        let out = run_with_server_env(elsewhere.path(), &server_addr, &["diff", "notes.txt"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
         assert!(
-            wait_for_index(dir.path(), "finder_del.txt", 1, 2000),
-            "Initial create should be recorded"
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert!(
+            stdout.contains("@@ -1,1 +1,2 @@\n hello\n+world\n"),
+            "stdout: {}",
+            stdout
         );
 
-        // Move the file out of the watched directory (mimics move-to-trash)
-        let dest = outside.path().join("finder_del.txt");
-        std::fs::rename(&file_path, &dest).unwrap();
+        // With --host, commands changing the watched directory are refused before any
+        // request; FTM_SERVER leaves them to the local server
+        let out = run_ftm_in_dir(elsewhere.path(), &["--host", &server_addr, "scan"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("Only read-only commands"));
+        let out = run_with_server_env(elsewhere.path(), &server_addr, &["--port", &port_s, "scan"]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+
+        stop_server(&mut server);
 
+        let out = run_with_server_env(elsewhere.path(), &server_addr, &["ls"]);
+        assert!(!out.status.success());
         assert!(
-            wait_for_index(dir.path(), "finder_del.txt", 2, 4000),
-            "Move-out (rename) should be recorded as delete"
+            String::from_utf8_lossy(&out.stderr).contains("No ftm server reachable at"),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
         );
+    }
+}
 
-        let index = load_test_index(dir.path());
-        let entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "finder_del.txt")
-            .collect();
-        assert_eq!(entries.len(), 2, "Should have 2 entries (create + delete)");
-        assert_eq!(entries[0].op, "create");
-        assert_eq!(entries[1].op, "delete");
+mod ls_tests {
+    use super::*;
+
+    #[test]
+    fn test_ls_not_checked_out() {
+        let (mut server, port) = start_server();
+
+        let out = run_ftm_with_port(port, &["ls"]);
+        assert!(!out.status.success(), "ls should fail when not checked out");
         assert!(
-            entries[1].checksum.is_none(),
-            "Delete should have no checksum"
+            String::from_utf8_lossy(&out.stderr).contains("No directory checked out"),
+            "stderr: {}",
+            String::from_utf8_lossy(&out.stderr)
         );
 
         stop_server(&mut server);
     }
 
-    /// Move a file from outside into the watched directory.
-    /// The watcher should detect this as a new file (create/snapshot).
     #[test]
-    fn test_file_moved_in_detected_as_create() {
+    fn test_ls_shows_watch_dir() {
         let dir = setup_test_dir();
-        let outside = tempfile::tempdir().unwrap();
-
-        let (mut server, _port) = start_server_and_checkout(dir.path());
-
-        // Create a file outside the watched directory
-        let external_file = outside.path().join("incoming.txt");
-        std::fs::write(&external_file, "moved in from outside").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // Move it into the watched directory
-        let dest = dir.path().join("incoming.txt");
-        std::fs::rename(&external_file, &dest).unwrap();
+        let output = run_ftm_with_port(port, &["ls"]);
+        assert!(output.status.success(), "ls should succeed");
+        let stdout = String::from_utf8_lossy(&output.stdout);
 
+        let dir_str = dir
+            .path()
+            .canonicalize()
+            .unwrap_or_else(|_| dir.path().to_path_buf())
+            .to_string_lossy()
+            .to_string();
         assert!(
-            wait_for_index(dir.path(), "incoming.txt", 1, 4000),
-            "Move-in (rename) should be recorded as create"
+            stdout.contains(&dir_str),
+            "ls should show watch directory, got: {}",
+            stdout
         );
-
-        let index = load_test_index(dir.path());
-        let entry = index
-            .history
-            .iter()
-            .find(|e| e.file == "incoming.txt")
-            .expect("incoming.txt should have a history entry");
-        assert_eq!(entry.op, "create");
         assert!(
-            entry.checksum.is_some(),
-            "Create entry should have a checksum"
+            stdout.contains("Watch directory:"),
+            "ls output should have 'Watch directory:' label"
         );
 
         stop_server(&mut server);
     }
 
-    /// Rename a file within the watched directory.  The watcher should record
-    /// a delete for the old name and a create for the new name.
     #[test]
-    fn test_rename_within_watched_dir() {
+    fn test_ls_empty() {
         let dir = setup_test_dir();
-        let (mut server, _port) = start_server_and_checkout(dir.path());
+        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let old_path = dir.path().join("before.txt");
-        std::fs::write(&old_path, "rename me").unwrap();
+        let out = run_ftm_with_port(port, &["ls"]);
+        assert!(out.status.success(), "ls should succeed");
         assert!(
-            wait_for_index(dir.path(), "before.txt", 1, 2000),
-            "Initial create should be recorded"
+            String::from_utf8_lossy(&out.stdout).contains("No files tracked yet"),
+            "stdout: {}",
+            String::from_utf8_lossy(&out.stdout)
         );
 
-        // Rename within the watched directory
-        let new_path = dir.path().join("after.txt");
-        std::fs::rename(&old_path, &new_path).unwrap();
+        stop_server(&mut server);
+    }
 
-        assert!(
-            wait_for_index(dir.path(), "before.txt", 2, 4000),
-            "Old name should get a delete entry"
-        );
-        assert!(
-            wait_for_index(dir.path(), "after.txt", 1, 4000),
-            "New name should get a create entry"
-        );
+    #[test]
+    fn test_ls_after_checkout_and_write() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let index = load_test_index(dir.path());
+        std::fs::write(dir.path().join("a.yaml"), "a: 1").unwrap();
+        std::fs::write(dir.path().join("b.yaml"), "b: 2").unwrap();
+        std::fs::write(dir.path().join("c.yaml"), "c: 3").unwrap();
 
-        let old_entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "before.txt")
-            .collect();
-        assert_eq!(
-            old_entries.len(),
-            2,
-            "Old name should have 2 entries (create + delete)"
-        );
-        assert_eq!(old_entries[0].op, "create");
-        assert_eq!(old_entries[1].op, "delete");
+        assert!(wait_for_index(dir.path(), "a.yaml", 1, 2000));
+        assert!(wait_for_index(dir.path(), "b.yaml", 1, 2000));
+        assert!(wait_for_index(dir.path(), "c.yaml", 1, 2000));
 
-        let new_entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "after.txt")
-            .collect();
-        assert_eq!(
-            new_entries.len(),
-            1,
-            "New name should have 1 entry (create)"
-        );
-        assert_eq!(new_entries[0].op, "create");
+        let out = run_ftm_with_port(port, &["ls"]);
+        assert!(out.status.success(), "ls should succeed");
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(s.contains("a.yaml"), "stdout: {}", s);
+        assert!(s.contains("b.yaml"), "stdout: {}", s);
+        assert!(s.contains("c.yaml"), "stdout: {}", s);
 
         stop_server(&mut server);
     }
+}
+
+mod watcher_tests {
+    use super::*;
 
-    /// Rename a folder within the watched directory. Old path files should get delete
-    /// entries; new path files should get create entries.
     #[test]
-    fn test_rename_folder_within_watched_dir() {
-        let dir = setup_test_dir();
-        let (mut server, _port) = start_server_and_checkout(dir.path());
+    fn test_snapshot_trigger_modes() {
+        use std::io::Write;
 
-        let old_dir = dir.path().join("old_name");
-        std::fs::create_dir_all(&old_dir).unwrap();
-        std::fs::write(old_dir.join("a.txt"), "content a").unwrap();
-        std::fs::write(old_dir.join("b.rs"), "content b").unwrap();
+        let dir = setup_test_dir();
+        std::fs::write(dir.path().join("a.txt"), "v1\n").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
 
-        assert!(
-            wait_for_index(dir.path(), "old_name/a.txt", 1, 3000),
-            "old_name/a.txt should be recorded"
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.snapshot_trigger", "often"],
         );
-        assert!(
-            wait_for_index(dir.path(), "old_name/b.rs", 1, 3000),
-            "old_name/b.rs should be recorded"
+        assert!(!out.status.success());
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.snapshot_trigger", "close_write"],
         );
+        assert!(out.status.success());
 
-        let new_dir = dir.path().join("new_name");
-        std::fs::rename(&old_dir, &new_dir).unwrap();
+        // Written but still open: nothing to snapshot until the writer closes it
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("a.txt"))
+            .unwrap();
+        file.write_all(b"v2\n").unwrap();
+        file.flush().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        assert!(!wait_for_index(dir.path(), "a.txt", 2, 0));
+        drop(file);
+        assert!(wait_for_index(dir.path(), "a.txt", 2, 5000));
 
-        assert!(
-            wait_for_index(dir.path(), "old_name/a.txt", 2, 5000),
-            "old_name/a.txt should have create + delete"
-        );
-        assert!(
-            wait_for_index(dir.path(), "old_name/b.rs", 2, 5000),
-            "old_name/b.rs should have create + delete"
-        );
-        assert!(
-            wait_for_index(dir.path(), "new_name/a.txt", 1, 5000),
-            "new_name/a.txt should be recorded after folder rename"
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.snapshot_trigger", "poll"],
         );
-        assert!(
-            wait_for_index(dir.path(), "new_name/b.rs", 1, 5000),
-            "new_name/b.rs should be recorded after folder rename"
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["config", "get", "settings.snapshot_trigger"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("poll"));
+        std::fs::write(dir.path().join("b.txt"), "polled").unwrap();
+        assert!(wait_for_index(dir.path(), "b.txt", 1, 15000));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_snapshot_rate_limit_defers_changes() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        // Refills one snapshot every 20s, so only the initial burst lands in the test
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.max_snapshots_per_minute", "3"],
         );
+        assert!(out.status.success());
 
-        let index = load_test_index(dir.path());
-        for file in &["old_name/a.txt", "old_name/b.rs"] {
-            let entries: Vec<_> = index.history.iter().filter(|e| e.file == *file).collect();
-            assert_eq!(
-                entries.len(),
-                2,
-                "{} should have 2 entries (create + delete)",
-                file
-            );
-            assert_eq!(entries[0].op, "create");
-            assert_eq!(entries[1].op, "delete");
-        }
-        for file in &["new_name/a.txt", "new_name/b.rs"] {
-            let entries: Vec<_> = index.history.iter().filter(|e| e.file == *file).collect();
-            assert_eq!(entries.len(), 1, "{} should have 1 create entry", file);
-            assert_eq!(entries[0].op, "create");
+        for i in 0..6 {
+            std::fs::write(dir.path().join(format!("f{}.txt", i)), format!("v{}", i)).unwrap();
         }
+        std::thread::sleep(std::time::Duration::from_millis(2000));
+        let tracked = |dir: &Path| {
+            load_test_index(dir)
+                .history
+                .iter()
+                .filter(|e| e.file.starts_with('f'))
+                .count()
+        };
+        assert_eq!(tracked(dir.path()), 3, "watcher should stop at the limit");
+
+        // Deferred files are not mistaken for deletes, and manual scans are unlimited
+        let index = load_test_index(dir.path());
+        assert!(index.history.iter().all(|e| e.op == "create"));
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert_eq!(tracked(dir.path()), 6);
 
         stop_server(&mut server);
     }
 
-    /// Move a folder (with tracked files) out of the watched directory.
-    /// Index should record delete for all files under that path.
     #[test]
-    fn test_rename_folder_move_out() {
+    fn test_health_reports_last_event_and_snapshot() {
         let dir = setup_test_dir();
-        let outside = tempfile::tempdir().unwrap();
-        let (mut server, _port) = start_server_and_checkout(dir.path());
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let health = || -> serde_json::Value {
+            reqwest::blocking::Client::builder()
+                .no_proxy()
+                .build()
+                .unwrap()
+                .get(format!("http://127.0.0.1:{}/api/health", port))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap()
+        };
+        let h = health();
+        assert!(h["last_event_at"].is_null(), "{}", h);
+        assert!(h["last_snapshot_at"].is_null(), "{}", h);
+
+        let before = chrono::Utc::now();
+        std::fs::write(dir.path().join("probe.txt"), "alive").unwrap();
+        assert!(wait_for_index(dir.path(), "probe.txt", 1, 5000));
+        // The counters are updated right after the index is saved
+        let mut h = health();
+        for _ in 0..20 {
+            if !h["last_snapshot_at"].is_null() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            h = health();
+        }
+        let at = |key: &str| {
+            chrono::DateTime::parse_from_rfc3339(h[key].as_str().unwrap())
+                .unwrap()
+                .with_timezone(&chrono::Utc)
+        };
+        assert!(at("last_event_at") >= before - chrono::Duration::seconds(1));
+        assert!(at("last_snapshot_at") >= at("last_event_at"));
 
-        let subdir = dir.path().join("subdir");
-        std::fs::create_dir_all(&subdir).unwrap();
-        std::fs::write(subdir.join("f.txt"), "moved out").unwrap();
+        stop_server(&mut server);
+    }
 
-        assert!(
-            wait_for_index(dir.path(), "subdir/f.txt", 1, 3000),
-            "subdir/f.txt should be recorded"
-        );
+    #[test]
+    fn test_watcher_sees_index_changes_made_by_others() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let a = dir.path().join("a.txt");
+        std::fs::write(&a, "one").unwrap();
+        assert!(wait_for_index(dir.path(), "a.txt", 1, 5000));
+        std::fs::write(&a, "two").unwrap();
+        assert!(wait_for_index(dir.path(), "a.txt", 2, 5000));
+
+        // Trimmed by the server while the watcher holds its copy of the index
+        let set = |v: &str| run_ftm_with_port(port, &["config", "set", "settings.max_history", v]);
+        assert!(set("1").status.success());
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        assert!(set("100").status.success());
+        assert_eq!(load_test_index(dir.path()).history.len(), 1);
+
+        std::fs::write(dir.path().join("b.txt"), "new").unwrap();
+        assert!(wait_for_index(dir.path(), "b.txt", 1, 5000));
+        let files: Vec<String> = load_test_index(dir.path())
+            .history
+            .into_iter()
+            .map(|e| e.file)
+            .collect();
+        assert_eq!(files, ["a.txt", "b.txt"], "trimmed entry came back");
 
-        let dest = outside.path().join("subdir");
-        std::fs::rename(&subdir, &dest).unwrap();
+        stop_server(&mut server);
+    }
 
-        assert!(
-            wait_for_index(dir.path(), "subdir/f.txt", 2, 5000),
-            "subdir/f.txt should have create + delete after folder move-out"
-        );
+    /// Directories listed by an earlier batch are reused while their mtime is unchanged;
+    /// changes inside them, and new or removed entries, must still be seen.
+    #[test]
+    fn test_incremental_scan_sees_changes_in_cached_dirs() {
+        let dir = setup_test_dir();
+        let deep = dir.path().join("src/deep");
+        std::fs::create_dir_all(&deep).unwrap();
+        std::fs::write(deep.join("a.txt"), "one").unwrap();
+        std::fs::write(dir.path().join("src/b.txt"), "b").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        // Let the directories settle so the next batch caches their listings
+        std::thread::sleep(std::time::Duration::from_millis(2500));
+        std::fs::write(dir.path().join("top.txt"), "top").unwrap();
+        assert!(wait_for_index(dir.path(), "top.txt", 1, 5000));
+
+        std::fs::write(deep.join("a.txt"), "two").unwrap();
+        assert!(wait_for_index(dir.path(), "src/deep/a.txt", 2, 5000));
+        std::fs::write(deep.join("c.txt"), "c").unwrap();
+        assert!(wait_for_index(dir.path(), "src/deep/c.txt", 1, 5000));
+        std::fs::remove_file(dir.path().join("src/b.txt")).unwrap();
+        assert!(wait_for_index(dir.path(), "src/b.txt", 2, 5000));
+        std::fs::create_dir(dir.path().join("src/new")).unwrap();
+        std::fs::write(dir.path().join("src/new/d.txt"), "d").unwrap();
+        assert!(wait_for_index(dir.path(), "src/new/d.txt", 1, 5000));
 
-        let index = load_test_index(dir.path());
-        let entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "subdir/f.txt")
-            .collect();
-        assert_eq!(entries.len(), 2);
-        assert_eq!(entries[0].op, "create");
-        assert_eq!(entries[1].op, "delete");
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.incremental_scan", "false"],
+        );
+        assert!(out.status.success());
+        std::fs::write(deep.join("a.txt"), "three").unwrap();
+        assert!(wait_for_index(dir.path(), "src/deep/a.txt", 3, 5000));
 
         stop_server(&mut server);
     }
 
-    /// Move a folder from outside into the watched directory.
-    /// Index should record create for all matching files under the new path.
+    #[cfg(target_os = "linux")]
     #[test]
-    fn test_rename_folder_move_in() {
+    fn test_track_writers_records_writing_process() {
+        use std::io::Write;
         let dir = setup_test_dir();
-        let outside = tempfile::tempdir().unwrap();
-        let (mut server, _port) = start_server_and_checkout(dir.path());
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let out = run_ftm_with_port(port, &["config", "set", "settings.track_writers", "true"]);
+        assert!(out.status.success());
 
-        let external_dir = outside.path().join("incoming");
-        std::fs::create_dir_all(&external_dir).unwrap();
-        std::fs::write(external_dir.join("x.yaml"), "moved in").unwrap();
+        // Still open for writing when the watcher records it, so /proc finds this process
+        // even without fanotify
+        let mut file = std::fs::File::create(dir.path().join("held.txt")).unwrap();
+        file.write_all(b"written by the test").unwrap();
+        file.flush().unwrap();
+        assert!(wait_for_index(dir.path(), "held.txt", 1, 5000));
 
-        let dest = dir.path().join("incoming");
-        std::fs::rename(&external_dir, &dest).unwrap();
+        let index: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(dir.path().join(".ftm/index.json")).unwrap(),
+        )
+        .unwrap();
+        let comm = std::fs::read_to_string("/proc/self/comm").unwrap();
+        assert_eq!(index["history"][0]["writer"], comm.trim_end());
+        drop(file);
 
+        let out = run_ftm_with_port(port, &["history", "held.txt"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
         assert!(
-            wait_for_index(dir.path(), "incoming/x.yaml", 1, 5000),
-            "incoming/x.yaml should be recorded after folder move-in"
+            stdout.contains(&format!("by {}", comm.trim_end())),
+            "{}",
+            stdout
         );
 
-        let index = load_test_index(dir.path());
-        let entry = index
-            .history
-            .iter()
-            .find(|e| e.file == "incoming/x.yaml")
-            .expect("incoming/x.yaml should have a history entry");
-        assert_eq!(entry.op, "create");
-        assert!(entry.checksum.is_some());
-
         stop_server(&mut server);
     }
-}
-
-mod dedup_tests {
-    use super::*;
 
     #[test]
-    fn test_same_content_no_duplicate_entry() {
+    fn test_excluded_files_not_tracked() {
         let dir = setup_test_dir();
         let (mut server, _port) = start_server_and_checkout(dir.path());
 
-        let content = "key: same_content";
+        // Write a file inside .ftm/ (excluded by default)
+        std::fs::write(dir.path().join(".ftm/sneaky.yaml"), "should: ignore").unwrap();
+        // Write a non-matching extension file
+        std::fs::write(dir.path().join("data.bin"), "binary stuff").unwrap();
+        // Write a tracked file as a reference
+        std::fs::write(dir.path().join("tracked.yaml"), "key: value").unwrap();
 
-        // First write
-        std::fs::write(dir.path().join("dup.yaml"), content).unwrap();
         assert!(
-            wait_for_index(dir.path(), "dup.yaml", 1, 2000),
-            "First write should be recorded"
+            wait_for_index(dir.path(), "tracked.yaml", 1, 2000),
+            "tracked.yaml should be recorded"
         );
 
-        // Second write with identical content
-        std::fs::write(dir.path().join("dup.yaml"), content).unwrap();
-
-        // Write a sync marker
-        std::fs::write(dir.path().join("sync.yaml"), "sync: marker").unwrap();
+        let index = load_test_index(dir.path());
         assert!(
-            wait_for_index(dir.path(), "sync.yaml", 1, 2000),
-            "Sync marker should be recorded"
+            !index.history.iter().any(|e| e.file.contains("sneaky.yaml")),
+            "Files inside .ftm/ should not be tracked"
         );
-
-        let index = load_test_index(dir.path());
-        let count = index
-            .history
-            .iter()
-            .filter(|e| e.file == "dup.yaml")
-            .count();
-        assert_eq!(
-            count, 1,
-            "Same content written twice should produce only 1 entry, got {}",
-            count
+        assert!(
+            !index.history.iter().any(|e| e.file.contains("data.bin")),
+            "Non-matching extension files should not be tracked"
         );
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_different_files_same_content_share_snapshot() {
+    fn test_non_matching_extension_ignored() {
         let dir = setup_test_dir();
         let (mut server, _port) = start_server_and_checkout(dir.path());
 
-        let content = "shared: content_value_12345";
-        std::fs::write(dir.path().join("file_a.yaml"), content).unwrap();
-        assert!(
-            wait_for_index(dir.path(), "file_a.yaml", 1, 2000),
-            "file_a.yaml should be recorded"
-        );
+        std::fs::write(dir.path().join("app.exe"), "not tracked").unwrap();
+        std::fs::write(dir.path().join("image.png"), "not tracked").unwrap();
+        // Reference file to prove watcher is running
+        std::fs::write(dir.path().join("ref.rs"), "fn main() {}").unwrap();
 
-        std::fs::write(dir.path().join("file_b.yaml"), content).unwrap();
         assert!(
-            wait_for_index(dir.path(), "file_b.yaml", 1, 2000),
-            "file_b.yaml should be recorded"
+            wait_for_index(dir.path(), "ref.rs", 1, 2000),
+            "ref.rs should be recorded"
         );
 
         let index = load_test_index(dir.path());
-        assert!(index.history.iter().any(|e| e.file == "file_a.yaml"));
-        assert!(index.history.iter().any(|e| e.file == "file_b.yaml"));
-
-        // Only 1 snapshot file (content-addressable dedup)
-        let snap_count = count_snapshot_files(dir.path());
-        assert_eq!(
-            snap_count, 1,
-            "Two files with same content should share 1 snapshot, got {}",
-            snap_count
+        assert!(
+            !index.history.iter().any(|e| e.file == "app.exe"),
+            ".exe files should not be tracked"
         );
-
-        // Both entries should have the same checksum
-        let checksum_a = index
-            .history
-            .iter()
-            .find(|e| e.file == "file_a.yaml")
-            .and_then(|e| e.checksum.as_ref());
-        let checksum_b = index
-            .history
-            .iter()
-            .find(|e| e.file == "file_b.yaml")
-            .and_then(|e| e.checksum.as_ref());
-        assert_eq!(
-            checksum_a, checksum_b,
-            "Both files should have the same checksum"
+        assert!(
+            !index.history.iter().any(|e| e.file == "image.png"),
+            ".png files should not be tracked"
         );
 
         stop_server(&mut server);
     }
-}
-
-mod history_tests {
-    use super::*;
-
-    #[test]
-    fn test_history_not_checked_out() {
-        let (mut server, port) = start_server();
-
-        let out = run_ftm_with_port(port, &["history", "test.rs"]);
-        assert!(!out.status.success());
-        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
-
-        stop_server(&mut server);
-    }
 
     #[test]
-    fn test_history_no_entries() {
+    fn test_subdirectory_files_tracked() {
         let dir = setup_test_dir();
+        let sub_dir = dir.path().join("sub/deep");
+        std::fs::create_dir_all(&sub_dir).unwrap();
+
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let out = run_ftm_with_port(port, &["history", "nonexistent.rs"]);
-        assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("No history for"));
+        std::fs::write(sub_dir.join("foo.rs"), "fn hello() {}").unwrap();
+
+        assert!(
+            wait_for_index(dir.path(), "sub/deep/foo.rs", 1, 2000),
+            "sub/deep/foo.rs should be recorded with relative path"
+        );
+
+        let ls_output = run_ftm_with_port(port, &["ls"]);
+        assert!(ls_output.status.success(), "ls should succeed");
+        let ls_stdout = String::from_utf8_lossy(&ls_output.stdout);
+        assert!(
+            ls_stdout.contains("foo.rs") && ls_stdout.contains("sub") && ls_stdout.contains("deep"),
+            "ls should show sub/deep/foo.rs (tree format); got:\n{}",
+            ls_stdout
+        );
 
         stop_server(&mut server);
     }
-}
-
-mod history_ops_tests {
-    use super::*;
 
     #[test]
-    fn test_history_create_then_modify_ops() {
+    fn test_empty_file_ignored() {
         let dir = setup_test_dir();
         let (mut server, _port) = start_server_and_checkout(dir.path());
-        let file_path = dir.path().join("ops.yaml");
 
-        // Create
-        std::fs::write(&file_path, "version: 1").unwrap();
-        assert!(wait_for_index(dir.path(), "ops.yaml", 1, 2000));
+        // Write an empty file
+        std::fs::write(dir.path().join("empty.txt"), "").unwrap();
+        // Write a non-empty reference file
+        std::fs::write(dir.path().join("notempty.txt"), "hello").unwrap();
 
-        // Modify
-        std::fs::write(&file_path, "version: 2").unwrap();
-        assert!(wait_for_index(dir.path(), "ops.yaml", 2, 2000));
+        assert!(
+            wait_for_index(dir.path(), "notempty.txt", 1, 2000),
+            "notempty.txt should be recorded"
+        );
 
         let index = load_test_index(dir.path());
-        let entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "ops.yaml")
-            .collect();
-        assert_eq!(entries.len(), 2, "Should have 2 entries");
-        assert_eq!(entries[0].op, "create", "First op should be create");
-        assert_eq!(entries[1].op, "modify", "Second op should be modify");
+        assert!(
+            !index.history.iter().any(|e| e.file == "empty.txt"),
+            "Empty files should not be tracked"
+        );
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_history_delete_recorded() {
+    fn test_unchanged_mtime_and_size_skips_hashing() {
         let dir = setup_test_dir();
         let (mut server, _port) = start_server_and_checkout(dir.path());
-        let file_path = dir.path().join("todelete.yaml");
 
-        // Create
-        std::fs::write(&file_path, "will be deleted").unwrap();
-        assert!(wait_for_index(dir.path(), "todelete.yaml", 1, 2000));
+        let path = dir.path().join("big.txt");
+        std::fs::write(&path, "aaaa").unwrap();
+        assert!(wait_for_index(dir.path(), "big.txt", 1, 2000));
+        let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
 
-        // Delete
-        std::fs::remove_file(&file_path).unwrap();
+        // Same size and mtime: taken as unchanged without reading the content
+        std::fs::write(&path, "bbbb").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1000));
+        assert_eq!(load_test_index(dir.path()).history.len(), 1);
+
+        // A real change (new mtime) is still recorded
+        std::fs::write(&path, "cccc").unwrap();
+        assert!(wait_for_index(dir.path(), "big.txt", 2, 2000));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_bulk_mode_records_final_state_once() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let health = || -> serde_json::Value {
+            reqwest::blocking::Client::builder()
+                .no_proxy()
+                .build()
+                .unwrap()
+                .get(format!("http://127.0.0.1:{}/api/health", port))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap()
+        };
+
+        let out = run_ftm_with_port(port, &["bulk-begin"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).starts_with("Bulk mode on until "));
+        assert!(health()["bulk_until"].is_string());
+        for v in ["v1", "v2", "v3"] {
+            std::fs::write(dir.path().join("a.txt"), v).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(800));
+        }
         assert!(
-            wait_for_index(dir.path(), "todelete.yaml", 2, 2000),
-            "Delete event should be recorded"
+            !wait_for_index(dir.path(), "a.txt", 1, 500),
+            "nothing is snapshotted during bulk mode"
         );
 
+        let out = run_ftm_with_port(port, &["bulk-end"]);
+        assert!(String::from_utf8_lossy(&out.stdout).starts_with("Bulk mode ended"));
+        assert!(health().get("bulk_until").is_none());
+        assert!(wait_for_index(dir.path(), "a.txt", 1, 5000));
+        std::thread::sleep(std::time::Duration::from_millis(500));
         let index = load_test_index(dir.path());
-        let entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "todelete.yaml")
-            .collect();
-        assert_eq!(entries.len(), 2, "Should have 2 entries (create + delete)");
-        assert_eq!(entries[0].op, "create");
-        assert_eq!(entries[1].op, "delete");
-        assert!(
-            entries[1].checksum.is_none(),
-            "Delete entry should have no checksum"
-        );
-        assert!(
-            entries[1].size.is_none(),
-            "Delete entry should have no size"
+        assert_eq!(index.history.len(), 1);
+        let v3 = blake3::hash(b"v3").to_hex().to_string();
+        assert_eq!(index.history[0].checksum.as_deref(), Some(v3.as_str()));
+        assert_eq!(index.history[0].source.as_deref(), Some("watcher"));
+        let out = run_ftm_with_port(port, &["bulk-end"]);
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout).trim(),
+            "Bulk mode was not on"
         );
 
+        // Without bulk-end, bulk mode runs out and the watcher catches up by itself
+        assert!(run_ftm_with_port(port, &["bulk-begin", "--timeout", "2s"])
+            .status
+            .success());
+        std::fs::write(dir.path().join("b.txt"), "b").unwrap();
+        assert!(!wait_for_index(dir.path(), "b.txt", 1, 1000));
+        assert!(wait_for_index(dir.path(), "b.txt", 1, 8000));
+
         stop_server(&mut server);
     }
 
-    /// Default `ftm ls` excludes deleted files; `ftm ls --include-deleted` shows them.
     #[test]
-    fn test_ls_hides_deleted_by_default() {
+    fn test_pause_holds_changes_until_resume() {
         let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
-        let file_path = dir.path().join("ls_hide_deleted.yaml");
+        let health = || -> serde_json::Value {
+            reqwest::blocking::Client::builder()
+                .no_proxy()
+                .build()
+                .unwrap()
+                .get(format!("http://127.0.0.1:{}/api/health", port))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap()
+        };
 
-        std::fs::write(&file_path, "content").unwrap();
+        let out = run_ftm_with_port(port, &["pause"]);
+        assert!(String::from_utf8_lossy(&out.stdout).starts_with("Recording paused"));
+        assert_eq!(health()["paused"], true);
+        let out = run_ftm_with_port(port, &["pause"]);
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout).trim(),
+            "Recording was already paused"
+        );
+        for v in ["v1", "v2"] {
+            std::fs::write(dir.path().join("a.txt"), v).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(800));
+        }
         assert!(
-            wait_for_index(dir.path(), "ls_hide_deleted.yaml", 1, 2000),
-            "Create should be recorded"
+            !wait_for_index(dir.path(), "a.txt", 1, 500),
+            "nothing is snapshotted while paused"
         );
 
-        let ls_default = run_ftm_with_port(port, &["ls"]);
-        assert!(ls_default.status.success(), "ftm ls should succeed");
-        let ls_stdout = String::from_utf8_lossy(&ls_default.stdout);
-        assert!(
-            ls_stdout.contains("ls_hide_deleted.yaml"),
-            "ls (default) should show file before delete; got:\n{}",
-            ls_stdout
+        let out = run_ftm_with_port(port, &["resume"]);
+        assert!(String::from_utf8_lossy(&out.stdout).starts_with("Recording resumed"));
+        assert!(health().get("paused").is_none());
+        assert!(wait_for_index(dir.path(), "a.txt", 1, 5000));
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let index = load_test_index(dir.path());
+        assert_eq!(index.history.len(), 1);
+        let v2 = blake3::hash(b"v2").to_hex().to_string();
+        assert_eq!(index.history[0].checksum.as_deref(), Some(v2.as_str()));
+        let out = run_ftm_with_port(port, &["resume"]);
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout).trim(),
+            "Recording was not paused"
         );
 
-        std::fs::remove_file(&file_path).unwrap();
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_git_aware_records_branch_switch_once() {
+        let dir = setup_test_dir();
+        let git_dir = dir.path().join(".git");
+        std::fs::create_dir(&git_dir).unwrap();
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(dir.path().join("a.txt"), "main").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
         assert!(
-            wait_for_index(dir.path(), "ls_hide_deleted.yaml", 2, 2000),
-            "Delete event should be recorded"
+            run_ftm_with_port(port, &["config", "set", "settings.git_aware", "true"])
+                .status
+                .success()
         );
 
-        let ls_after_delete = run_ftm_with_port(port, &["ls"]);
-        assert!(ls_after_delete.status.success(), "ftm ls should succeed");
-        let ls_stdout = String::from_utf8_lossy(&ls_after_delete.stdout);
+        // git holds the index lock while it rewrites the work tree
+        std::fs::write(git_dir.join("index.lock"), "").unwrap();
+        std::fs::write(dir.path().join("a.txt"), "halfway").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(800));
+        std::fs::write(dir.path().join("a.txt"), "feature").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "feature only").unwrap();
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/feature\n").unwrap();
         assert!(
-            !ls_stdout.contains("ls_hide_deleted.yaml"),
-            "ls (default) should hide deleted file; got:\n{}",
-            ls_stdout
+            !wait_for_index(dir.path(), "a.txt", 2, 1500),
+            "nothing is snapshotted while git holds the lock"
         );
+        std::fs::remove_file(git_dir.join("index.lock")).unwrap();
 
-        let ls_include_deleted = run_ftm_with_port(port, &["ls", "--include-deleted"]);
-        assert!(
-            ls_include_deleted.status.success(),
-            "ftm ls --include-deleted should succeed"
+        assert!(wait_for_index(dir.path(), "a.txt", 2, 6000));
+        assert!(wait_for_index(dir.path(), "b.txt", 1, 6000));
+        let index = load_test_index(dir.path());
+        let mut switched: Vec<_> = index.history[1..]
+            .iter()
+            .map(|e| (e.file.as_str(), e.source.as_deref()))
+            .collect();
+        switched.sort();
+        assert_eq!(
+            switched,
+            [("a.txt", Some("git")), ("b.txt", Some("git"))],
+            "one scan records the final state, tagged git"
         );
-        let ls_stdout = String::from_utf8_lossy(&ls_include_deleted.stdout);
-        assert!(
-            ls_stdout.contains("ls_hide_deleted.yaml"),
-            "ls --include-deleted should show deleted file; got:\n{}",
-            ls_stdout
+        let feature = blake3::hash(b"feature").to_hex().to_string();
+        let a = index.history.iter().rfind(|e| e.file == "a.txt").unwrap();
+        assert_eq!(a.checksum.as_deref(), Some(feature.as_str()));
+        let out = run_ftm_with_port(port, &["history", "a.txt"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("| git"));
+
+        // Edits on the branch are the watcher's again
+        std::fs::write(dir.path().join("a.txt"), "feature work").unwrap();
+        assert!(wait_for_index(dir.path(), "a.txt", 3, 5000));
+        let index = load_test_index(dir.path());
+        assert_eq!(
+            index.history.last().unwrap().source.as_deref(),
+            Some("watcher")
         );
 
         stop_server(&mut server);
     }
+}
+
+mod rename_tests {
+    use super::*;
 
+    /// Simulate file-manager "delete" (e.g. Finder, Nautilus, Explorer):
+    /// move (rename) a tracked file out of the watched directory.
+    /// The watcher should detect this as a delete.
     #[test]
-    fn test_history_recreate_after_delete() {
+    fn test_file_moved_out_detected_as_delete() {
         let dir = setup_test_dir();
+        // Create a directory outside the watched tree to move files into
+        // (simulates Trash / Recycle Bin or any external location).
+        let outside = tempfile::tempdir().unwrap();
+
         let (mut server, _port) = start_server_and_checkout(dir.path());
-        let file_path = dir.path().join("recreate.yaml");
+        let file_path = dir.path().join("finder_del.txt");
 
-        // Create
-        std::fs::write(&file_path, "original content").unwrap();
-        assert!(wait_for_index(dir.path(), "recreate.yaml", 1, 2000));
+        // Create the file so watcher records it
+        std::fs::write(&file_path, "will be moved to trash").unwrap();
+        assert!(
+            wait_for_index(dir.path(), "finder_del.txt", 1, 2000),
+            "Initial create should be recorded"
+        );
 
-        // Delete
-        std::fs::remove_file(&file_path).unwrap();
-        assert!(wait_for_index(dir.path(), "recreate.yaml", 2, 2000));
+        // Move the file out of the watched directory (mimics move-to-trash)
+        let dest = outside.path().join("finder_del.txt");
+        std::fs::rename(&file_path, &dest).unwrap();
 
-        // Recreate with new content
-        std::fs::write(&file_path, "recreated content").unwrap();
-        assert!(wait_for_index(dir.path(), "recreate.yaml", 3, 2000));
+        assert!(
+            wait_for_index(dir.path(), "finder_del.txt", 2, 4000),
+            "Move-out (rename) should be recorded as delete"
+        );
 
         let index = load_test_index(dir.path());
         let entries: Vec<_> = index
             .history
             .iter()
-            .filter(|e| e.file == "recreate.yaml")
+            .filter(|e| e.file == "finder_del.txt")
             .collect();
-        assert_eq!(entries.len(), 3, "Should have 3 entries");
-        assert_eq!(entries[0].op, "create", "First should be create");
-        assert_eq!(entries[1].op, "delete", "Second should be delete");
-        assert_eq!(
-            entries[2].op, "create",
-            "Third should be create (after delete)"
+        assert_eq!(entries.len(), 2, "Should have 2 entries (create + delete)");
+        assert_eq!(entries[0].op, "create");
+        assert_eq!(entries[1].op, "delete");
+        assert!(
+            entries[1].checksum.is_none(),
+            "Delete should have no checksum"
         );
 
         stop_server(&mut server);
     }
 
+    /// Move a file from outside into the watched directory.
+    /// The watcher should detect this as a new file (create/snapshot).
     #[test]
-    fn test_history_multiple_files_independent() {
+    fn test_file_moved_in_detected_as_create() {
         let dir = setup_test_dir();
+        let outside = tempfile::tempdir().unwrap();
+
         let (mut server, _port) = start_server_and_checkout(dir.path());
 
-        std::fs::write(dir.path().join("alpha.yaml"), "a: 1").unwrap();
-        std::fs::write(dir.path().join("beta.yaml"), "b: 1").unwrap();
-        assert!(wait_for_index(dir.path(), "alpha.yaml", 1, 2000));
-        assert!(wait_for_index(dir.path(), "beta.yaml", 1, 2000));
+        // Create a file outside the watched directory
+        let external_file = outside.path().join("incoming.txt");
+        std::fs::write(&external_file, "moved in from outside").unwrap();
 
-        // Modify only alpha
-        std::fs::write(dir.path().join("alpha.yaml"), "a: 2").unwrap();
-        assert!(wait_for_index(dir.path(), "alpha.yaml", 2, 2000));
+        // Move it into the watched directory
+        let dest = dir.path().join("incoming.txt");
+        std::fs::rename(&external_file, &dest).unwrap();
+
+        assert!(
+            wait_for_index(dir.path(), "incoming.txt", 1, 4000),
+            "Move-in (rename) should be recorded as create"
+        );
 
         let index = load_test_index(dir.path());
-        let alpha_count = index
-            .history
-            .iter()
-            .filter(|e| e.file == "alpha.yaml")
-            .count();
-        let beta_count = index
+        let entry = index
             .history
             .iter()
-            .filter(|e| e.file == "beta.yaml")
-            .count();
-        assert_eq!(
-            alpha_count, 2,
-            "alpha should have 2 entries (create + modify)"
-        );
-        assert_eq!(
-            beta_count, 1,
-            "beta should still have 1 entry (create only)"
+            .find(|e| e.file == "incoming.txt")
+            .expect("incoming.txt should have a history entry");
+        assert_eq!(entry.op, "create");
+        assert!(
+            entry.checksum.is_some(),
+            "Create entry should have a checksum"
         );
 
         stop_server(&mut server);
     }
-}
-
-mod restore_tests {
-    use super::*;
 
+    /// Rename a file within the watched directory.  The watcher should record
+    /// a delete for the old name and a create for the new name.
     #[test]
-    fn test_restore_not_checked_out() {
-        let (mut server, port) = start_server();
-
-        let out = run_ftm_with_port(port, &["restore", "test.rs", "abc12345"]);
-        assert!(!out.status.success());
-        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
-
-        stop_server(&mut server);
-    }
+    fn test_rename_within_watched_dir() {
+        let dir = setup_test_dir();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+
+        let old_path = dir.path().join("before.txt");
+        std::fs::write(&old_path, "rename me").unwrap();
+        assert!(
+            wait_for_index(dir.path(), "before.txt", 1, 2000),
+            "Initial create should be recorded"
+        );
+
+        // Rename within the watched directory
+        let new_path = dir.path().join("after.txt");
+        std::fs::rename(&old_path, &new_path).unwrap();
+
+        assert!(
+            wait_for_index(dir.path(), "before.txt", 2, 4000),
+            "Old name should get a delete entry"
+        );
+        assert!(
+            wait_for_index(dir.path(), "after.txt", 1, 4000),
+            "New name should get a create entry"
+        );
+
+        let index = load_test_index(dir.path());
+
+        let old_entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "before.txt")
+            .collect();
+        assert_eq!(
+            old_entries.len(),
+            2,
+            "Old name should have 2 entries (create + delete)"
+        );
+        assert_eq!(old_entries[0].op, "create");
+        assert_eq!(old_entries[1].op, "delete");
+
+        let new_entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "after.txt")
+            .collect();
+        assert_eq!(
+            new_entries.len(),
+            1,
+            "New name should have 1 entry (create)"
+        );
+        assert_eq!(new_entries[0].op, "create");
+
+        stop_server(&mut server);
+    }
+
+    /// Rename a folder within the watched directory. Old path files should get delete
+    /// entries; new path files should get create entries.
+    #[test]
+    fn test_rename_folder_within_watched_dir() {
+        let dir = setup_test_dir();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+
+        let old_dir = dir.path().join("old_name");
+        std::fs::create_dir_all(&old_dir).unwrap();
+        std::fs::write(old_dir.join("a.txt"), "content a").unwrap();
+        std::fs::write(old_dir.join("b.rs"), "content b").unwrap();
+
+        assert!(
+            wait_for_index(dir.path(), "old_name/a.txt", 1, 3000),
+            "old_name/a.txt should be recorded"
+        );
+        assert!(
+            wait_for_index(dir.path(), "old_name/b.rs", 1, 3000),
+            "old_name/b.rs should be recorded"
+        );
+
+        let new_dir = dir.path().join("new_name");
+        std::fs::rename(&old_dir, &new_dir).unwrap();
+
+        assert!(
+            wait_for_index(dir.path(), "old_name/a.txt", 2, 5000),
+            "old_name/a.txt should have create + delete"
+        );
+        assert!(
+            wait_for_index(dir.path(), "old_name/b.rs", 2, 5000),
+            "old_name/b.rs should have create + delete"
+        );
+        assert!(
+            wait_for_index(dir.path(), "new_name/a.txt", 1, 5000),
+            "new_name/a.txt should be recorded after folder rename"
+        );
+        assert!(
+            wait_for_index(dir.path(), "new_name/b.rs", 1, 5000),
+            "new_name/b.rs should be recorded after folder rename"
+        );
+
+        let index = load_test_index(dir.path());
+        for file in &["old_name/a.txt", "old_name/b.rs"] {
+            let entries: Vec<_> = index.history.iter().filter(|e| e.file == *file).collect();
+            assert_eq!(
+                entries.len(),
+                2,
+                "{} should have 2 entries (create + delete)",
+                file
+            );
+            assert_eq!(entries[0].op, "create");
+            assert_eq!(entries[1].op, "delete");
+        }
+        for file in &["new_name/a.txt", "new_name/b.rs"] {
+            let entries: Vec<_> = index.history.iter().filter(|e| e.file == *file).collect();
+            assert_eq!(entries.len(), 1, "{} should have 1 create entry", file);
+            assert_eq!(entries[0].op, "create");
+        }
+
+        stop_server(&mut server);
+    }
+
+    /// Move a folder (with tracked files) out of the watched directory.
+    /// Index should record delete for all files under that path.
+    #[test]
+    fn test_rename_folder_move_out() {
+        let dir = setup_test_dir();
+        let outside = tempfile::tempdir().unwrap();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+
+        let subdir = dir.path().join("subdir");
+        std::fs::create_dir_all(&subdir).unwrap();
+        std::fs::write(subdir.join("f.txt"), "moved out").unwrap();
+
+        assert!(
+            wait_for_index(dir.path(), "subdir/f.txt", 1, 3000),
+            "subdir/f.txt should be recorded"
+        );
+
+        let dest = outside.path().join("subdir");
+        std::fs::rename(&subdir, &dest).unwrap();
+
+        assert!(
+            wait_for_index(dir.path(), "subdir/f.txt", 2, 5000),
+            "subdir/f.txt should have create + delete after folder move-out"
+        );
+
+        let index = load_test_index(dir.path());
+        let entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "subdir/f.txt")
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].op, "create");
+        assert_eq!(entries[1].op, "delete");
+
+        stop_server(&mut server);
+    }
+
+    /// Move a folder from outside into the watched directory.
+    /// Index should record create for all matching files under the new path.
+    #[test]
+    fn test_rename_folder_move_in() {
+        let dir = setup_test_dir();
+        let outside = tempfile::tempdir().unwrap();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+
+        let external_dir = outside.path().join("incoming");
+        std::fs::create_dir_all(&external_dir).unwrap();
+        std::fs::write(external_dir.join("x.yaml"), "moved in").unwrap();
+
+        let dest = dir.path().join("incoming");
+        std::fs::rename(&external_dir, &dest).unwrap();
+
+        assert!(
+            wait_for_index(dir.path(), "incoming/x.yaml", 1, 5000),
+            "incoming/x.yaml should be recorded after folder move-in"
+        );
+
+        let index = load_test_index(dir.path());
+        let entry = index
+            .history
+            .iter()
+            .find(|e| e.file == "incoming/x.yaml")
+            .expect("incoming/x.yaml should have a history entry");
+        assert_eq!(entry.op, "create");
+        assert!(entry.checksum.is_some());
+
+        stop_server(&mut server);
+    }
+}
+
+mod dedup_tests {
+    use super::*;
+
+    /// Age a snapshot file past the grace period shared stores give young orphans.
+    fn backdate(path: &Path) {
+        let mut perms = std::fs::metadata(path).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        std::fs::set_permissions(path, perms).unwrap();
+        let past = std::time::SystemTime::now() - std::time::Duration::from_secs(2 * 3600);
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_shard_depth_and_migrate_store() {
+        let dir = setup_test_dir();
+        let snapshots = dir.path().join(".ftm").join("snapshots");
+        let at_depth = |checksum: &str, depth: usize| {
+            let mut path = snapshots.clone();
+            for i in 0..depth {
+                path.push(&checksum[i..i + 1]);
+            }
+            path.join(checksum)
+        };
+        let alpha = blake3::hash(b"alpha").to_hex().to_string();
+        let beta = blake3::hash(b"beta").to_hex().to_string();
+        std::fs::write(dir.path().join("a.txt"), "alpha").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(
+            at_depth(&alpha, 2).is_file(),
+            "Default layout is two levels"
+        );
+
+        let out = run_ftm_with_port(port, &["config", "set", "settings.shard_depth", "5"]);
+        assert!(!out.status.success(), "Depth is capped at 4");
+        let out = run_ftm_with_port(port, &["config", "set", "settings.shard_depth", "1"]);
+        assert!(out.status.success());
+        std::fs::write(dir.path().join("b.txt"), "beta").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(
+            at_depth(&beta, 1).is_file(),
+            "New snapshots use the new depth"
+        );
+        assert!(
+            at_depth(&alpha, 2).is_file(),
+            "Old snapshots stay until migrated"
+        );
+
+        let out = run_ftm_with_port(port, &["migrate-store"]);
+        assert!(out.status.success());
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(stdout.contains("Moved 1 snapshot(s)"), "{}", stdout);
+        assert!(at_depth(&alpha, 1).is_file());
+        assert!(
+            !snapshots.join(&alpha[..1]).join(&alpha[1..2]).exists(),
+            "Emptied shard directories are removed"
+        );
+        assert_eq!(count_snapshot_files(dir.path()), 2);
+
+        let out = run_ftm_with_port(port, &["migrate-store"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("nothing to move"));
+
+        std::fs::write(dir.path().join("a.txt"), "changed").unwrap();
+        let out = run_ftm_with_port(port, &["restore", "a.txt", &alpha[..8]]);
+        assert!(out.status.success());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "alpha"
+        );
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_shared_snapshot_store_across_projects() {
+        let store = setup_test_dir();
+        let store_arg = store.path().to_str().unwrap();
+        let shard = |checksum: &str| {
+            store
+                .path()
+                .join("snapshots")
+                .join(&checksum[..1])
+                .join(&checksum[1..2])
+                .join(checksum)
+        };
+        let checksum = |content: &str| blake3::hash(content.as_bytes()).to_hex().to_string();
+        let dir_a = setup_test_dir();
+        let dir_b = setup_test_dir();
+        std::fs::write(dir_a.path().join("same.txt"), "shared content").unwrap();
+        std::fs::write(dir_a.path().join("a_only.txt"), "only in a").unwrap();
+        std::fs::write(dir_b.path().join("same.txt"), "shared content").unwrap();
+        std::fs::write(dir_b.path().join("b_only.txt"), "only in b").unwrap();
+
+        // A's existing snapshots move into the store and its private store goes away
+        let (mut server_a, port_a) = start_server_and_checkout(dir_a.path());
+        assert!(run_ftm_with_port(port_a, &["scan"]).status.success());
+        assert_eq!(count_snapshot_files(dir_a.path()), 2);
+        let set_store = |port: u16, value: &str| {
+            run_ftm_with_port(port, &["config", "set", "settings.snapshot_store", value])
+        };
+        assert!(!set_store(port_a, "relative/store").status.success());
+        assert!(set_store(port_a, store_arg).status.success());
+        assert!(!dir_a.path().join(".ftm/snapshots").exists());
+        assert_eq!(count_files_recursive(&store.path().join("snapshots")), 2);
+
+        // Identical content from B is stored once
+        // Checked out over the API: `ftm checkout` would take over A's server
+        let (mut server_b, port_b) = start_server();
+        checkout_over_api(port_b, dir_b.path());
+        assert!(set_store(port_b, store_arg).status.success());
+        assert!(run_ftm_with_port(port_b, &["scan"]).status.success());
+        assert_eq!(count_snapshot_files(dir_b.path()), 0);
+        assert_eq!(count_files_recursive(&store.path().join("snapshots")), 3);
+
+        // A's clean keeps what B references and drops what nobody does
+        let orphan = "referenced by nobody";
+        let orphan_path = shard(&checksum(orphan));
+        std::fs::create_dir_all(orphan_path.parent().unwrap()).unwrap();
+        std::fs::write(&orphan_path, orphan).unwrap();
+        backdate(&orphan_path);
+        backdate(&shard(&checksum("only in b")));
+        let out = run_ftm_with_port(port_a, &["clean"]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert!(!orphan_path.exists(), "unreferenced snapshot is cleaned");
+        assert!(
+            shard(&checksum("only in b")).exists(),
+            "B's snapshot is kept"
+        );
+
+        std::fs::write(dir_a.path().join("a_only.txt"), "changed").unwrap();
+        let out = run_ftm_with_port(
+            port_a,
+            &["restore", "a_only.txt", &checksum("only in a")[..8]],
+        );
+        assert!(out.status.success());
+        assert_eq!(
+            std::fs::read_to_string(dir_a.path().join("a_only.txt")).unwrap(),
+            "only in a"
+        );
+
+        stop_server(&mut server_a);
+        stop_server(&mut server_b);
+    }
+
+    #[test]
+    fn test_same_content_no_duplicate_entry() {
+        let dir = setup_test_dir();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+
+        let content = "key: same_content";
+
+        // First write
+        std::fs::write(dir.path().join("dup.yaml"), content).unwrap();
+        assert!(
+            wait_for_index(dir.path(), "dup.yaml", 1, 2000),
+            "First write should be recorded"
+        );
+
+        // Second write with identical content
+        std::fs::write(dir.path().join("dup.yaml"), content).unwrap();
+
+        // Write a sync marker
+        std::fs::write(dir.path().join("sync.yaml"), "sync: marker").unwrap();
+        assert!(
+            wait_for_index(dir.path(), "sync.yaml", 1, 2000),
+            "Sync marker should be recorded"
+        );
+
+        let index = load_test_index(dir.path());
+        let count = index
+            .history
+            .iter()
+            .filter(|e| e.file == "dup.yaml")
+            .count();
+        assert_eq!(
+            count, 1,
+            "Same content written twice should produce only 1 entry, got {}",
+            count
+        );
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_different_files_same_content_share_snapshot() {
+        let dir = setup_test_dir();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+
+        let content = "shared: content_value_12345";
+        std::fs::write(dir.path().join("file_a.yaml"), content).unwrap();
+        assert!(
+            wait_for_index(dir.path(), "file_a.yaml", 1, 2000),
+            "file_a.yaml should be recorded"
+        );
+
+        std::fs::write(dir.path().join("file_b.yaml"), content).unwrap();
+        assert!(
+            wait_for_index(dir.path(), "file_b.yaml", 1, 2000),
+            "file_b.yaml should be recorded"
+        );
+
+        let index = load_test_index(dir.path());
+        assert!(index.history.iter().any(|e| e.file == "file_a.yaml"));
+        assert!(index.history.iter().any(|e| e.file == "file_b.yaml"));
+
+        // Only 1 snapshot file (content-addressable dedup)
+        let snap_count = count_snapshot_files(dir.path());
+        assert_eq!(
+            snap_count, 1,
+            "Two files with same content should share 1 snapshot, got {}",
+            snap_count
+        );
+
+        // Both entries should have the same checksum
+        let checksum_a = index
+            .history
+            .iter()
+            .find(|e| e.file == "file_a.yaml")
+            .and_then(|e| e.checksum.as_ref());
+        let checksum_b = index
+            .history
+            .iter()
+            .find(|e| e.file == "file_b.yaml")
+            .and_then(|e| e.checksum.as_ref());
+        assert_eq!(
+            checksum_a, checksum_b,
+            "Both files should have the same checksum"
+        );
+
+        stop_server(&mut server);
+    }
+}
+
+mod history_tests {
+    use super::*;
+
+    #[test]
+    fn test_history_not_checked_out() {
+        let (mut server, port) = start_server();
+
+        let out = run_ftm_with_port(port, &["history", "test.rs"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_history_no_entries() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["history", "nonexistent.rs"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("No history for"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_history_shows_size_deltas() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("grow.txt");
+        for content in ["12345", "12345\n7890", "123"] {
+            std::fs::write(&file_path, content).unwrap();
+            assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        }
+        std::fs::remove_file(&file_path).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let entries: Vec<serde_json::Value> = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(format!("http://127.0.0.1:{}/api/history", port))
+            .query(&[("file", "grow.txt")])
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        let deltas: Vec<i64> = entries
+            .iter()
+            .map(|e| e["size_delta"].as_i64().unwrap())
+            .collect();
+        assert_eq!(deltas, [5, 5, -7, -3]);
+        let lines: Vec<i64> = entries
+            .iter()
+            .map(|e| e["lines_delta"].as_i64().unwrap())
+            .collect();
+        assert_eq!(lines, [1, 1, -1, -1]);
+
+        let out = run_ftm_with_port(port, &["history", "grow.txt"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(
+            stdout.contains("| 10 bytes (+5), 2 lines (+1)"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(stdout.contains("| 3 bytes (-7)"), "stdout: {}", stdout);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_line_counts_recorded_for_text_only() {
+        let dir = setup_test_dir();
+        // A two-byte character split across the 64 KiB read chunks
+        let text = format!("{}é\nlast", "a".repeat(65535));
+        std::fs::write(dir.path().join("big.txt"), &text).unwrap();
+        std::fs::write(dir.path().join("blob.txt"), [0x66, 0xff, 0x0a, 0x67]).unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let index: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(dir.path().join(".ftm/index.json")).unwrap(),
+        )
+        .unwrap();
+        let lines_of = |file: &str| {
+            index["history"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|e| e["file"] == file)
+                .unwrap()["lines"]
+                .as_u64()
+        };
+        assert_eq!(lines_of("big.txt"), Some(2));
+        assert_eq!(lines_of("blob.txt"), None, "not UTF-8, so no line count");
+
+        stop_server(&mut server);
+    }
+}
+
+mod history_ops_tests {
+    use super::*;
+
+    #[test]
+    fn test_share_link_serves_one_version_until_expiry() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        std::fs::write(dir.path().join("a.txt"), "v1").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(dir.path().join("a.txt"), "v2").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let v1 = load_test_index(dir.path()).history[0]
+            .checksum
+            .clone()
+            .unwrap();
+
+        let share = |expires: &str| {
+            let out = run_ftm_with_port(port, &["share", "a.txt", &v1[..8], "--expires", expires]);
+            assert!(out.status.success(), "{:?}", out);
+            let stdout = String::from_utf8_lossy(&out.stdout).into_owned();
+            assert!(stdout.contains("Expires "), "stdout: {}", stdout);
+            stdout.lines().next().unwrap().to_string()
+        };
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let get = |url: &str| {
+            let resp = client.get(url).send().unwrap();
+            (resp.status().as_u16(), resp.text().unwrap())
+        };
+
+        let url = share("24h");
+        assert!(
+            url.starts_with(&format!("http://127.0.0.1:{}/share/", port)),
+            "{}",
+            url
+        );
+        assert_eq!(get(&url), (200, "v1".to_string()));
+
+        // The expiry is covered by the signature
+        let (head, signature) = url.rsplit_once('.').unwrap();
+        let (head, expires) = head.rsplit_once('.').unwrap();
+        let forged = format!(
+            "{}.{}.{}",
+            head,
+            expires.parse::<i64>().unwrap() + 1,
+            signature
+        );
+        assert_eq!(get(&forged).0, 403);
+
+        let short = share("1s");
+        std::thread::sleep(std::time::Duration::from_millis(2100));
+        let (status, body) = get(&short);
+        assert_eq!(status, 403);
+        assert!(body.contains("expired"), "{}", body);
+
+        assert!(
+            !run_ftm_with_port(port, &["share", "a.txt", &v1[..8], "--expires", "soon"])
+                .status
+                .success()
+        );
+        assert!(!run_ftm_with_port(port, &["share", "a.txt", "ffffffff"])
+            .status
+            .success());
+        // Too short to be sure which version a long-lived link exposes
+        let out = run_ftm_with_port(port, &["share", "a.txt", &v1[..4]]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("at least 8 characters"));
+
+        // Deleting the key revokes every link
+        std::fs::remove_file(dir.path().join(".ftm/share.key")).unwrap();
+        assert_eq!(get(&url).0, 403);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_delete_to_os_trash_and_restore_from_trash() {
+        let dir = setup_test_dir();
+        let data_home = setup_test_dir();
+        let (mut server, port) = start_server_with_env(&[], &[("XDG_DATA_HOME", data_home.path())]);
+        checkout_over_api(port, dir.path());
+        let canonical = dir.path().canonicalize().unwrap();
+
+        std::fs::write(dir.path().join("draft notes.txt"), "keep me").unwrap();
+        std::fs::write(dir.path().join("gone.txt"), "bye").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        // What a desktop does when moving a file to the trash
+        let trash = data_home.path().join("Trash");
+        std::fs::create_dir_all(trash.join("files")).unwrap();
+        std::fs::create_dir_all(trash.join("info")).unwrap();
+        std::fs::rename(
+            dir.path().join("draft notes.txt"),
+            trash.join("files/draft notes.txt"),
+        )
+        .unwrap();
+        let encoded = format!("{}/draft%20notes.txt", canonical.display());
+        std::fs::write(
+            trash.join("info/draft notes.txt.trashinfo"),
+            format!(
+                "[Trash Info]\nPath={}\nDeletionDate=2026-01-01T00:00:00\n",
+                encoded
+            ),
+        )
+        .unwrap();
+        std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let index = load_test_index(dir.path());
+        let last = |file: &str| index.history.iter().rev().find(|e| e.file == file).unwrap();
+        assert_eq!(last("draft notes.txt").op, "delete");
+        assert!(last("draft notes.txt").trashed);
+        assert!(!last("gone.txt").trashed);
+        let out = run_ftm_with_port(port, &["history", "draft notes.txt"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("| moved to trash"));
+
+        let out = run_ftm_with_port(port, &["restore", "--from-trash", "draft notes.txt"]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("draft notes.txt")).unwrap(),
+            "keep me"
+        );
+        // Only deleted files are restored this way
+        let out = run_ftm_with_port(port, &["restore", "--from-trash", "draft notes.txt"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("is not deleted"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_entries_record_their_source() {
+        let dir = setup_test_dir();
+        std::fs::write(dir.path().join("a.txt"), "v1").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        std::fs::write(dir.path().join("a.txt"), "v2").unwrap();
+        assert!(wait_for_index(dir.path(), "a.txt", 2, 5000));
+
+        let v1 = load_test_index(dir.path()).history[0]
+            .checksum
+            .clone()
+            .unwrap();
+        assert!(run_ftm_with_port(port, &["restore", "a.txt", &v1])
+            .status
+            .success());
+
+        let index = load_test_index(dir.path());
+        let sources: Vec<Option<&str>> =
+            index.history.iter().map(|e| e.source.as_deref()).collect();
+        assert_eq!(
+            sources,
+            [Some("manual"), Some("watcher"), Some("restore")],
+            "restored content is recorded by the restore, not the watcher"
+        );
+        assert_eq!(
+            index.history[2].restored_from.as_deref(),
+            Some(index.history[0].timestamp.as_str()),
+            "the restore entry points at the version it brought back"
+        );
+        assert!(index.history[..2].iter().all(|e| e.restored_from.is_none()));
+        let out = run_ftm_with_port(port, &["history", "a.txt"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let restore_line = stdout.lines().nth(1).unwrap();
+        assert!(
+            restore_line.contains(&format!("| restore from version {} of ", &v1[..8])),
+            "{}",
+            stdout
+        );
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_rm_records_user_initiated_delete() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("notes.txt");
+
+        std::fs::write(&file_path, "v1").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        // Not yet recorded when removed: rm snapshots it first
+        std::fs::write(&file_path, "v2").unwrap();
+        let out = run_ftm_with_port(port, &["rm", "notes.txt"]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert!(!file_path.exists());
+
+        // The watcher notices the removal but has nothing left to record
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        let index = load_test_index(dir.path());
+        let ops: Vec<&str> = index.history.iter().map(|e| e.op.as_str()).collect();
+        assert_eq!(ops, ["create", "modify", "delete"]);
+        assert!(index.history[2].user_initiated);
+        assert!(!index.history[0].user_initiated);
+
+        let out = run_ftm_with_port(port, &["history", "notes.txt"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let delete_line = stdout.lines().find(|l| l.contains("| delete |")).unwrap();
+        assert!(delete_line.ends_with("| via ftm rm"), "{}", delete_line);
+
+        let out = run_ftm_with_port(port, &["rm", "notes.txt"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("not a tracked file"));
+
+        let v2 = index.history[1].checksum.clone().unwrap();
+        assert!(run_ftm_with_port(port, &["restore", "notes.txt", &v2[..8]])
+            .status
+            .success());
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "v2");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_truncate_to_empty_is_recorded() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("log.txt");
+
+        std::fs::write(&file_path, "content").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(&file_path, "").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let index = load_test_index(dir.path());
+        let ops: Vec<&str> = index.history.iter().map(|e| e.op.as_str()).collect();
+        assert_eq!(ops, ["create", "truncate"]);
+        let truncate = &index.history[1];
+        assert_eq!(truncate.size, Some(0));
+        assert!(truncate.checksum.is_none());
+
+        let out = run_ftm_with_port(port, &["history", "log.txt"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("| truncate | - | 0 bytes"));
+
+        let first = index.history[0].checksum.clone().unwrap();
+        let out = run_ftm_with_port(port, &["restore", "log.txt", &first[..8]]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success());
+        assert!(
+            stdout.contains("truncated to 0 bytes"),
+            "stdout: {}",
+            stdout
+        );
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "content");
+
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let index = load_test_index(dir.path());
+        assert_eq!(index.history.last().unwrap().op, "modify");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_history_create_then_modify_ops() {
+        let dir = setup_test_dir();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("ops.yaml");
+
+        // Create
+        std::fs::write(&file_path, "version: 1").unwrap();
+        assert!(wait_for_index(dir.path(), "ops.yaml", 1, 2000));
+
+        // Modify
+        std::fs::write(&file_path, "version: 2").unwrap();
+        assert!(wait_for_index(dir.path(), "ops.yaml", 2, 2000));
+
+        let index = load_test_index(dir.path());
+        let entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "ops.yaml")
+            .collect();
+        assert_eq!(entries.len(), 2, "Should have 2 entries");
+        assert_eq!(entries[0].op, "create", "First op should be create");
+        assert_eq!(entries[1].op, "modify", "Second op should be modify");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_history_accepts_absolute_and_cwd_relative_paths() {
+        let dir = setup_test_dir();
+        std::fs::create_dir_all(dir.path().join("src/util")).unwrap();
+        std::fs::write(dir.path().join("src/util/io.rs"), "fn read() {}").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let port_s = port.to_string();
+
+        let absolute = dir.path().join("src/util/io.rs");
+        let runs = [
+            (dir.path().join("src"), "util/io.rs".to_string()),
+            (dir.path().join("src/util"), "./io.rs".to_string()),
+            (dir.path().join("src/util"), "../util/io.rs".to_string()),
+            (
+                dir.path().to_path_buf(),
+                absolute.to_string_lossy().to_string(),
+            ),
+            (dir.path().to_path_buf(), "src/util/io.rs".to_string()),
+        ];
+        for (cwd, arg) in &runs {
+            let out = run_ftm_in_dir(cwd, &["--port", &port_s, "history", arg]);
+            let stdout = String::from_utf8_lossy(&out.stdout);
+            assert!(out.status.success(), "{}", arg);
+            assert!(
+                stdout.starts_with("History for 'src/util/io.rs':"),
+                "{}: {}",
+                arg,
+                stdout
+            );
+        }
+
+        // Outside the watched directory the argument is still taken as a key
+        let out = run_ftm_with_port(port, &["history", "src/util/io.rs"]);
+        assert!(String::from_utf8_lossy(&out.stdout).starts_with("History for 'src/util/io.rs':"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_history_delete_recorded() {
+        let dir = setup_test_dir();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("todelete.yaml");
+
+        // Create
+        std::fs::write(&file_path, "will be deleted").unwrap();
+        assert!(wait_for_index(dir.path(), "todelete.yaml", 1, 2000));
+
+        // Delete
+        std::fs::remove_file(&file_path).unwrap();
+        assert!(
+            wait_for_index(dir.path(), "todelete.yaml", 2, 2000),
+            "Delete event should be recorded"
+        );
+
+        let index = load_test_index(dir.path());
+        let entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "todelete.yaml")
+            .collect();
+        assert_eq!(entries.len(), 2, "Should have 2 entries (create + delete)");
+        assert_eq!(entries[0].op, "create");
+        assert_eq!(entries[1].op, "delete");
+        assert!(
+            entries[1].checksum.is_none(),
+            "Delete entry should have no checksum"
+        );
+        assert!(
+            entries[1].size.is_none(),
+            "Delete entry should have no size"
+        );
+
+        stop_server(&mut server);
+    }
+
+    /// Default `ftm ls` excludes deleted files; `ftm ls --include-deleted` shows them.
+    #[test]
+    fn test_ls_hides_deleted_by_default() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("ls_hide_deleted.yaml");
+
+        std::fs::write(&file_path, "content").unwrap();
+        assert!(
+            wait_for_index(dir.path(), "ls_hide_deleted.yaml", 1, 2000),
+            "Create should be recorded"
+        );
+
+        let ls_default = run_ftm_with_port(port, &["ls"]);
+        assert!(ls_default.status.success(), "ftm ls should succeed");
+        let ls_stdout = String::from_utf8_lossy(&ls_default.stdout);
+        assert!(
+            ls_stdout.contains("ls_hide_deleted.yaml"),
+            "ls (default) should show file before delete; got:\n{}",
+            ls_stdout
+        );
+
+        std::fs::remove_file(&file_path).unwrap();
+        assert!(
+            wait_for_index(dir.path(), "ls_hide_deleted.yaml", 2, 2000),
+            "Delete event should be recorded"
+        );
+
+        let ls_after_delete = run_ftm_with_port(port, &["ls"]);
+        assert!(ls_after_delete.status.success(), "ftm ls should succeed");
+        let ls_stdout = String::from_utf8_lossy(&ls_after_delete.stdout);
+        assert!(
+            !ls_stdout.contains("ls_hide_deleted.yaml"),
+            "ls (default) should hide deleted file; got:\n{}",
+            ls_stdout
+        );
+
+        let ls_include_deleted = run_ftm_with_port(port, &["ls", "--include-deleted"]);
+        assert!(
+            ls_include_deleted.status.success(),
+            "ftm ls --include-deleted should succeed"
+        );
+        let ls_stdout = String::from_utf8_lossy(&ls_include_deleted.stdout);
+        assert!(
+            ls_stdout.contains("ls_hide_deleted.yaml"),
+            "ls --include-deleted should show deleted file; got:\n{}",
+            ls_stdout
+        );
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_ls_and_history_read_large_index() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        // 20,000 entries without seqs, as saved before they existed, with fields the
+        // readers skip before and after the history
+        let checksum = blake3::hash(b"x").to_hex().to_string();
+        let mut history: Vec<serde_json::Value> = (0..20_000)
+            .map(|i| {
+                serde_json::json!({
+                    "timestamp": "2024-01-01T00:00:00Z",
+                    "op": if i < 100 { "create" } else { "modify" },
+                    "file": format!("f{:02}.txt", i % 100),
+                    "checksum": checksum,
+                    "size": i,
+                })
+            })
+            .collect();
+        history.push(serde_json::json!({
+            "timestamp": "2024-01-02T00:00:00Z",
+            "op": "delete",
+            "file": "f00.txt",
+        }));
+        let index = serde_json::json!({
+            "unknown": { "nested": [1, 2, { "history": [] }] },
+            "history": history,
+            "next_seq": 0,
+        });
+        std::fs::write(
+            dir.path().join(".ftm/index.json"),
+            serde_json::to_string(&index).unwrap(),
+        )
+        .unwrap();
+
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let files = |include_deleted: bool| -> Vec<serde_json::Value> {
+            client
+                .get(format!(
+                    "http://127.0.0.1:{}/api/files?include_deleted={}",
+                    port, include_deleted
+                ))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap()
+        };
+        let listed = files(false);
+        assert_eq!(listed.len(), 99);
+        assert_eq!(listed[0]["name"], "f01.txt");
+        assert_eq!(listed[0]["count"], 200);
+        let listed = files(true);
+        assert_eq!(listed.len(), 100);
+        assert_eq!(listed[0]["count"], 201);
+
+        let versions: Vec<serde_json::Value> = client
+            .get(format!(
+                "http://127.0.0.1:{}/api/history?file=f07.txt",
+                port
+            ))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(versions.len(), 200);
+        assert_eq!(versions[0]["seq"], 8);
+        assert_eq!(versions[1]["seq"], 108);
+        assert_eq!(versions[199]["size"], 19_907);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_paths_completes_tracked_prefixes() {
+        let dir = setup_test_dir();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::create_dir_all(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("src/a.rs"), "a").unwrap();
+        std::fs::write(dir.path().join("src/b.rs"), "b").unwrap();
+        std::fs::write(dir.path().join("docs/c.md"), "c").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let paths = |query: &str| -> Vec<String> {
+            client
+                .get(format!("http://127.0.0.1:{}/api/paths?{}", port, query))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap()
+        };
+        assert_eq!(paths("prefix=src/"), ["src/a.rs", "src/b.rs"]);
+        assert_eq!(paths("prefix=src/&limit=1"), ["src/a.rs"]);
+        assert_eq!(paths("prefix=docs"), ["docs/c.md"]);
+        assert_eq!(paths("").len(), 3);
+        assert!(paths("prefix=lib").is_empty());
+
+        std::fs::remove_file(dir.path().join("src/b.rs")).unwrap();
+        std::fs::write(dir.path().join("src/d.rs"), "d").unwrap();
+        assert!(wait_for_index(dir.path(), "src/d.rs", 1, 5000));
+        assert!(wait_for_index(dir.path(), "src/b.rs", 2, 5000));
+        assert_eq!(paths("prefix=src/"), ["src/a.rs", "src/d.rs"]);
+        assert_eq!(
+            paths("prefix=src/&include_deleted=true"),
+            ["src/a.rs", "src/b.rs", "src/d.rs"]
+        );
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_history_recreate_after_delete() {
+        let dir = setup_test_dir();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("recreate.yaml");
+
+        // Create
+        std::fs::write(&file_path, "original content").unwrap();
+        assert!(wait_for_index(dir.path(), "recreate.yaml", 1, 2000));
+
+        // Delete
+        std::fs::remove_file(&file_path).unwrap();
+        assert!(wait_for_index(dir.path(), "recreate.yaml", 2, 2000));
+
+        // Recreate with new content
+        std::fs::write(&file_path, "recreated content").unwrap();
+        assert!(wait_for_index(dir.path(), "recreate.yaml", 3, 2000));
+
+        let index = load_test_index(dir.path());
+        let entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "recreate.yaml")
+            .collect();
+        assert_eq!(entries.len(), 3, "Should have 3 entries");
+        assert_eq!(entries[0].op, "create", "First should be create");
+        assert_eq!(entries[1].op, "delete", "Second should be delete");
+        assert_eq!(
+            entries[2].op, "create",
+            "Third should be create (after delete)"
+        );
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_history_multiple_files_independent() {
+        let dir = setup_test_dir();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+
+        std::fs::write(dir.path().join("alpha.yaml"), "a: 1").unwrap();
+        std::fs::write(dir.path().join("beta.yaml"), "b: 1").unwrap();
+        assert!(wait_for_index(dir.path(), "alpha.yaml", 1, 2000));
+        assert!(wait_for_index(dir.path(), "beta.yaml", 1, 2000));
+
+        // Modify only alpha
+        std::fs::write(dir.path().join("alpha.yaml"), "a: 2").unwrap();
+        assert!(wait_for_index(dir.path(), "alpha.yaml", 2, 2000));
+
+        let index = load_test_index(dir.path());
+        let alpha_count = index
+            .history
+            .iter()
+            .filter(|e| e.file == "alpha.yaml")
+            .count();
+        let beta_count = index
+            .history
+            .iter()
+            .filter(|e| e.file == "beta.yaml")
+            .count();
+        assert_eq!(
+            alpha_count, 2,
+            "alpha should have 2 entries (create + modify)"
+        );
+        assert_eq!(
+            beta_count, 1,
+            "beta should still have 1 entry (create only)"
+        );
+
+        stop_server(&mut server);
+    }
+}
+
+mod restore_tests {
+    use super::*;
+
+    /// Members of a zip archive of stored files, as `(name, content)`.
+    fn read_stored_zip(archive: &[u8]) -> Vec<(String, String)> {
+        let u16_at = |i: usize| u16::from_le_bytes([archive[i], archive[i + 1]]) as usize;
+        let mut members = Vec::new();
+        let mut pos = 0;
+        while archive[pos..].starts_with(&[0x50, 0x4b, 0x03, 0x04]) {
+            assert_eq!(u16_at(pos + 8), 0, "stored");
+            let size = u32::from_le_bytes(archive[pos + 22..pos + 26].try_into().unwrap()) as usize;
+            let name_len = u16_at(pos + 26);
+            let data = pos + 30 + name_len + u16_at(pos + 28);
+            members.push((
+                String::from_utf8(archive[pos + 30..pos + 30 + name_len].to_vec()).unwrap(),
+                String::from_utf8(archive[data..data + size].to_vec()).unwrap(),
+            ));
+            pos = data + size;
+        }
+        assert!(archive[pos..].starts_with(&[0x50, 0x4b, 0x01, 0x02]));
+        members
+    }
+
+    #[test]
+    fn test_export_dir_zips_files_as_they_were() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let sub = dir.path().join("sub");
+        std::fs::create_dir_all(sub.join("deep")).unwrap();
+        std::fs::write(sub.join("a.txt"), "a v1").unwrap();
+        std::fs::write(sub.join("deep/b.txt"), "b v1").unwrap();
+        std::fs::write(dir.path().join("other.txt"), "other").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let t1 = load_test_index(dir.path())
+            .history
+            .last()
+            .unwrap()
+            .timestamp
+            .clone();
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(sub.join("a.txt"), "a v2").unwrap();
+        std::fs::write(sub.join("late.txt"), "late").unwrap();
+        std::fs::remove_file(sub.join("deep/b.txt")).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let output = dir.path().join("sub-t1.zip");
+        let out = run_ftm_with_port(
+            port,
+            &[
+                "export-dir",
+                "sub",
+                "--at",
+                &t1,
+                "-o",
+                output.to_str().unwrap(),
+            ],
+        );
+        assert!(out.status.success(), "{:?}", out);
+        assert_eq!(
+            read_stored_zip(&std::fs::read(&output).unwrap()),
+            vec![
+                ("sub/a.txt".to_string(), "a v1".to_string()),
+                ("sub/deep/b.txt".to_string(), "b v1".to_string()),
+            ]
+        );
+        let out = run_ftm_with_port(port, &["export-dir", "sub", "-o", output.to_str().unwrap()]);
+        assert!(!out.status.success(), "existing output is not overwritten");
+
+        let resp = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(format!("http://127.0.0.1:{}/api/export-dir", port))
+            .query(&[("path", "sub")])
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "application/zip");
+        assert_eq!(
+            read_stored_zip(&resp.bytes().unwrap()),
+            vec![
+                ("sub/a.txt".to_string(), "a v2".to_string()),
+                ("sub/late.txt".to_string(), "late".to_string()),
+            ]
+        );
+
+        let out = run_ftm_with_port(
+            port,
+            &[
+                "export-dir",
+                "nowhere",
+                "-o",
+                output.with_extension("x").to_str().unwrap(),
+            ],
+        );
+        assert!(!out.status.success());
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_restore_dir_recovers_deleted_subtree() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let sub = dir.path().join("sub");
+        std::fs::create_dir_all(sub.join("deep")).unwrap();
+        std::fs::write(sub.join("a.txt"), "a v1").unwrap();
+        std::fs::write(sub.join("deep/b.txt"), "b v1").unwrap();
+        std::fs::write(dir.path().join("other.txt"), "other").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let t1 = load_test_index(dir.path())
+            .history
+            .last()
+            .unwrap()
+            .timestamp
+            .clone();
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(sub.join("a.txt"), "a v2").unwrap();
+        std::fs::write(sub.join("late.txt"), "late").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::remove_dir_all(&sub).unwrap();
+        std::fs::remove_file(dir.path().join("other.txt")).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        // Back to how the directory was at t1: late.txt did not exist yet
+        let out = run_ftm_with_port(port, &["restore-dir", "sub", "--at", &t1]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success(), "stdout: {}", stdout);
+        assert!(
+            stdout.contains("Restored 2 file(s) under 'sub'"),
+            "stdout: {}",
+            stdout
+        );
+        assert_eq!(std::fs::read_to_string(sub.join("a.txt")).unwrap(), "a v1");
+        assert_eq!(
+            std::fs::read_to_string(sub.join("deep/b.txt")).unwrap(),
+            "b v1"
+        );
+        assert!(!sub.join("late.txt").exists());
+        assert!(!dir.path().join("other.txt").exists());
+
+        // Without --at, remaining deleted files come back at their last version;
+        // files that exist again are left alone
+        std::fs::remove_file(sub.join("deep/b.txt")).unwrap();
+        let out = run_ftm_with_port(port, &["restore-dir", "sub/"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(stdout.contains("Restored 2 file(s)"), "stdout: {}", stdout);
+        assert_eq!(std::fs::read_to_string(sub.join("a.txt")).unwrap(), "a v1");
+        assert_eq!(
+            std::fs::read_to_string(sub.join("late.txt")).unwrap(),
+            "late"
+        );
+        assert_eq!(
+            std::fs::read_to_string(sub.join("deep/b.txt")).unwrap(),
+            "b v1"
+        );
+
+        let out = run_ftm_with_port(port, &["restore-dir", "nowhere"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("No deleted files"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_restore_dir_rolls_back_on_failure() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let sub = dir.path().join("sub");
+        std::fs::create_dir_all(sub.join("deep")).unwrap();
+        std::fs::write(sub.join("a.txt"), "a").unwrap();
+        std::fs::write(sub.join("deep/z.txt"), "z").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::remove_dir_all(&sub).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        // The last file's snapshot is damaged, so the batch cannot complete
+        let checksum = load_test_index(dir.path())
+            .history
+            .iter()
+            .find(|e| e.file == "sub/deep/z.txt")
+            .and_then(|e| e.checksum.clone())
+            .unwrap();
+        let snap = dir
+            .path()
+            .join(".ftm/snapshots")
+            .join(&checksum[..1])
+            .join(&checksum[1..2])
+            .join(&checksum);
+        let mut perms = std::fs::metadata(&snap).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        std::fs::set_permissions(&snap, perms).unwrap();
+        std::fs::write(&snap, "tampered").unwrap();
+
+        let out = run_ftm_with_port(port, &["restore-dir", "sub"]);
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("sub/deep/z.txt"), "stderr: {}", stderr);
+        // Nothing half-restored: no files, temporaries or created directories remain
+        assert!(!sub.exists());
+        let index: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(dir.path().join(".ftm/index.json")).unwrap(),
+        )
+        .unwrap();
+        assert!(index.get("events").is_none_or(|events| !events
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|e| e["op"] == "restore")));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_restore_not_checked_out() {
+        let (mut server, port) = start_server();
+
+        let out = run_ftm_with_port(port, &["restore", "test.rs", "abc12345"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_restore_version_not_found() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["restore", "test.rs", "abc12345"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("Version not found"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_restore_roundtrip() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("roundtrip.yaml");
+
+        let v1_content = "version: 1\ndata: original";
+        let v2_content = "version: 2\ndata: modified";
+
+        // Write v1
+        std::fs::write(&file_path, v1_content).unwrap();
+        assert!(wait_for_index(dir.path(), "roundtrip.yaml", 1, 2000));
+
+        // Write v2
+        std::fs::write(&file_path, v2_content).unwrap();
+        assert!(wait_for_index(dir.path(), "roundtrip.yaml", 2, 2000));
+
+        // Get v1's checksum from index
+        let index = load_test_index(dir.path());
+        let v1_entry = index
+            .history
+            .iter()
+            .find(|e| e.file == "roundtrip.yaml" && e.op == "create")
+            .expect("v1 create entry not found");
+        let v1_checksum = v1_entry.checksum.as_ref().unwrap();
+
+        // Verify current content is v2
+        let current = std::fs::read_to_string(&file_path).unwrap();
+        assert_eq!(current, v2_content, "File should currently be v2");
+
+        // Restore v1 via server
+        let out = run_ftm_with_port(port, &["restore", "roundtrip.yaml", v1_checksum]);
+        assert!(
+            out.status.success(),
+            "restore: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+
+        // Verify content is back to v1
+        let restored = std::fs::read_to_string(&file_path).unwrap();
+        assert_eq!(
+            restored, v1_content,
+            "File content should be restored to v1"
+        );
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_restore_with_short_checksum_prefix() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("prefix.yaml");
+
+        let original = "data: for_prefix_test";
+
+        std::fs::write(&file_path, original).unwrap();
+        assert!(wait_for_index(dir.path(), "prefix.yaml", 1, 2000));
+
+        std::fs::write(&file_path, "data: modified version").unwrap();
+        assert!(wait_for_index(dir.path(), "prefix.yaml", 2, 2000));
+
+        let index = load_test_index(dir.path());
+        let entry = index
+            .history
+            .iter()
+            .find(|e| e.file == "prefix.yaml" && e.op == "create")
+            .unwrap();
+        let full_checksum = entry.checksum.as_ref().unwrap();
+        let short_prefix = &full_checksum[..8];
+
+        // Restore using only the first 8 chars of the checksum
+        let out = run_ftm_with_port(port, &["restore", "prefix.yaml", short_prefix]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+
+        let restored = std::fs::read_to_string(&file_path).unwrap();
+        assert_eq!(
+            restored, original,
+            "Restore with 8-char prefix should recover original content"
+        );
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_restore_reports_conflict_with_unrecorded_changes() {
+        let dir = setup_test_dir();
+        let file_path = dir.path().join("conflict.txt");
+        std::fs::write(&file_path, "v1\n").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(&file_path, "v2\n").unwrap();
+        assert!(wait_for_index(dir.path(), "conflict.txt", 2, 3000));
+        let hash = |s: &[u8]| blake3::hash(s).to_hex().to_string();
+
+        // Bulk mode keeps the watcher from recording the edit
+        assert!(run_ftm_with_port(port, &["bulk-begin"]).status.success());
+        std::fs::write(&file_path, "v2 plus edits\n").unwrap();
+
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let restore = |checksum: &str, check_conflict: bool| {
+            client
+                .post(format!("http://127.0.0.1:{}/api/restore", port))
+                .json(&serde_json::json!({
+                    "file": "conflict.txt",
+                    "checksum": checksum,
+                    "check_conflict": check_conflict,
+                }))
+                .send()
+                .unwrap()
+        };
+        let resp = restore(&hash(b"v1\n")[..8], true);
+        assert_eq!(resp.status(), reqwest::StatusCode::CONFLICT);
+        let body: serde_json::Value = resp.json().unwrap();
+        let conflict = &body["conflict"];
+        assert_eq!(conflict["file"], "conflict.txt");
+        assert_eq!(conflict["working"]["content"], "v2 plus edits\n");
+        assert_eq!(conflict["working"]["checksum"], hash(b"v2 plus edits\n"));
+        assert_eq!(conflict["snapshot"]["content"], "v1\n");
+        assert_eq!(conflict["snapshot"]["checksum"], hash(b"v1\n"));
+        assert_eq!(conflict["ancestor"]["content"], "v2\n");
+        assert!(conflict["ancestor"]["timestamp"].is_string());
+        assert_eq!(
+            std::fs::read_to_string(&file_path).unwrap(),
+            "v2 plus edits\n",
+            "a conflict leaves the working copy alone"
+        );
+
+        // Choosing the snapshot overwrites the edits
+        assert!(restore(&hash(b"v1\n")[..8], false).status().is_success());
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "v1\n");
+
+        // The restored file matches a recorded version: no conflict
+        assert!(restore(&hash(b"v2\n")[..8], true).status().is_success());
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "v2\n");
+
+        assert!(run_ftm_with_port(port, &["bulk-end"]).status.success());
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_restore_deleted_file() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("willdelete.yaml");
+
+        let content = "precious: data";
+        std::fs::write(&file_path, content).unwrap();
+        assert!(wait_for_index(dir.path(), "willdelete.yaml", 1, 2000));
+
+        // Delete the file and wait for the delete event
+        std::fs::remove_file(&file_path).unwrap();
+        assert!(!file_path.exists(), "File should be deleted");
+        assert!(
+            wait_for_index(dir.path(), "willdelete.yaml", 2, 2000),
+            "Delete event should be recorded"
+        );
+
+        // Get the checksum from the create entry
+        let index = load_test_index(dir.path());
+        let entry = index
+            .history
+            .iter()
+            .find(|e| e.file == "willdelete.yaml" && e.op == "create")
+            .unwrap();
+        let checksum = entry.checksum.as_ref().unwrap().clone();
+
+        // Restore the deleted file via server (watcher will pick this up)
+        let out = run_ftm_with_port(port, &["restore", "willdelete.yaml", &checksum]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+
+        assert!(file_path.exists(), "File should be restored after deletion");
+        let restored = std::fs::read_to_string(&file_path).unwrap();
+        assert_eq!(restored, content, "Restored content should match original");
+
+        // Wait for the watcher to record the restored file as a new create
+        assert!(
+            wait_for_index(dir.path(), "willdelete.yaml", 3, 2000),
+            "Restored file should be recorded as a new create entry"
+        );
+
+        // Verify the full index: create -> delete -> create
+        let index_after = load_test_index(dir.path());
+        let entries: Vec<_> = index_after
+            .history
+            .iter()
+            .filter(|e| e.file == "willdelete.yaml")
+            .collect();
+        assert_eq!(
+            entries.len(),
+            3,
+            "Should have 3 entries: create, delete, create"
+        );
+        assert_eq!(entries[0].op, "create", "First entry should be create");
+        assert_eq!(entries[1].op, "delete", "Second entry should be delete");
+        assert_eq!(
+            entries[2].op, "create",
+            "Third entry (after restore) should be create"
+        );
+
+        // The newest create entry checksum should match the original content
+        let last_entry = entries.last().unwrap();
+        assert_eq!(last_entry.op, "create", "Latest entry must be create");
+        let expected_checksum = blake3::hash(content.as_bytes()).to_hex().to_string();
+        assert_eq!(
+            last_entry.checksum.as_ref().unwrap(),
+            &expected_checksum,
+            "Latest create entry checksum should match the original content hash"
+        );
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_restore_to_subdirectory() {
+        let dir = setup_test_dir();
+        let sub_dir = dir.path().join("nested/dir");
+        std::fs::create_dir_all(&sub_dir).unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let file_path = sub_dir.join("deep.yaml");
+
+        let content = "nested: file content";
+        std::fs::write(&file_path, content).unwrap();
+        assert!(wait_for_index(dir.path(), "nested/dir/deep.yaml", 1, 2000));
+
+        // Get checksum
+        let index = load_test_index(dir.path());
+        let entry = index
+            .history
+            .iter()
+            .find(|e| e.file == "nested/dir/deep.yaml")
+            .unwrap();
+        let checksum = entry.checksum.as_ref().unwrap();
+
+        // Delete the entire subdirectory tree
+        std::fs::remove_dir_all(dir.path().join("nested")).unwrap();
+        assert!(!file_path.exists());
+
+        // Restore should recreate parent directories automatically
+        let out = run_ftm_with_port(port, &["restore", "nested/dir/deep.yaml", checksum]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+
+        assert!(
+            file_path.exists(),
+            "File should be restored with parent dirs recreated"
+        );
+        let restored = std::fs::read_to_string(&file_path).unwrap();
+        assert_eq!(restored, content);
+
+        stop_server(&mut server);
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_xattr(path: &Path, name: &str, value: &[u8]) {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+        let name = std::ffi::CString::new(name).unwrap();
+        let rc = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        assert_eq!(rc, 0, "{}", std::io::Error::last_os_error());
+    }
+
+    #[cfg(target_os = "linux")]
+    fn get_xattr(path: &Path, name: &str) -> Vec<u8> {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+        let name = std::ffi::CString::new(name).unwrap();
+        let mut buf = [0u8; 64];
+        let n = unsafe {
+            libc::getxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+            )
+        };
+        assert!(n >= 0, "{}", std::io::Error::last_os_error());
+        buf[..n as usize].to_vec()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_restore_brings_back_tracked_xattrs() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server();
+        checkout_over_api(port, dir.path());
+        let set = run_ftm_with_port(port, &["config", "set", "settings.track_xattrs", "true"]);
+        assert!(set.status.success());
+        let path = dir.path().join("tagged.txt");
+
+        // Bulk mode keeps the watcher from recording the file before its tag is set
+        assert!(run_ftm_with_port(port, &["bulk-begin"]).status.success());
+        std::fs::write(&path, "v1\n").unwrap();
+        set_xattr(&path, "user.tag", b"red");
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(&path, "v2\n").unwrap();
+        set_xattr(&path, "user.tag", b"blue");
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let index = load_test_index(dir.path());
+        let versions: Vec<String> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "tagged.txt")
+            .filter_map(|e| e.checksum.clone())
+            .collect();
+        assert_eq!(versions.len(), 2);
+        assert!(dir.path().join(".ftm/xattrs").join(&versions[0]).is_dir());
+
+        let out = run_ftm_with_port(port, &["restore", "tagged.txt", &versions[0][..8]]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "v1\n");
+        assert_eq!(get_xattr(&path, "user.tag"), b"red");
+
+        stop_server(&mut server);
+    }
+}
+
+mod snapshot_tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_full_and_range_requests() {
+        let dir = setup_test_dir();
+        std::fs::write(dir.path().join("range.txt"), "0123456789").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let index = load_test_index(dir.path());
+        let checksum = index.history[0].checksum.clone().unwrap();
+        let url = format!(
+            "http://127.0.0.1:{}/api/snapshot?checksum={}",
+            port, checksum
+        );
+
+        let resp = http().get(&url).send().unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["accept-ranges"], "bytes");
+        assert_eq!(resp.text().unwrap(), "0123456789");
+
+        let resp = http()
+            .get(&url)
+            .header("Range", "bytes=2-5")
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.headers()["content-range"], "bytes 2-5/10");
+        assert_eq!(resp.text().unwrap(), "2345");
+
+        let resp = http().get(&url).header("Range", "bytes=7-").send().unwrap();
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.text().unwrap(), "789");
+
+        let resp = http().get(&url).header("Range", "bytes=-3").send().unwrap();
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.headers()["content-range"], "bytes 7-9/10");
+        assert_eq!(resp.text().unwrap(), "789");
+
+        let resp = http()
+            .get(&url)
+            .header("Range", "bytes=50-")
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 416);
+        assert_eq!(resp.headers()["content-range"], "bytes */10");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_snapshot_content_type_detected() {
+        let dir = setup_test_dir();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(dir.path().join("data.json"), "{}").unwrap();
+        std::fs::write(dir.path().join("blob.txt"), b"\xff\xfe\x00binary").unwrap();
+        std::fs::write(dir.path().join("image.txt"), b"\x89PNG\r\n\x1a\n....").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let latest = |file: &str| -> serde_json::Value {
+            let entries: Vec<serde_json::Value> = http()
+                .get(format!("http://127.0.0.1:{}/api/history", port))
+                .query(&[("file", file)])
+                .send()
+                .unwrap()
+                .json()
+                .unwrap();
+            entries.last().unwrap().clone()
+        };
+        let served = |entry: &serde_json::Value| {
+            let resp = http()
+                .get(format!("http://127.0.0.1:{}/api/snapshot", port))
+                .query(&[("checksum", entry["checksum"].as_str().unwrap())])
+                .send()
+                .unwrap();
+            assert_eq!(resp.headers()["x-content-type-options"], "nosniff");
+            resp.headers()["content-type"].to_str().unwrap().to_string()
+        };
+
+        let rs = latest("main.rs");
+        assert_eq!(rs["mime"], "text/x-rust");
+        assert_eq!(rs["language"], "rust");
+        assert_eq!(served(&rs), "text/x-rust; charset=utf-8");
+
+        let json = latest("data.json");
+        assert_eq!(json["language"], "json");
+        assert_eq!(served(&json), "application/json; charset=utf-8");
+
+        let blob = latest("blob.txt");
+        assert_eq!(blob["mime"], "application/octet-stream");
+        assert!(blob.get("language").is_none());
+        assert_eq!(served(&blob), "application/octet-stream");
+
+        // Content sniffing wins over the extension
+        assert_eq!(served(&latest("image.txt")), "image/png");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_snapshot_invalid_checksum_not_found() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        for checksum in ["a", "../../config.yaml", &"0".repeat(64)] {
+            let resp = http()
+                .get(format!("http://127.0.0.1:{}/api/snapshot", port))
+                .query(&[("checksum", checksum)])
+                .send()
+                .unwrap();
+            assert_eq!(resp.status(), 404, "checksum {:?} should be 404", checksum);
+        }
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_upload_snapshot_records_external_version() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let url = format!("http://127.0.0.1:{}/api/snapshot", port);
+        let upload = |file: &str, checksum: &str, content: &'static [u8]| {
+            // A reader of unknown length is sent with chunked transfer encoding
+            http()
+                .post(&url)
+                .query(&[("file", file), ("checksum", checksum), ("writer", "ci")])
+                .body(reqwest::blocking::Body::new(std::io::Cursor::new(content)))
+                .send()
+                .unwrap()
+        };
+
+        let content = b"build 42: ok\n";
+        let checksum = blake3::hash(content).to_hex().to_string();
+        let resp = upload("ci/report.txt", &checksum, content);
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = resp.json().unwrap();
+        assert_eq!(body["entry"]["op"], "create");
+        assert_eq!(body["entry"]["source"], "upload");
+        assert_eq!(body["entry"]["writer"], "ci");
+        assert_eq!(body["entry"]["lines"], 1);
+
+        // The same content again is not a new version
+        let body: serde_json::Value = upload("ci/report.txt", &checksum, content).json().unwrap();
+        assert!(body["entry"].is_null());
+
+        let served = http()
+            .get(&url)
+            .query(&[("checksum", checksum.as_str())])
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert_eq!(served, "build 42: ok\n");
+
+        // Wrong checksums and paths outside the watched directory are refused
+        let wrong = blake3::hash(b"other").to_hex().to_string();
+        assert_eq!(upload("ci/report.txt", &wrong, content).status(), 400);
+        assert_eq!(upload("../escape.txt", &checksum, content).status(), 400);
+        assert_eq!(upload("ci/report.txt", "abc", content).status(), 400);
+
+        // Bodies over settings.max_file_size are refused, by length or while streaming
+        let set = run_ftm_with_port(port, &["config", "set", "settings.max_file_size", "8"]);
+        assert!(set.status.success());
+        let big = b"0123456789abcdef";
+        let big_checksum = blake3::hash(big).to_hex().to_string();
+        assert_eq!(upload("ci/big.txt", &big_checksum, big).status(), 413);
+        let resp = http()
+            .post(&url)
+            .query(&[("file", "ci/big.txt"), ("checksum", big_checksum.as_str())])
+            .body(big.to_vec())
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 413);
+        let set = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.max_file_size", "1048576"],
+        );
+        assert!(set.status.success());
+
+        let index = load_test_index(dir.path());
+        assert_eq!(index.history.len(), 1);
+
+        // An uploaded version restores like any other
+        let out = run_ftm_with_port(port, &["restore", "ci/report.txt", &checksum[..8]]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("ci/report.txt")).unwrap(),
+            "build 42: ok\n"
+        );
+
+        // Versions the watcher records while an upload streams are kept
+        struct Slow(Vec<&'static [u8]>);
+        impl std::io::Read for Slow {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.0.is_empty() {
+                    return Ok(0);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(500));
+                let chunk = self.0.remove(0);
+                buf[..chunk.len()].copy_from_slice(chunk);
+                Ok(chunk.len())
+            }
+        }
+        let slow_checksum = blake3::hash(b"slow upload\n").to_hex().to_string();
+        let slow_url = url.clone();
+        let uploading = std::thread::spawn(move || {
+            http()
+                .post(&slow_url)
+                .query(&[
+                    ("file", "ci/slow.txt"),
+                    ("checksum", slow_checksum.as_str()),
+                ])
+                .body(reqwest::blocking::Body::new(Slow(vec![
+                    b"slow ", b"upload", b"\n",
+                ])))
+                .send()
+                .unwrap()
+                .status()
+        });
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        std::fs::write(dir.path().join("meanwhile.txt"), "edited during upload").unwrap();
+        assert!(wait_for_index(dir.path(), "meanwhile.txt", 1, 3000));
+        assert_eq!(uploading.join().unwrap(), 200);
+        let index = load_test_index(dir.path());
+        assert!(index.history.iter().any(|e| e.file == "meanwhile.txt"));
+        assert!(index.history.iter().any(|e| e.file == "ci/slow.txt"));
+
+        stop_server(&mut server);
+    }
+}
+
+mod sync_tests {
+    use super::*;
+
+    /// Start a second server and check out `dir` through the API directly
+    /// (`ftm checkout` would stop the other test server). Returns (child, port).
+    fn start_peer(dir: &Path) -> (std::process::Child, u16) {
+        let (child, port) = start_server();
+        let resp = http()
+            .post(format!("http://127.0.0.1:{}/api/checkout", port))
+            .json(&serde_json::json!({ "directory": dir.to_str().unwrap() }))
+            .send()
+            .unwrap();
+        assert!(resp.status().is_success(), "peer checkout should succeed");
+        (child, port)
+    }
+
+    fn stdout_of(out: &std::process::Output) -> String {
+        assert!(
+            out.status.success(),
+            "command should succeed: stdout={}, stderr={}",
+            String::from_utf8_lossy(&out.stdout),
+            String::from_utf8_lossy(&out.stderr)
+        );
+        String::from_utf8_lossy(&out.stdout).into_owned()
+    }
+
+    #[test]
+    fn test_push_copies_entries_and_snapshots_idempotently() {
+        let local_dir = setup_test_dir();
+        let remote_dir = setup_test_dir();
+        std::fs::write(local_dir.path().join("notes.txt"), "v1").unwrap();
+        let (mut local, local_port) = start_server_and_checkout(local_dir.path());
+        assert!(run_ftm_with_port(local_port, &["scan"]).status.success());
+        std::fs::write(local_dir.path().join("notes.txt"), "v2").unwrap();
+        assert!(run_ftm_with_port(local_port, &["scan"]).status.success());
+
+        let (mut remote, remote_port) = start_peer(remote_dir.path());
+        let remote_url = format!("http://127.0.0.1:{}", remote_port);
+
+        let out = run_ftm_with_port(local_port, &["push", &remote_url]);
+        let stdout = stdout_of(&out);
+        assert!(
+            stdout.contains("Pushed 2 entries (2 snapshots)"),
+            "stdout: {}",
+            stdout
+        );
+
+        let local_index = load_test_index(local_dir.path());
+        let remote_index = load_test_index(remote_dir.path());
+        let checksums = |index: &TestIndex| -> Vec<Option<String>> {
+            index
+                .history
+                .iter()
+                .filter(|e| e.file == "notes.txt")
+                .map(|e| e.checksum.clone())
+                .collect()
+        };
+        assert_eq!(checksums(&remote_index), checksums(&local_index));
+        for checksum in checksums(&remote_index).into_iter().flatten() {
+            let resp = http()
+                .get(format!("{}/api/snapshot", remote_url))
+                .query(&[("checksum", &checksum)])
+                .send()
+                .unwrap();
+            assert_eq!(resp.status(), 200);
+        }
+
+        let out = run_ftm_with_port(local_port, &["push", &format!("{}/", remote_url)]);
+        let stdout = stdout_of(&out);
+        assert!(stdout.contains("is up to date"), "stdout: {}", stdout);
+        assert_eq!(
+            load_test_index(remote_dir.path()).history.len(),
+            remote_index.history.len()
+        );
+
+        stop_server(&mut remote);
+        stop_server(&mut local);
+    }
+
+    /// Snapshots over the receiver's `settings.max_file_size` are refused while they
+    /// stream; the other entries are still pushed.
+    #[test]
+    fn test_push_refuses_snapshots_over_max_file_size() {
+        let local_dir = setup_test_dir();
+        let remote_dir = setup_test_dir();
+        std::fs::write(local_dir.path().join("small.txt"), "ok").unwrap();
+        std::fs::write(local_dir.path().join("big.txt"), "0123456789abcdef").unwrap();
+        let (mut local, local_port) = start_server_and_checkout(local_dir.path());
+        assert!(run_ftm_with_port(local_port, &["scan"]).status.success());
+
+        let (mut remote, remote_port) = start_peer(remote_dir.path());
+        let out = run_ftm_with_port(
+            remote_port,
+            &["config", "set", "settings.max_file_size", "8"],
+        );
+        stdout_of(&out);
+        let remote_url = format!("http://127.0.0.1:{}", remote_port);
+
+        let big = b"0123456789abcdef";
+        let resp = http()
+            .post(format!("{}/api/sync/snapshot", remote_url))
+            .query(&[("checksum", blake3::hash(big).to_hex().as_str())])
+            .body(reqwest::blocking::Body::new(std::io::Cursor::new(big)))
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 413);
+
+        let out = run_ftm_with_port(local_port, &["push", &remote_url]);
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("max_file_size"), "stderr: {}", stderr);
+        let files: Vec<String> = load_test_index(remote_dir.path())
+            .history
+            .into_iter()
+            .map(|e| e.file)
+            .collect();
+        assert_eq!(files, ["small.txt"]);
+
+        stop_server(&mut remote);
+        stop_server(&mut local);
+    }
+
+    #[test]
+    fn test_pull_makes_remote_versions_restorable() {
+        let local_dir = setup_test_dir();
+        let remote_dir = setup_test_dir();
+        std::fs::write(local_dir.path().join("mine.txt"), "local").unwrap();
+        std::fs::write(local_dir.path().join("todo.txt"), "local").unwrap();
+        let (mut local, local_port) = start_server_and_checkout(local_dir.path());
+        assert!(run_ftm_with_port(local_port, &["scan"]).status.success());
+        assert!(wait_for_index(local_dir.path(), "todo.txt", 1, 2000));
+
+        std::fs::write(remote_dir.path().join("shared.txt"), "from desktop").unwrap();
+        let (mut remote, remote_port) = start_peer(remote_dir.path());
+        let out = run_ftm_with_port(
+            remote_port,
+            &["config", "set", "settings.sign_history", "true"],
+        );
+        stdout_of(&out);
+        let resp = http()
+            .post(format!("http://127.0.0.1:{}/api/scan", remote_port))
+            .send()
+            .unwrap();
+        assert!(resp.status().is_success());
+        let remote_url = format!("127.0.0.1:{}", remote_port);
+
+        let out = run_ftm_with_port(local_port, &["pull", &remote_url]);
+        let stdout = stdout_of(&out);
+        assert!(
+            stdout.contains("Pulled 1 entries (1 snapshots)"),
+            "stdout: {}",
+            stdout
+        );
+
+        let index = load_test_index(local_dir.path());
+        let entry = index
+            .history
+            .iter()
+            .find(|e| e.file == "shared.txt")
+            .expect("pulled entry should be in the local index");
+        let checksum = entry.checksum.clone().unwrap();
+        let out = run_ftm_with_port(local_port, &["restore", "shared.txt", &checksum[..8]]);
+        stdout_of(&out);
+        assert_eq!(
+            std::fs::read_to_string(local_dir.path().join("shared.txt")).unwrap(),
+            "from desktop"
+        );
+
+        let out = run_ftm_with_port(local_port, &["pull", &remote_url]);
+        assert!(stdout_of(&out).contains("Already up to date"));
+
+        // The entry arrives whole, keeping the remote's signature
+        let raw: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(local_dir.path().join(".ftm/index.json")).unwrap(),
+        )
+        .unwrap();
+        let pulled = raw["history"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|e| e["file"] == "shared.txt")
+            .unwrap();
+        assert_eq!(pulled["lines"], 1);
+        assert_eq!(pulled["source"], "manual");
+        assert!(pulled["sig"].is_string());
+        assert_ne!(pulled["seq"], pulled["signed_seq"]);
+        assert!(pulled.get("mtime_nanos").is_none());
+        let out = run_ftm_with_port(local_port, &["verify-signatures"]);
+        let stdout = stdout_of(&out);
+        assert!(
+            stdout.contains("1 entries with a valid signature, 2 unsigned"),
+            "stdout: {}",
+            stdout
+        );
+
+        stop_server(&mut remote);
+        stop_server(&mut local);
+    }
+
+    #[test]
+    fn test_sync_rejects_bad_snapshots_and_entries() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let base = format!("http://127.0.0.1:{}", port);
+
+        let resp = http()
+            .post(format!("{}/api/sync/snapshot", base))
+            .query(&[("checksum", "0".repeat(64))])
+            .body("not matching")
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 400);
+
+        let entry = |file: &str, checksum: &str| {
+            serde_json::json!([{
+                "timestamp": "2026-01-01T00:00:00Z",
+                "op": "create",
+                "file": file,
+                "checksum": checksum,
+                "size": 3,
+            }])
+        };
+        let missing = entry("a.txt", &"1".repeat(64));
+        let resp = http()
+            .post(format!("{}/api/sync/entries", base))
+            .json(&missing)
+            .send()
+            .unwrap();
+        assert_eq!(
+            resp.status(),
+            400,
+            "entry without snapshot should be rejected"
+        );
+
+        let content = "abc";
+        let checksum = hex::encode(<sha2::Sha256 as sha2::Digest>::digest(content));
+        let resp = http()
+            .post(format!("{}/api/sync/snapshot", base))
+            .query(&[("checksum", &checksum)])
+            .body(content)
+            .send()
+            .unwrap();
+        assert!(resp.status().is_success());
+        let resp = http()
+            .post(format!("{}/api/sync/entries", base))
+            .json(&entry("../escape.txt", &checksum))
+            .send()
+            .unwrap();
+        assert_eq!(
+            resp.status(),
+            400,
+            "path escaping the root should be rejected"
+        );
+
+        let resp = http()
+            .post(format!("{}/api/sync/entries", base))
+            .json(&entry("a.txt", &checksum))
+            .send()
+            .unwrap();
+        assert!(resp.status().is_success());
+        assert!(load_test_index(dir.path())
+            .history
+            .iter()
+            .any(|e| e.file == "a.txt" && e.checksum.as_deref() == Some(checksum.as_str())));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_export_import_index_validates_bundle() {
+        let source_dir = setup_test_dir();
+        let target_dir = setup_test_dir();
+        let bundle_dir = setup_test_dir();
+        let bundle = bundle_dir.path().join("bundle");
+        std::fs::write(source_dir.path().join("notes.txt"), "v1").unwrap();
+        let (mut source, source_port) = start_server_and_checkout(source_dir.path());
+        assert!(run_ftm_with_port(source_port, &["scan"]).status.success());
+        std::fs::write(source_dir.path().join("notes.txt"), "v2").unwrap();
+        assert!(run_ftm_with_port(source_port, &["scan"]).status.success());
+
+        let out = run_ftm_with_port(source_port, &["export-index", bundle.to_str().unwrap()]);
+        assert!(
+            stdout_of(&out).contains("Exported 2 entries (2 snapshots)"),
+            "export should list entries and snapshots"
+        );
+        let out = run_ftm_with_port(source_port, &["export-index", bundle.to_str().unwrap()]);
+        assert!(
+            !out.status.success(),
+            "an existing bundle is not overwritten"
+        );
+        stop_server(&mut source);
+
+        // A tampered snapshot fails validation and nothing is imported
+        let checksum = load_test_index(source_dir.path()).history[0]
+            .checksum
+            .clone()
+            .unwrap();
+        let snapshot = bundle.join("snapshots").join(&checksum);
+        let original = std::fs::read(&snapshot).unwrap();
+        std::fs::write(&snapshot, "v9").unwrap();
+
+        let (mut target, target_port) = start_peer(target_dir.path());
+        let out = run_ftm_with_port(target_port, &["import-index", bundle.to_str().unwrap()]);
+        assert!(!out.status.success(), "corrupt bundle should be rejected");
+        assert!(
+            String::from_utf8_lossy(&out.stderr).contains("does not match its checksum"),
+            "stderr: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert!(load_test_index(target_dir.path()).history.is_empty());
+
+        std::fs::write(&snapshot, original).unwrap();
+        let out = run_ftm_with_port(target_port, &["import-index", bundle.to_str().unwrap()]);
+        let stdout = stdout_of(&out);
+        assert!(
+            stdout.contains("Imported 2 entries (2 snapshots)"),
+            "stdout: {}",
+            stdout
+        );
+        let out = run_ftm_with_port(target_port, &["import-index", bundle.to_str().unwrap()]);
+        assert!(stdout_of(&out).contains("already contains every entry"));
+
+        let last = load_test_index(target_dir.path()).history[1]
+            .checksum
+            .clone()
+            .unwrap();
+        let out = run_ftm_with_port(target_port, &["restore", "notes.txt", &last[..8]]);
+        stdout_of(&out);
+        assert_eq!(
+            std::fs::read_to_string(target_dir.path().join("notes.txt")).unwrap(),
+            "v2"
+        );
+
+        stop_server(&mut target);
+    }
+
+    #[test]
+    fn test_export_import_file_archive() {
+        let source_dir = setup_test_dir();
+        let target_dir = setup_test_dir();
+        let archive_dir = setup_test_dir();
+        let archive = archive_dir.path().join("notes-history.tar");
+        let archive_arg = archive.to_str().unwrap();
+        std::fs::write(source_dir.path().join("notes.txt"), "version one").unwrap();
+        std::fs::write(source_dir.path().join("other.txt"), "unrelated").unwrap();
+        let (mut source, source_port) = start_server_and_checkout(source_dir.path());
+        assert!(run_ftm_with_port(source_port, &["scan"]).status.success());
+        std::fs::write(source_dir.path().join("notes.txt"), "version two").unwrap();
+        assert!(run_ftm_with_port(source_port, &["scan"]).status.success());
+
+        let out = run_ftm_with_port(
+            source_port,
+            &["export-file", "notes.txt", "-o", archive_arg],
+        );
+        let stdout = stdout_of(&out);
+        assert!(
+            stdout.contains("Exported 2 entries (2 versions) of 'notes.txt'"),
+            "stdout: {}",
+            stdout
+        );
+        let out = run_ftm_with_port(
+            source_port,
+            &["export-file", "notes.txt", "-o", archive_arg],
+        );
+        assert!(
+            !out.status.success(),
+            "an existing archive is not overwritten"
+        );
+        let missing = archive_dir.path().join("missing.tar");
+        let out = run_ftm_with_port(
+            source_port,
+            &["export-file", "nope.txt", "-o", missing.to_str().unwrap()],
+        );
+        assert!(!out.status.success());
+        assert!(!missing.exists());
+        stop_server(&mut source);
+
+        // A tampered version fails validation and nothing is imported
+        let original = std::fs::read(&archive).unwrap();
+        let at = original
+            .windows(11)
+            .position(|w| w == b"version one")
+            .unwrap();
+        let mut tampered = original.clone();
+        tampered[at + 8] = b'0';
+        std::fs::write(&archive, &tampered).unwrap();
+
+        let (mut target, target_port) = start_peer(target_dir.path());
+        let out = run_ftm_with_port(target_port, &["import-file", archive_arg]);
+        assert!(!out.status.success(), "corrupt archive should be rejected");
+        assert!(
+            String::from_utf8_lossy(&out.stderr).contains("does not match its checksum"),
+            "stderr: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert!(load_test_index(target_dir.path()).history.is_empty());
+
+        std::fs::write(&archive, original).unwrap();
+        let out = run_ftm_with_port(target_port, &["import-file", archive_arg]);
+        let stdout = stdout_of(&out);
+        assert!(
+            stdout.contains("Imported 2 entries (2 snapshots)"),
+            "stdout: {}",
+            stdout
+        );
+        let history = load_test_index(target_dir.path()).history;
+        assert!(history.iter().all(|e| e.file == "notes.txt"));
+
+        let first = history[0].checksum.clone().unwrap();
+        let out = run_ftm_with_port(target_port, &["restore", "notes.txt", &first[..8]]);
+        stdout_of(&out);
+        assert_eq!(
+            std::fs::read_to_string(target_dir.path().join("notes.txt")).unwrap(),
+            "version one"
+        );
+
+        stop_server(&mut target);
+    }
+}
+
+mod mirror_tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_pulls_history_and_rejects_writes() {
+        let source_dir = setup_test_dir();
+        let mirror_dir = setup_test_dir();
+        std::fs::write(source_dir.path().join("doc.txt"), "first").unwrap();
+        let (mut source, source_port) = start_server_and_checkout(source_dir.path());
+        assert!(run_ftm_with_port(source_port, &["scan"]).status.success());
+
+        PreInitFtm::new(mirror_dir.path()).scan_interval(1).init();
+        let source_url = format!("http://127.0.0.1:{}", source_port);
+        let (mut mirror, mirror_port) = start_server_with_args(&[
+            "--mirror",
+            &source_url,
+            "--mirror-dir",
+            mirror_dir.path().to_str().unwrap(),
+        ]);
+
+        assert!(
+            wait_for_index(mirror_dir.path(), "doc.txt", 1, 5000),
+            "mirror should pull the initial version"
+        );
+        let out = run_ftm_with_port(mirror_port, &["history", "doc.txt"]);
+        assert!(out.status.success());
+
+        std::fs::write(source_dir.path().join("doc.txt"), "second").unwrap();
+        assert!(run_ftm_with_port(source_port, &["scan"]).status.success());
+        assert!(
+            wait_for_index(mirror_dir.path(), "doc.txt", 2, 5000),
+            "mirror should pick up new versions on the next pull"
+        );
+
+        let health: serde_json::Value = http()
+            .get(format!("http://127.0.0.1:{}/api/health", mirror_port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(health["mirror_of"], source_url.as_str());
+
+        for path in [
+            "/api/scan",
+            "/api/clean",
+            "/api/restore",
+            "/api/sync/entries",
+        ] {
+            let resp = http()
+                .post(format!("http://127.0.0.1:{}{}", mirror_port, path))
+                .json(&serde_json::json!({}))
+                .send()
+                .unwrap();
+            assert_eq!(
+                resp.status(),
+                403,
+                "{} should be rejected on a mirror",
+                path
+            );
+        }
+        assert!(
+            !mirror_dir.path().join("doc.txt").exists(),
+            "mirror must not touch its working directory"
+        );
+
+        stop_server(&mut mirror);
+        stop_server(&mut source);
+    }
+}
+
+mod trash_tests {
+    use super::*;
+
+    fn checksum_of(content: &str) -> String {
+        blake3::hash(content.as_bytes()).to_hex().to_string()
+    }
+
+    /// Record two versions of notes.txt with max_history=1, then clean so v1 is trimmed.
+    fn trim_first_version(dir: &Path, port: u16) {
+        let file = dir.join("notes.txt");
+        std::fs::write(&file, "v1").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(&file, "v2 longer").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let out = run_ftm_with_port(port, &["clean"]);
+        assert!(out.status.success());
+        assert_eq!(load_test_index(dir).history.len(), 1);
+    }
+
+    #[test]
+    fn test_trimmed_snapshot_goes_to_trash_and_unclean_recovers() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_history(1).init();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        trim_first_version(dir.path(), port);
+
+        let v1 = checksum_of("v1");
+        let trashed = dir.path().join(".ftm/trash/snapshots").join(&v1);
+        assert!(trashed.exists(), "trimmed snapshot should be in the trash");
+        assert!(dir.path().join(".ftm/trash/entries.jsonl").exists());
+
+        let out = run_ftm_with_port(port, &["unclean"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success());
+        assert!(
+            stdout.contains("Recovered 1 history entries and 1 snapshot(s)"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(!trashed.exists());
+
+        let index = load_test_index(dir.path());
+        let checksums: Vec<_> = index
+            .history
+            .iter()
+            .filter_map(|e| e.checksum.clone())
+            .collect();
+        assert_eq!(checksums, vec![v1.clone(), checksum_of("v2 longer")]);
+
+        let out = run_ftm_with_port(port, &["restore", "notes.txt", &v1[..8]]);
+        assert!(out.status.success());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(),
+            "v1"
+        );
+
+        let out = run_ftm_with_port(port, &["unclean"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("nothing to recover"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_zero_retention_purges_trash() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_history(1).init();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        trim_first_version(dir.path(), port);
+
+        let trash = dir.path().join(".ftm/trash/snapshots");
+        assert_eq!(count_files_recursive(&trash), 1);
+
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.trash_retention_days", "0"],
+        );
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["clean"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(
+            stdout.contains("1 expired snapshot(s) permanently deleted"),
+            "stdout: {}",
+            stdout
+        );
+        assert_eq!(count_files_recursive(&trash), 0);
+        assert!(!dir.path().join(".ftm/trash/entries.jsonl").exists());
+
+        // With trash disabled, trimmed snapshots are deleted right away.
+        std::fs::write(dir.path().join("notes.txt"), "v3 longest").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        assert_eq!(count_files_recursive(&trash), 0);
+        assert_eq!(count_snapshot_files(dir.path()), 1);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_trash_counts_against_max_quota() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path())
+            .max_history(1)
+            .max_quota(1500)
+            .init();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let file = dir.path().join("notes.txt");
+        let trash = dir.path().join(".ftm/trash/snapshots");
+
+        // v1 is trimmed for v2 and fits in the trash beside it (500 + 1000 bytes).
+        std::fs::write(&file, "1".repeat(500)).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(&file, "2".repeat(1000)).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        assert!(trash.join(checksum_of(&"1".repeat(500))).exists());
+
+        // v2 is trimmed for v3; the older v1 is purged so the trash fits beside v3.
+        std::fs::write(&file, "3".repeat(400)).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        assert!(!trash.join(checksum_of(&"1".repeat(500))).exists());
+        assert!(trash.join(checksum_of(&"2".repeat(1000))).exists());
+        assert_eq!(load_test_index(dir.path()).history.len(), 1);
+
+        stop_server(&mut server);
+    }
+}
+
+mod stats_tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_warning_in_stats_and_health() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_quota(100).init();
+        std::fs::write(dir.path().join("big.txt"), "x".repeat(95)).unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let health = |port: u16| -> serde_json::Value {
+            reqwest::blocking::Client::builder()
+                .no_proxy()
+                .build()
+                .unwrap()
+                .get(format!("http://127.0.0.1:{}/api/health", port))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap()
+        };
+        let out = run_ftm_with_port(port, &["stats"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(
+            stdout.contains("Warning: snapshot quota is 95% full"),
+            "stdout: {}",
+            stdout
+        );
+        assert_eq!(health(port)["quota_warning"], true);
+
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.quota_warn_percent", "101"],
+        );
+        assert!(!out.status.success());
+        let out = run_ftm_with_port(port, &["config", "set", "settings.quota_warn_percent", "0"]);
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["stats"]);
+        assert!(!String::from_utf8_lossy(&out.stdout).contains("Warning: snapshot quota"));
+        assert_eq!(health(port)["quota_warning"], false);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_file_stats() {
+        let dir = setup_test_dir();
+        std::fs::write(dir.path().join("a.txt"), "one").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "one").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(dir.path().join("a.txt"), "second").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(wait_for_index(dir.path(), "a.txt", 2, 2000));
+
+        let stats: serde_json::Value = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(format!("http://127.0.0.1:{}/api/stats/file", port))
+            .query(&[("file", "a.txt")])
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(stats["versions"], 2);
+        assert_eq!(stats["snapshots"], 2);
+        assert_eq!(stats["snapshot_bytes"], 9);
+        // "one" is also b.txt's content
+        assert_eq!(stats["shared_bytes"], 3);
+        assert!(stats["avg_interval_secs"].as_f64().unwrap() >= 0.0);
+
+        let out = run_ftm_with_port(port, &["stats", "a.txt"]);
+        assert!(out.status.success());
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(stdout.contains("Versions:  2"), "stdout: {}", stdout);
+        assert!(
+            stdout.contains("Snapshots: 2 (9 bytes, 3 bytes shared with other files)"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(
+            stdout.contains("Average change interval:"),
+            "stdout: {}",
+            stdout
+        );
+
+        let out = run_ftm_with_port(port, &["stats", "missing.txt"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("No history for 'missing.txt'"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_watcher_stats() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let stats = || -> serde_json::Value {
+            reqwest::blocking::Client::builder()
+                .no_proxy()
+                .build()
+                .unwrap()
+                .get(format!("http://127.0.0.1:{}/api/stats/watcher", port))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap()
+        };
+        assert_eq!(stats()["snapshots_saved"], 0);
+
+        std::fs::write(dir.path().join("watched.txt"), "hello").unwrap();
+        assert!(wait_for_index(dir.path(), "watched.txt", 1, 5000));
+        std::fs::remove_file(dir.path().join("watched.txt")).unwrap();
+        assert!(wait_for_index(dir.path(), "watched.txt", 2, 5000));
+
+        let st = stats();
+        assert!(st["events_received"].as_u64().unwrap() >= 2, "{}", st);
+        assert!(st["events_relevant"].as_u64().unwrap() >= 2, "{}", st);
+        assert!(st["batches"].as_u64().unwrap() >= 2, "{}", st);
+        assert!(st["largest_batch"].as_u64().unwrap() >= 1, "{}", st);
+        assert_eq!(st["snapshots_saved"], 1, "{}", st);
+        assert_eq!(st["deletes_recorded"], 1, "{}", st);
+        assert_eq!(st["scan_errors"], 0, "{}", st);
+        assert_eq!(st["overflows"], 0, "{}", st);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_usage_samples_recorded_daily_and_shown_in_stats() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).init();
+        // An older sample, as if recorded on an earlier day
+        std::fs::write(
+            dir.path().join(".ftm/stats.json"),
+            r#"[{"at":"2026-01-01T00:00:00Z","history":0,"quota":0}]"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("grow.txt"), "hello").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+
+        let samples: Vec<serde_json::Value> = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(format!("http://127.0.0.1:{}/api/stats/history", port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(samples.len(), 2, "one sample per day: {:?}", samples);
+        assert_eq!(samples[1]["history"], 1);
+        assert_eq!(samples[1]["quota"], 5);
+
+        let out = run_ftm_with_port(port, &["stats"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success());
+        assert!(
+            stdout.contains("Growth (daily samples):"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(stdout.contains("1 entries (+1)"), "stdout: {}", stdout);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_quota_projection_from_recent_growth() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).init();
+        std::fs::write(dir.path().join("a.txt"), "aaaa").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "bbbb").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        // Only today's sample: too early to tell
+        let out = run_ftm_with_port(port, &["stats", "--quota-projection"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success());
+        assert!(
+            stdout.contains("Projection: not enough usage history yet"),
+            "stdout: {}",
+            stdout
+        );
+
+        // Empty ten days ago, two entries now: 0.2 entries/day. An hour short of ten
+        // days, so time passing before the stats are read cannot tip the rounding.
+        let ten_days_ago = (chrono::Utc::now() - chrono::Duration::days(10)
+            + chrono::Duration::hours(1))
+        .to_rfc3339();
+        std::fs::write(
+            dir.path().join(".ftm/stats.json"),
+            format!(r#"[{{"at":"{}","history":0,"quota":0}}]"#, ten_days_ago),
+        )
+        .unwrap();
+        let out = run_ftm_with_port(port, &["config", "set", "settings.max_history", "12"]);
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["stats", "--quota-projection"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success());
+        assert!(
+            stdout.contains("Projection (growth over the last 10 days):"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(
+            stdout.contains("+0.2 entries/day, max_history (12) reached in ~50 days"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(stdout.contains("not within 10 years"), "stdout: {}", stdout);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_age_stats_bucket_snapshots_by_newest_version() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).init();
+        std::fs::write(dir.path().join("a.txt"), "aaaa").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "bbbbbb").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(wait_for_index(dir.path(), "b.txt", 1, 3000));
+
+        // b.txt last changed 3 days ago; an old version of a.txt's content and a
+        // snapshot last used 100 days ago
+        let days_ago = |d: i64| (chrono::Utc::now() - chrono::Duration::days(d)).to_rfc3339();
+        let index_path = dir.path().join(".ftm/index.json");
+        let mut index: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&index_path).unwrap()).unwrap();
+        let history = index["history"].as_array_mut().unwrap();
+        let a = history
+            .iter()
+            .find(|e| e["file"] == "a.txt")
+            .unwrap()
+            .clone();
+        for entry in history.iter_mut().filter(|e| e["file"] == "b.txt") {
+            entry["timestamp"] = days_ago(3).into();
+        }
+        let mut old_a = a.clone();
+        old_a["file"] = "old_a.txt".into();
+        old_a["timestamp"] = days_ago(100).into();
+        let mut old = a;
+        old["file"] = "old.txt".into();
+        old["checksum"] = blake3::hash(b"gone").to_hex().to_string().into();
+        old["size"] = 7.into();
+        old["timestamp"] = days_ago(100).into();
+        history.insert(0, old);
+        history.insert(0, old_a);
+        std::fs::write(&index_path, serde_json::to_string(&index).unwrap()).unwrap();
+
+        let buckets: serde_json::Value = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(format!("http://127.0.0.1:{}/api/stats/age", port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(
+            buckets,
+            serde_json::json!([
+                {"age": "today", "snapshots": 1, "bytes": 4},
+                {"age": "this_week", "snapshots": 1, "bytes": 6},
+                {"age": "this_month", "snapshots": 0, "bytes": 0},
+                {"age": "older", "snapshots": 1, "bytes": 7},
+            ])
+        );
+
+        let out = run_ftm_with_port(port, &["stats", "--age"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success());
+        assert!(stdout.contains("Snapshots by age of their newest version:"));
+        assert!(stdout.contains("this week"), "stdout: {}", stdout);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_dedup_stats_count_shared_snapshots() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).init();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(dir.path().join(name), "0123456789").unwrap();
+        }
+        std::fs::write(dir.path().join("d.txt"), "unique").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let dedup: serde_json::Value = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(format!("http://127.0.0.1:{}/api/dedup", port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(dedup["references"], 4);
+        assert_eq!(dedup["snapshots"], 2);
+        assert_eq!(dedup["logical_bytes"], 36);
+        assert_eq!(dedup["physical_bytes"], 16);
+        assert_eq!(dedup["duplicate_references"], 2);
+
+        let out = run_ftm_with_port(port, &["stats"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(
+            stdout.contains("36 bytes referenced by 4 versions, 16 bytes stored in 2 snapshots"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(
+            stdout.contains("2 duplicate references avoided, 20 bytes saved (2.25x)"),
+            "stdout: {}",
+            stdout
+        );
+
+        stop_server(&mut server);
+    }
+}
+
+mod resume_tests {
+    use super::*;
+
+    fn health_watch_dir(port: u16) -> Option<String> {
+        let health: serde_json::Value = http()
+            .get(format!("http://127.0.0.1:{}/api/health", port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        health["watch_dir"].as_str().map(String::from)
+    }
+
+    #[test]
+    fn test_serve_resume_rechecks_out_last_directory() {
+        let dir = setup_test_dir();
+        let state_dir = setup_test_dir();
+        let envs = [("FTM_STATE_DIR", state_dir.path())];
+
+        // Check out through the API: `ftm checkout` would start its own server
+        // without our FTM_STATE_DIR.
+        let (mut server, port) = start_server_with_env(&[], &envs);
+        let resp = http()
+            .post(format!("http://127.0.0.1:{}/api/checkout", port))
+            .json(&serde_json::json!({
+                "directory": dir.path().canonicalize().unwrap().to_str().unwrap()
+            }))
+            .send()
+            .unwrap();
+        assert!(resp.status().is_success());
+        stop_server(&mut server);
+
+        let state: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(state_dir.path().join("state.json"))
+                .expect("checkout should record the last directory"),
+        )
+        .unwrap();
+        let expected = dir.path().canonicalize().unwrap();
+        assert_eq!(
+            state["last_checkout"]
+                .as_str()
+                .map(std::path::PathBuf::from),
+            Some(expected.clone())
+        );
+
+        let (mut resumed, port) = start_server_with_env(&["--resume"], &envs);
+        assert_eq!(
+            health_watch_dir(port).map(std::path::PathBuf::from),
+            Some(expected)
+        );
+        stop_server(&mut resumed);
+
+        // Without --resume the server starts idle.
+        let (mut idle, port) = start_server_with_env(&[], &envs);
+        assert_eq!(health_watch_dir(port), None);
+        stop_server(&mut idle);
+    }
+}
+
+mod autostart_tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_autostart_enable_writes_systemd_unit_and_disable_removes_it() {
+        let config_home = setup_test_dir();
+        let run = |args: &[&str]| {
+            ftm_command()
+                .args(args)
+                .env("XDG_CONFIG_HOME", config_home.path())
+                .output()
+                .expect("failed to run ftm")
+        };
+
+        let out = run(&["--port", "14123", "autostart", "enable"]);
+        assert!(
+            out.status.success(),
+            "enable should succeed even without a user session bus: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let unit_path = config_home.path().join("systemd/user/ftm.service");
+        let unit = std::fs::read_to_string(&unit_path).expect("unit file should be written");
+        let exec = unit
+            .lines()
+            .find(|l| l.starts_with("ExecStart="))
+            .expect("unit should have ExecStart");
+        assert!(exec.contains(env!("CARGO_BIN_EXE_ftm")), "{}", exec);
+        assert!(exec.contains("--port 14123 serve --resume"), "{}", exec);
+        assert!(unit.contains("WantedBy=default.target"));
+
+        let out = run(&["autostart", "disable"]);
+        assert!(out.status.success());
+        assert!(!unit_path.exists(), "disable should remove the unit file");
+        let out = run(&["autostart", "disable"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("not enabled"));
+    }
+}
+
+mod lock_tests {
+    use super::*;
+
+    fn api_checkout(port: u16, dir: &Path) -> reqwest::blocking::Response {
+        reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .post(format!("http://127.0.0.1:{}/api/checkout", port))
+            .json(&serde_json::json!({ "directory": dir.to_str().unwrap() }))
+            .send()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_second_server_cannot_check_out_locked_directory() {
+        let dir = setup_test_dir();
+        let (mut first, first_port) = start_server();
+        assert!(api_checkout(first_port, dir.path()).status().is_success());
+
+        let (mut second, second_port) = start_server();
+        let resp = api_checkout(second_port, dir.path());
+        assert_eq!(resp.status(), 409);
+        let body: serde_json::Value = resp.json().unwrap();
+        let message = body["message"].as_str().unwrap();
+        assert!(
+            message.contains("already watched by another ftm server")
+                && message.contains(&format!("port {}", first_port)),
+            "error should point at the existing server: {}",
+            message
+        );
+
+        // The lock dies with its owner, even when it is killed.
+        stop_server(&mut first);
+        assert!(api_checkout(second_port, dir.path()).status().is_success());
+        stop_server(&mut second);
+    }
+}
+
+mod trim_tests {
+    use super::*;
+
+    #[test]
+    fn test_history_notes_trimmed_versions() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_history(1).init();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        for content in ["v1", "v2", "v3"] {
+            std::fs::write(dir.path().join("a.txt"), content).unwrap();
+            assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        }
+        let history = || {
+            let out = run_ftm_with_port(port, &["history", "a.txt"]);
+            assert!(out.status.success());
+            String::from_utf8_lossy(&out.stdout).into_owned()
+        };
+        assert!(!history().contains("trimmed"));
+
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        let stdout = history();
+        assert!(
+            stdout.contains(
+                "2 older version(s) were trimmed (settings.max_history / settings.max_quota)"
+            ),
+            "stdout: {}",
+            stdout
+        );
+        let out = run_ftm_with_port(port, &["stats", "a.txt"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("2 older version(s) were trimmed"));
+
+        // Versions brought back by unclean are no longer counted
+        assert!(run_ftm_with_port(port, &["unclean"]).status.success());
+        assert!(!history().contains("trimmed"), "stdout: {}", history());
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_min_versions_per_file_floor() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_history(1).init();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.min_versions_per_file", "2"],
+        );
+        assert!(out.status.success());
+
+        std::fs::write(dir.path().join("a.txt"), "a1").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "b1").unwrap();
+        std::fs::write(dir.path().join("c.txt"), "c1").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        for content in ["a2", "a3"] {
+            std::fs::write(dir.path().join("a.txt"), content).unwrap();
+            assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        }
+        std::fs::remove_file(dir.path().join("c.txt")).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        let index = load_test_index(dir.path());
+        let count = |file: &str| index.history.iter().filter(|e| e.file == file).count();
+        // Existing files keep their latest versions past max_history; deleted files do not
+        assert_eq!(count("a.txt"), 2);
+        assert_eq!(count("b.txt"), 1);
+        assert_eq!(count("c.txt"), 0);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_max_history_trims_old_entries() {
+        let dir = setup_test_dir();
+
+        // Pre-init .ftm with max_history=3
+        PreInitFtm::new(dir.path()).max_history(3).init();
+
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("trimme.yaml");
+
+        // Write 5 different versions with delay between each
+        for i in 0..5 {
+            std::fs::write(&file_path, format!("version: {}", i)).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        // Write a sync marker so we have 6 total entries and trigger trim to 3
+        std::fs::write(dir.path().join("sync.yaml"), "sync: done").unwrap();
+        assert!(
+            wait_for_index(dir.path(), "sync.yaml", 1, 5000),
+            "Sync marker should be recorded"
+        );
+
+        let index = load_test_index(dir.path());
+        assert!(
+            index.history.len() <= 3,
+            "global max_history=3: expected at most 3 total entries, got {}",
+            index.history.len()
+        );
+
+        let entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "trimme.yaml")
+            .collect();
+        assert!(
+            (1..=2).contains(&entries.len()),
+            "trimme.yaml should have 1 or 2 entries (sync may take one slot), got {}",
+            entries.len()
+        );
+
+        let expected_checksums: Vec<String> = (3..5)
+            .map(|i| {
+                blake3::hash(format!("version: {}", i).as_bytes())
+                    .to_hex()
+                    .to_string()
+            })
+            .collect();
+        let expected = if entries.len() == 2 {
+            &expected_checksums[..]
+        } else {
+            &expected_checksums[1..]
+        };
+        for (entry, expected_cs) in entries.iter().zip(expected.iter()) {
+            let cs = entry.checksum.as_ref().expect("entry should have checksum");
+            assert_eq!(
+                cs, expected_cs,
+                "Trimmed entries for trimme should be the newest versions (v3, v4) in order"
+            );
+        }
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_max_quota_trims_by_volume() {
+        let dir = setup_test_dir();
+        let max_quota = 150 * 1024; // 150KB
+        PreInitFtm::new(dir.path())
+            .max_history(1000)
+            .max_quota(max_quota)
+            .init();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let file_path = dir.path().join("bigfile.yaml");
+
+        // Write 5 versions, each ~50KB, so total ~250KB > 150KB quota
+        let chunk: String = "x".repeat(1024);
+        for i in 0..5 {
+            let content = format!("version: {}\n{}", i, chunk.repeat(50));
+            std::fs::write(&file_path, content).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success(), "scan should succeed");
+        assert!(
+            wait_for_index(dir.path(), "bigfile.yaml", 1, 5000),
+            "bigfile.yaml should have at least one entry"
+        );
+
+        let out = run_ftm_with_port(port, &["clean"]);
+        assert!(out.status.success(), "clean should succeed");
+
+        let index = load_test_index(dir.path());
+        let volume = referenced_snapshot_volume(dir.path(), &index);
+        assert!(
+            volume <= max_quota,
+            "referenced snapshot volume {} should be <= max_quota {}",
+            volume,
+            max_quota
+        );
+
+        let snapshot_count = count_snapshot_files(dir.path());
+        assert!(
+            snapshot_count < 5,
+            "oldest snapshots should be removed from disk, got {} files",
+            snapshot_count
+        );
+
+        stop_server(&mut server);
+    }
+}
+
+mod scan_tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_not_checked_out() {
+        let (mut server, port) = start_server();
+
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_detects_new_files() {
+        let dir = setup_test_dir();
+
+        // Create files BEFORE checkout (watcher won't see them)
+        std::fs::write(dir.path().join("hello.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.path().join("world.py"), "print('hi')").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(s.contains("2 created"));
+        assert!(s.contains("0 modified"));
+        assert!(s.contains("0 deleted"));
+
+        let index = load_test_index(dir.path());
+        let entries: Vec<_> = index.history.iter().collect();
+        assert_eq!(entries.len(), 2, "Should have 2 entries after scan");
+        assert!(entries.iter().all(|e| e.op == "create"));
+        assert!(entries.iter().any(|e| e.file == "hello.rs"));
+        assert!(entries.iter().any(|e| e.file == "world.py"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_dry_run_records_nothing() {
+        let dir = setup_test_dir();
+        std::fs::write(dir.path().join("hello.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.path().join("world.py"), "print('hi')").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["scan", "--dry-run"]);
+        assert!(out.status.success());
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(s.contains("Would create: hello.rs"), "{}", s);
+        assert!(s.contains("Would create: world.py"), "{}", s);
+        assert!(s.contains("Dry run: 2 created"), "{}", s);
+        assert!(load_test_index(dir.path()).history.is_empty());
+        assert_eq!(count_snapshot_files(dir.path()), 0);
+
+        // A real scan records what the dry run listed; afterwards nothing is pending
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("2 created"));
+        let out = run_ftm_with_port(port, &["scan", "--dry-run"]);
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(!s.contains("Would "), "{}", s);
+        assert!(s.contains("0 created, 0 modified, 0 deleted"), "{}", s);
+        assert_eq!(load_test_index(dir.path()).history.len(), 2);
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_verbose_and_json_list_changed_files() {
+        let dir = setup_test_dir();
+        std::fs::write(dir.path().join("keep.rs"), "fn a() {}").unwrap();
+        std::fs::write(dir.path().join("gone.rs"), "fn b() {}").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        std::fs::write(dir.path().join("keep.rs"), "fn a() { 1 }").unwrap();
+        std::fs::remove_file(dir.path().join("gone.rs")).unwrap();
+        std::fs::write(dir.path().join("new.rs"), "fn c() {}").unwrap();
+        let out = run_ftm_with_port(port, &["scan", "--verbose"]);
+        assert!(out.status.success());
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(s.contains("Created: new.rs"), "{}", s);
+        assert!(s.contains("Modified: keep.rs"), "{}", s);
+        assert!(s.contains("Deleted: gone.rs"), "{}", s);
+        assert!(s.contains("1 created, 1 modified, 1 deleted"), "{}", s);
+
+        std::fs::write(dir.path().join("new.rs"), "fn c() { 2 }").unwrap();
+        let out = run_ftm_with_port(port, &["scan", "--json"]);
+        let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        assert_eq!(result["modified"], 1);
+        assert_eq!(
+            result["changes"],
+            serde_json::json!([{"file": "new.rs", "op": "modify"}])
+        );
+        let out = run_ftm_with_port(port, &["scan", "--json"]);
+        let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        assert_eq!(result["changes"], serde_json::json!([]));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_max_tracked_files_blocks_checkout_and_scan() {
+        run_alone();
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).init();
+        let config_path = dir.path().join(".ftm/config.yaml");
+        let mut config = std::fs::read_to_string(&config_path).unwrap();
+        config.push_str("  max_tracked_files: 2\n");
+        std::fs::write(&config_path, config).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt", "skip.bin"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+
+        let (mut server, port) = start_server();
+        let path_s = dir.path().to_str().unwrap();
+        let out = run_ftm_with_port(port, &["checkout", path_s]);
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(
+            stderr.contains("More than 2 files") && stderr.contains("--force"),
+            "stderr: {}",
+            stderr
+        );
+
+        let out = run_ftm_with_port(port, &["checkout", path_s, "--force"]);
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("settings.max_tracked_files"));
+        assert!(
+            load_test_index(dir.path()).history.is_empty(),
+            "a refused scan records nothing"
+        );
+        let out = run_ftm_with_port(port, &["scan", "--force"]);
+        assert!(out.status.success());
+        assert!(load_test_index(dir.path()).history.len() >= 3);
+
+        // Unmatched files do not count towards the limit
+        let out = run_ftm_with_port(port, &["config", "set", "settings.max_tracked_files", "3"]);
+        assert!(out.status.success());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_detects_modifications() {
+        let dir = setup_test_dir();
+
+        // Create baseline file BEFORE checkout
+        std::fs::write(dir.path().join("app.rs"), "fn main() {}").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        // First scan: creates baseline
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+
+        // Modify the file (watcher will also detect this, but we verify final state)
+        std::fs::write(dir.path().join("app.rs"), "fn main() { println!(\"hi\"); }").unwrap();
+
+        // Wait for either watcher or scan to pick up the change
+        assert!(
+            wait_for_index(dir.path(), "app.rs", 2, 2000),
+            "Modification should be recorded"
+        );
+
+        let index = load_test_index(dir.path());
+        let entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "app.rs")
+            .collect();
+        assert_eq!(entries.len(), 2, "Should have create + modify");
+        assert_eq!(entries[0].op, "create");
+        assert_eq!(entries[1].op, "modify");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_detects_deletions() {
+        let dir = setup_test_dir();
+
+        // Create file BEFORE checkout
+        std::fs::write(dir.path().join("temp.txt"), "temporary content").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        // Scan to create baseline
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+
+        // Delete the file (watcher will also detect this)
+        std::fs::remove_file(dir.path().join("temp.txt")).unwrap();
+
+        // Wait for deletion to be recorded
+        assert!(
+            wait_for_index(dir.path(), "temp.txt", 2, 2000),
+            "Deletion should be recorded"
+        );
+
+        let index = load_test_index(dir.path());
+        let entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "temp.txt")
+            .collect();
+        assert_eq!(entries.len(), 2, "Should have create + delete");
+        assert_eq!(entries[0].op, "create");
+        assert_eq!(entries[1].op, "delete");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_no_changes_second_run() {
+        let dir = setup_test_dir();
+
+        // Create file BEFORE checkout
+        std::fs::write(dir.path().join("stable.md"), "# Stable").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        // First scan
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+
+        // Second scan - nothing changed
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(s.contains("0 created"));
+        assert!(s.contains("0 modified"));
+        assert!(s.contains("0 deleted"));
+        assert!(s.contains("1 unchanged"));
+
+        // Index should still only have 1 entry
+        let index = load_test_index(dir.path());
+        let count = index
+            .history
+            .iter()
+            .filter(|e| e.file == "stable.md")
+            .count();
+        assert_eq!(count, 1, "No new entries should be added on unchanged scan");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_ignores_non_matching_patterns() {
+        let dir = setup_test_dir();
+
+        // Create files BEFORE checkout
+        std::fs::write(dir.path().join("image.png"), "not tracked").unwrap();
+        std::fs::write(dir.path().join("binary.exe"), "not tracked").unwrap();
+        std::fs::write(dir.path().join("code.rs"), "fn test() {}").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+
+        let index = load_test_index(dir.path());
+        assert_eq!(
+            index.history.len(),
+            1,
+            "Only matching file should be tracked"
+        );
+        assert_eq!(index.history[0].file, "code.rs");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_skips_large_files() {
+        let dir = setup_test_dir();
+
+        // Pre-init .ftm with max_file_size=100
+        PreInitFtm::new(dir.path()).max_file_size(100).init();
+
+        // Create files BEFORE checkout
+        std::fs::write(dir.path().join("small.txt"), "tiny").unwrap();
+        std::fs::write(dir.path().join("large.txt"), "x".repeat(200)).unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+
+        let index = load_test_index(dir.path());
+        assert_eq!(index.history.len(), 1);
+        assert_eq!(index.history[0].file, "small.txt");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_subdirectories() {
+        let dir = setup_test_dir();
+
+        // Create files in subdirectories BEFORE checkout
+        let sub_dir = dir.path().join("src/lib");
+        std::fs::create_dir_all(&sub_dir).unwrap();
+        std::fs::write(sub_dir.join("mod.rs"), "pub mod lib;").unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("2 created"));
+
+        let index = load_test_index(dir.path());
+        assert!(index.history.iter().any(|e| e.file == "src/lib/mod.rs"));
+        assert!(index.history.iter().any(|e| e.file == "main.rs"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_skips_excluded_directories() {
+        let dir = setup_test_dir();
+
+        // Create files in excluded directories BEFORE checkout
+        let target_dir = dir.path().join("target/debug");
+        std::fs::create_dir_all(&target_dir).unwrap();
+        std::fs::write(target_dir.join("build.rs"), "// build artifact").unwrap();
+
+        let node_dir = dir.path().join("node_modules/pkg");
+        std::fs::create_dir_all(&node_dir).unwrap();
+        std::fs::write(node_dir.join("index.js"), "module.exports = {}").unwrap();
+
+        // Normal tracked file
+        std::fs::write(dir.path().join("app.rs"), "fn main() {}").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+
+        let index = load_test_index(dir.path());
+        assert_eq!(index.history.len(), 1);
+        assert_eq!(index.history[0].file, "app.rs");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_empty_files_ignored() {
+        let dir = setup_test_dir();
+
+        // Create files BEFORE checkout
+        std::fs::write(dir.path().join("empty.rs"), "").unwrap();
+        std::fs::write(dir.path().join("notempty.rs"), "fn x() {}").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+
+        let index = load_test_index(dir.path());
+        assert_eq!(index.history.len(), 1);
+        assert_eq!(index.history[0].file, "notempty.rs");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_scan_dedup_same_content() {
+        let dir = setup_test_dir();
+
+        // Create files BEFORE checkout
+        let content = "shared: content";
+        std::fs::write(dir.path().join("a.yaml"), content).unwrap();
+        std::fs::write(dir.path().join("b.yaml"), content).unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("2 created"));
+
+        // Both entries should share the same snapshot
+        let snap_count = count_snapshot_files(dir.path());
+        assert_eq!(
+            snap_count, 1,
+            "Two files with same content should share 1 snapshot"
+        );
+
+        let index = load_test_index(dir.path());
+        let checksums: Vec<_> = index
+            .history
+            .iter()
+            .filter_map(|e| e.checksum.as_ref())
+            .collect();
+        assert_eq!(checksums.len(), 2);
+        assert_eq!(checksums[0], checksums[1], "Checksums should match");
+
+        stop_server(&mut server);
+    }
+}
+
+mod clean_tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_not_checked_out() {
+        let (mut server, port) = start_server();
+
+        let out = run_ftm_with_port(port, &["clean"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_maintenance_log_lists_cleans_that_removed_something() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_history(1).init();
+        std::fs::write(dir.path().join("a.txt"), "v1").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(dir.path().join("a.txt"), "v2").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        // The second clean finds nothing and is not recorded
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+
+        let records: Vec<serde_json::Value> = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(format!("http://127.0.0.1:{}/api/maintenance", port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(records.len(), 1, "{:?}", records);
+        assert_eq!(records[0]["entries_trimmed"], 1);
+        assert_eq!(records[0]["bytes_freed_trim"], 2);
+        assert!(records[0]["at"].is_string());
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_orphans_lists_unreferenced_snapshots() {
+        let dir = setup_test_dir();
+        std::fs::write(dir.path().join("a.txt"), "v1").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let out = run_ftm_with_port(port, &["orphans"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("No orphan snapshots"));
+
+        let checksum = format!("ab{}", "0".repeat(62));
+        let shard = dir.path().join(".ftm/snapshots/a/b");
+        std::fs::create_dir_all(&shard).unwrap();
+        std::fs::write(shard.join(&checksum), "orphan").unwrap();
+
+        let orphans: Vec<serde_json::Value> = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(format!("http://127.0.0.1:{}/api/orphans", port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(orphans.len(), 1, "{:?}", orphans);
+        assert_eq!(orphans[0]["checksum"], checksum.as_str());
+        assert_eq!(orphans[0]["size"], 6);
+        assert!(orphans[0]["modified"].is_string());
+
+        let out = run_ftm_with_port(port, &["orphans"]);
+        let stdout = String::from_utf8_lossy(&out.stdout).into_owned();
+        assert!(stdout.contains(&checksum[..12]), "stdout: {}", stdout);
+        assert!(
+            stdout.contains("1 orphan snapshot(s)"),
+            "stdout: {}",
+            stdout
+        );
+
+        // Listing removes nothing; clean does
+        assert_eq!(count_snapshot_files(dir.path()), 2);
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        let out = run_ftm_with_port(port, &["orphans"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("No orphan snapshots"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_clean_removes_orphan_snapshots() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_history(1).init();
+
+        std::fs::write(dir.path().join("clean_orphan.yaml"), "v1").unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+
+        std::fs::write(dir.path().join("clean_orphan.yaml"), "v2").unwrap();
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("1 modified"));
+
+        let out = run_ftm_with_port(port, &["clean"]);
+        assert!(out.status.success());
+
+        let index = load_test_index(dir.path());
+        let entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "clean_orphan.yaml")
+            .collect();
+        assert_eq!(
+            entries.len(),
+            1,
+            "max_history=1 should trim to single entry after clean"
+        );
+        let snap_before = count_snapshot_files(dir.path());
+        assert_eq!(
+            snap_before, 1,
+            "Trim in clean deletes unreferenced snapshots; only v2 snapshot remains"
+        );
+
+        let out = run_ftm_with_port(port, &["clean"]);
+        assert!(out.status.success());
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(
+            stdout.contains("nothing to remove") || stdout.contains("Clean complete"),
+            "No orphans left for clean to remove: {}",
+            stdout
+        );
+
+        let snap_after = count_snapshot_files(dir.path());
+        assert_eq!(
+            snap_after, 1,
+            "One snapshot should remain after clean, got {}",
+            snap_after
+        );
+
+        let out = run_ftm_with_port(port, &["history", "clean_orphan.yaml"]);
+        assert!(out.status.success());
+        let entry = index
+            .history
+            .iter()
+            .find(|e| e.file == "clean_orphan.yaml")
+            .unwrap();
+        let checksum = entry.checksum.as_ref().unwrap();
+        let out = run_ftm_with_port(port, &["restore", "clean_orphan.yaml", &checksum[..8]]);
+        assert!(out.status.success());
+        let content = std::fs::read_to_string(dir.path().join("clean_orphan.yaml")).unwrap();
+        assert_eq!(content, "v2", "Restore should yield current version");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_clean_collapses_repeated_versions() {
+        let dir = setup_test_dir();
+        std::fs::write(dir.path().join("a.txt"), "v1").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(dir.path().join("a.txt"), "v2").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(wait_for_index(dir.path(), "a.txt", 2, 3000));
+        std::thread::sleep(std::time::Duration::from_millis(500));
+
+        // Append what racing writers could record: v2 twice more, v1, then v1 again
+        let index_path = dir.path().join(".ftm/index.json");
+        let mut index: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&index_path).unwrap()).unwrap();
+        let history = index["history"].as_array().unwrap().clone();
+        let v1 = history[history.len() - 2].clone();
+        let v2 = history[history.len() - 1].clone();
+        let mut next_seq = index["next_seq"].as_u64().unwrap();
+        for (mut entry, mtime) in [(v2.clone(), 11), (v2, 12), (v1.clone(), 13), (v1, 14)] {
+            entry["seq"] = next_seq.into();
+            entry["op"] = "modify".into();
+            entry["mtime_nanos"] = mtime.into();
+            index["history"].as_array_mut().unwrap().push(entry);
+            next_seq += 1;
+        }
+        index["next_seq"] = next_seq.into();
+        std::fs::write(&index_path, serde_json::to_string(&index).unwrap()).unwrap();
+
+        let out = run_ftm_with_port(port, &["clean"]);
+        assert!(out.status.success());
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(stdout.contains("Collapse: 3 repeated"), "{}", stdout);
+
+        let index = load_test_index(dir.path());
+        let versions: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "a.txt")
+            .map(|e| e.checksum.clone().unwrap())
+            .collect();
+        let hash = |s: &[u8]| blake3::hash(s).to_hex().to_string();
+        assert_eq!(versions, [hash(b"v1"), hash(b"v2"), hash(b"v1")]);
+        // The kept v1 takes the mtime of the last one dropped
+        let raw: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&index_path).unwrap()).unwrap();
+        let last = raw["history"].as_array().unwrap().last().unwrap().clone();
+        assert_eq!(last["mtime_nanos"], 14);
+
+        let out = run_ftm_with_port(port, &["clean"]);
+        assert!(!String::from_utf8_lossy(&out.stdout).contains("Collapse"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_clean_prunes_empty_shards_and_stale_tmp() {
+        let dir = setup_test_dir();
+        std::fs::write(dir.path().join("a.txt"), "v1").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let snapshots = dir.path().join(".ftm/snapshots");
+        std::fs::create_dir_all(snapshots.join("0").join("0")).unwrap();
+        std::fs::create_dir_all(snapshots.join(".tmp")).unwrap();
+        let stale = snapshots.join(".tmp").join("stale");
+        std::fs::write(&stale, "partial").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(7200))
+            .unwrap();
+
+        let out = run_ftm_with_port(port, &["clean"]);
+        assert!(out.status.success());
+        let stdout = String::from_utf8_lossy(&out.stdout).into_owned();
+        assert!(
+            stdout.contains("1 stale temp file(s), 2 empty directories removed"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(!stale.exists());
+        assert!(!snapshots.join("0").exists());
+        assert_eq!(count_snapshot_files(dir.path()), 1, "live snapshot is kept");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_checkout_removes_stale_tmp() {
+        let dir = setup_test_dir();
+        let tmp = dir.path().join(".ftm/snapshots/.tmp");
+        std::fs::create_dir_all(&tmp).unwrap();
+        let stale = tmp.join("crashed");
+        std::fs::write(&stale, "partial").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(7200))
+            .unwrap();
+        let fresh = tmp.join("recent");
+        std::fs::write(&fresh, "partial").unwrap();
+
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+        let start = std::time::Instant::now();
+        while stale.exists() && start.elapsed() < std::time::Duration::from_secs(5) {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        assert!(
+            !stale.exists(),
+            "stale temp file should be removed at checkout"
+        );
+        assert!(fresh.exists(), "recent temp file is left alone");
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_gc_removes_stale_tmp_and_empty_dirs() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_history(1).init();
+        std::fs::write(dir.path().join("gc.txt"), "v1").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(dir.path().join("gc.txt"), "v2").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let snapshots = dir.path().join(".ftm/snapshots");
+        let tmp = snapshots.join(".tmp");
+        std::fs::create_dir_all(&tmp).unwrap();
+        let stale = tmp.join("stale");
+        std::fs::write(&stale, "partial").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(7200))
+            .unwrap();
+        let fresh = tmp.join("fresh");
+        std::fs::write(&fresh, "in progress").unwrap();
+        let empty_shard = snapshots.join("f").join("e");
+        std::fs::create_dir_all(&empty_shard).unwrap();
+
+        let out = run_ftm_with_port(port, &["gc"]);
+        assert!(out.status.success());
+        let stdout = String::from_utf8_lossy(&out.stdout).into_owned();
+        assert!(
+            stdout.contains("1 history entries trimmed"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(
+            stdout.contains("1 stale temp file(s) removed"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(
+            stdout.contains("0 snapshot(s) permanently deleted"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(!stale.exists(), "stale temp file should be removed");
+        assert!(fresh.exists(), "recent temp file may still be written");
+        assert!(
+            !snapshots.join("f").exists(),
+            "empty shards should be pruned"
+        );
+        assert_eq!(count_snapshot_files(dir.path()), 1);
+
+        // --aggressive empties the trash holding the trimmed v1 snapshot
+        let out = run_ftm_with_port(port, &["gc", "--aggressive"]);
+        assert!(out.status.success());
+        let stdout = String::from_utf8_lossy(&out.stdout).into_owned();
+        assert!(
+            stdout.contains("Trash:    1 snapshot(s)"),
+            "stdout: {}",
+            stdout
+        );
+        let out = run_ftm_with_port(port, &["unclean"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("nothing to recover"));
+
+        stop_server(&mut server);
+    }
 
     #[test]
-    fn test_restore_version_not_found() {
+    fn test_periodic_clean_removes_orphans_after_interval() {
         let dir = setup_test_dir();
+        PreInitFtm::new(dir.path())
+            .max_history(1)
+            .clean_interval(2)
+            .init();
+
+        std::fs::write(dir.path().join("periodic_clean.yaml"), "v1").unwrap();
+
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let out = run_ftm_with_port(port, &["restore", "test.rs", "abc12345"]);
-        assert!(!out.status.success());
-        assert!(String::from_utf8_lossy(&out.stderr).contains("Version not found"));
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+        std::fs::write(dir.path().join("periodic_clean.yaml"), "v2").unwrap();
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(out.status.success());
+
+        let snap_before = count_snapshot_files(dir.path());
+        assert_eq!(
+            snap_before, 2,
+            "Before periodic clean: scan does not trim, so v1 and v2 snapshots both exist"
+        );
+
+        std::thread::sleep(std::time::Duration::from_secs(4));
+
+        let snap_after = count_snapshot_files(dir.path());
+        assert_eq!(
+            snap_after, 1,
+            "One snapshot should remain after periodic clean, got {}",
+            snap_after
+        );
 
         stop_server(&mut server);
     }
+}
+
+// ===========================================================================
+// Version tests
+// ===========================================================================
+
+mod version_tests {
+    use super::*;
 
     #[test]
-    fn test_restore_roundtrip() {
-        let dir = setup_test_dir();
-        let (mut server, port) = start_server_and_checkout(dir.path());
-        let file_path = dir.path().join("roundtrip.yaml");
+    fn test_version_without_server() {
+        // version should still print client version even when no server is running
+        let out = run_ftm_with_port(19999, &["version"]);
+        assert!(out.status.success());
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(s.contains("Client version:"));
+        assert!(s.contains("not running"));
+    }
 
-        let v1_content = "version: 1\ndata: original";
-        let v2_content = "version: 2\ndata: modified";
+    #[test]
+    fn test_version_with_server() {
+        let (mut server, port) = start_server();
 
-        // Write v1
-        std::fs::write(&file_path, v1_content).unwrap();
-        assert!(wait_for_index(dir.path(), "roundtrip.yaml", 1, 2000));
+        let out = run_ftm_with_port(port, &["version"]);
+        assert!(out.status.success());
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(s.contains("Client version:"));
+        assert!(s.contains("Server version:"));
 
-        // Write v2
-        std::fs::write(&file_path, v2_content).unwrap();
-        assert!(wait_for_index(dir.path(), "roundtrip.yaml", 2, 2000));
+        stop_server(&mut server);
+    }
+}
 
-        // Get v1's checksum from index
-        let index = load_test_index(dir.path());
-        let v1_entry = index
-            .history
-            .iter()
-            .find(|e| e.file == "roundtrip.yaml" && e.op == "create")
-            .expect("v1 create entry not found");
-        let v1_checksum = v1_entry.checksum.as_ref().unwrap();
+mod open_tests {
+    use super::*;
 
-        // Verify current content is v2
-        let current = std::fs::read_to_string(&file_path).unwrap();
-        assert_eq!(current, v2_content, "File should currently be v2");
+    #[cfg(unix)]
+    #[test]
+    fn test_open_prints_url_and_launches_browser() {
+        let (mut server, port) = start_server();
+        let open = |browser: &str| {
+            ftm_command()
+                .args(["--port", &port.to_string(), "open"])
+                .env("BROWSER", browser)
+                .output()
+                .expect("failed to run ftm")
+        };
 
-        // Restore v1 via server
-        let out = run_ftm_with_port(port, &["restore", "roundtrip.yaml", v1_checksum]);
+        let out = open("true");
+        assert!(out.status.success());
+        let url = format!("http://127.0.0.1:{}/", port);
+        assert!(String::from_utf8_lossy(&out.stdout).contains(&url));
+        assert!(out.stderr.is_empty(), "browser launched without warnings");
+
+        let out = open("false");
+        assert!(out.status.success(), "the printed URL is the fallback");
+        assert!(String::from_utf8_lossy(&out.stdout).contains(&url));
+        assert!(String::from_utf8_lossy(&out.stderr).contains("Could not launch a browser"));
+
+        stop_server(&mut server);
+        let out = run_ftm_with_port(port, &["open"]);
+        assert!(!out.status.success(), "no server to open");
+    }
+}
+
+// ===========================================================================
+// Config tests
+// ===========================================================================
+
+mod selftest_tests {
+    use super::*;
+
+    #[test]
+    fn test_selftest_passes_and_cleans_up() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["selftest"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
         assert!(
             out.status.success(),
-            "restore: {}",
+            "stdout: {}, stderr: {}",
+            stdout,
             String::from_utf8_lossy(&out.stderr)
         );
+        assert!(stdout.contains("create captured"), "stdout: {}", stdout);
+        assert!(stdout.contains("delete captured"), "stdout: {}", stdout);
+        assert!(stdout.contains("Self-test passed"), "stdout: {}", stdout);
+        let leftovers = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().starts_with("ftm-selftest-"))
+            .count();
+        assert_eq!(leftovers, 0);
 
-        // Verify content is back to v1
-        let restored = std::fs::read_to_string(&file_path).unwrap();
-        assert_eq!(
-            restored, v1_content,
-            "File content should be restored to v1"
-        );
+        // A probe the patterns would not track cannot test anything
+        let out = run_ftm_with_port(port, &["config", "set", "watch.patterns", "*.rs"]);
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["selftest"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("would not be tracked"));
 
         stop_server(&mut server);
     }
+}
+
+mod config_tests {
+    use super::*;
 
     #[test]
-    fn test_restore_with_short_checksum_prefix() {
+    fn test_max_depth_limits_scan_and_watch() {
         let dir = setup_test_dir();
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        std::fs::write(dir.path().join("top.txt"), "top").unwrap();
+        std::fs::write(dir.path().join("a/mid.txt"), "mid").unwrap();
+        std::fs::write(dir.path().join("a/b/deep.txt"), "deep").unwrap();
         let (mut server, port) = start_server_and_checkout(dir.path());
-        let file_path = dir.path().join("prefix.yaml");
-
-        let original = "data: for_prefix_test";
-
-        std::fs::write(&file_path, original).unwrap();
-        assert!(wait_for_index(dir.path(), "prefix.yaml", 1, 2000));
-
-        std::fs::write(&file_path, "data: modified version").unwrap();
-        assert!(wait_for_index(dir.path(), "prefix.yaml", 2, 2000));
 
+        let out = run_ftm_with_port(port, &["config", "set", "watch.max_depth", "1"]);
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("2 created"));
         let index = load_test_index(dir.path());
-        let entry = index
-            .history
-            .iter()
-            .find(|e| e.file == "prefix.yaml" && e.op == "create")
-            .unwrap();
-        let full_checksum = entry.checksum.as_ref().unwrap();
-        let short_prefix = &full_checksum[..8];
+        assert!(!index.history.iter().any(|e| e.file == "a/b/deep.txt"));
 
-        // Restore using only the first 8 chars of the checksum
-        let out = run_ftm_with_port(port, &["restore", "prefix.yaml", short_prefix]);
+        let out = run_ftm_in_dir(dir.path(), &["check-path", "a/b/deep.txt"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
         assert!(
-            out.status.success(),
-            "{}",
-            String::from_utf8_lossy(&out.stderr)
+            stdout.contains("not tracked (deeper than watch.max_depth (1))"),
+            "stdout: {}",
+            stdout
         );
 
-        let restored = std::fs::read_to_string(&file_path).unwrap();
-        assert_eq!(
-            restored, original,
-            "Restore with 8-char prefix should recover original content"
-        );
+        // The watcher sees changes down to the limit but not below it
+        std::fs::write(dir.path().join("a/b/deep2.txt"), "deep").unwrap();
+        std::fs::write(dir.path().join("a/new.txt"), "new").unwrap();
+        assert!(wait_for_index(dir.path(), "a/new.txt", 1, 5000));
+        let index = load_test_index(dir.path());
+        assert!(!index.history.iter().any(|e| e.file.starts_with("a/b/")));
+
+        // A directory created later is watched from the batch that saw it
+        std::fs::create_dir(dir.path().join("c")).unwrap();
+        std::fs::write(dir.path().join("c/first.txt"), "first").unwrap();
+        assert!(wait_for_index(dir.path(), "c/first.txt", 1, 5000));
+        std::fs::write(dir.path().join("c/second.txt"), "second").unwrap();
+        assert!(wait_for_index(dir.path(), "c/second.txt", 1, 5000));
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_restore_deleted_file() {
+    fn test_watch_roots_limit_scan_and_watch() {
         let dir = setup_test_dir();
+        for sub in ["src", "config", "big/nested"] {
+            std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+        std::fs::write(dir.path().join("top.txt"), "top").unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.path().join("config/app.yaml"), "a: 1").unwrap();
+        std::fs::write(dir.path().join("big/nested/data.txt"), "data").unwrap();
         let (mut server, port) = start_server_and_checkout(dir.path());
-        let file_path = dir.path().join("willdelete.yaml");
 
-        let content = "precious: data";
-        std::fs::write(&file_path, content).unwrap();
-        assert!(wait_for_index(dir.path(), "willdelete.yaml", 1, 2000));
+        for bad in ["/etc", "../elsewhere"] {
+            let out = run_ftm_with_port(port, &["config", "set", "watch.roots", bad]);
+            assert!(!out.status.success(), "{} is not a valid root", bad);
+        }
+        let out = run_ftm_with_port(port, &["config", "set", "watch.roots", "src/,config/"]);
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("2 created"));
 
-        // Delete the file and wait for the delete event
-        std::fs::remove_file(&file_path).unwrap();
-        assert!(!file_path.exists(), "File should be deleted");
+        let out = run_ftm_in_dir(dir.path(), &["check-path", "big/nested/data.txt"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
         assert!(
-            wait_for_index(dir.path(), "willdelete.yaml", 2, 2000),
-            "Delete event should be recorded"
+            stdout.contains("not tracked (outside watch.roots (src, config))"),
+            "stdout: {}",
+            stdout
         );
 
-        // Get the checksum from the create entry
+        // The watcher only sees the roots
+        std::fs::write(dir.path().join("big/nested/more.txt"), "more").unwrap();
+        std::fs::write(dir.path().join("src/lib.rs"), "pub fn f() {}").unwrap();
+        assert!(wait_for_index(dir.path(), "src/lib.rs", 1, 5000));
         let index = load_test_index(dir.path());
-        let entry = index
+        assert!(!index
             .history
             .iter()
-            .find(|e| e.file == "willdelete.yaml" && e.op == "create")
-            .unwrap();
-        let checksum = entry.checksum.as_ref().unwrap().clone();
+            .any(|e| e.file.starts_with("big/") || e.file == "top.txt"));
 
-        // Restore the deleted file via server (watcher will pick this up)
-        let out = run_ftm_with_port(port, &["restore", "willdelete.yaml", &checksum]);
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_retention_profile_sets_limits() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let get = |key: &str| {
+            let out = run_ftm_with_port(port, &["config", "get", key]);
+            assert!(out.status.success());
+            String::from_utf8_lossy(&out.stdout).trim().to_string()
+        };
+
+        let out = run_ftm_with_port(port, &["config", "set", "settings.profile", "hoarder"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("archival"));
+
+        let out = run_ftm_with_port(port, &["config", "set", "settings.profile", "archival"]);
+        assert!(out.status.success());
+        assert_eq!(get("settings.profile"), "archival");
+        assert_eq!(get("settings.max_history"), "100000");
+        assert_eq!(get("settings.max_quota"), (10u64 << 30).to_string());
+        assert_eq!(get("settings.trash_retention_days"), "30");
+        let yaml = std::fs::read_to_string(dir.path().join(".ftm/config.yaml")).unwrap();
+        assert!(yaml.contains("profile: archival"), "{}", yaml);
+
+        // Tuning one of the values by hand leaves the preset
+        let out = run_ftm_with_port(port, &["config", "set", "settings.max_history", "500"]);
+        assert!(out.status.success());
+        assert_eq!(get("settings.profile"), "");
+        assert_eq!(get("settings.max_quota"), (10u64 << 30).to_string());
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_include_only_mode_matches_full_paths() {
+        let dir = setup_test_dir();
+        std::fs::create_dir_all(dir.path().join("src/nested")).unwrap();
+        std::fs::create_dir_all(dir.path().join("secrets")).unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.path().join("src/nested/util.rs"), "// util").unwrap();
+        std::fs::write(dir.path().join("top.rs"), "// top").unwrap();
+        std::fs::write(dir.path().join("secrets/key.txt"), "secret").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["config", "set", "watch.mode", "everything"]);
+        assert!(!out.status.success());
+        for (key, value) in [
+            ("watch.mode", "include_only"),
+            ("watch.patterns", "src/**/*.rs"),
+        ] {
+            let out = run_ftm_with_port(port, &["config", "set", key, value]);
+            assert!(out.status.success());
+        }
+        let out = run_ftm_with_port(port, &["config", "get", "watch.mode"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("include_only"));
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("2 created"));
+        let index = load_test_index(dir.path());
+        let mut files: Vec<&str> = index.history.iter().map(|e| e.file.as_str()).collect();
+        files.sort();
+        assert_eq!(files, ["src/main.rs", "src/nested/util.rs"]);
+
+        let out = run_ftm_in_dir(dir.path(), &["check-path", "top.rs"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
         assert!(
-            out.status.success(),
-            "{}",
-            String::from_utf8_lossy(&out.stderr)
+            stdout.contains("not tracked (no watch pattern matches)"),
+            "stdout: {}",
+            stdout
         );
 
-        assert!(file_path.exists(), "File should be restored after deletion");
-        let restored = std::fs::read_to_string(&file_path).unwrap();
-        assert_eq!(restored, content, "Restored content should match original");
+        stop_server(&mut server);
+    }
 
-        // Wait for the watcher to record the restored file as a new create
+    #[test]
+    fn test_include_hidden() {
+        let dir = setup_test_dir();
+        std::fs::create_dir_all(dir.path().join(".config")).unwrap();
+        std::fs::write(dir.path().join("visible.txt"), "v").unwrap();
+        std::fs::write(dir.path().join(".hidden.txt"), "h").unwrap();
+        std::fs::write(dir.path().join(".config/app.toml"), "a = 1").unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["config", "set", "watch.include_hidden", "false"]);
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+        let out = run_ftm_in_dir(dir.path(), &["check-path", ".config/app.toml"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
         assert!(
-            wait_for_index(dir.path(), "willdelete.yaml", 3, 2000),
-            "Restored file should be recorded as a new create entry"
+            stdout.contains("directory '.config' is hidden and watch.include_hidden is false"),
+            "stdout: {}",
+            stdout
         );
+        let out = run_ftm_in_dir(dir.path(), &["check-path", ".hidden.txt"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(stdout.contains("not tracked (hidden"), "stdout: {}", stdout);
+
+        // Hidden files are tracked by default
+        let out = run_ftm_with_port(port, &["config", "set", "watch.include_hidden", "true"]);
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["scan"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("2 created"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_check_path_explains_decision() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_file_size(10).init();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::create_dir_all(dir.path().join("target/debug")).unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.path().join("src/small.rs"), "//").unwrap();
+        let (mut server, _port) = start_server_and_checkout(dir.path());
 
-        // Verify the full index: create -> delete -> create
-        let index_after = load_test_index(dir.path());
-        let entries: Vec<_> = index_after
-            .history
-            .iter()
-            .filter(|e| e.file == "willdelete.yaml")
-            .collect();
+        let check = |path: &str| {
+            let out = run_ftm_in_dir(&dir.path().join("src"), &["check-path", path]);
+            assert!(
+                out.status.success(),
+                "stderr: {}",
+                String::from_utf8_lossy(&out.stderr)
+            );
+            String::from_utf8_lossy(&out.stdout).trim().to_string()
+        };
         assert_eq!(
-            entries.len(),
-            3,
-            "Should have 3 entries: create, delete, create"
+            check("small.rs"),
+            "src/small.rs: tracked (matches watch pattern: *.rs)"
         );
-        assert_eq!(entries[0].op, "create", "First entry should be create");
-        assert_eq!(entries[1].op, "delete", "Second entry should be delete");
         assert_eq!(
-            entries[2].op, "create",
-            "Third entry (after restore) should be create"
+            check("../target/debug/gen.rs"),
+            "target/debug/gen.rs: not tracked (directory 'target' is excluded: **/target/**)"
         );
-
-        // The newest create entry checksum should match the original content
-        let last_entry = entries.last().unwrap();
-        assert_eq!(last_entry.op, "create", "Latest entry must be create");
-        use sha2::{Digest, Sha256};
-        let expected_checksum = hex::encode(Sha256::digest(content.as_bytes()));
         assert_eq!(
-            last_entry.checksum.as_ref().unwrap(),
-            &expected_checksum,
-            "Latest create entry checksum should match the original content hash"
+            check("photo.png"),
+            "src/photo.png: not tracked (no watch pattern matches)"
+        );
+        assert!(check("main.rs").contains("not tracked (larger than settings.max_file_size"));
+
+        let out = run_ftm_in_dir(dir.path(), &["check-path", "/elsewhere/file.rs"]);
+        assert!(
+            !out.status.success(),
+            "paths outside the checkout are rejected"
         );
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_restore_to_subdirectory() {
+    fn test_config_get_all() {
         let dir = setup_test_dir();
-        let sub_dir = dir.path().join("nested/dir");
-        std::fs::create_dir_all(&sub_dir).unwrap();
-
         let (mut server, port) = start_server_and_checkout(dir.path());
-        let file_path = sub_dir.join("deep.yaml");
 
-        let content = "nested: file content";
-        std::fs::write(&file_path, content).unwrap();
-        assert!(wait_for_index(dir.path(), "nested/dir/deep.yaml", 1, 2000));
-
-        // Get checksum
-        let index = load_test_index(dir.path());
-        let entry = index
-            .history
-            .iter()
-            .find(|e| e.file == "nested/dir/deep.yaml")
-            .unwrap();
-        let checksum = entry.checksum.as_ref().unwrap();
+        let out = run_ftm_with_port(port, &["config", "get"]);
+        assert!(out.status.success());
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(s.contains("max_history"));
+        assert!(s.contains("patterns"));
 
-        // Delete the entire subdirectory tree
-        std::fs::remove_dir_all(dir.path().join("nested")).unwrap();
-        assert!(!file_path.exists());
+        stop_server(&mut server);
+    }
 
-        // Restore should recreate parent directories automatically
-        let out = run_ftm_with_port(port, &["restore", "nested/dir/deep.yaml", checksum]);
-        assert!(
-            out.status.success(),
-            "{}",
-            String::from_utf8_lossy(&out.stderr)
-        );
+    #[test]
+    fn test_config_get_single_key() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        assert!(
-            file_path.exists(),
-            "File should be restored with parent dirs recreated"
-        );
-        let restored = std::fs::read_to_string(&file_path).unwrap();
-        assert_eq!(restored, content);
+        let out = run_ftm_with_port(port, &["config", "get", "settings.max_history"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("10000"));
 
         stop_server(&mut server);
     }
-}
 
-mod trim_tests {
-    use super::*;
+    #[test]
+    fn test_config_get_invalid_key() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["config", "get", "nonexistent.key"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("Unknown config key"));
+
+        stop_server(&mut server);
+    }
 
     #[test]
-    fn test_max_history_trims_old_entries() {
+    fn test_config_set_and_get() {
         let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // Pre-init .ftm with max_history=3
-        PreInitFtm::new(dir.path()).max_history(3).init();
+        // Set max_history to 200
+        let out = run_ftm_with_port(port, &["config", "set", "settings.max_history", "200"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("Set settings.max_history = 200"));
 
-        let (mut server, _port) = start_server_and_checkout(dir.path());
-        let file_path = dir.path().join("trimme.yaml");
+        // Verify it was changed
+        let out = run_ftm_with_port(port, &["config", "get", "settings.max_history"]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("200"));
 
-        // Write 5 different versions with delay between each
-        for i in 0..5 {
-            std::fs::write(&file_path, format!("version: {}", i)).unwrap();
-            std::thread::sleep(std::time::Duration::from_millis(50));
-        }
+        // Verify persisted to config.yaml
+        let config_content = std::fs::read_to_string(dir.path().join(".ftm/config.yaml")).unwrap();
+        assert!(config_content.contains("200"));
 
-        // Write a sync marker so we have 6 total entries and trigger trim to 3
-        std::fs::write(dir.path().join("sync.yaml"), "sync: done").unwrap();
-        assert!(
-            wait_for_index(dir.path(), "sync.yaml", 1, 5000),
-            "Sync marker should be recorded"
-        );
+        stop_server(&mut server);
+    }
 
-        let index = load_test_index(dir.path());
-        assert!(
-            index.history.len() <= 3,
-            "global max_history=3: expected at most 3 total entries, got {}",
-            index.history.len()
+    #[test]
+    fn test_config_set_invalid_value() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        // max_history expects a number
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.max_history", "not_a_number"],
         );
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("Invalid value"));
 
-        let entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "trimme.yaml")
-            .collect();
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_config_set_scan_interval_minimum_2() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["config", "set", "settings.scan_interval", "1"]);
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr);
         assert!(
-            entries.len() >= 1 && entries.len() <= 2,
-            "trimme.yaml should have 1 or 2 entries (sync may take one slot), got {}",
-            entries.len()
+            stderr.contains("scan_interval must be >= 2") || stderr.contains(">= 2"),
+            "expected scan_interval minimum 2 error, got: {}",
+            stderr
         );
 
-        use sha2::{Digest, Sha256};
-        let expected_checksums: Vec<String> = (3..5)
-            .map(|i| hex::encode(Sha256::digest(format!("version: {}", i).as_bytes())))
-            .collect();
-        let expected = if entries.len() == 2 {
-            &expected_checksums[..]
-        } else {
-            &expected_checksums[1..]
-        };
-        for (entry, expected_cs) in entries.iter().zip(expected.iter()) {
-            let cs = entry.checksum.as_ref().expect("entry should have checksum");
-            assert_eq!(
-                cs, expected_cs,
-                "Trimmed entries for trimme should be the newest versions (v3, v4) in order"
-            );
-        }
+        let out = run_ftm_with_port(port, &["config", "set", "settings.scan_interval", "2"]);
+        assert!(out.status.success());
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_max_quota_trims_by_volume() {
+    fn test_config_set_watch_patterns() {
         let dir = setup_test_dir();
-        let max_quota = 150 * 1024; // 150KB
-        PreInitFtm::new(dir.path())
-            .max_history(1000)
-            .max_quota(max_quota)
-            .init();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let out = run_ftm_with_port(port, &["config", "set", "watch.patterns", "*.rs,*.go,*.py"]);
+        assert!(out.status.success());
+
+        let out = run_ftm_with_port(port, &["config", "get", "watch.patterns"]);
+        assert!(out.status.success());
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(s.contains("*.rs"));
+        assert!(s.contains("*.go"));
+        assert!(s.contains("*.py"));
+
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_config_not_checked_out() {
+        let (mut server, port) = start_server();
+
+        let out = run_ftm_with_port(port, &["config", "get"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
+
+        stop_server(&mut server);
+    }
 
+    #[test]
+    fn test_config_log_lists_changes() {
+        let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
-        let file_path = dir.path().join("bigfile.yaml");
 
-        // Write 5 versions, each ~50KB, so total ~250KB > 150KB quota
-        let chunk: String = "x".repeat(1024);
-        for i in 0..5 {
-            let content = format!("version: {}\n{}", i, chunk.repeat(50));
-            std::fs::write(&file_path, content).unwrap();
-            std::thread::sleep(std::time::Duration::from_millis(50));
-        }
+        let out = run_ftm_with_port(port, &["config", "log"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("No recorded config changes"));
 
-        let out = run_ftm_with_port(port, &["scan"]);
-        assert!(out.status.success(), "scan should succeed");
+        let out = run_ftm_with_port(port, &["config", "set", "watch.patterns", "*.rs,*.go"]);
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["config", "set", "watch.patterns", "*.rs"]);
+        assert!(out.status.success());
+        let out = run_ftm_with_port(port, &["config", "set", "settings.max_history", "50"]);
+        assert!(out.status.success());
+
+        let out = run_ftm_with_port(port, &["config", "log", "watch.patterns"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success());
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines.len(), 2, "stdout: {}", stdout);
+        assert!(lines[0].ends_with("-> *.rs,*.go"), "stdout: {}", stdout);
         assert!(
-            wait_for_index(dir.path(), "bigfile.yaml", 1, 5000),
-            "bigfile.yaml should have at least one entry"
+            lines[1].ends_with("*.rs,*.go -> *.rs"),
+            "stdout: {}",
+            stdout
         );
 
-        let out = run_ftm_with_port(port, &["clean"]);
-        assert!(out.status.success(), "clean should succeed");
+        let out = run_ftm_with_port(port, &["config", "log"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert_eq!(stdout.lines().count(), 3);
+        assert!(stdout.contains("settings.max_history | 10000 -> 50"));
 
-        let index = load_test_index(dir.path());
-        let volume = referenced_snapshot_volume(dir.path(), &index);
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_config_json_validates_every_field() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let url = format!("http://127.0.0.1:{}/api/config/json", port);
+        let get = || -> serde_json::Value { client.get(&url).send().unwrap().json().unwrap() };
+
+        let mut config = get();
+        assert_eq!(config["settings"]["max_history"], 10000);
+        assert_eq!(config["settings"]["snapshot_trigger"], "debounced_modify");
+        assert!(config["watch"]["patterns"].as_array().unwrap().len() > 1);
+
+        // One invalid field rejects the whole update
+        config["settings"]["max_history"] = 50.into();
+        config["settings"]["scan_interval"] = 1.into();
+        config["watch"]["roots"] = serde_json::json!(["../outside"]);
+        let resp = client.put(&url).json(&config).send().unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = resp.json().unwrap();
+        let errors = body["errors"].as_object().unwrap();
+        assert_eq!(errors.len(), 2, "{}", body);
+        assert!(errors["settings.scan_interval"]
+            .as_str()
+            .unwrap()
+            .contains(">= 2"));
+        assert!(errors.contains_key("watch.roots"));
+        assert_eq!(get()["settings"]["max_history"], 10000);
+
+        // Wrong types are refused too, naming the field
+        let mut wrong = config.clone();
+        wrong["settings"]["max_quota"] = "lots".into();
+        let resp = client.put(&url).json(&wrong).send().unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        assert!(resp.text().unwrap().contains("settings.max_quota"));
+
+        // Lists keep patterns holding commas intact
+        config["settings"]["scan_interval"] = 60.into();
+        config["watch"]["roots"] = serde_json::json!([]);
+        config["watch"]["patterns"] = serde_json::json!(["*.{rs,toml}", "*.md"]);
+        let resp = client.put(&url).json(&config).send().unwrap();
+        assert!(resp.status().is_success());
+        let saved: serde_json::Value = resp.json().unwrap();
+        assert_eq!(saved["settings"]["max_history"], 50);
+        assert_eq!(saved, get());
+        assert_eq!(saved["watch"]["patterns"][0], "*.{rs,toml}");
+
+        let yaml = std::fs::read_to_string(dir.path().join(".ftm/config.yaml")).unwrap();
+        assert!(yaml.contains("max_history: 50"), "{}", yaml);
+        assert!(!dir.path().join(".ftm/config.yaml.tmp").exists());
+        let out = run_ftm_with_port(port, &["config", "log"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
         assert!(
-            volume <= max_quota,
-            "referenced snapshot volume {} should be <= max_quota {}",
-            volume,
-            max_quota
+            stdout.contains("settings.max_history | 10000 -> 50"),
+            "{}",
+            stdout
         );
-
-        let snapshot_count = count_snapshot_files(dir.path());
         assert!(
-            snapshot_count < 5,
-            "oldest snapshots should be removed from disk, got {} files",
-            snapshot_count
+            stdout.contains("settings.scan_interval | 300 -> 60"),
+            "{}",
+            stdout
         );
+        assert_eq!(stdout.lines().count(), 3, "{}", stdout);
 
         stop_server(&mut server);
     }
 }
 
-mod scan_tests {
-    use super::*;
-
-    #[test]
-    fn test_scan_not_checked_out() {
-        let (mut server, port) = start_server();
-
-        let out = run_ftm_with_port(port, &["scan"]);
-        assert!(!out.status.success());
-        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
+// ===========================================================================
+// Config hot-reload tests
+// ===========================================================================
 
-        stop_server(&mut server);
-    }
+mod config_hot_reload_tests {
+    use super::*;
 
     #[test]
-    fn test_scan_detects_new_files() {
+    fn test_hand_edited_config_is_reloaded() {
         let dir = setup_test_dir();
-
-        // Create files BEFORE checkout (watcher won't see them)
-        std::fs::write(dir.path().join("hello.rs"), "fn main() {}").unwrap();
-        std::fs::write(dir.path().join("world.py"), "print('hi')").unwrap();
-
         let (mut server, port) = start_server_and_checkout(dir.path());
+        let config_path = dir.path().join(".ftm/config.yaml");
+        let get = |key: &str| {
+            let out = run_ftm_with_port(port, &["config", "get", key]);
+            String::from_utf8_lossy(&out.stdout).trim().to_string()
+        };
+        let wait_for = |key: &str, value: &str| {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            while get(key) != value && std::time::Instant::now() < deadline {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+            }
+            get(key) == value
+        };
 
-        let out = run_ftm_with_port(port, &["scan"]);
-        assert!(out.status.success());
-        let s = String::from_utf8_lossy(&out.stdout);
-        assert!(s.contains("2 created"));
-        assert!(s.contains("0 modified"));
-        assert!(s.contains("0 deleted"));
+        let yaml = std::fs::read_to_string(&config_path).unwrap();
+        std::fs::write(
+            &config_path,
+            yaml.replace("max_history: 10000", "max_history: 42"),
+        )
+        .unwrap();
+        assert!(wait_for("settings.max_history", "42"));
+        let out = run_ftm_with_port(port, &["config", "log"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(
+            stdout.contains("settings.max_history | 10000 -> 42"),
+            "{}",
+            stdout
+        );
 
-        let index = load_test_index(dir.path());
-        let entries: Vec<_> = index.history.iter().collect();
-        assert_eq!(entries.len(), 2, "Should have 2 entries after scan");
-        assert!(entries.iter().all(|e| e.op == "create"));
-        assert!(entries.iter().any(|e| e.file == "hello.rs"));
-        assert!(entries.iter().any(|e| e.file == "world.py"));
+        // An invalid value keeps the whole edit out
+        let yaml = std::fs::read_to_string(&config_path).unwrap();
+        let edited = yaml
+            .replace("max_history: 42", "max_history: 7")
+            .replace("quota_warn_percent: 90", "quota_warn_percent: 150");
+        assert_ne!(edited, yaml);
+        std::fs::write(&config_path, edited).unwrap();
+        assert!(!wait_for("settings.max_history", "7"));
+        assert_eq!(get("settings.quota_warn_percent"), "90");
+
+        // Fixing it applies the rest
+        let yaml = std::fs::read_to_string(&config_path).unwrap();
+        std::fs::write(
+            &config_path,
+            yaml.replace("quota_warn_percent: 150", "quota_warn_percent: 80"),
+        )
+        .unwrap();
+        assert!(wait_for("settings.quota_warn_percent", "80"));
+        assert_eq!(get("settings.max_history"), "7");
 
         stop_server(&mut server);
     }
 
+    /// After `config set watch.patterns`, the watcher should immediately use
+    /// the new patterns — newly added extensions get tracked.
     #[test]
-    fn test_scan_detects_modifications() {
+    fn test_config_set_patterns_adds_new_extension_to_watcher() {
         let dir = setup_test_dir();
-
-        // Create baseline file BEFORE checkout
-        std::fs::write(dir.path().join("app.rs"), "fn main() {}").unwrap();
-
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // First scan: creates baseline
-        let out = run_ftm_with_port(port, &["scan"]);
+        // Default patterns do NOT include *.go
+        // Add *.go via config set
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "watch.patterns", "*.rs,*.go,*.yaml"],
+        );
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
 
-        // Modify the file (watcher will also detect this, but we verify final state)
-        std::fs::write(dir.path().join("app.rs"), "fn main() { println!(\"hi\"); }").unwrap();
+        // Write a .go file — should now be tracked
+        std::fs::write(dir.path().join("main.go"), "package main").unwrap();
 
-        // Wait for either watcher or scan to pick up the change
         assert!(
-            wait_for_index(dir.path(), "app.rs", 2, 2000),
-            "Modification should be recorded"
+            wait_for_index(dir.path(), "main.go", 1, 3000),
+            "After adding *.go to patterns, .go files should be tracked by the watcher"
         );
 
-        let index = load_test_index(dir.path());
-        let entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "app.rs")
-            .collect();
-        assert_eq!(entries.len(), 2, "Should have create + modify");
-        assert_eq!(entries[0].op, "create");
-        assert_eq!(entries[1].op, "modify");
-
         stop_server(&mut server);
     }
 
+    /// After `config set watch.patterns` to remove an extension, the watcher
+    /// should stop tracking files with that extension.
     #[test]
-    fn test_scan_detects_deletions() {
+    fn test_config_set_patterns_removes_extension_from_watcher() {
         let dir = setup_test_dir();
-
-        // Create file BEFORE checkout
-        std::fs::write(dir.path().join("temp.txt"), "temporary content").unwrap();
-
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // Scan to create baseline
-        let out = run_ftm_with_port(port, &["scan"]);
+        // Verify .yaml is tracked by default
+        std::fs::write(dir.path().join("before.yaml"), "before: change").unwrap();
+        assert!(
+            wait_for_index(dir.path(), "before.yaml", 1, 2000),
+            "before.yaml should be tracked with default patterns"
+        );
+
+        // Remove *.yaml from patterns (keep only *.rs)
+        let out = run_ftm_with_port(port, &["config", "set", "watch.patterns", "*.rs"]);
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
 
-        // Delete the file (watcher will also detect this)
-        std::fs::remove_file(dir.path().join("temp.txt")).unwrap();
+        // Write a .yaml file — should NOT be tracked anymore
+        std::fs::write(dir.path().join("after.yaml"), "after: change").unwrap();
 
-        // Wait for deletion to be recorded
+        // Write a .rs file as sync marker — should be tracked
+        std::fs::write(dir.path().join("sync.rs"), "fn sync() {}").unwrap();
         assert!(
-            wait_for_index(dir.path(), "temp.txt", 2, 2000),
-            "Deletion should be recorded"
+            wait_for_index(dir.path(), "sync.rs", 1, 2000),
+            "sync.rs should be tracked (proves watcher is still running)"
         );
 
         let index = load_test_index(dir.path());
-        let entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "temp.txt")
-            .collect();
-        assert_eq!(entries.len(), 2, "Should have create + delete");
-        assert_eq!(entries[0].op, "create");
-        assert_eq!(entries[1].op, "delete");
+        assert!(
+            !index.history.iter().any(|e| e.file == "after.yaml"),
+            "after.yaml should NOT be tracked after removing *.yaml from patterns"
+        );
 
         stop_server(&mut server);
     }
 
+    /// After `config set watch.patterns`, manual scan should use the new patterns.
     #[test]
-    fn test_scan_no_changes_second_run() {
+    fn test_config_set_patterns_applied_to_manual_scan() {
         let dir = setup_test_dir();
 
-        // Create file BEFORE checkout
-        std::fs::write(dir.path().join("stable.md"), "# Stable").unwrap();
+        // Create a .go file BEFORE checkout (watcher won't see it)
+        std::fs::write(dir.path().join("lib.go"), "package lib").unwrap();
 
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // First scan
+        // Default scan should NOT pick up .go files
         let out = run_ftm_with_port(port, &["scan"]);
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+        assert!(String::from_utf8_lossy(&out.stdout).contains("0 created"));
 
-        // Second scan - nothing changed
-        let out = run_ftm_with_port(port, &["scan"]);
+        // Add *.go to patterns
+        let out = run_ftm_with_port(port, &["config", "set", "watch.patterns", "*.rs,*.go"]);
         assert!(out.status.success());
-        let s = String::from_utf8_lossy(&out.stdout);
-        assert!(s.contains("0 created"));
-        assert!(s.contains("0 modified"));
-        assert!(s.contains("0 deleted"));
-        assert!(s.contains("1 unchanged"));
-
-        // Index should still only have 1 entry
-        let index = load_test_index(dir.path());
-        let count = index
-            .history
-            .iter()
-            .filter(|e| e.file == "stable.md")
-            .count();
-        assert_eq!(count, 1, "No new entries should be added on unchanged scan");
-
-        stop_server(&mut server);
-    }
-
-    #[test]
-    fn test_scan_ignores_non_matching_patterns() {
-        let dir = setup_test_dir();
-
-        // Create files BEFORE checkout
-        std::fs::write(dir.path().join("image.png"), "not tracked").unwrap();
-        std::fs::write(dir.path().join("binary.exe"), "not tracked").unwrap();
-        std::fs::write(dir.path().join("code.rs"), "fn test() {}").unwrap();
-
-        let (mut server, port) = start_server_and_checkout(dir.path());
 
+        // Scan again — should now find the .go file
         let out = run_ftm_with_port(port, &["scan"]);
         assert!(out.status.success());
         assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
 
         let index = load_test_index(dir.path());
-        assert_eq!(
-            index.history.len(),
-            1,
-            "Only matching file should be tracked"
+        assert!(
+            index.history.iter().any(|e| e.file == "lib.go"),
+            "lib.go should appear in history after pattern change + scan"
         );
-        assert_eq!(index.history[0].file, "code.rs");
 
         stop_server(&mut server);
     }
 
+    /// After `config set settings.scan_interval` to a shorter value,
+    /// the new interval takes effect immediately (within ~1s).
     #[test]
-    fn test_scan_skips_large_files() {
+    fn test_config_set_scan_interval_enables_periodic_scan() {
         let dir = setup_test_dir();
 
-        // Pre-init .ftm with max_file_size=100
-        PreInitFtm::new(dir.path()).max_file_size(100).init();
+        std::fs::write(
+            dir.path().join("pre_existing.txt"),
+            "created before checkout",
+        )
+        .unwrap();
 
-        // Create files BEFORE checkout
-        std::fs::write(dir.path().join("small.txt"), "tiny").unwrap();
-        std::fs::write(dir.path().join("large.txt"), "x".repeat(200)).unwrap();
+        // Pre-init with 8s interval; no scan in 1s
+        PreInitFtm::new(dir.path()).scan_interval(8).init();
 
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let out = run_ftm_with_port(port, &["scan"]);
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let index = load_test_index(dir.path());
+        assert!(
+            !index.history.iter().any(|e| e.file == "pre_existing.txt"),
+            "With 8s scan_interval, file should not be scanned in 1s"
+        );
+
+        // Shorten to 2s; takes effect on next tick (~1s), then 2s wait, then scan
+        let out = run_ftm_with_port(port, &["config", "set", "settings.scan_interval", "2"]);
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
 
-        let index = load_test_index(dir.path());
-        assert_eq!(index.history.len(), 1);
-        assert_eq!(index.history[0].file, "small.txt");
+        let found = wait_for_index(dir.path(), "pre_existing.txt", 1, 5000);
+        assert!(
+            found,
+            "After setting scan_interval=2, periodic scanner should pick up pre_existing.txt"
+        );
 
         stop_server(&mut server);
     }
 
+    /// After `config set settings.max_file_size`, scan should respect the new limit.
     #[test]
-    fn test_scan_subdirectories() {
+    fn test_config_set_max_file_size_applied_to_scan() {
         let dir = setup_test_dir();
 
-        // Create files in subdirectories BEFORE checkout
-        let sub_dir = dir.path().join("src/lib");
-        std::fs::create_dir_all(&sub_dir).unwrap();
-        std::fs::write(sub_dir.join("mod.rs"), "pub mod lib;").unwrap();
-        std::fs::write(dir.path().join("main.rs"), "fn main() {}").unwrap();
+        // Create a 200-byte file BEFORE checkout
+        std::fs::write(dir.path().join("medium.txt"), "x".repeat(200)).unwrap();
+
+        // Pre-init with max_file_size=100 — file will be skipped
+        PreInitFtm::new(dir.path()).max_file_size(100).init();
 
         let (mut server, port) = start_server_and_checkout(dir.path());
 
+        // Scan — file exceeds 100 bytes, should be skipped
         let out = run_ftm_with_port(port, &["scan"]);
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("2 created"));
-
-        let index = load_test_index(dir.path());
-        assert!(index.history.iter().any(|e| e.file == "src/lib/mod.rs"));
-        assert!(index.history.iter().any(|e| e.file == "main.rs"));
-
-        stop_server(&mut server);
-    }
-
-    #[test]
-    fn test_scan_skips_excluded_directories() {
-        let dir = setup_test_dir();
-
-        // Create files in excluded directories BEFORE checkout
-        let target_dir = dir.path().join("target/debug");
-        std::fs::create_dir_all(&target_dir).unwrap();
-        std::fs::write(target_dir.join("build.rs"), "// build artifact").unwrap();
-
-        let node_dir = dir.path().join("node_modules/pkg");
-        std::fs::create_dir_all(&node_dir).unwrap();
-        std::fs::write(node_dir.join("index.js"), "module.exports = {}").unwrap();
-
-        // Normal tracked file
-        std::fs::write(dir.path().join("app.rs"), "fn main() {}").unwrap();
+        assert!(String::from_utf8_lossy(&out.stdout).contains("0 created"));
 
-        let (mut server, port) = start_server_and_checkout(dir.path());
+        // Raise max_file_size to 1000
+        let out = run_ftm_with_port(port, &["config", "set", "settings.max_file_size", "1000"]);
+        assert!(out.status.success());
 
+        // Scan again — file should now be picked up
         let out = run_ftm_with_port(port, &["scan"]);
         assert!(out.status.success());
         assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
 
         let index = load_test_index(dir.path());
-        assert_eq!(index.history.len(), 1);
-        assert_eq!(index.history[0].file, "app.rs");
+        assert!(
+            index.history.iter().any(|e| e.file == "medium.txt"),
+            "medium.txt should be tracked after raising max_file_size"
+        );
 
         stop_server(&mut server);
     }
 
+    /// After `config set watch.exclude`, the watcher should respect the new
+    /// exclude patterns.
     #[test]
-    fn test_scan_empty_files_ignored() {
+    fn test_config_set_exclude_applied_to_watcher() {
         let dir = setup_test_dir();
-
-        // Create files BEFORE checkout
-        std::fs::write(dir.path().join("empty.rs"), "").unwrap();
-        std::fs::write(dir.path().join("notempty.rs"), "fn x() {}").unwrap();
+        let custom_dir = dir.path().join("custom");
+        std::fs::create_dir_all(&custom_dir).unwrap();
 
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let out = run_ftm_with_port(port, &["scan"]);
+        // Verify files in custom/ ARE tracked before exclude change
+        std::fs::write(custom_dir.join("before.yaml"), "tracked: yes").unwrap();
+        assert!(
+            wait_for_index(dir.path(), "custom/before.yaml", 1, 2000),
+            "custom/before.yaml should be tracked before exclude change"
+        );
+
+        // Add **/custom/** to exclude patterns
+        let out = run_ftm_with_port(
+            port,
+            &[
+                "config",
+                "set",
+                "watch.exclude",
+                "**/target/**,**/node_modules/**,**/.git/**,**/.ftm/**,**/custom/**",
+            ],
+        );
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+
+        // Write a new file in custom/ — should NOT be tracked
+        std::fs::write(custom_dir.join("after.yaml"), "tracked: no").unwrap();
+
+        // Write a sync marker in root — should be tracked
+        std::fs::write(dir.path().join("sync.yaml"), "sync: yes").unwrap();
+        assert!(
+            wait_for_index(dir.path(), "sync.yaml", 1, 2000),
+            "sync.yaml should be tracked (proves watcher still running)"
+        );
 
         let index = load_test_index(dir.path());
-        assert_eq!(index.history.len(), 1);
-        assert_eq!(index.history[0].file, "notempty.rs");
+        assert!(
+            !index.history.iter().any(|e| e.file == "custom/after.yaml"),
+            "custom/after.yaml should NOT be tracked after adding **/custom/** to exclude"
+        );
 
         stop_server(&mut server);
     }
+}
 
-    #[test]
-    fn test_scan_dedup_same_content() {
-        let dir = setup_test_dir();
+// ===========================================================================
+// Read-only listener tests
+// ===========================================================================
 
-        // Create files BEFORE checkout
-        let content = "shared: content";
-        std::fs::write(dir.path().join("a.yaml"), content).unwrap();
-        std::fs::write(dir.path().join("b.yaml"), content).unwrap();
+mod read_only_listener_tests {
+    use super::*;
 
+    #[test]
+    fn test_ui_listen_serves_read_only_api() {
+        let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
+        std::fs::write(dir.path().join("a.txt"), "hello").unwrap();
+        assert!(wait_for_index(dir.path(), "a.txt", 1, 5000));
 
-        let out = run_ftm_with_port(port, &["scan"]);
+        let ui_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addr = format!("127.0.0.1:{}", ui_port);
+        let out = run_ftm_with_port(port, &["config", "set", "settings.ui_listen", &addr]);
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("2 created"));
 
-        // Both entries should share the same snapshot
-        let snap_count = count_snapshot_files(dir.path());
-        assert_eq!(
-            snap_count, 1,
-            "Two files with same content should share 1 snapshot"
-        );
+        let client = http();
+        let url = |path: &str| format!("http://{}{}", addr, path);
+        let mut history = None;
+        for _ in 0..50 {
+            if let Ok(resp) = client.get(url("/api/history?file=a.txt")).send() {
+                history = Some(resp);
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        let history: serde_json::Value = history.expect("listener up").json().unwrap();
+        assert_eq!(history.as_array().unwrap().len(), 1);
 
-        let index = load_test_index(dir.path());
-        let checksums: Vec<_> = index
-            .history
-            .iter()
-            .filter_map(|e| e.checksum.as_ref())
-            .collect();
-        assert_eq!(checksums.len(), 2);
-        assert_eq!(checksums[0], checksums[1], "Checksums should match");
+        for path in ["/api/logs", "/api/index", "/api/signatures"] {
+            let resp = client.get(url(path)).send().unwrap();
+            assert_eq!(resp.status(), 403, "{}", path);
+        }
+        let resp = client.post(url("/api/scan")).send().unwrap();
+        assert_eq!(resp.status(), 403);
+        let resp = client.post(url("/api/shutdown")).send().unwrap();
+        assert_eq!(resp.status(), 403);
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let out = run_ftm_with_port(port, &["config", "set", "settings.ui_listen", "bogus"]);
+        assert!(!out.status.success());
+        let out = run_ftm_with_port(port, &["config", "set", "settings.ui_listen", ""]);
+        assert!(out.status.success());
+        let mut closed = false;
+        for _ in 0..50 {
+            if client.get(url("/api/health")).send().is_err() {
+                closed = true;
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        assert!(closed, "listener stopped");
 
         stop_server(&mut server);
     }
 }
 
-mod clean_tests {
+// ===========================================================================
+// Rate limit tests
+// ===========================================================================
+
+mod rate_limit_tests {
     use super::*;
 
+    /// Past `settings.api_rate_limit` requests per second a client gets 429 with
+    /// Retry-After, on share links as on the API; shutdown is never limited.
     #[test]
-    fn test_clean_not_checked_out() {
-        let (mut server, port) = start_server();
+    fn test_api_rate_limit_rejects_bursts() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let url = |path: &str| format!("http://127.0.0.1:{}{}", port, path);
+        let client = http();
+        for _ in 0..20 {
+            assert_eq!(client.get(url("/api/health")).send().unwrap().status(), 200);
+        }
 
-        let out = run_ftm_with_port(port, &["clean"]);
-        assert!(!out.status.success());
-        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
+        let out = run_ftm_with_port(port, &["config", "set", "settings.api_rate_limit", "5"]);
+        assert!(out.status.success());
+        let burst = |url: &str| {
+            (0..20)
+                .map(|_| client.get(url).send().unwrap())
+                .find(|r| r.status() == 429)
+        };
+        let limited =
+            burst(&url("/share/not-a-token")).expect("burst of share requests was limited");
+        assert!(limited.headers().contains_key("retry-after"));
 
-        stop_server(&mut server);
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert_eq!(client.get(url("/api/health")).send().unwrap().status(), 200);
+        assert!(
+            burst(&url("/api/health")).is_some(),
+            "burst of API requests was limited"
+        );
+        assert!(client
+            .post(url("/api/shutdown"))
+            .send()
+            .unwrap()
+            .status()
+            .is_success());
+        assert!(wait_for_server_exit(
+            &mut server,
+            std::time::Duration::from_secs(5)
+        ));
     }
+}
 
-    #[test]
-    fn test_clean_removes_orphan_snapshots() {
-        let dir = setup_test_dir();
-        PreInitFtm::new(dir.path()).max_history(1).init();
+// ===========================================================================
+// Logs tests
+// ===========================================================================
 
-        std::fs::write(dir.path().join("clean_orphan.yaml"), "v1").unwrap();
+mod logs_tests {
+    use super::*;
 
+    #[test]
+    fn test_logs_no_log_files() {
+        let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let out = run_ftm_with_port(port, &["scan"]);
+        // The server auto-creates a log file on startup, so "logs" should
+        // find it and print "Opening: ..." instead of "No log files".
+        let out = run_ftm_with_port(port, &["logs"]);
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+        assert!(String::from_utf8_lossy(&out.stdout).contains("Opening:"));
 
-        std::fs::write(dir.path().join("clean_orphan.yaml"), "v2").unwrap();
-        let out = run_ftm_with_port(port, &["scan"]);
-        assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("1 modified"));
+        stop_server(&mut server);
+    }
 
-        let out = run_ftm_with_port(port, &["clean"]);
-        assert!(out.status.success());
+    #[test]
+    fn test_logs_with_log_file() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let index = load_test_index(dir.path());
-        let entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "clean_orphan.yaml")
-            .collect();
-        assert_eq!(
-            entries.len(),
-            1,
-            "max_history=1 should trim to single entry after clean"
-        );
-        let snap_before = count_snapshot_files(dir.path());
-        assert_eq!(
-            snap_before, 1,
-            "Trim in clean deletes unreferenced snapshots; only v2 snapshot remains"
-        );
+        // Create a log file with a far-future timestamp so it is picked as
+        // the newest (the server auto-creates its own log on startup).
+        let log_dir = dir.path().join(".ftm/logs");
+        std::fs::create_dir_all(&log_dir).unwrap();
+        std::fs::write(
+            log_dir.join("30000101-120000.log"),
+            "INFO test log line 1\nINFO test log line 2\n",
+        )
+        .unwrap();
 
-        let out = run_ftm_with_port(port, &["clean"]);
+        // logs command should find the file and try less, then fallback to print
+        let out = run_ftm_with_port(port, &["logs"]);
         assert!(out.status.success());
-        let stdout = String::from_utf8_lossy(&out.stdout);
-        assert!(
-            stdout.contains("nothing to remove") || stdout.contains("Clean complete"),
-            "No orphans left for clean to remove: {}",
-            stdout
-        );
+        assert!(String::from_utf8_lossy(&out.stdout).contains("30000101-120000.log"));
 
-        let snap_after = count_snapshot_files(dir.path());
-        assert_eq!(
-            snap_after, 1,
-            "One snapshot should remain after clean, got {}",
-            snap_after
-        );
+        stop_server(&mut server);
+    }
 
-        let out = run_ftm_with_port(port, &["history", "clean_orphan.yaml"]);
-        assert!(out.status.success());
-        let entry = index
-            .history
-            .iter()
-            .find(|e| e.file == "clean_orphan.yaml")
-            .unwrap();
-        let checksum = entry.checksum.as_ref().unwrap();
-        let out = run_ftm_with_port(port, &["restore", "clean_orphan.yaml", &checksum[..8]]);
+    #[test]
+    fn test_logs_picks_latest_file() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        // Create multiple log files with far-future timestamps so both are
+        // newer than the server's auto-created log file.
+        let log_dir = dir.path().join(".ftm/logs");
+        std::fs::create_dir_all(&log_dir).unwrap();
+        std::fs::write(log_dir.join("30000101-100000.log"), "old log\n").unwrap();
+        std::fs::write(log_dir.join("30000201-150000.log"), "new log\n").unwrap();
+
+        // Should pick the newest one (30000201-150000.log)
+        let out = run_ftm_with_port(port, &["logs"]);
         assert!(out.status.success());
-        let content = std::fs::read_to_string(dir.path().join("clean_orphan.yaml")).unwrap();
-        assert_eq!(content, "v2", "Restore should yield current version");
+        assert!(String::from_utf8_lossy(&out.stdout).contains("30000201-150000.log"));
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_periodic_clean_removes_orphans_after_interval() {
-        let dir = setup_test_dir();
-        PreInitFtm::new(dir.path())
-            .max_history(1)
-            .clean_interval(2)
-            .init();
+    fn test_logs_not_checked_out() {
+        let (mut server, port) = start_server();
 
-        std::fs::write(dir.path().join("periodic_clean.yaml"), "v1").unwrap();
+        let out = run_ftm_with_port(port, &["logs"]);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
 
-        let (mut server, port) = start_server_and_checkout(dir.path());
+        stop_server(&mut server);
+    }
 
-        let out = run_ftm_with_port(port, &["scan"]);
-        assert!(out.status.success());
-        std::fs::write(dir.path().join("periodic_clean.yaml"), "v2").unwrap();
-        let out = run_ftm_with_port(port, &["scan"]);
-        assert!(out.status.success());
+    /// Pruning: when server starts with file logging, only the 100 most recent log files are kept.
+    #[test]
+    fn test_logs_prune_keeps_only_100() {
+        const KEEP: usize = 100;
+        let total_before = 105;
 
-        let snap_before = count_snapshot_files(dir.path());
+        let dir = setup_test_dir();
+        let log_dir = dir.path().join(".ftm/logs");
+        std::fs::create_dir_all(&log_dir).unwrap();
+
+        for i in 0..total_before {
+            let name = format!("20000101-000000.{:03}.log", i);
+            std::fs::write(log_dir.join(&name), format!("log content {}\n", i)).unwrap();
+        }
+        let count_before: usize = std::fs::read_dir(&log_dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "log"))
+            .count();
         assert_eq!(
-            snap_before, 2,
-            "Before periodic clean: scan does not trim, so v1 and v2 snapshots both exist"
+            count_before, total_before,
+            "should have 105 log files before checkout"
         );
 
-        std::thread::sleep(std::time::Duration::from_secs(4));
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+        stop_server(&mut server);
 
-        let snap_after = count_snapshot_files(dir.path());
+        let entries: Vec<_> = std::fs::read_dir(&log_dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "log"))
+            .collect();
         assert_eq!(
-            snap_after, 1,
-            "One snapshot should remain after periodic clean, got {}",
-            snap_after
+            entries.len(),
+            KEEP + 1,
+            "after prune: 100 kept + 1 new server log = 101 total"
+        );
+        let names: Vec<String> = entries
+            .iter()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(
+            !names.iter().any(|n| n == "20000101-000000.000.log"),
+            "oldest file should be pruned"
+        );
+        assert!(
+            names.iter().any(|n| n == "20000101-000000.005.log"),
+            "file just after prune cutoff should still exist"
         );
-
-        stop_server(&mut server);
     }
 }
 
 // ===========================================================================
-// Version tests
+// Watchdog tests (.ftm deletion -> auto shutdown, unmounted directory -> degraded)
 // ===========================================================================
 
-mod version_tests {
+mod watchdog_tests {
     use super::*;
 
     #[test]
-    fn test_version_without_server() {
-        // version should still print client version even when no server is running
-        let out = run_ftm_with_port(19999, &["version"]);
-        assert!(out.status.success());
-        let s = String::from_utf8_lossy(&out.stdout);
-        assert!(s.contains("Client version:"));
-        assert!(s.contains("not running"));
+    fn test_server_stops_on_ftm_deleted() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        // Verify server is healthy
+        let out = run_ftm_with_port(port, &["ls"]);
+        assert!(
+            out.status.success(),
+            "ls: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+
+        // Delete the entire .ftm directory
+        let ftm_dir = dir.path().join(".ftm");
+        assert!(ftm_dir.exists(), ".ftm should exist before deletion");
+        std::fs::remove_dir_all(&ftm_dir).unwrap();
+        assert!(!ftm_dir.exists(), ".ftm should be gone after deletion");
+
+        // The watchdog checks every 2 seconds; allow up to 10 seconds
+        let exited = wait_for_server_exit(&mut server, std::time::Duration::from_secs(10));
+        assert!(
+            exited,
+            "Server should have exited after .ftm directory was deleted"
+        );
     }
 
+    /// A watched directory that goes away with its volume (simulated by moving it) leaves
+    /// the server degraded rather than stopped; when it returns, watching resumes and a
+    /// full scan records what changed meanwhile.
     #[test]
-    fn test_version_with_server() {
-        let (mut server, port) = start_server();
+    fn test_server_waits_for_unmounted_directory() {
+        let dir = setup_test_dir();
+        let watched = dir.path().join("drive");
+        let away = dir.path().join("away");
+        std::fs::create_dir(&watched).unwrap();
+        std::fs::write(watched.join("a.txt"), "v1").unwrap();
+        let (mut server, port) = start_server_and_checkout(&watched);
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let health = || -> serde_json::Value {
+            reqwest::blocking::Client::builder()
+                .no_proxy()
+                .build()
+                .unwrap()
+                .get(format!("http://127.0.0.1:{}/api/health", port))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap()
+        };
+        let wait_for_status = |status: &str| {
+            let start = std::time::Instant::now();
+            while health()["status"] != status {
+                assert!(
+                    start.elapsed() < std::time::Duration::from_secs(10),
+                    "status never became {}",
+                    status
+                );
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+        };
+        assert_eq!(health()["status"], "ok");
+
+        std::fs::rename(&watched, &away).unwrap();
+        wait_for_status("degraded");
+        assert!(health()["unmounted_since"].is_string());
+
+        std::fs::write(away.join("a.txt"), "v2").unwrap();
+        std::fs::write(away.join("b.txt"), "new").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(3));
+        std::fs::rename(&away, &watched).unwrap();
+
+        assert!(wait_for_index(&watched, "a.txt", 2, 10000));
+        assert!(wait_for_index(&watched, "b.txt", 1, 10000));
+        wait_for_status("ok");
+        assert!(health().get("unmounted_since").is_none());
+        let index = load_test_index(&watched);
+        assert!(
+            index.history.iter().all(|e| e.op != "delete"),
+            "nothing recorded as deleted while away: {:?}",
+            index.history.iter().map(|e| &e.op).collect::<Vec<_>>()
+        );
 
-        let out = run_ftm_with_port(port, &["version"]);
-        assert!(out.status.success());
-        let s = String::from_utf8_lossy(&out.stdout);
-        assert!(s.contains("Client version:"));
-        assert!(s.contains("Server version:"));
+        // Watches are back in place
+        std::fs::write(watched.join("c.txt"), "after").unwrap();
+        assert!(wait_for_index(&watched, "c.txt", 1, 5000));
 
         stop_server(&mut server);
     }
 }
 
 // ===========================================================================
-// Config tests
+// Periodic scan tests
 // ===========================================================================
 
-mod config_tests {
+mod periodic_scan_tests {
     use super::*;
 
     #[test]
-    fn test_config_get_all() {
+    fn test_periodic_scan_detects_existing_file() {
         let dir = setup_test_dir();
-        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let out = run_ftm_with_port(port, &["config", "get"]);
-        assert!(out.status.success());
-        let s = String::from_utf8_lossy(&out.stdout);
-        assert!(s.contains("max_history"));
-        assert!(s.contains("patterns"));
+        // Create a file BEFORE checkout so the watcher won't catch it;
+        // only the periodic scanner should pick it up.
+        std::fs::write(
+            dir.path().join("pre_existing.txt"),
+            "hello from before checkout",
+        )
+        .unwrap();
+
+        // Pre-init with 2s scan interval (minimum)
+        PreInitFtm::new(dir.path()).scan_interval(2).init();
+
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+
+        let found = wait_for_index(dir.path(), "pre_existing.txt", 1, 5000);
+        assert!(
+            found,
+            "Periodic scanner should have picked up pre_existing.txt"
+        );
+
+        // Verify the entry in index
+        let index = load_test_index(dir.path());
+        let entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "pre_existing.txt")
+            .collect();
+        assert!(
+            !entries.is_empty(),
+            "Should have history for pre_existing.txt"
+        );
+        assert_eq!(entries[0].op, "create");
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_config_get_single_key() {
+    fn test_scan_throttle_paces_periodic_scan() {
         let dir = setup_test_dir();
+        for i in 0..6 {
+            std::fs::write(dir.path().join(format!("f{}.txt", i)), "content").unwrap();
+        }
+        PreInitFtm::new(dir.path()).scan_interval(2).init();
         let (mut server, port) = start_server_and_checkout(dir.path());
-
-        let out = run_ftm_with_port(port, &["config", "get", "settings.max_history"]);
+        let out = run_ftm_with_port(port, &["config", "set", "settings.scan_throttle", "2"]);
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("10000"));
+
+        // At 2 files/s the first periodic scan (starting ~2s in) needs ~2.5s for 6 files,
+        // and the index is only written once it finishes.
+        std::thread::sleep(std::time::Duration::from_millis(3500));
+        assert!(
+            !dir.path().join(".ftm/index.json").exists()
+                || load_test_index(dir.path()).history.is_empty(),
+            "throttled scan should still be running"
+        );
+        assert!(wait_for_index(dir.path(), "f5.txt", 1, 8000));
+        assert_eq!(load_test_index(dir.path()).history.len(), 6);
+
+        let out = run_ftm_with_port(port, &["config", "set", "settings.scan_throttle", "fast"]);
+        assert!(!out.status.success());
 
         stop_server(&mut server);
     }
 
+    /// The last full scan is kept in .ftm/state.json, reported by stats and health, and
+    /// still known after a restart.
     #[test]
-    fn test_config_get_invalid_key() {
+    fn test_last_scan_persists_across_restart() {
         let dir = setup_test_dir();
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
         let (mut server, port) = start_server_and_checkout(dir.path());
+        let health = |port: u16| -> serde_json::Value {
+            reqwest::blocking::Client::builder()
+                .no_proxy()
+                .build()
+                .unwrap()
+                .get(format!("http://127.0.0.1:{}/api/health", port))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap()
+        };
+        assert!(health(port)["last_scan_at"].is_null());
 
-        let out = run_ftm_with_port(port, &["config", "get", "nonexistent.key"]);
-        assert!(!out.status.success());
-        assert!(String::from_utf8_lossy(&out.stderr).contains("Unknown config key"));
+        assert!(run_ftm_with_port(port, &["scan", "--dry-run"])
+            .status
+            .success());
+        assert!(
+            !dir.path().join(".ftm/state.json").exists(),
+            "dry runs are not recorded"
+        );
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let last_scan_at = health(port)["last_scan_at"].clone();
+        assert!(last_scan_at.is_string());
+        let out = run_ftm_with_port(port, &["stats"]);
+        let s = String::from_utf8_lossy(&out.stdout);
+        assert!(s.contains("Last scan: "), "{}", s);
+        assert!(s.contains("(1 created, 0 modified, 0 deleted)"), "{}", s);
+        stop_server(&mut server);
 
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert_eq!(health(port)["last_scan_at"], last_scan_at);
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_config_set_and_get() {
+    fn test_periodic_scan_respects_interval() {
         let dir = setup_test_dir();
-        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // Set max_history to 200
-        let out = run_ftm_with_port(port, &["config", "set", "settings.max_history", "200"]);
-        assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("Set settings.max_history = 200"));
+        // Create a file BEFORE checkout
+        std::fs::write(dir.path().join("should_not_scan.txt"), "no scan").unwrap();
 
-        // Verify it was changed
-        let out = run_ftm_with_port(port, &["config", "get", "settings.max_history"]);
-        assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("200"));
+        // Pre-init with 5s interval so no scan runs within 2s
+        PreInitFtm::new(dir.path()).scan_interval(5).init();
 
-        // Verify persisted to config.yaml
-        let config_content = std::fs::read_to_string(dir.path().join(".ftm/config.yaml")).unwrap();
-        assert!(config_content.contains("200"));
+        let (mut server, _port) = start_server_and_checkout(dir.path());
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+
+        let index = load_test_index(dir.path());
+        let entries: Vec<_> = index
+            .history
+            .iter()
+            .filter(|e| e.file == "should_not_scan.txt")
+            .collect();
+        assert!(
+            entries.is_empty(),
+            "With 5s scan_interval, no periodic scan should run within 2s"
+        );
 
         stop_server(&mut server);
     }
+}
+
+// ---------------------------------------------------------------------------
+// Sequence numbers
+// ---------------------------------------------------------------------------
+
+mod seq_tests {
+    use super::*;
+
+    fn seqs(index: &TestIndex) -> Vec<u64> {
+        index.history.iter().map(|e| e.seq).collect()
+    }
 
     #[test]
-    fn test_config_set_invalid_value() {
+    fn test_seq_increases_and_survives_trim() {
         let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_history(2).init();
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // max_history expects a number
-        let out = run_ftm_with_port(
-            port,
-            &["config", "set", "settings.max_history", "not_a_number"],
-        );
-        assert!(!out.status.success());
-        assert!(String::from_utf8_lossy(&out.stderr).contains("Invalid value"));
+        let file = dir.path().join("notes.txt");
+        for v in ["v1", "v2 a", "v3 ab"] {
+            std::fs::write(&file, v).unwrap();
+            assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        }
+        assert_eq!(seqs(&load_test_index(dir.path())), vec![1, 2, 3]);
+
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        let index = load_test_index(dir.path());
+        assert_eq!(seqs(&index), vec![2, 3]);
+        // The clean itself is recorded as an event, which takes seq 4
+        assert_eq!(index.next_seq, 5);
+
+        std::fs::write(&file, "v4 abc").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let index = load_test_index(dir.path());
+        assert_eq!(index.history.last().unwrap().seq, 5, "seq is never reused");
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_config_set_scan_interval_minimum_2() {
+    fn test_legacy_index_numbered_in_stored_order() {
         let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).init();
+        // Written before seq existed, with a clock that jumped backwards in between.
+        let legacy = r#"{"history":[
+            {"timestamp":"2030-01-01T00:00:00Z","op":"delete","file":"later.txt"},
+            {"timestamp":"2020-01-01T00:00:00Z","op":"delete","file":"earlier.txt"}
+        ]}"#;
+        std::fs::write(dir.path().join(".ftm/index.json"), legacy).unwrap();
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let out = run_ftm_with_port(port, &["config", "set", "settings.scan_interval", "1"]);
-        assert!(!out.status.success());
-        let stderr = String::from_utf8_lossy(&out.stderr);
-        assert!(
-            stderr.contains("scan_interval must be >= 2") || stderr.contains(">= 2"),
-            "expected scan_interval minimum 2 error, got: {}",
-            stderr
-        );
+        std::fs::write(dir.path().join("new.txt"), "hello").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
 
-        let out = run_ftm_with_port(port, &["config", "set", "settings.scan_interval", "2"]);
-        assert!(out.status.success());
+        let index = load_test_index(dir.path());
+        let files: Vec<_> = index.history.iter().map(|e| e.file.as_str()).collect();
+        assert_eq!(files, vec!["later.txt", "earlier.txt", "new.txt"]);
+        assert_eq!(seqs(&index), vec![1, 2, 3]);
+        assert_eq!(index.next_seq, 4);
 
         stop_server(&mut server);
     }
 
+    /// Reads that stream the index number entries like a full load: by position as soon
+    /// as any entry has no seq.
     #[test]
-    fn test_config_set_watch_patterns() {
+    fn test_partly_legacy_index_numbered_by_position_when_streamed() {
         let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).init();
+        let legacy = r#"{"history":[
+            {"seq":7,"timestamp":"2020-01-01T00:00:00Z","op":"delete","file":"gone.txt"},
+            {"timestamp":"2020-01-02T00:00:00Z","op":"delete","file":"gone.txt"}
+        ]}"#;
+        std::fs::write(dir.path().join(".ftm/index.json"), legacy).unwrap();
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        let out = run_ftm_with_port(port, &["config", "set", "watch.patterns", "*.rs,*.go,*.py"]);
-        assert!(out.status.success());
-
-        let out = run_ftm_with_port(port, &["config", "get", "watch.patterns"]);
-        assert!(out.status.success());
-        let s = String::from_utf8_lossy(&out.stdout);
-        assert!(s.contains("*.rs"));
-        assert!(s.contains("*.go"));
-        assert!(s.contains("*.py"));
+        let history: Vec<serde_json::Value> = http()
+            .get(format!("http://127.0.0.1:{}/api/history", port))
+            .query(&[("file", "gone.txt")])
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        let seqs: Vec<_> = history.iter().map(|v| v["seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, vec![1, 2]);
 
         stop_server(&mut server);
     }
+}
+
+// ---------------------------------------------------------------------------
+// Checksum algorithms
+// ---------------------------------------------------------------------------
+
+mod checksum_tests {
+    use super::*;
 
     #[test]
-    fn test_config_not_checked_out() {
-        let (mut server, port) = start_server();
+    fn test_new_snapshots_use_blake3_and_sha256_history_keeps_working() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).init();
 
-        let out = run_ftm_with_port(port, &["config", "get"]);
-        assert!(!out.status.success());
-        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
+        // History recorded before BLAKE3: untagged SHA-256 entry with its snapshot
+        let content = "legacy content";
+        let sha = hex::encode(<sha2::Sha256 as sha2::Digest>::digest(content));
+        let snap_dir = dir
+            .path()
+            .join(".ftm/snapshots")
+            .join(&sha[..1])
+            .join(&sha[1..2]);
+        std::fs::create_dir_all(&snap_dir).unwrap();
+        std::fs::write(snap_dir.join(&sha), content).unwrap();
+        let index = format!(
+            r#"{{"history":[{{"timestamp":"2026-01-01T00:00:00Z","op":"create","file":"old.txt","checksum":"{}","size":{}}}]}}"#,
+            sha,
+            content.len()
+        );
+        std::fs::write(dir.path().join(".ftm/index.json"), index).unwrap();
+        std::fs::write(dir.path().join("old.txt"), content).unwrap();
+
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert_eq!(
+            load_test_index(dir.path()).history.len(),
+            1,
+            "unchanged content must not be re-recorded under the new algorithm"
+        );
+
+        std::fs::write(dir.path().join("old.txt"), "new content").unwrap();
+        assert!(wait_for_index(dir.path(), "old.txt", 2, 2000));
+        let index = load_test_index(dir.path());
+        let last = index.history.last().unwrap();
+        assert_eq!(last.algo.as_deref(), Some("blake3"));
+        assert_eq!(
+            last.checksum.as_deref(),
+            Some(blake3::hash(b"new content").to_hex().as_str())
+        );
+        assert_eq!(index.history[0].algo, None);
+
+        let out = run_ftm_with_port(port, &["restore", "old.txt", &sha[..8]]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("old.txt")).unwrap(),
+            content
+        );
 
         stop_server(&mut server);
     }
 }
 
-// ===========================================================================
-// Config hot-reload tests
-// ===========================================================================
+// ---------------------------------------------------------------------------
+// Disk-space guard
+// ---------------------------------------------------------------------------
 
-mod config_hot_reload_tests {
+mod disk_space_tests {
     use super::*;
 
-    /// After `config set watch.patterns`, the watcher should immediately use
-    /// the new patterns — newly added extensions get tracked.
     #[test]
-    fn test_config_set_patterns_adds_new_extension_to_watcher() {
+    fn test_low_disk_space_pauses_snapshots_but_records_deletes() {
         let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // Default patterns do NOT include *.go
-        // Add *.go via config set
+        std::fs::write(dir.path().join("kept.txt"), "before").unwrap();
+        assert!(wait_for_index(dir.path(), "kept.txt", 1, 2000));
+
+        // No volume has an exabyte free, so the guard trips
         let out = run_ftm_with_port(
             port,
-            &["config", "set", "watch.patterns", "*.rs,*.go,*.yaml"],
+            &[
+                "config",
+                "set",
+                "settings.min_free_space",
+                "1000000000000000000",
+            ],
         );
         assert!(out.status.success());
 
-        // Write a .go file — should now be tracked
-        std::fs::write(dir.path().join("main.go"), "package main").unwrap();
+        std::fs::write(dir.path().join("new.txt"), "not snapshotted").unwrap();
+        std::fs::remove_file(dir.path().join("kept.txt")).unwrap();
+        let out = run_ftm_with_port(port, &["scan"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success());
+        assert!(stdout.contains("min_free_space"), "stdout: {}", stdout);
 
-        assert!(
-            wait_for_index(dir.path(), "main.go", 1, 3000),
-            "After adding *.go to patterns, .go files should be tracked by the watcher"
-        );
+        let index = load_test_index(dir.path());
+        assert!(!index.history.iter().any(|e| e.file == "new.txt"));
+        assert_eq!(index.history.last().unwrap().op, "delete");
+
+        let health: serde_json::Value = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(format!("http://127.0.0.1:{}/api/health", port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(health["low_disk_space"], true);
+        let out = run_ftm_with_port(port, &["stats"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("snapshotting is paused"));
+
+        // Lifting the guard resumes snapshotting
+        let out = run_ftm_with_port(port, &["config", "set", "settings.min_free_space", "0"]);
+        assert!(out.status.success());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(wait_for_index(dir.path(), "new.txt", 1, 2000));
 
         stop_server(&mut server);
     }
+}
+
+// ---------------------------------------------------------------------------
+// Snapshot integrity
+// ---------------------------------------------------------------------------
+
+mod snapshot_integrity_tests {
+    use super::*;
 
-    /// After `config set watch.patterns` to remove an extension, the watcher
-    /// should stop tracking files with that extension.
     #[test]
-    fn test_config_set_patterns_removes_extension_from_watcher() {
+    fn test_snapshots_read_only_and_tampering_detected() {
         let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // Verify .yaml is tracked by default
-        std::fs::write(dir.path().join("before.yaml"), "before: change").unwrap();
+        std::fs::write(dir.path().join("doc.txt"), "original").unwrap();
+        assert!(wait_for_index(dir.path(), "doc.txt", 1, 2000));
+        let checksum = load_test_index(dir.path()).history[0]
+            .checksum
+            .clone()
+            .unwrap();
+        let snap = dir
+            .path()
+            .join(".ftm/snapshots")
+            .join(&checksum[..1])
+            .join(&checksum[1..2])
+            .join(&checksum);
+        let mut perms = std::fs::metadata(&snap).unwrap().permissions();
+        assert!(perms.readonly(), "snapshot should be read-only");
+
+        // A stray write (after forcing the file writable) must not go unnoticed
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        std::fs::set_permissions(&snap, perms).unwrap();
+        std::fs::write(&snap, "tampered").unwrap();
+
+        let http = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let base = format!("http://127.0.0.1:{}", port);
+        let resp = http
+            .get(format!("{}/api/snapshot", base))
+            .query(&[("checksum", &checksum)])
+            .send();
+        // Checked as it streams, so the download breaks off instead of completing
         assert!(
-            wait_for_index(dir.path(), "before.yaml", 1, 2000),
-            "before.yaml should be tracked with default patterns"
+            resp.and_then(|r| r.error_for_status()?.bytes()).is_err(),
+            "corrupt snapshot should not download cleanly"
         );
 
-        // Remove *.yaml from patterns (keep only *.rs)
-        let out = run_ftm_with_port(port, &["config", "set", "watch.patterns", "*.rs"]);
-        assert!(out.status.success());
-
-        // Write a .yaml file — should NOT be tracked anymore
-        std::fs::write(dir.path().join("after.yaml"), "after: change").unwrap();
+        let resp = http
+            .get(format!("{}/api/diff", base))
+            .query(&[("to", &checksum)])
+            .send()
+            .unwrap();
+        assert!(!resp.status().is_success());
+        assert!(resp.text().unwrap().contains("corrupt"));
 
-        // Write a .rs file as sync marker — should be tracked
-        std::fs::write(dir.path().join("sync.rs"), "fn sync() {}").unwrap();
-        assert!(
-            wait_for_index(dir.path(), "sync.rs", 1, 2000),
-            "sync.rs should be tracked (proves watcher is still running)"
-        );
+        stop_server(&mut server);
+    }
+}
 
-        let index = load_test_index(dir.path());
-        assert!(
-            !index.history.iter().any(|e| e.file == "after.yaml"),
-            "after.yaml should NOT be tracked after removing *.yaml from patterns"
-        );
+mod verify_tests {
+    use super::*;
 
-        stop_server(&mut server);
+    fn snapshot_path(dir: &Path, checksum: &str) -> std::path::PathBuf {
+        dir.join(".ftm/snapshots")
+            .join(&checksum[..1])
+            .join(&checksum[1..2])
+            .join(checksum)
     }
 
-    /// After `config set watch.patterns`, manual scan should use the new patterns.
     #[test]
-    fn test_config_set_patterns_applied_to_manual_scan() {
+    fn test_background_verification_reports_corrupt_and_missing() {
         let dir = setup_test_dir();
-
-        // Create a .go file BEFORE checkout (watcher won't see it)
-        std::fs::write(dir.path().join("lib.go"), "package lib").unwrap();
-
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // Default scan should NOT pick up .go files
-        let out = run_ftm_with_port(port, &["scan"]);
-        assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("0 created"));
+        std::fs::write(dir.path().join("a.txt"), "alpha").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "beta").unwrap();
+        assert!(wait_for_index(dir.path(), "b.txt", 1, 2000));
+        assert!(wait_for_index(dir.path(), "a.txt", 1, 2000));
+        let index = load_test_index(dir.path());
+        let checksum = |file: &str| {
+            index
+                .history
+                .iter()
+                .find(|e| e.file == file)
+                .and_then(|e| e.checksum.clone())
+                .unwrap()
+        };
 
-        // Add *.go to patterns
-        let out = run_ftm_with_port(port, &["config", "set", "watch.patterns", "*.rs,*.go"]);
-        assert!(out.status.success());
+        let corrupt = snapshot_path(dir.path(), &checksum("a.txt"));
+        let mut perms = std::fs::metadata(&corrupt).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        std::fs::set_permissions(&corrupt, perms).unwrap();
+        std::fs::write(&corrupt, "bit rot").unwrap();
+        std::fs::remove_file(snapshot_path(dir.path(), &checksum("b.txt"))).unwrap();
 
-        // Scan again — should now find the .go file
-        let out = run_ftm_with_port(port, &["scan"]);
+        let out = run_ftm_with_port(port, &["config", "set", "settings.verify_interval", "2"]);
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
 
-        let index = load_test_index(dir.path());
+        let http = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(8);
+        let health = loop {
+            let health: serde_json::Value = http
+                .get(format!("http://127.0.0.1:{}/api/health", port))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap();
+            if health["corrupt_count"] == 1 || std::time::Instant::now() > deadline {
+                break health;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(200));
+        };
+        assert_eq!(health["corrupt_count"], 1, "health: {}", health);
+        assert_eq!(health["missing_count"], 1, "health: {}", health);
+
+        let out = run_ftm_with_port(port, &["stats"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
         assert!(
-            index.history.iter().any(|e| e.file == "lib.go"),
-            "lib.go should appear in history after pattern change + scan"
+            stdout.contains("Integrity: 1 corrupt, 1 missing snapshot(s)"),
+            "stdout: {}",
+            stdout
         );
 
+        // Range reads are not re-hashed; they rely on the verification findings
+        let resp = http
+            .get(format!("http://127.0.0.1:{}/api/snapshot", port))
+            .query(&[("checksum", checksum("a.txt"))])
+            .header("Range", "bytes=0-2")
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 500);
+        assert!(resp.text().unwrap().contains("corrupt"));
+
         stop_server(&mut server);
     }
+}
 
-    /// After `config set settings.scan_interval` to a shorter value,
-    /// the new interval takes effect immediately (within ~1s).
-    #[test]
-    fn test_config_set_scan_interval_enables_periodic_scan() {
-        let dir = setup_test_dir();
-
-        std::fs::write(
-            dir.path().join("pre_existing.txt"),
-            "created before checkout",
-        )
-        .unwrap();
+// ---------------------------------------------------------------------------
+// Signed history
+// ---------------------------------------------------------------------------
 
-        // Pre-init with 8s interval; no scan in 1s
-        PreInitFtm::new(dir.path()).scan_interval(8).init();
+mod signing_tests {
+    use super::*;
 
+    #[test]
+    fn test_signed_entries_verify_and_tampering_is_detected() {
+        let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        std::thread::sleep(std::time::Duration::from_secs(1));
-        let index = load_test_index(dir.path());
-        assert!(
-            !index.history.iter().any(|e| e.file == "pre_existing.txt"),
-            "With 8s scan_interval, file should not be scanned in 1s"
-        );
-
-        // Shorten to 2s; takes effect on next tick (~1s), then 2s wait, then scan
-        let out = run_ftm_with_port(port, &["config", "set", "settings.scan_interval", "2"]);
+        std::fs::write(dir.path().join("before.txt"), "unsigned").unwrap();
+        assert!(wait_for_index(dir.path(), "before.txt", 1, 2000));
+        let out = run_ftm_with_port(port, &["config", "set", "settings.sign_history", "true"]);
         assert!(out.status.success());
+        std::fs::write(dir.path().join("signed.txt"), "signed").unwrap();
+        assert!(wait_for_index(dir.path(), "signed.txt", 1, 2000));
 
-        let found = wait_for_index(dir.path(), "pre_existing.txt", 1, 5000);
+        let out = run_ftm_with_port(port, &["verify-signatures"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success(), "stdout: {}", stdout);
         assert!(
-            found,
-            "After setting scan_interval=2, periodic scanner should pick up pre_existing.txt"
+            stdout.contains("1 entries with a valid signature, 1 unsigned"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(
+            stdout.contains("complete and in order"),
+            "stdout: {}",
+            stdout
         );
 
+        // Edit the signed entry behind ftm's back
+        let index_path = dir.path().join(".ftm/index.json");
+        let mut index: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&index_path).unwrap()).unwrap();
+        let entry = index["history"]
+            .as_array_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|e| e["file"] == "signed.txt")
+            .unwrap();
+        assert!(entry["sig"].is_string());
+        entry["file"] = "forged.txt".into();
+        std::fs::write(&index_path, index.to_string()).unwrap();
+
+        let out = run_ftm_with_port(port, &["verify-signatures"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(!out.status.success());
+        assert!(stdout.contains("INVALID"), "stdout: {}", stdout);
+        assert!(stdout.contains("forged.txt"), "stdout: {}", stdout);
+
         stop_server(&mut server);
     }
 
-    /// After `config set settings.max_file_size`, scan should respect the new limit.
     #[test]
-    fn test_config_set_max_file_size_applied_to_scan() {
+    fn test_removed_signed_entry_is_detected() {
         let dir = setup_test_dir();
-
-        // Create a 200-byte file BEFORE checkout
-        std::fs::write(dir.path().join("medium.txt"), "x".repeat(200)).unwrap();
-
-        // Pre-init with max_file_size=100 — file will be skipped
-        PreInitFtm::new(dir.path()).max_file_size(100).init();
-
         let (mut server, port) = start_server_and_checkout(dir.path());
-
-        // Scan — file exceeds 100 bytes, should be skipped
-        let out = run_ftm_with_port(port, &["scan"]);
+        let out = run_ftm_with_port(port, &["config", "set", "settings.sign_history", "true"]);
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("0 created"));
-
-        // Raise max_file_size to 1000
-        let out = run_ftm_with_port(port, &["config", "set", "settings.max_file_size", "1000"]);
+        for (i, name) in ["a.txt", "b.txt", "c.txt"].iter().enumerate() {
+            std::fs::write(dir.path().join(name), format!("v{}", i)).unwrap();
+            assert!(wait_for_index(dir.path(), name, 1, 2000));
+        }
+        let out = run_ftm_with_port(port, &["verify-signatures"]);
         assert!(out.status.success());
 
-        // Scan again — file should now be picked up
-        let out = run_ftm_with_port(port, &["scan"]);
-        assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("1 created"));
+        // Take the middle entry out; the others still verify one by one
+        let index_path = dir.path().join(".ftm/index.json");
+        let mut index: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&index_path).unwrap()).unwrap();
+        index["history"]
+            .as_array_mut()
+            .unwrap()
+            .retain(|e| e["file"] != "b.txt");
+        std::fs::write(&index_path, index.to_string()).unwrap();
 
-        let index = load_test_index(dir.path());
+        let out = run_ftm_with_port(port, &["verify-signatures"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(!out.status.success());
         assert!(
-            index.history.iter().any(|e| e.file == "medium.txt"),
-            "medium.txt should be tracked after raising max_file_size"
+            stdout.contains("2 entries with a valid signature, 0 unsigned"),
+            "stdout: {}",
+            stdout
+        );
+        assert!(
+            stdout.contains("removed or reordered"),
+            "stdout: {}",
+            stdout
         );
 
         stop_server(&mut server);
     }
 
-    /// After `config set watch.exclude`, the watcher should respect the new
-    /// exclude patterns.
+    /// Checking signatures is a read: without a signing key it fails instead of
+    /// creating one.
     #[test]
-    fn test_config_set_exclude_applied_to_watcher() {
+    fn test_signature_report_does_not_create_a_key() {
+        let state_dir = setup_test_dir();
         let dir = setup_test_dir();
-        let custom_dir = dir.path().join("custom");
-        std::fs::create_dir_all(&custom_dir).unwrap();
+        let (mut server, port) = start_server_with_env(&[], &[("FTM_STATE_DIR", state_dir.path())]);
+        checkout_over_api(port, dir.path());
+
+        let resp = http()
+            .get(format!("http://127.0.0.1:{}/api/signatures", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 404);
+        assert!(!state_dir.path().join("signing.key").exists());
+
+        stop_server(&mut server);
+    }
+}
+
+mod audit_tests {
+    use super::*;
 
+    #[test]
+    fn test_audit_exports_entries_and_events_in_order() {
+        let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // Verify files in custom/ ARE tracked before exclude change
-        std::fs::write(custom_dir.join("before.yaml"), "tracked: yes").unwrap();
-        assert!(
-            wait_for_index(dir.path(), "custom/before.yaml", 1, 2000),
-            "custom/before.yaml should be tracked before exclude change"
-        );
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, "v1").unwrap();
+        assert!(wait_for_index(dir.path(), "a.txt", 1, 2000));
+        std::fs::write(&file, "v2").unwrap();
+        assert!(wait_for_index(dir.path(), "a.txt", 2, 2000));
+        let first = load_test_index(dir.path()).history[0]
+            .checksum
+            .clone()
+            .unwrap();
 
-        // Add **/custom/** to exclude patterns
+        let out = run_ftm_with_port(port, &["restore", "a.txt", &first[..8]]);
+        assert!(out.status.success());
+        assert!(wait_for_index(dir.path(), "a.txt", 3, 2000));
+        let out = run_ftm_with_port(port, &["config", "set", "settings.max_history", "500"]);
+        assert!(out.status.success());
+        // Setting the current value again is not a change
+        let out = run_ftm_with_port(port, &["config", "set", "settings.max_history", "500"]);
+        assert!(out.status.success());
+
+        let audit_path = dir.path().join("audit.jsonl");
         let out = run_ftm_with_port(
             port,
             &[
-                "config",
-                "set",
-                "watch.exclude",
-                "**/target/**,**/node_modules/**,**/.git/**,**/.ftm/**,**/custom/**",
+                "audit",
+                "--since",
+                "2000-01-01",
+                "--output",
+                audit_path.to_str().unwrap(),
             ],
         );
         assert!(out.status.success());
-
-        // Write a new file in custom/ — should NOT be tracked
-        std::fs::write(custom_dir.join("after.yaml"), "tracked: no").unwrap();
-
-        // Write a sync marker in root — should be tracked
-        std::fs::write(dir.path().join("sync.yaml"), "sync: yes").unwrap();
-        assert!(
-            wait_for_index(dir.path(), "sync.yaml", 1, 2000),
-            "sync.yaml should be tracked (proves watcher still running)"
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(&audit_path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let ops: Vec<&str> = records.iter().map(|r| r["op"].as_str().unwrap()).collect();
+        assert_eq!(
+            ops,
+            ["create", "modify", "restore", "modify", "config_change"],
+            "records: {:?}",
+            records
         );
+        let seqs: Vec<u64> = records.iter().map(|r| r["seq"].as_u64().unwrap()).collect();
+        assert!(seqs.is_sorted() && seqs[0] < seqs[4]);
+        assert_eq!(records[2]["checksum"], first.as_str());
+        assert_eq!(records[4]["key"], "settings.max_history");
+        assert_eq!(records[4]["new_value"], "500");
+        assert!(records[0].get("mtime_nanos").is_none());
+
+        let modified: Vec<serde_json::Value> = http()
+            .get(format!("http://127.0.0.1:{}/api/audit?op=modify", port))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(modified.len(), 2);
+        assert!(modified.iter().all(|r| r["op"] == "modify"));
 
-        let index = load_test_index(dir.path());
-        assert!(
-            !index.history.iter().any(|e| e.file == "custom/after.yaml"),
-            "custom/after.yaml should NOT be tracked after adding **/custom/** to exclude"
-        );
+        let out = run_ftm_with_port(port, &["audit", "--since", "2999-01-01"]);
+        assert!(out.status.success());
+        assert!(out.stdout.is_empty());
 
         stop_server(&mut server);
     }
-}
 
-// ===========================================================================
-// Logs tests
-// ===========================================================================
+    #[test]
+    fn test_clean_and_unclean_are_recorded() {
+        let dir = setup_test_dir();
+        PreInitFtm::new(dir.path()).max_history(1).init();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, "v1").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(&file, "v2 longer").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        // Nothing left to remove: not recorded
+        assert!(run_ftm_with_port(port, &["clean"]).status.success());
+        assert!(run_ftm_with_port(port, &["unclean"]).status.success());
+
+        let out = run_ftm_with_port(port, &["audit"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success());
+        let records: Vec<serde_json::Value> = stdout
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let ops: Vec<&str> = records.iter().map(|r| r["op"].as_str().unwrap()).collect();
+        assert_eq!(
+            ops,
+            ["create", "modify", "clean", "unclean"],
+            "stdout: {}",
+            stdout
+        );
+        assert_eq!(records[2]["entries_trimmed"], 1);
+        assert_eq!(records[3]["entries_restored"], 1);
+
+        stop_server(&mut server);
+    }
+}
 
-mod logs_tests {
+mod diff_tests {
     use super::*;
 
+    /// Record two versions of a.txt and return their checksums.
+    fn two_versions(dir: &Path, port: u16, old: &str, new: &str) -> (String, String) {
+        let file = dir.join("a.txt");
+        std::fs::write(&file, old).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(&file, new).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let index = load_test_index(dir);
+        (
+            index.history[0].checksum.clone().unwrap(),
+            index.history[1].checksum.clone().unwrap(),
+        )
+    }
+
     #[test]
-    fn test_logs_no_log_files() {
+    fn test_file_timeline_summarizes_each_version() {
         let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
+        two_versions(dir.path(), port, "a\nb\n", "a\nc\nd\n");
+        std::fs::remove_file(dir.path().join("a.txt")).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
 
-        // The server auto-creates a log file on startup, so "logs" should
-        // find it and print "Opening: ..." instead of "No log files".
-        let out = run_ftm_with_port(port, &["logs"]);
-        assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("Opening:"));
+        let resp = http()
+            .get(format!("http://127.0.0.1:{}/api/file-timeline", port))
+            .query(&[("path", "a.txt")])
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        let timeline: serde_json::Value = resp.json().unwrap();
+        let versions = timeline["versions"].as_array().unwrap();
+        assert_eq!(versions.len(), 3);
+        assert_eq!(versions[0]["lines"], 2);
+        assert_eq!(versions[0]["delta"]["added"], 2);
+        assert_eq!(versions[1]["lines"], 3);
+        assert_eq!(versions[1]["delta"]["added"], 2);
+        assert_eq!(versions[1]["delta"]["removed"], 1);
+        assert_eq!(versions[1]["delta"]["hunks"], 1);
+        assert_eq!(versions[2]["op"], "delete");
+        assert!(versions[2].get("delta").is_none());
+
+        let resp = http()
+            .get(format!("http://127.0.0.1:{}/api/file-timeline", port))
+            .query(&[("path", "missing.txt")])
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 404);
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_logs_with_log_file() {
+    fn test_history_diff_summary_counts_and_caches_changes() {
         let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
-
-        // Create a log file with a far-future timestamp so it is picked as
-        // the newest (the server auto-creates its own log on startup).
-        let log_dir = dir.path().join(".ftm/logs");
-        std::fs::create_dir_all(&log_dir).unwrap();
-        std::fs::write(
-            log_dir.join("30000101-120000.log"),
-            "INFO test log line 1\nINFO test log line 2\n",
-        )
-        .unwrap();
-
-        // logs command should find the file and try less, then fallback to print
-        let out = run_ftm_with_port(port, &["logs"]);
-        assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("30000101-120000.log"));
+        two_versions(dir.path(), port, "a\nb\n", "a\nc\nd\n");
+        std::fs::remove_file(dir.path().join("a.txt")).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+
+        let url = format!("http://127.0.0.1:{}/api/history-diff-summary", port);
+        let summary = || {
+            let resp = http().get(&url).query(&[("file", "a.txt")]).send().unwrap();
+            assert_eq!(resp.status(), 200);
+            resp.json::<serde_json::Value>().unwrap()
+        };
+        let first = summary();
+        let versions = first["versions"].as_array().unwrap();
+        assert_eq!(versions.len(), 3);
+        assert_eq!(versions[0]["added"], 2);
+        assert_eq!(versions[0]["removed"], 0);
+        assert_eq!(versions[1]["added"], 2);
+        assert_eq!(versions[1]["removed"], 1);
+        assert_eq!(versions[2]["op"], "delete");
+        assert!(versions[2].get("added").is_none());
+
+        // Counted pairs come from the cache without reading the snapshots again
+        std::fs::remove_dir_all(dir.path().join(".ftm/snapshots")).unwrap();
+        assert_eq!(summary(), first);
+
+        let resp = http()
+            .get(&url)
+            .query(&[("file", "missing.txt")])
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 404);
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_logs_picks_latest_file() {
+    fn test_large_diff_is_truncated_with_full_patch() {
         let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
+        // About 3 MB, past the 2 MB inline diff limit
+        let lines = |changed: &str| -> String {
+            (0..60_000)
+                .map(|i| match i {
+                    10 | 59_990 => format!("{} {:05}\n", changed, i),
+                    _ => format!("line {:05} {}\n", i, "x".repeat(38)),
+                })
+                .collect()
+        };
+        let (from, to) = two_versions(dir.path(), port, &lines("old"), &lines("new"));
 
-        // Create multiple log files with far-future timestamps so both are
-        // newer than the server's auto-created log file.
-        let log_dir = dir.path().join(".ftm/logs");
-        std::fs::create_dir_all(&log_dir).unwrap();
-        std::fs::write(log_dir.join("30000101-100000.log"), "old log\n").unwrap();
-        std::fs::write(log_dir.join("30000201-150000.log"), "new log\n").unwrap();
+        let resp = http()
+            .get(format!("http://127.0.0.1:{}/api/diff", port))
+            .query(&[("from", from.as_str()), ("to", to.as_str())])
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        let diff: serde_json::Value = resp.json().unwrap();
+        assert_eq!(diff["truncated"], true);
+        assert_eq!(diff["old_total"], 60_000);
+        assert_eq!(diff["new_total"], 60_000);
+        let hunks = diff["hunks"].as_array().unwrap();
+        assert_eq!(
+            hunks.len(),
+            1,
+            "only the change within the limit: {:?}",
+            hunks
+        );
+        assert!(hunks[0]["lines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|l| l["tag"] == "insert" && l["content"] == "new 00010"));
+
+        let resp = http()
+            .get(format!("http://127.0.0.1:{}/api/patch", port))
+            .query(&[
+                ("from", from.as_str()),
+                ("to", to.as_str()),
+                ("file", "a.txt"),
+            ])
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers()["content-disposition"]
+            .to_str()
+            .unwrap()
+            .contains("a.txt.patch"));
+        let patch = resp.text().unwrap();
+        assert!(
+            patch.starts_with("--- a/a.txt\n+++ b/a.txt\n"),
+            "{}",
+            &patch[..100]
+        );
+        for change in ["-old 00010", "+new 00010", "-old 59990", "+new 59990"] {
+            assert!(patch.contains(change), "missing {}", change);
+        }
 
-        // Should pick the newest one (30000201-150000.log)
-        let out = run_ftm_with_port(port, &["logs"]);
+        stop_server(&mut server);
+    }
+
+    #[test]
+    fn test_concurrent_diffs_share_the_worker_pool() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let out = run_ftm_with_port(port, &["config", "set", "settings.diff_workers", "2"]);
         assert!(out.status.success());
-        assert!(String::from_utf8_lossy(&out.stdout).contains("30000201-150000.log"));
+        let (from, to) = two_versions(dir.path(), port, "a\nb\nc\n", "a\nB\nc\n");
+
+        let handles: Vec<_> = (0..6)
+            .map(|_| {
+                let (from, to) = (from.clone(), to.clone());
+                std::thread::spawn(move || {
+                    http()
+                        .get(format!("http://127.0.0.1:{}/api/diff", port))
+                        .query(&[("from", &from), ("to", &to)])
+                        .send()
+                        .unwrap()
+                        .status()
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 200);
+        }
 
         stop_server(&mut server);
     }
 
     #[test]
-    fn test_logs_not_checked_out() {
-        let (mut server, port) = start_server();
-
-        let out = run_ftm_with_port(port, &["logs"]);
+    fn test_diff_timeout_is_configurable() {
+        let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let out = run_ftm_with_port(port, &["config", "set", "settings.diff_timeout_ms", "0"]);
         assert!(!out.status.success());
-        assert!(String::from_utf8_lossy(&out.stderr).contains("No directory checked out"));
+        let out = run_ftm_with_port(port, &["config", "set", "settings.diff_timeout_ms", "1"]);
+        assert!(out.status.success());
+
+        let old: String = (0..200_000).map(|i| format!("line {}\n", i)).collect();
+        let new: String = (0..200_000).map(|i| format!("line {}\n", i * 7)).collect();
+        let (from, to) = two_versions(dir.path(), port, &old, &new);
+
+        let resp = http()
+            .get(format!("http://127.0.0.1:{}/api/diff", port))
+            .query(&[("from", &from), ("to", &to)])
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 408);
+        assert!(resp.text().unwrap().contains("1ms limit"));
 
         stop_server(&mut server);
     }
+}
 
-    /// Pruning: when server starts with file logging, only the 100 most recent log files are kept.
-    #[test]
-    fn test_logs_prune_keeps_only_100() {
-        const KEEP: usize = 100;
-        let total_before = 105;
+mod similarity_tests {
+    use super::*;
+
+    fn lines(range: std::ops::Range<u32>) -> String {
+        range.map(|i| format!("line {}\n", i)).collect()
+    }
 
+    #[test]
+    fn test_similar_reports_copies_and_renames() {
         let dir = setup_test_dir();
-        let log_dir = dir.path().join(".ftm/logs");
-        std::fs::create_dir_all(&log_dir).unwrap();
+        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        for i in 0..total_before {
-            let name = format!("20000101-000000.{:03}.log", i);
-            std::fs::write(log_dir.join(&name), format!("log content {}\n", i)).unwrap();
-        }
-        let count_before: usize = std::fs::read_dir(&log_dir)
-            .unwrap()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().is_some_and(|ext| ext == "log"))
-            .count();
-        assert_eq!(
-            count_before, total_before,
-            "should have 105 log files before checkout"
-        );
+        std::fs::write(dir.path().join("notes.md"), lines(0..20)).unwrap();
+        std::fs::write(dir.path().join("old.txt"), "moved\nas is\n").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
 
-        let (mut server, _port) = start_server_and_checkout(dir.path());
-        stop_server(&mut server);
+        // 18 of 20 lines kept: 90% similar
+        let copy = lines(0..18) + "changed\nlines\n";
+        std::fs::write(dir.path().join("copy.md"), copy).unwrap();
+        std::fs::write(dir.path().join("unrelated.md"), lines(100..120)).unwrap();
+        std::fs::rename(dir.path().join("old.txt"), dir.path().join("new.txt")).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
 
-        let entries: Vec<_> = std::fs::read_dir(&log_dir)
-            .unwrap()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().extension().is_some_and(|ext| ext == "log"))
-            .collect();
-        assert_eq!(
-            entries.len(),
-            KEEP + 1,
-            "after prune: 100 kept + 1 new server log = 101 total"
-        );
-        let names: Vec<String> = entries
-            .iter()
-            .map(|e| e.file_name().to_string_lossy().into_owned())
-            .collect();
+        let out = run_ftm_with_port(port, &["similar"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(out.status.success(), "stdout: {}", stdout);
         assert!(
-            !names.iter().any(|n| n == "20000101-000000.000.log"),
-            "oldest file should be pruned"
+            stdout.contains("copy.md <- notes.md  (90%, copy)"),
+            "stdout: {}",
+            stdout
         );
         assert!(
-            names.iter().any(|n| n == "20000101-000000.005.log"),
-            "file just after prune cutoff should still exist"
+            stdout.contains("new.txt <- old.txt  (100%, rename)"),
+            "stdout: {}",
+            stdout
         );
+        assert!(!stdout.contains("unrelated.md"), "stdout: {}", stdout);
+
+        let out = run_ftm_with_port(port, &["similar", "--threshold", "95"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(!stdout.contains("copy.md"), "stdout: {}", stdout);
+        assert!(stdout.contains("new.txt"), "stdout: {}", stdout);
+
+        let out = run_ftm_with_port(port, &["similar", "--since", "2999-01-01"]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("No new files"));
+
+        stop_server(&mut server);
     }
 }
 
-// ===========================================================================
-// Watchdog tests (.ftm deletion -> auto shutdown)
-// ===========================================================================
-
-mod watchdog_tests {
+mod empty_file_tests {
     use super::*;
 
     #[test]
-    fn test_server_stops_on_ftm_deleted() {
+    fn test_track_empty_files_records_create_and_delete() {
         let dir = setup_test_dir();
         let (mut server, port) = start_server_and_checkout(dir.path());
+        let marker = dir.path().join("marker.txt");
 
-        // Verify server is healthy
-        let out = run_ftm_with_port(port, &["ls"]);
+        std::fs::write(&marker, "").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
         assert!(
-            out.status.success(),
-            "ls: {}",
-            String::from_utf8_lossy(&out.stderr)
+            load_test_index(dir.path()).history.is_empty(),
+            "ignored by default"
         );
 
-        // Delete the entire .ftm directory
-        let ftm_dir = dir.path().join(".ftm");
-        assert!(ftm_dir.exists(), ".ftm should exist before deletion");
-        std::fs::remove_dir_all(&ftm_dir).unwrap();
-        assert!(!ftm_dir.exists(), ".ftm should be gone after deletion");
-
-        // The watchdog checks every 2 seconds; allow up to 10 seconds
-        let exited = wait_for_server_exit(&mut server, std::time::Duration::from_secs(10));
-        assert!(
-            exited,
-            "Server should have exited after .ftm directory was deleted"
+        let out = run_ftm_with_port(
+            port,
+            &["config", "set", "settings.track_empty_files", "true"],
         );
+        assert!(out.status.success());
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        let index = load_test_index(dir.path());
+        assert_eq!(index.history.len(), 1);
+        assert_eq!(index.history[0].op, "create");
+        assert_eq!(index.history[0].size, Some(0));
+        assert!(index.history[0].checksum.is_none());
+        assert_eq!(count_snapshot_files(dir.path()), 0);
+
+        std::fs::remove_file(&marker).unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        assert_eq!(load_test_index(dir.path()).history[1].op, "delete");
+
+        let out = run_ftm_with_port(port, &["restore-dir", "."]);
+        assert!(String::from_utf8_lossy(&out.stdout).contains("Restored 1 file(s)"));
+        assert_eq!(std::fs::read(&marker).unwrap(), b"");
+
+        stop_server(&mut server);
     }
 }
 
-// ===========================================================================
-// Periodic scan tests
-// ===========================================================================
+// ---------------------------------------------------------------------------
+// JSON-RPC over stdio
+// ---------------------------------------------------------------------------
 
-mod periodic_scan_tests {
+mod rpc_tests {
     use super::*;
+    use std::io::Write;
+
+    /// Feed `requests` (one JSON value per line) to `ftm rpc` and parse the responses.
+    fn rpc(port: u16, requests: &[serde_json::Value]) -> Vec<serde_json::Value> {
+        let mut child = ftm_command()
+            .args(["--port", &port.to_string(), "rpc"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .expect("failed to spawn ftm rpc");
+        let mut stdin = child.stdin.take().unwrap();
+        for request in requests {
+            writeln!(stdin, "{}", request).unwrap();
+        }
+        drop(stdin);
+        let out = child.wait_with_output().unwrap();
+        assert!(out.status.success());
+        String::from_utf8(out.stdout)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
 
     #[test]
-    fn test_periodic_scan_detects_existing_file() {
+    fn test_rpc_history_snapshot_diff_restore() {
         let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
 
-        // Create a file BEFORE checkout so the watcher won't catch it;
-        // only the periodic scanner should pick it up.
-        std::fs::write(
-            dir.path().join("pre_existing.txt"),
-            "hello from before checkout",
-        )
-        .unwrap();
-
-        // Pre-init with 2s scan interval (minimum)
-        PreInitFtm::new(dir.path()).scan_interval(2).init();
-
-        let (mut server, _port) = start_server_and_checkout(dir.path());
-
-        let found = wait_for_index(dir.path(), "pre_existing.txt", 1, 5000);
-        assert!(
-            found,
-            "Periodic scanner should have picked up pre_existing.txt"
-        );
-
-        // Verify the entry in index
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "one\n").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+        assert!(run_ftm_with_port(port, &["scan"]).status.success());
         let index = load_test_index(dir.path());
-        let entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "pre_existing.txt")
-            .collect();
-        assert!(
-            !entries.is_empty(),
-            "Should have history for pre_existing.txt"
+        let first = index.history[0].checksum.clone().unwrap();
+        let second = index.history[1].checksum.clone().unwrap();
+
+        let responses = rpc(
+            port,
+            &[
+                serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "history",
+                    "params": {"file": "notes.txt"}}),
+                serde_json::json!({"jsonrpc": "2.0", "id": 2, "method": "snapshot",
+                    "params": {"checksum": first}}),
+                serde_json::json!({"jsonrpc": "2.0", "id": 3, "method": "diff",
+                    "params": {"from": first, "to": second}}),
+                // Notifications get no response
+                serde_json::json!({"jsonrpc": "2.0", "method": "history",
+                    "params": {"file": "notes.txt"}}),
+                serde_json::json!({"jsonrpc": "2.0", "id": 4, "method": "restore",
+                    "params": {"file": "notes.txt", "checksum": first}}),
+                serde_json::json!({"jsonrpc": "2.0", "id": 5, "method": "nope"}),
+                serde_json::json!({"jsonrpc": "2.0", "id": 6, "method": "history"}),
+            ],
         );
-        assert_eq!(entries[0].op, "create");
+        assert_eq!(responses.len(), 6, "responses: {:?}", responses);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["result"].as_array().unwrap().len(), 2);
+        assert_eq!(responses[1]["result"]["content"], "one\n");
+        assert_eq!(responses[2]["result"]["new_total"], 2);
+        assert_eq!(responses[3]["id"], 4);
+        assert!(responses[3]["result"]["message"].is_string());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\n");
+        assert_eq!(responses[4]["error"]["code"], -32601);
+        assert_eq!(responses[5]["error"]["code"], -32602);
 
         stop_server(&mut server);
     }
+}
+
+// ---------------------------------------------------------------------------
+// Paths with spaces and non-ASCII characters
+// ---------------------------------------------------------------------------
+mod unicode_path_tests {
+    use super::*;
 
     #[test]
-    fn test_periodic_scan_respects_interval() {
+    fn test_space_cjk_and_emoji_paths_round_trip() {
         let dir = setup_test_dir();
+        let (mut server, port) = start_server_and_checkout(dir.path());
+        let key = "my notes/日本語 文件/draft 🎉 v1.txt";
+        let path = dir.path().join(key);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
 
-        // Create a file BEFORE checkout
-        std::fs::write(dir.path().join("should_not_scan.txt"), "no scan").unwrap();
+        std::fs::write(&path, "first\n").unwrap();
+        assert!(wait_for_index(dir.path(), key, 1, 3000));
+        std::fs::write(&path, "second\n").unwrap();
+        assert!(wait_for_index(dir.path(), key, 2, 3000));
 
-        // Pre-init with 5s interval so no scan runs within 2s
-        PreInitFtm::new(dir.path()).scan_interval(5).init();
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let entries: Vec<serde_json::Value> = client
+            .get(format!("http://127.0.0.1:{}/api/history", port))
+            .query(&[("file", key)])
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e["file"] == key));
+        let first = entries[0]["checksum"].as_str().unwrap().to_string();
 
-        let (mut server, _port) = start_server_and_checkout(dir.path());
+        let files = client
+            .get(format!("http://127.0.0.1:{}/api/files", port))
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert!(files.contains("draft 🎉 v1.txt"), "{}", files);
+        let out = run_ftm_with_port(port, &["ls"]);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        assert!(stdout.contains("日本語 文件/"), "{}", stdout);
+        assert!(stdout.contains("draft 🎉 v1.txt (2 entries)"), "{}", stdout);
+
+        // Relative from inside the directory, absolute, and as a key
+        let port_s = port.to_string();
+        let runs = [
+            (
+                path.parent().unwrap().to_path_buf(),
+                "draft 🎉 v1.txt".to_string(),
+            ),
+            (
+                dir.path().join("my notes"),
+                "./日本語 文件/draft 🎉 v1.txt".to_string(),
+            ),
+            (dir.path().to_path_buf(), path.to_string_lossy().to_string()),
+            (dir.path().to_path_buf(), key.to_string()),
+        ];
+        for (cwd, arg) in &runs {
+            let out = run_ftm_in_dir(cwd, &["--port", &port_s, "history", arg]);
+            let stdout = String::from_utf8_lossy(&out.stdout);
+            assert!(out.status.success(), "{}", arg);
+            assert!(
+                stdout.starts_with(&format!("History for '{}':", key)),
+                "{}: {}",
+                arg,
+                stdout
+            );
+        }
 
-        std::thread::sleep(std::time::Duration::from_secs(2));
+        let snapshot = client
+            .get(format!("http://127.0.0.1:{}/api/snapshot", port))
+            .query(&[("checksum", first.as_str())])
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert_eq!(snapshot, "first\n");
 
-        let index = load_test_index(dir.path());
-        let entries: Vec<_> = index
-            .history
-            .iter()
-            .filter(|e| e.file == "should_not_scan.txt")
-            .collect();
+        let out = run_ftm_in_dir(
+            path.parent().unwrap(),
+            &["--port", &port_s, "restore", "draft 🎉 v1.txt", &first[..8]],
+        );
         assert!(
-            entries.is_empty(),
-            "With 5s scan_interval, no periodic scan should run within 2s"
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
         );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\n");
+        assert!(wait_for_index(dir.path(), key, 3, 3000));
+        let index = load_test_index(dir.path());
+        assert!(index.history.iter().all(|e| e.file == key));
 
         stop_server(&mut server);
     }